@@ -0,0 +1,105 @@
+// Build script: exports build metadata for `common::buildinfo` and generates memory.x
+//
+// - BUILD_GIT_SHA:  short commit hash, "-dirty" with uncommitted changes ("unknown" outside git)
+// - BUILD_PROFILE:  cargo profile ("debug" / "release")
+// - BUILD_FEATURES: enabled cargo features, comma separated
+// - DFU_PUBLIC_KEY: update signing key for `service::dfu` (64 hex digits, from the environment; empty if unset)
+//
+// memory.x comes from the selected board's memory map (`src/board/*_memory.rs`, which
+// `BoardConfig` uses too): FLASH for code ends where the storage / filesystem regions begin, so
+// the linker refuses firmware that would grow into them, and the `.noinit` section of
+// `common::noinit` follows `.bss`. It is written to OUT_DIR and put on the linker search path
+// (embassy-stm32's own `memory-x` feature stays off so there is only one).
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[cfg(feature = "board-nucleo-f446re")]
+#[path = "src/board/nucleo_f446re_memory.rs"]
+#[allow(dead_code)]
+mod memory;
+#[cfg(feature = "board-nucleo144-f413zh")]
+#[path = "src/board/nucleo144_f413zh_memory.rs"]
+#[allow(dead_code)]
+mod memory;
+#[cfg(not(any(feature = "board-nucleo-f446re", feature = "board-nucleo144-f413zh")))]
+compile_error!("select a board with a cargo feature: board-nucleo-f446re or board-nucleo144-f413zh");
+
+// memory.x for the board's memory map
+fn memory_x() -> String {
+  let flash_end = memory::FLASH_ORIGIN + memory::FLASH_SIZE_KB * 1024;
+  let storage_end = memory::FLASH_STORAGE_START + memory::FLASH_STORAGE_SIZE as u32;
+  let fs_end = memory::FS_STORAGE_START + memory::FS_STORAGE_SIZE as u32;
+  for (name, start, end) in [
+    ("FLASH_STORAGE", memory::FLASH_STORAGE_START, storage_end),
+    ("FS_STORAGE", memory::FS_STORAGE_START, fs_end),
+  ] {
+    assert!(start >= memory::FLASH_ORIGIN && end <= flash_end, "{name} region {start:#X}..{end:#X} is outside the flash");
+  }
+  // Code gets everything below the first reserved region
+  let code_len = memory::FLASH_STORAGE_START.min(memory::FS_STORAGE_START) - memory::FLASH_ORIGIN;
+
+  let (flash, code_kb) = (memory::FLASH_ORIGIN, code_len / 1024);
+  let (storage, storage_kb) = (memory::FLASH_STORAGE_START, memory::FLASH_STORAGE_SIZE / 1024);
+  let (ram, ram_kb) = (memory::RAM_ORIGIN, memory::RAM_SIZE_KB);
+  format!(
+    "/* Generated by build.rs from the board memory map; edit src/board/<board>_memory.rs instead */
+MEMORY
+{{
+  FLASH (rx)   : ORIGIN = {flash:#010X}, LENGTH = {code_kb}K
+  STORAGE (r)  : ORIGIN = {storage:#010X}, LENGTH = {storage_kb}K
+  RAM (rwx)    : ORIGIN = {ram:#010X}, LENGTH = {ram_kb}K
+}}
+
+/* RAM kept across soft resets (`common::noinit`): neither loaded nor zeroed at startup */
+SECTIONS
+{{
+  .noinit (NOLOAD) : ALIGN(4)
+  {{
+    *(.noinit .noinit.*);
+    . = ALIGN(4);
+  }} > RAM
+}} INSERT AFTER .bss;
+"
+  )
+}
+
+fn git(args: &[&str]) -> Option<String> {
+  let output = Command::new("git").args(args).output().ok()?;
+  output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn main() {
+  let sha = match git(&["rev-parse", "--short=12", "HEAD"]) {
+    Some(sha) if git(&["status", "--porcelain", "--untracked-files=no"]).is_some_and(|s| !s.is_empty()) => format!("{sha}-dirty"),
+    Some(sha) => sha,
+    None => "unknown".to_string(),
+  };
+
+  let mut features: Vec<String> = env::vars()
+    .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(|name| name.to_lowercase()))
+    .collect();
+  features.sort();
+
+  println!("cargo:rustc-env=BUILD_GIT_SHA={sha}");
+  println!("cargo:rustc-env=BUILD_PROFILE={}", env::var("PROFILE").unwrap_or_default());
+  println!("cargo:rustc-env=BUILD_FEATURES={}", features.join(","));
+  println!("cargo:rustc-env=DFU_PUBLIC_KEY={}", env::var("DFU_PUBLIC_KEY").unwrap_or_default().trim());
+  println!("cargo:rerun-if-env-changed=DFU_PUBLIC_KEY");
+
+  let out = PathBuf::from(env::var("OUT_DIR").unwrap());
+  std::fs::write(out.join("memory.x"), memory_x()).unwrap();
+  println!("cargo:rustc-link-search={}", out.display());
+  // A memory.x left in the package root by older setups is found first and would shadow this one
+  if PathBuf::from("memory.x").exists() {
+    println!("cargo:warning=memory.x in the package root overrides the generated one; delete it");
+  }
+  println!("cargo:rerun-if-changed=src/board/nucleo_f446re_memory.rs");
+  println!("cargo:rerun-if-changed=src/board/nucleo144_f413zh_memory.rs");
+  // New commits and checkouts change HEAD or the branch it points to
+  println!("cargo:rerun-if-changed=.git/HEAD");
+  println!("cargo:rerun-if-changed=.git/refs/heads");
+  println!("cargo:rerun-if-changed=.git/index");
+  println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,85 @@
+//! `sign-image` - sign firmware images for `signed_dfu` builds
+//!
+//! ```text
+//! cargo run --bin sign-image -- keygen dfu.key         # secret key (keep it safe); prints the public key
+//! DFU_PUBLIC_KEY=<64 hex> cargo build --release        # in the firmware tree: bake the public key in
+//! cargo run --bin sign-image -- sign dfu.key firmware.bin firmware.signed
+//! cargo run --bin sign-image -- verify <64 hex> firmware.signed
+//! ```
+
+use std::io::Read;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+use clap::{Parser, Subcommand};
+
+use embassy_stm32_starter_host::image;
+
+#[derive(Parser)]
+#[command(version, about = "Sign firmware images for embassy-stm32-starter signed updates")]
+struct Cli {
+  #[command(subcommand)]
+  command: Cmd,
+}
+
+#[derive(Subcommand)]
+enum Cmd {
+  /// Generate a secret key file (32 bytes from /dev/urandom) and print its public key
+  Keygen { secret: PathBuf },
+  /// Print the public key of a secret key file (the firmware's DFU_PUBLIC_KEY)
+  Public { secret: PathBuf },
+  /// Append a signed trailer to a firmware binary
+  Sign { secret: PathBuf, image: PathBuf, output: PathBuf },
+  /// Check a signed image against a public key (64 hex digits)
+  Verify { public: String, signed: PathBuf },
+}
+
+fn main() -> Result<()> {
+  match Cli::parse().command {
+    Cmd::Keygen { secret } => {
+      if secret.exists() {
+        bail!("{} already exists", secret.display());
+      }
+      let mut key = [0u8; 32];
+      std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut key))
+        .context("reading /dev/urandom")?;
+      std::fs::write(&secret, key).with_context(|| format!("writing {}", secret.display()))?;
+      println!("{}", hex(&image::public_key(&key)));
+    }
+    Cmd::Public { secret } => println!("{}", hex(&image::public_key(&read_secret(&secret)?))),
+    Cmd::Sign { secret, image: input, output } => {
+      let data = std::fs::read(&input).with_context(|| format!("reading {}", input.display()))?;
+      let signed = image::sign(&data, &read_secret(&secret)?)?;
+      std::fs::write(&output, &signed).with_context(|| format!("writing {}", output.display()))?;
+      println!("signed {} bytes -> {}", data.len(), output.display());
+    }
+    Cmd::Verify { public, signed } => {
+      let key = parse_public(&public)?;
+      let data = std::fs::read(&signed).with_context(|| format!("reading {}", signed.display()))?;
+      let len = image::verify(&data, &key).context("signature check failed")?;
+      println!("ok: {len} byte image");
+    }
+  }
+  Ok(())
+}
+
+fn read_secret(path: &PathBuf) -> Result<[u8; 32]> {
+  let bytes = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+  bytes.try_into().map_err(|_| anyhow::anyhow!("{} is not a 32-byte secret key", path.display()))
+}
+
+fn parse_public(hex: &str) -> Result<[u8; 32]> {
+  if hex.len() != 64 {
+    bail!("public key must be 64 hex digits");
+  }
+  let mut key = [0u8; 32];
+  for (i, byte) in key.iter_mut().enumerate() {
+    *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).with_context(|| format!("invalid hex in '{hex}'"))?;
+  }
+  Ok(key)
+}
+
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
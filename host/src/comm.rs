@@ -0,0 +1,504 @@
+//! Comms message encoding/decoding (host side)
+// Mirrors `src/service/comm.rs` in the firmware.
+//
+// Comms message format (little-endian):
+// - command:      u16
+// - id:           u8
+// - fragments:    u16 (total fragments)
+// - fragment:     u16 (0-based index)
+// - length:       u16  (payload length in bytes)
+// - payload:      [u8; length]
+// Firmware built with `comms_routing` (protocol version 2) adds src, dst and hops after the id;
+// `Addressing` adds and strips them around this format.
+
+pub const COMMS_HEADER_LEN: usize = 9;
+pub const COMMS_MAX_PAYLOAD: usize = 256;
+/// Command bit flagging a heatshrink-compressed payload (firmware `comm_compress`)
+pub const COMMS_COMPRESSED: u16 = 0x8000;
+/// Payloads from this long on are worth compressing (the firmware's threshold)
+pub const COMMS_COMPRESS_MIN: usize = 64;
+/// Destination address every node takes
+pub const BROADCAST: u8 = 0xFF;
+
+/// Command identifiers for Comms messages.
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+  Ack = 0x01,
+  Nak = 0x02,
+  Ping = 0x03,
+  Raw = 0x04,
+  Stats = 0x05,
+  Alarm = 0x06,
+  AlarmAck = 0x07,
+  Rules = 0x08,
+  ConfigExport = 0x09,
+  ConfigImport = 0x0A,
+  Telemetry = 0x0B,
+  Sensors = 0x0C,
+  GetConfig = 0x0D,
+  SetConfig = 0x0E,
+  Ident = 0x0F,
+  SetKey = 0x10,
+  Subscribe = 0x11,
+  Unsubscribe = 0x12,
+  Publish = 0x13,
+  Protection = 0x14,
+  Bootloader = 0x15,
+  EventLog = 0x16,
+  Schedule = 0x17,
+}
+
+impl TryFrom<u16> for Command {
+  type Error = u16;
+  fn try_from(value: u16) -> Result<Self, Self::Error> {
+    match value {
+      0x01 => Ok(Command::Ack),
+      0x02 => Ok(Command::Nak),
+      0x03 => Ok(Command::Ping),
+      0x04 => Ok(Command::Raw),
+      0x05 => Ok(Command::Stats),
+      0x06 => Ok(Command::Alarm),
+      0x07 => Ok(Command::AlarmAck),
+      0x08 => Ok(Command::Rules),
+      0x09 => Ok(Command::ConfigExport),
+      0x0A => Ok(Command::ConfigImport),
+      0x0B => Ok(Command::Telemetry),
+      0x0C => Ok(Command::Sensors),
+      0x0D => Ok(Command::GetConfig),
+      0x0E => Ok(Command::SetConfig),
+      0x0F => Ok(Command::Ident),
+      0x10 => Ok(Command::SetKey),
+      0x11 => Ok(Command::Subscribe),
+      0x12 => Ok(Command::Unsubscribe),
+      0x13 => Ok(Command::Publish),
+      0x14 => Ok(Command::Protection),
+      0x15 => Ok(Command::Bootloader),
+      0x16 => Ok(Command::EventLog),
+      0x17 => Ok(Command::Schedule),
+      other => Err(other),
+    }
+  }
+}
+
+/// Error codes carried in `Command::Nak` replies (`[code, offending id]`)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum NakCode {
+  BadLength,
+  BadCommand,
+  QueueFull,
+  FcsError,
+  AuthFailed,
+  Unknown(u8),
+}
+
+impl From<u8> for NakCode {
+  fn from(value: u8) -> Self {
+    match value {
+      0x01 => NakCode::BadLength,
+      0x02 => NakCode::BadCommand,
+      0x03 => NakCode::QueueFull,
+      0x04 => NakCode::FcsError,
+      0x05 => NakCode::AuthFailed,
+      other => NakCode::Unknown(other),
+    }
+  }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Message {
+  pub command: u16,
+  pub id: u8,
+  pub fragments: u16,
+  pub fragment: u16,
+  pub payload: Vec<u8>,
+}
+
+impl Message {
+  /// Single-fragment message
+  pub fn new(command: Command, id: u8, payload: &[u8]) -> Self {
+    Self {
+      command: command as u16,
+      id,
+      fragments: 1,
+      fragment: 0,
+      payload: payload.to_vec(),
+    }
+  }
+
+  /// Copy with the payload heatshrink-compressed and flagged, if it is long enough and shrinks
+  pub fn compressed(&self) -> Option<Self> {
+    if self.payload.len() < COMMS_COMPRESS_MIN || self.command & COMMS_COMPRESSED != 0 {
+      return None;
+    }
+    let payload = crate::heatshrink::compress(&self.payload);
+    (payload.len() < self.payload.len()).then(|| Self {
+      command: self.command | COMMS_COMPRESSED,
+      payload,
+      ..self.clone()
+    })
+  }
+
+  /// Undo `compressed` (unchanged if the payload is not flagged as compressed)
+  pub fn decompressed(self) -> anyhow::Result<Self> {
+    if self.command & COMMS_COMPRESSED == 0 {
+      return Ok(self);
+    }
+    Ok(Self {
+      command: self.command & !COMMS_COMPRESSED,
+      payload: crate::heatshrink::decompress(&self.payload, COMMS_MAX_PAYLOAD)?,
+      ..self
+    })
+  }
+
+  /// Encode header + payload (unframed)
+  pub fn encode(&self) -> Vec<u8> {
+    let mut out = Vec::with_capacity(COMMS_HEADER_LEN + self.payload.len());
+    out.extend_from_slice(&self.command.to_le_bytes());
+    out.push(self.id);
+    out.extend_from_slice(&self.fragments.to_le_bytes());
+    out.extend_from_slice(&self.fragment.to_le_bytes());
+    out.extend_from_slice(&(self.payload.len() as u16).to_le_bytes());
+    out.extend_from_slice(&self.payload);
+    out
+  }
+
+  /// Decode header + payload (unframed); `None` if the length field does not match
+  pub fn decode(bytes: &[u8]) -> Option<Self> {
+    if bytes.len() < COMMS_HEADER_LEN {
+      return None;
+    }
+    let len = u16::from_le_bytes([bytes[7], bytes[8]]) as usize;
+    if bytes.len() != COMMS_HEADER_LEN + len {
+      return None;
+    }
+    Some(Self {
+      command: u16::from_le_bytes([bytes[0], bytes[1]]),
+      id: bytes[2],
+      fragments: u16::from_le_bytes([bytes[3], bytes[4]]),
+      fragment: u16::from_le_bytes([bytes[5], bytes[6]]),
+      payload: bytes[COMMS_HEADER_LEN..].to_vec(),
+    })
+  }
+}
+
+/// Addressed header (protocol version 2) for talking to one node on a shared bus
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Addressing {
+  /// Our own address (src of what we send)
+  pub host: u8,
+  /// Node we talk to (dst of what we send; `BROADCAST` for all)
+  pub node: u8,
+}
+
+impl Addressing {
+  /// Bytes the addressed header adds (src, dst, hops)
+  pub const LEN: usize = 3;
+
+  /// Insert the address bytes into an encoded message (hops 0)
+  pub fn wrap(&self, encoded: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(encoded.len() + Self::LEN);
+    out.extend_from_slice(&encoded[..3]);
+    out.extend_from_slice(&[self.host, self.node, 0]);
+    out.extend_from_slice(&encoded[3..]);
+    out
+  }
+
+  /// Strip the address bytes of a received message; `None` if it is not from our node (any node
+  /// when talking to `BROADCAST`) to us or broadcast
+  pub fn unwrap(&self, bytes: &[u8]) -> Option<Vec<u8>> {
+    if bytes.len() < COMMS_HEADER_LEN + Self::LEN {
+      return None;
+    }
+    let (src, dst) = (bytes[3], bytes[4]);
+    if (self.node != BROADCAST && src != self.node) || (dst != self.host && dst != BROADCAST) {
+      return None;
+    }
+    let mut out = bytes[..3].to_vec();
+    out.extend_from_slice(&bytes[3 + Self::LEN..]);
+    Some(out)
+  }
+}
+
+/// Link statistics returned by `Command::Stats`
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+  pub rx_frames: u32,
+  pub tx_frames: u32,
+  pub fcs_errors: u32,
+  pub parse_errors: u32,
+  pub queue_drops: u32,
+  pub rx_bytes: u32,
+  pub tx_bytes: u32,
+}
+
+impl Stats {
+  pub const LEN: usize = 11 * 4;
+
+  pub fn decode(payload: &[u8]) -> Option<Self> {
+    if payload.len() < Self::LEN {
+      return None;
+    }
+    let field = |i: usize| u32::from_le_bytes(payload[i * 4..i * 4 + 4].try_into().unwrap());
+    Some(Self {
+      rx_frames: field(0),
+      tx_frames: field(1),
+      fcs_errors: field(2),
+      parse_errors: field(3),
+      queue_drops: field(4),
+      rx_bytes: field(5),
+      tx_bytes: field(6),
+    })
+  }
+}
+
+/// System health record returned (or sent unsolicited) as `Command::Telemetry`
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Telemetry {
+  pub uptime_s: u32,
+  pub reset_cause: u32,
+  pub stack_high_water: u32,
+  pub heap_used: u32,
+  pub fcs_errors: u32,
+  pub vdda_mv: u32,
+  pub cs_max_us: u32,
+  pub idle_pct: u32,
+  pub serial_rx_max_us: u32,
+  pub comm_rx_max_us: u32,
+  pub telemetry_max_us: u32,
+  pub sleep_pct: u32,
+  pub current_ua: u32,
+}
+
+impl Telemetry {
+  /// Shortest record accepted; fields older firmware does not send decode as 0
+  pub const LEN: usize = 7 * 4;
+
+  pub fn decode(payload: &[u8]) -> Option<Self> {
+    if payload.len() < Self::LEN {
+      return None;
+    }
+    let field = |i: usize| payload.get(i * 4..i * 4 + 4).map_or(0, |b| u32::from_le_bytes(b.try_into().unwrap()));
+    Some(Self {
+      uptime_s: field(0),
+      reset_cause: field(1),
+      stack_high_water: field(2),
+      heap_used: field(3),
+      fcs_errors: field(4),
+      vdda_mv: field(5),
+      cs_max_us: field(6),
+      idle_pct: field(7),
+      serial_rx_max_us: field(8),
+      comm_rx_max_us: field(9),
+      telemetry_max_us: field(10),
+      sleep_pct: field(11),
+      current_ua: field(12),
+    })
+  }
+}
+
+/// Environmental sensor reading returned (or sent unsolicited) as `Command::Sensors`
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SensorReading {
+  /// Temperature in 0.01 °C
+  pub temperature_centi_c: i32,
+  /// Relative humidity in 0.01 %
+  pub humidity_centi_pct: u32,
+  /// Pressure in Pa (0 without a barometer)
+  pub pressure_pa: u32,
+}
+
+impl SensorReading {
+  pub const LEN: usize = 12;
+
+  pub fn decode(payload: &[u8]) -> Option<Self> {
+    if payload.len() < Self::LEN {
+      return None;
+    }
+    let field = |i: usize| payload[i * 4..i * 4 + 4].try_into().unwrap();
+    Some(Self {
+      temperature_centi_c: i32::from_le_bytes(field(0)),
+      humidity_centi_pct: u32::from_le_bytes(field(1)),
+      pressure_pa: u32::from_le_bytes(field(2)),
+    })
+  }
+}
+
+/// Flash protection state returned by `Command::Protection`
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Protection {
+  /// Readout protection level (0, 1 or 2)
+  pub rdp: u8,
+  /// Brown-out reset level (0 = off, 1-3)
+  pub bor: u8,
+  /// Bit n set: sector n is write protected
+  pub write_protected: u16,
+  /// Sector holding the device's storage region
+  pub storage_sector: u8,
+  pub storage_protected: bool,
+}
+
+impl Protection {
+  pub const LEN: usize = 6;
+
+  pub fn decode(payload: &[u8]) -> Option<Self> {
+    if payload.len() < Self::LEN {
+      return None;
+    }
+    Some(Self {
+      rdp: payload[0],
+      bor: payload[1],
+      write_protected: u16::from_le_bytes([payload[2], payload[3]]),
+      storage_sector: payload[4],
+      storage_protected: payload[5] != 0,
+    })
+  }
+}
+
+/// Black box entry carried by `Command::EventLog` (10 bytes each)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Event {
+  /// Device uptime in ms (restarts at every boot)
+  pub ms: u32,
+  pub code: u16,
+  pub arg: u32,
+}
+
+impl Event {
+  pub const LEN: usize = 10;
+  /// Codes from here on are application defined
+  pub const APP: u16 = 0x100;
+
+  /// Decode a reply payload (trailing partial entries are ignored)
+  pub fn decode_all(payload: &[u8]) -> Vec<Self> {
+    payload
+      .chunks_exact(Self::LEN)
+      .map(|e| Self {
+        ms: u32::from_le_bytes([e[0], e[1], e[2], e[3]]),
+        code: u16::from_le_bytes([e[4], e[5]]),
+        arg: u32::from_le_bytes([e[6], e[7], e[8], e[9]]),
+      })
+      .collect()
+  }
+
+  /// Name of a code the firmware records itself
+  pub fn name(&self) -> Option<&'static str> {
+    match self.code {
+      0x01 => Some("boot"),
+      0x02 => Some("hard fault"),
+      0x03 => Some("failsafe"),
+      0x04 => Some("link"),
+      0x05 => Some("comm reset"),
+      0x06 => Some("mpu fault"),
+      0x07 => Some("stack overflow"),
+      0x08 => Some("schedule"),
+      _ => None,
+    }
+  }
+}
+
+/// Device configuration record carried by `Command::GetConfig` / `Command::SetConfig`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DeviceConfig {
+  pub baud: u32,
+  pub device_id: u32,
+  pub flags: u32,
+  /// Node address on a shared bus (firmware `comms_routing`; 0 = firmware default)
+  pub address: u8,
+  /// RTC calibration at 25 °C in 0.1 ppm
+  pub rtc_ppm_tenths: i16,
+  /// Opaque application data (up to `USER_MAX` bytes)
+  pub user: Vec<u8>,
+}
+
+impl DeviceConfig {
+  pub const VERSION: u8 = 3;
+  pub const USER_MAX: usize = 62;
+  const MAGIC: u16 = 0xDC0F;
+  const HEADER_LEN: usize = 20;
+  // Version 1 records have no rtc_ppm_tenths, version 1 and 2 records no address
+  const HEADER_LEN_V1: usize = 18;
+
+  pub fn encode(&self) -> Vec<u8> {
+    let mut out = Vec::with_capacity(Self::HEADER_LEN + self.user.len() + 2);
+    out.extend_from_slice(&Self::MAGIC.to_le_bytes());
+    out.extend_from_slice(&[Self::VERSION, self.address]);
+    out.extend_from_slice(&self.baud.to_le_bytes());
+    out.extend_from_slice(&self.device_id.to_le_bytes());
+    out.extend_from_slice(&self.flags.to_le_bytes());
+    out.extend_from_slice(&self.rtc_ppm_tenths.to_le_bytes());
+    out.extend_from_slice(&(self.user.len() as u16).to_le_bytes());
+    out.extend_from_slice(&self.user);
+    let crc = crate::hdlc::fcs16(&out);
+    out.extend_from_slice(&crc.to_le_bytes());
+    out
+  }
+
+  /// `None` if the record is malformed, from an unknown format version, or fails its CRC
+  pub fn decode(payload: &[u8]) -> Option<Self> {
+    if payload.len() < Self::HEADER_LEN_V1 + 2 || u16::from_le_bytes([payload[0], payload[1]]) != Self::MAGIC {
+      return None;
+    }
+    let header_len = match payload[2] {
+      Self::VERSION | 2 => Self::HEADER_LEN,
+      1 => Self::HEADER_LEN_V1,
+      _ => return None,
+    };
+    if payload.len() < header_len + 2 {
+      return None;
+    }
+    let field = |i: usize| u32::from_le_bytes(payload[i..i + 4].try_into().unwrap());
+    let end = header_len + u16::from_le_bytes([payload[header_len - 2], payload[header_len - 1]]) as usize;
+    if payload.len() < end + 2 || crate::hdlc::fcs16(&payload[..end]) != u16::from_le_bytes([payload[end], payload[end + 1]]) {
+      return None;
+    }
+    Some(Self {
+      baud: field(4),
+      device_id: field(8),
+      flags: field(12),
+      address: if payload[2] == Self::VERSION { payload[3] } else { 0 },
+      rtc_ppm_tenths: if header_len == Self::HEADER_LEN {
+        i16::from_le_bytes([payload[16], payload[17]])
+      } else {
+        0
+      },
+      user: payload[header_len..end].to_vec(),
+    })
+  }
+}
+
+/// Firmware build record returned as `Command::Ident`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BuildInfo {
+  pub version: String,
+  pub git_sha: String,
+  pub profile: String,
+  pub features: Vec<String>,
+  pub board: String,
+}
+
+impl BuildInfo {
+  pub const MAGIC: u32 = 0x444C_4942;
+  // Text field widths after the magic: version, git SHA, profile, features, board
+  const FIELDS: [usize; 5] = [16, 24, 8, 128, 32];
+
+  pub fn decode(payload: &[u8]) -> Option<Self> {
+    if payload.len() < 4 + Self::FIELDS.iter().sum::<usize>() || u32::from_le_bytes(payload[..4].try_into().unwrap()) != Self::MAGIC {
+      return None;
+    }
+    let mut offset = 4;
+    let mut text = Self::FIELDS.map(|len| {
+      let field = &payload[offset..offset + len];
+      offset += len;
+      let end = field.iter().position(|&b| b == 0).unwrap_or(len);
+      String::from_utf8_lossy(&field[..end]).into_owned()
+    });
+    Some(Self {
+      version: std::mem::take(&mut text[0]),
+      git_sha: std::mem::take(&mut text[1]),
+      profile: std::mem::take(&mut text[2]),
+      features: text[3].split(',').filter(|f| !f.is_empty()).map(str::to_string).collect(),
+      board: std::mem::take(&mut text[4]),
+    })
+  }
+}
@@ -0,0 +1,111 @@
+//! HDLC framing/deframing (host side)
+// Mirrors `src/protocol/hdlc.rs` in the firmware: flag 0x7E, escape 0x7D (XOR 0x20),
+// PPP/HDLC 16-bit FCS appended little-endian.
+
+pub const HDLC_FLAG: u8 = 0x7E;
+pub const HDLC_ESCAPE: u8 = 0x7D;
+pub const HDLC_XOR: u8 = 0x20;
+
+/// Compute PPP/HDLC 16-bit FCS.
+/// Polynomial 0x8408 (reversed 0x1021), init 0xFFFF, reflected, final XOR 0xFFFF.
+pub fn fcs16(data: &[u8]) -> u16 {
+  let mut fcs: u16 = 0xFFFF;
+  for &b in data {
+    fcs ^= b as u16;
+    for _ in 0..8 {
+      if fcs & 0x0001 != 0 {
+        fcs = (fcs >> 1) ^ 0x8408;
+      } else {
+        fcs >>= 1;
+      }
+    }
+  }
+  !fcs
+}
+
+fn push_escaped(out: &mut Vec<u8>, b: u8) {
+  match b {
+    HDLC_FLAG | HDLC_ESCAPE => {
+      out.push(HDLC_ESCAPE);
+      out.push(b ^ HDLC_XOR);
+    }
+    _ => out.push(b),
+  }
+}
+
+/// Frame a payload (flag, escaped payload, escaped FCS, flag)
+pub fn frame(payload: &[u8]) -> Vec<u8> {
+  let mut out = Vec::with_capacity(payload.len() * 2 + 6);
+  out.push(HDLC_FLAG);
+  for &b in payload {
+    push_escaped(&mut out, b);
+  }
+  for b in fcs16(payload).to_le_bytes() {
+    push_escaped(&mut out, b);
+  }
+  out.push(HDLC_FLAG);
+  out
+}
+
+/// Deframe error type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HdlcError {
+  FcsMismatch { received: u16, calculated: u16, len: usize },
+}
+
+/// Streaming deframer: feed raw serial bytes, get decoded payloads back
+///
+/// Same rules as the firmware: leading noise is ignored, consecutive flags and runt frames are
+/// skipped, a closing flag also opens the next frame, and escape+flag aborts a frame.
+#[derive(Debug, Default)]
+pub struct Deframer {
+  in_frame: bool,
+  escape: bool,
+  buf: Vec<u8>,
+}
+
+impl Deframer {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Push received bytes; returns every frame completed by them
+  pub fn push(&mut self, bytes: &[u8]) -> Vec<Result<Vec<u8>, HdlcError>> {
+    let mut frames = Vec::new();
+    for &b in bytes {
+      if b == HDLC_FLAG {
+        if self.in_frame && !self.escape && self.buf.len() >= 2 {
+          frames.push(Self::check(&self.buf));
+        }
+        self.in_frame = true;
+        self.escape = false;
+        self.buf.clear();
+      } else if self.in_frame {
+        if self.escape {
+          self.buf.push(b ^ HDLC_XOR);
+          self.escape = false;
+        } else if b == HDLC_ESCAPE {
+          self.escape = true;
+        } else {
+          self.buf.push(b);
+        }
+      }
+    }
+    frames
+  }
+
+  fn check(raw: &[u8]) -> Result<Vec<u8>, HdlcError> {
+    let (payload, fcs) = raw.split_at(raw.len() - 2);
+    let received = u16::from_le_bytes([fcs[0], fcs[1]]);
+    let calculated = fcs16(payload);
+    if received == calculated {
+      Ok(payload.to_vec())
+    } else {
+      Err(HdlcError::FcsMismatch {
+        received,
+        calculated,
+        len: payload.len(),
+      })
+    }
+  }
+}
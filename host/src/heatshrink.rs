@@ -0,0 +1,103 @@
+//! Heatshrink payload compression (host side)
+// Mirrors `src/protocol/heatshrink.rs` in the firmware (feature `comm_compress`): window 8 bits,
+// lookahead 4 bits, MSB-first bit stream. Literal: 1 + byte; back-reference: 0 + (offset - 1)
+// + (length - 1). The last byte is zero padded.
+
+use anyhow::{Result, bail};
+
+pub const WINDOW_BITS: u32 = 8;
+pub const LOOKAHEAD_BITS: u32 = 4;
+
+const WINDOW: usize = 1 << WINDOW_BITS;
+const MAX_MATCH: usize = 1 << LOOKAHEAD_BITS;
+const MIN_MATCH: usize = 2;
+
+pub fn compress(input: &[u8]) -> Vec<u8> {
+  let mut bits = BitWriter::default();
+  let mut pos = 0;
+  while pos < input.len() {
+    let limit = MAX_MATCH.min(input.len() - pos);
+    let (offset, len) = (pos.saturating_sub(WINDOW)..pos)
+      .map(|start| (pos - start, (0..limit).take_while(|&i| input[start + i] == input[pos + i]).count()))
+      // Longest, and the nearest of equally long ones (as the firmware picks)
+      .min_by_key(|&(offset, len)| (std::cmp::Reverse(len), offset))
+      .unwrap_or((0, 0));
+    if len >= MIN_MATCH {
+      bits.put(0, 1);
+      bits.put((offset - 1) as u32, WINDOW_BITS);
+      bits.put((len - 1) as u32, LOOKAHEAD_BITS);
+      pos += len;
+    } else {
+      bits.put(1, 1);
+      bits.put(input[pos] as u32, 8);
+      pos += 1;
+    }
+  }
+  bits.finish()
+}
+
+/// Decompress, refusing output longer than `max_len`
+pub fn decompress(input: &[u8], max_len: usize) -> Result<Vec<u8>> {
+  let mut out = Vec::new();
+  let total = input.len() * 8;
+  let mut pos = 0;
+  let mut get = |count: u32| -> Option<u32> {
+    if pos + count as usize > total {
+      return None;
+    }
+    let value = (0..count).fold(0, |value, _| {
+      let bit = (input[pos / 8] >> (7 - pos % 8)) & 1;
+      pos += 1;
+      (value << 1) | bit as u32
+    });
+    Some(value)
+  };
+  while let Some(tag) = get(1) {
+    if tag == 1 {
+      let Some(byte) = get(8) else { break };
+      out.push(byte as u8);
+    } else {
+      let (Some(offset), Some(len)) = (get(WINDOW_BITS), get(LOOKAHEAD_BITS)) else {
+        break;
+      };
+      let offset = offset as usize + 1;
+      if offset > out.len() {
+        bail!("compressed payload refers before its start");
+      }
+      for _ in 0..=len {
+        out.push(out[out.len() - offset]);
+      }
+    }
+    if out.len() > max_len {
+      bail!("compressed payload expands past {max_len} bytes");
+    }
+  }
+  Ok(out)
+}
+
+#[derive(Default)]
+struct BitWriter {
+  out: Vec<u8>,
+  byte: u8,
+  used: u32,
+}
+
+impl BitWriter {
+  fn put(&mut self, value: u32, count: u32) {
+    for bit in (0..count).rev() {
+      self.byte = (self.byte << 1) | ((value >> bit) & 1) as u8;
+      self.used += 1;
+      if self.used == 8 {
+        self.out.push(self.byte);
+        (self.byte, self.used) = (0, 0);
+      }
+    }
+  }
+
+  fn finish(mut self) -> Vec<u8> {
+    if self.used > 0 {
+      self.put(0, 8 - self.used);
+    }
+    self.out
+  }
+}
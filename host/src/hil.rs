@@ -0,0 +1,321 @@
+//! Hardware-in-the-loop serial test against the `hil` firmware (`src/bin/hil.rs`)
+// The board echoes Ping and Raw messages unchanged; `run` sends it randomized traffic and checks:
+//   - echo: random payloads (empty up to `max_payload`, some made only of flag/escape bytes)
+//     come back intact, with the request's command and id
+//   - FCS: a frame with one flipped bit is NAKed with `FcsError` and never echoed
+//   - fragmentation: frames written to the port in random pieces, and messages split into
+//     several comms fragments that are echoed and reassembled in order
+//   - throughput: echoed payload bytes per second and round-trip times
+// The board's Stats before and after must differ by one FCS error per corrupted frame. The same
+// seed replays the same traffic.
+
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use crate::comm::{Command, Message, NakCode, Stats};
+use crate::hdlc::{HDLC_ESCAPE, HDLC_FLAG};
+use crate::link::Link;
+
+/// Pause between the pieces of a frame written in parts (long enough for the USB/UART bridge
+/// to pass them on separately)
+pub const HIL_PIECE_GAP: Duration = Duration::from_millis(1);
+
+/// Small deterministic generator (xorshift64*), so a failing run can be replayed by its seed
+pub struct Rng(u64);
+
+impl Rng {
+  pub fn new(seed: u64) -> Self {
+    Self(seed.max(1))
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.0 ^= self.0 >> 12;
+    self.0 ^= self.0 << 25;
+    self.0 ^= self.0 >> 27;
+    self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+  }
+
+  /// Uniform in `0..n` (n > 0)
+  pub fn below(&mut self, n: usize) -> usize {
+    (self.next_u64() % n as u64) as usize
+  }
+
+  pub fn bytes(&mut self, len: usize) -> Vec<u8> {
+    (0..len).map(|_| self.next_u64() as u8).collect()
+  }
+}
+
+/// Test run parameters
+#[derive(Copy, Clone, Debug)]
+pub struct HilConfig {
+  /// Frames to send (a fragmented message counts each fragment)
+  pub count: u32,
+  pub seed: u64,
+  /// Largest payload the firmware accepts per message
+  pub max_payload: usize,
+  /// How long to wait for each echo or NAK
+  pub timeout: Duration,
+}
+
+/// Outcome of `run`
+#[derive(Clone, Debug, Default)]
+pub struct HilReport {
+  /// Frames written, corrupted ones included
+  pub frames: u32,
+  /// Frames echoed intact
+  pub echoed: u32,
+  /// Frames sent with a flipped bit, and how many of them were NAKed
+  pub corrupted: u32,
+  pub naked: u32,
+  /// Frames written to the port in pieces
+  pub split: u32,
+  /// Messages sent as several comms fragments
+  pub fragmented: u32,
+  /// Payload bytes echoed intact
+  pub bytes: u64,
+  pub elapsed: Duration,
+  pub rtt_min: Duration,
+  pub rtt_max: Duration,
+  rtt_total: Duration,
+  /// FCS errors the device counted during the run
+  pub fcs_errors: u32,
+  /// One line per check that failed
+  pub failures: Vec<String>,
+}
+
+impl HilReport {
+  pub fn passed(&self) -> bool {
+    self.failures.is_empty()
+  }
+
+  /// Echoed payload bytes per second
+  pub fn throughput(&self) -> f64 {
+    self.bytes as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+  }
+
+  pub fn rtt_avg(&self) -> Duration {
+    self.rtt_total.checked_div(self.echoed).unwrap_or_default()
+  }
+
+  fn record_rtt(&mut self, rtt: Duration) {
+    if self.echoed == 0 || rtt < self.rtt_min {
+      self.rtt_min = rtt;
+    }
+    self.rtt_max = self.rtt_max.max(rtt);
+    self.rtt_total += rtt;
+    self.echoed += 1;
+  }
+}
+
+/// Run the test against a board running the `hil` firmware
+pub fn run<P: Read + Write>(link: &mut Link<P>, config: &HilConfig) -> Result<HilReport> {
+  let mut test = Hil {
+    link,
+    rng: Rng::new(config.seed),
+    config: *config,
+    report: HilReport::default(),
+  };
+  let before = test.stats().context("reading device stats before the run")?;
+  let start = Instant::now();
+  while test.report.frames < config.count {
+    match test.rng.below(10) {
+      0 => test.corrupt()?,
+      1 if config.count - test.report.frames >= 2 => test.fragmented()?,
+      _ => test.echo()?,
+    }
+  }
+  test.report.elapsed = start.elapsed();
+  let after = test.stats().context("reading device stats after the run")?;
+  let report = &mut test.report;
+  report.fcs_errors = after.fcs_errors.wrapping_sub(before.fcs_errors);
+  if report.fcs_errors != report.corrupted {
+    report
+      .failures
+      .push(format!("device counted {} FCS errors for {} corrupted frames", report.fcs_errors, report.corrupted));
+  }
+  Ok(test.report)
+}
+
+struct Hil<'l, P> {
+  link: &'l mut Link<P>,
+  rng: Rng,
+  config: HilConfig,
+  report: HilReport,
+}
+
+impl<P: Read + Write> Hil<'_, P> {
+  // One message with a random payload, echoed back
+  fn echo(&mut self) -> Result<()> {
+    let id = self.link.next_id();
+    let payload = self.payload(self.config.max_payload);
+    let command = if payload.is_empty() && self.rng.below(2) == 0 { Command::Ping } else { Command::Raw };
+    let msg = Message::new(command, id, &payload);
+    let start = Instant::now();
+    self.write(&msg)?;
+    match self.wait(|m| m.id == id)? {
+      Some(reply) if reply.command == msg.command && reply.payload == payload => {
+        self.report.record_rtt(start.elapsed());
+        self.report.bytes += payload.len() as u64;
+      }
+      Some(reply) => self.fail(format!("id {id}: echo of {} bytes differs: {}", payload.len(), describe(&reply))),
+      None => self.fail(format!("id {id}: no echo of {} payload bytes", payload.len())),
+    }
+    Ok(())
+  }
+
+  // One frame with a single flipped bit: NAKed, not echoed (a late echo shows up as unexpected)
+  fn corrupt(&mut self) -> Result<()> {
+    let id = self.link.next_id();
+    let len = 1 + self.rng.below(self.config.max_payload);
+    let payload = self.rng.bytes(len);
+    let mut frame = self.link.frame(&Message::new(Command::Raw, id, &payload))?;
+    // Never touch the flags or an escape, or change a byte into one: the frame keeps its length
+    loop {
+      let index = 1 + self.rng.below(frame.len() - 2);
+      let flipped = frame[index] ^ (1 << self.rng.below(8));
+      if ![frame[index], flipped].iter().any(|b| *b == HDLC_FLAG || *b == HDLC_ESCAPE) {
+        frame[index] = flipped;
+        break;
+      }
+    }
+    self.write_frame(&frame)?;
+    self.report.corrupted += 1;
+    if self.wait(|m| m.command == Command::Nak as u16 && nak_code(m) == NakCode::FcsError)?.is_some() {
+      self.report.naked += 1;
+    } else {
+      self.fail(format!("id {id}: corrupted frame not NAKed"));
+    }
+    Ok(())
+  }
+
+  // One message split into 2-4 comms fragments, each echoed before the next is sent
+  fn fragmented(&mut self) -> Result<()> {
+    let max = self.config.max_payload;
+    let fragments = (2 + self.rng.below(3)).min((self.config.count - self.report.frames) as usize);
+    let len = max * (fragments - 1) + 1 + self.rng.below(max);
+    let data = self.fill(len);
+    let id = self.link.next_id();
+    let mut echoed = Vec::with_capacity(len);
+    let start = Instant::now();
+    for (index, chunk) in data.chunks(max).enumerate() {
+      let msg = Message {
+        command: Command::Raw as u16,
+        id,
+        fragments: fragments as u16,
+        fragment: index as u16,
+        payload: chunk.to_vec(),
+      };
+      self.write(&msg)?;
+      match self.wait(|m| m.id == id)? {
+        Some(reply) if reply.command == msg.command && reply.fragments == msg.fragments && reply.fragment == msg.fragment => {
+          echoed.extend_from_slice(&reply.payload);
+        }
+        Some(reply) => {
+          self.fail(format!(
+            "id {id}: echo of fragment {index}/{fragments} ({} bytes) differs: {}",
+            chunk.len(),
+            describe(&reply)
+          ));
+          return Ok(());
+        }
+        None => {
+          self.fail(format!("id {id}: no echo of fragment {index}/{fragments}"));
+          return Ok(());
+        }
+      }
+    }
+    self.report.fragmented += 1;
+    if echoed == data {
+      self.report.record_rtt(start.elapsed());
+      self.report.bytes += len as u64;
+    } else {
+      self.fail(format!("id {id}: {len} bytes in {fragments} fragments reassembled differently"));
+    }
+    Ok(())
+  }
+
+  fn stats(&mut self) -> Result<Stats> {
+    let id = self.link.next_id();
+    self.link.send(&Message::new(Command::Stats, id, &[]))?;
+    let reply = self.wait(|m| m.id == id)?.context("no Stats reply")?;
+    Stats::decode(&reply.payload).context("short Stats reply")
+  }
+
+  // Random payload of up to `max` bytes
+  fn payload(&mut self, max: usize) -> Vec<u8> {
+    let len = self.rng.below(max + 1);
+    self.fill(len)
+  }
+
+  // `len` random bytes; one fill in eight is made only of flag and escape bytes (all escaped)
+  fn fill(&mut self, len: usize) -> Vec<u8> {
+    if self.rng.below(8) == 0 {
+      (0..len).map(|_| if self.rng.below(2) == 0 { HDLC_FLAG } else { HDLC_ESCAPE }).collect()
+    } else {
+      self.rng.bytes(len)
+    }
+  }
+
+  fn write(&mut self, msg: &Message) -> Result<()> {
+    let frame = self.link.frame(msg)?;
+    self.write_frame(&frame)
+  }
+
+  // Write a frame whole, or (one in four) in 2-8 pieces with a gap between them
+  fn write_frame(&mut self, frame: &[u8]) -> Result<()> {
+    self.report.frames += 1;
+    if self.rng.below(4) != 0 {
+      return self.link.write_raw(frame);
+    }
+    self.report.split += 1;
+    let mut cuts: Vec<usize> = (0..1 + self.rng.below(7)).map(|_| 1 + self.rng.below(frame.len() - 1)).collect();
+    cuts.sort_unstable();
+    cuts.dedup();
+    let mut from = 0;
+    for cut in cuts.into_iter().chain([frame.len()]) {
+      self.link.write_raw(&frame[from..cut])?;
+      std::thread::sleep(HIL_PIECE_GAP);
+      from = cut;
+    }
+    Ok(())
+  }
+
+  // First message matching `want` within the timeout; anything else received meanwhile is a
+  // failure (an echo of a corrupted frame, a stray NAK, a late echo)
+  fn wait(&mut self, want: impl Fn(&Message) -> bool) -> Result<Option<Message>> {
+    let deadline = Instant::now() + self.config.timeout;
+    while Instant::now() < deadline {
+      let mut found = None;
+      for msg in self.link.poll(Duration::from_millis(1))? {
+        if found.is_none() && want(&msg) {
+          found = Some(msg);
+        } else {
+          self.fail(format!("unexpected {}", describe(&msg)));
+        }
+      }
+      if found.is_some() {
+        return Ok(found);
+      }
+    }
+    Ok(None)
+  }
+
+  fn fail(&mut self, failure: String) {
+    self.report.failures.push(failure);
+  }
+}
+
+fn nak_code(msg: &Message) -> NakCode {
+  NakCode::from(msg.payload.first().copied().unwrap_or(0))
+}
+
+// A received message for a failure line
+fn describe(msg: &Message) -> String {
+  match Command::try_from(msg.command) {
+    Ok(Command::Nak) => format!("NAK {:?} id={}", nak_code(msg), msg.id),
+    Ok(cmd) => format!("{cmd:?} id={} fragment {}/{}, {} bytes", msg.id, msg.fragment, msg.fragments, msg.payload.len()),
+    Err(raw) => format!("command 0x{raw:04X} id={}", msg.id),
+  }
+}
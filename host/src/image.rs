@@ -0,0 +1,58 @@
+//! Signed firmware images (host side)
+// Mirrors `src/protocol/image.rs` in the firmware (feature `signed_dfu`).
+//
+// Signed file: firmware binary, then a 76-byte trailer (little-endian): magic u32 0x4E474953,
+// version u8, reserved [u8; 3], image_len u32, Ed25519ph signature [u8; 64]. The signature
+// (SHA-512 prehash, context `SIGNING_CONTEXT`) covers the image and the first 12 trailer bytes.
+
+use anyhow::{Result, bail};
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha512};
+
+pub const IMAGE_TRAILER_LEN: usize = 12 + 64;
+pub const SIGNING_CONTEXT: &[u8] = b"embassy-stm32-starter image";
+
+const MAGIC: u32 = 0x4E47_4953;
+const VERSION: u8 = 1;
+
+fn signed_header(image_len: u32) -> [u8; 12] {
+  let mut out = [0u8; 12];
+  out[..4].copy_from_slice(&MAGIC.to_le_bytes());
+  out[4] = VERSION;
+  out[8..].copy_from_slice(&image_len.to_le_bytes());
+  out
+}
+
+fn prehash(image: &[u8], header: &[u8; 12]) -> Sha512 {
+  let mut hash = Sha512::new();
+  hash.update(image);
+  hash.update(header);
+  hash
+}
+
+/// Append a signed trailer to `image`
+pub fn sign(image: &[u8], secret: &[u8; 32]) -> Result<Vec<u8>> {
+  let header = signed_header(u32::try_from(image.len())?);
+  let signature = SigningKey::from_bytes(secret).sign_prehashed(prehash(image, &header), Some(SIGNING_CONTEXT))?;
+  Ok([image, &header, &signature.to_bytes()].concat())
+}
+
+/// Check a signed file against `public` (as the firmware does before committing it); returns the image length
+pub fn verify(signed: &[u8], public: &[u8; 32]) -> Result<usize> {
+  let Some(split) = signed.len().checked_sub(IMAGE_TRAILER_LEN) else {
+    bail!("too short to be a signed image");
+  };
+  let (image, trailer) = signed.split_at(split);
+  let header: [u8; 12] = trailer[..12].try_into()?;
+  if header != signed_header(split as u32) {
+    bail!("no valid trailer (unsigned image, or length mismatch)");
+  }
+  let signature = Signature::from_bytes(trailer[12..].try_into()?);
+  VerifyingKey::from_bytes(public)?.verify_prehashed_strict(prehash(image, &header), Some(SIGNING_CONTEXT), &signature)?;
+  Ok(image.len())
+}
+
+/// Public key for a secret key
+pub fn public_key(secret: &[u8; 32]) -> [u8; 32] {
+  SigningKey::from_bytes(secret).verifying_key().to_bytes()
+}
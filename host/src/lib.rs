@@ -0,0 +1,17 @@
+//! Host-side reference implementation of the embassy-stm32-starter serial protocol
+//!
+//! - `hdlc`: HDLC framing with PPP FCS-16 (mirrors `src/protocol/hdlc.rs`)
+//! - `comm`: Comms message header/payload encoding (mirrors `src/service/comm.rs`)
+//! - `heatshrink`: payload compression (mirrors `src/protocol/heatshrink.rs`, feature `comm_compress`)
+//! - `hil`: hardware-in-the-loop serial test against the `hil` firmware
+//! - `image`: signed firmware images (mirrors `src/protocol/image.rs`, feature `signed_dfu`)
+//! - `link`: request/response helper over a serial port
+//! - `secure`: AES-128-CCM payload sealing (mirrors `src/protocol/secure.rs`, feature `comm_crypto`)
+
+pub mod comm;
+pub mod hdlc;
+pub mod heatshrink;
+pub mod hil;
+pub mod image;
+pub mod link;
+pub mod secure;
@@ -0,0 +1,177 @@
+//! Serial link: frame/send messages and collect decoded replies
+
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+
+use crate::comm::{Addressing, Message};
+use crate::hdlc::{self, Deframer};
+use crate::secure::{self, Key, Session};
+
+pub struct Link<P> {
+  port: P,
+  deframer: Deframer,
+  next_id: u8,
+  session: Option<Session>,
+  addressing: Option<Addressing>,
+  compress: bool,
+}
+
+impl Link<Box<dyn serialport::SerialPort>> {
+  /// Open a serial port (8N1, read timeout used as the polling interval)
+  pub fn open(path: &str, baud: u32) -> Result<Self> {
+    let port = serialport::new(path, baud)
+      .timeout(Duration::from_millis(10))
+      .open()
+      .with_context(|| format!("opening {path}"))?;
+    Ok(Self::new(port))
+  }
+}
+
+// Ids start at a clock-derived value so back-to-back CLI runs do not reuse the ids of the last
+// one, which the firmware still drops as duplicates for a couple of seconds
+fn first_id() -> u8 {
+  let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.subsec_nanos());
+  ((nanos >> 10) as u8).max(1)
+}
+
+impl<P: Read + Write> Link<P> {
+  pub fn new(port: P) -> Self {
+    Self {
+      port,
+      deframer: Deframer::new(),
+      next_id: first_id(),
+      session: None,
+      addressing: None,
+      compress: false,
+    }
+  }
+
+  /// Seal outgoing and open incoming payloads with `key` (firmware `comm_crypto`), or stop with `None`
+  pub fn set_key(&mut self, key: Option<&Key>) {
+    self.session = key.map(Session::new);
+  }
+
+  /// Use the addressed header (firmware `comms_routing`) to talk to one node on a shared bus, or
+  /// the plain header with `None`; messages for other addresses are ignored
+  pub fn set_addressing(&mut self, addressing: Option<Addressing>) {
+    self.addressing = addressing;
+  }
+
+  /// Compress long outgoing payloads (firmware `comm_compress`); compressed replies are always
+  /// accepted
+  pub fn set_compress(&mut self, compress: bool) {
+    self.compress = compress;
+  }
+
+  /// Allocate the next message id (wraps, skips 0 which the firmware uses for "unknown")
+  pub fn next_id(&mut self) -> u8 {
+    let id = self.next_id;
+    self.next_id = self.next_id.wrapping_add(1).max(1);
+    id
+  }
+
+  /// Frame and write a message
+  pub fn send(&mut self, msg: &Message) -> Result<()> {
+    let frame = self.frame(msg)?;
+    self.write_raw(&frame)
+  }
+
+  /// The HDLC frame `send` would write for `msg` (compressed, sealed and addressed as configured)
+  pub fn frame(&mut self, msg: &Message) -> Result<Vec<u8>> {
+    let compressed = msg.compressed().filter(|_| self.compress);
+    let msg = compressed.as_ref().unwrap_or(msg);
+    let bytes = match self.session.as_mut() {
+      Some(session) => session.seal(msg)?.encode(),
+      None => msg.encode(),
+    };
+    let bytes = match self.addressing {
+      Some(addressing) => addressing.wrap(&bytes),
+      None => bytes,
+    };
+    Ok(hdlc::frame(&bytes))
+  }
+
+  /// Write bytes to the port as they are (part of a frame, or a deliberately corrupted one)
+  pub fn write_raw(&mut self, bytes: &[u8]) -> Result<()> {
+    self.port.write_all(bytes)?;
+    self.port.flush()?;
+    Ok(())
+  }
+
+  /// Collect every message received within `timeout`
+  pub fn poll(&mut self, timeout: Duration) -> Result<Vec<Message>> {
+    let deadline = Instant::now() + timeout;
+    let mut messages = Vec::new();
+    while Instant::now() < deadline {
+      messages.extend(self.read_once()?);
+    }
+    Ok(messages)
+  }
+
+  /// Send `msg` and wait for the first reply carrying the same id; sent again once if the device
+  /// answers with the challenge to open a session with (the first request under a key)
+  pub fn request(&mut self, msg: &Message, timeout: Duration) -> Result<Message> {
+    let reply = self.request_once(msg, timeout)?;
+    if self.session.is_some() && secure::challenge(&reply).is_some() {
+      return self.request_once(msg, timeout);
+    }
+    Ok(reply)
+  }
+
+  fn request_once(&mut self, msg: &Message, timeout: Duration) -> Result<Message> {
+    self.send(msg)?;
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+      if let Some(reply) = self.read_once()?.into_iter().find(|m| m.id == msg.id) {
+        return Ok(reply);
+      }
+    }
+    bail!("no reply to message id {} within {:?}", msg.id, timeout)
+  }
+
+  fn read_once(&mut self) -> Result<Vec<Message>> {
+    let mut chunk = [0u8; 256];
+    let n = match self.port.read(&mut chunk) {
+      Ok(n) => n,
+      Err(e) if e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock => 0,
+      Err(e) => return Err(e.into()),
+    };
+    let mut messages = Vec::new();
+    for frame in self.deframer.push(&chunk[..n]) {
+      let bytes = match (frame, self.addressing) {
+        (Ok(bytes), None) => bytes,
+        (Ok(bytes), Some(addressing)) => match addressing.unwrap(&bytes) {
+          Some(bytes) => bytes,
+          // Traffic between other nodes on the bus
+          None => continue,
+        },
+        (Err(e), _) => {
+          eprintln!("warning: {e:?}");
+          continue;
+        }
+      };
+      match Message::decode(&bytes) {
+        Some(msg) => {
+          let opened = match self.session.as_mut() {
+            Some(session) => session.open(&msg),
+            None => Ok(msg),
+          };
+          match opened.and_then(Message::decompressed) {
+            Ok(msg) => {
+              // Later messages are sealed for the session the device expects
+              if let (Some(session), Some(challenge)) = (self.session.as_mut(), secure::challenge(&msg)) {
+                session.rechallenge(challenge);
+              }
+              messages.push(msg)
+            }
+            Err(e) => eprintln!("warning: {e}"),
+          }
+        }
+        None => eprintln!("warning: malformed message ({} bytes)", bytes.len()),
+      }
+    }
+    Ok(messages)
+  }
+}
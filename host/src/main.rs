@@ -0,0 +1,532 @@
+//! `comm` - talk to an embassy-stm32-starter board over the HDLC serial protocol
+//!
+//! ```text
+//! cargo run -- --port /dev/ttyACM0 ping
+//! cargo run -- --port /dev/ttyACM0 raw d8 01
+//! cargo run -- --port /dev/ttyACM0 stats
+//! cargo run -- --port /dev/ttyACM0 ident
+//! cargo run -- --port /dev/ttyACM0 ack 3
+//! cargo run -- --port /dev/ttyACM0 rules 01 02 f4 01 01 07 82 00
+//! cargo run -- --port /dev/ttyACM0 config
+//! cargo run -- --port /dev/ttyACM0 set-config --device-id 7 --flags 0x3
+//! cargo run -- --port /dev/ttyACM0 export config.bin
+//! cargo run -- --port /dev/ttyACM0 import config.bin
+//! cargo run -- --port /dev/ttyACM0 send firmware.bin
+//! cargo run -- --port /dev/ttyACM0 set-key 000102030405060708090a0b0c0d0e0f
+//! cargo run -- --port /dev/ttyACM0 --key 000102030405060708090a0b0c0d0e0f stats
+//! cargo run -- --port /dev/ttyUSB0 --address 3 stats
+//! cargo run -- --port /dev/ttyACM0 --compress send log.bin
+//! cargo run -- --port /dev/ttyACM0 hil --count 1000
+//! ```
+
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+use clap::builder::TypedValueParser;
+use clap::{Parser, Subcommand};
+
+use embassy_stm32_starter_host::comm::{Addressing, BuildInfo, COMMS_MAX_PAYLOAD, Command, DeviceConfig, Event, Message, NakCode, Protection, SensorReading, Stats, Telemetry};
+use embassy_stm32_starter_host::hil::{self, HilConfig};
+use embassy_stm32_starter_host::link::Link;
+use embassy_stm32_starter_host::secure::{self, SECURE_OVERHEAD};
+
+#[derive(Parser)]
+#[command(version, about = "Host tool for the embassy-stm32-starter HDLC/Comms protocol")]
+struct Cli {
+  /// Serial port (e.g. /dev/ttyACM0, COM3)
+  #[arg(short, long)]
+  port: String,
+  /// Baud rate (firmware default: 115200)
+  #[arg(short, long, default_value_t = 115_200)]
+  baud: u32,
+  /// Reply timeout in milliseconds
+  #[arg(short, long, default_value_t = 1000)]
+  timeout: u64,
+  /// Link key (32 hex digits) for firmware built with `comm_crypto`
+  #[arg(short, long, value_parser = secure::parse_key)]
+  key: Option<secure::Key>,
+  /// Largest message payload the firmware accepts (128 for a `comms_small` build)
+  #[arg(long, default_value_t = COMMS_MAX_PAYLOAD, value_parser = clap::value_parser!(u16).range(64..=COMMS_MAX_PAYLOAD as i64).map(usize::from))]
+  max_payload: usize,
+  /// Node address (decimal or 0x hex) on a shared bus, for firmware built with `comms_routing`
+  #[arg(short, long, value_parser = parse_address)]
+  address: Option<u8>,
+  /// Compress long payloads (e.g. `send` fragments) for firmware built with `comm_compress`
+  #[arg(long)]
+  compress: bool,
+  /// Our own address on the bus (the src of what we send), with `--address`
+  #[arg(long, default_value_t = 0, value_parser = parse_address)]
+  host_address: u8,
+  #[command(subcommand)]
+  command: Cmd,
+}
+
+#[derive(Subcommand)]
+enum Cmd {
+  /// Ping the device and report round-trip time
+  Ping {
+    /// Number of pings, 0 to keep pinging (a keepalive for the device's link monitor)
+    #[arg(short, long, default_value_t = 1)]
+    count: u32,
+    /// Pause between pings in milliseconds
+    #[arg(short, long, default_value_t = 0)]
+    interval: u64,
+  },
+  /// Send a Raw command with hex payload bytes (e.g. `raw d8 01`)
+  Raw { bytes: Vec<String> },
+  /// Dump link statistics
+  Stats,
+  /// Identify the firmware (version, git commit, profile, features, board)
+  Ident,
+  /// Show the latest system telemetry record (uptime, reset cause, stack, heap, supply voltage)
+  Telemetry,
+  /// Show the latest environmental sensor reading
+  Sensors,
+  /// Show the flash protection state (readout protection, brown-out level, write-protected sectors)
+  Protection,
+  /// Reboot the device into the STM32 ROM bootloader for reflashing without a probe
+  Bootloader,
+  /// Dump the device's black box: events logged before and since the last reset, oldest first
+  Events,
+  /// Acknowledge an alarm (all alarms if no id is given)
+  Ack { id: Option<u8> },
+  /// Replace the device's rule set with hex rule bytes (8 per rule), or print it if none are given
+  Rules { bytes: Vec<String> },
+  /// Replace the device's calendar schedule with hex entry bytes (6 per entry: minute, hour or FF,
+  /// weekday mask, day of month, event, 0), or print it if none are given
+  Schedule { bytes: Vec<String> },
+  /// Show the persistent device configuration
+  Config,
+  /// Change fields of the persistent device configuration (staged, then committed with an ACK)
+  SetConfig {
+    /// Serial baud rate (takes effect at the next boot)
+    #[arg(long)]
+    baud: Option<u32>,
+    #[arg(long)]
+    device_id: Option<u32>,
+    /// Feature flags (decimal or 0x hex)
+    #[arg(long, value_parser = parse_u32)]
+    flags: Option<u32>,
+    /// RTC calibration at 25 °C in 0.1 ppm (normally measured on the device)
+    #[arg(long, allow_negative_numbers = true)]
+    rtc_ppm_tenths: Option<i16>,
+    /// Node address on a shared bus (`comms_routing`; takes effect at the next boot)
+    #[arg(long = "node-address", value_parser = parse_address)]
+    node_address: Option<u8>,
+    /// User blob as hex bytes (e.g. `--user 01 02 03`)
+    #[arg(long, num_args = 0..)]
+    user: Option<Vec<String>>,
+  },
+  /// Save the device configuration (CRC-protected blob) to a file
+  Export { file: std::path::PathBuf },
+  /// Load a configuration saved with `export` into the device
+  Import { file: std::path::PathBuf },
+  /// Stream a file (e.g. a firmware image) as fragmented Raw messages
+  Send {
+    file: std::path::PathBuf,
+    /// Delay between fragments in milliseconds (also the window for NAK detection)
+    #[arg(long, default_value_t = 20)]
+    pace: u64,
+  },
+  /// Subscribe to topic ids (decimal or 0x hex) and print what is published on them until interrupted
+  Subscribe {
+    #[arg(required = true, value_parser = parse_topic)]
+    topics: Vec<u16>,
+  },
+  /// Publish hex bytes on a topic id (decimal or 0x hex)
+  Publish {
+    #[arg(value_parser = parse_topic)]
+    topic: u16,
+    bytes: Vec<String>,
+  },
+  /// Install a new link key (32 hex digits); sent under `--key` if the device already has one
+  SetKey {
+    #[arg(value_parser = secure::parse_key)]
+    new_key: secure::Key,
+  },
+  /// Hardware-in-the-loop test against the `hil` firmware: randomized echo, FCS, fragmentation and throughput checks
+  Hil {
+    /// Frames to send
+    #[arg(short, long, default_value_t = 1000)]
+    count: u32,
+    /// Seed of the random traffic (printed by every run, to replay a failure)
+    #[arg(short, long)]
+    seed: Option<u64>,
+  },
+}
+
+fn main() -> Result<()> {
+  let cli = Cli::parse();
+  let timeout = Duration::from_millis(cli.timeout);
+  let mut link = Link::open(&cli.port, cli.baud)?;
+  link.set_key(cli.key.as_ref());
+  link.set_compress(cli.compress);
+  link.set_addressing(cli.address.map(|node| Addressing { host: cli.host_address, node }));
+  // Sealing takes room in each message
+  let max_payload = if cli.key.is_some() { cli.max_payload - SECURE_OVERHEAD } else { cli.max_payload };
+
+  match cli.command {
+    Cmd::Ping { count, interval } => {
+      for n in 0.. {
+        if count != 0 && n >= count {
+          break;
+        }
+        if n > 0 {
+          std::thread::sleep(Duration::from_millis(interval));
+        }
+        let id = link.next_id();
+        let start = Instant::now();
+        let reply = link.request(&Message::new(Command::Ping, id, &[]), timeout)?;
+        check_reply(&reply, Command::Ping)?;
+        println!("ping id={id} time={:.1} ms", start.elapsed().as_secs_f64() * 1000.0);
+      }
+    }
+    Cmd::Raw { bytes } => {
+      let payload = parse_hex(&bytes)?;
+      let id = link.next_id();
+      link.send(&Message::new(Command::Raw, id, &payload))?;
+      for reply in link.poll(timeout)?.iter().filter(|m| m.id == id) {
+        print_message(reply);
+      }
+    }
+    Cmd::Stats => {
+      let id = link.next_id();
+      let reply = link.request(&Message::new(Command::Stats, id, &[]), timeout)?;
+      check_reply(&reply, Command::Stats)?;
+      let stats = Stats::decode(&reply.payload).context("short Stats reply")?;
+      println!("{stats:#?}");
+    }
+    Cmd::Ident => {
+      let id = link.next_id();
+      let reply = link.request(&Message::new(Command::Ident, id, &[]), timeout)?;
+      check_reply(&reply, Command::Ident)?;
+      let info = BuildInfo::decode(&reply.payload).context("invalid Ident reply")?;
+      println!("{} {} ({}, {})", info.board, info.version, info.git_sha, info.profile);
+      println!("features: {}", info.features.join(" "));
+    }
+    Cmd::Telemetry => {
+      let id = link.next_id();
+      let reply = link.request(&Message::new(Command::Telemetry, id, &[]), timeout)?;
+      check_reply(&reply, Command::Telemetry)?;
+      let telemetry = Telemetry::decode(&reply.payload).context("short Telemetry reply")?;
+      println!("{telemetry:#?}");
+    }
+    Cmd::Sensors => {
+      let id = link.next_id();
+      let reply = link.request(&Message::new(Command::Sensors, id, &[]), timeout)?;
+      check_reply(&reply, Command::Sensors)?;
+      match SensorReading::decode(&reply.payload) {
+        Some(r) => {
+          print!(
+            "temperature {:.2} °C, humidity {:.2} %",
+            r.temperature_centi_c as f64 / 100.0,
+            r.humidity_centi_pct as f64 / 100.0
+          );
+          if r.pressure_pa != 0 {
+            print!(", pressure {:.2} hPa", r.pressure_pa as f64 / 100.0);
+          }
+          println!();
+        }
+        None => println!("no reading yet"),
+      }
+    }
+    Cmd::Bootloader => {
+      let id = link.next_id();
+      let reply = link.request(&Message::new(Command::Bootloader, id, &[]), timeout)?;
+      check_reply(&reply, Command::Ack)?;
+      println!("device is in the ROM bootloader: flash it over USART1 (e.g. stm32flash) or USB DFU (dfu-util)");
+    }
+    Cmd::Protection => {
+      let id = link.next_id();
+      let reply = link.request(&Message::new(Command::Protection, id, &[]), timeout)?;
+      check_reply(&reply, Command::Protection)?;
+      let p = Protection::decode(&reply.payload).context("short Protection reply")?;
+      let bor = if p.bor == 0 { "off".to_string() } else { format!("level {}", p.bor) };
+      println!("readout protection: level {}", p.rdp);
+      println!("brown-out reset: {bor}");
+      let sectors: Vec<String> = (0..16).filter(|s| p.write_protected & (1 << s) != 0).map(|s: u32| s.to_string()).collect();
+      println!("write-protected sectors: {}", if sectors.is_empty() { "none".to_string() } else { sectors.join(" ") });
+      println!("storage sector {}: {}", p.storage_sector, if p.storage_protected { "protected" } else { "writable" });
+    }
+    Cmd::Events => {
+      let id = link.next_id();
+      link.send(&Message::new(Command::EventLog, id, &[]))?;
+      let events = Event::decode_all(&collect_fragments(&mut link, id, timeout, "event log")?);
+      if events.is_empty() {
+        println!("event log empty");
+      }
+      for event in events {
+        let name = match event.name() {
+          Some(name) => name.to_string(),
+          None if event.code >= Event::APP => format!("app 0x{:04X}", event.code),
+          None => format!("0x{:04X}", event.code),
+        };
+        println!("{:>10} ms  {name:<12} 0x{:08X}", event.ms, event.arg);
+      }
+    }
+    Cmd::Ack { id } => {
+      let msg_id = link.next_id();
+      let payload: Vec<u8> = id.into_iter().collect();
+      let reply = link.request(&Message::new(Command::AlarmAck, msg_id, &payload), timeout)?;
+      check_reply(&reply, Command::Ack)?;
+      println!("acknowledged {}", id.map_or("all alarms".to_string(), |id| format!("alarm {id}")));
+    }
+    Cmd::Rules { bytes } => {
+      let payload = parse_hex(&bytes)?;
+      let id = link.next_id();
+      let reply = link.request(&Message::new(Command::Rules, id, &payload), timeout)?;
+      if payload.is_empty() {
+        check_reply(&reply, Command::Rules)?;
+        for rule in reply.payload.chunks(8) {
+          println!("{rule:02X?}");
+        }
+      } else {
+        check_reply(&reply, Command::Ack)?;
+        println!("loaded {} rules", payload.len() / 8);
+      }
+    }
+    Cmd::Schedule { bytes } => {
+      let payload = parse_hex(&bytes)?;
+      let id = link.next_id();
+      let reply = link.request(&Message::new(Command::Schedule, id, &payload), timeout)?;
+      if payload.is_empty() {
+        check_reply(&reply, Command::Schedule)?;
+        for entry in reply.payload.chunks(6) {
+          println!("{entry:02X?}");
+        }
+      } else {
+        check_reply(&reply, Command::Ack)?;
+        println!("loaded {} schedule entries", payload.len() / 6);
+      }
+    }
+    Cmd::Config => {
+      let config = get_config(&mut link, timeout)?;
+      println!("{config:#X?}");
+    }
+    Cmd::SetConfig {
+      baud,
+      device_id,
+      flags,
+      rtc_ppm_tenths,
+      node_address,
+      user,
+    } => {
+      let mut config = get_config(&mut link, timeout)?;
+      config.baud = baud.unwrap_or(config.baud);
+      config.device_id = device_id.unwrap_or(config.device_id);
+      config.flags = flags.unwrap_or(config.flags);
+      config.rtc_ppm_tenths = rtc_ppm_tenths.unwrap_or(config.rtc_ppm_tenths);
+      config.address = node_address.unwrap_or(config.address);
+      if let Some(user) = user {
+        config.user = parse_hex(&user)?;
+      }
+      if config.user.len() > DeviceConfig::USER_MAX {
+        bail!("user blob longer than {} bytes", DeviceConfig::USER_MAX);
+      }
+      // The device echoes the staged record; only an ACK with the same id commits it
+      let id = link.next_id();
+      let staged = link.request(&Message::new(Command::SetConfig, id, &config.encode()), timeout)?;
+      check_reply(&staged, Command::SetConfig)?;
+      if DeviceConfig::decode(&staged.payload).as_ref() != Some(&config) {
+        bail!("device staged a different configuration; not committing");
+      }
+      let committed = link.request(&Message::new(Command::Ack, id, &[]), timeout)?;
+      check_reply(&committed, Command::GetConfig)?;
+      let config = DeviceConfig::decode(&committed.payload).context("invalid GetConfig reply")?;
+      println!("committed {config:#X?}");
+    }
+    Cmd::Export { file } => {
+      let id = link.next_id();
+      link.send(&Message::new(Command::ConfigExport, id, &[]))?;
+      let blob = collect_fragments(&mut link, id, timeout, "export")?;
+      std::fs::write(&file, &blob).with_context(|| format!("writing {}", file.display()))?;
+      println!("saved {} bytes to {}", blob.len(), file.display());
+    }
+    Cmd::Import { file } => {
+      let blob = std::fs::read(&file).with_context(|| format!("reading {}", file.display()))?;
+      let chunks: Vec<&[u8]> = blob.chunks(max_payload).collect();
+      let fragments = u16::try_from(chunks.len()).context("configuration too large")?;
+      let id = link.next_id();
+      let (last, rest) = chunks.split_last().context("empty configuration file")?;
+      for (index, chunk) in rest.iter().enumerate() {
+        link.send(&Message {
+          command: Command::ConfigImport as u16,
+          id,
+          fragments,
+          fragment: index as u16,
+          payload: chunk.to_vec(),
+        })?;
+      }
+      let msg = Message {
+        command: Command::ConfigImport as u16,
+        id,
+        fragments,
+        fragment: fragments - 1,
+        payload: last.to_vec(),
+      };
+      check_reply(&link.request(&msg, timeout)?, Command::Ack)?;
+      println!("imported {} bytes from {}", blob.len(), file.display());
+    }
+    Cmd::Send { file, pace } => {
+      let data = std::fs::read(&file).with_context(|| format!("reading {}", file.display()))?;
+      let chunks: Vec<&[u8]> = data.chunks(max_payload).collect();
+      let fragments = u16::try_from(chunks.len()).context("file too large for one transfer")?;
+      let id = link.next_id();
+      let mut index = 0;
+      while index < chunks.len() {
+        let msg = Message {
+          command: Command::Raw as u16,
+          id,
+          fragments,
+          fragment: index as u16,
+          payload: chunks[index].to_vec(),
+        };
+        link.send(&msg)?;
+        // Retransmit the fragment if the device reports its queue full or sends the challenge to
+        // seal it with (the link has adopted it); abort on any other NAK
+        match link.poll(Duration::from_millis(pace))?.iter().find(|m| m.id == id && m.command == Command::Nak as u16) {
+          Some(nak) if nak_code(nak) == NakCode::QueueFull || secure::challenge(nak).is_some() => continue,
+          Some(nak) => bail!("fragment {index} rejected: {:?}", nak_code(nak)),
+          None => index += 1,
+        }
+      }
+      println!("sent {} bytes in {} fragments (id={id})", data.len(), fragments);
+    }
+    Cmd::Subscribe { topics } => {
+      let id = link.next_id();
+      let payload: Vec<u8> = topics.iter().flat_map(|t| t.to_le_bytes()).collect();
+      let reply = link.request(&Message::new(Command::Subscribe, id, &payload), timeout)?;
+      check_reply(&reply, Command::Ack)?;
+      println!("subscribed to {topics:04X?}; Ctrl-C to stop");
+      loop {
+        for msg in link.poll(timeout)? {
+          match Command::try_from(msg.command) {
+            Ok(Command::Publish) if msg.payload.len() >= 2 => {
+              let topic = u16::from_le_bytes([msg.payload[0], msg.payload[1]]);
+              println!("topic 0x{topic:04X}: {:02X?}", &msg.payload[2..]);
+            }
+            _ => print_message(&msg),
+          }
+        }
+      }
+    }
+    Cmd::Publish { topic, bytes } => {
+      let mut payload = topic.to_le_bytes().to_vec();
+      payload.extend(parse_hex(&bytes)?);
+      let id = link.next_id();
+      link.send(&Message::new(Command::Publish, id, &payload))?;
+      // Publish has no reply; only a NAK comes back
+      if let Some(nak) = link.poll(timeout)?.iter().find(|m| m.id == id && Command::try_from(m.command) == Ok(Command::Nak)) {
+        bail!("device NAK: {:?}", nak_code(nak));
+      }
+      println!("published {} bytes on topic 0x{topic:04X}", payload.len() - 2);
+    }
+    Cmd::SetKey { new_key } => {
+      // The device switches before replying, so its reply is sealed under the new key
+      let id = link.next_id();
+      link.send(&Message::new(Command::SetKey, id, &new_key))?;
+      link.set_key(Some(&new_key));
+      let reply = link.poll(timeout)?.into_iter().find(|m| m.id == id).context("no reply under the new key")?;
+      check_reply(&reply, Command::SetKey)?;
+      println!("key installed; pass it with --key from now on");
+    }
+    Cmd::Hil { count, seed } => {
+      let seed = seed.unwrap_or_else(|| std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(1, |t| t.as_nanos() as u64));
+      println!("hil: {count} frames, seed {seed}");
+      let report = hil::run(
+        &mut link,
+        &HilConfig {
+          count,
+          seed,
+          max_payload,
+          timeout,
+        },
+      )?;
+      println!(
+        "frames {}: {} echoed, {} corrupted ({} NAKed, device counted {}), {} written in pieces, {} fragmented messages",
+        report.frames, report.echoed, report.corrupted, report.naked, report.fcs_errors, report.split, report.fragmented
+      );
+      println!(
+        "throughput {:.1} kB/s ({} payload bytes in {:.2} s), round trip min/avg/max {:.1}/{:.1}/{:.1} ms",
+        report.throughput() / 1000.0,
+        report.bytes,
+        report.elapsed.as_secs_f64(),
+        report.rtt_min.as_secs_f64() * 1000.0,
+        report.rtt_avg().as_secs_f64() * 1000.0,
+        report.rtt_max.as_secs_f64() * 1000.0
+      );
+      for failure in &report.failures {
+        println!("FAIL {failure}");
+      }
+      if !report.passed() {
+        bail!("{} checks failed (replay with --seed {seed})", report.failures.len());
+      }
+      println!("PASS");
+    }
+  }
+  Ok(())
+}
+
+fn parse_hex(bytes: &[String]) -> Result<Vec<u8>> {
+  bytes
+    .iter()
+    .map(|b| u8::from_str_radix(b.trim_start_matches("0x"), 16).with_context(|| format!("invalid hex byte '{b}'")))
+    .collect()
+}
+
+fn parse_u32(value: &str) -> Result<u32> {
+  match value.strip_prefix("0x") {
+    Some(hex) => u32::from_str_radix(hex, 16),
+    None => value.parse(),
+  }
+  .with_context(|| format!("invalid number '{value}'"))
+}
+
+fn parse_topic(value: &str) -> Result<u16> {
+  u16::try_from(parse_u32(value)?).with_context(|| format!("topic id '{value}' out of range"))
+}
+
+fn parse_address(value: &str) -> Result<u8> {
+  u8::try_from(parse_u32(value)?).with_context(|| format!("address '{value}' out of range"))
+}
+
+fn get_config<P: std::io::Read + std::io::Write>(link: &mut Link<P>, timeout: Duration) -> Result<DeviceConfig> {
+  let id = link.next_id();
+  let reply = link.request(&Message::new(Command::GetConfig, id, &[]), timeout)?;
+  check_reply(&reply, Command::GetConfig)?;
+  DeviceConfig::decode(&reply.payload).context("invalid GetConfig reply")
+}
+
+// Gather a fragmented reply to `id` and join the payloads in fragment order
+fn collect_fragments<P: std::io::Read + std::io::Write>(link: &mut Link<P>, id: u8, timeout: Duration, what: &str) -> Result<Vec<u8>> {
+  let mut fragments: Vec<Message> = link.poll(timeout)?.into_iter().filter(|m| m.id == id).collect();
+  if let Some(nak) = fragments.iter().find(|m| m.command == Command::Nak as u16) {
+    bail!("device NAK: {:?}", nak_code(nak));
+  }
+  fragments.sort_by_key(|m| m.fragment);
+  let expected = fragments.first().map_or(0, |m| m.fragments as usize);
+  if expected == 0 || fragments.len() != expected {
+    bail!("incomplete {what}: {} of {expected} fragments", fragments.len());
+  }
+  Ok(fragments.iter().flat_map(|m| m.payload.iter().copied()).collect())
+}
+
+fn nak_code(msg: &Message) -> NakCode {
+  NakCode::from(msg.payload.first().copied().unwrap_or(0))
+}
+
+fn check_reply(reply: &Message, expected: Command) -> Result<()> {
+  match Command::try_from(reply.command) {
+    Ok(cmd) if cmd == expected => Ok(()),
+    Ok(Command::Nak) => bail!("device NAK: {:?}", nak_code(reply)),
+    _ => bail!("unexpected reply command 0x{:04X}", reply.command),
+  }
+}
+
+fn print_message(msg: &Message) {
+  match Command::try_from(msg.command) {
+    Ok(Command::Nak) => println!("NAK id={} {:?}", msg.id, nak_code(msg)),
+    Ok(cmd) => println!("{cmd:?} id={} payload={:02X?}", msg.id, msg.payload),
+    Err(raw) => println!("0x{raw:04X} id={} payload={:02X?}", msg.id, msg.payload),
+  }
+}
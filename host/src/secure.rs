@@ -0,0 +1,141 @@
+//! Comms payload sealing (AES-128-CCM, host side)
+// Mirrors `src/protocol/secure.rs` in the firmware (feature `comm_crypto`).
+//
+// Sealed payload: nonce (13: direction, salt[8], counter u32 LE) | ciphertext | tag (8).
+// Associated data: command u16, id, fragments u16, fragment u16 (little-endian).
+// Our salt starts with the device's challenge, learned from the NAK `AuthFailed` (code, id,
+// challenge) it sends to a session it did not open.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aes::Aes128;
+use anyhow::{Context, Result, bail};
+use ccm::Ccm;
+use ccm::aead::generic_array::GenericArray;
+use ccm::aead::{AeadInPlace, KeyInit};
+use ccm::consts::{U8, U13};
+
+use crate::comm::{COMMS_MAX_PAYLOAD, Command, Message, NakCode};
+
+pub const KEY_LEN: usize = 16;
+pub const NONCE_LEN: usize = 13;
+pub const TAG_LEN: usize = 8;
+pub const SECURE_OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+/// Largest payload that still fits in a message once sealed
+pub const SECURE_MAX_PLAINTEXT: usize = COMMS_MAX_PAYLOAD - SECURE_OVERHEAD;
+/// Leading salt bytes taken from the device's challenge
+pub const CHALLENGE_LEN: usize = 4;
+
+const DIRECTION_HOST: u8 = b'H';
+
+pub type Key = [u8; KEY_LEN];
+
+type Aes128Ccm = Ccm<Aes128, U8, U13>;
+
+/// Parse a 128-bit key written as 32 hex digits
+pub fn parse_key(hex: &str) -> Result<Key> {
+  let hex = hex.trim_start_matches("0x");
+  if hex.len() != 2 * KEY_LEN {
+    bail!("key must be {} hex digits", 2 * KEY_LEN);
+  }
+  let mut key = [0u8; KEY_LEN];
+  for (i, byte) in key.iter_mut().enumerate() {
+    *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).with_context(|| format!("invalid hex in key '{hex}'"))?;
+  }
+  Ok(key)
+}
+
+/// Challenge carried by a NAK `AuthFailed`: the device refused our session and expects one
+/// opened with it
+pub fn challenge(msg: &Message) -> Option<[u8; CHALLENGE_LEN]> {
+  let [code, _, challenge @ ..] = msg.payload.as_slice() else {
+    return None;
+  };
+  let is_auth_nak = msg.command == Command::Nak as u16 && NakCode::from(*code) == NakCode::AuthFailed;
+  challenge.try_into().ok().filter(|_| is_auth_nak)
+}
+
+/// One host session under a key: our salt and counter, and the device's last nonce
+pub struct Session {
+  cipher: Aes128Ccm,
+  salt: [u8; 8],
+  counter: u32,
+  peer: Option<([u8; 8], u32)>,
+}
+
+impl Session {
+  /// New session; the device refuses it until `rechallenge` adopts its challenge
+  pub fn new(key: &Key) -> Self {
+    let mut session = Self {
+      cipher: Aes128Ccm::new(GenericArray::from_slice(key)),
+      salt: [0; 8],
+      counter: 0,
+      peer: None,
+    };
+    session.rechallenge([0; CHALLENGE_LEN]);
+    session
+  }
+
+  /// Start over with a salt opening with the device's `challenge`; the rest is the time in ns, so
+  /// sessions under the same challenge never share nonces
+  pub fn rechallenge(&mut self, challenge: [u8; CHALLENGE_LEN]) {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u32);
+    self.salt[..CHALLENGE_LEN].copy_from_slice(&challenge);
+    self.salt[CHALLENGE_LEN..].copy_from_slice(&nanos.to_le_bytes());
+    self.counter = 0;
+  }
+
+  /// Seal the payload of an outgoing message
+  pub fn seal(&mut self, msg: &Message) -> Result<Message> {
+    if msg.payload.len() > SECURE_MAX_PLAINTEXT {
+      bail!("payload of {} bytes too long to seal (max {SECURE_MAX_PLAINTEXT})", msg.payload.len());
+    }
+    self.counter = self.counter.checked_add(1).context("session counter exhausted")?;
+    let mut nonce = vec![DIRECTION_HOST];
+    nonce.extend_from_slice(&self.salt);
+    nonce.extend_from_slice(&self.counter.to_le_bytes());
+    let mut body = msg.payload.clone();
+    let tag = self
+      .cipher
+      .encrypt_in_place_detached(GenericArray::from_slice(&nonce), &aad(msg), &mut body)
+      .map_err(|_| anyhow::anyhow!("sealing failed"))?;
+    let mut sealed = msg.clone();
+    sealed.payload = [nonce.as_slice(), &body, &tag].concat();
+    Ok(sealed)
+  }
+
+  /// Check and decrypt the payload of an incoming message (Err: unauthenticated or replayed)
+  pub fn open(&mut self, msg: &Message) -> Result<Message> {
+    if msg.payload.len() < SECURE_OVERHEAD {
+      bail!("message id {} is not sealed", msg.id);
+    }
+    let (nonce, rest) = msg.payload.split_at(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+    let mut body = ciphertext.to_vec();
+    self
+      .cipher
+      .decrypt_in_place_detached(GenericArray::from_slice(nonce), &aad(msg), &mut body, GenericArray::from_slice(tag))
+      .map_err(|_| anyhow::anyhow!("message id {} failed authentication", msg.id))?;
+    let salt: [u8; 8] = nonce[1..9].try_into().unwrap();
+    let counter = u32::from_le_bytes(nonce[9..].try_into().unwrap());
+    if let Some((last_salt, last)) = self.peer
+      && last_salt == salt
+      && counter <= last
+    {
+      bail!("message id {} replayed", msg.id);
+    }
+    self.peer = Some((salt, counter));
+    let mut opened = msg.clone();
+    opened.payload = body;
+    Ok(opened)
+  }
+}
+
+fn aad(msg: &Message) -> [u8; 7] {
+  let mut out = [0u8; 7];
+  out[..2].copy_from_slice(&msg.command.to_le_bytes());
+  out[2] = msg.id;
+  out[3..5].copy_from_slice(&msg.fragments.to_le_bytes());
+  out[5..].copy_from_slice(&msg.fragment.to_le_bytes());
+  out
+}
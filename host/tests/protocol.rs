@@ -0,0 +1,230 @@
+//! Host protocol tests against frames produced by the firmware encoder (see `tests/hdlc.rs`)
+
+use embassy_stm32_starter_host::comm::{Addressing, BROADCAST, COMMS_COMPRESSED, Command, DeviceConfig, Message};
+use embassy_stm32_starter_host::hdlc::{self, Deframer, HdlcError};
+use embassy_stm32_starter_host::hil::{self, HilConfig};
+use embassy_stm32_starter_host::link::Link;
+use embassy_stm32_starter_host::secure::{self, CHALLENGE_LEN, Session, parse_key};
+use embassy_stm32_starter_host::{heatshrink, image};
+
+// Raw (id=2) payload [0xD8, 0x01]
+const RAW_FRAME: &[u8] = &[0x7E, 0x04, 0x00, 0x02, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0xD8, 0x01, 0xC0, 0xF2, 0x7E];
+// Raw (id=0xDF) payload [0xDF, 0x00] - FCS low byte 0x7E is escaped
+const ESCAPED_FCS_FRAME: &[u8] = &[0x7E, 0x04, 0x00, 0xDF, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0xDF, 0x00, 0xB3, 0x7D, 0x5E, 0x7E];
+
+#[test]
+fn encodes_like_firmware() {
+  let msg = Message::new(Command::Raw, 2, &[0xD8, 0x01]);
+  assert_eq!(hdlc::frame(&msg.encode()), RAW_FRAME);
+  assert_eq!(hdlc::frame(&Message::new(Command::Raw, 0xDF, &[0xDF, 0x00]).encode()), ESCAPED_FCS_FRAME);
+}
+
+#[test]
+fn deframes_noise_shared_flags_and_splits() {
+  let mut stream = vec![0x00, 0x55, 0x7D];
+  stream.extend_from_slice(RAW_FRAME);
+  stream.extend_from_slice(&ESCAPED_FCS_FRAME[1..]); // shares the previous closing flag
+
+  let mut deframer = Deframer::new();
+  let (a, b) = stream.split_at(7);
+  let mut frames = deframer.push(a);
+  frames.extend(deframer.push(b));
+
+  let messages: Vec<Message> = frames.into_iter().map(|f| Message::decode(&f.unwrap()).unwrap()).collect();
+  assert_eq!(messages.len(), 2);
+  assert_eq!(messages[0].payload, [0xD8, 0x01]);
+  assert_eq!(messages[1].id, 0xDF);
+}
+
+#[test]
+fn reports_fcs_mismatch() {
+  let mut corrupted = RAW_FRAME.to_vec();
+  corrupted[3] ^= 0x01;
+  let frames = Deframer::new().push(&corrupted);
+  assert!(matches!(frames[..], [Err(HdlcError::FcsMismatch { .. })]));
+}
+
+#[test]
+fn device_config_roundtrip_and_crc() {
+  let config = DeviceConfig {
+    baud: 921_600,
+    device_id: 7,
+    flags: 0x3,
+    address: 0x21,
+    rtc_ppm_tenths: -57,
+    user: vec![0xDE, 0xAD],
+  };
+  let mut bytes = config.encode();
+  assert_eq!(bytes.len(), 20 + 2 + 2);
+  assert_eq!(DeviceConfig::decode(&bytes), Some(config));
+  bytes[8] ^= 0x01;
+  assert_eq!(DeviceConfig::decode(&bytes), None);
+}
+
+#[test]
+fn addressed_header_for_shared_bus() {
+  let addressing = Addressing { host: 0x00, node: 0x03 };
+  let msg = Message::new(Command::Raw, 2, &[0xD8, 0x01]);
+  let wrapped = addressing.wrap(&msg.encode());
+  // src, dst, hops after the id
+  assert_eq!(wrapped[..7], [0x04, 0x00, 0x02, 0x00, 0x03, 0x00, 0x01]);
+  // The node's reply comes back with src and dst swapped
+  let mut reply = wrapped.clone();
+  reply[3..5].copy_from_slice(&[0x03, 0x00]);
+  assert_eq!(addressing.unwrap(&reply).and_then(|b| Message::decode(&b)), Some(msg));
+  // Replies from other nodes, and traffic between them, are not ours
+  reply[3] = 0x04;
+  assert_eq!(addressing.unwrap(&reply), None);
+  reply[3..5].copy_from_slice(&[0x03, 0x05]);
+  assert_eq!(addressing.unwrap(&reply), None);
+  // Talking to every node takes a reply from any of them
+  reply[4] = 0x00;
+  assert!(Addressing { host: 0x00, node: BROADCAST }.unwrap(&reply).is_some());
+}
+
+#[test]
+fn compresses_like_firmware() {
+  // Same bit stream as `protocol::heatshrink` (see tests/host/heatshrink.rs)
+  assert_eq!(heatshrink::compress(b"aaaa"), [0xB0, 0x80, 0x08]);
+  let log: Vec<u8> = b"t=1024 temp=21.5 rh=40\n".iter().cycle().take(200).copied().collect();
+  let msg = Message::new(Command::Raw, 9, &log);
+  let compressed = msg.compressed().unwrap();
+  assert_eq!(compressed.command, Command::Raw as u16 | COMMS_COMPRESSED);
+  assert!(compressed.payload.len() < 80);
+  assert_eq!(compressed.decompressed().unwrap(), msg);
+  // Short payloads go as they are
+  assert_eq!(Message::new(Command::Raw, 9, b"aaaa").compressed(), None);
+}
+
+// Stats reply (id=5, fragments=1, fragment=1) payload [1, 2, 3, 4] sealed by the firmware
+// (`protocol::secure`, key "0123456789abcdef", device salt 01 00 00 00 AA BB CC DD, counter 1)
+const SEALED_STATS: &[u8] = &[
+  0x44, 0x01, 0x00, 0x00, 0x00, 0xAA, 0xBB, 0xCC, 0xDD, 0x01, 0x00, 0x00, 0x00, 0xE6, 0x6A, 0xB4, 0x9C, 0x42, 0x00, 0x90, 0x0B, 0xDE, 0xEF, 0xDA, 0x33,
+];
+
+#[test]
+fn opens_firmware_sealed_payloads() {
+  let key = parse_key("30313233343536373839616263646566").unwrap();
+  let sealed = Message {
+    command: Command::Stats as u16,
+    id: 5,
+    fragments: 1,
+    fragment: 1,
+    payload: SEALED_STATS.to_vec(),
+  };
+  let mut session = Session::new(&key);
+  assert_eq!(session.open(&sealed).unwrap().payload, [1, 2, 3, 4]);
+  // Same counter again is a replay; a changed header fails authentication
+  assert!(session.open(&sealed).is_err());
+  let relabelled = Message { id: 6, ..sealed };
+  assert!(Session::new(&key).open(&relabelled).is_err());
+
+  // Host-sealed messages open under the same key
+  let ping = Message::new(Command::Ping, 9, b"hello");
+  let out = session.seal(&ping).unwrap();
+  assert_eq!(out.payload.len(), 5 + 21);
+  assert_eq!(Session::new(&key).open(&out).unwrap(), ping);
+}
+
+#[test]
+fn session_opens_with_the_device_challenge() {
+  let key = parse_key("30313233343536373839616263646566").unwrap();
+  let mut session = Session::new(&key);
+  // NAK AuthFailed (0x05) for id 9 with the device's challenge
+  let nak = Message::new(Command::Nak, 9, &[0x05, 9, 0xC1, 0xC2, 0xC3, 0xC4]);
+  let challenge = secure::challenge(&nak).unwrap();
+  assert_eq!(challenge, [0xC1, 0xC2, 0xC3, 0xC4]);
+  assert_eq!(secure::challenge(&Message::new(Command::Nak, 9, &[0x05, 9])), None);
+  assert_eq!(secure::challenge(&Message::new(Command::Nak, 9, &[0x03, 9, 1, 2, 3, 4])), None);
+
+  session.rechallenge(challenge);
+  let sealed = session.seal(&Message::new(Command::Ping, 10, &[])).unwrap();
+  // Nonce: direction, salt (challenge first), counter restarted at 1
+  assert_eq!(sealed.payload[0], b'H');
+  assert_eq!(sealed.payload[1..1 + CHALLENGE_LEN], challenge);
+  assert_eq!(sealed.payload[9..13], [1, 0, 0, 0]);
+}
+
+#[test]
+fn signs_images_for_the_firmware_check() {
+  let secret = [7u8; 32];
+  let firmware: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+  let signed = image::sign(&firmware, &secret).unwrap();
+  assert_eq!(signed.len(), firmware.len() + image::IMAGE_TRAILER_LEN);
+  // Trailer header: "SIGN", version 1, reserved, image length
+  assert_eq!(signed[1000..1012], [0x53, 0x49, 0x47, 0x4E, 0x01, 0x00, 0x00, 0x00, 0xE8, 0x03, 0x00, 0x00]);
+  assert_eq!(image::verify(&signed, &image::public_key(&secret)).unwrap(), 1000);
+
+  let mut patched = signed.clone();
+  patched[10] ^= 0x80;
+  assert!(image::verify(&patched, &image::public_key(&secret)).is_err());
+  assert!(image::verify(&signed, &image::public_key(&[8u8; 32])).is_err());
+  assert!(image::verify(&firmware, &image::public_key(&secret)).is_err());
+}
+
+// Stand-in for a board running the `hil` firmware: echoes what it receives, NAKs bad frames
+#[derive(Default)]
+struct EchoDevice {
+  deframer: Deframer,
+  out: std::collections::VecDeque<u8>,
+  fcs_errors: u32,
+}
+
+impl std::io::Write for EchoDevice {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    for frame in self.deframer.push(buf) {
+      let reply = match frame {
+        Ok(bytes) => {
+          let msg = Message::decode(&bytes).unwrap();
+          if msg.command == Command::Stats as u16 {
+            let mut stats = [0u8; 44];
+            stats[8..12].copy_from_slice(&self.fcs_errors.to_le_bytes());
+            Message::new(Command::Stats, msg.id, &stats)
+          } else {
+            msg
+          }
+        }
+        Err(_) => {
+          self.fcs_errors += 1;
+          Message::new(Command::Nak, 0, &[0x04, 0])
+        }
+      };
+      self.out.extend(hdlc::frame(&reply.encode()));
+    }
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+impl std::io::Read for EchoDevice {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    if self.out.is_empty() {
+      return Err(std::io::ErrorKind::TimedOut.into());
+    }
+    let n = buf.len().min(self.out.len());
+    for (b, out) in buf.iter_mut().zip(self.out.drain(..n)) {
+      *b = out;
+    }
+    Ok(n)
+  }
+}
+
+#[test]
+fn hil_run_passes_against_an_echo_device() {
+  let mut link = Link::new(EchoDevice::default());
+  let config = HilConfig {
+    count: 300,
+    seed: 7,
+    max_payload: 256,
+    timeout: std::time::Duration::from_millis(100),
+  };
+  let report = hil::run(&mut link, &config).unwrap();
+  assert!(report.passed(), "{:?}", report.failures);
+  assert!(report.frames >= 300);
+  assert!(report.corrupted > 0 && report.naked == report.corrupted && report.fcs_errors == report.corrupted);
+  assert!(report.split > 0 && report.fragmented > 0);
+  assert!(report.echoed > 0 && report.bytes > 0);
+}
@@ -1,25 +0,0 @@
-/* WWZMDiB STM32F103C8T6 Blue Pill */
-/*
-MEMORY
-{
-  FLASH (rx)      : ORIGIN = 0x08000000, LENGTH = 64K
-  RAM (rwx)       : ORIGIN = 0x20000000, LENGTH = 20K
-}
-*/
-/* STM32F446RE (Nucleo-64) */
-/*
-MEMORY
-{
-  FLASH (rx)      : ORIGIN = 0x08000000, LENGTH = 512K
-  RAM (rwx)       : ORIGIN = 0x20000000, LENGTH = 128K
-}
-*/
-
-/* STM32F413ZH (Nucleo-144) */
-/*
-MEMORY
-{
-  FLASH (rx)      : ORIGIN = 0x08000000, LENGTH = 1536K
-  RAM (rwx)       : ORIGIN = 0x20000000, LENGTH = 320K
-}
-*/
@@ -0,0 +1,94 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use embassy_executor::Spawner;
+use embassy_stm32::adc::Adc;
+use embassy_stm32::peripherals::{ADC1, PA0};
+use embassy_stm32_starter::board::BoardConfig;
+use embassy_stm32_starter::common::buildinfo;
+use embassy_stm32_starter::hardware::display::{self, STATUS_LINES, Ssd1306Display, StatusLine, StatusScreen};
+use embassy_stm32_starter::hardware::{SharedBus, Timing};
+use embassy_stm32_starter::service::comm;
+use embassy_stm32_starter::service::status_led::{self, Pattern, status_led_task};
+use embassy_stm32_starter::*;
+use embassy_time::Instant;
+
+/// Screen refresh interval
+const REFRESH_MS: u64 = 500;
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+  info!("Display app starting");
+  buildinfo::log();
+  info!("Board: {}", BoardConfig::BOARD_NAME);
+
+  let p = embassy_stm32::init(BoardConfig::embassy_config());
+  let (led, _button, mut wdt, _rtc, comm) = BoardConfig::init_all_hardware(spawner, p);
+  spawner.spawn(status_led_task(led)).ok();
+  spawner.spawn(comm_task(comm)).ok();
+
+  // SSD1306 on I2C1 (Arduino D15 = SCL, D14 = SDA); the analog input is A0 (PA0)
+  let bus = SharedBus::blocking_i2c(BoardConfig::init_i2c_blocking());
+  let adc = BoardConfig::init_supply_adc();
+  // SAFETY: PA0 is not claimed anywhere else in this application
+  let a0 = unsafe { PA0::steal() };
+  match display::ssd1306(SharedBus::blocking_i2c_device(bus)) {
+    Some(oled) => {
+      status_led::request(Pattern::SlowBlink);
+      spawner.spawn(display_task(oled, adc, a0)).ok();
+    }
+    None => {
+      error!("No SSD1306 found at 0x3C");
+      status_led::request(Pattern::Solid);
+    }
+  }
+
+  loop {
+    wdt.pet();
+    Timing::delay_ms(Timing::WATCHDOG_PET_MS).await;
+  }
+}
+
+/// Redraw uptime, the A0 reading and the comm counters
+#[embassy_executor::task]
+async fn display_task(mut oled: Ssd1306Display, mut adc: Adc<'static, ADC1>, mut a0: embassy_stm32::Peri<'static, PA0>) {
+  let screen = StatusScreen::mono();
+  loop {
+    let uptime = Instant::now().as_secs();
+    let raw = adc.blocking_read(&mut a0);
+    let stats = comm::stats();
+
+    let mut lines: [StatusLine; STATUS_LINES] = Default::default();
+    write!(lines[0], "{}", BoardConfig::MCU_NAME).ok();
+    write!(lines[1], "Up {:02}:{:02}:{:02}", uptime / 3600, uptime / 60 % 60, uptime % 60).ok();
+    write!(lines[2], "A0 {:4} ({} mV)", raw, raw as u32 * 3300 / 4095).ok();
+    write!(lines[3], "RX {} TX {}", stats.rx_frames, stats.tx_frames).ok();
+    write!(lines[4], "FCS err {}", stats.fcs_errors).ok();
+    write!(lines[5], "Parse err {}", stats.parse_errors).ok();
+    if !screen.show(&mut oled, &lines) {
+      warn!("Display update failed");
+    }
+    Timer::after_millis(REFRESH_MS).await;
+  }
+}
+
+/// Answer host requests so the comm counters move
+#[embassy_executor::task]
+async fn comm_task(mut tx: embassy_stm32::usart::UartTx<'static, embassy_stm32::mode::Async>) {
+  loop {
+    let mut tx_ref = &mut tx;
+    comm::send_pending(&mut tx_ref);
+    match comm::read() {
+      Some(msg) => {
+        status_led::activity();
+        if comm::handle_builtin(&mut tx_ref, &msg) {
+          // Built-in command (e.g. Stats) already answered
+        } else if comm::Command::try_from(msg.command) == Ok(comm::Command::Ping) {
+          comm::write_reply(&mut tx_ref, &msg);
+        }
+      }
+      None => Timer::after_millis(1).await,
+    }
+  }
+}
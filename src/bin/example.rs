@@ -2,18 +2,27 @@
 #![no_main]
 
 use embassy_executor::Spawner;
-use embassy_stm32::Config;
-use embassy_stm32_starter::board::BoardConfig;
+use embassy_stm32_starter::board::{BoardConfig, BoardConfiguration};
+use embassy_stm32_starter::common::buildinfo;
+use embassy_stm32_starter::common::random;
+use embassy_stm32_starter::common::system;
 use embassy_stm32_starter::common::tasks::*;
 use embassy_stm32_starter::hardware::Timing;
 use embassy_stm32_starter::hardware::flash;
 #[allow(unused_imports)]
 use embassy_stm32_starter::prelude::*;
+use embassy_stm32_starter::service::status_led::{self, Pattern, status_led_task};
 use embassy_stm32_starter::*;
 
 #[embassy_executor::main]
 async fn main(_spawner: Spawner) {
+  // A `Bootloader` request from the previous run jumps to the ROM bootloader from the reset state
+  system::check_bootloader_request();
+  // RTT must be set up before the first log line
+  #[cfg(feature = "rtt_control")]
+  let rtt_down = embassy_stm32_starter::service::rtt_control::init();
   info!("Example starting...");
+  buildinfo::log();
 
   // Log board configuration info
   info!("Running on {}", BoardConfig::BOARD_NAME);
@@ -25,60 +34,133 @@ async fn main(_spawner: Spawner) {
   );
   info!("LED: {} ({})", BoardConfig::LED_PIN_NAME, BoardConfig::LED_DESCRIPTION);
   info!("Button: {} ({})", BoardConfig::BUTTON_PIN_NAME, BoardConfig::BUTTON_DESCRIPTION);
+  info!(
+    "VCP: {}, ADC: {}, RNG: {}, 3 LEDs: {}, Ethernet: {}, DFSDM: {}",
+    BoardConfig::UART_FOR_VCP,
+    BoardConfig::HAS_ADC,
+    BoardConfig::HAS_RNG,
+    BoardConfig::HAS_THREE_LEDS,
+    BoardConfig::HAS_ETHERNET,
+    BoardConfig::HAS_DFSDM
+  );
 
-  let config = Config::default();
-  let p = embassy_stm32::init(config);
-  let (led, button, mut wdt, rtc, comm) = BoardConfig::init_all_hardware(_spawner, p);
+  // Heap before anything allocates
+  #[cfg(feature = "alloc")]
+  embassy_stm32_starter::common::heap::init();
+
+  let p = embassy_stm32::init(BoardConfig::embassy_config());
+  info!("RTC clock: {}", embassy_stm32_starter::hardware::rtc::clock_source());
+  // Black box: show what led up to the reset, then mark this boot
+  embassy_stm32_starter::diagnostics::eventlog::dump();
+  embassy_stm32_starter::diagnostics::eventlog::init();
+  info!("Boot {} since power-on", embassy_stm32_starter::diagnostics::reset::boots_since_power_on());
+  if let Some(reason) = system::last_reboot_reason() {
+    info!("Rebooted by the firmware: {}", reason);
+  }
+  let (led, mut button, mut wdt, rtc, comm) = BoardConfig::init_all_hardware(_spawner, p);
+  // Hold the user button through the first 3 s to reflash over the ROM bootloader
+  system::bootloader_if_held(&mut button, 3_000).await;
+  random::seed_from_uid();
+  // True random seed where the part has an RNG unit
+  #[cfg(feature = "has_rng")]
+  embassy_stm32_starter::hardware::rng::HwRng::new(BoardConfig::init_rng()).seed_shared();
+  // Start before the flash demo so its erase/program stalls are measured
+  #[cfg(feature = "cs_monitor")]
+  {
+    embassy_stm32_starter::diagnostics::cs_monitor::start(16_000_000); // default HSI clock
+    _spawner.spawn(embassy_stm32_starter::diagnostics::cs_monitor::report_task()).ok();
+  }
+  #[cfg(feature = "task_metrics")]
+  {
+    embassy_stm32_starter::diagnostics::task_metrics::start(16_000_000); // default HSI clock
+    _spawner.spawn(embassy_stm32_starter::diagnostics::task_metrics::report_task()).ok();
+  }
 
   // Demonstrate flash storage functionality
   flash_demo().await;
 
   _spawner.spawn(button_monitor(button)).ok();
   _spawner.spawn(rtc_clock(rtc)).ok();
-  _spawner.spawn(comm_task(comm, led)).ok();
+  _spawner.spawn(embassy_stm32_starter::diagnostics::stack::monitor_task()).ok();
+  // Health record every 10 s to the host and the log (uptime, reset cause, stack, heap, VDDA)
+  #[cfg(feature = "has_adc")]
+  {
+    use embassy_stm32_starter::service::telemetry::TelemetryOutput;
+    _spawner.spawn(telemetry_task(BoardConfig::init_supply_adc(), 10, TelemetryOutput::Both)).ok();
+  }
+  // Status LEDs: green heartbeat, blue comm activity, red alarms (single-LED boards: comm activity only)
+  let leds = BoardConfig::init_leds(led);
+  let activity_led = match leds.activity {
+    Some(blue) => {
+      _spawner.spawn(heartbeat(leds.heartbeat)).ok();
+      blue
+    }
+    None => leds.heartbeat,
+  };
+  if let Some(red) = leds.error {
+    _spawner.spawn(alarm_indicator(red, None)).ok();
+  }
+  _spawner.spawn(status_led_task(activity_led)).ok();
+  status_led::request(Pattern::SlowBlink);
+  _spawner.spawn(comm_task(comm)).ok();
+  _spawner.spawn(embassy_stm32_starter::service::comm::link_monitor_task(3_000)).ok();
+  _spawner.spawn(link_watch()).ok();
+  #[cfg(feature = "rtt_control")]
+  _spawner.spawn(embassy_stm32_starter::service::rtt_control::rtt_control_task(rtt_down)).ok();
 
   info!("U ready? U ain't ready!");
-  let mut last_sp: u32 = 0;
   loop {
-    // Print stack usage in KB only if changed
-    let sp: u32;
-    unsafe { core::arch::asm!("mov {}, sp", out(reg) sp) }
-    if sp > last_sp {
-      let stack_used = sp.saturating_sub(BoardConfig::RAM_START);
-      let stack_used_kb = (stack_used as u32) / 1024; // Explicitly cast stack_used to u32 before division to ensure no implicit type promotion
-      let stack_left = BoardConfig::RAM_END.saturating_sub(sp);
-      let stack_left_kb = stack_left / 1024;
-      info!("Stack used: {}/{} KB (SP: {=u32:x})", stack_used_kb, stack_used_kb + stack_left_kb, sp);
-      last_sp = sp;
-    }
-
     wdt.pet();
     Timing::delay_ms(Timing::WATCHDOG_PET_MS).await;
   }
 }
 
+/// Report host link changes (a real application would put its outputs in a safe state on Down)
+#[embassy_executor::task]
+async fn link_watch() {
+  loop {
+    match embassy_stm32_starter::service::comm::link_event().await {
+      embassy_stm32_starter::service::comm::LinkState::Up => info!("Host connected"),
+      embassy_stm32_starter::service::comm::LinkState::Down => warn!("Host gone quiet"),
+    }
+  }
+}
+
 #[embassy_executor::task]
-async fn comm_task(mut tx: embassy_stm32::usart::UartTx<'static, embassy_stm32::mode::Async>, mut led: embassy_stm32::gpio::Output<'static>) {
-  let mut last_fcs_error_count = 0u8;
+async fn comm_task(mut tx: embassy_stm32::usart::UartTx<'static, embassy_stm32::mode::Async>) {
+  let mut last_fcs_error_count = 0u32;
   loop {
+    #[cfg(feature = "task_metrics")]
+    let busy = embassy_stm32_starter::diagnostics::task_metrics::Iteration::begin(embassy_stm32_starter::diagnostics::task_metrics::Task::App);
+    // Send automatic replies (NAKs) queued by the receive path
+    let mut tx_ref = &mut tx;
+    embassy_stm32_starter::service::comm::send_pending(&mut tx_ref);
     // Try to read a message; if FCS error occurred, log it
     match embassy_stm32_starter::service::comm::read() {
       Some(msg) => {
-        led.set_high(); // Turn on the LED when a message is received
+        // Flash the status LED on each message; a good message also ends a comm error
+        status_led::activity();
+        status_led::release(Pattern::FastBlink);
         // *** Handle command(s) here *** //
-        if core::convert::TryFrom::try_from(msg.command) == Ok(embassy_stm32_starter::service::comm::Command::Ping) {
-          let mut tx_ref = &mut tx;
-          embassy_stm32_starter::service::comm::write(&mut tx_ref, &msg);
+        if embassy_stm32_starter::service::comm::handle_builtin(&mut tx_ref, &msg) {
+          // Built-in command (e.g. Stats) already answered
+        } else if core::convert::TryFrom::try_from(msg.command) == Ok(embassy_stm32_starter::service::comm::Command::Ping) {
+          embassy_stm32_starter::service::comm::write_reply(&mut tx_ref, &msg);
         }
       }
       None => {
         // Could be no message, or FCS error (already logged in comm.rs)
-        led.set_low(); // Turn off the LED when no message is received
+        // Held on from the RTT control channel
+        #[cfg(feature = "rtt_control")]
+        status_led::set(Pattern::Solid, embassy_stm32_starter::service::rtt_control::led_on());
         let fcs_errors = embassy_stm32_starter::service::comm::fcs_error_count();
         if fcs_errors != last_fcs_error_count {
           debug!("HDLC FCS error count: {}", fcs_errors);
           last_fcs_error_count = fcs_errors;
+          status_led::request(Pattern::FastBlink);
         }
+        #[cfg(feature = "task_metrics")]
+        drop(busy);
         Timer::after_millis(1).await; // backoff when no message is ready
       }
     }
@@ -0,0 +1,153 @@
+#![no_std]
+#![no_main]
+
+// Gateway: aggregates downstream nodes (RS-485 / radio) and exposes them upstream
+// over the ST-LINK VCP (USB CDC on the host PC). Requires the `comms_routing` feature.
+//
+// - Messages from the host addressed to a downstream node are forwarded on LINK_DOWNSTREAM, an
+//   nRF24L01+ radio (`hardware::nrf24`) whose peer is the downstream side of the hop.
+// - Telemetry (Raw) sent by downstream nodes to the gateway is relayed to the host with the
+//   node's address preserved in `src`, and the gateway tracks when each node was last heard.
+
+use embassy_executor::Spawner;
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::Output;
+use embassy_stm32_starter::board::BoardConfig;
+use embassy_stm32_starter::common::buildinfo;
+use embassy_stm32_starter::hardware::nrf24::{Nrf24, Nrf24CommTx, Nrf24Config};
+use embassy_stm32_starter::hardware::{BlockingSharedSpi, SharedBus, Timing};
+use embassy_stm32_starter::protocol::routing::LinkId;
+use embassy_stm32_starter::service::comm::{self, Command, Message};
+use embassy_stm32_starter::service::status_led::{self, Pattern, status_led_task};
+use embassy_stm32_starter::*;
+use embassy_time::Instant;
+use heapless::LinearMap;
+
+/// This gateway's node address
+const GATEWAY_ADDRESS: u8 = 0x01;
+/// Host PC address (upstream)
+const HOST_ADDRESS: u8 = 0x00;
+/// Downstream node addresses served by this gateway
+const DOWNSTREAM_NODES: [u8; 3] = [0x10, 0x11, 0x12];
+
+/// Link ids used in the routing table
+const LINK_UPSTREAM: LinkId = 0; // ST-LINK VCP
+const LINK_DOWNSTREAM: LinkId = 1; // nRF24L01+ radio
+
+/// Summary log interval
+const NODE_SUMMARY_INTERVAL_MS: u64 = 10_000;
+
+type Radio = Nrf24<BlockingSharedSpi, Output<'static>, ExtiInput<'static>>;
+
+/// Per-node bookkeeping
+#[derive(Clone, Copy)]
+struct NodeInfo {
+  last_seen: Instant,
+  messages: u32,
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+  info!("Gateway app starting");
+  buildinfo::log();
+  info!("Board: {}", BoardConfig::BOARD_NAME);
+
+  let p = embassy_stm32::init(BoardConfig::embassy_config());
+  let (led, _button, mut wdt, _rtc, comm) = BoardConfig::init_all_hardware(spawner, p);
+
+  comm::routing(|table| {
+    table.set_address(GATEWAY_ADDRESS);
+    table.add(HOST_ADDRESS, LINK_UPSTREAM).ok();
+    for node in DOWNSTREAM_NODES {
+      table.add(node, LINK_DOWNSTREAM).ok();
+    }
+  });
+  info!("Gateway 0x{:02X} serving {} downstream nodes", GATEWAY_ADDRESS, DOWNSTREAM_NODES.len());
+
+  spawner.spawn(status_led_task(led)).ok();
+  status_led::request(Pattern::SlowBlink);
+  spawner.spawn(gateway_task(comm)).ok();
+
+  // Downstream radio on the Arduino headers (SPI1 on D13/D12/D11, CSN D10, CE D7, IRQ D2)
+  let (spi, csn, ce, irq) = BoardConfig::init_nrf24();
+  let bus = SharedBus::blocking_spi(spi);
+  match Nrf24::new(SharedBus::blocking_spi_device(bus, csn), ce, irq, Nrf24Config::default()).await {
+    Ok(radio) => {
+      spawner.spawn(radio_task(radio)).ok();
+    }
+    Err(e) => warn!("No nRF24 radio ({}): downstream messages will be dropped", e),
+  }
+
+  loop {
+    wdt.pet();
+    Timing::delay_ms(Timing::WATCHDOG_PET_MS).await;
+  }
+}
+
+#[embassy_executor::task]
+async fn gateway_task(mut tx: embassy_stm32::usart::UartTx<'static, embassy_stm32::mode::Async>) {
+  let mut nodes: LinearMap<u8, NodeInfo, { DOWNSTREAM_NODES.len() }> = LinearMap::new();
+  let mut last_summary = Instant::now();
+  loop {
+    let mut tx_ref = &mut tx;
+    comm::send_pending(&mut tx_ref);
+
+    // Messages addressed to the gateway itself
+    if let Some(msg) = comm::read() {
+      status_led::activity();
+      if comm::handle_builtin(&mut tx_ref, &msg) {
+        // Built-in command (e.g. Stats) already answered
+      } else if Command::try_from(msg.command) == Ok(Command::Ping) {
+        let mut reply = msg.clone();
+        reply.src = GATEWAY_ADDRESS;
+        reply.dst = msg.src;
+        comm::write_reply(&mut tx_ref, &reply);
+      } else if Command::try_from(msg.command) == Ok(Command::Raw) && DOWNSTREAM_NODES.contains(&msg.src) {
+        // Downstream telemetry: record and relay to the host
+        let messages = nodes.get(&msg.src).map_or(0, |n| n.messages) + 1;
+        let node = NodeInfo {
+          last_seen: Instant::now(),
+          messages,
+        };
+        nodes.insert(msg.src, node).ok();
+        comm::write(&mut tx_ref, &relay(msg, HOST_ADDRESS));
+      }
+    }
+
+    // Messages passing through the gateway
+    while let Some((link, msg)) = comm::read_forward() {
+      match link {
+        LINK_UPSTREAM => comm::forward(&mut tx_ref, &msg),
+        LINK_DOWNSTREAM => downstream_write(&msg),
+        other => warn!("No transport for link {} (message id {})", other, msg.id),
+      }
+    }
+
+    if last_summary.elapsed().as_millis() >= NODE_SUMMARY_INTERVAL_MS {
+      last_summary = Instant::now();
+      for (addr, node) in nodes.iter() {
+        info!("Node 0x{:02X}: {} messages, last seen {} s ago", addr, node.messages, node.last_seen.elapsed().as_secs());
+      }
+    }
+
+    Timer::after_millis(1).await;
+  }
+}
+
+/// Re-address a message received by the gateway to `dst`, keeping the original sender
+fn relay(mut msg: Message, dst: u8) -> Message {
+  msg.dst = dst;
+  msg.hops = msg.hops.saturating_add(1);
+  msg
+}
+
+/// Send a message on the downstream radio (sent by `radio_task`)
+fn downstream_write(msg: &Message) {
+  comm::forward(&mut Nrf24CommTx, msg);
+}
+
+/// Carry frames over the radio hop; what arrives is routed like messages from the VCP
+#[embassy_executor::task]
+async fn radio_task(mut radio: Radio) {
+  radio.comm_link().await
+}
@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+// HIL: hardware-in-the-loop echo target for `comm hil` in `host/`.
+//
+// - Every Ping and Raw message (each fragment of a fragmented one too) comes back unchanged
+// - Built-in commands (Stats) are answered as usual; bad frames are NAKed by the receive path
+// - Nothing else runs, so the host measures the serial/HDLC stack alone
+
+use embassy_executor::Spawner;
+use embassy_stm32_starter::board::BoardConfig;
+use embassy_stm32_starter::common::buildinfo;
+use embassy_stm32_starter::hardware::Timing;
+use embassy_stm32_starter::service::comm::{self, Command, ErrorPolicy};
+use embassy_stm32_starter::service::status_led::{self, Pattern, status_led_task};
+use embassy_stm32_starter::*;
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+  info!("HIL echo target starting");
+  buildinfo::log();
+  info!("Board: {}", BoardConfig::BOARD_NAME);
+
+  let p = embassy_stm32::init(BoardConfig::embassy_config());
+  let (led, _button, mut wdt, _rtc, tx) = BoardConfig::init_all_hardware(spawner, p);
+  // The runner sends corrupted frames on purpose: resync, never reset
+  comm::set_error_policy(ErrorPolicy::Resync);
+
+  spawner.spawn(status_led_task(led)).ok();
+  status_led::request(Pattern::SlowBlink);
+  spawner.spawn(echo_task(tx)).ok();
+
+  loop {
+    wdt.pet();
+    Timing::delay_ms(Timing::WATCHDOG_PET_MS).await;
+  }
+}
+
+#[embassy_executor::task]
+async fn echo_task(mut tx: embassy_stm32::usart::UartTx<'static, embassy_stm32::mode::Async>) {
+  let mut echoed = 0u32;
+  loop {
+    // NAKs for bad frames queued by the receive path
+    let mut tx_ref = &mut tx;
+    comm::send_pending(&mut tx_ref);
+    match comm::read() {
+      Some(msg) => {
+        status_led::activity();
+        if comm::handle_builtin(&mut tx_ref, &msg) {
+          // Built-in command (e.g. Stats) already answered
+        } else if matches!(Command::try_from(msg.command), Ok(Command::Ping | Command::Raw)) {
+          comm::write_reply(&mut tx_ref, &msg);
+          echoed += 1;
+          if echoed % 1000 == 0 {
+            info!("{} messages echoed, {} FCS errors", echoed, comm::fcs_error_count());
+          }
+        }
+      }
+      None => Timer::after_millis(1).await,
+    }
+  }
+}
@@ -0,0 +1,79 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write as _;
+use embassy_executor::Spawner;
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::Output;
+use embassy_stm32_starter::board::BoardConfig;
+use embassy_stm32_starter::common::buildinfo;
+use embassy_stm32_starter::common::random::{self, Backoff, SharedRandom};
+use embassy_stm32_starter::hardware::lora::{LORA_MAX_PAYLOAD, LoraConfig, LoraError, Sx127x};
+use embassy_stm32_starter::hardware::{BlockingSharedSpi, SharedBus, Timing};
+use embassy_stm32_starter::*;
+use embassy_time::Instant;
+
+/// Seconds between beacons (randomized by up to a second so two nodes do not keep colliding)
+const BEACON_PERIOD_S: u64 = 5;
+
+type Radio = Sx127x<BlockingSharedSpi, Output<'static>, ExtiInput<'static>>;
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+  info!("LoRa P2P app starting");
+  buildinfo::log();
+  info!("Board: {}", BoardConfig::BOARD_NAME);
+  random::seed_from_uid();
+
+  let p = embassy_stm32::init(BoardConfig::embassy_config());
+  let (_led, _button, mut wdt, _rtc, _comm) = BoardConfig::init_all_hardware(spawner, p);
+
+  // SX1276 shield on the Arduino headers; change `LoraConfig::frequency_hz` to the module's band
+  let (name, sck, miso, mosi) = BoardConfig::LORA_SPI_PINS;
+  let (nss, reset_pin, dio0_pin) = BoardConfig::LORA_CONTROL_PINS;
+  info!("LoRa bus: {} (SCK {}, MISO {}, MOSI {})", name, sck, miso, mosi);
+  info!("LoRa pins: NSS {}, RESET {}, DIO0 {}", nss, reset_pin, dio0_pin);
+  let (spi, cs, reset, dio0) = BoardConfig::init_lora();
+  let bus = SharedBus::blocking_spi(spi);
+  match Sx127x::new(SharedBus::blocking_spi_device(bus, cs), reset, dio0, LoraConfig::default()).await {
+    Ok(radio) => {
+      spawner.spawn(beacon_task(radio)).ok();
+    }
+    Err(e) => error!("No LoRa radio found: {}", e),
+  }
+
+  loop {
+    wdt.pet();
+    Timing::delay_ms(Timing::WATCHDOG_PET_MS).await;
+  }
+}
+
+/// Send a numbered beacon every `BEACON_PERIOD_S` and log every packet heard in between; run it
+/// on two boards to see them hear each other
+#[embassy_executor::task]
+async fn beacon_task(mut radio: Radio) {
+  let node = random::uid_seed(0) as u16;
+  let mut count: u32 = 0;
+  let mut buf = [0u8; LORA_MAX_PAYLOAD];
+  loop {
+    let mut beacon = heapless::String::<32>::new();
+    write!(beacon, "node {:04x} #{}", node, count).ok();
+    match radio.send(beacon.as_bytes()).await {
+      Ok(()) => info!("LoRa: sent \"{}\"", beacon.as_str()),
+      Err(e) => warn!("LoRa: send failed: {}", e),
+    }
+    count = count.wrapping_add(1);
+
+    let next = Instant::now().as_millis() + Backoff::jitter_ms(&mut SharedRandom, BEACON_PERIOD_S * 1000, 1000);
+    while let Some(remaining) = next.checked_sub(Instant::now().as_millis()).filter(|&ms| ms > 0) {
+      match radio.receive(&mut buf, Some(remaining)).await {
+        Ok(rx) => {
+          let data = &buf[..rx.len.min(buf.len())];
+          info!("LoRa: received {=[u8]:a} (RSSI {} dBm, SNR {} dB/4)", data, rx.rssi_dbm, rx.snr_quarter_db);
+        }
+        Err(LoraError::Timeout) => {}
+        Err(e) => warn!("LoRa: receive failed: {}", e),
+      }
+    }
+  }
+}
@@ -2,25 +2,118 @@
 #![no_main]
 
 use embassy_executor::Spawner;
-use embassy_stm32::Config;
-use embassy_stm32::gpio::Output;
+use embassy_stm32::gpio::{Level, Output};
 use embassy_stm32_starter::board::BoardConfig;
+use embassy_stm32_starter::common::fsm::{Fsm, Machine};
+use embassy_stm32_starter::common::{buildinfo, executor};
 use embassy_stm32_starter::hardware::{GpioDefaults, Timing};
+#[cfg(feature = "cbor")]
+use embassy_stm32_starter::protocol::cbor::{self, Decode, Encode};
+use embassy_stm32_starter::service::comm::{ErrorPolicy, LinkState};
+use embassy_stm32_starter::service::failsafe::{self, PvdThreshold};
+use embassy_stm32_starter::service::status_led::{self, Pattern, status_led_task};
 use embassy_stm32_starter::*;
 
+/// Typed pin command (`cbor` feature): the Raw payload `[pin, on]`, e.g. `82 08 F5` = D8 HIGH
+#[cfg(feature = "cbor")]
+#[derive(Encode, Decode)]
+struct PinCommand {
+  #[n(0)]
+  pin: u8,
+  #[n(1)]
+  on: bool,
+}
+
+/// D8 is released (`service::failsafe`) when the host has sent nothing for this long
+const LINK_TIMEOUT_MS: u32 = 5_000;
+
+/// Relay modes
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+enum Mode {
+  /// D8 low
+  Idle,
+  /// D8 high
+  Active,
+  /// Host link lost while in use: D8 held low (the fail-safe already released it) until the
+  /// host is back or the button acknowledges the fault
+  Fault,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+enum Input {
+  /// Debounced button press
+  Button,
+  /// D8 level requested by the host
+  Command(bool),
+  LinkDown,
+  LinkUp,
+}
+
+/// D8 and the status LED follow the mode
+struct Relay {
+  d8: Output<'static>,
+}
+
+impl Machine for Relay {
+  type State = Mode;
+  type Event = Input;
+
+  fn transition(&self, mode: Mode, input: &Input) -> Option<Mode> {
+    match (mode, *input) {
+      (Mode::Idle, Input::Button) => Some(Mode::Active),
+      (Mode::Active, Input::Button) => Some(Mode::Idle),
+      // A command implies the link is up again, so it also clears a fault
+      (_, Input::Command(on)) => Some(if on { Mode::Active } else { Mode::Idle }),
+      (Mode::Active | Mode::Idle, Input::LinkDown) => Some(Mode::Fault),
+      (Mode::Fault, Input::LinkUp | Input::Button) => Some(Mode::Idle),
+      _ => None,
+    }
+  }
+
+  fn enter(&mut self, mode: Mode) {
+    match mode {
+      Mode::Active => self.d8.set_high(),
+      Mode::Idle => self.d8.set_low(),
+      Mode::Fault => {
+        self.d8.set_low();
+        status_led::request(Pattern::FastBlink);
+      }
+    }
+  }
+
+  fn exit(&mut self, mode: Mode) {
+    if mode == Mode::Fault {
+      status_led::request(Pattern::SlowBlink);
+    }
+  }
+}
+
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
   info!("Relay app starting");
+  buildinfo::log();
   info!("Board: {}", BoardConfig::BOARD_NAME);
 
-  let p = embassy_stm32::init(Config::default());
+  let p = embassy_stm32::init(BoardConfig::embassy_config());
+  // Serial RX and the fail-safe run on the interrupt executor, ahead of the thread-mode tasks
+  let high = executor::start_high_priority();
   let (led, button, mut wdt, _rtc, comm) = BoardConfig::init_all_hardware(spawner, p);
 
   // Create D8 output (Arduino D8 = PA9 on Nucleo-F446RE)
   let p2 = unsafe { embassy_stm32::Peripherals::steal() };
+  // D8 drops to LOW on host link loss, a stalled watchdog, a hard fault or a brown-out
+  failsafe::register(&*p2.PA9, Level::Low).ok();
+  failsafe::enable_brownout(PvdThreshold::V2_7);
   let d8 = Output::new(p2.PA9, GpioDefaults::LED_LEVEL, GpioDefaults::LED_SPEED);
 
-  spawner.spawn(operation_task(comm, led, d8, button)).ok();
+  // Resync on bad frames; only a link that stays corrupt (10 FCS errors within 10 s) resets the board
+  embassy_stm32_starter::service::comm::set_error_policy(ErrorPolicy::ResetAfter { errors: 10, window_ms: 10_000 });
+
+  spawner.spawn(status_led_task(led)).ok();
+  status_led::request(Pattern::SlowBlink);
+  spawner.spawn(embassy_stm32_starter::service::comm::link_monitor_task(LINK_TIMEOUT_MS)).ok();
+  high.spawn(failsafe::failsafe_task()).ok();
+  spawner.spawn(operation_task(comm, d8, button)).ok();
 
   loop {
     wdt.pet();
@@ -31,14 +124,19 @@ async fn main(spawner: Spawner) {
 #[embassy_executor::task]
 async fn operation_task(
   mut tx: embassy_stm32::usart::UartTx<'static, embassy_stm32::mode::Async>,
-  mut led: embassy_stm32::gpio::Output<'static>,
-  mut d8: embassy_stm32::gpio::Output<'static>,
+  d8: embassy_stm32::gpio::Output<'static>,
   mut button: embassy_stm32::gpio::Input<'static>,
 ) {
-  let mut last_fcs = 0u8;
-  d8.set_low();
+  let mut last_fcs = 0u32;
+  let mut relay = Fsm::new("relay", Relay { d8 }, Mode::Idle);
   let mut btn_state = button.is_high();
+  let mut link = embassy_stm32_starter::service::comm::link_state();
   loop {
+    let state = embassy_stm32_starter::service::comm::link_state();
+    if state != link {
+      link = state;
+      relay.handle(if state == LinkState::Up { Input::LinkUp } else { Input::LinkDown });
+    }
     // Debounced button edge: on press, toggle D8
     let cur = button.is_high();
     if cur != btn_state {
@@ -47,26 +145,40 @@ async fn operation_task(
       if confirm == cur {
         btn_state = cur;
         if btn_state {
-          d8.toggle();
+          relay.handle(Input::Button);
         }
       }
     }
+    // Send automatic replies (NAKs) queued by the receive path
+    let mut tx_ref = &mut tx;
+    embassy_stm32_starter::service::comm::send_pending(&mut tx_ref);
     match embassy_stm32_starter::service::comm::read() {
       Some(msg) => {
-        led.set_high();
-        if core::convert::TryFrom::try_from(msg.command) == Ok(embassy_stm32_starter::service::comm::Command::Ping) {
-          let mut tx_ref = &mut tx;
-          embassy_stm32_starter::service::comm::write(&mut tx_ref, &msg);
+        status_led::activity();
+        if embassy_stm32_starter::service::comm::handle_builtin(&mut tx_ref, &msg) {
+          // Built-in command (e.g. Stats) already answered
+        } else if core::convert::TryFrom::try_from(msg.command) == Ok(embassy_stm32_starter::service::comm::Command::Ping) {
+          embassy_stm32_starter::service::comm::write_reply(&mut tx_ref, &msg);
         } else if core::convert::TryFrom::try_from(msg.command) == Ok(embassy_stm32_starter::service::comm::Command::Raw) {
+          #[cfg(feature = "cbor")]
+          if let Ok(cmd) = cbor::from_payload::<PinCommand>(&msg.payload) {
+            if cmd.pin == 8 {
+              info!("D8 command: {} (from comms, CBOR)", if cmd.on { "HIGH" } else { "LOW" });
+              relay.handle(Input::Command(cmd.on));
+            } else {
+              info!("Pin command for unknown pin D{} (ignored)", cmd.pin);
+            }
+            continue;
+          }
           if msg.payload.len() >= 2 && msg.payload[0] == 0xD8 {
             match msg.payload[1] {
               1 => {
                 info!("D8 command: HIGH (from comms)");
-                d8.set_high()
+                relay.handle(Input::Command(true));
               }
               0 => {
                 info!("D8 command: LOW (from comms)");
-                d8.set_low()
+                relay.handle(Input::Command(false));
               }
               other => {
                 info!("D8 command: unknown value {} (ignored)", other);
@@ -76,12 +188,10 @@ async fn operation_task(
         }
       }
       None => {
-        led.set_low();
         let fcs = embassy_stm32_starter::service::comm::fcs_error_count();
         if fcs != last_fcs {
           debug!("HDLC FCS errors: {}", fcs);
           last_fcs = fcs;
-          cortex_m::peripheral::SCB::sys_reset();
         }
         Timer::after_millis(1).await;
       }
@@ -0,0 +1,79 @@
+#![no_std]
+#![no_main]
+
+use embassy_executor::Spawner;
+use embassy_stm32_starter::board::BoardConfig;
+use embassy_stm32_starter::common::buildinfo;
+use embassy_stm32_starter::hardware::env_sensor::EnvSensor;
+use embassy_stm32_starter::hardware::flash::{self, Storage};
+use embassy_stm32_starter::hardware::{SharedBus, Timing};
+use embassy_stm32_starter::service::comm;
+use embassy_stm32_starter::service::config::{self, ConfigStore, config_task};
+use embassy_stm32_starter::service::scheduler::scheduler_task;
+use embassy_stm32_starter::service::sensors::sensors_task;
+use embassy_stm32_starter::service::status_led::{self, Pattern, status_led_task};
+use embassy_stm32_starter::*;
+
+/// Seconds between sensor readings
+const SENSOR_PERIOD_S: u64 = 10;
+
+// Mostly waiting for the next reading: sleep in WFI whenever no task is ready
+#[embassy_executor::main(executor = "embassy_stm32_starter::common::power::Executor")]
+async fn main(spawner: Spawner) {
+  info!("Sensor node starting");
+  buildinfo::log();
+  info!("Board: {}", BoardConfig::BOARD_NAME);
+
+  // Persistent configuration (device id, baud, ...) before the serial port is opened
+  let mut store = ConfigStore::open(Storage, BoardConfig::FLASH_STORAGE_SIZE as u32);
+  let node = config::load(&mut store);
+  info!("Node id: {} (storage at 0x{:08X})", node.device_id, flash::start());
+
+  let p = embassy_stm32::init(BoardConfig::embassy_config());
+  let (led, _button, mut wdt, _rtc, comm) = BoardConfig::init_all_hardware(spawner, p);
+  spawner.spawn(config_task(store)).ok();
+  spawner.spawn(status_led_task(led)).ok();
+  spawner.spawn(comm_task(comm)).ok();
+  // Calendar events (e.g. a daily reading upload) for the host, on topic SCHEDULE
+  spawner.spawn(scheduler_task()).ok();
+
+  // BME280 or SHT31 on I2C1 (Arduino D15 = SCL, D14 = SDA), shared so more devices can be added
+  let (name, scl, sda) = BoardConfig::I2C_PINS;
+  info!("Sensor bus: {} (SCL {}, SDA {})", name, scl, sda);
+  let bus = SharedBus::i2c(BoardConfig::init_i2c());
+  match EnvSensor::detect(SharedBus::i2c_device(bus)).await {
+    Ok(sensor) => {
+      status_led::request(Pattern::SlowBlink);
+      spawner.spawn(sensors_task(sensor, SENSOR_PERIOD_S)).ok();
+    }
+    Err(e) => {
+      error!("No environmental sensor found: {}", e);
+      status_led::request(Pattern::Solid);
+    }
+  }
+
+  loop {
+    wdt.pet();
+    Timing::delay_ms(Timing::WATCHDOG_PET_MS).await;
+  }
+}
+
+/// Send queued readings to the host and answer its requests
+#[embassy_executor::task]
+async fn comm_task(mut tx: embassy_stm32::usart::UartTx<'static, embassy_stm32::mode::Async>) {
+  loop {
+    let mut tx_ref = &mut tx;
+    comm::send_pending(&mut tx_ref);
+    match comm::read() {
+      Some(msg) => {
+        status_led::activity();
+        if comm::handle_builtin(&mut tx_ref, &msg) {
+          // Built-in command (e.g. Sensors, Stats) already answered
+        } else if comm::Command::try_from(msg.command) == Ok(comm::Command::Ping) {
+          comm::write_reply(&mut tx_ref, &msg);
+        }
+      }
+      None => Timer::after_millis(1).await,
+    }
+  }
+}
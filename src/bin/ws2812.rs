@@ -0,0 +1,50 @@
+#![no_std]
+#![no_main]
+
+use embassy_executor::Spawner;
+use embassy_stm32::peripherals::{DMA1_CH2, TIM3};
+use embassy_stm32_starter::board::BoardConfig;
+use embassy_stm32_starter::common::buildinfo;
+use embassy_stm32_starter::hardware::Timing;
+use embassy_stm32_starter::hardware::ws2812::{Rgb, Ws2812};
+use embassy_stm32_starter::*;
+
+/// LEDs on the strip (e.g. an 8-pixel stick)
+const PIXELS: usize = 8;
+/// Keep brightness low: a full-white pixel draws ~60 mA
+const BRIGHTNESS: u8 = 32;
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+  info!("WS2812 app starting");
+  buildinfo::log();
+  info!("Board: {}", BoardConfig::BOARD_NAME);
+
+  let p = embassy_stm32::init(BoardConfig::embassy_config());
+  let (_led, _button, mut wdt, _rtc, _comm) = BoardConfig::init_all_hardware(spawner, p);
+
+  // Strip data on PB4 (Arduino D5, TIM3_CH1); TIM3_UP is on DMA1 stream 2 for both boards
+  let p2 = unsafe { embassy_stm32::Peripherals::steal() };
+  let strip = Ws2812::new(p2.TIM3, p2.PB4, p2.DMA1_CH2);
+  spawner.spawn(rainbow_task(strip)).ok();
+
+  loop {
+    wdt.pet();
+    Timing::delay_ms(Timing::WATCHDOG_PET_MS).await;
+  }
+}
+
+/// Scroll a rainbow along the strip
+#[embassy_executor::task]
+async fn rainbow_task(mut strip: Ws2812<TIM3, DMA1_CH2>) {
+  let mut offset: u8 = 0;
+  let mut pixels = [Rgb::OFF; PIXELS];
+  loop {
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+      *pixel = Rgb::wheel(offset.wrapping_add((i * 256 / PIXELS) as u8)).dim(BRIGHTNESS);
+    }
+    strip.set_pixels(&pixels).await;
+    offset = offset.wrapping_add(2);
+    Timer::after_millis(20).await;
+  }
+}
@@ -0,0 +1,52 @@
+// Board selection: exactly one `board-*` cargo feature picks the board module at compile time
+//
+// Each board feature also enables the MCU family feature (`stm32f446`, `stm32f413`) and the
+// matching chip feature of embassy-stm32, so `cargo build --features board-nucleo-f446re` is all a
+// clean checkout needs. It also enables the board's `has_*` capability features, which must match
+// the `BoardConfiguration` HAS_* consts (checked below). `./setup <board>` records the choice in the `.cargo/config.toml` aliases.
+
+// Include the base trait definitions
+mod base;
+
+// Export the base traits for use by other modules
+pub use base::{BoardConfiguration, InterruptHandlers};
+
+#[cfg(feature = "board-nucleo-f446re")]
+mod nucleo_f446re;
+#[cfg(feature = "board-nucleo-f446re")]
+pub use nucleo_f446re::BoardConfig;
+
+#[cfg(feature = "board-nucleo144-f413zh")]
+mod nucleo144_f413zh;
+#[cfg(feature = "board-nucleo144-f413zh")]
+pub use nucleo144_f413zh::BoardConfig;
+
+#[cfg(not(any(feature = "board-nucleo-f446re", feature = "board-nucleo144-f413zh")))]
+compile_error!("select a board with a cargo feature: board-nucleo-f446re or board-nucleo144-f413zh");
+#[cfg(all(feature = "board-nucleo-f446re", feature = "board-nucleo144-f413zh"))]
+compile_error!("more than one board feature enabled; select exactly one");
+
+// The `has_*` features and the HAS_* consts describe the same board
+#[cfg(any(feature = "board-nucleo-f446re", feature = "board-nucleo144-f413zh"))]
+const _: () = {
+  assert!(
+    <BoardConfig as BoardConfiguration>::HAS_ADC == cfg!(feature = "has_adc"),
+    "HAS_ADC does not match the has_adc feature"
+  );
+  assert!(
+    <BoardConfig as BoardConfiguration>::HAS_THREE_LEDS == cfg!(feature = "has_three_leds"),
+    "HAS_THREE_LEDS does not match the has_three_leds feature"
+  );
+  assert!(
+    <BoardConfig as BoardConfiguration>::HAS_ETHERNET == cfg!(feature = "has_ethernet"),
+    "HAS_ETHERNET does not match the has_ethernet feature"
+  );
+  assert!(
+    <BoardConfig as BoardConfiguration>::HAS_RNG == cfg!(feature = "has_rng"),
+    "HAS_RNG does not match the has_rng feature"
+  );
+  assert!(
+    <BoardConfig as BoardConfiguration>::HAS_DFSDM == cfg!(feature = "has_dfsdm"),
+    "HAS_DFSDM does not match the has_dfsdm feature"
+  );
+};
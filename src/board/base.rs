@@ -1,7 +1,27 @@
 // Base board configuration module - defines the common interface for all board implementations
 
-/// Stub trait for board configuration
+/// Board identity and capabilities
+///
+/// The capability flags let shared binaries adapt to the board: `if BoardConfig::HAS_THREE_LEDS`
+/// for code that compiles everywhere, or the matching `has_*` cargo feature (enabled by the board
+/// feature) with `#[cfg]` where the code needs a peripheral the other boards lack (e.g.
+/// `BoardConfig::init_rng`). `board.rs` checks at compile time that both agree.
 pub trait BoardConfiguration {
+  /// ADC for the supply and temperature channels (`init_supply_adc`; `has_adc`)
+  const HAS_ADC: bool;
+  /// Green/blue/red user LEDs rather than one (`init_leds` fills in `activity`/`error`; `has_three_leds`)
+  const HAS_THREE_LEDS: bool;
+  /// Ethernet MAC and PHY for `service::net` (`has_ethernet`)
+  const HAS_ETHERNET: bool;
+  /// True random number generator (`init_rng`, `hardware::rng`; `has_rng`)
+  const HAS_RNG: bool;
+  /// DFSDM digital filter for PDM microphones (`init_microphone`, `hardware::dfsdm`; `has_dfsdm`)
+  const HAS_DFSDM: bool;
+  /// Name of the UART wired to the ST-LINK virtual COM port
+  const UART_FOR_VCP: &'static str;
+  /// The UART wired to the ST-LINK virtual COM port
+  type VcpUart: embassy_stm32::usart::Instance;
+
   fn board_name() -> &'static str;
 }
 
@@ -15,25 +15,65 @@
 // - User LED3 (LD3): PB14 (Red LED)
 // - User Button (B1): PC13 (Blue tactile button)
 //
-// Note: This board has 3 user LEDs, we'll use LD1 (Green) as the primary LED
+// Note: This board has 3 user LEDs; LD1 (Green) is the primary LED, `init_leds` adds LD2/LD3
 
 use super::{BoardConfiguration, InterruptHandlers};
-use crate::hardware::GpioDefaults;
-use crate::hardware::serial;
+use crate::common::power::PllSettings;
+use crate::hardware::bus;
+use crate::hardware::dfsdm::{MicConfig, Microphone};
+use crate::hardware::encoder::Encoder;
+use crate::hardware::onewire::OneWire;
+use crate::hardware::rtc::{self, RtcClock};
+use crate::hardware::serial::{self, SerialConfig, SerialMode};
+use crate::hardware::watchdog::{Watchdog, WatchdogConfig};
+use crate::hardware::{GpioDefaults, Leds, gpio};
+use crate::hardware::{lora, nrf24};
 use embassy_executor::Spawner;
-use embassy_stm32::gpio::{Input, Output};
-use embassy_stm32::mode::Async;
+use embassy_stm32::Peri;
+use embassy_stm32::adc::Adc;
+use embassy_stm32::crc::Crc;
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::{Input, Level, Output, Pin, Pull, Speed};
+use embassy_stm32::i2c::{self, I2c};
+use embassy_stm32::interrupt::{Interrupt, Priority};
+use embassy_stm32::mode::{Async, Blocking};
+use embassy_stm32::peripherals::{
+  ADC1, CRC, DMA1_CH0, DMA1_CH5, DMA1_CH6, DMA1_CH7, DMA2_CH1, EXTI15, I2C1, PA3, PA5, PA6, PA7, PB2, PB7, PB8, PB9, PB14, PC10, PC11, PC12, PD2, PD3, PD5, PD6, PD7, PD14, PE7,
+  PF6, PF7, PF8, PF9, PF13, PF15, PG6, QUADSPI, RNG, SPI1, SPI3, TIM3, USART2,
+};
+use embassy_stm32::qspi::enums::{AddressSize, ChipSelectHighTime, FIFOThresholdLevel, MemorySize};
+use embassy_stm32::qspi::{self, Qspi};
+use embassy_stm32::rng::{self, Rng};
 use embassy_stm32::rtc::{Rtc, RtcConfig};
-use embassy_stm32::usart::UartTx;
-use embassy_stm32::wdg::IndependentWatchdog;
+use embassy_stm32::spi::{self, Spi};
+use embassy_stm32::time::Hertz;
+use embassy_stm32::usart::{self, HalfDuplexConfig, HalfDuplexReadback, Uart, UartTx};
 
 use embassy_stm32::Config as EmbassyConfig;
+
+// Memory map shared with build.rs, which generates memory.x from it
+#[path = "nucleo144_f413zh_memory.rs"]
+mod memory;
+use embassy_stm32::bind_interrupts;
 // Advanced RCC configuration disabled for compatibility
 
+// RNG interrupt for `init_rng`
+bind_interrupts!(pub struct RngIrqs {
+  RNG => rng::InterruptHandler<RNG>;
+});
+
 pub struct BoardConfig;
 
 // Implement the minimal trait per base.rs
 impl BoardConfiguration for BoardConfig {
+  const HAS_ADC: bool = true;
+  const HAS_THREE_LEDS: bool = true;
+  const HAS_ETHERNET: bool = false;
+  const HAS_RNG: bool = true;
+  const HAS_DFSDM: bool = true;
+  const UART_FOR_VCP: &'static str = "USART3";
+  type VcpUart = embassy_stm32::peripherals::USART3;
+
   fn board_name() -> &'static str {
     "STM32 Nucleo-144 F413ZH"
   }
@@ -46,33 +86,73 @@ impl InterruptHandlers for BoardConfig {
 }
 
 impl BoardConfig {
-  /// Returns the default Embassy config (16 MHz HSI)
+  /// Returns the default Embassy config (16 MHz HSI) with the RTC on `RTC_CLOCK`
+  /// (falling back to the LSI if the LSE crystal does not start; see `hardware::rtc::ls_config`)
   /// Note: Advanced clock configuration disabled due to embassy-stm32 API changes
   pub fn embassy_config() -> EmbassyConfig {
-    EmbassyConfig::default()
+    let mut config = EmbassyConfig::default();
+    config.rcc.ls = rtc::ls_config(Self::RTC_CLOCK);
+    config
   }
+  /// RTC clock source: the 32.768 kHz LSE crystal (X2, fitted on the board)
+  pub const RTC_CLOCK: RtcClock = RtcClock::Lse;
+  /// Interrupt running the high-priority executor (`common::executor`; FMPI2C1 is not used, so its vector is free)
+  pub const EXECUTOR_IRQ: Interrupt = Interrupt::FMPI2C1_ER;
+  /// Priority of `EXECUTOR_IRQ`: above thread mode, below the embassy driver interrupts (P0)
+  pub const EXECUTOR_PRIORITY: Priority = Priority::P6;
+  /// Interrupt waking `common::power::Executor` (the FMPI2C1 event vector is free)
+  pub const IDLE_IRQ: Interrupt = Interrupt::FMPI2C1_EV;
+  /// Supply current (uA) with the core running and sleeping at 16 MHz HSI, peripherals in use
+  /// clocked (typical datasheet figures; measure on the IDD jumper for your setup). Weighted by
+  /// the sleep share into the telemetry's `current_ua`.
+  pub const RUN_CURRENT_UA: u32 = 4_500;
+  pub const SLEEP_CURRENT_UA: u32 = 1_800;
+  /// `common::power::SysclkProfile::Performance`: HSI / 8 x 200 / 4 = 100 MHz (the maximum),
+  /// APB1 50 MHz, APB2 100 MHz, 3 wait states; typical run current
+  pub const PERFORMANCE_PLL: PllSettings = PllSettings {
+    m: 8,
+    n: 200,
+    p: 4,
+    q: 9,
+    apb1_div: 2,
+    apb2_div: 1,
+    flash_latency: 3,
+    run_current_ua: 12_000,
+  };
   /// Busy-wait loop cycles per ms for delays (used by timers.rs)
   pub const fn cycles_per_ms() -> u32 {
     0 // Not used (async timer available)
   }
   /// Start address of RAM (for stack usage reporting)
-  pub const RAM_START: u32 = 0x20000000;
+  pub const RAM_START: u32 = memory::RAM_ORIGIN;
   /// Watchdog timeout in microseconds
   pub const WATCHDOG_TIMEOUT_US: u32 = 1_000_000;
   /// End address of RAM (for stack usage reporting)
-  pub const RAM_END: u32 = 0x20050000; // 320KB RAM ends at 0x20050000
-
-  /// Flash storage region: Use last 128KB sector of STM32F413ZH (1536KB flash)
-  /// STM32F413ZH flash: 1536KB total (0x08000000 to 0x08180000)
-  /// Using last 128KB for storage: 1408KB to 1536KB from flash base
-  pub const FLASH_STORAGE_START: u32 = 0x08160000; // Start of last 128KB (1408KB from base)
-  pub const FLASH_STORAGE_END: u32 = 0x08180000; // End of flash (1536KB from base)
-  pub const FLASH_STORAGE_SIZE: usize = 128 * 1024; // 128KB storage region
+  pub const RAM_END: u32 = memory::RAM_ORIGIN + memory::RAM_SIZE_KB * 1024;
+  /// Heap size for the `alloc` feature (statically reserved, so it counts against RAM)
+  pub const HEAP_SIZE: usize = 64 * 1024; // 64KB of the 320KB RAM
+  /// Comm receive/NAK/forward and high-priority TX queue depth (messages of `COMMS_MAX_PAYLOAD` bytes each)
+  pub const COMMS_QUEUE_DEPTH: usize = 6;
+  /// Comm normal-priority outgoing queue depth (`comm::send` data)
+  pub const COMMS_TX_QUEUE_DEPTH: usize = 16;
+
+  /// Flash storage region: the last 128KB sector (see `nucleo144_f413zh_memory.rs`, also read by build.rs)
+  pub const FLASH_STORAGE_START: u32 = memory::FLASH_STORAGE_START;
+  pub const FLASH_STORAGE_END: u32 = memory::FLASH_STORAGE_START + memory::FLASH_STORAGE_SIZE as u32;
+  pub const FLASH_STORAGE_SIZE: usize = memory::FLASH_STORAGE_SIZE;
+  /// Filesystem region for the `fs` feature: sectors 12-14, just below the storage region
+  pub const FS_STORAGE_START: u32 = memory::FS_STORAGE_START;
+  pub const FS_STORAGE_SIZE: usize = memory::FS_STORAGE_SIZE;
+  /// Embassy-time's timer (`time-driver-tim5` in the board feature): 32-bit TIM5 on APB1, its
+  /// registers at this address (`power` rescales its prescaler on clock changes)
+  pub const TIME_DRIVER_TIM_BASE: u32 = 0x4000_0C00;
+  /// Timers free for `HwTimer` (TIM5 drives embassy-time; the TIM6_DAC vector is stubbed below)
+  pub const HW_TIMERS_FREE: &'static [&'static str] = &["TIM1", "TIM2", "TIM3", "TIM4", "TIM7", "TIM8", "TIM9", "TIM10", "TIM11", "TIM12", "TIM13", "TIM14"];
   // Board constants (mirroring F446RE style)
   pub const BOARD_NAME: &'static str = "STM32 Nucleo-144 F413ZH";
   pub const MCU_NAME: &'static str = "STM32F413ZH";
-  pub const FLASH_SIZE_KB: u32 = 1536; // 1.5 MB Flash
-  pub const RAM_SIZE_KB: u32 = 320; // 320 KB SRAM total (256KB + 64KB CCM)
+  pub const FLASH_SIZE_KB: u32 = memory::FLASH_SIZE_KB;
+  pub const RAM_SIZE_KB: u32 = memory::RAM_SIZE_KB;
   pub const LED_PIN_NAME: &'static str = "PB0"; // LD1 - Green LED
   pub const LED_DESCRIPTION: &'static str = "Built-in LED LD1 (Green)";
   pub const BUTTON_PIN_NAME: &'static str = "PC13"; // B1 - Blue tactile button
@@ -93,25 +173,245 @@ impl BoardConfig {
     )
   }
 
+  /// All user LEDs: LD1 green heartbeat (from `init_all_hardware`), LD2 blue activity, LD3 red error
+  pub fn init_leds(led: Output<'static>) -> Leds {
+    // SAFETY: PB7/PB14 are not claimed anywhere else in the board configuration
+    let (blue, red) = unsafe { (PB7::steal(), PB14::steal()) };
+    Leds {
+      heartbeat: led,
+      activity: Some(Output::new(blue, GpioDefaults::LED_LEVEL, GpioDefaults::LED_SPEED)),
+      error: Some(Output::new(red, GpioDefaults::LED_LEVEL, GpioDefaults::LED_SPEED)),
+    }
+  }
+
+  /// Default quadrature encoder: TIM3 with A on PA6 and B on PA7 (Arduino D12/D11 on CN7)
+  pub const ENCODER_PINS: (&'static str, &'static str, &'static str) = ("TIM3", "PA6", "PA7");
+
+  /// Encoder on `ENCODER_PINS` with `counts_per_rev` counts (4x PPR) per revolution.
+  /// These peripherals are not used by `init_all_hardware`, so this can be called after it.
+  pub fn init_encoder(counts_per_rev: u32) -> Encoder<TIM3> {
+    // SAFETY: TIM3/PA6/PA7 are not claimed anywhere else in the board configuration
+    let (tim, a, b) = unsafe { (TIM3::steal(), PA6::steal(), PA7::steal()) };
+    Encoder::new(tim, a, b, counts_per_rev)
+  }
+
+  /// VREFINT calibration value (raw 12-bit reading at VDDA = 3.3 V, factory programmed)
+  pub const VREFINT_CAL_ADDR: u32 = 0x1FFF7A2A;
+
+  /// ADC1 for internal measurements (supply voltage via VREFINT in `telemetry_task`).
+  /// Not used by `init_all_hardware`, so this can be called after it.
+  pub fn init_supply_adc() -> Adc<'static, ADC1> {
+    // SAFETY: ADC1 is not claimed anywhere else in the board configuration
+    Adc::new(unsafe { ADC1::steal() })
+  }
+
+  /// Default I2C bus: I2C1 with SCL on PB8 and SDA on PB9 (Arduino D15/D14 on CN7)
+  pub const I2C_PINS: (&'static str, &'static str, &'static str) = ("I2C1", "PB8", "PB9");
+
+  /// Async I2C on `I2C_PINS` at 100 kHz (TX on DMA1_CH7, RX on DMA1_CH0), e.g. for `SharedBus::i2c`.
+  /// These peripherals are not used by `init_all_hardware`, so this can be called after it.
+  pub fn init_i2c() -> I2c<'static, Async> {
+    // SAFETY: I2C1/PB8/PB9 and DMA1 streams 0/7 are not claimed anywhere else in the board configuration
+    let (i2c, scl, sda, tx_dma, rx_dma) = unsafe { (I2C1::steal(), PB8::steal(), PB9::steal(), DMA1_CH7::steal(), DMA1_CH0::steal()) };
+    I2c::new(i2c, scl, sda, bus::I2c1Irqs, tx_dma, rx_dma, i2c::Config::default())
+  }
+
+  /// Blocking I2C on `I2C_PINS` at 100 kHz, e.g. for `SharedBus::blocking_i2c` (use instead of `init_i2c`)
+  pub fn init_i2c_blocking() -> I2c<'static, Blocking> {
+    // SAFETY: I2C1/PB8/PB9 are not claimed anywhere else in the board configuration
+    let (i2c, scl, sda) = unsafe { (I2C1::steal(), PB8::steal(), PB9::steal()) };
+    I2c::new_blocking(i2c, scl, sda, i2c::Config::default())
+  }
+
+  /// Default SPI bus: SPI3 with SCK on PC10, MISO on PC11, MOSI on PC12 (morpho connector), and
+  /// the SD card chip select on PD2
+  pub const SPI_PINS: (&'static str, &'static str, &'static str, &'static str) = ("SPI3", "PC10", "PC11", "PC12");
+  pub const SD_CS_PIN: &'static str = "PD2";
+
+  /// Blocking SPI on `SPI_PINS` at `frequency`, e.g. for `SharedBus::blocking_spi`.
+  /// These peripherals are not used by `init_all_hardware`, so this can be called after it.
+  pub fn init_spi_blocking(frequency: Hertz) -> Spi<'static, Blocking> {
+    // SAFETY: SPI3/PC10/PC11/PC12 are not claimed anywhere else in the board configuration
+    let (spi, sck, miso, mosi) = unsafe { (SPI3::steal(), PC10::steal(), PC11::steal(), PC12::steal()) };
+    let mut config = spi::Config::default();
+    config.frequency = frequency;
+    Spi::new_blocking(spi, sck, mosi, miso, config)
+  }
+
+  /// SD card chip select on `SD_CS_PIN` (idle high)
+  pub fn init_sd_cs() -> Output<'static> {
+    // SAFETY: PD2 is not claimed anywhere else in the board configuration
+    Output::new(unsafe { PD2::steal() }, Level::High, Speed::VeryHigh)
+  }
+
+  /// Auxiliary serial port for the application (GPS, modem): USART2 with TX on PD5 and RX on PD6
+  /// (CN9 "USART" pins)
+  pub const AUX_UART_PINS: (&'static str, &'static str, &'static str) = ("USART2", "PD5", "PD6");
+  /// USART2 clock output for smartcard mode: PD7 (CN9)
+  pub const AUX_UART_CK_PIN: &'static str = "PD7";
+
+  /// UART on `AUX_UART_PINS` at `baudrate` (TX on DMA1_CH6, RX on DMA1_CH5); received bytes go to
+  /// `serial::read_aux`/`recv_aux`, not the comm protocol. These peripherals are not used by
+  /// `init_all_hardware`, so this can be called after it.
+  pub fn init_aux_serial(spawner: Spawner, baudrate: u32) -> UartTx<'static, Async> {
+    Self::init_aux_serial_with(spawner, SerialConfig::new(baudrate))
+  }
+
+  /// `init_aux_serial` in another line mode (IrDA, smartcard with the card clock on `AUX_UART_CK_PIN`)
+  pub fn init_aux_serial_with(spawner: Spawner, config: SerialConfig) -> UartTx<'static, Async> {
+    // SAFETY: USART2/PD5/PD6 and DMA1 streams 5/6 are not claimed anywhere else in the board configuration
+    let (usart, rx, tx, tx_dma, rx_dma) = unsafe { (USART2::steal(), PD6::steal(), PD5::steal(), DMA1_CH6::steal(), DMA1_CH5::steal()) };
+    if let SerialMode::Smartcard { .. } = config.mode {
+      // SAFETY: PD7 is not claimed anywhere else in the board configuration
+      serial::clock_output::<USART2, _>(unsafe { PD7::steal() });
+    }
+    serial::init_aux_serial(spawner, usart, rx, tx, serial::Serial2Irqs, tx_dma, rx_dma, config)
+  }
+
+  /// 1-Wire bus (`hardware::onewire`, DS18B20): USART2 TX on PD5 (CN9) in half-duplex, open drain,
+  /// with a 4.7k pull-up to 3.3 V. Same USART as `AUX_UART_PINS`, so use one or the other.
+  pub const ONEWIRE_PIN: (&'static str, &'static str) = ("USART2", "PD5");
+
+  /// 1-Wire master on `ONEWIRE_PIN`. Not used by `init_all_hardware`, so this can be called after it.
+  pub fn init_onewire() -> OneWire<'static> {
+    // SAFETY: USART2/PD5 are not claimed anywhere else in the board configuration (`init_aux_serial` excluded)
+    let (peri, pin) = unsafe { (USART2::steal(), PD5::steal()) };
+    let uart = Uart::new_blocking_half_duplex(peri, pin, usart::Config::default(), HalfDuplexReadback::Readback, HalfDuplexConfig::OpenDrainExternal).unwrap();
+    OneWire::new(uart)
+  }
+
+  /// Suggested UART for an ESP-AT WiFi module (`hardware::wifi_at`): USART6 with TX on PG14 and
+  /// RX on PG9 (Arduino D1/D0 on CN10)
+  pub const WIFI_UART_PINS: (&'static str, &'static str, &'static str) = ("USART6", "PG14", "PG9");
+
+  /// LoRa shield (SX1276MB1xAS layout): SPI1 with SCK on PA5, MISO on PA6, MOSI on PA7 (Arduino
+  /// D13/D12/D11 on CN7)
+  pub const LORA_SPI_PINS: (&'static str, &'static str, &'static str, &'static str) = ("SPI1", "PA5", "PA6", "PA7");
+  /// LoRa NSS on PD14 (D10), RESET on PA3 (A0) and DIO0 on PF15 (D2)
+  pub const LORA_CONTROL_PINS: (&'static str, &'static str, &'static str) = ("PD14", "PA3", "PF15");
+
+  /// SPI bus (blocking, `lora::LORA_SPI_HZ`), NSS (idle high), RESET (idle high) and DIO0 for
+  /// `hardware::lora::Sx127x`. These peripherals are not used by `init_all_hardware`, so this can
+  /// be called after it; PA6/PA7 are also the `ENCODER_PINS`.
+  pub fn init_lora() -> (Spi<'static, Blocking>, Output<'static>, Output<'static>, ExtiInput<'static>) {
+    // SAFETY: SPI1/PA5/PA6/PA7/PD14/PA3/PF15/EXTI15 are not claimed anywhere else in the board configuration
+    let (spi, sck, miso, mosi) = unsafe { (SPI1::steal(), PA5::steal(), PA6::steal(), PA7::steal()) };
+    let (nss, reset, dio0, exti) = unsafe { (PD14::steal(), PA3::steal(), PF15::steal(), EXTI15::steal()) };
+    let mut config = spi::Config::default();
+    config.frequency = Hertz(lora::LORA_SPI_HZ);
+    (
+      Spi::new_blocking(spi, sck, mosi, miso, config),
+      Output::new(nss, Level::High, Speed::VeryHigh),
+      Output::new(reset, Level::High, Speed::Low),
+      ExtiInput::new(dio0, exti, Pull::Down),
+    )
+  }
+
+  /// nRF24L01+ module (alternative to the LoRa shield, same SPI1 pins): CSN on PD14 (D10), CE on
+  /// PF13 (D7) and IRQ on PF15 (D2)
+  pub const NRF24_CONTROL_PINS: (&'static str, &'static str, &'static str) = ("PD14", "PF13", "PF15");
+
+  /// SPI bus on `LORA_SPI_PINS` (blocking, `nrf24::NRF24_SPI_HZ`), CSN (idle high), CE (low) and
+  /// IRQ (active low) for `hardware::nrf24::Nrf24`. Use instead of `init_lora`.
+  pub fn init_nrf24() -> (Spi<'static, Blocking>, Output<'static>, Output<'static>, ExtiInput<'static>) {
+    // SAFETY: SPI1/PA5/PA6/PA7/PD14/PF13/PF15/EXTI15 are not claimed anywhere else in the board configuration
+    let (spi, sck, miso, mosi) = unsafe { (SPI1::steal(), PA5::steal(), PA6::steal(), PA7::steal()) };
+    let (csn, ce, irq, exti) = unsafe { (PD14::steal(), PF13::steal(), PF15::steal(), EXTI15::steal()) };
+    let mut config = spi::Config::default();
+    config.frequency = Hertz(nrf24::NRF24_SPI_HZ);
+    (
+      Spi::new_blocking(spi, sck, mosi, miso, config),
+      Output::new(csn, Level::High, Speed::VeryHigh),
+      Output::new(ce, Level::Low, Speed::VeryHigh),
+      ExtiInput::new(irq, exti, Pull::Up),
+    )
+  }
+
+  /// External QSPI NOR flash (e.g. W25Q128 on the morpho connector): CLK, NCS, IO0..IO3
+  pub const QSPI_PINS: (&'static str, &'static str, &'static str, &'static str, &'static str, &'static str) = ("PB2", "PG6", "PF8", "PF9", "PF7", "PF6");
+
+  /// Blocking QUADSPI bank 1 on `QSPI_PINS` at 25 MHz (24-bit addresses, up to 16 MB), for
+  /// `qspi_flash::QspiFlash::new`. Not used by `init_all_hardware`, so this can be called after it.
+  pub fn init_qspi() -> Qspi<'static, QUADSPI, Blocking> {
+    // SAFETY: QUADSPI and PB2/PG6/PF6..PF9 are not claimed anywhere else in the board configuration
+    let (qspi, clk, ncs, io0, io1, io2, io3) = unsafe { (QUADSPI::steal(), PB2::steal(), PG6::steal(), PF8::steal(), PF9::steal(), PF7::steal(), PF6::steal()) };
+    let config = qspi::Config {
+      memory_size: MemorySize::_16MiB,
+      address_size: AddressSize::_24bit,
+      prescaler: 3, // 100 MHz HCLK / (3 + 1)
+      cs_high_time: ChipSelectHighTime::_3Cycle,
+      fifo_threshold: FIFOThresholdLevel::_16Bytes,
+    };
+    Qspi::new_blocking_bank1(qspi, io0, io1, io2, io3, clk, ncs, config)
+  }
+
+  /// CRC unit for `hardware::crc::install` (feature `hw_crc`).
+  /// Not used by `init_all_hardware`, so this can be called after it.
+  pub fn init_crc() -> Crc<'static> {
+    // SAFETY: the CRC unit is not claimed anywhere else in the board configuration
+    Crc::new(unsafe { CRC::steal() })
+  }
+
+  /// DMA2 stream 1 for `hardware::dma::install` (feature `hw_dma`): memory-to-memory copies.
+  /// Not used by `init_all_hardware`, so this can be called after it.
+  pub fn init_dma() -> Peri<'static, DMA2_CH1> {
+    // SAFETY: DMA2 stream 1 is not claimed anywhere else in the board configuration
+    unsafe { DMA2_CH1::steal() }
+  }
+
+  /// True random number generator for `hardware::rng::HwRng`.
+  /// Not used by `init_all_hardware`, so this can be called after it.
+  pub fn init_rng() -> Rng<'static, RNG> {
+    // SAFETY: the RNG unit is not claimed anywhere else in the board configuration
+    Rng::new(unsafe { RNG::steal() }, RngIrqs)
+  }
+
+  /// PDM microphone (`hardware::dfsdm`): DFSDM2 CKOUT on PD3 and DATIN1 on PE7, both alternate
+  /// function 3 (CN9/CN10). Tie the microphone's L/R pin low (data on the rising edge).
+  pub const MIC_PINS: (&'static str, &'static str, &'static str) = ("DFSDM2", "PD3", "PE7");
+  // DFSDM2 channel fed by DATIN1, and the pins' alternate function
+  const MIC_CHANNEL: u8 = 1;
+  const MIC_AF: u8 = 3;
+
+  /// Start the microphone on `MIC_PINS` at 16 kHz (APB2 at the default 16 MHz).
+  /// Not used by `init_all_hardware`, so this can be called after it.
+  pub fn init_microphone() -> Microphone {
+    // SAFETY: PD3/PE7 are not claimed anywhere else in the board configuration
+    let (ckout, datin) = unsafe { (PD3::steal(), PE7::steal()) };
+    gpio::set_alternate(ckout.port(), ckout.pin(), Self::MIC_AF);
+    gpio::set_alternate(datin.port(), datin.pin(), Self::MIC_AF);
+    Microphone::start(Self::MIC_CHANNEL, MicConfig::new(16_000_000))
+  }
+
+  /// Default watchdog settings: `WATCHDOG_TIMEOUT_US`, paused while a debugger halts the core, and
+  /// enabled (except in debug builds with the `debug_no_watchdog` feature)
+  pub const fn watchdog_config() -> WatchdogConfig {
+    WatchdogConfig::new(Self::WATCHDOG_TIMEOUT_US).enabled(!(cfg!(feature = "debug_no_watchdog") && cfg!(debug_assertions)))
+  }
+
   /// Initialize LED, button, watchdog, RTC, and serial for this board.
-  pub fn init_all_hardware(
+  pub fn init_all_hardware(spawner: Spawner, p: embassy_stm32::Peripherals) -> (Output<'static>, Input<'static>, Watchdog, Rtc, UartTx<'static, Async>) {
+    Self::init_all_hardware_with(spawner, p, Self::watchdog_config())
+  }
+
+  /// `init_all_hardware` with other watchdog settings, e.g.
+  /// `BoardConfig::watchdog_config().enabled(!cfg!(debug_assertions))` to keep it off while debugging
+  pub fn init_all_hardware_with(
     spawner: Spawner,
     p: embassy_stm32::Peripherals,
-  ) -> (
-    Output<'static>,
-    Input<'static>,
-    IndependentWatchdog<'static, embassy_stm32::peripherals::IWDG>,
-    Rtc,
-    UartTx<'static, Async>,
-  ) {
+    watchdog: WatchdogConfig,
+  ) -> (Output<'static>, Input<'static>, Watchdog, Rtc, UartTx<'static, Async>) {
+    // Stack guard and read-only flash before anything deep runs
+    #[cfg(feature = "mpu")]
+    crate::hardware::mpu::init(crate::hardware::mpu::MpuConfig::new());
+
     // GPIO
     let led = Output::new(p.PB0, GpioDefaults::LED_LEVEL, GpioDefaults::LED_SPEED);
     let button = Input::new(p.PC13, GpioDefaults::BUTTON_PULL);
 
     // Watchdog and RTC
-    let mut wdt = IndependentWatchdog::new(p.IWDG, Self::WATCHDOG_TIMEOUT_US);
     let rtc = Rtc::new(p.RTC, RtcConfig::default());
-    wdt.unleash();
+    let wdt = Watchdog::new(p.IWDG, watchdog);
 
     // Serial (USART3 on PD8/PD9 - ST-LINK VCP)
     let comm = serial::init_serial(
@@ -131,55 +431,25 @@ impl BoardConfig {
 // Compile-time validation
 crate::validate_board_config!(BoardConfig);
 
-// STM32F413ZH-specific interrupt handlers
-#[unsafe(no_mangle)]
-extern "C" fn WWDG() {}
-
-#[unsafe(no_mangle)]
-extern "C" fn I2C1_EV() {}
-
-#[unsafe(no_mangle)]
-extern "C" fn I2C1_ER() {}
-
-#[unsafe(no_mangle)]
-extern "C" fn I2C2_EV() {}
-
-#[unsafe(no_mangle)]
-extern "C" fn I2C2_ER() {}
-
-#[unsafe(no_mangle)]
-extern "C" fn RTC_ALARM() {}
-
-#[unsafe(no_mangle)]
-extern "C" fn OTG_FS_WKUP() {}
-
-#[unsafe(no_mangle)]
-extern "C" fn SPI3() {}
-
-#[unsafe(no_mangle)]
-extern "C" fn TIM6_DAC() {}
-
-#[unsafe(no_mangle)]
-extern "C" fn LPTIM1() {}
-
-#[unsafe(no_mangle)]
-extern "C" fn DFSDM2_FLT0() {}
-
-#[unsafe(no_mangle)]
-extern "C" fn DFSDM2_FLT1() {}
-
-#[unsafe(no_mangle)]
-extern "C" fn DFSDM2_FLT2() {}
-
-#[unsafe(no_mangle)]
-extern "C" fn DFSDM2_FLT3() {}
-
-// Additional shared interrupts that F413ZH also needs
-#[unsafe(no_mangle)]
-extern "C" fn QUADSPI() {}
-
-#[unsafe(no_mangle)]
-extern "C" fn FMPI2C1_EV() {}
+// Handler of `EXECUTOR_IRQ`
+crate::executor_interrupt!(FMPI2C1_ER);
+// Handler of `IDLE_IRQ`
+crate::idle_interrupt!(FMPI2C1_EV);
 
-#[unsafe(no_mangle)]
-extern "C" fn FMPI2C1_ER() {}
+// STM32F413ZH interrupt vectors required for linking but not used by this configuration
+// (I2C1_EV/I2C1_ER are bound in `hardware::bus` for `init_i2c`, DFSDM2_FLT0 in `hardware::dfsdm`, FMPI2C1_ER runs the high-priority executor, FMPI2C1_EV wakes the low-power one, RTC_ALARM is handled by `service::scheduler`; `init_qspi` is blocking, so QUADSPI stays stubbed):
+// an unexpected interrupt on any of them is recorded and masked (see `hardfault::unexpected_irq`)
+crate::interrupt_stubs!(
+  DefaultHandler,
+  WWDG,
+  I2C2_EV,
+  I2C2_ER,
+  OTG_FS_WKUP,
+  SPI3,
+  TIM6_DAC,
+  LPTIM1,
+  DFSDM2_FLT1,
+  DFSDM2_FLT2,
+  DFSDM2_FLT3,
+  QUADSPI,
+);
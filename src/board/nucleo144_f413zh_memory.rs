@@ -0,0 +1,17 @@
+// STM32F413ZH memory map: the one definition behind `BoardConfig`'s memory constants and the
+// `memory.x` that build.rs generates for the linker. build.rs includes this file, so it holds
+// plain constants only.
+
+/// Flash base address and size (1.5 MB)
+pub const FLASH_ORIGIN: u32 = 0x08000000;
+pub const FLASH_SIZE_KB: u32 = 1536;
+/// RAM base address and size (SRAM1 256KB + SRAM2 64KB, contiguous)
+pub const RAM_ORIGIN: u32 = 0x20000000;
+pub const RAM_SIZE_KB: u32 = 320;
+
+/// Flash storage region: the last 128KB sector (sector 15)
+pub const FLASH_STORAGE_START: u32 = 0x08160000; // 1408KB from base
+pub const FLASH_STORAGE_SIZE: usize = 128 * 1024;
+/// Filesystem region for the `fs` feature: sectors 12-14, just below the storage region
+pub const FS_STORAGE_START: u32 = 0x08100000; // 1024KB from base
+pub const FS_STORAGE_SIZE: usize = 384 * 1024;
@@ -14,72 +14,311 @@
 // - USART2 TX: PA2
 // - USART2 RX: PA3
 
-use embassy_stm32::gpio::{Input, Output};
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::{Input, Level, Output, Pull, Speed};
 // use embassy_stm32::peripherals;
 use super::{BoardConfiguration, InterruptHandlers};
-use crate::hardware::GpioDefaults;
-use crate::hardware::serial;
+use crate::common::power::PllSettings;
+use crate::hardware::bus;
+use crate::hardware::encoder::Encoder;
+use crate::hardware::onewire::OneWire;
+use crate::hardware::rtc::{self, RtcClock};
+use crate::hardware::serial::{self, SerialConfig, SerialMode};
+use crate::hardware::watchdog::{Watchdog, WatchdogConfig};
+use crate::hardware::{GpioDefaults, Leds};
+use crate::hardware::{lora, nrf24};
 use embassy_executor::Spawner;
-use embassy_stm32::mode::Async;
+use embassy_stm32::Peri;
+use embassy_stm32::adc::Adc;
+use embassy_stm32::crc::Crc;
+use embassy_stm32::i2c::{self, I2c};
+use embassy_stm32::interrupt::{Interrupt, Priority};
+use embassy_stm32::mode::{Async, Blocking};
+use embassy_stm32::peripherals::{
+  ADC1, CRC, DMA1_CH0, DMA1_CH7, DMA2_CH1, DMA2_CH2, DMA2_CH7, EXTI10, I2C1, PA0, PA5, PA6, PA7, PA8, PA9, PA10, PB6, PB8, PB9, PC10, PC11, PC12, PD2, SPI1, SPI3, TIM3, USART1,
+};
 use embassy_stm32::rtc::{Rtc, RtcConfig};
-use embassy_stm32::usart::UartTx;
-use embassy_stm32::wdg::IndependentWatchdog;
+use embassy_stm32::spi::{self, Spi};
+use embassy_stm32::time::Hertz;
+use embassy_stm32::usart::{self, HalfDuplexConfig, HalfDuplexReadback, Uart, UartTx};
 
 use embassy_stm32::Config as EmbassyConfig;
 
+// Memory map shared with build.rs, which generates memory.x from it
+#[path = "nucleo_f446re_memory.rs"]
+mod memory;
+
 pub struct BoardConfig;
 
 impl BoardConfig {
-  /// Returns the default Embassy config (16 MHz HSI)
+  /// Returns the default Embassy config (16 MHz HSI) with the RTC on `RTC_CLOCK`
+  /// (falling back to the LSI if the LSE crystal does not start; see `hardware::rtc::ls_config`)
   pub fn embassy_config() -> EmbassyConfig {
-    EmbassyConfig::default()
+    let mut config = EmbassyConfig::default();
+    config.rcc.ls = rtc::ls_config(Self::RTC_CLOCK);
+    config
   }
+  /// RTC clock source: the 32.768 kHz LSE crystal (X2, fitted on the board)
+  pub const RTC_CLOCK: RtcClock = RtcClock::Lse;
+  /// Interrupt running the high-priority executor (`common::executor`; SAI2 is not used, so its vector is free)
+  pub const EXECUTOR_IRQ: Interrupt = Interrupt::SAI2;
+  /// Priority of `EXECUTOR_IRQ`: above thread mode, below the embassy driver interrupts (P0)
+  pub const EXECUTOR_PRIORITY: Priority = Priority::P6;
+  /// Interrupt waking `common::power::Executor` (FMPI2C1 is not used, so its event vector is free)
+  pub const IDLE_IRQ: Interrupt = Interrupt::FMPI2C1_EV;
+  /// Supply current (uA) with the core running and sleeping at 16 MHz HSI, peripherals in use
+  /// clocked (typical datasheet figures; measure on the IDD jumper for your setup). Weighted by
+  /// the sleep share into the telemetry's `current_ua`.
+  pub const RUN_CURRENT_UA: u32 = 6_000;
+  pub const SLEEP_CURRENT_UA: u32 = 2_500;
+  /// `common::power::SysclkProfile::Performance`: HSI / 8 x 168 / 2 = 168 MHz (the most without
+  /// over-drive), APB1 42 MHz, APB2 84 MHz, 5 wait states; typical run current
+  pub const PERFORMANCE_PLL: PllSettings = PllSettings {
+    m: 8,
+    n: 168,
+    p: 2,
+    q: 7,
+    apb1_div: 4,
+    apb2_div: 2,
+    flash_latency: 5,
+    run_current_ua: 40_000,
+  };
   /// Busy-wait loop cycles per ms for delays (used by timers.rs)
   pub const fn cycles_per_ms() -> u32 {
     0 // Not used (async timer available)
   }
   /// Start address of RAM (for stack usage reporting)
-  pub const RAM_START: u32 = 0x20000000;
+  pub const RAM_START: u32 = memory::RAM_ORIGIN;
   /// Watchdog timeout in microseconds
   pub const WATCHDOG_TIMEOUT_US: u32 = 1_000_000;
   /// End address of RAM (for stack usage reporting)
-  pub const RAM_END: u32 = 0x20020000; // 128KB RAM ends at 0x20020000
-
-  /// Flash storage region: Use sector 6 (128KB sector of STM32F446RE)
-  /// STM32F446RE flash layout: Sectors 0-3 (16KB each), Sector 4 (64KB), Sectors 5-7 (128KB each)
-  /// Using sector 6: 256KB to 384KB from flash base
-  pub const FLASH_STORAGE_START: u32 = 0x08040000; // Start of sector 6 (256KB from base)
-  pub const FLASH_STORAGE_END: u32 = 0x08060000; // End of sector 6 (384KB from base)  
-  pub const FLASH_STORAGE_SIZE: usize = 128 * 1024; // 128KB - size of sector 6
+  pub const RAM_END: u32 = memory::RAM_ORIGIN + memory::RAM_SIZE_KB * 1024;
+  /// Heap size for the `alloc` feature (statically reserved, so it counts against RAM)
+  pub const HEAP_SIZE: usize = 16 * 1024; // 16KB of the 128KB RAM
+  /// Comm receive/NAK/forward and high-priority TX queue depth (messages of `COMMS_MAX_PAYLOAD` bytes each)
+  pub const COMMS_QUEUE_DEPTH: usize = 3;
+  /// Comm normal-priority outgoing queue depth (`comm::send` data)
+  pub const COMMS_TX_QUEUE_DEPTH: usize = 8;
+
+  /// Flash storage region: sector 6, sector 5 with `fs` (see `nucleo_f446re_memory.rs`, also read by build.rs)
+  pub const FLASH_STORAGE_START: u32 = memory::FLASH_STORAGE_START;
+  pub const FLASH_STORAGE_END: u32 = memory::FLASH_STORAGE_START + memory::FLASH_STORAGE_SIZE as u32;
+  pub const FLASH_STORAGE_SIZE: usize = memory::FLASH_STORAGE_SIZE;
+  /// Filesystem region for the `fs` feature: sectors 6-7
+  pub const FS_STORAGE_START: u32 = memory::FS_STORAGE_START;
+  pub const FS_STORAGE_SIZE: usize = memory::FS_STORAGE_SIZE;
+  /// Embassy-time's timer (`time-driver-tim5` in the board feature): 32-bit TIM5 on APB1, its
+  /// registers at this address (`power` rescales its prescaler on clock changes)
+  pub const TIME_DRIVER_TIM_BASE: u32 = 0x4000_0C00;
+  /// Timers free for `HwTimer` (TIM5 drives embassy-time; TIM2_CH1 is on PA5/LD2)
+  pub const HW_TIMERS_FREE: &'static [&'static str] = &["TIM1", "TIM2", "TIM3", "TIM4", "TIM6", "TIM7", "TIM8", "TIM9", "TIM10", "TIM11", "TIM12", "TIM13", "TIM14"];
   // Board constants (for compatibility with existing applications)
   pub const BOARD_NAME: &'static str = "STM32 Nucleo-64 F446RE";
   pub const MCU_NAME: &'static str = "STM32F446RE";
-  pub const FLASH_SIZE_KB: u32 = 512;
-  pub const RAM_SIZE_KB: u32 = 128;
+  pub const FLASH_SIZE_KB: u32 = memory::FLASH_SIZE_KB;
+  pub const RAM_SIZE_KB: u32 = memory::RAM_SIZE_KB;
   pub const LED_PIN_NAME: &'static str = "PA5";
   pub const LED_DESCRIPTION: &'static str = "Green User LED (LD2)";
   pub const BUTTON_PIN_NAME: &'static str = "PC13";
   pub const BUTTON_DESCRIPTION: &'static str = "Blue User Button (B1)";
 
+  /// All user LEDs: this board only has LD2, so it takes every status role
+  pub fn init_leds(led: Output<'static>) -> Leds {
+    Leds {
+      heartbeat: led,
+      activity: None,
+      error: None,
+    }
+  }
+
+  /// Default quadrature encoder: TIM3 with A on PA6 and B on PA7 (Arduino D12/D11)
+  pub const ENCODER_PINS: (&'static str, &'static str, &'static str) = ("TIM3", "PA6", "PA7");
+
+  /// Encoder on `ENCODER_PINS` with `counts_per_rev` counts (4x PPR) per revolution.
+  /// These peripherals are not used by `init_all_hardware`, so this can be called after it.
+  pub fn init_encoder(counts_per_rev: u32) -> Encoder<TIM3> {
+    // SAFETY: TIM3/PA6/PA7 are not claimed anywhere else in the board configuration
+    let (tim, a, b) = unsafe { (TIM3::steal(), PA6::steal(), PA7::steal()) };
+    Encoder::new(tim, a, b, counts_per_rev)
+  }
+
+  /// VREFINT calibration value (raw 12-bit reading at VDDA = 3.3 V, factory programmed)
+  pub const VREFINT_CAL_ADDR: u32 = 0x1FFF7A2A;
+
+  /// ADC1 for internal measurements (supply voltage via VREFINT in `telemetry_task`).
+  /// Not used by `init_all_hardware`, so this can be called after it.
+  pub fn init_supply_adc() -> Adc<'static, ADC1> {
+    // SAFETY: ADC1 is not claimed anywhere else in the board configuration
+    Adc::new(unsafe { ADC1::steal() })
+  }
+
+  /// Default I2C bus: I2C1 with SCL on PB8 and SDA on PB9 (Arduino D15/D14)
+  pub const I2C_PINS: (&'static str, &'static str, &'static str) = ("I2C1", "PB8", "PB9");
+
+  /// Async I2C on `I2C_PINS` at 100 kHz (TX on DMA1_CH7, RX on DMA1_CH0), e.g. for `SharedBus::i2c`.
+  /// These peripherals are not used by `init_all_hardware`, so this can be called after it.
+  pub fn init_i2c() -> I2c<'static, Async> {
+    // SAFETY: I2C1/PB8/PB9 and DMA1 streams 0/7 are not claimed anywhere else in the board configuration
+    let (i2c, scl, sda, tx_dma, rx_dma) = unsafe { (I2C1::steal(), PB8::steal(), PB9::steal(), DMA1_CH7::steal(), DMA1_CH0::steal()) };
+    I2c::new(i2c, scl, sda, bus::I2c1Irqs, tx_dma, rx_dma, i2c::Config::default())
+  }
+
+  /// Blocking I2C on `I2C_PINS` at 100 kHz, e.g. for `SharedBus::blocking_i2c` (use instead of `init_i2c`)
+  pub fn init_i2c_blocking() -> I2c<'static, Blocking> {
+    // SAFETY: I2C1/PB8/PB9 are not claimed anywhere else in the board configuration
+    let (i2c, scl, sda) = unsafe { (I2C1::steal(), PB8::steal(), PB9::steal()) };
+    I2c::new_blocking(i2c, scl, sda, i2c::Config::default())
+  }
+
+  /// Default SPI bus: SPI3 with SCK on PC10, MISO on PC11, MOSI on PC12 (morpho connector), and
+  /// the SD card chip select on PD2
+  pub const SPI_PINS: (&'static str, &'static str, &'static str, &'static str) = ("SPI3", "PC10", "PC11", "PC12");
+  pub const SD_CS_PIN: &'static str = "PD2";
+
+  /// Blocking SPI on `SPI_PINS` at `frequency`, e.g. for `SharedBus::blocking_spi`.
+  /// These peripherals are not used by `init_all_hardware`, so this can be called after it.
+  pub fn init_spi_blocking(frequency: Hertz) -> Spi<'static, Blocking> {
+    // SAFETY: SPI3/PC10/PC11/PC12 are not claimed anywhere else in the board configuration
+    let (spi, sck, miso, mosi) = unsafe { (SPI3::steal(), PC10::steal(), PC11::steal(), PC12::steal()) };
+    let mut config = spi::Config::default();
+    config.frequency = frequency;
+    Spi::new_blocking(spi, sck, mosi, miso, config)
+  }
+
+  /// SD card chip select on `SD_CS_PIN` (idle high)
+  pub fn init_sd_cs() -> Output<'static> {
+    // SAFETY: PD2 is not claimed anywhere else in the board configuration
+    Output::new(unsafe { PD2::steal() }, Level::High, Speed::VeryHigh)
+  }
+
+  /// Auxiliary serial port for the application (GPS, modem): USART1 with TX on PA9 (Arduino D8) and
+  /// RX on PA10 (D2, also the LoRa DIO0 / nRF24 IRQ pin). The ROM bootloader listens on it too.
+  pub const AUX_UART_PINS: (&'static str, &'static str, &'static str) = ("USART1", "PA9", "PA10");
+  /// USART1 clock output for smartcard mode: PA8 (D7, also the nRF24 CE pin)
+  pub const AUX_UART_CK_PIN: &'static str = "PA8";
+
+  /// UART on `AUX_UART_PINS` at `baudrate` (TX on DMA2_CH7, RX on DMA2_CH2); received bytes go to
+  /// `serial::read_aux`/`recv_aux`, not the comm protocol. These peripherals are not used by
+  /// `init_all_hardware`, so this can be called after it.
+  pub fn init_aux_serial(spawner: Spawner, baudrate: u32) -> UartTx<'static, Async> {
+    Self::init_aux_serial_with(spawner, SerialConfig::new(baudrate))
+  }
+
+  /// `init_aux_serial` in another line mode (IrDA, smartcard with the card clock on `AUX_UART_CK_PIN`)
+  pub fn init_aux_serial_with(spawner: Spawner, config: SerialConfig) -> UartTx<'static, Async> {
+    // SAFETY: USART1/PA9/PA10 and DMA2 streams 2/7 are not claimed anywhere else in the board configuration
+    let (usart, rx, tx, tx_dma, rx_dma) = unsafe { (USART1::steal(), PA10::steal(), PA9::steal(), DMA2_CH7::steal(), DMA2_CH2::steal()) };
+    if let SerialMode::Smartcard { .. } = config.mode {
+      // SAFETY: PA8 is only otherwise used by `init_nrf24`, which smartcard mode excludes
+      serial::clock_output::<USART1, _>(unsafe { PA8::steal() });
+    }
+    serial::init_aux_serial(spawner, usart, rx, tx, serial::Serial1Irqs, tx_dma, rx_dma, config)
+  }
+
+  /// 1-Wire bus (`hardware::onewire`, DS18B20): USART1 TX on PA9 (Arduino D8) in half-duplex, open
+  /// drain, with a 4.7k pull-up to 3.3 V. Same USART as `AUX_UART_PINS`, so use one or the other.
+  pub const ONEWIRE_PIN: (&'static str, &'static str) = ("USART1", "PA9");
+
+  /// 1-Wire master on `ONEWIRE_PIN`. Not used by `init_all_hardware`, so this can be called after it.
+  pub fn init_onewire() -> OneWire<'static> {
+    // SAFETY: USART1/PA9 are not claimed anywhere else in the board configuration (`init_aux_serial` excluded)
+    let (peri, pin) = unsafe { (USART1::steal(), PA9::steal()) };
+    let uart = Uart::new_blocking_half_duplex(peri, pin, usart::Config::default(), HalfDuplexReadback::Readback, HalfDuplexConfig::OpenDrainExternal).unwrap();
+    OneWire::new(uart)
+  }
+
+  /// Suggested UART for an ESP-AT WiFi module (`hardware::wifi_at`): USART6 with TX on PC6
+  /// (morpho connector) and RX on PC7 (Arduino D9)
+  pub const WIFI_UART_PINS: (&'static str, &'static str, &'static str) = ("USART6", "PC6", "PC7");
+
+  /// LoRa shield (SX1276MB1xAS layout): SPI1 with SCK on PA5, MISO on PA6, MOSI on PA7 (Arduino
+  /// D13/D12/D11; D13 is also LD2, which stays dark while SPI1 drives it)
+  pub const LORA_SPI_PINS: (&'static str, &'static str, &'static str, &'static str) = ("SPI1", "PA5", "PA6", "PA7");
+  /// LoRa NSS on PB6 (D10), RESET on PA0 (A0) and DIO0 on PA10 (D2)
+  pub const LORA_CONTROL_PINS: (&'static str, &'static str, &'static str) = ("PB6", "PA0", "PA10");
+
+  /// SPI bus (blocking, `lora::LORA_SPI_HZ`), NSS (idle high), RESET (idle high) and DIO0 for
+  /// `hardware::lora::Sx127x`. These peripherals are not used by `init_all_hardware` apart from
+  /// PA5 (LD2), so this can be called after it; PA6/PA7 are also the `ENCODER_PINS`.
+  pub fn init_lora() -> (Spi<'static, Blocking>, Output<'static>, Output<'static>, ExtiInput<'static>) {
+    // SAFETY: SPI1/PA6/PA7/PB6/PA0/PA10/EXTI10 are not claimed anywhere else in the board
+    // configuration; PA5 is taken over from the LED, which then only writes its output latch
+    let (spi, sck, miso, mosi) = unsafe { (SPI1::steal(), PA5::steal(), PA6::steal(), PA7::steal()) };
+    let (nss, reset, dio0, exti) = unsafe { (PB6::steal(), PA0::steal(), PA10::steal(), EXTI10::steal()) };
+    let mut config = spi::Config::default();
+    config.frequency = Hertz(lora::LORA_SPI_HZ);
+    (
+      Spi::new_blocking(spi, sck, mosi, miso, config),
+      Output::new(nss, Level::High, Speed::VeryHigh),
+      Output::new(reset, Level::High, Speed::Low),
+      ExtiInput::new(dio0, exti, Pull::Down),
+    )
+  }
+
+  /// nRF24L01+ module (alternative to the LoRa shield, same SPI1 pins): CSN on PB6 (D10), CE on
+  /// PA8 (D7) and IRQ on PA10 (D2)
+  pub const NRF24_CONTROL_PINS: (&'static str, &'static str, &'static str) = ("PB6", "PA8", "PA10");
+
+  /// SPI bus on `LORA_SPI_PINS` (blocking, `nrf24::NRF24_SPI_HZ`), CSN (idle high), CE (low) and
+  /// IRQ (active low) for `hardware::nrf24::Nrf24`. Use instead of `init_lora`.
+  pub fn init_nrf24() -> (Spi<'static, Blocking>, Output<'static>, Output<'static>, ExtiInput<'static>) {
+    // SAFETY: SPI1/PA6/PA7/PB6/PA8/PA10/EXTI10 are not claimed anywhere else in the board
+    // configuration; PA5 is taken over from the LED, which then only writes its output latch
+    let (spi, sck, miso, mosi) = unsafe { (SPI1::steal(), PA5::steal(), PA6::steal(), PA7::steal()) };
+    let (csn, ce, irq, exti) = unsafe { (PB6::steal(), PA8::steal(), PA10::steal(), EXTI10::steal()) };
+    let mut config = spi::Config::default();
+    config.frequency = Hertz(nrf24::NRF24_SPI_HZ);
+    (
+      Spi::new_blocking(spi, sck, mosi, miso, config),
+      Output::new(csn, Level::High, Speed::VeryHigh),
+      Output::new(ce, Level::Low, Speed::VeryHigh),
+      ExtiInput::new(irq, exti, Pull::Up),
+    )
+  }
+
+  /// CRC unit for `hardware::crc::install` (feature `hw_crc`).
+  /// Not used by `init_all_hardware`, so this can be called after it.
+  pub fn init_crc() -> Crc<'static> {
+    // SAFETY: the CRC unit is not claimed anywhere else in the board configuration
+    Crc::new(unsafe { CRC::steal() })
+  }
+
+  /// DMA2 stream 1 for `hardware::dma::install` (feature `hw_dma`): memory-to-memory copies.
+  /// Not used by `init_all_hardware`, so this can be called after it.
+  pub fn init_dma() -> Peri<'static, DMA2_CH1> {
+    // SAFETY: DMA2 stream 1 is not claimed anywhere else in the board configuration
+    unsafe { DMA2_CH1::steal() }
+  }
+
+  /// Default watchdog settings: `WATCHDOG_TIMEOUT_US`, paused while a debugger halts the core, and
+  /// enabled (except in debug builds with the `debug_no_watchdog` feature)
+  pub const fn watchdog_config() -> WatchdogConfig {
+    WatchdogConfig::new(Self::WATCHDOG_TIMEOUT_US).enabled(!(cfg!(feature = "debug_no_watchdog") && cfg!(debug_assertions)))
+  }
+
   /// Initialize LED, button, watchdog, RTC, and serial for this board.
-  pub fn init_all_hardware(
+  pub fn init_all_hardware(spawner: Spawner, p: embassy_stm32::Peripherals) -> (Output<'static>, Input<'static>, Watchdog, Rtc, UartTx<'static, Async>) {
+    Self::init_all_hardware_with(spawner, p, Self::watchdog_config())
+  }
+
+  /// `init_all_hardware` with other watchdog settings, e.g.
+  /// `BoardConfig::watchdog_config().enabled(!cfg!(debug_assertions))` to keep it off while debugging
+  pub fn init_all_hardware_with(
     spawner: Spawner,
     p: embassy_stm32::Peripherals,
-  ) -> (
-    Output<'static>,
-    Input<'static>,
-    IndependentWatchdog<'static, embassy_stm32::peripherals::IWDG>,
-    Rtc,
-    UartTx<'static, Async>,
-  ) {
+    watchdog: WatchdogConfig,
+  ) -> (Output<'static>, Input<'static>, Watchdog, Rtc, UartTx<'static, Async>) {
+    // Stack guard and read-only flash before anything deep runs
+    #[cfg(feature = "mpu")]
+    crate::hardware::mpu::init(crate::hardware::mpu::MpuConfig::new());
+
     // GPIO
     let led = Output::new(p.PA5, GpioDefaults::LED_LEVEL, GpioDefaults::LED_SPEED);
     let button = Input::new(p.PC13, GpioDefaults::BUTTON_PULL);
 
     // Watchdog and RTC
-    let mut wdt = IndependentWatchdog::new(p.IWDG, Self::WATCHDOG_TIMEOUT_US);
     let rtc = Rtc::new(p.RTC, RtcConfig::default());
-    wdt.unleash();
+    let wdt = Watchdog::new(p.IWDG, watchdog);
 
     // Serial (USART2 on PA2/PA3)
     let comm = serial::init_serial(
@@ -110,6 +349,15 @@ impl BoardConfig {
 }
 
 impl BoardConfiguration for BoardConfig {
+  const HAS_ADC: bool = true;
+  const HAS_THREE_LEDS: bool = false;
+  const HAS_ETHERNET: bool = false;
+  // No RNG unit on the F446
+  const HAS_RNG: bool = false;
+  const HAS_DFSDM: bool = false;
+  const UART_FOR_VCP: &'static str = "USART2";
+  type VcpUart = embassy_stm32::peripherals::USART2;
+
   fn board_name() -> &'static str {
     "STM32 Nucleo-64 F446RE"
   }
@@ -121,70 +369,11 @@ impl InterruptHandlers for BoardConfig {
   }
 }
 
-// STM32F446RE interrupt handlers - required for linking
-#[unsafe(no_mangle)]
-extern "C" fn DefaultHandler() {
-  // Default handler - just hang
-  loop {}
-}
-
-// Provide default implementations for the missing interrupt handlers
-#[unsafe(no_mangle)]
-extern "C" fn PVD() {
-  DefaultHandler();
-}
-
-#[unsafe(no_mangle)]
-extern "C" fn OTG_HS_EP1_OUT() {
-  DefaultHandler();
-}
-
-#[unsafe(no_mangle)]
-extern "C" fn OTG_HS_EP1_IN() {
-  DefaultHandler();
-}
+// Handler of `EXECUTOR_IRQ`
+crate::executor_interrupt!(SAI2);
+// Handler of `IDLE_IRQ`
+crate::idle_interrupt!(FMPI2C1_EV);
 
-#[unsafe(no_mangle)]
-extern "C" fn OTG_HS_WKUP() {
-  DefaultHandler();
-}
-
-#[unsafe(no_mangle)]
-extern "C" fn OTG_HS() {
-  DefaultHandler();
-}
-
-#[unsafe(no_mangle)]
-extern "C" fn SAI1() {
-  DefaultHandler();
-}
-
-#[unsafe(no_mangle)]
-extern "C" fn SAI2() {
-  DefaultHandler();
-}
-
-#[unsafe(no_mangle)]
-extern "C" fn QUADSPI() {
-  DefaultHandler();
-}
-
-#[unsafe(no_mangle)]
-extern "C" fn CEC() {
-  DefaultHandler();
-}
-
-#[unsafe(no_mangle)]
-extern "C" fn SPDIF_RX() {
-  DefaultHandler();
-}
-
-#[unsafe(no_mangle)]
-extern "C" fn FMPI2C1_EV() {
-  DefaultHandler();
-}
-
-#[unsafe(no_mangle)]
-extern "C" fn FMPI2C1_ER() {
-  DefaultHandler();
-}
+// STM32F446RE interrupt vectors required for linking but not used by this configuration:
+// an unexpected interrupt on any of them is recorded and masked (see `hardfault::unexpected_irq`)
+crate::interrupt_stubs!(DefaultHandler, OTG_HS_EP1_OUT, OTG_HS_EP1_IN, OTG_HS_WKUP, OTG_HS, SAI1, QUADSPI, CEC, SPDIF_RX, FMPI2C1_ER,);
@@ -0,0 +1,22 @@
+// STM32F446RE memory map: the one definition behind `BoardConfig`'s memory constants and the
+// `memory.x` that build.rs generates for the linker. build.rs includes this file, so it holds
+// plain constants only.
+
+/// Flash base address and size
+pub const FLASH_ORIGIN: u32 = 0x08000000;
+pub const FLASH_SIZE_KB: u32 = 512;
+/// RAM base address and size
+pub const RAM_ORIGIN: u32 = 0x20000000;
+pub const RAM_SIZE_KB: u32 = 128;
+
+/// Flash storage region: sector 6 (sectors 0-3 are 16KB, sector 4 64KB, sectors 5-7 128KB), or
+/// sector 5 with the `fs` feature, clear of the filesystem
+#[cfg(not(feature = "fs"))]
+pub const FLASH_STORAGE_START: u32 = 0x08040000; // 256KB from base
+#[cfg(feature = "fs")]
+pub const FLASH_STORAGE_START: u32 = 0x08020000; // 128KB from base
+pub const FLASH_STORAGE_SIZE: usize = 128 * 1024;
+/// Filesystem region for the `fs` feature: sectors 6-7 (littlefs needs at least two blocks, so
+/// firmware built with `fs` is left sectors 0-4, 128KB)
+pub const FS_STORAGE_START: u32 = 0x08040000;
+pub const FS_STORAGE_SIZE: usize = 256 * 1024;
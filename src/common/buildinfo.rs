@@ -0,0 +1,108 @@
+/// Firmware build information
+///
+/// Version, git commit, cargo profile, enabled features and board, captured at compile time by
+/// `build.rs` and kept as one fixed-layout record in the `.rodata.buildinfo` linker section, so
+/// it can be read back from a running unit (`Ident` command, startup banner) or found in an
+/// image or flash dump by its magic (`BUILD_INFO_MAGIC`, little-endian).
+use crate::board::BoardConfig;
+
+/// Record marker ("BILD")
+pub const BUILD_INFO_MAGIC: u32 = 0x444C_4942;
+
+const VERSION_LEN: usize = 16;
+const GIT_SHA_LEN: usize = 24;
+const PROFILE_LEN: usize = 8;
+const FEATURES_LEN: usize = 128;
+const BOARD_LEN: usize = 32;
+
+/// Build record; text fields are ASCII padded with NULs (truncated if longer)
+#[repr(C)]
+pub struct BuildInfo {
+  magic: u32,
+  version: [u8; VERSION_LEN],
+  git_sha: [u8; GIT_SHA_LEN],
+  profile: [u8; PROFILE_LEN],
+  features: [u8; FEATURES_LEN],
+  board: [u8; BOARD_LEN],
+}
+
+impl BuildInfo {
+  /// Length of `to_bytes()` (the `Ident` reply payload)
+  pub const LEN: usize = 4 + VERSION_LEN + GIT_SHA_LEN + PROFILE_LEN + FEATURES_LEN + BOARD_LEN;
+
+  /// Crate version (Cargo.toml)
+  pub fn version(&self) -> &str {
+    text(&self.version)
+  }
+
+  /// Short commit hash, with "-dirty" if built from uncommitted changes
+  pub fn git_sha(&self) -> &str {
+    text(&self.git_sha)
+  }
+
+  /// Cargo profile ("debug" or "release")
+  pub fn profile(&self) -> &str {
+    text(&self.profile)
+  }
+
+  /// Enabled cargo features, comma separated
+  pub fn features(&self) -> &str {
+    text(&self.features)
+  }
+
+  pub fn board(&self) -> &str {
+    text(&self.board)
+  }
+
+  /// Raw record (magic then the padded text fields) for the `Ident` reply
+  pub fn to_bytes(&self) -> [u8; Self::LEN] {
+    let mut out = [0u8; Self::LEN];
+    let fields: [&[u8]; 6] = [&self.magic.to_le_bytes(), &self.version, &self.git_sha, &self.profile, &self.features, &self.board];
+    let mut offset = 0;
+    for field in fields {
+      out[offset..offset + field.len()].copy_from_slice(field);
+      offset += field.len();
+    }
+    out
+  }
+}
+
+// Copy `s` into a NUL-padded array at compile time
+const fn padded<const N: usize>(s: &str) -> [u8; N] {
+  let bytes = s.as_bytes();
+  let mut out = [0u8; N];
+  let mut i = 0;
+  while i < N && i < bytes.len() {
+    out[i] = bytes[i];
+    i += 1;
+  }
+  out
+}
+
+fn text(field: &[u8]) -> &str {
+  let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+  core::str::from_utf8(&field[..len]).unwrap_or("?")
+}
+
+#[used]
+#[unsafe(link_section = ".rodata.buildinfo")]
+static BUILD_INFO: BuildInfo = BuildInfo {
+  magic: BUILD_INFO_MAGIC,
+  version: padded(env!("CARGO_PKG_VERSION")),
+  git_sha: padded(env!("BUILD_GIT_SHA")),
+  profile: padded(env!("BUILD_PROFILE")),
+  features: padded(env!("BUILD_FEATURES")),
+  board: padded(BoardConfig::BOARD_NAME),
+};
+
+/// This firmware's build record
+pub fn get() -> &'static BuildInfo {
+  &BUILD_INFO
+}
+
+/// Log the build record (startup banner)
+pub fn log() {
+  let info = get();
+  defmt::info!("Firmware {} ({}, {}) for {}", info.version(), info.git_sha(), info.profile(), info.board());
+  defmt::info!("Features: {}", info.features());
+}
@@ -0,0 +1,65 @@
+/// Time Slicing for CPU-Heavy Work on the Cooperative Executor
+///
+/// Embassy tasks only switch at an `.await`, so a task hashing a 128 KB image or working through a
+/// burst of frames holds every other task off until it is done, and the serial RX task's DMA
+/// buffer can overrun meanwhile. These helpers cut such work into slices with a yield in between:
+/// - `yield_now().await` gives the other ready tasks one turn
+/// - `yield_every!(n)` in a loop of an async fn yields on every n-th pass (one counter per call
+///   site, shared by the tasks running that code)
+/// - `for_each_chunk(data, len, f)` calls `f` on `len`-element slices, yielding after each, e.g.
+///   `for_each_chunk(image, WORK_CHUNK_LEN, |c| crc = crc32_update(crc, c)).await`
+///
+/// A yield costs one executor round trip (a few us when nothing else is ready), so slices of
+/// around a kilobyte keep the overhead small and the latency for other tasks well under a
+/// millisecond.
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+/// Bytes of a large job worth processing between two yields
+pub const WORK_CHUNK_LEN: usize = 1024;
+
+/// Future that is pending once (waking itself), letting the executor run other tasks
+pub struct YieldNow {
+  yielded: bool,
+}
+
+impl Future for YieldNow {
+  type Output = ();
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+    if self.yielded {
+      return Poll::Ready(());
+    }
+    self.yielded = true;
+    cx.waker().wake_by_ref();
+    Poll::Pending
+  }
+}
+
+/// Give the other ready tasks a turn
+pub fn yield_now() -> YieldNow {
+  YieldNow { yielded: false }
+}
+
+/// Call `f` on consecutive `chunk_len`-element slices of `data`, yielding after each
+pub async fn for_each_chunk<T>(data: &[T], chunk_len: usize, mut f: impl FnMut(&[T])) {
+  for chunk in data.chunks(chunk_len.max(1)) {
+    f(chunk);
+    yield_now().await;
+  }
+}
+
+/// Yield to the executor on every `n`-th pass (use in loops of async functions)
+///
+/// `for block in blocks { process(block); yield_every!(8); }`
+#[macro_export]
+macro_rules! yield_every {
+  ($n:expr) => {{
+    static PASSES: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(0);
+    if PASSES.fetch_add(1, core::sync::atomic::Ordering::Relaxed) + 1 >= ($n) as u32 {
+      PASSES.store(0, core::sync::atomic::Ordering::Relaxed);
+      $crate::common::cooperative::yield_now().await;
+    }
+  }};
+}
@@ -0,0 +1,67 @@
+/// High-Priority Interrupt Executor for Latency-Critical Tasks
+///
+/// Everything normally runs on the thread-mode executor of `main`, where a woken task waits
+/// until the running one reaches an `.await`. `start_high_priority()` starts a second executor
+/// run from a spare interrupt, `BoardConfig::EXECUTOR_IRQ` at `BoardConfig::EXECUTOR_PRIORITY`
+/// (the board file defines its handler with `executor_interrupt!`): its tasks preempt the
+/// thread-mode ones as soon as they are woken, so serial RX drains its DMA buffer and the
+/// fail-safe keeps watching while a thread-mode task hashes an image.
+///
+/// Started before `init_all_hardware` (or `init_serial`), it also hosts the serial RX task;
+/// spawn `failsafe::failsafe_task` and your own tasks on the returned `SendSpawner`. Tasks there
+/// hold off thread mode and lower-priority interrupts for each poll, so keep them short, share
+/// state only through `CriticalSectionRawMutex` primitives and atomics (as the crate's queues
+/// and signals already do), and pass them `Send` arguments.
+use core::cell::Cell;
+use embassy_executor::{InterruptExecutor, SendSpawner};
+use embassy_stm32::interrupt::InterruptExt;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+use crate::board::BoardConfig;
+
+/// Executor run by the board's `executor_interrupt!` handler
+pub static HIGH_PRIORITY_EXECUTOR: InterruptExecutor = InterruptExecutor::new();
+
+static SPAWNER: Mutex<CriticalSectionRawMutex, Cell<Option<SendSpawner>>> = Mutex::new(Cell::new(None));
+
+/// Start the high-priority executor on the board's interrupt (once; later calls return the same spawner)
+pub fn start_high_priority() -> SendSpawner {
+  if let Some(spawner) = high_priority() {
+    return spawner;
+  }
+  let irq = BoardConfig::EXECUTOR_IRQ;
+  irq.set_priority(BoardConfig::EXECUTOR_PRIORITY);
+  let spawner = HIGH_PRIORITY_EXECUTOR.start(irq);
+  SPAWNER.lock(|cell| cell.set(Some(spawner)));
+  defmt::info!(
+    "High-priority executor on {} at {}",
+    defmt::Debug2Format(&irq),
+    defmt::Debug2Format(&BoardConfig::EXECUTOR_PRIORITY)
+  );
+  spawner
+}
+
+/// Spawner of the high-priority executor, if `start_high_priority` has run
+pub fn high_priority() -> Option<SendSpawner> {
+  SPAWNER.lock(|cell| cell.get())
+}
+
+/// Define the interrupt handler running the high-priority executor
+///
+/// `executor_interrupt!(SAI2);` in the board file, naming the same interrupt as
+/// `BoardConfig::EXECUTOR_IRQ` (one nothing else uses: take it off the board's `interrupt_stubs!`).
+#[macro_export]
+macro_rules! executor_interrupt {
+  ($irq:ident) => {
+    const _: () = {
+      use embassy_stm32::interrupt;
+
+      #[interrupt]
+      unsafe fn $irq() {
+        // SAFETY: this is the interrupt `start_high_priority` started the executor on
+        unsafe { $crate::common::executor::HIGH_PRIORITY_EXECUTOR.on_interrupt() }
+      }
+    };
+  };
+}
@@ -0,0 +1,86 @@
+/// Finite State Machine for Application Modes
+///
+/// Device modes (Idle/Active/Fault/DFU...) tend to grow into flags and nested `if`s spread over a
+/// task loop. `Fsm` keeps the current state and runs a `Machine`: one `transition` function that
+/// maps (state, event) to the next state, with guards as conditions in its match arms, plus
+/// `enter`/`exit` hooks that own the side effects of each state (outputs, LED patterns, timers).
+/// Every state change is logged with defmt as `name: From -> To on Event`; ignored events are
+/// logged at debug level.
+///
+/// ```ignore
+/// impl Machine for Relay {
+///   type State = Mode;
+///   type Event = Input;
+///   fn transition(&self, state: Mode, event: &Input) -> Option<Mode> {
+///     match (state, event) {
+///       (Mode::Idle, Input::Button) => Some(Mode::Active),
+///       (_, Input::LinkDown) if self.needs_host => Some(Mode::Fault),
+///       _ => None,
+///     }
+///   }
+///   fn enter(&mut self, state: Mode) { self.output.set_level((state == Mode::Active).into()) }
+/// }
+/// let mut fsm = Fsm::new("relay", relay, Mode::Idle);
+/// fsm.handle(Input::Button);
+/// ```
+use defmt::Format;
+
+/// States, events and behaviour of a state machine driven by `Fsm`
+pub trait Machine {
+  type State: Copy + PartialEq + Format;
+  type Event: Format;
+
+  /// Next state for `event` in `state`, or None to ignore the event. Returning the current
+  /// state is an internal transition: no exit/entry hooks run.
+  fn transition(&self, state: Self::State, event: &Self::Event) -> Option<Self::State>;
+
+  /// `state` is being left
+  fn exit(&mut self, _state: Self::State) {}
+
+  /// `state` has been entered (also the initial state, from `Fsm::new`)
+  fn enter(&mut self, _state: Self::State) {}
+}
+
+/// Current state of a `Machine`, with logged transitions
+pub struct Fsm<M: Machine> {
+  name: &'static str,
+  machine: M,
+  state: M::State,
+}
+
+impl<M: Machine> Fsm<M> {
+  /// Start `machine` in `initial` (its entry hook runs now); `name` prefixes the log lines
+  pub fn new(name: &'static str, mut machine: M, initial: M::State) -> Self {
+    defmt::info!("{}: start in {}", name, initial);
+    machine.enter(initial);
+    Self { name, machine, state: initial }
+  }
+
+  pub fn state(&self) -> M::State {
+    self.state
+  }
+
+  pub fn machine(&self) -> &M {
+    &self.machine
+  }
+
+  pub fn machine_mut(&mut self) -> &mut M {
+    &mut self.machine
+  }
+
+  /// Dispatch `event`: exit hook, state change, entry hook. Returns the state after the event,
+  /// or None if the event was ignored in the current state.
+  pub fn handle(&mut self, event: M::Event) -> Option<M::State> {
+    let Some(next) = self.machine.transition(self.state, &event) else {
+      defmt::debug!("{}: {} ignored in {}", self.name, event, self.state);
+      return None;
+    };
+    if next != self.state {
+      defmt::info!("{}: {} -> {} on {}", self.name, self.state, next, event);
+      self.machine.exit(self.state);
+      self.state = next;
+      self.machine.enter(next);
+    }
+    Some(next)
+  }
+}
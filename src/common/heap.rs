@@ -0,0 +1,47 @@
+/// Heap allocator (feature `alloc`)
+///
+/// Sets up `embedded-alloc` as the global allocator over a static region of
+/// `BoardConfig::HEAP_SIZE` bytes so `alloc` types (`Vec`, `Box`, `String`) and
+/// crates that need them can be used. Call `init()` once, before the first
+/// allocation; `usage()` reports how much of the heap is in use.
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embedded_alloc::LlffHeap as Heap;
+
+use crate::board::BoardConfig;
+
+#[global_allocator]
+static HEAP: Heap = Heap::empty();
+
+static mut HEAP_MEM: [MaybeUninit<u8>; BoardConfig::HEAP_SIZE] = [MaybeUninit::uninit(); BoardConfig::HEAP_SIZE];
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Heap usage in bytes
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct HeapUsage {
+  pub used: usize,
+  pub free: usize,
+}
+
+/// Hand the heap region to the allocator (later calls do nothing)
+pub fn init() {
+  if INITIALIZED.swap(true, Ordering::AcqRel) {
+    return;
+  }
+  // SAFETY: runs once (guarded above) before any allocation; HEAP_MEM is only used by the allocator
+  unsafe { HEAP.init(&raw mut HEAP_MEM as usize, BoardConfig::HEAP_SIZE) }
+}
+
+/// Current heap usage
+pub fn usage() -> HeapUsage {
+  HeapUsage {
+    used: HEAP.used(),
+    free: HEAP.free(),
+  }
+}
+
+/// Log heap usage in KB
+pub fn log_usage() {
+  let usage = usage();
+  defmt::info!("Heap used: {}/{} KB ({} bytes)", usage.used / 1024, (usage.used + usage.free) / 1024, usage.used);
+}
@@ -0,0 +1,109 @@
+/// RAM kept across soft resets (`.noinit` section)
+///
+/// The `memory.x` generated by build.rs places a `.noinit` section after `.bss` that the runtime
+/// neither loads nor zeroes, so statics in it keep their contents through software, watchdog and
+/// fault resets; after a power-on they hold garbage. `NoInit<T>` guards such a value with a marker
+/// (covering the size of `T`) and a CRC, so garbage is never returned: `get()` is None until a
+/// `set()` in this or an earlier run. Declare one with the section attribute:
+///
+/// ```ignore
+/// #[unsafe(link_section = ".noinit.BOOT_COUNT")]
+/// static BOOT_COUNT: NoInit<u32> = NoInit::new();
+/// ```
+///
+/// `T` must be plain data without padding (integers, arrays of them, `#[repr(C)]` structs laid out
+/// without gaps), since the CRC covers its bytes. A value written by older firmware with a different
+/// layout of the same size still passes; add a version field to `T` where that matters.
+use core::cell::UnsafeCell;
+use core::mem::{MaybeUninit, size_of};
+use core::ptr::{addr_of, addr_of_mut};
+
+use crate::protocol::hdlc::fcs16_ppp;
+
+// "NOIN", mixed with the size of the value
+const MAGIC: u32 = 0x4E49_4F4E;
+
+#[repr(C)]
+struct Slot<T> {
+  magic: u32,
+  crc: u32,
+  value: T,
+}
+
+/// A value in `.noinit` RAM, validated by marker and CRC
+pub struct NoInit<T: Copy> {
+  slot: UnsafeCell<MaybeUninit<Slot<T>>>,
+}
+
+// SAFETY: every access runs with interrupts disabled and copies `T` in or out
+unsafe impl<T: Copy + Send> Sync for NoInit<T> {}
+
+impl<T: Copy> NoInit<T> {
+  const MAGIC: u32 = MAGIC ^ size_of::<T>() as u32;
+
+  /// Empty slot; the initializer is ignored in `.noinit`, where the contents come from the last run
+  pub const fn new() -> Self {
+    Self {
+      slot: UnsafeCell::new(MaybeUninit::uninit()),
+    }
+  }
+
+  /// The stored value, if one was set and is intact
+  pub fn get(&self) -> Option<T> {
+    cortex_m::interrupt::free(|_| self.read())
+  }
+
+  /// Store `value`
+  pub fn set(&self, value: T) {
+    cortex_m::interrupt::free(|_| self.write(value));
+  }
+
+  /// Forget the stored value
+  pub fn clear(&self) {
+    // SAFETY: plain word write with interrupts disabled
+    cortex_m::interrupt::free(|_| unsafe { (*self.slot.get()).as_mut_ptr().cast::<u32>().write_volatile(0) });
+  }
+
+  /// Replace the value with `f(current)` in one step and return the new value
+  pub fn update(&self, f: impl FnOnce(Option<T>) -> T) -> T {
+    cortex_m::interrupt::free(|_| {
+      let value = f(self.read());
+      self.write(value);
+      value
+    })
+  }
+
+  fn read(&self) -> Option<T> {
+    let slot = self.slot.get().cast::<Slot<T>>();
+    // SAFETY: marker and CRC are plain words; the value is only read as `T` once both show it
+    // was written by `write`, i.e. it holds a valid `T`
+    unsafe {
+      let magic = addr_of!((*slot).magic).read_volatile();
+      let crc = addr_of!((*slot).crc).read_volatile();
+      let value = addr_of!((*slot).value);
+      (magic == Self::MAGIC && crc == fcs16_ppp(bytes(value)) as u32).then(|| value.read_volatile())
+    }
+  }
+
+  fn write(&self, value: T) {
+    let slot = self.slot.get().cast::<Slot<T>>();
+    // SAFETY: called with interrupts disabled; `T: Copy` has no destructor to skip
+    unsafe {
+      addr_of_mut!((*slot).value).write_volatile(value);
+      let crc = fcs16_ppp(bytes(addr_of!((*slot).value))) as u32;
+      addr_of_mut!((*slot).crc).write_volatile(crc);
+      addr_of_mut!((*slot).magic).write_volatile(Self::MAGIC);
+    }
+  }
+}
+
+impl<T: Copy> Default for NoInit<T> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+// Raw bytes of the value in the slot
+unsafe fn bytes<'a, T>(value: *const T) -> &'a [u8] {
+  unsafe { core::slice::from_raw_parts(value.cast::<u8>(), size_of::<T>()) }
+}
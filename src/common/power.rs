@@ -0,0 +1,546 @@
+/// Low-Power Idle and Run-Time Clock Scaling
+///
+/// The stock thread executor of `#[embassy_executor::main]` spins back into its poll loop on
+/// every event, and the application loops poll their queues every millisecond, so the core
+/// hardly ever reaches Sleep. `Executor` is a drop-in thread executor whose idle hook, once no
+/// task is ready, gates the clocks nobody needs while sleeping and executes WFI:
+///
+/// ```ignore
+/// #[embassy_executor::main(executor = "embassy_stm32_starter::common::power::Executor")]
+/// async fn main(spawner: Spawner) { ... }
+/// ```
+///
+/// Wake-ups reach it through `BoardConfig::IDLE_IRQ`, a spare interrupt used as the executor's
+/// pender (the board file defines its handler with `idle_interrupt!`): a task woken from an
+/// interrupt or from another task pends it, which ends WFI even when it happens just before the
+/// core goes to sleep. Embassy's time driver (TIM5, `tick-hz-32_768`) is already tickless: it
+/// wakes the core only for the next alarm and once per second for its overflow, and keeps
+/// running in Sleep. Before each WFI the timers whose counter is stopped lose their Sleep-mode
+/// clock (RCC `APBxLPENR`), and with `PowerConfig::flash_off_in_sleep` so does the flash
+/// interface. Embassy's default `enable_debug_during_sleep` keeps HCLK on for the debug probe,
+/// so measure current with it off or the probe detached.
+///
+/// The idle hook times each sleep with the time driver: `sleep_pct` is the share of the last
+/// second spent in WFI and `average_current_ua` weighs the board's `RUN_CURRENT_UA` and
+/// `SLEEP_CURRENT_UA` with it; both go out in the telemetry record.
+///
+/// `set_sysclk_profile` switches the core between `SysclkProfile::LowPower` (HSI 16 MHz, the
+/// boot clock) and `SysclkProfile::Performance` (the board's `PERFORMANCE_PLL` from the HSI) at
+/// run time, with the voltage scale, flash wait states and APB dividers to match. Embassy's
+/// drivers compute their dividers once, from the boot clocks, so the switch rescales what they
+/// left running: the time driver's prescaler (the tick rate stays `TICK_HZ`), the baud rate of
+/// every enabled USART and the ADC prescaler. Everything else that depends on a clock
+/// (DWT-based timing, SysTick, I2C/SPI/PWM set up by the application) subscribes with
+/// `on_clock_change` and is called with the old and new clocks right after the switch. Switch
+/// while the serial links are idle (a character in flight is garbled) and create new embassy
+/// drivers only in `LowPower`, the clocks embassy knows about.
+use core::cell::RefCell;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering};
+use cortex_m::interrupt::InterruptNumber;
+use embassy_executor::{Spawner, raw};
+use embassy_stm32::interrupt::{InterruptExt, Priority};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use heapless::Vec;
+
+use crate::board::BoardConfig;
+
+/// Clock-change subscribers `on_clock_change` can hold
+pub const CLOCK_SUBSCRIBERS_MAX: usize = 8;
+/// HSI frequency (the `LowPower` system clock)
+pub const HSI_HZ: u32 = 16_000_000;
+
+// RCC clock enables and Sleep-mode clock enables (RM0390 / RM0430)
+const RCC_BASE: u32 = 0x4002_3800;
+const RCC_CR: *mut u32 = RCC_BASE as *mut u32;
+const RCC_PLLCFGR: *mut u32 = (RCC_BASE + 0x04) as *mut u32;
+const RCC_CFGR: *mut u32 = (RCC_BASE + 0x08) as *mut u32;
+const CR_PLLON: u32 = 1 << 24;
+const CR_PLLRDY: u32 = 1 << 25;
+// PLLCFGR: M [5:0], N [14:6], P [17:16] ((P / 2) - 1), source [22] (0 = HSI), Q [27:24]
+const PLLCFGR_FIELDS: u32 = 0x3F | (0x1FF << 6) | (0b11 << 16) | (1 << 22) | (0xF << 24);
+// CFGR: SW [1:0], SWS [3:2], HPRE [7:4], PPRE1 [12:10], PPRE2 [15:13]
+const CFGR_SW_MASK: u32 = 0b11;
+const CFGR_SW_HSI: u32 = 0b00;
+const CFGR_SW_PLL: u32 = 0b10;
+const CFGR_PPRE_MASK: u32 = 0b11_1111 << 10;
+const PWR_CR: *mut u32 = 0x4000_7000 as *mut u32;
+const PWR_CSR: *const u32 = 0x4000_7004 as *const u32;
+const PWR_CR_VOS_MASK: u32 = 0b11 << 14;
+const PWR_CR_VOS_SCALE1: u32 = 0b11 << 14;
+const PWR_CR_VOS_SCALE3: u32 = 0b01 << 14;
+const PWR_CSR_VOSRDY: u32 = 1 << 14;
+const RCC_APB1ENR_PWREN: u32 = 1 << 28;
+const FLASH_ACR: *mut u32 = 0x4002_3C00 as *mut u32;
+const FLASH_ACR_LATENCY: u32 = 0xF;
+// Time driver timer (`BoardConfig::TIME_DRIVER_TIM_BASE`): CR1.URS, EGR.UG
+const TIM_CR1_URS: u32 = 1 << 2;
+const TIM_EGR_UG: u32 = 1 << 0;
+// USART BRR/CR1 and the ADC common prescaler (ADCCLK at most 36 MHz)
+const USART_BRR: u32 = 0x08;
+const USART_CR1: u32 = 0x0C;
+const USART_CR1_UE: u32 = 1 << 13;
+const USART_CR1_OVER8: u32 = 1 << 15;
+const ADC_CCR: *mut u32 = 0x4001_2304 as *mut u32;
+const ADC_CCR_ADCPRE_SHIFT: u32 = 16;
+const ADC_MAX_HZ: u32 = 36_000_000;
+const RCC_APB1ENR: *const u32 = (RCC_BASE + 0x40) as *const u32;
+const RCC_APB2ENR: *const u32 = (RCC_BASE + 0x44) as *const u32;
+const RCC_AHB1LPENR: *mut u32 = (RCC_BASE + 0x50) as *mut u32;
+const RCC_APB1LPENR: *mut u32 = (RCC_BASE + 0x60) as *mut u32;
+const RCC_APB2LPENR: *mut u32 = (RCC_BASE + 0x64) as *mut u32;
+const AHB1LPENR_FLITFLPEN: u32 = 1 << 15;
+const TIM_CR1_CEN: u32 = 1 << 0;
+
+// Timers as (on APB2, RCC enable bit, base address): TIM2-7 and TIM12-14 on APB1, TIM1, TIM8
+// and TIM9-11 on APB2
+const TIMERS: [(bool, u32, u32); 14] = [
+  (false, 1 << 0, 0x4000_0000),
+  (false, 1 << 1, 0x4000_0400),
+  (false, 1 << 2, 0x4000_0800),
+  (false, 1 << 3, 0x4000_0C00),
+  (false, 1 << 4, 0x4000_1000),
+  (false, 1 << 5, 0x4000_1400),
+  (false, 1 << 6, 0x4000_1800),
+  (false, 1 << 7, 0x4000_1C00),
+  (false, 1 << 8, 0x4000_2000),
+  (true, 1 << 0, 0x4001_0000),
+  (true, 1 << 1, 0x4001_0400),
+  (true, 1 << 16, 0x4001_4000),
+  (true, 1 << 17, 0x4001_4400),
+  (true, 1 << 18, 0x4001_4800),
+];
+
+// USARTs as (on APB2, base address)
+const USARTS: &[(bool, u32)] = &[
+  (true, 0x4001_1000),
+  (false, 0x4000_4400),
+  (false, 0x4000_4800),
+  (false, 0x4000_4C00),
+  (false, 0x4000_5000),
+  (true, 0x4001_1400),
+  #[cfg(feature = "stm32f413")]
+  (false, 0x4000_7800),
+  #[cfg(feature = "stm32f413")]
+  (false, 0x4000_7C00),
+  #[cfg(feature = "stm32f413")]
+  (true, 0x4001_1800),
+  #[cfg(feature = "stm32f413")]
+  (true, 0x4001_1C00),
+];
+
+/// Sleep-mode settings of `Executor`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct PowerConfig {
+  /// Stop the clock of timers whose counter is not running while the core sleeps
+  pub gate_stopped_timers: bool,
+  /// Stop the flash interface clock while the core sleeps (no DMA may read flash meanwhile)
+  pub flash_off_in_sleep: bool,
+}
+
+impl Default for PowerConfig {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl PowerConfig {
+  /// Stopped timers gated, flash interface kept
+  pub const fn new() -> Self {
+    Self {
+      gate_stopped_timers: true,
+      flash_off_in_sleep: false,
+    }
+  }
+
+  pub const fn gate_stopped_timers(mut self, gate: bool) -> Self {
+    self.gate_stopped_timers = gate;
+    self
+  }
+
+  pub const fn flash_off_in_sleep(mut self, off: bool) -> Self {
+    self.flash_off_in_sleep = off;
+    self
+  }
+}
+
+static GATE_TIMERS: AtomicBool = AtomicBool::new(true);
+static FLASH_OFF: AtomicBool = AtomicBool::new(false);
+// Set by the `IDLE_IRQ` handler: a task was woken since the executor last polled
+static WOKEN: AtomicBool = AtomicBool::new(false);
+static RUNNING: AtomicBool = AtomicBool::new(false);
+// Current window (time driver ticks): start and time spent in WFI; share of the last window
+static WINDOW_START: AtomicU32 = AtomicU32::new(0);
+static WINDOW_SLEPT: AtomicU32 = AtomicU32::new(0);
+static SLEEP_PCT: AtomicU32 = AtomicU32::new(0);
+
+/// Apply Sleep-mode settings (takes effect from the next idle)
+pub fn configure(config: PowerConfig) {
+  GATE_TIMERS.store(config.gate_stopped_timers, Ordering::Relaxed);
+  FLASH_OFF.store(config.flash_off_in_sleep, Ordering::Relaxed);
+  defmt::info!("Power: {}", config);
+}
+
+/// Share of the last second the core spent in WFI (0 unless `Executor` runs)
+pub fn sleep_pct() -> u32 {
+  SLEEP_PCT.load(Ordering::Relaxed)
+}
+
+/// Average supply current over the last second estimated from `sleep_pct` and the board's run
+/// and sleep figures, in uA (0 unless `Executor` runs)
+pub fn average_current_ua() -> u32 {
+  if !RUNNING.load(Ordering::Relaxed) {
+    return 0;
+  }
+  let run = match profile() {
+    SysclkProfile::LowPower => BoardConfig::RUN_CURRENT_UA,
+    SysclkProfile::Performance => BoardConfig::PERFORMANCE_PLL.run_current_ua,
+  };
+  let pct = sleep_pct().min(100);
+  (run * (100 - pct) + BoardConfig::SLEEP_CURRENT_UA * pct) / 100
+}
+
+/// Thread-mode executor sleeping in WFI when idle (see the module documentation)
+pub struct Executor {
+  inner: raw::Executor,
+  not_send: PhantomData<*mut ()>,
+}
+
+impl Default for Executor {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Executor {
+  pub fn new() -> Self {
+    Self {
+      // The pender pends this interrupt number
+      inner: raw::Executor::new(BoardConfig::IDLE_IRQ.number() as usize as *mut ()),
+      not_send: PhantomData,
+    }
+  }
+
+  /// Spawn the initial tasks with `init`, then poll forever, sleeping whenever nothing is ready
+  pub fn run(&'static mut self, init: impl FnOnce(Spawner)) -> ! {
+    let irq = BoardConfig::IDLE_IRQ;
+    irq.set_priority(Priority::P15);
+    // SAFETY: the board's `idle_interrupt!` handler only sets a flag
+    unsafe { irq.enable() };
+    RUNNING.store(true, Ordering::Relaxed);
+    init(self.inner.spawner());
+    loop {
+      WOKEN.store(false, Ordering::Relaxed);
+      // SAFETY: only ever polled from this thread-mode loop
+      unsafe { self.inner.poll() };
+      idle();
+    }
+  }
+}
+
+/// Called by the `IDLE_IRQ` handler (see `idle_interrupt!`)
+pub fn on_interrupt() {
+  WOKEN.store(true, Ordering::Relaxed);
+}
+
+// Sleep until an interrupt, unless a task was woken during the poll. With interrupts masked, a
+// wake-up after the check leaves `IDLE_IRQ` pending, which ends WFI at once; its handler runs
+// when they are unmasked.
+fn idle() {
+  cortex_m::interrupt::free(|_| {
+    if WOKEN.load(Ordering::Relaxed) {
+      return;
+    }
+    sleep_clocks();
+    let start = ticks();
+    cortex_m::asm::dsb();
+    cortex_m::asm::wfi();
+    WINDOW_SLEPT.fetch_add(ticks().wrapping_sub(start), Ordering::Relaxed);
+  });
+  roll_window();
+}
+
+// Close the window once it spans a second: the time slept becomes `sleep_pct`
+fn roll_window() {
+  let now = ticks();
+  let window = now.wrapping_sub(WINDOW_START.load(Ordering::Relaxed));
+  if window >= embassy_time::TICK_HZ as u32 {
+    let slept = WINDOW_SLEPT.swap(0, Ordering::Relaxed).min(window);
+    SLEEP_PCT.store((slept as u64 * 100 / window as u64) as u32, Ordering::Relaxed);
+    WINDOW_START.store(now, Ordering::Relaxed);
+  }
+}
+
+fn ticks() -> u32 {
+  embassy_time::Instant::now().as_ticks() as u32
+}
+
+// Sleep-mode clocks: kept for the timers whose counter runs (the time driver's among them),
+// dropped for the stopped ones; flash interface per `PowerConfig::flash_off_in_sleep`
+fn sleep_clocks() {
+  // SAFETY: read-modify-write of the RCC Sleep-mode enables with interrupts masked; timer CR1
+  // is only read when the timer's clock is on
+  unsafe {
+    if GATE_TIMERS.load(Ordering::Relaxed) {
+      let mut lpenr = [RCC_APB1LPENR.read_volatile(), RCC_APB2LPENR.read_volatile()];
+      let enr = [RCC_APB1ENR.read_volatile(), RCC_APB2ENR.read_volatile()];
+      for (apb2, bit, base) in TIMERS {
+        let bus = apb2 as usize;
+        let running = enr[bus] & bit != 0 && (base as *const u32).read_volatile() & TIM_CR1_CEN != 0;
+        if running {
+          lpenr[bus] |= bit;
+        } else {
+          lpenr[bus] &= !bit;
+        }
+      }
+      RCC_APB1LPENR.write_volatile(lpenr[0]);
+      RCC_APB2LPENR.write_volatile(lpenr[1]);
+    }
+    let ahb1 = RCC_AHB1LPENR.read_volatile();
+    let ahb1 = if FLASH_OFF.load(Ordering::Relaxed) {
+      ahb1 & !AHB1LPENR_FLITFLPEN
+    } else {
+      ahb1 | AHB1LPENR_FLITFLPEN
+    };
+    RCC_AHB1LPENR.write_volatile(ahb1);
+  }
+}
+
+/// Define the interrupt handler waking `power::Executor`
+///
+/// `idle_interrupt!(FMPI2C1_EV);` in the board file, naming the same interrupt as
+/// `BoardConfig::IDLE_IRQ` (one nothing else uses: take it off the board's `interrupt_stubs!`).
+#[macro_export]
+macro_rules! idle_interrupt {
+  ($irq:ident) => {
+    const _: () = {
+      use embassy_stm32::interrupt;
+
+      #[interrupt]
+      fn $irq() {
+        $crate::common::power::on_interrupt();
+      }
+    };
+  };
+}
+
+/// System clock profiles for `set_sysclk_profile`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum SysclkProfile {
+  /// HSI 16 MHz straight, APB buses undivided, voltage scale 3 (the boot clock)
+  LowPower = 0,
+  /// `BoardConfig::PERFORMANCE_PLL` from the HSI, voltage scale 1
+  Performance = 1,
+}
+
+/// PLL and divider settings of the `Performance` profile (in the board file)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct PllSettings {
+  /// HSI / `m` (2 MHz recommended) x `n` / `p` = SYSCLK; VCO / `q` for USB/SDIO (at most 48 MHz)
+  pub m: u32,
+  pub n: u32,
+  pub p: u32,
+  pub q: u32,
+  /// APB1 and APB2 dividers (1, 2, 4, 8 or 16)
+  pub apb1_div: u32,
+  pub apb2_div: u32,
+  /// Flash wait states at this SYSCLK (2.7-3.6 V)
+  pub flash_latency: u32,
+  /// Supply current running at this SYSCLK, in uA (for `average_current_ua`)
+  pub run_current_ua: u32,
+}
+
+impl PllSettings {
+  pub const fn sysclk_hz(&self) -> u32 {
+    HSI_HZ / self.m * self.n / self.p
+  }
+}
+
+/// Core and bus clocks, in Hz
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct Clocks {
+  pub sysclk: u32,
+  pub hclk: u32,
+  pub pclk1: u32,
+  pub pclk2: u32,
+  /// Timer kernel clocks: PCLK, or twice PCLK when the APB divider is not 1
+  pub apb1_timers: u32,
+  pub apb2_timers: u32,
+}
+
+/// Clocks before and after a profile change, handed to the `on_clock_change` subscribers
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct ClockChange {
+  pub from: Clocks,
+  pub to: Clocks,
+}
+
+/// Error from `on_clock_change`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct TableFull;
+
+static PROFILE: AtomicU8 = AtomicU8::new(SysclkProfile::LowPower as u8);
+static SUBSCRIBERS: Mutex<CriticalSectionRawMutex, RefCell<Vec<fn(&ClockChange), CLOCK_SUBSCRIBERS_MAX>>> = Mutex::new(RefCell::new(Vec::new()));
+
+/// Call `subscriber` after every clock change (with interrupts off: keep it to register writes)
+pub fn on_clock_change(subscriber: fn(&ClockChange)) -> Result<(), TableFull> {
+  SUBSCRIBERS.lock(|subscribers| subscribers.borrow_mut().push(subscriber).map_err(|_| TableFull))
+}
+
+/// Current system clock profile
+pub fn profile() -> SysclkProfile {
+  match PROFILE.load(Ordering::Relaxed) {
+    1 => SysclkProfile::Performance,
+    _ => SysclkProfile::LowPower,
+  }
+}
+
+/// Current clocks, read back from RCC
+pub fn clocks() -> Clocks {
+  // SAFETY: read-only access to the RCC configuration
+  let (cfgr, pllcfgr) = unsafe { (RCC_CFGR.read_volatile(), RCC_PLLCFGR.read_volatile()) };
+  let sysclk = if (cfgr >> 2) & 0b11 == CFGR_SW_PLL {
+    let (m, n, p) = (pllcfgr & 0x3F, (pllcfgr >> 6) & 0x1FF, (((pllcfgr >> 16) & 0b11) + 1) * 2);
+    HSI_HZ / m * n / p
+  } else {
+    HSI_HZ
+  };
+  // HPRE 0b0xxx: /1, 0b1000..0b1111: /2, /4, /8, /16, /64, /128, /256, /512
+  let hpre = (cfgr >> 4) & 0xF;
+  let hclk = if hpre < 8 { sysclk } else { sysclk >> [1, 2, 3, 4, 6, 7, 8, 9][(hpre - 8) as usize] };
+  // PPRE 0b0xx: /1, 0b100..0b111: /2, /4, /8, /16
+  let apb = |ppre: u32| if ppre < 4 { (hclk, hclk) } else { (hclk >> (ppre - 3), hclk >> (ppre - 4)) };
+  let (pclk1, apb1_timers) = apb((cfgr >> 10) & 0b111);
+  let (pclk2, apb2_timers) = apb((cfgr >> 13) & 0b111);
+  Clocks {
+    sysclk,
+    hclk,
+    pclk1,
+    pclk2,
+    apb1_timers,
+    apb2_timers,
+  }
+}
+
+/// Switch the system clock to `profile`, rescale the embassy-owned dividers and notify the
+/// `on_clock_change` subscribers (see the module documentation)
+pub fn set_sysclk_profile(profile: SysclkProfile) {
+  let from = clocks();
+  let pll = BoardConfig::PERFORMANCE_PLL;
+  cortex_m::interrupt::free(|_| {
+    // SAFETY: the clock tree is reconfigured with interrupts off, in the order RM0390/RM0430
+    // give: flash wait states up before the clock rises and down after it falls, the voltage
+    // scale changed with the PLL off, APB dividers in range before SYSCLK moves
+    unsafe {
+      let apb1enr = (RCC_BASE + 0x40) as *mut u32;
+      apb1enr.write_volatile(apb1enr.read_volatile() | RCC_APB1ENR_PWREN);
+      let cfgr = RCC_CFGR.read_volatile();
+      if cfgr & CFGR_SW_MASK == CFGR_SW_PLL {
+        // Back to the HSI first: the PLL cannot change while it runs the core
+        RCC_CFGR.write_volatile(cfgr & !CFGR_SW_MASK | CFGR_SW_HSI);
+        while (RCC_CFGR.read_volatile() >> 2) & 0b11 != CFGR_SW_HSI {}
+      }
+      RCC_CR.write_volatile(RCC_CR.read_volatile() & !CR_PLLON);
+      while RCC_CR.read_volatile() & CR_PLLRDY != 0 {}
+      match profile {
+        SysclkProfile::LowPower => {
+          RCC_CFGR.write_volatile(RCC_CFGR.read_volatile() & !CFGR_PPRE_MASK);
+          set_flash_latency(0);
+          PWR_CR.write_volatile(PWR_CR.read_volatile() & !PWR_CR_VOS_MASK | PWR_CR_VOS_SCALE3);
+        }
+        SysclkProfile::Performance => {
+          PWR_CR.write_volatile(PWR_CR.read_volatile() & !PWR_CR_VOS_MASK | PWR_CR_VOS_SCALE1);
+          set_flash_latency(pll.flash_latency);
+          let fields = pll.m | pll.n << 6 | (pll.p / 2 - 1) << 16 | pll.q << 24;
+          RCC_PLLCFGR.write_volatile(RCC_PLLCFGR.read_volatile() & !PLLCFGR_FIELDS | fields);
+          RCC_CR.write_volatile(RCC_CR.read_volatile() | CR_PLLON);
+          while RCC_CR.read_volatile() & CR_PLLRDY == 0 {}
+          while PWR_CSR.read_volatile() & PWR_CSR_VOSRDY == 0 {}
+          let ppre = ppre(pll.apb1_div) << 10 | ppre(pll.apb2_div) << 13;
+          RCC_CFGR.write_volatile(RCC_CFGR.read_volatile() & !CFGR_PPRE_MASK | ppre);
+          RCC_CFGR.write_volatile(RCC_CFGR.read_volatile() & !CFGR_SW_MASK | CFGR_SW_PLL);
+          while (RCC_CFGR.read_volatile() >> 2) & 0b11 != CFGR_SW_PLL {}
+        }
+      }
+    }
+    PROFILE.store(profile as u8, Ordering::Relaxed);
+    let change = ClockChange { from, to: clocks() };
+    rescale_time_driver(&change.to);
+    rescale_usarts(&change);
+    rescale_adc(&change.to);
+    SUBSCRIBERS.lock(|subscribers| subscribers.borrow().iter().for_each(|subscriber| subscriber(&change)));
+  });
+  let to = clocks();
+  defmt::info!("Power: {} profile, SYSCLK {} Hz (PCLK1 {} Hz, PCLK2 {} Hz)", profile, to.sysclk, to.pclk1, to.pclk2);
+}
+
+// FLASH_ACR.LATENCY, read back before the clock changes
+unsafe fn set_flash_latency(wait_states: u32) {
+  // SAFETY: forwarded from `set_sysclk_profile`
+  unsafe {
+    FLASH_ACR.write_volatile(FLASH_ACR.read_volatile() & !FLASH_ACR_LATENCY | wait_states);
+    while FLASH_ACR.read_volatile() & FLASH_ACR_LATENCY != wait_states {}
+  }
+}
+
+// PPRE field for an APB divider
+const fn ppre(div: u32) -> u32 {
+  match div {
+    2 => 0b100,
+    4 => 0b101,
+    8 => 0b110,
+    16 => 0b111,
+    _ => 0b000,
+  }
+}
+
+// Time driver prescaler for the new APB1 timer clock, as embassy computes it at init. The
+// counter value survives: the update that loads PSC (URS set, so no update interrupt) clears
+// CNT, which is then written back.
+fn rescale_time_driver(to: &Clocks) {
+  let psc = (to.apb1_timers / embassy_time::TICK_HZ as u32).saturating_sub(1).min(0xFFFF);
+  let reg = |offset: u32| (BoardConfig::TIME_DRIVER_TIM_BASE + offset) as *mut u32;
+  // SAFETY: interrupts are off; the time driver only reads the counter and its flags
+  unsafe {
+    let cr1 = reg(0x00).read_volatile();
+    let cnt = reg(0x24).read_volatile();
+    reg(0x28).write_volatile(psc);
+    reg(0x00).write_volatile(cr1 | TIM_CR1_URS);
+    reg(0x14).write_volatile(TIM_EGR_UG);
+    reg(0x24).write_volatile(cnt);
+    reg(0x00).write_volatile(cr1);
+  }
+}
+
+// Enabled USARTs keep their baud rate: BRR holds PCLK / baud (x2 with OVER8, fraction in 3 bits)
+fn rescale_usarts(change: &ClockChange) {
+  for &(apb2, base) in USARTS {
+    let (from, to) = if apb2 {
+      (change.from.pclk2, change.to.pclk2)
+    } else {
+      (change.from.pclk1, change.to.pclk1)
+    };
+    let reg = |offset: u32| (base + offset) as *mut u32;
+    // SAFETY: interrupts are off; a USART without its clock reads as 0 (not enabled)
+    unsafe {
+      let cr1 = reg(USART_CR1).read_volatile();
+      if cr1 & USART_CR1_UE == 0 {
+        continue;
+      }
+      let brr = reg(USART_BRR).read_volatile();
+      let over8 = cr1 & USART_CR1_OVER8 != 0;
+      let div = if over8 { brr & !0xF | (brr & 0x7) << 1 } else { brr };
+      let div = ((div as u64 * to as u64 + from as u64 / 2) / from as u64) as u32;
+      let brr = if over8 { div & !0xF | (div & 0xF) >> 1 } else { div };
+      reg(USART_BRR).write_volatile(brr & 0xFFFF);
+    }
+  }
+}
+
+// Smallest ADC prescaler (/2, /4, /6, /8) keeping ADCCLK within its maximum
+fn rescale_adc(to: &Clocks) {
+  let adcpre = (0..4).find(|&pre| to.pclk2 / (2 * (pre + 1)) <= ADC_MAX_HZ).unwrap_or(3);
+  // SAFETY: interrupts are off; ADC_CCR is otherwise only written by embassy's ADC at init
+  unsafe { ADC_CCR.write_volatile(ADC_CCR.read_volatile() & !(0b11 << ADC_CCR_ADCPRE_SHIFT) | adcpre << ADC_CCR_ADCPRE_SHIFT) };
+}
@@ -0,0 +1,108 @@
+/// Pseudo-random numbers for protocol timing
+///
+/// Small xorshift PRNG used for retransmit jitter, bus backoff and duty-cycle
+/// spreading. Seeded from the 96-bit device unique ID so identical boards that
+/// power up together do not pick the same slots. The source is pluggable via
+/// `RandomSource` (e.g. `hardware::rng::HwRng` where available).
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+/// A source of random 32-bit words
+pub trait RandomSource {
+  fn next_u32(&mut self) -> u32;
+
+  /// Uniform value in `[0, bound)` (0 if `bound` is 0)
+  fn below(&mut self, bound: u32) -> u32 {
+    if bound == 0 {
+      return 0;
+    }
+    // Multiply-shift range reduction (no division, negligible bias for small bounds)
+    ((self.next_u32() as u64 * bound as u64) >> 32) as u32
+  }
+}
+
+/// Xorshift32 generator (never reaches the all-zero state)
+#[derive(Clone, Copy, Debug)]
+pub struct XorShift32 {
+  state: u32,
+}
+
+impl XorShift32 {
+  pub const fn new(seed: u32) -> Self {
+    Self {
+      state: if seed == 0 { 0x9E37_79B9 } else { seed },
+    }
+  }
+}
+
+impl RandomSource for XorShift32 {
+  fn next_u32(&mut self) -> u32 {
+    let mut x = self.state;
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+    self.state = x;
+    x
+  }
+}
+
+/// Murmur3 finalizer - spreads entropy across all bits
+const fn mix(mut x: u32) -> u32 {
+  x ^= x >> 16;
+  x = x.wrapping_mul(0x85EB_CA6B);
+  x ^= x >> 13;
+  x = x.wrapping_mul(0xC2B2_AE35);
+  x ^= x >> 16;
+  x
+}
+
+/// Derive a seed from the device unique ID, optionally mixed with extra entropy (e.g. uptime ticks)
+pub fn uid_seed(extra: u32) -> u32 {
+  let uid = embassy_stm32::uid::uid();
+  uid.chunks_exact(4).fold(mix(extra), |acc, w| mix(acc ^ u32::from_le_bytes([w[0], w[1], w[2], w[3]])))
+}
+
+// Shared generator used by the free functions below
+static RNG: Mutex<CriticalSectionRawMutex, RefCell<XorShift32>> = Mutex::new(RefCell::new(XorShift32::new(0)));
+
+/// Reseed the shared generator
+pub fn seed(seed: u32) {
+  RNG.lock(|rng| *rng.borrow_mut() = XorShift32::new(seed));
+}
+
+/// Seed the shared generator from the unique ID (call once at startup)
+pub fn seed_from_uid() {
+  seed(uid_seed(embassy_time::Instant::now().as_ticks() as u32));
+}
+
+/// Next word from the shared generator
+pub fn next_u32() -> u32 {
+  RNG.lock(|rng| rng.borrow_mut().next_u32())
+}
+
+/// Timing helpers built on a `RandomSource`
+pub struct Backoff;
+
+impl Backoff {
+  /// `base_ms` plus a random jitter in `[0, spread_ms)`
+  pub fn jitter_ms<R: RandomSource>(rng: &mut R, base_ms: u64, spread_ms: u32) -> u64 {
+    base_ms + rng.below(spread_ms) as u64
+  }
+
+  /// Exponential backoff with full jitter: random in `[0, min(max_ms, base_ms << attempt))`
+  pub fn exponential_ms<R: RandomSource>(rng: &mut R, attempt: u32, base_ms: u32, max_ms: u32) -> u64 {
+    let ceiling = ((base_ms as u64) << attempt.min(32)).min(max_ms as u64) as u32;
+    rng.below(ceiling.max(1)) as u64
+  }
+}
+
+/// `RandomSource` handle onto the shared generator
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SharedRandom;
+
+impl RandomSource for SharedRandom {
+  fn next_u32(&mut self) -> u32 {
+    next_u32()
+  }
+}
@@ -0,0 +1,203 @@
+/// System control: deliberate resets, standby and the STM32 ROM bootloader
+///
+/// Application code resets through `reboot(reason)` rather than `SCB::sys_reset()`: it gives
+/// the TX owner up to `REBOOT_FLUSH_MS` to write the queued comm messages and `config_task` to
+/// write a pending configuration, then leaves `reason` in the `rtc::BKP_RESET_REASON` backup
+/// register and resets. `shutdown_to_standby()` does the same and then enters standby (lowest
+/// power, RAM lost); only NRST, or an RTC alarm/wake-up or WKUP pin the application armed, brings
+/// it back, through a reset. `last_reboot_reason()` reads (and clears) the register at the next
+/// boot. Callers that cannot wait (handlers outside a task, a broken link) use `reset(reason)`,
+/// which records the reason and resets straight away.
+///
+/// The built-in bootloader in system memory reflashes the part over USART1 (PA9/PA10), USART3 or
+/// USB DFU (user USB port) without a debug probe. It expects the reset state, so
+/// `enter_rom_bootloader()` does not jump from a running system: it leaves a request word in
+/// the `rtc::BKP_BOOTLOADER` backup register (kept across a reset) and resets. `check_bootloader_request()`, called
+/// first thing in `main` before `embassy_stm32::init`, finds the word on the way back up, quiets
+/// the core (interrupts off, SysTick stopped, NVIC cleared), maps system memory at 0 and loads
+/// MSP/PC from its vector table.
+/// Triggers: the `Bootloader` comm command (answered with `Ack` first) or the user button held
+/// at boot (`bootloader_if_held`).
+use core::sync::atomic::{AtomicU32, Ordering};
+use cortex_m::peripheral::{NVIC, SCB};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal::digital::InputPin;
+
+use crate::hardware::ButtonReader;
+use crate::hardware::rtc::{self, BKP_BOOTLOADER, BKP_RESET_REASON};
+use crate::service::{comm, config, duty_cycle};
+
+/// Start of system memory (ROM bootloader vector table) on the STM32F4
+const SYSTEM_MEMORY: u32 = 0x1FFF_0000;
+// SYSCFG clock (RCC_APB2ENR.SYSCFGEN) and SYSCFG_MEMRMP.MEM_MODE = 01 (system flash at 0)
+const RCC_APB2ENR: *mut u32 = 0x4002_3844 as *mut u32;
+const RCC_APB2ENR_SYSCFGEN: u32 = 1 << 14;
+const SYSCFG_MEMRMP: *mut u32 = 0x4001_3800 as *mut u32;
+const MEMRMP_SYSTEM_FLASH: u32 = 0b01;
+// Standby: PWR_CR.PDDS (standby on deep sleep) and CWUF (clear the wake-up flag)
+const PWR_CR: *mut u32 = 0x4000_7000 as *mut u32;
+const PWR_CR_PDDS: u32 = 1 << 1;
+const PWR_CR_CWUF: u32 = 1 << 2;
+
+/// Longest `reboot` / `shutdown_to_standby` wait for queued comm messages and config writes
+pub const REBOOT_FLUSH_MS: u64 = 500;
+const REBOOT_POLL_MS: u64 = 10;
+
+/// Marker left in `BKP_BOOTLOADER` ("BOOT")
+const BOOT_REQUEST_MAGIC: u32 = 0x544F_4F42;
+
+/// Why the firmware reset itself, kept in `rtc::BKP_RESET_REASON` for the next boot
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum RebootReason {
+  /// `reboot` command (shell, RTT, host)
+  Command = 1,
+  /// ROM bootloader request (`enter_rom_bootloader`)
+  Bootloader = 2,
+  /// Staged firmware update to install (`service::dfu`)
+  Update = 3,
+  /// Too many comm link errors (`comm::ErrorPolicy::ResetAfter`)
+  CommErrors = 4,
+  /// Woken up from `shutdown_to_standby`
+  Standby = 5,
+  /// Application decision
+  Application = 6,
+}
+
+impl RebootReason {
+  fn from_u32(value: u32) -> Option<Self> {
+    match value {
+      1 => Some(RebootReason::Command),
+      2 => Some(RebootReason::Bootloader),
+      3 => Some(RebootReason::Update),
+      4 => Some(RebootReason::CommErrors),
+      5 => Some(RebootReason::Standby),
+      6 => Some(RebootReason::Application),
+      _ => None,
+    }
+  }
+}
+
+const REASON_NOT_READ: u32 = u32::MAX;
+static LAST_REASON: AtomicU32 = AtomicU32::new(REASON_NOT_READ);
+
+/// Reason `reboot`/`reset` left for this boot; `None` after any other reset (power-on, pin,
+/// watchdog, a raw `SCB::sys_reset`). The first call reads and clears the backup register.
+pub fn last_reboot_reason() -> Option<RebootReason> {
+  let mut value = LAST_REASON.load(Ordering::Relaxed);
+  if value == REASON_NOT_READ {
+    value = rtc::backup_read(BKP_RESET_REASON).unwrap_or(0);
+    let _ = rtc::backup_write(BKP_RESET_REASON, 0);
+    LAST_REASON.store(value, Ordering::Relaxed);
+  }
+  RebootReason::from_u32(value)
+}
+
+/// Record `reason` and reset at once, without flushing anything
+pub fn reset(reason: RebootReason) -> ! {
+  defmt::warn!("Reset: {}", reason);
+  let _ = rtc::backup_write(BKP_RESET_REASON, reason as u32);
+  SCB::sys_reset()
+}
+
+/// Flush queued comm TX and pending config writes (up to `REBOOT_FLUSH_MS`), record `reason`
+/// and reset
+pub async fn reboot(reason: RebootReason) -> ! {
+  defmt::info!("Rebooting: {}", reason);
+  flush().await;
+  reset(reason)
+}
+
+/// Flush like `reboot`, then enter standby until NRST or an armed wake-up source resets the MCU
+/// (`last_reboot_reason()` is then `Standby`). A running independent watchdog keeps counting in
+/// standby and resets it too.
+pub async fn shutdown_to_standby() -> ! {
+  defmt::info!("Entering standby");
+  flush().await;
+  // Also unlocks the backup domain, which enables the PWR clock PWR_CR needs
+  let _ = rtc::backup_write(BKP_RESET_REASON, RebootReason::Standby as u32);
+  // SAFETY: the system is being shut down; nothing runs after this
+  unsafe {
+    quiesce();
+    PWR_CR.write_volatile(PWR_CR.read_volatile() | PWR_CR_PDDS | PWR_CR_CWUF);
+    cortex_m::Peripherals::steal().SCB.set_sleepdeep();
+  }
+  loop {
+    cortex_m::asm::dsb();
+    cortex_m::asm::wfi();
+  }
+}
+
+// Wait for the comm TX queues to drain and config_task to finish, then for the last frame on the wire
+async fn flush() {
+  let deadline = Instant::now() + Duration::from_millis(REBOOT_FLUSH_MS);
+  while !(comm::tx_idle() && !config::save_pending()) {
+    if Instant::now() >= deadline {
+      defmt::warn!(
+        "Reboot: flush timed out (comm TX idle {}, duty-cycle outbox {}, config pending {})",
+        comm::tx_idle(),
+        duty_cycle::pending(),
+        config::save_pending()
+      );
+      return;
+    }
+    Timer::after_millis(REBOOT_POLL_MS).await;
+  }
+  Timer::after_millis(REBOOT_POLL_MS).await;
+}
+
+/// Reset into the ROM bootloader (through `check_bootloader_request` at the next boot)
+pub fn enter_rom_bootloader() -> ! {
+  defmt::warn!("Entering ROM bootloader");
+  let _ = rtc::backup_write(BKP_BOOTLOADER, BOOT_REQUEST_MAGIC);
+  reset(RebootReason::Bootloader)
+}
+
+/// Jump to the ROM bootloader if `enter_rom_bootloader` asked for it; call first thing in `main`
+pub fn check_bootloader_request() {
+  if rtc::backup_read(BKP_BOOTLOADER) != Some(BOOT_REQUEST_MAGIC) {
+    return;
+  }
+  // Consumed once so the bootloader's own reset returns to the application
+  let _ = rtc::backup_write(BKP_BOOTLOADER, 0);
+  // SAFETY: still in the reset state apart from the core: nothing else owns these yet
+  unsafe { jump_to_system_memory() }
+}
+
+/// Enter the ROM bootloader when the user button is still held `hold_ms` after boot
+pub async fn bootloader_if_held<B: InputPin>(button: &mut B, hold_ms: u64) {
+  if !ButtonReader::is_pressed(button) {
+    return;
+  }
+  defmt::info!("Button held at boot: release within {} ms to continue", hold_ms);
+  Timer::after_millis(hold_ms).await;
+  if ButtonReader::is_pressed(button) {
+    enter_rom_bootloader();
+  }
+}
+
+// Interrupts off, SysTick stopped, every NVIC interrupt disabled and unpended
+unsafe fn quiesce() {
+  cortex_m::interrupt::disable();
+  let mut cp = unsafe { cortex_m::Peripherals::steal() };
+  cp.SYST.disable_counter();
+  cp.SYST.disable_interrupt();
+  cp.SYST.clear_current();
+  for i in 0..8 {
+    unsafe {
+      (*NVIC::PTR).icer[i].write(u32::MAX);
+      (*NVIC::PTR).icpr[i].write(u32::MAX);
+    }
+  }
+}
+
+unsafe fn jump_to_system_memory() -> ! {
+  unsafe {
+    quiesce();
+    RCC_APB2ENR.write_volatile(RCC_APB2ENR.read_volatile() | RCC_APB2ENR_SYSCFGEN);
+    SYSCFG_MEMRMP.write_volatile(MEMRMP_SYSTEM_FLASH);
+    cortex_m::interrupt::enable();
+    // Loads MSP and the reset vector from the bootloader's vector table
+    cortex_m::asm::bootload(SYSTEM_MEMORY as *const u32)
+  }
+}
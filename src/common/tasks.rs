@@ -1,11 +1,20 @@
+use crate::hardware::adc::{vdda_mv, vrefint_cal};
+use crate::hardware::encoder::Encoder;
+use crate::hardware::onewire::{DS18B20_CONVERSION_MS, DS18B20_FAMILY, Ds18b20, ONEWIRE_MAX_DEVICES, OneWire};
 use crate::hardware::{ButtonReader, LedControl, Timing};
+use crate::service::alarm::{self, Severity};
+use crate::service::comm;
+use crate::service::telemetry::{self, Telemetry, TelemetryOutput};
 use crate::*;
 /// Task definitions and implementations
 ///
 /// This module contains reusable Embassy tasks that can be
 /// used across different binaries and applications.
+use embassy_stm32::adc::{Adc, SampleTime};
 use embassy_stm32::gpio::{Input, Output};
+use embassy_stm32::peripherals::{ADC1, TIM3};
 use embassy_stm32::rtc::Rtc;
+use embassy_time::Duration;
 
 /// LED blinking task - configurable blink rate
 #[embassy_executor::task]
@@ -19,12 +28,41 @@ pub async fn led_blink(mut led: Output<'static>, delay_ms: u64) {
   }
 }
 
+/// Heartbeat task - short blink every `Timing::HEARTBEAT_INTERVAL_MS` to show the firmware is alive
+#[embassy_executor::task]
+pub async fn heartbeat(mut led: Output<'static>) {
+  let mut ticks = Timing::every(Duration::from_millis(Timing::HEARTBEAT_INTERVAL_MS));
+  loop {
+    LedControl::turn_on(&mut led);
+    Timing::delay_ms(50).await;
+    LedControl::turn_off(&mut led);
+    ticks.next().await;
+  }
+}
+
+/// Comm activity task - flashes the LED whenever frames are received or sent
+#[embassy_executor::task]
+pub async fn comm_activity(mut led: Output<'static>) {
+  let mut last = 0;
+  loop {
+    let stats = comm::stats();
+    let frames = stats.rx_frames.wrapping_add(stats.tx_frames);
+    if frames != last {
+      last = frames;
+      LedControl::turn_on(&mut led);
+      Timing::delay_ms(20).await;
+    }
+    LedControl::turn_off(&mut led);
+    Timing::delay_ms(20).await;
+  }
+}
+
 /// Button monitoring task
 #[embassy_executor::task]
-pub async fn button_monitor(button: Input<'static>) {
-  let mut last_state = ButtonReader::is_released(&button);
+pub async fn button_monitor(mut button: Input<'static>) {
+  let mut last_state = ButtonReader::is_released(&mut button);
   loop {
-    let current_state = ButtonReader::is_pressed(&button);
+    let current_state = ButtonReader::is_pressed(&mut button);
     if current_state != last_state {
       if current_state {
         debug!("Button released!");
@@ -41,11 +79,140 @@ pub async fn button_monitor(button: Input<'static>) {
 #[embassy_executor::task]
 pub async fn rtc_clock(_rtc: Rtc) {
   let mut seconds: u64 = 0;
+  let mut ticks = Timing::every(Duration::from_millis(Timing::RTC_UPDATE_INTERVAL_MS));
   loop {
-    seconds = seconds.wrapping_add(1);
-    if seconds % 60 == 0 {
+    // Missed ticks still count, so the minutes stay in step with real time
+    let missed = ticks.next().await;
+    let before = seconds;
+    seconds = seconds.wrapping_add(1 + missed as u64);
+    if seconds / 60 != before / 60 {
       debug!("RTC minutes: {}", seconds / 60);
     }
-    Timing::delay_ms(Timing::RTC_UPDATE_INTERVAL_MS).await;
+  }
+}
+
+/// Alarm annunciation task - blinks the LED (and pulses an optional active-high buzzer)
+/// for the most severe unacknowledged alarm: fast for critical, slow for warning, steady for info
+#[embassy_executor::task]
+pub async fn alarm_indicator(mut led: Output<'static>, mut buzzer: Option<Output<'static>>) {
+  let mut on = false;
+  loop {
+    let severity = alarm::highest_unacked();
+    let period_ms = match severity {
+      Some(Severity::Critical) => 100,
+      _ => 500,
+    };
+    on = match severity {
+      Some(Severity::Critical | Severity::Warning) => !on,
+      Some(Severity::Info) => true,
+      None => false,
+    };
+    if on {
+      LedControl::turn_on(&mut led);
+    } else {
+      LedControl::turn_off(&mut led);
+    }
+    if let Some(buzzer) = buzzer.as_mut() {
+      // Only critical alarms sound the buzzer
+      buzzer.set_level((on && severity == Some(Severity::Critical)).into());
+    }
+    Timing::delay_ms(period_ms).await;
+  }
+}
+
+/// Encoder monitor task - logs position and speed while the encoder moves
+/// (spawn with `BoardConfig::init_encoder(counts_per_rev)`)
+#[embassy_executor::task]
+pub async fn encoder_monitor(mut encoder: Encoder<TIM3>) {
+  loop {
+    let sample = encoder.sample();
+    if sample.delta != 0 {
+      debug!("Encoder: position {} ({} counts/s, {} rpm)", sample.position, sample.counts_per_s, sample.rpm);
+    }
+    Timing::delay_ms(Timing::ENCODER_SAMPLE_MS).await;
+  }
+}
+
+/// DS18B20 task - finds the sensors on a 1-Wire bus and logs each temperature every `period_s`
+/// seconds (spawn with `BoardConfig::init_onewire()`)
+#[embassy_executor::task]
+pub async fn ds18b20_task(mut bus: OneWire<'static>, period_s: u64) {
+  let sensors = match bus.search() {
+    Ok(roms) => roms
+      .into_iter()
+      .filter(|rom| rom.family() == DS18B20_FAMILY)
+      .collect::<heapless::Vec<_, ONEWIRE_MAX_DEVICES>>(),
+    Err(e) => {
+      warn!("DS18B20: bus search failed: {}", e);
+      heapless::Vec::new()
+    }
+  };
+  info!("DS18B20: {} sensors", sensors.len());
+  loop {
+    // One conversion for all sensors, then read them one by one
+    if Ds18b20::start_conversion(&mut bus, None).is_ok() {
+      Timer::after_millis(DS18B20_CONVERSION_MS).await;
+      for rom in &sensors {
+        match Ds18b20::read_centi_c(&mut bus, Some(rom)) {
+          Ok(centi_c) => info!("DS18B20 {:x}: {} (0.01 °C)", rom.0, centi_c),
+          Err(e) => warn!("DS18B20 {:x}: {}", rom.0, e),
+        }
+      }
+    }
+    Timer::after_secs(period_s).await;
+  }
+}
+
+/// Telemetry task - publishes a `service::telemetry` record every `period_s` seconds
+/// (spawn with `BoardConfig::init_supply_adc()`)
+#[embassy_executor::task]
+pub async fn telemetry_task(mut adc: Adc<'static, ADC1>, period_s: u64, output: TelemetryOutput) {
+  let mut vrefint = adc.enable_vrefint();
+  adc.set_sample_time(SampleTime::CYCLES480);
+  Timing::delay_ms(1).await; // VREFINT start-up (10 us max)
+  let cal = vrefint_cal();
+  let mut ticks = Timing::every(Duration::from_secs(period_s));
+  loop {
+    {
+      #[cfg(feature = "task_metrics")]
+      let _busy = crate::diagnostics::task_metrics::Iteration::begin(crate::diagnostics::task_metrics::Task::Telemetry);
+      let vdda = vdda_mv(adc.blocking_read(&mut vrefint), cal);
+      telemetry::publish(Telemetry::gather(vdda), output);
+    }
+    let missed = ticks.next().await;
+    if missed > 0 {
+      warn!("Telemetry: {} records skipped (task held up)", missed);
+    }
+  }
+}
+
+/// Microphone level task - logs the RMS and peak level of each `period_ms` block and publishes
+/// them (u16 LE each) on pubsub `topic` when the host subscribed (spawn with
+/// `BoardConfig::init_microphone()`)
+#[cfg(feature = "has_dfsdm")]
+#[embassy_executor::task]
+pub async fn mic_level_task(mut mic: crate::hardware::dfsdm::Microphone, period_ms: u32, topic: u16) {
+  use crate::hardware::dfsdm::{self, DFSDM_RING_LEN};
+  use crate::service::pubsub;
+
+  let mut block = [0i16; 256];
+  let block_len = block.len().min(DFSDM_RING_LEN);
+  let blocks = (mic.config().sample_rate_hz() * period_ms / 1000 / block_len as u32).max(1);
+  loop {
+    let (mut sum_sq, mut count, mut peak) = (0u64, 0u64, 0u16);
+    for _ in 0..blocks {
+      let n = mic.read(&mut block[..block_len]).await;
+      sum_sq += block[..n].iter().map(|&s| (s as i64 * s as i64) as u64).sum::<u64>();
+      count += n as u64;
+      peak = peak.max(dfsdm::peak(&block[..n]));
+    }
+    let rms = dfsdm::rms_of_mean_square(sum_sq / count.max(1));
+    info!("Mic: RMS {} peak {} (overruns {})", rms, peak, dfsdm::overruns());
+    if pubsub::host_subscribed(topic) {
+      let mut data = [0u8; 4];
+      data[..2].copy_from_slice(&rms.to_le_bytes());
+      data[2..].copy_from_slice(&peak.to_le_bytes());
+      let _ = pubsub::publish(topic, &data);
+    }
   }
 }
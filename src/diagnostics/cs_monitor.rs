@@ -0,0 +1,116 @@
+//! Interrupt-disabled window monitor (feature `cs_monitor`)
+// Measures how long interrupts stay blocked (critical sections, direct flash erase/program
+// busy-waits, long high-priority ISRs) by running SysTick at the highest priority and timing
+// how late each tick is serviced:
+// - within one period, SysTick's own counter gives the exact delay since the tick fired;
+// - for longer windows, the DWT cycle counter gap since the previous tick gives the delay
+//   to within one period.
+// Windows shorter than `CS_MONITOR_PERIOD_US` that do not overlap a tick are not seen, so
+// treat the result as "at least this long" with one-period resolution.
+//
+// The worst window of each second and since boot are kept for telemetry; `report_task`
+// logs them and warns above `CS_WARN_US`. Costs one short exception per period (~1% CPU at
+// 16 MHz). SysTick is otherwise unused (embassy-time runs on TIM5).
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use cortex_m::peripheral::scb::SystemHandler;
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m::peripheral::{DWT, SYST};
+use cortex_m_rt::exception;
+use embassy_time::Timer;
+
+use crate::common::power::{self, ClockChange};
+
+/// Sampling period (and resolution) in microseconds
+pub const CS_MONITOR_PERIOD_US: u32 = 250;
+/// `report_task` warns when a second's worst window exceeds this
+pub const CS_WARN_US: u32 = 1000;
+
+static CYCLES_PER_US: AtomicU32 = AtomicU32::new(0);
+static LAST_TICK_CYCLES: AtomicU32 = AtomicU32::new(0);
+static TICKS: AtomicU32 = AtomicU32::new(0);
+// Worst window so far in the current second, in the last complete second, and since boot (cycles)
+static WINDOW_MAX: AtomicU32 = AtomicU32::new(0);
+static LAST_SECOND_MAX: AtomicU32 = AtomicU32::new(0);
+static BOOT_MAX: AtomicU32 = AtomicU32::new(0);
+
+/// Start monitoring; `hclk_hz` is the core clock (16 MHz with the default HSI config)
+pub fn start(hclk_hz: u32) {
+  // SAFETY: SysTick, DWT/DCB and the SysTick priority are not used elsewhere in the crate
+  let mut cp = unsafe { cortex_m::Peripherals::steal() };
+  cp.DCB.enable_trace();
+  cp.DWT.enable_cycle_counter();
+
+  let cycles_per_us = hclk_hz / 1_000_000;
+  CYCLES_PER_US.store(cycles_per_us, Ordering::Relaxed);
+  LAST_TICK_CYCLES.store(DWT::cycle_count(), Ordering::Relaxed);
+  unsafe { cp.SCB.set_priority(SystemHandler::SysTick, 0) };
+
+  cp.SYST.set_clock_source(SystClkSource::Core);
+  cp.SYST.set_reload(cycles_per_us * CS_MONITOR_PERIOD_US - 1);
+  cp.SYST.clear_current();
+  cp.SYST.enable_interrupt();
+  cp.SYST.enable_counter();
+  if power::on_clock_change(clock_changed).is_err() {
+    defmt::warn!("CS monitor: no room to follow clock changes");
+  }
+  defmt::info!("CS monitor: sampling every {} us", CS_MONITOR_PERIOD_US);
+}
+
+// `power::set_sysclk_profile` moved HCLK: keep the sampling period in microseconds
+fn clock_changed(change: &ClockChange) {
+  let cycles_per_us = change.to.hclk / 1_000_000;
+  CYCLES_PER_US.store(cycles_per_us, Ordering::Relaxed);
+  // SAFETY: SysTick is only used by this module
+  let mut syst = unsafe { cortex_m::Peripherals::steal() }.SYST;
+  syst.set_reload(cycles_per_us * CS_MONITOR_PERIOD_US - 1);
+  syst.clear_current();
+  LAST_TICK_CYCLES.store(DWT::cycle_count(), Ordering::Relaxed);
+}
+
+#[exception]
+fn SysTick() {
+  let now = DWT::cycle_count();
+  let period = SYST::get_reload() + 1;
+  // Delay since this tick fired, and since the one before it was due (covers missed ticks)
+  let since_fire = period - 1 - SYST::get_current();
+  let gap = now.wrapping_sub(LAST_TICK_CYCLES.swap(now, Ordering::Relaxed));
+  let late = since_fire.max(gap.saturating_sub(period));
+  WINDOW_MAX.fetch_max(late, Ordering::Relaxed);
+
+  let ticks_per_s = 1_000_000 / CS_MONITOR_PERIOD_US;
+  if TICKS.fetch_add(1, Ordering::Relaxed) + 1 >= ticks_per_s {
+    TICKS.store(0, Ordering::Relaxed);
+    let second = WINDOW_MAX.swap(0, Ordering::Relaxed);
+    LAST_SECOND_MAX.store(second, Ordering::Relaxed);
+    BOOT_MAX.fetch_max(second, Ordering::Relaxed);
+  }
+}
+
+fn to_us(cycles: u32) -> u32 {
+  cycles / CYCLES_PER_US.load(Ordering::Relaxed).max(1)
+}
+
+/// Longest interrupt-blocked window in the last complete second, in microseconds
+pub fn last_second_max_us() -> u32 {
+  to_us(LAST_SECOND_MAX.load(Ordering::Relaxed))
+}
+
+/// Longest interrupt-blocked window since `start`, in microseconds
+pub fn max_us() -> u32 {
+  to_us(BOOT_MAX.load(Ordering::Relaxed))
+}
+
+/// Log the worst window once per second (warning above `CS_WARN_US`)
+#[embassy_executor::task]
+pub async fn report_task() {
+  loop {
+    Timer::after_secs(1).await;
+    let second = last_second_max_us();
+    if second > CS_WARN_US {
+      defmt::warn!("CS monitor: interrupts blocked for {} us (max since boot {} us)", second, max_us());
+    } else {
+      defmt::debug!("CS monitor: {} us this second, {} us max", second, max_us());
+    }
+  }
+}
@@ -0,0 +1,132 @@
+//! Black box: timestamped event ring buffer that survives soft resets
+// Tasks append compact events with `record(code, arg)`: uptime in ms, a u16 code and a u32
+// argument. The ring lives in `.noinit` RAM (see `common::noinit`), which the runtime does not
+// zero, so after a watchdog reset, hard fault or software reset it still holds what led up to
+// it. `init()` at boot keeps the ring when its header is intact (after a power-on RAM is
+// garbage and the ring starts empty) and appends `EVENT_BOOT` with the reset cause, separating
+// one run from the next.
+// Read it back with `dump()` (defmt) or the `EventLog` comm command, oldest event first; once
+// full, the oldest events are overwritten.
+//
+// Codes below `EVENT_APP` are recorded by the crate itself; applications number their own
+// from `EVENT_APP`. Timestamps restart at every boot.
+
+use core::mem::MaybeUninit;
+use heapless::Vec;
+
+use crate::diagnostics::reset;
+
+/// Events kept (the oldest are overwritten)
+pub const EVENT_LOG_LEN: usize = 64;
+
+/// Boot; arg: `ResetCause` of this boot
+pub const EVENT_BOOT: u16 = 0x01;
+/// Hard fault; arg: faulting PC
+pub const EVENT_HARDFAULT: u16 = 0x02;
+/// Fail-safe outputs applied; arg: `failsafe::Trigger`
+pub const EVENT_FAILSAFE: u16 = 0x03;
+/// Host link state changed; arg: 1 up, 0 down
+pub const EVENT_LINK: u16 = 0x04;
+/// Reset requested by the comm error policy; arg: FCS errors in the window
+pub const EVENT_COMM_RESET: u16 = 0x05;
+/// MPU violation (`hardware::mpu`); arg: faulting address, or the SP if none was captured
+pub const EVENT_MEMFAULT: u16 = 0x06;
+/// Stack overflow into the MPU guard; arg: SP in the fault handler
+pub const EVENT_STACK_OVERFLOW: u16 = 0x07;
+/// Calendar schedule entry fired (`service::scheduler`); arg: the entry's event id
+pub const EVENT_SCHEDULE: u16 = 0x08;
+/// First code free for applications
+pub const EVENT_APP: u16 = 0x100;
+
+/// One logged event
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct Event {
+  /// Uptime of the boot that recorded it
+  pub ms: u32,
+  pub code: u16,
+  pub arg: u32,
+}
+
+impl Event {
+  /// Encoded size in an `EventLog` reply
+  pub const LEN: usize = 10;
+
+  /// Encode as ms (u32), code (u16), arg (u32), little-endian
+  pub fn to_bytes(&self) -> [u8; Self::LEN] {
+    let mut out = [0u8; Self::LEN];
+    out[0..4].copy_from_slice(&self.ms.to_le_bytes());
+    out[4..6].copy_from_slice(&self.code.to_le_bytes());
+    out[6..10].copy_from_slice(&self.arg.to_le_bytes());
+    out
+  }
+}
+
+// "EVLG"; the header is only trusted with the marker and in-range indices
+const RING_MAGIC: u32 = 0x474C_5645;
+
+struct Ring {
+  magic: u32,
+  // Next slot to write and number of valid events
+  head: u32,
+  len: u32,
+  events: [Event; EVENT_LOG_LEN],
+}
+
+// Not zeroed at startup, so it survives resets other than a power loss. Its own header check
+// rather than `NoInit`, which would re-CRC the whole ring on every event
+#[unsafe(link_section = ".noinit.EVENT_LOG")]
+static mut RING: MaybeUninit<Ring> = MaybeUninit::uninit();
+
+// Run `f` on the ring with interrupts off, emptying it first if the header is not intact
+fn with_ring<R>(f: impl FnOnce(&mut Ring) -> R) -> R {
+  cortex_m::interrupt::free(|_| {
+    // SAFETY: only accessed here, with interrupts disabled; every field is plain data, so garbage
+    // after power-on is a valid (if meaningless) value until the header check resets it
+    let ring = unsafe { (*(&raw mut RING)).assume_init_mut() };
+    if ring.magic != RING_MAGIC || ring.head as usize >= EVENT_LOG_LEN || ring.len as usize > EVENT_LOG_LEN {
+      ring.magic = RING_MAGIC;
+      ring.head = 0;
+      ring.len = 0;
+    }
+    f(ring)
+  })
+}
+
+/// Keep the events of the previous run and mark this boot; call once early in `main`
+pub fn init() {
+  let kept = with_ring(|ring| ring.len);
+  defmt::info!("Event log: {} events from before this boot", kept);
+  record(EVENT_BOOT, reset::reset_cause() as u32);
+}
+
+/// Append an event (callable from any context, including fault handlers)
+pub fn record(code: u16, arg: u32) {
+  let ms = embassy_time::Instant::now().as_millis() as u32;
+  with_ring(|ring| {
+    ring.events[ring.head as usize] = Event { ms, code, arg };
+    ring.head = (ring.head + 1) % EVENT_LOG_LEN as u32;
+    ring.len = (ring.len + 1).min(EVENT_LOG_LEN as u32);
+  });
+}
+
+/// Copy of the logged events, oldest first
+pub fn events() -> Vec<Event, EVENT_LOG_LEN> {
+  with_ring(|ring| {
+    let start = (ring.head + EVENT_LOG_LEN as u32 - ring.len) as usize;
+    (0..ring.len as usize).map(|i| ring.events[(start + i) % EVENT_LOG_LEN]).collect()
+  })
+}
+
+/// Empty the log
+pub fn clear() {
+  with_ring(|ring| ring.len = 0);
+}
+
+/// Print the logged events, oldest first
+pub fn dump() {
+  let events = events();
+  defmt::info!("Event log: {} events", events.len());
+  for event in &events {
+    defmt::info!("  {=u32} ms: code {=u16:#x} arg {=u32:#x}", event.ms, event.code, event.arg);
+  }
+}
@@ -0,0 +1,87 @@
+//! Reset cause from the RCC reset flags
+// The flags in RCC_CSR accumulate across resets until cleared, so the first call to
+// `reset_cause()` reads them, clears them (RMVF) and caches the result for later callers.
+// It also counts the boots since the last power-on in `.noinit` RAM (`boots_since_power_on`),
+// which tells a one-off reset from a unit stuck in a reset loop.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+use embassy_stm32::pac;
+
+use crate::common::noinit::NoInit;
+
+/// Why the MCU last reset (most specific flag wins: a watchdog reset also sets the pin flag)
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum ResetCause {
+  Unknown = 0,
+  PowerOn = 1,
+  Pin = 2,
+  Brownout = 3,
+  Software = 4,
+  IndependentWatchdog = 5,
+  WindowWatchdog = 6,
+  LowPower = 7,
+}
+
+const NOT_READ: u8 = 0xFF;
+static CAUSE: AtomicU8 = AtomicU8::new(NOT_READ);
+
+#[unsafe(link_section = ".noinit.BOOT_COUNT")]
+static BOOT_COUNT: NoInit<u32> = NoInit::new();
+
+/// Reset cause of the current boot
+pub fn reset_cause() -> ResetCause {
+  match CAUSE.load(Ordering::Relaxed) {
+    NOT_READ => {
+      let cause = read_and_clear();
+      CAUSE.store(cause as u8, Ordering::Relaxed);
+      // Power loss empties `.noinit` anyway; a brown-out may leave it readable but still restarts the count
+      let power_on = matches!(cause, ResetCause::PowerOn | ResetCause::Brownout);
+      BOOT_COUNT.update(|count| if power_on { 1 } else { count.unwrap_or(0).wrapping_add(1) });
+      cause
+    }
+    cached => from_u8(cached),
+  }
+}
+
+/// Boots since the last power-on, this one included (1 after a power-on)
+pub fn boots_since_power_on() -> u32 {
+  reset_cause();
+  BOOT_COUNT.get().unwrap_or(1)
+}
+
+fn read_and_clear() -> ResetCause {
+  let csr = pac::RCC.csr().read();
+  let cause = if csr.lpwrrstf() {
+    ResetCause::LowPower
+  } else if csr.wwdgrstf() {
+    ResetCause::WindowWatchdog
+  } else if csr.iwdgrstf() {
+    ResetCause::IndependentWatchdog
+  } else if csr.sftrstf() {
+    ResetCause::Software
+  } else if csr.porrstf() {
+    ResetCause::PowerOn
+  } else if csr.borrstf() {
+    ResetCause::Brownout
+  } else if csr.padrstf() {
+    ResetCause::Pin
+  } else {
+    ResetCause::Unknown
+  };
+  pac::RCC.csr().modify(|w| w.set_rmvf(true));
+  cause
+}
+
+fn from_u8(value: u8) -> ResetCause {
+  match value {
+    1 => ResetCause::PowerOn,
+    2 => ResetCause::Pin,
+    3 => ResetCause::Brownout,
+    4 => ResetCause::Software,
+    5 => ResetCause::IndependentWatchdog,
+    6 => ResetCause::WindowWatchdog,
+    7 => ResetCause::LowPower,
+    _ => ResetCause::Unknown,
+  }
+}
@@ -0,0 +1,103 @@
+//! Stack high-water mark via stack painting
+// `__pre_init` below fills the whole main stack (from the end of static RAM, `__sheap`, up to the
+// initial stack pointer) with a known word, before cortex-m-rt initialises RAM and calls `main`.
+// Whatever the stack later reaches (the runtime, `embassy_stm32::init`, the executor, and every
+// interrupt handler that nests on top) overwrites the paint, so scanning up from the bottom for
+// the first changed word gives the deepest point ever used, unlike sampling SP, which only sees
+// the stack at the moment of sampling.
+//
+// Embassy task futures live in static memory, so this measures the shared main/interrupt stack.
+// Every binary linking the crate is painted; `monitor_task` warns above `STACK_WARN_PERCENT`.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_time::Timer;
+
+/// `monitor_task` warns when the high-water mark exceeds this share of the stack
+pub const STACK_WARN_PERCENT: u32 = 75;
+/// Check interval of `monitor_task`
+pub const STACK_CHECK_SECS: u64 = 5;
+
+const PAINT: u32 = 0xC0DE_57AC;
+
+unsafe extern "C" {
+  // cortex-m-rt linker symbols: end of .bss/.uninit and initial stack pointer (top of RAM)
+  static __sheap: u32;
+  static _stack_start: u32;
+}
+
+// Lowest address known to have been used (scans stop here, or at the top before the first scan)
+static LOWEST_USED: AtomicU32 = AtomicU32::new(u32::MAX);
+
+// With the MPU guard on (`hardware::mpu`), the stack ends above it
+fn bottom() -> u32 {
+  #[cfg(feature = "mpu")]
+  if let Some(guard) = crate::hardware::mpu::stack_guard() {
+    return guard.end;
+  }
+  (&raw const __sheap) as u32
+}
+
+fn top() -> u32 {
+  (&raw const _stack_start) as u32
+}
+
+// Called by cortex-m-rt's reset handler before .data/.bss are initialised and with nothing on the
+// stack yet, so it is assembly that touches neither statics nor the stack: store `PAINT` to every
+// word in [__sheap, sp)
+core::arch::global_asm!(
+  ".section .text.__pre_init, \"ax\"",
+  ".global __pre_init",
+  ".type __pre_init, %function",
+  ".thumb_func",
+  "__pre_init:",
+  "  ldr r0, =__sheap",
+  "  mov r1, sp",
+  "  ldr r2, ={paint}",
+  "1:",
+  "  cmp r0, r1",
+  "  bhs 2f",
+  "  str r2, [r0], #4",
+  "  b 1b",
+  "2:",
+  "  bx lr",
+  ".ltorg",
+  ".size __pre_init, . - __pre_init",
+  paint = const PAINT,
+);
+
+/// Total stack size in bytes (from the end of static RAM to the top)
+pub fn stack_size() -> u32 {
+  top() - bottom()
+}
+
+/// Deepest stack usage since reset, in bytes
+pub fn stack_high_water() -> u32 {
+  let lowest = LOWEST_USED.load(Ordering::Relaxed).min(top());
+  // Only the region below the previous mark can have changed since the last scan
+  let mut addr = (bottom() + 3) & !3;
+  // SAFETY: reads painted RAM between __sheap and the previous mark
+  while addr < lowest && unsafe { core::ptr::read_volatile(addr as *const u32) } == PAINT {
+    addr += 4;
+  }
+  LOWEST_USED.fetch_min(addr, Ordering::Relaxed);
+  top() - addr
+}
+
+/// Check the high-water mark every `STACK_CHECK_SECS`, logging increases and warning above `STACK_WARN_PERCENT`
+#[embassy_executor::task]
+pub async fn monitor_task() {
+  let mut last = 0;
+  loop {
+    let used = stack_high_water();
+    if used != last {
+      last = used;
+      let size = stack_size();
+      if used * 100 > size * STACK_WARN_PERCENT {
+        defmt::warn!("Stack high water: {}/{} KB - above {}%", used / 1024, size / 1024, STACK_WARN_PERCENT);
+      } else {
+        defmt::info!("Stack high water: {}/{} KB", used / 1024, size / 1024);
+      }
+    }
+    Timer::after_secs(STACK_CHECK_SECS).await;
+  }
+}
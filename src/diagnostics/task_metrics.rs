@@ -0,0 +1,146 @@
+//! Task runtime metrics and executor load (feature `task_metrics`)
+// Times the busy part of each loop iteration of the provided tasks (from the await that woke
+// it to the next one) with the DWT cycle counter:
+// - `Iteration::begin(task)` after the await returns; the guard records when it is dropped,
+//   so scope it to end before the next await;
+// - per task, the longest iteration of the last second and since boot, and an iteration count.
+// `report_task` closes a one-second window: the time not spent in instrumented iterations is
+// reported as the executor idle percentage. This is coarse: uninstrumented tasks and interrupts
+// count as idle, so instrument the application's own busy loops with `Task::App`.
+//
+// Telemetry carries the idle percentage and each task's worst iteration of the last second;
+// a task that keeps the executor for milliseconds delays every other task by as much.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use cortex_m::peripheral::DWT;
+use embassy_time::Timer;
+
+use crate::common::power;
+
+/// `report_task` warns when an iteration of the last second exceeds this
+pub const ITERATION_WARN_US: u32 = 1000;
+
+/// Instrumented task loops
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum Task {
+  /// `hardware::serial::serial_rx_task_dma`
+  SerialRx = 0,
+  /// `service::comm::serial_hdlc_consumer_task`
+  CommRx = 1,
+  /// `common::tasks::telemetry_task`
+  Telemetry = 2,
+  /// The application's own loop (e.g. the comm task of `bin/example.rs`)
+  App = 3,
+}
+
+const TASK_COUNT: usize = 4;
+const TASKS: [Task; TASK_COUNT] = [Task::SerialRx, Task::CommRx, Task::Telemetry, Task::App];
+
+/// Figures for one task
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, defmt::Format)]
+pub struct TaskMetrics {
+  /// Iterations since boot (wrapping)
+  pub iterations: u32,
+  /// Longest iteration in the last complete second
+  pub last_second_max_us: u32,
+  /// Longest iteration since `start`
+  pub max_us: u32,
+}
+
+static CYCLES_PER_US: AtomicU32 = AtomicU32::new(0);
+static WINDOW_START: AtomicU32 = AtomicU32::new(0);
+// Cycles spent in instrumented iterations in the current window
+static WINDOW_BUSY: AtomicU32 = AtomicU32::new(0);
+static IDLE_PCT: AtomicU32 = AtomicU32::new(0);
+// Per task: iterations, worst iteration in the current window, last complete second and since boot (cycles)
+static ITERATIONS: [AtomicU32; TASK_COUNT] = [const { AtomicU32::new(0) }; TASK_COUNT];
+static WINDOW_MAX: [AtomicU32; TASK_COUNT] = [const { AtomicU32::new(0) }; TASK_COUNT];
+static LAST_SECOND_MAX: [AtomicU32; TASK_COUNT] = [const { AtomicU32::new(0) }; TASK_COUNT];
+static BOOT_MAX: [AtomicU32; TASK_COUNT] = [const { AtomicU32::new(0) }; TASK_COUNT];
+
+/// Start measuring; `hclk_hz` is the core clock (16 MHz with the default HSI config)
+pub fn start(hclk_hz: u32) {
+  // SAFETY: DCB/DWT are only otherwise touched by cs_monitor, which enables the same counter
+  let mut cp = unsafe { cortex_m::Peripherals::steal() };
+  cp.DCB.enable_trace();
+  cp.DWT.enable_cycle_counter();
+  CYCLES_PER_US.store(hclk_hz / 1_000_000, Ordering::Relaxed);
+  WINDOW_START.store(DWT::cycle_count(), Ordering::Relaxed);
+  IDLE_PCT.store(100, Ordering::Relaxed);
+  // Follow `power::set_sysclk_profile`
+  if power::on_clock_change(|change| CYCLES_PER_US.store(change.to.hclk / 1_000_000, Ordering::Relaxed)).is_err() {
+    defmt::warn!("Task metrics: no room to follow clock changes");
+  }
+  defmt::info!("Task metrics: started");
+}
+
+/// Busy part of one loop iteration, recorded on drop
+pub struct Iteration {
+  task: Task,
+  start: u32,
+}
+
+impl Iteration {
+  /// Start timing `task` (call right after the await that woke it)
+  pub fn begin(task: Task) -> Self {
+    Self { task, start: DWT::cycle_count() }
+  }
+}
+
+impl Drop for Iteration {
+  fn drop(&mut self) {
+    let cycles = DWT::cycle_count().wrapping_sub(self.start);
+    let i = self.task as usize;
+    ITERATIONS[i].fetch_add(1, Ordering::Relaxed);
+    WINDOW_MAX[i].fetch_max(cycles, Ordering::Relaxed);
+    WINDOW_BUSY.fetch_add(cycles, Ordering::Relaxed);
+  }
+}
+
+fn to_us(cycles: u32) -> u32 {
+  cycles / CYCLES_PER_US.load(Ordering::Relaxed).max(1)
+}
+
+/// Figures for `task`
+pub fn metrics(task: Task) -> TaskMetrics {
+  let i = task as usize;
+  TaskMetrics {
+    iterations: ITERATIONS[i].load(Ordering::Relaxed),
+    last_second_max_us: to_us(LAST_SECOND_MAX[i].load(Ordering::Relaxed)),
+    max_us: to_us(BOOT_MAX[i].load(Ordering::Relaxed)),
+  }
+}
+
+/// Share of the last complete second not spent in instrumented iterations (0 before `start`)
+pub fn idle_pct() -> u32 {
+  IDLE_PCT.load(Ordering::Relaxed)
+}
+
+// Close the current window: per-task maxima move to the last second, busy time becomes idle %
+fn roll_window() {
+  let now = DWT::cycle_count();
+  let window = now.wrapping_sub(WINDOW_START.swap(now, Ordering::Relaxed)).max(1);
+  let busy = WINDOW_BUSY.swap(0, Ordering::Relaxed).min(window);
+  IDLE_PCT.store(100 - (busy as u64 * 100 / window as u64) as u32, Ordering::Relaxed);
+  for i in 0..TASK_COUNT {
+    let second = WINDOW_MAX[i].swap(0, Ordering::Relaxed);
+    LAST_SECOND_MAX[i].store(second, Ordering::Relaxed);
+    BOOT_MAX[i].fetch_max(second, Ordering::Relaxed);
+  }
+}
+
+/// Close a window every second and log it (warning when an iteration exceeds `ITERATION_WARN_US`)
+#[embassy_executor::task]
+pub async fn report_task() {
+  loop {
+    Timer::after_secs(1).await;
+    roll_window();
+    for task in TASKS {
+      let m = metrics(task);
+      if m.last_second_max_us > ITERATION_WARN_US {
+        defmt::warn!("Task metrics: {} iteration took {} us (max since boot {} us)", task, m.last_second_max_us, m.max_us);
+      }
+    }
+    defmt::debug!("Task metrics: {}% idle", idle_pct());
+  }
+}
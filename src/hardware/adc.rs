@@ -0,0 +1,381 @@
+/// ADC1 Reads with an Analog Watchdog and Injected Conversions
+///
+/// `AdcReader` wraps the blocking embassy `Adc` for ADC1 and adds the analog watchdog, which
+/// embassy-stm32 does not expose on the F4: the hardware compares every conversion against a
+/// low/high threshold and raises the `ADC` interrupt when one falls outside them, so over-voltage
+/// or stall-current detection needs no task polling readings.
+///
+/// The watchdog only sees conversions that happen, so `watch` puts the ADC into continuous
+/// conversion of one channel (the hardware keeps converting with no CPU involvement) and
+/// `wait_watchdog` arms the interrupt and waits for the first reading outside the window. The
+/// handler disarms it again (a channel that stays out of range would otherwise interrupt on every
+/// conversion), so call `wait_watchdog` again once the event has been handled. `read` while
+/// watching stops the continuous conversion (call `watch` again afterwards).
+///
+/// Injected conversions (`inject`) are a second group of up to four channels with their own
+/// result registers, started by a timer event, EXTI15 or software. A trigger interrupts whatever
+/// regular conversion (`read`, `watch`) is running, converts the injected group and resumes, so a
+/// latency-critical measurement such as current sense is taken at exactly the right moment (e.g.
+/// the middle of a PWM period via a timer's CC4) without waiting for the regular loop.
+/// `wait_injected` returns the results of the next completed group.
+///
+/// Ratiometric helpers read a channel against VREFINT (`read_millivolts`, independent of the
+/// supply) or against another channel (`read_ratio`, `read_differential` for bridge sensors
+/// sharing the ADC reference), returning integers: ratios are Q16 fixed point (`RATIO_ONE` =
+/// 65536 = 1.0), so no float math ends up in interrupt or control paths. VREFINT needs at least
+/// 10 us of sampling: use `SampleTime::CYCLES480` (the default sample time is far shorter).
+use core::sync::atomic::{AtomicU8, AtomicU16, Ordering};
+use embassy_stm32::adc::{Adc, AdcChannel, SampleTime, VrefInt};
+use embassy_stm32::interrupt;
+use embassy_stm32::interrupt::InterruptExt;
+use embassy_stm32::peripherals::ADC1;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use heapless::Vec;
+
+use crate::board::BoardConfig;
+
+/// Largest 12-bit reading (watchdog thresholds are raw readings)
+pub const ADC_MAX: u16 = 4095;
+/// Q16 fixed-point 1.0 of the ratio helpers
+pub const RATIO_ONE: u32 = 1 << 16;
+/// VDDA at which the factory VREFINT calibration reading was taken
+pub const VREFINT_CAL_MV: u32 = 3300;
+/// Channels in the injected group
+pub const ADC_INJECTED_MAX: usize = 4;
+
+// ADC1 (RM0390 / RM0430 memory map)
+const ADC1_BASE: u32 = 0x4001_2000;
+const ADC_SR: u32 = 0x00;
+const ADC_CR1: u32 = 0x04;
+const ADC_CR2: u32 = 0x08;
+const ADC_HTR: u32 = 0x24;
+const ADC_LTR: u32 = 0x28;
+const ADC_SQR3: u32 = 0x34;
+const ADC_JSQR: u32 = 0x38;
+const ADC_JDR1: u32 = 0x3C;
+const ADC_DR: u32 = 0x4C;
+
+const SR_AWD: u32 = 1 << 0;
+const SR_JEOC: u32 = 1 << 2;
+const SR_OVR: u32 = 1 << 5;
+const CR1_AWDIE: u32 = 1 << 6;
+const CR1_JEOCIE: u32 = 1 << 7;
+// Watchdog on a single channel (AWDCH) instead of all regular channels
+const CR1_AWDSGL: u32 = 1 << 9;
+const CR1_AWDEN: u32 = 1 << 23;
+const CR2_ADON: u32 = 1 << 0;
+const CR2_CONT: u32 = 1 << 1;
+const CR2_EOCS: u32 = 1 << 10;
+const CR2_JEXTSEL_SHIFT: u32 = 16;
+const CR2_JEXTEN_MASK: u32 = 0b11 << 20;
+const CR2_JEXTEN_RISING: u32 = 0b01 << 20;
+const CR2_JSWSTART: u32 = 1 << 22;
+const CR2_SWSTART: u32 = 1 << 30;
+const JSQR_JL_SHIFT: u32 = 20;
+
+// The reading that tripped the watchdog, for `wait_watchdog`
+static TRIPPED: Signal<CriticalSectionRawMutex, u16> = Signal::new();
+static LOW: AtomicU16 = AtomicU16::new(0);
+// Results of the last injected group, for `wait_injected`
+static INJECTED: Signal<CriticalSectionRawMutex, Vec<u16, ADC_INJECTED_MAX>> = Signal::new();
+static INJECTED_LEN: AtomicU8 = AtomicU8::new(0);
+
+/// A reading outside the watchdog window
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum AdcWatchdogEvent {
+  /// Above the high threshold (raw reading)
+  Above(u16),
+  /// Below the low threshold (raw reading)
+  Below(u16),
+}
+
+/// What starts the injected group (JEXTSEL, on the rising edge)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum InjectTrigger {
+  Tim1Cc4 = 0,
+  Tim1Trgo = 1,
+  Tim2Cc1 = 2,
+  Tim2Trgo = 3,
+  Tim3Cc2 = 4,
+  Tim3Cc4 = 5,
+  Tim4Cc1 = 6,
+  Tim4Cc2 = 7,
+  Tim4Cc3 = 8,
+  Tim4Trgo = 9,
+  Tim5Cc4 = 10,
+  Tim5Trgo = 11,
+  Tim8Cc2 = 12,
+  Tim8Cc3 = 13,
+  Tim8Cc4 = 14,
+  Exti15 = 15,
+  /// `start_injected` only
+  Software = 16,
+}
+
+/// `signal / reference` in Q16 (`RATIO_ONE` when equal; u32::MAX for a zero reference)
+pub fn ratio_q16(signal: u16, reference: u16) -> u32 {
+  match reference {
+    0 => u32::MAX,
+    r => ((signal as u32) << 16) / r as u32,
+  }
+}
+
+/// `(plus - minus) / reference` in signed Q16 (i32::MAX for a zero reference)
+pub fn differential_q16(plus: u16, minus: u16, reference: u16) -> i32 {
+  match reference {
+    0 => i32::MAX,
+    r => ((plus as i32 - minus as i32) << 16) / r as i32,
+  }
+}
+
+/// `value` scaled by a Q16 ratio, e.g. `scale_q16(ratio, 100_000)` for a 100k full-scale bridge
+pub fn scale_q16(ratio: u32, value: u32) -> u32 {
+  ((ratio as u64 * value as u64) >> 16).min(u32::MAX as u64) as u32
+}
+
+/// VDDA in mV from a VREFINT reading and its factory calibration value (0 for a zero reading)
+pub fn vdda_mv(vrefint_raw: u16, cal: u16) -> u32 {
+  match vrefint_raw {
+    0 => 0,
+    raw => VREFINT_CAL_MV * cal as u32 / raw as u32,
+  }
+}
+
+/// Factory VREFINT reading at `VREFINT_CAL_MV`
+pub fn vrefint_cal() -> u16 {
+  // SAFETY: factory calibration value in system memory, always readable
+  unsafe { core::ptr::read_volatile(BoardConfig::VREFINT_CAL_ADDR as *const u16) }
+}
+
+fn reg(offset: u32) -> *mut u32 {
+  (ADC1_BASE + offset) as *mut u32
+}
+
+fn enable_interrupt() {
+  interrupt::ADC.unpend();
+  // SAFETY: the handler below only uses ADC1's registers and signals
+  unsafe { interrupt::ADC.enable() };
+}
+
+/// Blocking ADC1 reads plus the analog watchdog and injected group (from `BoardConfig::init_supply_adc()`)
+pub struct AdcReader<'d> {
+  adc: Adc<'d, ADC1>,
+  vrefint: VrefInt,
+  injected: Vec<u8, ADC_INJECTED_MAX>,
+}
+
+impl<'d> AdcReader<'d> {
+  pub fn new(adc: Adc<'d, ADC1>) -> Self {
+    let vrefint = adc.enable_vrefint();
+    // VREFINT start-up (10 us max)
+    embassy_time::block_for(embassy_time::Duration::from_micros(10));
+    Self {
+      adc,
+      vrefint,
+      injected: Vec::new(),
+    }
+  }
+
+  pub fn set_sample_time(&mut self, sample_time: SampleTime) {
+    self.adc.set_sample_time(sample_time);
+  }
+
+  /// One raw reading of `channel` (stops a running `watch`)
+  pub fn read(&mut self, channel: &mut impl AdcChannel<ADC1>) -> u16 {
+    self.stop_watch();
+    self.adc.blocking_read(channel)
+  }
+
+  /// Raw VREFINT reading
+  pub fn read_vrefint(&mut self) -> u16 {
+    self.stop_watch();
+    self.adc.blocking_read(&mut self.vrefint)
+  }
+
+  /// Supply (VDDA) in mV, from VREFINT and its factory calibration
+  pub fn read_vdda_mv(&mut self) -> u32 {
+    vdda_mv(self.read_vrefint(), vrefint_cal())
+  }
+
+  /// `channel` in mV, measured against VREFINT so that supply drift cancels out
+  pub fn read_millivolts(&mut self, channel: &mut impl AdcChannel<ADC1>) -> u32 {
+    let raw = self.read(channel) as u32;
+    self.read_vdda_mv() * raw / ADC_MAX as u32
+  }
+
+  /// `signal / reference` in Q16, read back to back (ratiometric sensors fed from `reference`)
+  pub fn read_ratio(&mut self, signal: &mut impl AdcChannel<ADC1>, reference: &mut impl AdcChannel<ADC1>) -> u32 {
+    let signal = self.read(signal);
+    ratio_q16(signal, self.read(reference))
+  }
+
+  /// `(plus - minus) / reference` in signed Q16: a bridge's two outputs against its excitation
+  pub fn read_differential(&mut self, plus: &mut impl AdcChannel<ADC1>, minus: &mut impl AdcChannel<ADC1>, reference: &mut impl AdcChannel<ADC1>) -> i32 {
+    let (plus, minus) = (self.read(plus), self.read(minus));
+    differential_q16(plus, minus, self.read(reference))
+  }
+
+  /// Guard the converted channel: readings below `low` or above `high` (raw, 0..=`ADC_MAX`)
+  /// trip the watchdog; takes effect for `watch` and for single reads alike
+  pub fn set_watchdog(&mut self, low: u16, high: u16) {
+    LOW.store(low.min(ADC_MAX), Ordering::Relaxed);
+    // SAFETY: ADC1 is owned by this reader; the interrupt handler only touches SR and AWDIE
+    unsafe {
+      reg(ADC_LTR).write_volatile(low.min(ADC_MAX) as u32);
+      reg(ADC_HTR).write_volatile(high.min(ADC_MAX) as u32);
+      // All regular channels: the sequence only ever holds the channel being read
+      let cr1 = reg(ADC_CR1).read_volatile() & !CR1_AWDSGL;
+      reg(ADC_CR1).write_volatile(cr1 | CR1_AWDEN);
+    }
+    enable_interrupt();
+  }
+
+  /// Turn the watchdog off (a pending `wait_watchdog` keeps waiting)
+  pub fn clear_watchdog(&mut self) {
+    // SAFETY: see `set_watchdog`
+    unsafe {
+      let cr1 = reg(ADC_CR1).read_volatile();
+      reg(ADC_CR1).write_volatile(cr1 & !(CR1_AWDEN | CR1_AWDIE));
+    }
+  }
+
+  /// Convert `channel` continuously in the background, for the watchdog to check every reading
+  pub fn watch(&mut self, channel: &mut impl AdcChannel<ADC1>) -> u16 {
+    // A single read selects the channel and its sample time
+    let first = self.adc.blocking_read(channel);
+    // SAFETY: see `set_watchdog`; without EOCS nothing counts unread results as overruns
+    unsafe {
+      let cr2 = reg(ADC_CR2).read_volatile() & !CR2_EOCS;
+      reg(ADC_CR2).write_volatile(cr2 | CR2_CONT);
+      reg(ADC_SR).write_volatile(!SR_OVR);
+      reg(ADC_CR2).write_volatile(cr2 | CR2_CONT | CR2_SWSTART);
+    }
+    first
+  }
+
+  /// Stop the conversions started by `watch`
+  pub fn stop_watch(&mut self) {
+    // SAFETY: see `set_watchdog`; the conversion in progress completes, no further one starts
+    unsafe {
+      let cr2 = reg(ADC_CR2).read_volatile();
+      reg(ADC_CR2).write_volatile(cr2 & !CR2_CONT);
+    }
+  }
+
+  /// Latest conversion while watching
+  pub fn latest(&self) -> u16 {
+    // SAFETY: reading DR only clears EOC, which nothing waits on while watching
+    unsafe { reg(ADC_DR).read_volatile() as u16 }
+  }
+
+  /// Arm the watchdog interrupt and wait for a reading outside the window (`set_watchdog`)
+  pub async fn wait_watchdog(&mut self) -> AdcWatchdogEvent {
+    TRIPPED.reset();
+    // SAFETY: see `set_watchdog`; SR is cleared by writing 0 to a flag
+    unsafe {
+      reg(ADC_SR).write_volatile(!SR_AWD);
+      let cr1 = reg(ADC_CR1).read_volatile();
+      reg(ADC_CR1).write_volatile(cr1 | CR1_AWDIE);
+    }
+    let value = TRIPPED.wait().await;
+    if value < LOW.load(Ordering::Relaxed) {
+      AdcWatchdogEvent::Below(value)
+    } else {
+      AdcWatchdogEvent::Above(value)
+    }
+  }
+
+  /// Add `channel` to the injected group, converted on `trigger` (the last call's trigger starts
+  /// the whole group); returns its index in `wait_injected` results, None if the group is full.
+  /// Stops a running `watch`, as the channel is set up with a single read.
+  pub fn inject(&mut self, channel: &mut impl AdcChannel<ADC1>, trigger: InjectTrigger) -> Option<usize> {
+    if self.injected.is_full() {
+      return None;
+    }
+    self.stop_watch();
+    // A single read switches the pin to analog and sets the channel's sample time; the regular
+    // sequence then names the channel number
+    self.adc.blocking_read(channel);
+    // SAFETY: see `set_watchdog`
+    let number = unsafe { reg(ADC_SQR3).read_volatile() & 0x1F } as u8;
+    self.injected.push(number).ok();
+    // With JL = n - 1 the group converts JSQ(4 - n + 1)..JSQ4, so the channels fill the top ranks
+    let n = self.injected.len();
+    let jsqr = self
+      .injected
+      .iter()
+      .enumerate()
+      .fold(((n - 1) as u32) << JSQR_JL_SHIFT, |jsqr, (i, &ch)| jsqr | ((ch as u32) << ((ADC_INJECTED_MAX - n + i) * 5)));
+    INJECTED_LEN.store(n as u8, Ordering::Relaxed);
+    // SAFETY: see `set_watchdog`
+    unsafe {
+      reg(ADC_JSQR).write_volatile(jsqr);
+      let mut cr2 = reg(ADC_CR2).read_volatile() & !(CR2_JEXTEN_MASK | (0xF << CR2_JEXTSEL_SHIFT));
+      if trigger != InjectTrigger::Software {
+        cr2 |= CR2_JEXTEN_RISING | ((trigger as u32) << CR2_JEXTSEL_SHIFT);
+      }
+      reg(ADC_CR2).write_volatile(cr2 | CR2_ADON);
+      reg(ADC_SR).write_volatile(!SR_JEOC);
+      let cr1 = reg(ADC_CR1).read_volatile();
+      reg(ADC_CR1).write_volatile(cr1 | CR1_JEOCIE);
+    }
+    enable_interrupt();
+    Some(n - 1)
+  }
+
+  /// Empty the injected group and stop listening for its trigger
+  pub fn clear_injected(&mut self) {
+    self.injected.clear();
+    INJECTED_LEN.store(0, Ordering::Relaxed);
+    // SAFETY: see `set_watchdog`
+    unsafe {
+      let cr1 = reg(ADC_CR1).read_volatile();
+      reg(ADC_CR1).write_volatile(cr1 & !CR1_JEOCIE);
+      let cr2 = reg(ADC_CR2).read_volatile();
+      reg(ADC_CR2).write_volatile(cr2 & !CR2_JEXTEN_MASK);
+    }
+  }
+
+  /// Convert the injected group now (any trigger)
+  pub fn start_injected(&mut self) {
+    // SAFETY: see `set_watchdog`
+    unsafe {
+      let cr2 = reg(ADC_CR2).read_volatile();
+      reg(ADC_CR2).write_volatile(cr2 | CR2_JSWSTART);
+    }
+  }
+
+  /// Results of the next injected group to complete, in `inject` order (a group that completes
+  /// while nobody waits is kept until the next one replaces it)
+  pub async fn wait_injected(&mut self) -> Vec<u16, ADC_INJECTED_MAX> {
+    INJECTED.wait().await
+  }
+}
+
+impl Drop for AdcReader<'_> {
+  fn drop(&mut self) {
+    self.stop_watch();
+    self.clear_watchdog();
+    self.clear_injected();
+  }
+}
+
+#[interrupt]
+fn ADC() {
+  // SAFETY: flags are cleared by writing 0 to them; AWDIE is disarmed until the next `wait_watchdog`
+  unsafe {
+    let sr = reg(ADC_SR).read_volatile();
+    let cr1 = reg(ADC_CR1).read_volatile();
+    if sr & SR_JEOC != 0 && cr1 & CR1_JEOCIE != 0 {
+      reg(ADC_SR).write_volatile(!SR_JEOC);
+      let len = INJECTED_LEN.load(Ordering::Relaxed) as u32;
+      INJECTED.signal((0..len).map(|rank| reg(ADC_JDR1 + 4 * rank).read_volatile() as u16).collect());
+    }
+    if sr & SR_AWD != 0 && cr1 & CR1_AWDIE != 0 {
+      reg(ADC_SR).write_volatile(!SR_AWD);
+      reg(ADC_CR1).write_volatile(cr1 & !CR1_AWDIE);
+      TRIPPED.signal(reg(ADC_DR).read_volatile() as u16);
+    }
+  }
+}
@@ -0,0 +1,102 @@
+/// Shared Bus Hardware Abstraction Layer
+///
+/// This module provides mutex-protected async I2C/SPI bus handles so several
+/// drivers (OLED + IMU + EEPROM, ...) can live on one peripheral. Each device
+/// handle implements the embedded-hal-async bus traits and locks the bus per transaction.
+/// The blocking variants do the same for driver crates written against the blocking
+/// `embedded-hal` 1.0 traits.
+use core::cell::RefCell;
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+use embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice;
+use embassy_embedded_hal::shared_bus::blocking::i2c::I2cDevice as BlockingI2cDevice;
+use embassy_embedded_hal::shared_bus::blocking::spi::SpiDevice as BlockingSpiDevice;
+use embassy_stm32::bind_interrupts;
+use embassy_stm32::gpio::Output;
+use embassy_stm32::i2c::{self, I2c};
+use embassy_stm32::mode::{Async, Blocking};
+use embassy_stm32::peripherals::I2C1;
+use embassy_stm32::spi::Spi;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use static_cell::StaticCell;
+
+// Interrupt bindings for I2C1 (`BoardConfig::init_i2c`)
+bind_interrupts!(pub struct I2c1Irqs {
+    I2C1_EV => i2c::EventInterruptHandler<I2C1>;
+    I2C1_ER => i2c::ErrorInterruptHandler<I2C1>;
+});
+
+/// Mutex-protected I2C bus shared between device handles
+pub type I2cBus = Mutex<CriticalSectionRawMutex, I2c<'static, Async>>;
+
+/// Mutex-protected SPI bus shared between device handles
+pub type SpiBus = Mutex<CriticalSectionRawMutex, Spi<'static, Async>>;
+
+/// Per-driver I2C handle (implements `embedded_hal_async::i2c::I2c`)
+pub type SharedI2c = I2cDevice<'static, CriticalSectionRawMutex, I2c<'static, Async>>;
+
+/// Per-driver SPI handle with its own chip select (implements `embedded_hal_async::spi::SpiDevice`)
+pub type SharedSpi = SpiDevice<'static, CriticalSectionRawMutex, Spi<'static, Async>, Output<'static>>;
+
+/// Blocking I2C bus shared between device handles
+pub type BlockingI2cBus = BlockingMutex<CriticalSectionRawMutex, RefCell<I2c<'static, Blocking>>>;
+
+/// Blocking SPI bus shared between device handles
+pub type BlockingSpiBus = BlockingMutex<CriticalSectionRawMutex, RefCell<Spi<'static, Blocking>>>;
+
+/// Per-driver blocking I2C handle (implements `embedded_hal::i2c::I2c`)
+pub type BlockingSharedI2c = BlockingI2cDevice<'static, CriticalSectionRawMutex, I2c<'static, Blocking>>;
+
+/// Per-driver blocking SPI handle with its own chip select (implements `embedded_hal::spi::SpiDevice`)
+pub type BlockingSharedSpi = BlockingSpiDevice<'static, CriticalSectionRawMutex, Spi<'static, Blocking>, Output<'static>>;
+
+static I2C_BUS: StaticCell<I2cBus> = StaticCell::new();
+static SPI_BUS: StaticCell<SpiBus> = StaticCell::new();
+static BLOCKING_I2C_BUS: StaticCell<BlockingI2cBus> = StaticCell::new();
+static BLOCKING_SPI_BUS: StaticCell<BlockingSpiBus> = StaticCell::new();
+
+/// Shared bus utilities
+pub struct SharedBus;
+
+impl SharedBus {
+  /// Move an async I2C peripheral into the shared bus slot (call once)
+  pub fn i2c(i2c: I2c<'static, Async>) -> &'static I2cBus {
+    I2C_BUS.init(Mutex::new(i2c))
+  }
+
+  /// Move an async SPI peripheral into the shared bus slot (call once)
+  pub fn spi(spi: Spi<'static, Async>) -> &'static SpiBus {
+    SPI_BUS.init(Mutex::new(spi))
+  }
+
+  /// Create a driver handle on the shared I2C bus
+  pub fn i2c_device(bus: &'static I2cBus) -> SharedI2c {
+    I2cDevice::new(bus)
+  }
+
+  /// Create a driver handle on the shared SPI bus, using `cs` as its chip select
+  pub fn spi_device(bus: &'static SpiBus, cs: Output<'static>) -> SharedSpi {
+    SpiDevice::new(bus, cs)
+  }
+
+  /// Move a blocking I2C peripheral into the shared blocking bus slot (call once)
+  pub fn blocking_i2c(i2c: I2c<'static, Blocking>) -> &'static BlockingI2cBus {
+    BLOCKING_I2C_BUS.init(BlockingMutex::new(RefCell::new(i2c)))
+  }
+
+  /// Move a blocking SPI peripheral into the shared blocking bus slot (call once)
+  pub fn blocking_spi(spi: Spi<'static, Blocking>) -> &'static BlockingSpiBus {
+    BLOCKING_SPI_BUS.init(BlockingMutex::new(RefCell::new(spi)))
+  }
+
+  /// Create a blocking driver handle on the shared I2C bus
+  pub fn blocking_i2c_device(bus: &'static BlockingI2cBus) -> BlockingSharedI2c {
+    BlockingI2cDevice::new(bus)
+  }
+
+  /// Create a blocking driver handle on the shared SPI bus, using `cs` as its chip select
+  pub fn blocking_spi_device(bus: &'static BlockingSpiBus, cs: Output<'static>) -> BlockingSharedSpi {
+    BlockingSpiDevice::new(bus, cs)
+  }
+}
@@ -0,0 +1,80 @@
+/// CRC Peripheral Hardware Abstraction Layer (feature `hw_crc`)
+///
+/// This module moves CRC computation onto the STM32 CRC unit. On the F4 the unit is fixed to
+/// CRC-32 (poly 0x04C11DB7, init 0xFFFFFFFF, MSB first, no output XOR, i.e. CRC-32/MPEG-2) on
+/// 32-bit words: `crc32` feeds whole words to it and finishes any tail bytes in software, for
+/// firmware image checks. Parts with a programmable unit (H7) also compute the HDLC FCS-16 in
+/// hardware, which `protocol::hdlc::fcs16_ppp` uses when the unit is installed; on the F4 the
+/// FCS stays in software. Until `install` is called every function falls back to software.
+use core::cell::RefCell;
+use embassy_stm32::crc::Crc;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+const CRC32_POLY: u32 = 0x04C1_1DB7;
+const CRC32_INIT: u32 = 0xFFFF_FFFF;
+
+// The unit is shared, so each computation runs start to finish inside the lock
+static UNIT: Mutex<CriticalSectionRawMutex, RefCell<Option<Crc<'static>>>> = Mutex::new(RefCell::new(None));
+
+/// Hand the CRC unit to this module (e.g. `crc::install(BoardConfig::init_crc())`)
+pub fn install(crc: Crc<'static>) {
+  UNIT.lock(|unit| unit.replace(Some(crc)));
+}
+
+/// CRC-32/MPEG-2 of `data` (hardware for whole words, software for the tail)
+pub fn crc32(data: &[u8]) -> u32 {
+  let tail = &data[data.len() - data.len() % 4..];
+  let crc = UNIT.lock(|unit| {
+    let mut unit = unit.borrow_mut();
+    let crc = unit.as_mut()?;
+    crc.reset();
+    let mut value = CRC32_INIT;
+    // The unit shifts each word MSB first, so big-endian words keep the byte-stream order
+    for word in data.chunks_exact(4) {
+      value = crc.feed_word(u32::from_be_bytes([word[0], word[1], word[2], word[3]]));
+    }
+    Some(value)
+  });
+  match crc {
+    Some(value) => crc32_update(value, tail),
+    None => crc32_software(data),
+  }
+}
+
+/// CRC-32/MPEG-2 of `data` in software (reference, and fallback before `install`)
+pub fn crc32_software(data: &[u8]) -> u32 {
+  crc32_update(CRC32_INIT, data)
+}
+
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+  for &byte in data {
+    crc ^= (byte as u32) << 24;
+    for _ in 0..8 {
+      crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ CRC32_POLY } else { crc << 1 };
+    }
+  }
+  crc
+}
+
+/// PPP/HDLC FCS-16 of `data` on a programmable CRC unit (None: not installed, or fixed-polynomial unit)
+#[cfg(feature = "stm32h7")]
+pub fn fcs16(data: &[u8]) -> Option<u16> {
+  use embassy_stm32::crc::{Config, InputReverseConfig, PolySize};
+  UNIT.lock(|unit| {
+    let mut unit = unit.borrow_mut();
+    let crc = unit.as_mut()?;
+    // Reflected 0x1021 with init 0xFFFF; the final XOR is applied below
+    crc.reconfigure(Config::new(InputReverseConfig::Byte, true, PolySize::Width16, 0xFFFF, 0x1021).ok()?);
+    crc.reset();
+    let fcs = !(crc.feed_bytes(data) as u16);
+    crc.reconfigure(Config::new(InputReverseConfig::None, false, PolySize::Width32, CRC32_INIT, CRC32_POLY).ok()?);
+    Some(fcs)
+  })
+}
+
+/// PPP/HDLC FCS-16 of `data` on a programmable CRC unit (None: not installed, or fixed-polynomial unit)
+#[cfg(not(feature = "stm32h7"))]
+pub fn fcs16(_data: &[u8]) -> Option<u16> {
+  None
+}
@@ -0,0 +1,246 @@
+/// DFSDM PDM Microphone Capture (STM32F413)
+///
+/// The DFSDM (digital filter for sigma-delta modulators) turns the 1-bit PDM stream of a MEMS
+/// microphone (MP34DT01, IMP34DT05, SPH0641, ...) into PCM: it drives the microphone clock on
+/// CKOUT, samples DATIN on the clock edge the microphone's L/R pin selects, and runs the bits
+/// through a sinc filter that decimates them to the audio rate. embassy-stm32 has no driver for it,
+/// so `Microphone` programs DFSDM2 directly: channel `channel` takes its clock from CKOUT,
+/// filter 0 converts it continuously and raises `DFSDM2_FLT0` for every sample.
+///
+/// The interrupt handler pushes the samples into a ring buffer (`DFSDM_RING_LEN`, the oldest
+/// sample is dropped and counted in `overruns()` when the reader falls behind), and
+/// `Microphone::read` waits until a whole block is there. With the default 16 MHz APB2 clock,
+/// `MicConfig::new(16_000_000)` runs the microphone at 2 MHz and decimates by 125 (sinc3) to
+/// 16 kHz. Use `BoardConfig::init_microphone()` and `common::tasks::mic_level_task` for an RMS
+/// level meter.
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use embassy_stm32::interrupt;
+use embassy_stm32::interrupt::InterruptExt;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use heapless::Deque;
+
+/// Samples buffered between the interrupt handler and `Microphone::read` (64 ms at 16 kHz)
+pub const DFSDM_RING_LEN: usize = 1024;
+
+// DFSDM2 (RM0430 memory map); channel y at 0x20 * y, filter x at 0x100 + 0x80 * x
+const DFSDM2_BASE: u32 = 0x4001_6400;
+const CH_CFGR1: u32 = 0x00;
+const CH_CFGR2: u32 = 0x04;
+const FLT0: u32 = 0x100;
+const FLT_CR1: u32 = 0x00;
+const FLT_CR2: u32 = 0x04;
+const FLT_ICR: u32 = 0x0C;
+const FLT_FCR: u32 = 0x14;
+const FLT_RDATAR: u32 = 0x1C;
+
+// CH0CFGR1 holds the global enable and the CKOUT divider for all channels
+const CFGR1_DFSDMEN: u32 = 1 << 31;
+const CFGR1_CKOUTDIV_SHIFT: u32 = 16;
+const CFGR1_CHEN: u32 = 1 << 7;
+// SPI input clocked by the internal CKOUT
+const CFGR1_SPICKSEL_CKOUT: u32 = 0b01 << 2;
+// SPI data sampled on the falling clock edge (instead of the rising one)
+const CFGR1_SITP_FALLING: u32 = 0b01;
+const CFGR2_DTRBS_SHIFT: u32 = 3;
+const CR1_DFEN: u32 = 1 << 0;
+const CR1_RSWSTART: u32 = 1 << 17;
+const CR1_RCONT: u32 = 1 << 18;
+const CR1_RCH_SHIFT: u32 = 24;
+const CR1_FAST: u32 = 1 << 29;
+const CR2_REOCIE: u32 = 1 << 1;
+const ICR_CLRROVRF: u32 = 1 << 3;
+const FCR_FORD_SINC3: u32 = 3 << 29;
+const FCR_FOSR_SHIFT: u32 = 16;
+
+const RCC_APB2ENR: *mut u32 = 0x4002_3844 as *mut u32;
+const RCC_APB2ENR_DFSDM2EN: u32 = 1 << 24;
+
+static RING: Mutex<CriticalSectionRawMutex, RefCell<Deque<i16, DFSDM_RING_LEN>>> = Mutex::new(RefCell::new(Deque::new()));
+// Samples `read` is waiting for; the handler signals once the ring holds that many
+static WANTED: AtomicUsize = AtomicUsize::new(usize::MAX);
+static READY: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static OVERRUNS: AtomicU32 = AtomicU32::new(0);
+
+fn reg(offset: u32) -> *mut u32 {
+  (DFSDM2_BASE + offset) as *mut u32
+}
+
+/// Clock divider, decimation and scaling of the microphone channel
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct MicConfig {
+  /// DFSDM clock (APB2) in Hz
+  pub kernel_hz: u32,
+  /// CKOUT = `kernel_hz / ckout_div` (2..=256; most PDM microphones want 1-3.25 MHz)
+  pub ckout_div: u16,
+  /// Sinc3 decimation ratio: sample rate = CKOUT / `oversampling` (1..=1024)
+  pub oversampling: u16,
+  /// Right shift of the filter output (sinc3 gives about +/-`oversampling`^3) down to 16 bits
+  pub shift: u8,
+  /// Sample on the falling CKOUT edge (microphone L/R pin high) instead of the rising one
+  pub falling_edge: bool,
+}
+
+impl MicConfig {
+  /// 2 MHz microphone clock (`kernel_hz` a multiple of it) and 16 kHz samples
+  pub const fn new(kernel_hz: u32) -> Self {
+    Self {
+      kernel_hz,
+      ckout_div: (kernel_hz / 2_000_000) as u16,
+      oversampling: 125,
+      shift: 6,
+      falling_edge: false,
+    }
+  }
+
+  pub const fn ckout_div(mut self, div: u16) -> Self {
+    self.ckout_div = div;
+    self
+  }
+
+  pub const fn oversampling(mut self, ratio: u16, shift: u8) -> Self {
+    self.oversampling = ratio;
+    self.shift = shift;
+    self
+  }
+
+  pub const fn falling_edge(mut self, falling: bool) -> Self {
+    self.falling_edge = falling;
+    self
+  }
+
+  /// Samples per second
+  pub const fn sample_rate_hz(&self) -> u32 {
+    self.kernel_hz / self.ckout_div as u32 / self.oversampling as u32
+  }
+}
+
+/// PDM microphone on one DFSDM2 channel, converted continuously into the ring buffer
+pub struct Microphone {
+  config: MicConfig,
+}
+
+impl Microphone {
+  /// Start capturing on `channel` (0..=7, whose DATIN pin the caller has put in its alternate
+  /// function, as well as CKOUT)
+  pub fn start(channel: u8, config: MicConfig) -> Self {
+    let ch = 0x20 * (channel as u32 & 7);
+    let mut cfgr1 = CFGR1_CHEN | CFGR1_SPICKSEL_CKOUT;
+    if config.falling_edge {
+      cfgr1 |= CFGR1_SITP_FALLING;
+    }
+    let ckoutdiv = (config.ckout_div.clamp(2, 256) - 1) as u32;
+    let fosr = (config.oversampling.clamp(1, 1024) - 1) as u32;
+    RING.lock(|ring| ring.borrow_mut().clear());
+    // SAFETY: DFSDM2 and its RCC enable bit are only touched here and in `stop`
+    unsafe {
+      RCC_APB2ENR.write_volatile(RCC_APB2ENR.read_volatile() | RCC_APB2ENR_DFSDM2EN);
+      reg(CH_CFGR1).write_volatile(0);
+      reg(ch + CH_CFGR2).write_volatile((config.shift as u32 & 0x1F) << CFGR2_DTRBS_SHIFT);
+      reg(ch + CH_CFGR1).write_volatile(cfgr1);
+      reg(CH_CFGR1).write_volatile(reg(CH_CFGR1).read_volatile() | ckoutdiv << CFGR1_CKOUTDIV_SHIFT);
+      reg(FLT0 + FLT_FCR).write_volatile(FCR_FORD_SINC3 | fosr << FCR_FOSR_SHIFT);
+      reg(FLT0 + FLT_CR2).write_volatile(CR2_REOCIE);
+      reg(FLT0 + FLT_CR1).write_volatile((channel as u32 & 7) << CR1_RCH_SHIFT | CR1_RCONT | CR1_FAST);
+      reg(CH_CFGR1).write_volatile(reg(CH_CFGR1).read_volatile() | CFGR1_DFSDMEN);
+      reg(FLT0 + FLT_CR1).write_volatile(reg(FLT0 + FLT_CR1).read_volatile() | CR1_DFEN);
+      reg(FLT0 + FLT_CR1).write_volatile(reg(FLT0 + FLT_CR1).read_volatile() | CR1_RSWSTART);
+      interrupt::DFSDM2_FLT0.unpend();
+      interrupt::DFSDM2_FLT0.enable();
+    }
+    defmt::info!("DFSDM: microphone on channel {}, {} Hz", channel, config.sample_rate_hz());
+    Self { config }
+  }
+
+  pub fn config(&self) -> MicConfig {
+    self.config
+  }
+
+  /// Fill `buf` with the next samples, waiting until that many are buffered (at most
+  /// `DFSDM_RING_LEN` at a time; returns how many were copied)
+  pub async fn read(&mut self, buf: &mut [i16]) -> usize {
+    let wanted = buf.len().min(DFSDM_RING_LEN);
+    loop {
+      READY.reset();
+      WANTED.store(wanted, Ordering::Relaxed);
+      let copied = RING.lock(|ring| {
+        let mut ring = ring.borrow_mut();
+        if ring.len() < wanted {
+          return None;
+        }
+        buf[..wanted].iter_mut().for_each(|sample| *sample = ring.pop_front().unwrap_or(0));
+        Some(wanted)
+      });
+      if let Some(copied) = copied {
+        WANTED.store(usize::MAX, Ordering::Relaxed);
+        return copied;
+      }
+      READY.wait().await;
+    }
+  }
+
+  /// Stop converting and turn DFSDM2 off (the microphone clock stops)
+  pub fn stop(self) {
+    interrupt::DFSDM2_FLT0.disable();
+    // SAFETY: see `start`
+    unsafe {
+      reg(FLT0 + FLT_CR1).write_volatile(0);
+      reg(CH_CFGR1).write_volatile(0);
+      RCC_APB2ENR.write_volatile(RCC_APB2ENR.read_volatile() & !RCC_APB2ENR_DFSDM2EN);
+    }
+  }
+}
+
+/// Samples dropped because the ring buffer was full
+pub fn overruns() -> u32 {
+  OVERRUNS.load(Ordering::Relaxed)
+}
+
+/// RMS level of a block of samples (0..=32767)
+pub fn rms(samples: &[i16]) -> u16 {
+  if samples.is_empty() {
+    return 0;
+  }
+  rms_of_mean_square(samples.iter().map(|&s| (s as i64 * s as i64) as u64).sum::<u64>() / samples.len() as u64)
+}
+
+/// Square root of a mean square (for RMS over several blocks), saturated to 32767
+pub fn rms_of_mean_square(mean_square: u64) -> u16 {
+  // Integer square root (Newton)
+  let (mut x, mut y) = (mean_square, mean_square.div_ceil(2));
+  while y < x {
+    x = y;
+    y = (x + mean_square / x) / 2;
+  }
+  x.min(i16::MAX as u64) as u16
+}
+
+/// Largest absolute sample of a block
+pub fn peak(samples: &[i16]) -> u16 {
+  samples.iter().map(|s| s.unsigned_abs()).max().unwrap_or(0)
+}
+
+#[interrupt]
+fn DFSDM2_FLT0() {
+  // SAFETY: reading RDATAR clears the end-of-conversion flag; ICR is write-one-to-clear
+  let raw = unsafe {
+    reg(FLT0 + FLT_ICR).write_volatile(ICR_CLRROVRF);
+    reg(FLT0 + FLT_RDATAR).read_volatile()
+  };
+  // 24-bit signed result in bits 31:8, already shifted down by DTRBS
+  let sample = ((raw as i32) >> 8).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+  let buffered = RING.lock(|ring| {
+    let mut ring = ring.borrow_mut();
+    if ring.is_full() {
+      ring.pop_front();
+      OVERRUNS.fetch_add(1, Ordering::Relaxed);
+    }
+    ring.push_back(sample).ok();
+    ring.len()
+  });
+  if buffered >= WANTED.load(Ordering::Relaxed) {
+    READY.signal(());
+  }
+}
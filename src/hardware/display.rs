@@ -0,0 +1,73 @@
+/// Display Hardware Abstraction Layer (feature `display`)
+///
+/// This module provides glue for small status displays drawn with embedded-graphics: an
+/// SSD1306 128x64 OLED on the shared blocking I2C bus, and `StatusScreen`, which lays out
+/// lines of text on any `DrawTarget`. Other panels plug into the same screen through their
+/// embedded-graphics driver (e.g. an SPI ST7789 via `mipidsi` on a `BlockingSharedSpi`).
+use embedded_graphics::mono_font::ascii::FONT_6X10;
+use embedded_graphics::mono_font::{MonoTextStyle, MonoTextStyleBuilder};
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::{Baseline, Text};
+use heapless::String;
+use ssd1306::mode::BufferedGraphicsMode;
+use ssd1306::prelude::*;
+use ssd1306::{I2CDisplayInterface, Ssd1306};
+
+use crate::hardware::BlockingSharedI2c;
+
+/// Characters per status line (128 px / 6 px font)
+pub const STATUS_LINE_LEN: usize = 21;
+/// Status lines that fit a 64 px tall display
+pub const STATUS_LINES: usize = 6;
+// Font height plus one pixel of spacing
+const LINE_HEIGHT: i32 = 11;
+
+/// One line of status text
+pub type StatusLine = String<STATUS_LINE_LEN>;
+
+/// Buffered SSD1306 128x64 OLED on the shared blocking I2C bus (address 0x3C)
+pub type Ssd1306Display = Ssd1306<I2CInterface<BlockingSharedI2c>, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>>;
+
+/// Initialize an SSD1306 at 0x3C on `i2c` (None if it does not answer)
+pub fn ssd1306(i2c: BlockingSharedI2c) -> Option<Ssd1306Display> {
+  let mut display = Ssd1306::new(I2CDisplayInterface::new(i2c), DisplaySize128x64, DisplayRotation::Rotate0).into_buffered_graphics_mode();
+  display.init().ok()?;
+  Some(display)
+}
+
+/// Lines of text, top to bottom, on any embedded-graphics target
+pub struct StatusScreen<C: PixelColor> {
+  style: MonoTextStyle<'static, C>,
+}
+
+impl<C: PixelColor> StatusScreen<C> {
+  /// `background` is painted behind each character, so lines overwrite in place without a full clear
+  pub fn new(foreground: C, background: C) -> Self {
+    Self {
+      style: MonoTextStyleBuilder::new().font(&FONT_6X10).text_color(foreground).background_color(background).build(),
+    }
+  }
+
+  /// Draw `lines`, each padded to `STATUS_LINE_LEN` so shorter text erases what was there
+  pub fn draw<D: DrawTarget<Color = C>>(&self, target: &mut D, lines: &[StatusLine]) -> Result<(), D::Error> {
+    for (row, line) in lines.iter().enumerate() {
+      let mut padded = line.clone();
+      while padded.push(' ').is_ok() {}
+      Text::with_baseline(&padded, Point::new(0, row as i32 * LINE_HEIGHT), self.style, Baseline::Top).draw(target)?;
+    }
+    Ok(())
+  }
+}
+
+impl StatusScreen<BinaryColor> {
+  /// White-on-black screen for monochrome OLEDs
+  pub fn mono() -> Self {
+    Self::new(BinaryColor::On, BinaryColor::Off)
+  }
+
+  /// Draw `lines` into the SSD1306 buffer and send it to the panel
+  pub fn show(&self, display: &mut Ssd1306Display, lines: &[StatusLine]) -> bool {
+    self.draw(display, lines).is_ok() && display.flush().is_ok()
+  }
+}
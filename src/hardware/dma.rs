@@ -0,0 +1,169 @@
+/// DMA Memory-to-Memory Copies (feature `hw_dma`)
+///
+/// Moves large buffers with a DMA2 stream instead of the CPU: on the F4 only DMA2 can do
+/// memory-to-memory transfers, and this module claims stream 1 for them (handed over with
+/// `dma::install(BoardConfig::init_dma())`). The stream reads SRAM or flash and writes SRAM,
+/// in words when both buffers are word-aligned and in bytes otherwise, through its FIFO (direct
+/// mode is not allowed for memory-to-memory).
+///
+/// `copy` is the drop-in for `copy_from_slice` used by the flash and comm paths: buffers of at
+/// least `DMA_COPY_MIN` bytes go to the stream, shorter ones (where setting up the stream costs
+/// more than it saves) and copies while the stream is busy elsewhere (e.g. a higher-priority
+/// task) are done by the CPU, as is everything before `install`. `copy_async` hands the stream a
+/// buffer and awaits its completion, so other tasks run during e.g. a frame buffer move; dropping
+/// the future stops the transfer. The stream's interrupt belongs to embassy-stm32's DMA driver,
+/// so completion is polled from the transfer-complete flag, never interrupt-driven: a 4 KB copy
+/// takes a few microseconds.
+use core::future::poll_fn;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Poll;
+use embassy_stm32::Peri;
+use embassy_stm32::peripherals::DMA2_CH1;
+
+/// Smallest copy worth the stream (shorter ones use the CPU)
+pub const DMA_COPY_MIN: usize = 256;
+/// Largest single transfer (NDTR is 16 bits); longer copies are split
+pub const DMA_COPY_MAX_ITEMS: usize = 0xFFFF;
+
+// DMA2 stream 1 (RM0390 / RM0430 memory map)
+const DMA2_BASE: u32 = 0x4002_6400;
+const DMA_LISR: u32 = 0x00;
+const DMA_LIFCR: u32 = 0x08;
+const DMA_S1CR: u32 = 0x10 + 0x18;
+const DMA_S1NDTR: u32 = 0x14 + 0x18;
+const DMA_S1PAR: u32 = 0x18 + 0x18;
+const DMA_S1M0AR: u32 = 0x1C + 0x18;
+const DMA_S1FCR: u32 = 0x24 + 0x18;
+const RCC_AHB1ENR: *mut u32 = 0x4002_3830 as *mut u32;
+const RCC_AHB1ENR_DMA2EN: u32 = 1 << 22;
+
+// Stream 1 flags in LISR/LIFCR: FEIF, DMEIF, TEIF, HTIF, TCIF
+const LISR_S1_ALL: u32 = 0b11_1101 << 6;
+const LISR_S1_TEIF: u32 = 1 << 9;
+const LISR_S1_TCIF: u32 = 1 << 11;
+const CR_EN: u32 = 1 << 0;
+// Memory-to-memory, both addresses incremented, high priority
+const CR_DIR_M2M: u32 = 0b10 << 6;
+const CR_PINC: u32 = 1 << 9;
+const CR_MINC: u32 = 1 << 10;
+const CR_SIZE_WORD: u32 = (0b10 << 11) | (0b10 << 13);
+const CR_PL_HIGH: u32 = 0b10 << 16;
+// FIFO mode (direct mode disabled), threshold full
+const FCR_DMDIS_FULL: u32 = (1 << 2) | 0b11;
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+// Claimed by one copy at a time; a copy that finds it taken uses the CPU
+static BUSY: AtomicBool = AtomicBool::new(false);
+
+fn reg(offset: u32) -> *mut u32 {
+  (DMA2_BASE + offset) as *mut u32
+}
+
+/// Hand DMA2 stream 1 to this module (e.g. `dma::install(BoardConfig::init_dma())`)
+pub fn install(_stream: Peri<'static, DMA2_CH1>) {
+  // SAFETY: read-modify-write of RCC_AHB1ENR with interrupts off; DMA2's clock is normally
+  // already on (embassy-stm32 enables it at init)
+  cortex_m::interrupt::free(|_| unsafe { RCC_AHB1ENR.write_volatile(RCC_AHB1ENR.read_volatile() | RCC_AHB1ENR_DMA2EN) });
+  INSTALLED.store(true, Ordering::Release);
+}
+
+/// Copy `src` into `dst` (same length, like `copy_from_slice`): on the stream from
+/// `DMA_COPY_MIN` bytes, on the CPU otherwise. Blocks until done.
+pub fn copy(dst: &mut [u8], src: &[u8]) {
+  assert_eq!(dst.len(), src.len(), "dma::copy length mismatch");
+  if src.len() < DMA_COPY_MIN || !claim() {
+    dst.copy_from_slice(src);
+    return;
+  }
+  let _stream = Claimed;
+  let mut done = 0;
+  while done < src.len() {
+    let len = start(&mut dst[done..], &src[done..]);
+    while !finished() {}
+    done += len;
+  }
+}
+
+/// Copy `src` into `dst` on the stream and wait for it without blocking other tasks (on the
+/// CPU if the buffers are shorter than `DMA_COPY_MIN` or the stream is busy). Dropping the
+/// future stops the transfer.
+pub async fn copy_async(dst: &mut [u8], src: &[u8]) {
+  assert_eq!(dst.len(), src.len(), "dma::copy_async length mismatch");
+  if src.len() < DMA_COPY_MIN || !claim() {
+    dst.copy_from_slice(src);
+    return;
+  }
+  let _stream = Claimed;
+  let mut done = 0;
+  while done < src.len() {
+    let len = start(&mut dst[done..], &src[done..]);
+    poll_fn(|cx| {
+      if finished() {
+        Poll::Ready(())
+      } else {
+        // Polled again on the next executor pass
+        cx.waker().wake_by_ref();
+        Poll::Pending
+      }
+    })
+    .await;
+    done += len;
+  }
+}
+
+// Take the stream if it is installed and free
+fn claim() -> bool {
+  INSTALLED.load(Ordering::Acquire) && BUSY.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok()
+}
+
+// Owner of the stream for one copy: stops any transfer still running and frees the stream
+struct Claimed;
+
+impl Drop for Claimed {
+  fn drop(&mut self) {
+    // SAFETY: stream 1 registers are only touched by the holder of BUSY
+    unsafe {
+      let cr = reg(DMA_S1CR);
+      cr.write_volatile(cr.read_volatile() & !CR_EN);
+      while cr.read_volatile() & CR_EN != 0 {}
+      reg(DMA_LIFCR).write_volatile(LISR_S1_ALL);
+    }
+    BUSY.store(false, Ordering::Release);
+  }
+}
+
+// Start copying the head of `src` into `dst`; returns the bytes the transfer covers
+fn start(dst: &mut [u8], src: &[u8]) -> usize {
+  let aligned = (src.as_ptr() as usize | dst.as_ptr() as usize | src.len()) % 4 == 0;
+  let (item, size) = if aligned { (4, CR_SIZE_WORD) } else { (1, 0) };
+  let len = src.len().min(DMA_COPY_MAX_ITEMS * item);
+  // SAFETY: the caller holds BUSY; both buffers outlive the transfer, which is stopped by
+  // `Claimed` before they can be released
+  unsafe {
+    reg(DMA_S1CR).write_volatile(0);
+    while reg(DMA_S1CR).read_volatile() & CR_EN != 0 {}
+    reg(DMA_LIFCR).write_volatile(LISR_S1_ALL);
+    // Memory-to-memory reads from the "peripheral" address and writes to memory 0
+    reg(DMA_S1PAR).write_volatile(src.as_ptr() as u32);
+    reg(DMA_S1M0AR).write_volatile(dst.as_mut_ptr() as u32);
+    reg(DMA_S1NDTR).write_volatile((len / item) as u32);
+    reg(DMA_S1FCR).write_volatile(FCR_DMDIS_FULL);
+    // Buffer writes must land before the stream reads them
+    cortex_m::asm::dsb();
+    reg(DMA_S1CR).write_volatile(CR_DIR_M2M | CR_PINC | CR_MINC | size | CR_PL_HIGH | CR_EN);
+  }
+  len
+}
+
+// The running transfer completed (a transfer error panics: a bad address is a bug)
+fn finished() -> bool {
+  // SAFETY: read-only access to the status register
+  let status = unsafe { reg(DMA_LISR).read_volatile() };
+  assert!(status & LISR_S1_TEIF == 0, "DMA2 stream 1 transfer error");
+  if status & LISR_S1_TCIF == 0 {
+    return false;
+  }
+  // Stream writes are complete; make them visible before the buffer is used
+  cortex_m::asm::dsb();
+  true
+}
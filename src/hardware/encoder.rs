@@ -0,0 +1,99 @@
+/// Quadrature Encoder Hardware Abstraction Layer
+///
+/// This module tracks the position and velocity of a rotary encoder (motor shaft, control knob)
+/// with a timer in encoder mode, so counting happens in hardware without interrupts.
+/// The timer counts both edges of both channels: one encoder cycle (PPR line) is 4 counts.
+/// `BoardConfig::init_encoder()` maps each board's default encoder timer and pins.
+use embassy_stm32::Peri;
+use embassy_stm32::timer::qei::{Qei, QeiPin};
+use embassy_stm32::timer::{Ch1, Ch2, GeneralInstance4Channel, TimerPin};
+use embassy_time::Instant;
+
+pub use embassy_stm32::timer::qei::Direction;
+
+/// Position/velocity reading from `Encoder::sample`
+#[derive(Copy, Clone, Debug, PartialEq, defmt::Format)]
+pub struct EncoderSample {
+  /// Accumulated position in counts
+  pub position: i32,
+  /// Counts moved since the previous sample
+  pub delta: i32,
+  /// Velocity in counts per second since the previous sample
+  pub counts_per_s: f32,
+  /// Velocity in revolutions per minute (0.0 if counts per revolution is unknown)
+  pub rpm: f32,
+}
+
+/// Rotary encoder on channels 1/2 of a timer
+///
+/// The hardware counter is 16 bits; `position` extends it to 32 bits as long as it is read
+/// (via `position` or `sample`) at least every 32768 counts.
+pub struct Encoder<T: GeneralInstance4Channel> {
+  qei: Qei<'static, T>,
+  counts_per_rev: u32,
+  last_count: u16,
+  position: i32,
+  last_sample: Instant,
+  last_position: i32,
+}
+
+impl<T: GeneralInstance4Channel> Encoder<T> {
+  /// Encoder with A on `a` (CH1) and B on `b` (CH2); `counts_per_rev` is 4x the encoder PPR (0 if unknown)
+  ///
+  /// Mechanical knobs with open-contact outputs need external pull-ups (or a board with them fitted).
+  pub fn new(tim: Peri<'static, T>, a: Peri<'static, impl TimerPin<T, Ch1>>, b: Peri<'static, impl TimerPin<T, Ch2>>, counts_per_rev: u32) -> Self {
+    let qei = Qei::new(tim, QeiPin::new(a), QeiPin::new(b));
+    let last_count = qei.count();
+    Self {
+      qei,
+      counts_per_rev,
+      last_count,
+      position: 0,
+      last_sample: Instant::now(),
+      last_position: 0,
+    }
+  }
+
+  /// Accumulated position in counts (positive when counting up)
+  pub fn position(&mut self) -> i32 {
+    let count = self.qei.count();
+    self.position = self.position.wrapping_add(count.wrapping_sub(self.last_count) as i16 as i32);
+    self.last_count = count;
+    self.position
+  }
+
+  /// Set the current position (e.g. 0 at a homing switch)
+  pub fn set_position(&mut self, position: i32) {
+    self.position();
+    self.position = position;
+    self.last_position = position;
+  }
+
+  /// Direction of the last movement
+  pub fn direction(&self) -> Direction {
+    self.qei.read_direction()
+  }
+
+  /// Position plus velocity averaged since the previous sample (call at a steady rate)
+  pub fn sample(&mut self) -> EncoderSample {
+    let position = self.position();
+    let now = Instant::now();
+    let elapsed_us = (now - self.last_sample).as_micros().max(1) as f32;
+    let delta = position.wrapping_sub(self.last_position);
+    self.last_sample = now;
+    self.last_position = position;
+
+    let counts_per_s = delta as f32 * 1_000_000.0 / elapsed_us;
+    let rpm = if self.counts_per_rev > 0 {
+      counts_per_s * 60.0 / self.counts_per_rev as f32
+    } else {
+      0.0
+    };
+    EncoderSample {
+      position,
+      delta,
+      counts_per_s,
+      rpm,
+    }
+  }
+}
@@ -0,0 +1,257 @@
+/// Environmental Sensor Drivers (BME280, SHT31)
+///
+/// This module reads temperature/humidity (and pressure on the BME280) from the two most
+/// common I2C environmental sensors, over any `embedded-hal-async` I2C bus (e.g. a
+/// `SharedBus::i2c_device` handle). `EnvSensor::detect` probes the usual addresses so
+/// applications work with whichever sensor is fitted. Readings are integers in fixed
+/// units (0.01 °C, 0.01 %RH, Pa) so they can go on the wire unchanged.
+use embassy_time::Timer;
+use embedded_hal_async::i2c::I2c;
+
+/// BME280 addresses (SDO low / high)
+pub const BME280_ADDRESSES: [u8; 2] = [0x76, 0x77];
+/// SHT31 addresses (ADDR low / high)
+pub const SHT31_ADDRESSES: [u8; 2] = [0x44, 0x45];
+
+/// One measurement
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, defmt::Format)]
+pub struct EnvReading {
+  /// Temperature in 0.01 °C
+  pub temperature_centi_c: i32,
+  /// Relative humidity in 0.01 %
+  pub humidity_centi_pct: u32,
+  /// Pressure in Pa (None for sensors without a barometer)
+  pub pressure_pa: Option<u32>,
+}
+
+/// Sensor errors
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum EnvSensorError {
+  /// I2C transfer failed (sensor missing or bus fault)
+  Bus,
+  /// No supported sensor answered at the probed addresses
+  NotFound,
+  /// SHT31 data failed its CRC check
+  Crc,
+}
+
+/// Whichever supported sensor `detect` found
+pub enum EnvSensor<I: I2c> {
+  Bme280(Bme280<I>),
+  Sht31(Sht31<I>),
+}
+
+impl<I: I2c> EnvSensor<I> {
+  /// Probe the BME280 then the SHT31 addresses and initialize the first sensor found
+  pub async fn detect(mut i2c: I) -> Result<Self, EnvSensorError> {
+    for address in BME280_ADDRESSES {
+      let mut id = [0u8; 1];
+      if i2c.write_read(address, &[bme280::REG_ID], &mut id).await.is_ok() && id[0] == bme280::CHIP_ID {
+        return Ok(Self::Bme280(Bme280::new(i2c, address).await?));
+      }
+    }
+    for address in SHT31_ADDRESSES {
+      if i2c.write(address, &sht31::CMD_SOFT_RESET).await.is_ok() {
+        Timer::after_millis(2).await;
+        return Ok(Self::Sht31(Sht31::new(i2c, address)));
+      }
+    }
+    Err(EnvSensorError::NotFound)
+  }
+
+  /// Sensor name for logging
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::Bme280(_) => "BME280",
+      Self::Sht31(_) => "SHT31",
+    }
+  }
+
+  /// Take one measurement
+  pub async fn read(&mut self) -> Result<EnvReading, EnvSensorError> {
+    match self {
+      Self::Bme280(sensor) => sensor.read().await,
+      Self::Sht31(sensor) => sensor.read().await,
+    }
+  }
+}
+
+mod bme280 {
+  pub const REG_ID: u8 = 0xD0;
+  pub const CHIP_ID: u8 = 0x60;
+  pub const REG_CALIB_T_P: u8 = 0x88; // 26 bytes: T1..T3, P1..P9, reserved, H1
+  pub const REG_CALIB_H: u8 = 0xE1; // 7 bytes: H2..H6
+  pub const REG_CTRL_HUM: u8 = 0xF2;
+  pub const REG_CTRL_MEAS: u8 = 0xF4;
+  pub const REG_DATA: u8 = 0xF7; // press[3], temp[3], hum[2]
+  // x1 oversampling for all three channels, forced mode (one conversion, then sleep)
+  pub const CTRL_HUM_X1: u8 = 0x01;
+  pub const CTRL_MEAS_FORCED_X1: u8 = (1 << 5) | (1 << 2) | 0b01;
+  // Worst-case conversion time at x1 oversampling is ~9.3 ms
+  pub const CONVERSION_MS: u64 = 10;
+}
+
+mod sht31 {
+  pub const CMD_SOFT_RESET: [u8; 2] = [0x30, 0xA2];
+  // Single shot, high repeatability, no clock stretching
+  pub const CMD_MEASURE: [u8; 2] = [0x24, 0x00];
+  pub const CONVERSION_MS: u64 = 16;
+}
+
+/// Factory trimming parameters (datasheet section 4.2.2)
+#[derive(Copy, Clone, Debug, Default)]
+struct Bme280Calibration {
+  t1: u16,
+  t2: i16,
+  t3: i16,
+  p1: u16,
+  p: [i16; 8], // P2..P9
+  h1: u8,
+  h2: i16,
+  h3: u8,
+  h4: i16,
+  h5: i16,
+  h6: i8,
+}
+
+/// Bosch BME280 temperature/humidity/pressure sensor
+pub struct Bme280<I: I2c> {
+  i2c: I,
+  address: u8,
+  calibration: Bme280Calibration,
+}
+
+impl<I: I2c> Bme280<I> {
+  /// Read the calibration data of the sensor at `address`
+  pub async fn new(mut i2c: I, address: u8) -> Result<Self, EnvSensorError> {
+    let mut tp = [0u8; 26];
+    let mut h = [0u8; 7];
+    i2c.write_read(address, &[bme280::REG_CALIB_T_P], &mut tp).await.map_err(|_| EnvSensorError::Bus)?;
+    i2c.write_read(address, &[bme280::REG_CALIB_H], &mut h).await.map_err(|_| EnvSensorError::Bus)?;
+    let u16_at = |b: &[u8], i: usize| u16::from_le_bytes([b[i], b[i + 1]]);
+    let i16_at = |b: &[u8], i: usize| i16::from_le_bytes([b[i], b[i + 1]]);
+    let mut p = [0i16; 8];
+    for (n, value) in p.iter_mut().enumerate() {
+      *value = i16_at(&tp, 8 + n * 2);
+    }
+    let calibration = Bme280Calibration {
+      t1: u16_at(&tp, 0),
+      t2: i16_at(&tp, 2),
+      t3: i16_at(&tp, 4),
+      p1: u16_at(&tp, 6),
+      p,
+      h1: tp[25],
+      h2: i16_at(&h, 0),
+      h3: h[2],
+      // H4/H5 are 12-bit values sharing the nibbles of 0xE5
+      h4: ((h[3] as i8 as i16) << 4) | (h[4] & 0x0F) as i16,
+      h5: ((h[5] as i8 as i16) << 4) | (h[4] >> 4) as i16,
+      h6: h[6] as i8,
+    };
+    Ok(Self { i2c, address, calibration })
+  }
+
+  /// Trigger a forced-mode conversion and read the compensated values
+  pub async fn read(&mut self) -> Result<EnvReading, EnvSensorError> {
+    // ctrl_hum only takes effect after a write to ctrl_meas
+    self.write(bme280::REG_CTRL_HUM, bme280::CTRL_HUM_X1).await?;
+    self.write(bme280::REG_CTRL_MEAS, bme280::CTRL_MEAS_FORCED_X1).await?;
+    Timer::after_millis(bme280::CONVERSION_MS).await;
+    let mut d = [0u8; 8];
+    self.i2c.write_read(self.address, &[bme280::REG_DATA], &mut d).await.map_err(|_| EnvSensorError::Bus)?;
+    let adc_p = ((d[0] as i32) << 12) | ((d[1] as i32) << 4) | (d[2] as i32 >> 4);
+    let adc_t = ((d[3] as i32) << 12) | ((d[4] as i32) << 4) | (d[5] as i32 >> 4);
+    let adc_h = ((d[6] as i32) << 8) | d[7] as i32;
+
+    let t_fine = self.t_fine(adc_t);
+    Ok(EnvReading {
+      temperature_centi_c: (t_fine * 5 + 128) >> 8,
+      humidity_centi_pct: self.humidity_q22_10(adc_h, t_fine) * 100 / 1024,
+      pressure_pa: Some(self.pressure_q24_8(adc_p, t_fine) / 256),
+    })
+  }
+
+  async fn write(&mut self, register: u8, value: u8) -> Result<(), EnvSensorError> {
+    self.i2c.write(self.address, &[register, value]).await.map_err(|_| EnvSensorError::Bus)
+  }
+
+  // Compensation formulas from the datasheet (section 4.2.3, integer versions)
+
+  fn t_fine(&self, adc_t: i32) -> i32 {
+    let c = &self.calibration;
+    let var1 = (((adc_t >> 3) - ((c.t1 as i32) << 1)) * c.t2 as i32) >> 11;
+    let var2 = (((((adc_t >> 4) - c.t1 as i32) * ((adc_t >> 4) - c.t1 as i32)) >> 12) * c.t3 as i32) >> 14;
+    var1 + var2
+  }
+
+  fn pressure_q24_8(&self, adc_p: i32, t_fine: i32) -> u32 {
+    let c = &self.calibration;
+    let p = |n: usize| c.p[n - 2] as i64;
+    let mut var1 = t_fine as i64 - 128_000;
+    let mut var2 = var1 * var1 * p(6);
+    var2 += (var1 * p(5)) << 17;
+    var2 += p(4) << 35;
+    var1 = ((var1 * var1 * p(3)) >> 8) + ((var1 * p(2)) << 12);
+    var1 = (((1i64 << 47) + var1) * c.p1 as i64) >> 33;
+    if var1 == 0 {
+      return 0; // avoid division by zero
+    }
+    let mut pressure = 1_048_576 - adc_p as i64;
+    pressure = (((pressure << 31) - var2) * 3125) / var1;
+    var1 = (p(9) * (pressure >> 13) * (pressure >> 13)) >> 25;
+    var2 = (p(8) * pressure) >> 19;
+    (((pressure + var1 + var2) >> 8) + (p(7) << 4)) as u32
+  }
+
+  fn humidity_q22_10(&self, adc_h: i32, t_fine: i32) -> u32 {
+    let c = &self.calibration;
+    let mut v = t_fine - 76_800;
+    v = ((((adc_h << 14) - ((c.h4 as i32) << 20) - (c.h5 as i32 * v)) + 16_384) >> 15)
+      * (((((((v * c.h6 as i32) >> 10) * (((v * c.h3 as i32) >> 11) + 32_768)) >> 10) + 2_097_152) * c.h2 as i32 + 8192) >> 14);
+    v -= ((((v >> 15) * (v >> 15)) >> 7) * c.h1 as i32) >> 4;
+    (v.clamp(0, 419_430_400) >> 12) as u32
+  }
+}
+
+/// Sensirion SHT31 temperature/humidity sensor
+pub struct Sht31<I: I2c> {
+  i2c: I,
+  address: u8,
+}
+
+impl<I: I2c> Sht31<I> {
+  pub fn new(i2c: I, address: u8) -> Self {
+    Self { i2c, address }
+  }
+
+  /// Take a single-shot high-repeatability measurement
+  pub async fn read(&mut self) -> Result<EnvReading, EnvSensorError> {
+    self.i2c.write(self.address, &sht31::CMD_MEASURE).await.map_err(|_| EnvSensorError::Bus)?;
+    Timer::after_millis(sht31::CONVERSION_MS).await;
+    let mut d = [0u8; 6];
+    self.i2c.read(self.address, &mut d).await.map_err(|_| EnvSensorError::Bus)?;
+    // Each 16-bit word is followed by its CRC-8
+    if crc8(&d[0..2]) != d[2] || crc8(&d[3..5]) != d[5] {
+      return Err(EnvSensorError::Crc);
+    }
+    let raw_t = u16::from_be_bytes([d[0], d[1]]) as i32;
+    let raw_h = u16::from_be_bytes([d[3], d[4]]) as u32;
+    Ok(EnvReading {
+      temperature_centi_c: -4500 + 17_500 * raw_t / 65_535,
+      humidity_centi_pct: 10_000 * raw_h / 65_535,
+      pressure_pa: None,
+    })
+  }
+}
+
+/// Sensirion CRC-8 (polynomial 0x31, init 0xFF)
+fn crc8(data: &[u8]) -> u8 {
+  let mut crc = 0xFFu8;
+  for &byte in data {
+    crc ^= byte;
+    for _ in 0..8 {
+      crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x31 } else { crc << 1 };
+    }
+  }
+  crc
+}
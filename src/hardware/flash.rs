@@ -1,8 +1,9 @@
 // Simple flash storage for STM32 using last sector
 /// Provides block read/write APIs for persistent storage
 use crate::board::BoardConfig;
-use core::ptr;
 use embassy_stm32::flash::Error;
+use embedded_storage::nor_flash::{ErrorType, NorFlash, ReadNorFlash};
+use embedded_storage_async::nor_flash as async_nor_flash;
 
 // Direct flash operations using register addresses (STM32 reference manual)
 // Flash register base addresses - conditional compilation based on MCU family
@@ -52,14 +53,24 @@ pub fn end() -> u32 {
 
 /// Read a block of data from flash storage
 pub fn read_block(offset: usize, buf: &mut [u8]) -> Result<(), Error> {
-  let addr = start() + offset as u32;
-  let flash_ptr = addr as *const u8;
-  unsafe {
-    ptr::copy_nonoverlapping(flash_ptr, buf.as_mut_ptr(), buf.len());
-  }
+  copy_out(start() + offset as u32, buf);
   Ok(())
 }
 
+// The memory-mapped flash at `addr`, as long as `len`
+fn mapped(addr: u32, len: usize) -> &'static [u8] {
+  // SAFETY: flash is always mapped and only changes under erase/program, which stall reads
+  unsafe { core::slice::from_raw_parts(addr as *const u8, len) }
+}
+
+// Copy flash at `addr` into `buf` (large blocks on the DMA with `hw_dma`)
+fn copy_out(addr: u32, buf: &mut [u8]) {
+  #[cfg(feature = "hw_dma")]
+  crate::hardware::dma::copy(buf, mapped(addr, buf.len()));
+  #[cfg(not(feature = "hw_dma"))]
+  buf.copy_from_slice(mapped(addr, buf.len()));
+}
+
 /// Direct flash erase using register manipulation (workaround for embassy-stm32 v0.4.0 bug)
 pub fn erase_sector_direct(sector_addr: u32) -> Result<(), Error> {
   defmt::info!("Direct erase sector at address: 0x{:08X}", sector_addr);
@@ -184,7 +195,7 @@ unsafe fn wait_flash_ready() {
   }
 }
 
-fn get_sector_number(addr: u32) -> Result<u32, Error> {
+pub(crate) fn get_sector_number(addr: u32) -> Result<u32, Error> {
   // STM32F4 sector mapping
   match addr {
     0x08000000..=0x08003FFF => Ok(0), // Sector 0: 16KB
@@ -251,3 +262,168 @@ pub async fn erase() -> Result<(), Error> {
     }
   }
 }
+
+/// `embedded-storage` view of the storage region (offsets are relative to `start()`)
+///
+/// Lets ecosystem crates (sequential-storage, littlefs2, ...) use the storage sector directly.
+/// The region is a single sector, so erases must cover the whole region.
+pub struct Storage;
+
+impl Storage {
+  /// Validate an offset/length against the region and an alignment
+  fn check(offset: u32, len: usize, align: usize) -> Result<(), Error> {
+    let offset = offset as usize;
+    if offset % align != 0 || len % align != 0 {
+      return Err(Error::Unaligned);
+    }
+    if offset.checked_add(len).map_or(true, |end| end > BoardConfig::FLASH_STORAGE_SIZE) {
+      return Err(Error::Size);
+    }
+    Ok(())
+  }
+}
+
+impl ErrorType for Storage {
+  type Error = Error;
+}
+
+impl ReadNorFlash for Storage {
+  const READ_SIZE: usize = 1;
+
+  fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+    Self::check(offset, bytes.len(), Self::READ_SIZE)?;
+    read_block(offset as usize, bytes)
+  }
+
+  fn capacity(&self) -> usize {
+    BoardConfig::FLASH_STORAGE_SIZE
+  }
+}
+
+impl NorFlash for Storage {
+  const WRITE_SIZE: usize = 1; // STM32F4 supports byte programming
+  const ERASE_SIZE: usize = BoardConfig::FLASH_STORAGE_SIZE;
+
+  fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+    if to < from {
+      return Err(Error::Size);
+    }
+    Self::check(from, (to - from) as usize, Self::ERASE_SIZE)?;
+    if from == to {
+      return Ok(());
+    }
+    erase_sector_direct(start() + from)
+  }
+
+  fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+    Self::check(offset, bytes.len(), Self::WRITE_SIZE)?;
+    write_block(start() + offset, bytes)
+  }
+}
+
+impl async_nor_flash::ReadNorFlash for Storage {
+  const READ_SIZE: usize = <Self as ReadNorFlash>::READ_SIZE;
+
+  async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+    // Other tasks run while the DMA moves a large block
+    #[cfg(feature = "hw_dma")]
+    {
+      Self::check(offset, bytes.len(), <Self as ReadNorFlash>::READ_SIZE)?;
+      crate::hardware::dma::copy_async(bytes, mapped(start() + offset, bytes.len())).await;
+      Ok(())
+    }
+    #[cfg(not(feature = "hw_dma"))]
+    ReadNorFlash::read(self, offset, bytes)
+  }
+
+  fn capacity(&self) -> usize {
+    ReadNorFlash::capacity(self)
+  }
+}
+
+impl async_nor_flash::NorFlash for Storage {
+  const WRITE_SIZE: usize = <Self as NorFlash>::WRITE_SIZE;
+  const ERASE_SIZE: usize = <Self as NorFlash>::ERASE_SIZE;
+
+  async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+    NorFlash::erase(self, from, to)
+  }
+
+  async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+    NorFlash::write(self, offset, bytes)
+  }
+}
+
+/// Size of the 128 KB sectors at the top of flash that hold the storage and filesystem regions
+pub const SECTOR_SIZE: usize = 128 * 1024;
+
+/// `embedded-storage` view of a run of 128 KB sectors (offsets are relative to the first one)
+///
+/// Unlike `Storage`, erases work per sector, so block-based users such as the `fs` feature's
+/// littlefs can erase one block without losing the rest of the region.
+pub struct Sectors {
+  start: u32,
+  size: usize,
+}
+
+impl Sectors {
+  /// `start` must be the address of a 128 KB sector and `size` a multiple of `SECTOR_SIZE`
+  pub const fn new(start: u32, size: usize) -> Self {
+    Self { start, size }
+  }
+
+  /// The board's filesystem region (`FS_STORAGE_START`, `FS_STORAGE_SIZE`)
+  pub const fn filesystem() -> Self {
+    Self::new(BoardConfig::FS_STORAGE_START, BoardConfig::FS_STORAGE_SIZE)
+  }
+
+  fn check(&self, offset: u32, len: usize, align: usize) -> Result<(), Error> {
+    let offset = offset as usize;
+    if offset % align != 0 || len % align != 0 {
+      return Err(Error::Unaligned);
+    }
+    if offset.checked_add(len).map_or(true, |end| end > self.size) {
+      return Err(Error::Size);
+    }
+    Ok(())
+  }
+}
+
+impl ErrorType for Sectors {
+  type Error = Error;
+}
+
+impl ReadNorFlash for Sectors {
+  const READ_SIZE: usize = 1;
+
+  fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+    self.check(offset, bytes.len(), Self::READ_SIZE)?;
+    copy_out(self.start + offset, bytes);
+    Ok(())
+  }
+
+  fn capacity(&self) -> usize {
+    self.size
+  }
+}
+
+impl NorFlash for Sectors {
+  const WRITE_SIZE: usize = 1;
+  const ERASE_SIZE: usize = SECTOR_SIZE;
+
+  fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+    if to < from {
+      return Err(Error::Size);
+    }
+    self.check(from, (to - from) as usize, Self::ERASE_SIZE)?;
+    for sector in (from..to).step_by(SECTOR_SIZE) {
+      erase_sector_direct(self.start + sector)?;
+    }
+    Ok(())
+  }
+
+  fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+    self.check(offset, bytes.len(), Self::WRITE_SIZE)?;
+    write_block(self.start + offset, bytes)
+  }
+}
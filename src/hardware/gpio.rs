@@ -1,41 +1,67 @@
 /// GPIO Hardware Abstraction Layer
 ///
 /// This module provides convenient utilities and constants for GPIO operations
-/// specific to the STM32F446RE microcontroller setup.
-use embassy_stm32::gpio::{Input, Level, Output, Pull, Speed};
+/// specific to the STM32F446RE microcontroller setup. The LED and button helpers take
+/// any `embedded-hal` 1.0 pin, so they also work with I/O expanders and other driver crates.
+use embassy_stm32::gpio::{Level, Output, Pull, Speed};
+use embedded_hal::digital::{InputPin, OutputPin, StatefulOutputPin};
+use embedded_hal_async::digital::Wait;
 
 /// LED control utilities
 pub struct LedControl;
 
 impl LedControl {
   /// Turn LED on
-  pub fn turn_on(led: &mut Output<'_>) {
-    led.set_high();
+  pub fn turn_on<P: OutputPin>(led: &mut P) {
+    led.set_high().ok();
   }
 
   /// Turn LED off  
-  pub fn turn_off(led: &mut Output<'_>) {
-    led.set_low();
+  pub fn turn_off<P: OutputPin>(led: &mut P) {
+    led.set_low().ok();
   }
 
   /// Toggle LED state
-  pub fn toggle(led: &mut Output<'_>) {
-    led.toggle();
+  pub fn toggle<P: StatefulOutputPin>(led: &mut P) {
+    led.toggle().ok();
   }
 }
 
+/// A board's user LEDs by status role: green heartbeat, blue comm activity, red error
+///
+/// Built by `BoardConfig::init_leds` from the LED returned by `init_all_hardware`; roles the
+/// board has no dedicated LED for are None (single-LED boards use `heartbeat` for everything).
+pub struct Leds {
+  /// Green: heartbeat (the board's primary LED)
+  pub heartbeat: Output<'static>,
+  /// Blue: comm activity
+  pub activity: Option<Output<'static>>,
+  /// Red: errors/alarms
+  pub error: Option<Output<'static>>,
+}
+
 /// Button reading utilities
 pub struct ButtonReader;
 
 impl ButtonReader {
-  /// Check if button is pressed
-  pub fn is_pressed(button: &Input<'_>) -> bool {
-    button.is_high()
+  /// Check if button is pressed (false if the pin cannot be read)
+  pub fn is_pressed<B: InputPin>(button: &mut B) -> bool {
+    button.is_high().unwrap_or(false)
+  }
+
+  /// Check if button is released (false if the pin cannot be read)
+  pub fn is_released<B: InputPin>(button: &mut B) -> bool {
+    button.is_low().unwrap_or(false)
+  }
+
+  /// Wait until the button is pressed (any `embedded-hal-async` pin, e.g. `ExtiInput`)
+  pub async fn wait_pressed<B: Wait>(button: &mut B) -> Result<(), B::Error> {
+    button.wait_for_high().await
   }
 
-  /// Check if button is released
-  pub fn is_released(button: &Input<'_>) -> bool {
-    button.is_low()
+  /// Wait until the button is released (any `embedded-hal-async` pin, e.g. `ExtiInput`)
+  pub async fn wait_released<B: Wait>(button: &mut B) -> Result<(), B::Error> {
+    button.wait_for_low().await
   }
 }
 
@@ -50,3 +76,34 @@ impl GpioDefaults {
   /// Standard button configuration with pull-down
   pub const BUTTON_PULL: Pull = Pull::Down;
 }
+
+// GPIO register blocks: GPIOA at 0x4002_0000, one every 0x400
+const GPIO_BASE: u32 = 0x4002_0000;
+const GPIO_MODER: u32 = 0x00;
+const GPIO_OTYPER: u32 = 0x04;
+const GPIO_AFRL: u32 = 0x20;
+
+// Register of GPIO port `port` (0 = A)
+fn gpio_reg(port: u8, offset: u32) -> *mut u32 {
+  (GPIO_BASE + 0x400 * port as u32 + offset) as *mut u32
+}
+
+/// Put pin `pin` of port `port` (0 = A) in alternate function `af`, for peripherals embassy-stm32
+/// has no driver for (USART clock output, DFSDM). The caller owns the pin.
+pub fn set_alternate(port: u8, pin: u8, af: u8) {
+  let (n, af) = (pin as u32, af as u32);
+  // SAFETY: mode and alternate function fields of that one pin only
+  unsafe {
+    let afr = gpio_reg(port, GPIO_AFRL + 4 * (n / 8));
+    afr.write_volatile((afr.read_volatile() & !(0xF << (4 * (n % 8)))) | af << (4 * (n % 8)));
+    let moder = gpio_reg(port, GPIO_MODER);
+    moder.write_volatile((moder.read_volatile() & !(0b11 << (2 * n))) | 0b10 << (2 * n));
+  }
+}
+
+/// Make an output or alternate function pin open drain
+pub fn set_open_drain(port: u8, pin: u8) {
+  let otyper = gpio_reg(port, GPIO_OTYPER);
+  // SAFETY: output type bit of that one pin only
+  unsafe { otyper.write_volatile(otyper.read_volatile() | 1 << pin) }
+}
@@ -1,6 +1,20 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+use cortex_m::peripheral::{NVIC, SCB};
 use cortex_m_rt::exception;
+#[cfg(not(feature = "rtt_control"))]
 use defmt_rtt as _;
 
+// SCB fault status: CFSR (MMFSR in bits 0-7) and the MemManage fault address
+const SCB_CFSR: *const u32 = 0xE000_ED28 as *const u32;
+const SCB_MMFAR: *const u32 = 0xE000_ED34 as *const u32;
+#[cfg(feature = "mpu")]
+const MMFSR_MSTKERR: u32 = 1 << 4;
+const MMFSR_MMARVALID: u32 = 1 << 7;
+
+// Unexpected interrupts taken so far, and the last IRQ number (u32::MAX = none)
+static UNEXPECTED_IRQ_COUNT: AtomicU32 = AtomicU32::new(0);
+static LAST_UNEXPECTED_IRQ: AtomicU32 = AtomicU32::new(u32::MAX);
+
 /// Performs a system reset via the System Control Block (SCB)
 unsafe fn system_reset() -> ! {
   const SCB_AIRCR: *mut u32 = 0xE000_ED0C as *mut u32;
@@ -34,8 +48,50 @@ unsafe fn HardFault(ef: &cortex_m_rt::ExceptionFrame) -> ! {
     let pc = *regs.offset(6);
     let instr = core::ptr::read_volatile(pc as *const u16);
     defmt::error!("Last instruction (16-bit at PC): {=u16:x}", instr);
+    crate::diagnostics::eventlog::record(crate::diagnostics::eventlog::EVENT_HARDFAULT, pc);
   }
+  // An MPU violation the MemManage handler could not report (no stack left) escalates to here
+  memfault(regs as u32);
+  fault_reset()
+}
+
+/// MPU violation: a stack overflow into the guard or a write to protected flash (`hardware::mpu`)
+#[cfg(feature = "mpu")]
+#[exception]
+fn MemoryManagement() -> ! {
+  let sp: u32;
+  unsafe { core::arch::asm!("mov {}, sp", out(reg) sp) }
+  memfault(sp);
+  fault_reset()
+}
 
+// Report and log a MemManage fault from the status registers, if one is pending (`sp`: the
+// stack pointer in the handler, logged when no fault address was captured)
+fn memfault(sp: u32) {
+  use crate::diagnostics::eventlog;
+  // SAFETY: read-only access to the fault status registers
+  let mmfsr = unsafe { SCB_CFSR.read_volatile() } & 0xFF;
+  if mmfsr == 0 {
+    return;
+  }
+  let addr = (mmfsr & MMFSR_MMARVALID != 0).then(|| unsafe { SCB_MMFAR.read_volatile() });
+  #[cfg(feature = "mpu")]
+  if mmfsr & MMFSR_MSTKERR != 0 || addr.is_some_and(crate::hardware::mpu::in_stack_guard) {
+    defmt::error!("Stack overflow: access 0x{:08X} with SP 0x{:08X} (MMFSR 0x{:02X})", addr.unwrap_or(0), sp, mmfsr);
+    eventlog::record(eventlog::EVENT_STACK_OVERFLOW, sp);
+    return;
+  }
+  match addr {
+    Some(addr) => defmt::error!("MemManage fault: access 0x{:08X} (MMFSR 0x{:02X})", addr, mmfsr),
+    None => defmt::error!("MemManage fault: SP 0x{:08X} (MMFSR 0x{:02X})", sp, mmfsr),
+  }
+  eventlog::record(eventlog::EVENT_MEMFAULT, addr.unwrap_or(sp));
+}
+
+// Release the fail-safe outputs, give the log time to drain and reset
+fn fault_reset() -> ! {
+  // Release relays and other registered outputs before waiting out the log
+  crate::service::failsafe::apply(crate::service::failsafe::Trigger::HardFault);
   defmt::error!("Performing automatic system reset in 100ms...");
 
   // Short delay to allow log output to be transmitted
@@ -48,3 +104,51 @@ unsafe fn HardFault(ef: &cortex_m_rt::ExceptionFrame) -> ! {
     system_reset();
   }
 }
+
+/// Default handler for interrupts nobody claimed (see `interrupt_stubs!`)
+///
+/// Records the IRQ number and masks it in the NVIC, so a stuck interrupt source shows up in
+/// `last_unexpected_irq()` instead of hanging the MCU or re-entering forever.
+pub fn unexpected_irq() {
+  // ICSR.VECTACTIVE is the active exception number; external IRQs start at 16
+  let vector = unsafe { (*SCB::PTR).icsr.read() & 0x1FF };
+  UNEXPECTED_IRQ_COUNT.fetch_add(1, Ordering::Relaxed);
+  let Some(irq) = vector.checked_sub(16) else {
+    defmt::warn!("Unexpected exception {}", vector);
+    return;
+  };
+  LAST_UNEXPECTED_IRQ.store(irq, Ordering::Relaxed);
+  // SAFETY: single write to the ICER bit of this IRQ (write-one-to-clear, no read-modify-write)
+  unsafe { (*NVIC::PTR).icer[irq as usize / 32].write(1 << (irq % 32)) };
+  defmt::warn!("Unexpected interrupt: IRQ {} (masked)", irq);
+}
+
+/// Number of unexpected interrupts taken since boot
+pub fn unexpected_irq_count() -> u32 {
+  UNEXPECTED_IRQ_COUNT.load(Ordering::Relaxed)
+}
+
+/// IRQ number of the most recent unexpected interrupt
+pub fn last_unexpected_irq() -> Option<u16> {
+  let irq = LAST_UNEXPECTED_IRQ.load(Ordering::Relaxed);
+  (irq != u32::MAX).then_some(irq as u16)
+}
+
+/// Define linker-visible handlers for interrupt vectors a board leaves unused
+///
+/// `interrupt_stubs!(WWDG, SPI3, LPTIM1);` routes each to `hardfault::unexpected_irq`;
+/// `interrupt_stubs!(handler = my_handler; WWDG, SPI3);` routes them to `my_handler()` instead.
+#[macro_export]
+macro_rules! interrupt_stubs {
+  (handler = $handler:path; $($irq:ident),+ $(,)?) => {
+    $(
+      #[unsafe(no_mangle)]
+      extern "C" fn $irq() {
+        $handler();
+      }
+    )+
+  };
+  ($($irq:ident),+ $(,)?) => {
+    $crate::interrupt_stubs!(handler = $crate::hardware::hardfault::unexpected_irq; $($irq),+);
+  };
+}
@@ -0,0 +1,101 @@
+/// LIN mode for a USART: break generation and detection, plus a slave responder loop
+///
+/// The STM32F4 USARTs have a LIN mode (CR2.LINEN): the receiver flags a break of 11 low bits
+/// (SR.LBD) separately from data, and CR1.SBK sends a 13-bit break. embassy-stm32 does not expose
+/// it, so `UsartPort::enable_lin` (`hardware::serial`) switches a USART embassy has already set up
+/// (8N1, usually 19200 baud; LIN is always 8 data bits, so leave `DataBits8`) into LIN mode with
+/// direct register access. Wire the port's TX/RX to a LIN transceiver (TJA1021, MCP2003, ...); the
+/// single-wire bus echoes every byte sent.
+///
+/// `run_slave` answers headers with `protocol::lin::LinSlave`: it reads the bus a byte at a time,
+/// feeds breaks and bytes to the slave, asks a `LinHandler` for the data of published frames and
+/// hands it the data of subscribed ones. A master sends headers with `send_break` followed by a
+/// write of the sync byte and protected id.
+use embassy_stm32::mode::Async;
+use embassy_stm32::usart::{UartRx, UartTx};
+
+use crate::hardware::serial::UsartPort;
+use crate::protocol::lin::{LinEvent, LinSlave, MAX_DATA};
+
+// USART register offsets and bits
+const SR: u32 = 0x00;
+const CR1: u32 = 0x0C;
+const CR2: u32 = 0x10;
+const CR3: u32 = 0x14;
+const SR_LBD: u32 = 1 << 8;
+const CR1_SBK: u32 = 1 << 0;
+const CR2_LBDL: u32 = 1 << 5;
+const CR2_CLKEN: u32 = 1 << 11;
+const CR2_STOP: u32 = 0b11 << 12;
+const CR2_LINEN: u32 = 1 << 14;
+// Smartcard, half-duplex and IrDA must be off in LIN mode
+const CR3_LIN_CONFLICTS: u32 = (1 << 5) | (1 << 3) | (1 << 1);
+
+impl UsartPort {
+  /// Switch the (already initialized) USART to LIN mode with 11-bit break detection
+  pub fn enable_lin(self) {
+    // SAFETY: read-modify-write of configuration bits embassy leaves alone after init
+    unsafe {
+      let cr2 = self.reg(CR2).read_volatile();
+      self.reg(CR2).write_volatile((cr2 & !(CR2_CLKEN | CR2_STOP)) | CR2_LINEN | CR2_LBDL);
+      let cr3 = self.reg(CR3).read_volatile();
+      self.reg(CR3).write_volatile(cr3 & !CR3_LIN_CONFLICTS);
+    }
+  }
+
+  /// Queue a break (13 low bits) before the next byte written; SBK clears itself once sent
+  pub fn send_break(self) {
+    // SAFETY: SBK only requests a break character
+    unsafe { self.reg(CR1).write_volatile(self.reg(CR1).read_volatile() | CR1_SBK) }
+  }
+
+  /// Whether a break was detected since the last call (clears the flag)
+  pub fn take_break(self) -> bool {
+    // SAFETY: LBD is cleared by writing 0; the other SR bits ignore writes of 1
+    unsafe {
+      if self.reg(SR).read_volatile() & SR_LBD == 0 {
+        return false;
+      }
+      self.reg(SR).write_volatile(!SR_LBD);
+    }
+    true
+  }
+}
+
+/// Application side of a LIN slave
+pub trait LinHandler {
+  /// Fill `data` (the frame's length) for a frame this node publishes; false stays silent
+  fn publish(&mut self, id: u8, data: &mut [u8]) -> bool;
+  /// Data of a subscribed frame, checksum verified
+  fn received(&mut self, id: u8, data: &[u8]);
+}
+
+/// Serve `slave`'s frame table on `port` forever (`port.enable_lin()` is called first)
+pub async fn run_slave(port: UsartPort, mut rx: UartRx<'_, Async>, mut tx: UartTx<'_, Async>, slave: &mut LinSlave<'_>, handler: &mut impl LinHandler) -> ! {
+  port.enable_lin();
+  loop {
+    let mut byte = [0u8];
+    let received = rx.read(&mut byte).await;
+    // The break also shows up as a framing error or a 0x00 byte, depending on the line
+    if (received.is_err() || byte[0] == 0x00) && port.take_break() {
+      slave.on_break();
+      continue;
+    }
+    if received.is_err() {
+      continue;
+    }
+    match slave.push(byte[0]) {
+      Some(LinEvent::Publish(frame)) => {
+        let mut data = [0u8; MAX_DATA];
+        let data = &mut data[..(frame.len as usize).min(MAX_DATA)];
+        if handler.publish(frame.id, data) {
+          let response = slave.response(frame.id, data);
+          let _ = tx.write(&response).await;
+        }
+      }
+      Some(LinEvent::Received { id, data }) => handler.received(id, data),
+      Some(LinEvent::Error(e)) => defmt::debug!("LIN: {}", defmt::Debug2Format(&e)),
+      None => {}
+    }
+  }
+}
@@ -0,0 +1,305 @@
+/// LoRa Radio Driver (Semtech SX1276/77/78/79, HopeRF RFM95/96/98)
+///
+/// This module runs an SX127x in LoRa mode for raw point-to-point packets (no LoRaWAN stack):
+/// `Sx127x::new` resets the chip, checks its version register and applies a `LoraConfig`
+/// (frequency, spreading factor, bandwidth, coding rate, power, sync word); `send` and `receive`
+/// then wait on the DIO0 interrupt line instead of polling, so other tasks run during the air
+/// time. Registers go over any blocking `embedded-hal` SPI device (e.g. a `SharedBus` handle; the
+/// transfers are a few bytes), DIO0 is any `embedded-hal-async` pin (`ExtiInput`). Radios with
+/// the TX pin on PA_BOOST (most shields and RFM9x modules) are assumed. The SX126x family has a
+/// command-based interface and needs its own driver.
+use embassy_time::{Duration, Timer, with_timeout};
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::{Operation, SpiDevice};
+use embedded_hal_async::digital::Wait;
+
+/// SPI clock for the radio (10 MHz maximum)
+pub const LORA_SPI_HZ: u32 = 8_000_000;
+/// Largest packet payload
+pub const LORA_MAX_PAYLOAD: usize = 255;
+/// Longest `send` may wait for TX done (SF12 / 125 kHz / 255 bytes is about 10 s on air)
+pub const LORA_TX_TIMEOUT_MS: u64 = 12_000;
+
+// Version register value of the SX1276/77/78/79
+const SX127X_VERSION: u8 = 0x12;
+// Crystal frequency; the synthesizer step is 32 MHz / 2^19
+const FXOSC_HZ: u64 = 32_000_000;
+
+mod reg {
+  pub const FIFO: u8 = 0x00;
+  pub const OP_MODE: u8 = 0x01;
+  pub const FRF_MSB: u8 = 0x06;
+  pub const PA_CONFIG: u8 = 0x09;
+  pub const LNA: u8 = 0x0C;
+  pub const FIFO_ADDR_PTR: u8 = 0x0D;
+  pub const FIFO_TX_BASE_ADDR: u8 = 0x0E;
+  pub const FIFO_RX_BASE_ADDR: u8 = 0x0F;
+  pub const FIFO_RX_CURRENT_ADDR: u8 = 0x10;
+  pub const IRQ_FLAGS: u8 = 0x12;
+  pub const RX_NB_BYTES: u8 = 0x13;
+  pub const PKT_SNR_VALUE: u8 = 0x19;
+  pub const PKT_RSSI_VALUE: u8 = 0x1A;
+  pub const MODEM_CONFIG_1: u8 = 0x1D;
+  pub const MODEM_CONFIG_2: u8 = 0x1E;
+  pub const PREAMBLE_MSB: u8 = 0x20;
+  pub const PAYLOAD_LENGTH: u8 = 0x22;
+  pub const MODEM_CONFIG_3: u8 = 0x26;
+  pub const SYNC_WORD: u8 = 0x39;
+  pub const DIO_MAPPING_1: u8 = 0x40;
+  pub const VERSION: u8 = 0x42;
+}
+
+// OP_MODE: LoRa mode bit plus the transceiver mode
+const MODE_LONG_RANGE: u8 = 0x80;
+const MODE_SLEEP: u8 = 0x00;
+const MODE_STDBY: u8 = 0x01;
+const MODE_TX: u8 = 0x03;
+const MODE_RX_CONTINUOUS: u8 = 0x05;
+
+const IRQ_RX_DONE: u8 = 0x40;
+const IRQ_PAYLOAD_CRC_ERROR: u8 = 0x20;
+
+// DIO_MAPPING_1 bits 7:6 select what DIO0 signals
+const DIO0_RX_DONE: u8 = 0x00;
+const DIO0_TX_DONE: u8 = 0x40;
+
+/// Signal bandwidth
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum Bandwidth {
+  Khz125 = 7,
+  Khz250 = 8,
+  Khz500 = 9,
+}
+
+/// Forward error correction rate
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum CodingRate {
+  Cr4_5 = 1,
+  Cr4_6 = 2,
+  Cr4_7 = 3,
+  Cr4_8 = 4,
+}
+
+/// Radio settings; both ends of a link need the same frequency, spreading factor, bandwidth,
+/// coding rate and sync word
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct LoraConfig {
+  /// Carrier frequency in Hz (must match the module's band: 433, 868 or 915 MHz)
+  pub frequency_hz: u32,
+  /// Spreading factor 7..=12 (higher: longer range, slower)
+  pub spreading_factor: u8,
+  pub bandwidth: Bandwidth,
+  pub coding_rate: CodingRate,
+  /// Output power on PA_BOOST, 2..=17 dBm
+  pub tx_power_dbm: i8,
+  /// 0x12 for private networks (0x34 is reserved for LoRaWAN)
+  pub sync_word: u8,
+  /// Preamble symbols
+  pub preamble_len: u16,
+}
+
+impl Default for LoraConfig {
+  /// 868.1 MHz (EU868; use 915 MHz in the Americas), SF7, 125 kHz, 4/5, 14 dBm
+  fn default() -> Self {
+    Self {
+      frequency_hz: 868_100_000,
+      spreading_factor: 7,
+      bandwidth: Bandwidth::Khz125,
+      coding_rate: CodingRate::Cr4_5,
+      tx_power_dbm: 14,
+      sync_word: 0x12,
+      preamble_len: 8,
+    }
+  }
+}
+
+/// A received packet
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct RxInfo {
+  /// Payload length (copied into the caller's buffer up to its size)
+  pub len: usize,
+  /// Packet RSSI in dBm
+  pub rssi_dbm: i16,
+  /// Packet SNR in 0.25 dB steps
+  pub snr_quarter_db: i8,
+}
+
+/// Radio errors
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum LoraError {
+  /// SPI transfer or pin access failed
+  Bus,
+  /// The version register did not read as an SX127x (value read; 0x00/0xFF: no radio)
+  NotFound(u8),
+  /// Setting out of range
+  Config,
+  /// Payload longer than `LORA_MAX_PAYLOAD`
+  TooLong,
+  /// Packet received with a bad CRC (dropped)
+  Crc,
+  /// Nothing sent or received within the timeout
+  Timeout,
+}
+
+/// SX127x radio on SPI with a reset line and the DIO0 interrupt line
+pub struct Sx127x<S: SpiDevice, R: OutputPin, D: Wait> {
+  spi: S,
+  reset: R,
+  dio0: D,
+  config: LoraConfig,
+}
+
+impl<S: SpiDevice, R: OutputPin, D: Wait> Sx127x<S, R, D> {
+  /// Reset and identify the radio, apply `config` and leave it in standby
+  pub async fn new(spi: S, reset: R, dio0: D, config: LoraConfig) -> Result<Self, LoraError> {
+    let mut radio = Self { spi, reset, dio0, config };
+    radio.reset().await?;
+    let version = radio.read_reg(reg::VERSION)?;
+    if version != SX127X_VERSION {
+      return Err(LoraError::NotFound(version));
+    }
+    radio.configure(&config)?;
+    defmt::info!("LoRa: SX127x at {} Hz, SF{}", config.frequency_hz, config.spreading_factor);
+    Ok(radio)
+  }
+
+  /// Pulse the reset line (the chip needs 5 ms afterwards)
+  pub async fn reset(&mut self) -> Result<(), LoraError> {
+    self.reset.set_low().map_err(|_| LoraError::Bus)?;
+    Timer::after_millis(1).await;
+    self.reset.set_high().map_err(|_| LoraError::Bus)?;
+    Timer::after_millis(5).await;
+    Ok(())
+  }
+
+  /// Apply new settings (leaves the radio in standby)
+  pub fn configure(&mut self, config: &LoraConfig) -> Result<(), LoraError> {
+    if !(7..=12).contains(&config.spreading_factor) || !(2..=17).contains(&config.tx_power_dbm) {
+      return Err(LoraError::Config);
+    }
+    // LoRa mode can only be selected from sleep
+    self.write_reg(reg::OP_MODE, MODE_LONG_RANGE | MODE_SLEEP)?;
+    let frf = ((config.frequency_hz as u64) << 19) / FXOSC_HZ;
+    self.write_regs(reg::FRF_MSB, &[(frf >> 16) as u8, (frf >> 8) as u8, frf as u8])?;
+    self.write_reg(reg::FIFO_TX_BASE_ADDR, 0)?;
+    self.write_reg(reg::FIFO_RX_BASE_ADDR, 0)?;
+    // LNA: maximum gain, boost on (HF port)
+    self.write_reg(reg::LNA, 0x23)?;
+    // PA_BOOST, Pout = 2 + OutputPower dBm
+    self.write_reg(reg::PA_CONFIG, 0x80 | (config.tx_power_dbm - 2) as u8)?;
+    // Explicit header (length and CRC flag sent in the packet)
+    self.write_reg(reg::MODEM_CONFIG_1, ((config.bandwidth as u8) << 4) | ((config.coding_rate as u8) << 1))?;
+    // Payload CRC on
+    self.write_reg(reg::MODEM_CONFIG_2, (config.spreading_factor << 4) | 0x04)?;
+    // AGC on, plus low data rate optimization where a symbol lasts over 16 ms (SF11/12 at 125 kHz)
+    let low_data_rate = config.bandwidth == Bandwidth::Khz125 && config.spreading_factor >= 11;
+    self.write_reg(reg::MODEM_CONFIG_3, 0x04 | if low_data_rate { 0x08 } else { 0 })?;
+    self.write_regs(reg::PREAMBLE_MSB, &config.preamble_len.to_be_bytes())?;
+    self.write_reg(reg::SYNC_WORD, config.sync_word)?;
+    self.set_mode(MODE_STDBY)?;
+    self.config = *config;
+    Ok(())
+  }
+
+  /// Settings in use
+  pub fn config(&self) -> &LoraConfig {
+    &self.config
+  }
+
+  /// Transmit one packet and wait until it is on air (returns in standby)
+  pub async fn send(&mut self, data: &[u8]) -> Result<(), LoraError> {
+    if data.len() > LORA_MAX_PAYLOAD {
+      return Err(LoraError::TooLong);
+    }
+    self.set_mode(MODE_STDBY)?;
+    self.write_reg(reg::FIFO_ADDR_PTR, 0)?;
+    self.write_regs(reg::FIFO, data)?;
+    self.write_reg(reg::PAYLOAD_LENGTH, data.len() as u8)?;
+    self.write_reg(reg::DIO_MAPPING_1, DIO0_TX_DONE)?;
+    self.clear_irq()?;
+    self.set_mode(MODE_TX)?;
+    let done = self.wait_dio0(Some(LORA_TX_TIMEOUT_MS)).await;
+    self.clear_irq()?;
+    self.set_mode(MODE_STDBY)?;
+    done
+  }
+
+  /// Listen until a packet arrives (or `timeout_ms` passes) and copy its payload into `buf`
+  /// (returns in standby)
+  pub async fn receive(&mut self, buf: &mut [u8], timeout_ms: Option<u64>) -> Result<RxInfo, LoraError> {
+    self.set_mode(MODE_STDBY)?;
+    self.write_reg(reg::DIO_MAPPING_1, DIO0_RX_DONE)?;
+    self.write_reg(reg::FIFO_ADDR_PTR, 0)?;
+    self.clear_irq()?;
+    self.set_mode(MODE_RX_CONTINUOUS)?;
+    let done = self.wait_dio0(timeout_ms).await;
+    self.set_mode(MODE_STDBY)?;
+    done?;
+
+    let flags = self.read_reg(reg::IRQ_FLAGS)?;
+    self.clear_irq()?;
+    if flags & IRQ_PAYLOAD_CRC_ERROR != 0 {
+      return Err(LoraError::Crc);
+    }
+    if flags & IRQ_RX_DONE == 0 {
+      return Err(LoraError::Timeout);
+    }
+    let len = self.read_reg(reg::RX_NB_BYTES)? as usize;
+    let start = self.read_reg(reg::FIFO_RX_CURRENT_ADDR)?;
+    self.write_reg(reg::FIFO_ADDR_PTR, start)?;
+    let copy = len.min(buf.len());
+    self.read_regs(reg::FIFO, &mut buf[..copy])?;
+
+    let snr_quarter_db = self.read_reg(reg::PKT_SNR_VALUE)? as i8;
+    // RSSI offset of the HF port (868/915 MHz); the LF port (433 MHz) reads 7 dB lower
+    let offset = if self.config.frequency_hz > 525_000_000 { -157 } else { -164 };
+    let rssi_dbm = offset + self.read_reg(reg::PKT_RSSI_VALUE)? as i16;
+    Ok(RxInfo { len, rssi_dbm, snr_quarter_db })
+  }
+
+  /// Put the radio to sleep (lowest current; `send`/`receive` wake it)
+  pub fn sleep(&mut self) -> Result<(), LoraError> {
+    self.set_mode(MODE_SLEEP)
+  }
+
+  async fn wait_dio0(&mut self, timeout_ms: Option<u64>) -> Result<(), LoraError> {
+    let result = match timeout_ms {
+      Some(ms) => with_timeout(Duration::from_millis(ms), self.dio0.wait_for_high()).await.map_err(|_| LoraError::Timeout)?,
+      None => self.dio0.wait_for_high().await,
+    };
+    result.map_err(|_| LoraError::Bus)
+  }
+
+  fn set_mode(&mut self, mode: u8) -> Result<(), LoraError> {
+    self.write_reg(reg::OP_MODE, MODE_LONG_RANGE | mode)
+  }
+
+  fn clear_irq(&mut self) -> Result<(), LoraError> {
+    self.write_reg(reg::IRQ_FLAGS, 0xFF)
+  }
+
+  fn read_reg(&mut self, addr: u8) -> Result<u8, LoraError> {
+    let mut value = [0u8; 1];
+    self.read_regs(addr, &mut value)?;
+    Ok(value[0])
+  }
+
+  fn write_reg(&mut self, addr: u8, value: u8) -> Result<(), LoraError> {
+    self.write_regs(addr, &[value])
+  }
+
+  // Burst access: consecutive registers, or repeated FIFO bytes
+  fn read_regs(&mut self, addr: u8, buf: &mut [u8]) -> Result<(), LoraError> {
+    self
+      .spi
+      .transaction(&mut [Operation::Write(&[addr & 0x7F]), Operation::Read(buf)])
+      .map_err(|_| LoraError::Bus)
+  }
+
+  fn write_regs(&mut self, addr: u8, data: &[u8]) -> Result<(), LoraError> {
+    self
+      .spi
+      .transaction(&mut [Operation::Write(&[addr | 0x80]), Operation::Write(data)])
+      .map_err(|_| LoraError::Bus)
+  }
+}
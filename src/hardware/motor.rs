@@ -0,0 +1,161 @@
+/// Motor Hardware Abstraction Layer
+///
+/// This module provides hobby servo control (50 Hz PWM via `HwTimer::pwm`) and a step/dir
+/// stepper driver (A4988, DRV8825, TMC2208, ...) with a trapezoidal acceleration ramp.
+/// Step timing uses embassy-time (32.768 kHz tick), which suits step rates up to a few kHz.
+use core::sync::atomic::{AtomicI32, Ordering};
+use embassy_stm32::Peri;
+use embassy_stm32::gpio::Output;
+use embassy_stm32::time::Hertz;
+use embassy_stm32::timer::simple_pwm::SimplePwm;
+use embassy_stm32::timer::{Ch1, GeneralInstance4Channel, TimerPin};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Timer;
+
+use crate::hardware::HwTimer;
+
+/// Servo frame period (50 Hz)
+const SERVO_PERIOD_US: u16 = 20_000;
+
+/// Hobby servo on channel 1 of a timer
+pub struct Servo<T: GeneralInstance4Channel> {
+  pwm: SimplePwm<'static, T>,
+  min_us: u16,
+  max_us: u16,
+  range_deg: f32,
+}
+
+impl<T: GeneralInstance4Channel> Servo<T> {
+  /// Servo with the common 1000-2000 us pulse range over 180 degrees
+  pub fn new(tim: Peri<'static, T>, pin: Peri<'static, impl TimerPin<T, Ch1>>) -> Self {
+    let mut pwm = HwTimer::pwm(tim, pin, Hertz(50));
+    pwm.ch1().enable();
+    let mut servo = Self {
+      pwm,
+      min_us: 1000,
+      max_us: 2000,
+      range_deg: 180.0,
+    };
+    servo.set_angle_deg(90.0);
+    servo
+  }
+
+  /// Calibrate the pulse width at 0 degrees (`min_us`) and at `range_deg` (`max_us`)
+  pub fn with_range(mut self, min_us: u16, max_us: u16, range_deg: f32) -> Self {
+    self.min_us = min_us;
+    self.max_us = max_us;
+    self.range_deg = range_deg;
+    self
+  }
+
+  /// Output a raw pulse width in microseconds
+  pub fn set_pulse_us(&mut self, us: u16) {
+    self.pwm.ch1().set_duty_cycle_fraction(us.min(SERVO_PERIOD_US).into(), SERVO_PERIOD_US.into());
+  }
+
+  /// Move to `deg` (clamped to 0..=range)
+  pub fn set_angle_deg(&mut self, deg: f32) {
+    let fraction = (deg / self.range_deg).clamp(0.0, 1.0);
+    let span = (self.max_us - self.min_us) as f32;
+    self.set_pulse_us(self.min_us + (fraction * span) as u16);
+  }
+
+  /// Stop sending pulses (most servos then go limp)
+  pub fn disable(&mut self) {
+    self.pwm.ch1().disable();
+  }
+}
+
+/// Step/dir stepper driver with an acceleration ramp
+pub struct Stepper {
+  step: Output<'static>,
+  dir: Output<'static>,
+  position: i32,
+  /// Speed at the start/end of a move, in steps/s
+  start_speed: f32,
+  /// Cruise speed in steps/s
+  max_speed: f32,
+  /// Acceleration in steps/s^2
+  accel: f32,
+}
+
+impl Stepper {
+  /// Stepper on `step`/`dir` outputs (dir high = positive direction), speeds in steps/s
+  pub fn new(step: Output<'static>, dir: Output<'static>, max_speed: f32, accel: f32) -> Self {
+    Self {
+      step,
+      dir,
+      position: 0,
+      start_speed: (max_speed / 10.0).max(1.0),
+      max_speed,
+      accel,
+    }
+  }
+
+  /// Current position in steps
+  pub fn position(&self) -> i32 {
+    self.position
+  }
+
+  /// Redefine the current position (e.g. 0 at a homing switch)
+  pub fn set_position(&mut self, position: i32) {
+    self.position = position;
+  }
+
+  /// Move to absolute `target`, accelerating from and decelerating to the start speed
+  pub async fn move_to(&mut self, target: i32) {
+    let steps = target.abs_diff(self.position);
+    let forward = target > self.position;
+    self.dir.set_level(forward.into());
+    // Driver direction setup time
+    Timer::after_micros(5).await;
+
+    let mut speed = self.start_speed;
+    let mut accel_steps = 0;
+    for done in 0..steps {
+      self.step.set_high();
+      Timer::after_micros(2).await;
+      self.step.set_low();
+      self.position += if forward { 1 } else { -1 };
+
+      // v' = v +/- a * dt with dt = 1 / v: ramp down once the remaining steps match the ramp up
+      let remaining = steps - done - 1;
+      if remaining <= accel_steps {
+        speed = (speed - self.accel / speed).max(self.start_speed);
+      } else if speed < self.max_speed {
+        speed = (speed + self.accel / speed).min(self.max_speed);
+        accel_steps += 1;
+      }
+      Timer::after_micros((1_000_000.0 / speed) as u64).await;
+    }
+  }
+
+  /// Move by `steps` relative to the current position
+  pub async fn move_by(&mut self, steps: i32) {
+    self.move_to(self.position.wrapping_add(steps)).await;
+  }
+}
+
+static STEPPER_TARGET: Signal<CriticalSectionRawMutex, i32> = Signal::new();
+static STEPPER_POSITION: AtomicI32 = AtomicI32::new(0);
+
+/// Ask `stepper_task` to move to `target` (replaces a target it has not started on yet)
+pub fn stepper_move_to(target: i32) {
+  STEPPER_TARGET.signal(target);
+}
+
+/// Last position reported by `stepper_task`
+pub fn stepper_position() -> i32 {
+  STEPPER_POSITION.load(Ordering::Relaxed)
+}
+
+/// Stepper ramp task: runs each target from `stepper_move_to` to completion
+#[embassy_executor::task]
+pub async fn stepper_task(mut stepper: Stepper) {
+  loop {
+    let target = STEPPER_TARGET.wait().await;
+    stepper.move_to(target).await;
+    STEPPER_POSITION.store(stepper.position(), Ordering::Relaxed);
+  }
+}
@@ -0,0 +1,185 @@
+/// Memory Protection Unit: Stack Guard and Read-Only Flash (feature `mpu`)
+///
+/// The main stack grows down from the top of RAM towards the end of the statics (`__sheap`);
+/// without protection, an overflow silently overwrites `.bss`/`.noinit` and the firmware fails
+/// later somewhere unrelated. `init` programs the Cortex-M4 MPU with a no-access guard region
+/// (`MpuConfig::stack_guard` bytes, aligned to its size) at the bottom of the stack, so the
+/// first access into it raises a MemManage fault that `hardware::hardfault` reports as a stack
+/// overflow, and maps the flash sectors write-protected in the option bytes read-only, so a
+/// stray write to them faults with its address instead of only setting a flash error flag.
+///
+/// Everything else keeps the default memory map (PRIVDEFENA). The MPU stays off in HardFault
+/// (HFNMIENA clear): when the MemManage handler itself has no stack left, the fault escalates to
+/// HardFault, which still runs and reports the overflow. The guard catches frames that touch it: a
+/// function with more locals than the guard size can jump over it, so size the guard above the
+/// largest stack frame. The guard comes out of the stack (`diagnostics::stack` scans above it).
+///
+/// `BoardConfig::init_all_hardware` calls `init(MpuConfig::new())` when the feature is on (the
+/// stack, guard included, is painted at reset, before the MPU is on).
+use core::ops::Range;
+use core::sync::atomic::{AtomicU32, Ordering};
+use cortex_m::peripheral::{MPU, SCB};
+
+use crate::hardware::option_bytes;
+
+/// Default guard size (a power of two, at least 32)
+pub const MPU_STACK_GUARD: u32 = 512;
+
+// MPU_CTRL
+const CTRL_ENABLE: u32 = 1 << 0;
+const CTRL_PRIVDEFENA: u32 = 1 << 2;
+// MPU_RASR fields
+const RASR_ENABLE: u32 = 1 << 0;
+const RASR_SIZE_SHIFT: u32 = 1;
+const RASR_SRD_SHIFT: u32 = 8;
+const RASR_XN: u32 = 1 << 28;
+const RASR_AP_NONE: u32 = 0b000 << 24;
+const RASR_AP_RO: u32 = 0b110 << 24;
+// Normal memory: flash write-through (C), SRAM write-back shareable (S, C, B)
+const RASR_FLASH: u32 = 1 << 17;
+const RASR_SRAM: u32 = (1 << 18) | (1 << 17) | (1 << 16);
+// SHCSR.MEMFAULTENA: MPU violations raise MemManage instead of HardFault
+const SHCSR_MEMFAULTENA: u32 = 1 << 16;
+
+// Regions (a higher number wins where regions overlap)
+const REGION_FLASH_LOW: u32 = 0;
+const REGION_FLASH_MID: u32 = 1;
+const REGION_FLASH_HIGH: u32 = 2;
+const REGION_STACK_GUARD: u32 = 7;
+
+// Flash sectors as MPU regions with 8 subregions each: sectors 0-3 (16 KB) and 4 (64 KB) in a
+// 128 KB region, sectors 5-11 in subregions 1-7 of a 1 MB region, and sectors 12-15 (F413ZH) in
+// subregions 0-3 of the next 1 MB
+const FLASH_BASE: u32 = 0x0800_0000;
+const FLASH_HIGH_BASE: u32 = FLASH_BASE + 0x10_0000;
+
+unsafe extern "C" {
+  // cortex-m-rt linker symbol: end of the statics, the lowest address the stack may reach
+  static __sheap: u32;
+}
+
+// Guard region bounds (empty: no guard)
+static GUARD_START: AtomicU32 = AtomicU32::new(0);
+static GUARD_END: AtomicU32 = AtomicU32::new(0);
+
+/// MPU settings for `init`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct MpuConfig {
+  /// Guard size in bytes (a power of two, at least 32), 0 for none
+  pub stack_guard: u32,
+  /// Map the option-byte write-protected flash sectors read-only
+  pub protect_flash: bool,
+}
+
+impl Default for MpuConfig {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl MpuConfig {
+  /// `MPU_STACK_GUARD` guard and read-only write-protected sectors
+  pub const fn new() -> Self {
+    Self {
+      stack_guard: MPU_STACK_GUARD,
+      protect_flash: true,
+    }
+  }
+
+  pub const fn stack_guard(mut self, bytes: u32) -> Self {
+    self.stack_guard = bytes;
+    self
+  }
+
+  pub const fn protect_flash(mut self, protect: bool) -> Self {
+    self.protect_flash = protect;
+    self
+  }
+}
+
+/// Program and enable the MPU (call once)
+pub fn init(config: MpuConfig) {
+  assert!(
+    config.stack_guard == 0 || (config.stack_guard.is_power_of_two() && config.stack_guard >= 32),
+    "MPU stack guard must be a power of two of at least 32 bytes"
+  );
+  cortex_m::interrupt::free(|_| {
+    // SAFETY: the MPU is reprogrammed with interrupts off and re-enabled behind barriers
+    unsafe {
+      let mpu = &*MPU::PTR;
+      mpu.ctrl.write(0);
+      for region in 0..8 {
+        mpu.rnr.write(region);
+        mpu.rasr.write(0);
+      }
+      if config.protect_flash {
+        protect_sectors(mpu, option_bytes::read().write_protected);
+      }
+      if config.stack_guard != 0 {
+        let sheap = (&raw const __sheap) as u32;
+        let start = sheap.next_multiple_of(config.stack_guard);
+        set_region(mpu, REGION_STACK_GUARD, start, config.stack_guard, 0, RASR_AP_NONE | RASR_XN | RASR_SRAM);
+        GUARD_START.store(start, Ordering::Relaxed);
+        GUARD_END.store(start + config.stack_guard, Ordering::Relaxed);
+      }
+      (*SCB::PTR).shcsr.modify(|v| v | SHCSR_MEMFAULTENA);
+      mpu.ctrl.write(CTRL_ENABLE | CTRL_PRIVDEFENA);
+      cortex_m::asm::dsb();
+      cortex_m::asm::isb();
+    }
+  });
+  match stack_guard() {
+    Some(guard) => defmt::info!("MPU: stack guard 0x{:08X}..0x{:08X}", guard.start, guard.end),
+    None => defmt::info!("MPU: enabled without stack guard"),
+  }
+}
+
+/// Address range of the stack guard (None before `init`, or without a guard)
+pub fn stack_guard() -> Option<Range<u32>> {
+  let guard = GUARD_START.load(Ordering::Relaxed)..GUARD_END.load(Ordering::Relaxed);
+  (!guard.is_empty()).then_some(guard)
+}
+
+/// True if an access to `addr` hit the stack guard
+pub fn in_stack_guard(addr: u32) -> bool {
+  stack_guard().is_some_and(|guard| guard.contains(&addr))
+}
+
+// Read-only regions over the write-protected sectors (`protected` bit n = sector n)
+unsafe fn protect_sectors(mpu: &cortex_m::peripheral::mpu::RegisterBlock, protected: u16) {
+  let attrs = RASR_AP_RO | RASR_FLASH;
+  // Subregions 0-3: sectors 0-3; subregions 4-7: sector 4 (64 KB)
+  let mut low = (protected & 0xF) as u8;
+  if protected & (1 << 4) != 0 {
+    low |= 0xF0;
+  }
+  // Subregions 1-7: sectors 5-11
+  let mid = ((protected >> 5) as u8 & 0x7F) << 1;
+  // Subregions 0-3: sectors 12-15
+  let high = (protected >> 12) as u8 & 0xF;
+  for (region, base, size, enabled) in [
+    (REGION_FLASH_LOW, FLASH_BASE, 128 * 1024, low),
+    (REGION_FLASH_MID, FLASH_BASE, 1024 * 1024, mid),
+    (REGION_FLASH_HIGH, FLASH_HIGH_BASE, 1024 * 1024, high),
+  ] {
+    if enabled != 0 {
+      // SAFETY: forwarded from `init`
+      unsafe { set_region(mpu, region, base, size, !enabled, attrs) };
+    }
+  }
+}
+
+// Program region `region` over `size` bytes (power of two, aligned) at `base`, with the
+// subregions set in `disabled` left out
+unsafe fn set_region(mpu: &cortex_m::peripheral::mpu::RegisterBlock, region: u32, base: u32, size: u32, disabled: u8, attrs: u32) {
+  // SAFETY: the caller has the MPU disabled
+  unsafe {
+    mpu.rnr.write(region);
+    mpu.rbar.write(base);
+    // SIZE field: region size is 2^(SIZE + 1)
+    let size_field = size.trailing_zeros() - 1;
+    mpu
+      .rasr
+      .write(attrs | (u32::from(disabled) << RASR_SRD_SHIFT) | (size_field << RASR_SIZE_SHIFT) | RASR_ENABLE);
+  }
+}
@@ -0,0 +1,345 @@
+/// nRF24L01+ 2.4 GHz Radio Driver
+///
+/// This module runs a Nordic nRF24L01+ (or a clone such as the Si24R1) with Enhanced ShockBurst:
+/// hardware CRC, auto-acknowledge and up to 15 retransmissions, with dynamic payloads of up to
+/// 32 bytes. `Nrf24::new` checks the chip answers and applies an `Nrf24Config` (channel, data
+/// rate, power, this node's address and the peer's); `send` waits for the peer's acknowledge and
+/// `receive` for a packet, both on the IRQ line. `comm_link` carries the comm protocol over the
+/// radio: frames written to `Nrf24CommTx` are split into packets, and received packets go through
+/// a `comm::FrameReceiver` (the link is a byte stream, so the usual framing and FCS apply and a
+/// whole `Message` crosses the hop). Registers go over any blocking `embedded-hal` SPI device; CE
+/// is an output pin and IRQ any `embedded-hal-async` pin (`ExtiInput`).
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pipe::Pipe;
+use embassy_time::{Duration, Timer, with_timeout};
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::{Operation, SpiDevice};
+use embedded_hal_async::digital::Wait;
+
+use crate::service::comm::{COMMS_FRAMED_MAX, COMMS_TX_POLL_MS, FrameReceiver};
+
+/// SPI clock for the radio (10 MHz maximum)
+pub const NRF24_SPI_HZ: u32 = 8_000_000;
+/// Largest packet payload
+pub const NRF24_MAX_PAYLOAD: usize = 32;
+/// Highest RF channel (2400 + channel MHz)
+pub const NRF24_MAX_CHANNEL: u8 = 125;
+/// Longest `send` may wait for the acknowledge, retransmissions included
+pub const NRF24_TX_TIMEOUT_MS: u64 = 100;
+
+// Outgoing comm frames waiting for the radio
+const NRF24_TX_PIPE_SIZE: usize = 2 * COMMS_FRAMED_MAX;
+
+mod cmd {
+  pub const R_REGISTER: u8 = 0x00;
+  pub const W_REGISTER: u8 = 0x20;
+  pub const R_RX_PL_WID: u8 = 0x60;
+  pub const R_RX_PAYLOAD: u8 = 0x61;
+  pub const W_TX_PAYLOAD: u8 = 0xA0;
+  pub const FLUSH_TX: u8 = 0xE1;
+  pub const FLUSH_RX: u8 = 0xE2;
+}
+
+mod reg {
+  pub const CONFIG: u8 = 0x00;
+  pub const EN_AA: u8 = 0x01;
+  pub const EN_RXADDR: u8 = 0x02;
+  pub const SETUP_AW: u8 = 0x03;
+  pub const SETUP_RETR: u8 = 0x04;
+  pub const RF_CH: u8 = 0x05;
+  pub const RF_SETUP: u8 = 0x06;
+  pub const STATUS: u8 = 0x07;
+  pub const RX_ADDR_P0: u8 = 0x0A;
+  pub const RX_ADDR_P1: u8 = 0x0B;
+  pub const TX_ADDR: u8 = 0x10;
+  pub const FIFO_STATUS: u8 = 0x17;
+  pub const DYNPD: u8 = 0x1C;
+  pub const FEATURE: u8 = 0x1D;
+}
+
+// CONFIG: 2-byte CRC, powered up; PRIM_RX selects receive
+const CONFIG_BASE: u8 = 0x0E;
+const CONFIG_PRIM_RX: u8 = 0x01;
+// STATUS interrupt flags (write 1 to clear)
+const STATUS_RX_DR: u8 = 0x40;
+const STATUS_TX_DS: u8 = 0x20;
+const STATUS_MAX_RT: u8 = 0x10;
+const FIFO_RX_EMPTY: u8 = 0x01;
+// Retransmit every 1500 us, up to 15 times
+const SETUP_RETR_VALUE: u8 = 0x5F;
+// 5-byte addresses
+const SETUP_AW_5: u8 = 0x03;
+
+static COMM_TX: Pipe<CriticalSectionRawMutex, NRF24_TX_PIPE_SIZE> = Pipe::new();
+static TX_DROPS: AtomicU32 = AtomicU32::new(0);
+static TX_LOST: AtomicU32 = AtomicU32::new(0);
+
+/// Air data rate (lower: longer range)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum DataRate {
+  Kbps250,
+  Mbps1,
+  Mbps2,
+}
+
+/// Output power
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum PowerLevel {
+  Minus18Dbm,
+  Minus12Dbm,
+  Minus6Dbm,
+  ZeroDbm,
+}
+
+/// Radio settings; two nodes talk when they share channel and data rate and each one's `peer`
+/// is the other's `address`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct Nrf24Config {
+  /// RF channel 0..=`NRF24_MAX_CHANNEL` (2400 + channel MHz; above 100 avoids most WiFi)
+  pub channel: u8,
+  pub data_rate: DataRate,
+  pub power: PowerLevel,
+  /// This node's receive address
+  pub address: [u8; 5],
+  /// Address `send` transmits to
+  pub peer: [u8; 5],
+}
+
+impl Default for Nrf24Config {
+  /// Channel 108, 1 Mbps, 0 dBm, address "NODE1" sending to "NODE2"
+  fn default() -> Self {
+    Self {
+      channel: 108,
+      data_rate: DataRate::Mbps1,
+      power: PowerLevel::ZeroDbm,
+      address: *b"NODE1",
+      peer: *b"NODE2",
+    }
+  }
+}
+
+/// Radio errors
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum Nrf24Error {
+  /// SPI transfer or pin access failed
+  Bus,
+  /// The chip did not answer (register read back wrong)
+  NotFound,
+  /// Setting out of range
+  Config,
+  /// Payload longer than `NRF24_MAX_PAYLOAD`
+  TooLong,
+  /// The peer did not acknowledge (all retransmissions used)
+  NoAck,
+  /// Nothing sent or received within the timeout
+  Timeout,
+}
+
+/// nRF24L01+ on SPI with the CE and IRQ lines
+pub struct Nrf24<S: SpiDevice, C: OutputPin, I: Wait> {
+  spi: S,
+  ce: C,
+  irq: I,
+  config: Nrf24Config,
+}
+
+impl<S: SpiDevice, C: OutputPin, I: Wait> Nrf24<S, C, I> {
+  /// Check the radio answers, apply `config` and leave it powered up in standby
+  pub async fn new(spi: S, ce: C, irq: I, config: Nrf24Config) -> Result<Self, Nrf24Error> {
+    let mut radio = Self { spi, ce, irq, config };
+    radio.ce.set_low().map_err(|_| Nrf24Error::Bus)?;
+    // Power-on reset takes up to 100 ms
+    Timer::after_millis(100).await;
+    radio.write_reg(reg::SETUP_AW, SETUP_AW_5)?;
+    if radio.read_reg(reg::SETUP_AW)? != SETUP_AW_5 {
+      return Err(Nrf24Error::NotFound);
+    }
+    radio.configure(&config)?;
+    // Crystal start-up after PWR_UP
+    Timer::after_millis(2).await;
+    defmt::info!("nRF24: channel {}, {}", config.channel, config.data_rate);
+    Ok(radio)
+  }
+
+  /// Apply new settings (leaves the radio in standby)
+  pub fn configure(&mut self, config: &Nrf24Config) -> Result<(), Nrf24Error> {
+    if config.channel > NRF24_MAX_CHANNEL {
+      return Err(Nrf24Error::Config);
+    }
+    self.ce.set_low().map_err(|_| Nrf24Error::Bus)?;
+    let rate = match config.data_rate {
+      DataRate::Kbps250 => 0x20,
+      DataRate::Mbps1 => 0x00,
+      DataRate::Mbps2 => 0x08,
+    };
+    self.write_reg(reg::RF_SETUP, rate | ((config.power as u8) << 1))?;
+    self.write_reg(reg::RF_CH, config.channel)?;
+    self.write_reg(reg::SETUP_RETR, SETUP_RETR_VALUE)?;
+    // Pipe 0 takes the peer's acknowledges, pipe 1 receives on this node's address
+    self.write_reg(reg::EN_AA, 0x03)?;
+    self.write_reg(reg::EN_RXADDR, 0x03)?;
+    self.write_regs(reg::TX_ADDR, &config.peer)?;
+    self.write_regs(reg::RX_ADDR_P0, &config.peer)?;
+    self.write_regs(reg::RX_ADDR_P1, &config.address)?;
+    // Dynamic payload length on both pipes
+    self.write_reg(reg::FEATURE, 0x04)?;
+    self.write_reg(reg::DYNPD, 0x03)?;
+    self.command(cmd::FLUSH_TX)?;
+    self.command(cmd::FLUSH_RX)?;
+    self.clear_irq()?;
+    self.write_reg(reg::CONFIG, CONFIG_BASE)?;
+    self.config = *config;
+    Ok(())
+  }
+
+  /// Settings in use
+  pub fn config(&self) -> &Nrf24Config {
+    &self.config
+  }
+
+  /// Transmit one packet to `config.peer` and wait for its acknowledge (returns in standby)
+  pub async fn send(&mut self, data: &[u8]) -> Result<(), Nrf24Error> {
+    if data.len() > NRF24_MAX_PAYLOAD {
+      return Err(Nrf24Error::TooLong);
+    }
+    self.ce.set_low().map_err(|_| Nrf24Error::Bus)?;
+    self.write_reg(reg::CONFIG, CONFIG_BASE)?;
+    self.clear_irq()?;
+    self.write_cmd(cmd::W_TX_PAYLOAD, data)?;
+    // CE high for at least 10 us starts the transmission; keeping it high until done is fine
+    self.ce.set_high().map_err(|_| Nrf24Error::Bus)?;
+    let done = with_timeout(Duration::from_millis(NRF24_TX_TIMEOUT_MS), self.irq.wait_for_low()).await;
+    self.ce.set_low().map_err(|_| Nrf24Error::Bus)?;
+    let status = self.clear_irq()?;
+    if status & STATUS_TX_DS != 0 {
+      return Ok(());
+    }
+    self.command(cmd::FLUSH_TX)?;
+    match done {
+      Ok(_) if status & STATUS_MAX_RT != 0 => Err(Nrf24Error::NoAck),
+      Ok(Err(_)) => Err(Nrf24Error::Bus),
+      _ => Err(Nrf24Error::Timeout),
+    }
+  }
+
+  /// Switch to receive mode (`receive` does this too; the radio listens until the next `send`)
+  pub fn listen(&mut self) -> Result<(), Nrf24Error> {
+    self.write_reg(reg::CONFIG, CONFIG_BASE | CONFIG_PRIM_RX)?;
+    self.ce.set_high().map_err(|_| Nrf24Error::Bus)
+  }
+
+  /// Wait for a packet and copy it into `buf`; returns its length
+  pub async fn receive(&mut self, buf: &mut [u8; NRF24_MAX_PAYLOAD]) -> Result<usize, Nrf24Error> {
+    self.listen()?;
+    while self.read_reg(reg::FIFO_STATUS)? & FIFO_RX_EMPTY != 0 {
+      self.irq.wait_for_low().await.map_err(|_| Nrf24Error::Bus)?;
+      self.write_reg(reg::STATUS, STATUS_RX_DR)?;
+    }
+    let mut width = [0u8; 1];
+    self.read_cmd(cmd::R_RX_PL_WID, &mut width)?;
+    let len = width[0] as usize;
+    if len == 0 || len > NRF24_MAX_PAYLOAD {
+      // Corrupt length: the datasheet says to discard the packet
+      self.command(cmd::FLUSH_RX)?;
+      return Ok(0);
+    }
+    self.read_cmd(cmd::R_RX_PAYLOAD, &mut buf[..len])?;
+    self.write_reg(reg::STATUS, STATUS_RX_DR)?;
+    Ok(len)
+  }
+
+  /// Power down (900 nA; `send`/`receive` need `configure` to power up again)
+  pub fn power_down(&mut self) -> Result<(), Nrf24Error> {
+    self.ce.set_low().map_err(|_| Nrf24Error::Bus)?;
+    self.write_reg(reg::CONFIG, CONFIG_BASE & !0x02)
+  }
+
+  /// Carry the comm protocol over the radio to `config.peer`: listen, feed received packets to
+  /// a `comm::FrameReceiver` and send what was written to `Nrf24CommTx` (run from an application
+  /// task; the peer runs the same loop)
+  pub async fn comm_link(&mut self) -> ! {
+    let mut receiver = FrameReceiver::new();
+    let mut packet = [0u8; NRF24_MAX_PAYLOAD];
+    loop {
+      match with_timeout(Duration::from_millis(COMMS_TX_POLL_MS), self.receive(&mut packet)).await {
+        Ok(Ok(n)) => receiver.push(&packet[..n]),
+        Ok(Err(e)) => defmt::warn!("nRF24: receive failed: {}", e),
+        Err(_) => {}
+      }
+      while let Ok(n) = COMM_TX.try_read(&mut packet) {
+        if self.send(&packet[..n]).await.is_err() {
+          // The rest of the frame is useless to the peer now; its FCS check drops the remainder
+          TX_LOST.fetch_add(1, Ordering::Relaxed);
+        }
+      }
+    }
+  }
+
+  fn clear_irq(&mut self) -> Result<u8, Nrf24Error> {
+    let status = self.read_reg(reg::STATUS)?;
+    self.write_reg(reg::STATUS, STATUS_RX_DR | STATUS_TX_DS | STATUS_MAX_RT)?;
+    Ok(status)
+  }
+
+  fn command(&mut self, command: u8) -> Result<(), Nrf24Error> {
+    self.spi.write(&[command]).map_err(|_| Nrf24Error::Bus)
+  }
+
+  fn read_reg(&mut self, addr: u8) -> Result<u8, Nrf24Error> {
+    let mut value = [0u8; 1];
+    self.read_cmd(cmd::R_REGISTER | addr, &mut value)?;
+    Ok(value[0])
+  }
+
+  fn write_reg(&mut self, addr: u8, value: u8) -> Result<(), Nrf24Error> {
+    self.write_regs(addr, &[value])
+  }
+
+  fn write_regs(&mut self, addr: u8, data: &[u8]) -> Result<(), Nrf24Error> {
+    self.write_cmd(cmd::W_REGISTER | addr, data)
+  }
+
+  fn read_cmd(&mut self, command: u8, buf: &mut [u8]) -> Result<(), Nrf24Error> {
+    self.spi.transaction(&mut [Operation::Write(&[command]), Operation::Read(buf)]).map_err(|_| Nrf24Error::Bus)
+  }
+
+  fn write_cmd(&mut self, command: u8, data: &[u8]) -> Result<(), Nrf24Error> {
+    self
+      .spi
+      .transaction(&mut [Operation::Write(&[command]), Operation::Write(data)])
+      .map_err(|_| Nrf24Error::Bus)
+  }
+}
+
+/// Frames dropped because the radio link was not draining `Nrf24CommTx`
+pub fn tx_drop_count() -> u32 {
+  TX_DROPS.load(Ordering::Relaxed)
+}
+
+/// Packets the peer never acknowledged
+pub fn tx_lost_count() -> u32 {
+  TX_LOST.load(Ordering::Relaxed)
+}
+
+/// Writer onto the radio comm link (frames are dropped whole while it is full)
+pub struct Nrf24CommTx;
+
+impl embedded_io::ErrorType for Nrf24CommTx {
+  type Error = core::convert::Infallible;
+}
+
+impl embedded_io::Write for Nrf24CommTx {
+  fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+    // A frame cut short would desync the peer, so it goes in whole or not at all
+    if COMM_TX.free_capacity() < buf.len() {
+      TX_DROPS.fetch_add(1, Ordering::Relaxed);
+      return Ok(buf.len());
+    }
+    Ok(COMM_TX.try_write(buf).unwrap_or(buf.len()))
+  }
+
+  fn flush(&mut self) -> Result<(), Self::Error> {
+    Ok(())
+  }
+}
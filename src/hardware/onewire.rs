@@ -0,0 +1,229 @@
+/// 1-Wire Bus over a Half-Duplex UART, with DS18B20 Temperature Sensors
+///
+/// The UART trick: the USART's TX pin runs open-drain in half-duplex mode, joined to the bus (4.7k
+/// pull-up to 3.3 V), and every byte sent is read back. At 9600 baud a 0xF0 byte is a ~520 us low
+/// reset pulse, and any device answering with its presence pulse corrupts the byte read back. At
+/// 115200 baud one byte is one time slot: 0xFF writes a 1 (a short low start bit), 0x00 writes a 0
+/// (low for ~78 us), and 0xFF reads a bit, which is 1 if it comes back unchanged. The hardware does
+/// the timing, so interrupts and other tasks cannot stretch a slot.
+///
+/// `OneWire` has the bus primitives (reset, bits, bytes, ROM search with the Maxim CRC-8);
+/// `Ds18b20` starts conversions and reads temperatures in 0.01 °C like `hardware::env_sensor`.
+/// Use `BoardConfig::init_onewire()` and `common::tasks::ds18b20_task` for a logging loop.
+use embassy_stm32::mode::Blocking;
+use embassy_stm32::usart::{self, Uart};
+use embassy_time::Timer;
+use heapless::Vec;
+
+/// Reset/presence baud rate
+const RESET_BAUD: u32 = 9_600;
+/// Time slot baud rate
+const SLOT_BAUD: u32 = 115_200;
+
+const CMD_SEARCH_ROM: u8 = 0xF0;
+const CMD_MATCH_ROM: u8 = 0x55;
+const CMD_SKIP_ROM: u8 = 0xCC;
+const CMD_CONVERT_T: u8 = 0x44;
+const CMD_READ_SCRATCHPAD: u8 = 0xBE;
+
+/// DS18B20 family code (first ROM byte)
+pub const DS18B20_FAMILY: u8 = 0x28;
+/// Worst-case DS18B20 conversion time at 12-bit resolution
+pub const DS18B20_CONVERSION_MS: u64 = 750;
+/// Devices `search` collects at most
+pub const ONEWIRE_MAX_DEVICES: usize = 8;
+
+/// 1-Wire errors
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum OneWireError {
+  /// No presence pulse after a reset
+  NoDevice,
+  /// Data failed its CRC-8 (bus noise or a device dropping off)
+  Crc,
+  /// UART error
+  Uart(usart::Error),
+}
+
+impl From<usart::Error> for OneWireError {
+  fn from(e: usart::Error) -> Self {
+    OneWireError::Uart(e)
+  }
+}
+
+/// 64-bit device ROM code: family, 48-bit serial, CRC (byte 0 first)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct Rom(pub [u8; 8]);
+
+impl Rom {
+  pub fn family(&self) -> u8 {
+    self.0[0]
+  }
+}
+
+/// Dallas/Maxim CRC-8 (polynomial x^8 + x^5 + x^4 + 1, reflected); 0 over data plus its CRC
+pub fn crc8(data: &[u8]) -> u8 {
+  data.iter().fold(0u8, |mut crc, &byte| {
+    let mut byte = byte;
+    for _ in 0..8 {
+      let mix = (crc ^ byte) & 1;
+      crc >>= 1;
+      if mix != 0 {
+        crc ^= 0x8C;
+      }
+      byte >>= 1;
+    }
+    crc
+  })
+}
+
+/// 1-Wire master on a half-duplex UART (open drain, reading back what it sends)
+pub struct OneWire<'d> {
+  uart: Uart<'d, Blocking>,
+}
+
+impl<'d> OneWire<'d> {
+  /// Take a UART made with `Uart::new_blocking_half_duplex` (open drain, `HalfDuplexReadback::Readback`)
+  pub fn new(uart: Uart<'d, Blocking>) -> Self {
+    Self { uart }
+  }
+
+  // Send one byte and return what came back from the bus
+  fn exchange(&mut self, byte: u8) -> Result<u8, OneWireError> {
+    let mut echo = [0u8];
+    self.uart.blocking_write(&[byte])?;
+    self.uart.blocking_read(&mut echo)?;
+    Ok(echo[0])
+  }
+
+  /// Reset pulse; true if at least one device answered with a presence pulse
+  pub fn reset(&mut self) -> Result<bool, OneWireError> {
+    let _ = self.uart.set_baudrate(RESET_BAUD);
+    let echo = self.exchange(0xF0);
+    let _ = self.uart.set_baudrate(SLOT_BAUD);
+    Ok(echo? != 0xF0)
+  }
+
+  /// One time slot writing `bit` (a 1 is also a read slot); returns the bit seen on the bus
+  pub fn bit(&mut self, bit: bool) -> Result<bool, OneWireError> {
+    Ok(self.exchange(if bit { 0xFF } else { 0x00 })? == 0xFF)
+  }
+
+  pub fn write_byte(&mut self, byte: u8) -> Result<(), OneWireError> {
+    for i in 0..8 {
+      self.bit(byte >> i & 1 != 0)?;
+    }
+    Ok(())
+  }
+
+  pub fn read_byte(&mut self) -> Result<u8, OneWireError> {
+    let mut byte = 0;
+    for i in 0..8 {
+      if self.bit(true)? {
+        byte |= 1 << i;
+      }
+    }
+    Ok(byte)
+  }
+
+  /// Reset and address one device (`Some`) or all of them (`None`, skip ROM)
+  pub fn select(&mut self, rom: Option<&Rom>) -> Result<(), OneWireError> {
+    if !self.reset()? {
+      return Err(OneWireError::NoDevice);
+    }
+    match rom {
+      Some(rom) => {
+        self.write_byte(CMD_MATCH_ROM)?;
+        rom.0.iter().try_for_each(|&b| self.write_byte(b))
+      }
+      None => self.write_byte(CMD_SKIP_ROM),
+    }
+  }
+
+  /// ROM codes of the devices on the bus (Maxim search algorithm), up to `ONEWIRE_MAX_DEVICES`
+  pub fn search(&mut self) -> Result<Vec<Rom, ONEWIRE_MAX_DEVICES>, OneWireError> {
+    let mut found = Vec::new();
+    let mut rom = [0u8; 8];
+    // Bit position (1-based) of the last discrepancy where 0 was taken; 0 when none is left
+    let mut last_discrepancy = 0;
+    loop {
+      if !self.reset()? {
+        return Ok(found);
+      }
+      self.write_byte(CMD_SEARCH_ROM)?;
+      let mut discrepancy = 0;
+      for position in 1..=64 {
+        let (byte, mask) = ((position - 1) / 8, 1u8 << ((position - 1) % 8));
+        let bit = self.bit(true)?;
+        let complement = self.bit(true)?;
+        let take = match (bit, complement) {
+          // No device answered: the bus emptied mid-search
+          (true, true) => return Ok(found),
+          // Devices disagree: follow the earlier choice, then 1 at the last discrepancy
+          (false, false) => {
+            let take = if position < last_discrepancy {
+              rom[byte] & mask != 0
+            } else {
+              position == last_discrepancy
+            };
+            if !take {
+              discrepancy = position;
+            }
+            take
+          }
+          // All remaining devices have this bit
+          (bit, _) => bit,
+        };
+        if take {
+          rom[byte] |= mask;
+        } else {
+          rom[byte] &= !mask;
+        }
+        self.bit(take)?;
+      }
+      if crc8(&rom) != 0 {
+        return Err(OneWireError::Crc);
+      }
+      if found.push(Rom(rom)).is_err() {
+        return Ok(found);
+      }
+      last_discrepancy = discrepancy;
+      if last_discrepancy == 0 {
+        return Ok(found);
+      }
+    }
+  }
+}
+
+/// DS18B20 temperature sensors on a `OneWire` bus
+pub struct Ds18b20;
+
+impl Ds18b20 {
+  /// Start a conversion on one sensor, or all of them with `None`
+  pub fn start_conversion(bus: &mut OneWire<'_>, rom: Option<&Rom>) -> Result<(), OneWireError> {
+    bus.select(rom)?;
+    bus.write_byte(CMD_CONVERT_T)
+  }
+
+  /// Temperature of the last conversion in 0.01 °C (`None` addresses the only sensor on the bus)
+  pub fn read_centi_c(bus: &mut OneWire<'_>, rom: Option<&Rom>) -> Result<i32, OneWireError> {
+    bus.select(rom)?;
+    bus.write_byte(CMD_READ_SCRATCHPAD)?;
+    let mut scratchpad = [0u8; 9];
+    for byte in scratchpad.iter_mut() {
+      *byte = bus.read_byte()?;
+    }
+    if crc8(&scratchpad) != 0 {
+      return Err(OneWireError::Crc);
+    }
+    // 1/16 °C steps, two's complement
+    let raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]) as i32;
+    Ok(raw * 100 / 16)
+  }
+
+  /// Convert on every sensor at once, wait for it, and read `rom`
+  pub async fn measure(bus: &mut OneWire<'_>, rom: Option<&Rom>) -> Result<i32, OneWireError> {
+    Self::start_conversion(bus, None)?;
+    Timer::after_millis(DS18B20_CONVERSION_MS).await;
+    Self::read_centi_c(bus, rom)
+  }
+}
@@ -0,0 +1,211 @@
+/// Flash option bytes: readout protection (RDP), brown-out reset level (BOR) and sector write protection
+///
+/// Reads the active option bytes from FLASH_OPTCR (and FLASH_OPTCR1 for sectors 12+) and programs
+/// them through the OPTKEYR unlock sequence. Programming is deliberately narrow:
+/// - RDP can only be raised from Level 0 to Level 1 (debugger and bootloader can no longer read
+///   the flash); going back to Level 0 mass-erases the chip and Level 2 disables debug for good,
+///   so both are left to external tools;
+/// - BOR level and write protection can be changed freely.
+/// New option bytes are written at once but RDP only takes effect after a power cycle.
+/// `Command::Protection` returns `OptionBytes::to_bytes` so production tooling can check a unit.
+use crate::board::BoardConfig;
+use crate::hardware::flash;
+
+const FLASH_BASE: u32 = 0x40023C00;
+const FLASH_OPTKEYR: *mut u32 = (FLASH_BASE + 0x08) as *mut u32;
+const FLASH_SR: *const u32 = (FLASH_BASE + 0x0C) as *const u32;
+const FLASH_OPTCR: *mut u32 = (FLASH_BASE + 0x14) as *mut u32;
+const FLASH_OPTCR1: *mut u32 = (FLASH_BASE + 0x18) as *mut u32;
+
+const OPT_KEY1: u32 = 0x0819_2A3B;
+const OPT_KEY2: u32 = 0x4C5D_6E7F;
+
+const FLASH_SR_BSY: u32 = 1 << 16;
+const OPTCR_OPTLOCK: u32 = 1 << 0;
+const OPTCR_OPTSTRT: u32 = 1 << 1;
+const OPTCR_BOR_SHIFT: u32 = 2;
+const OPTCR_RDP_SHIFT: u32 = 8;
+// nWRP: one bit per sector, 0 = protected (sectors 0-11 in OPTCR, 12+ in OPTCR1, both from bit 16)
+const NWRP_SHIFT: u32 = 16;
+const NWRP_SECTORS: u8 = 12;
+
+const RDP_LEVEL0: u8 = 0xAA;
+const RDP_LEVEL2: u8 = 0xCC;
+
+/// Readout protection level
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum RdpLevel {
+  /// No protection
+  Level0 = 0,
+  /// Flash not readable by the debugger or the system bootloader (reversible with a mass erase)
+  Level1 = 1,
+  /// Debug permanently disabled (irreversible)
+  Level2 = 2,
+}
+
+/// Brown-out reset threshold (BOR_LEV)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum BorLevel {
+  /// Reset only at power-on / power-down (~1.8 V)
+  Off = 0,
+  /// ~2.1 V
+  Level1 = 1,
+  /// ~2.4 V
+  Level2 = 2,
+  /// ~2.7 V
+  Level3 = 3,
+}
+
+impl BorLevel {
+  // BOR_LEV field value (00 = level 3 ... 11 = off)
+  const fn bits(self) -> u32 {
+    3 - self as u32
+  }
+
+  const fn from_bits(bits: u32) -> Self {
+    match bits & 0b11 {
+      0 => BorLevel::Level3,
+      1 => BorLevel::Level2,
+      2 => BorLevel::Level1,
+      _ => BorLevel::Off,
+    }
+  }
+}
+
+/// Why option bytes were not changed
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum OptionError {
+  /// RDP change this module does not make (lowering it, or Level 2)
+  Refused,
+  /// Sector outside the flash
+  BadSector,
+  /// Option bytes stayed locked
+  Locked,
+}
+
+/// Active option bytes
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct OptionBytes {
+  pub rdp: RdpLevel,
+  pub bor: BorLevel,
+  /// Bit n set: sector n is write protected
+  pub write_protected: u16,
+}
+
+impl OptionBytes {
+  /// Encoded size of a `Command::Protection` reply
+  pub const LEN: usize = 6;
+
+  /// Encode as the `Command::Protection` reply: RDP level, BOR level (0 = off), write-protected
+  /// sectors (u16 LE), storage sector, and whether it is write protected
+  pub fn to_bytes(&self) -> [u8; Self::LEN] {
+    let storage = storage_sector();
+    let [lo, hi] = self.write_protected.to_le_bytes();
+    [self.rdp as u8, self.bor as u8, lo, hi, storage, ((self.write_protected >> storage) & 1) as u8]
+  }
+}
+
+/// Current option bytes
+pub fn read() -> OptionBytes {
+  // SAFETY: read-only access to the option control registers
+  let (optcr, optcr1) = unsafe { (FLASH_OPTCR.read_volatile(), read_optcr1()) };
+  let rdp = match (optcr >> OPTCR_RDP_SHIFT) as u8 {
+    RDP_LEVEL0 => RdpLevel::Level0,
+    RDP_LEVEL2 => RdpLevel::Level2,
+    _ => RdpLevel::Level1,
+  };
+  let mut write_protected = 0u16;
+  for sector in 0..sector_count() {
+    let (reg, bit) = if sector < NWRP_SECTORS { (optcr, sector) } else { (optcr1, sector - NWRP_SECTORS) };
+    if reg & (1 << (NWRP_SHIFT + bit as u32)) == 0 {
+      write_protected |= 1 << sector;
+    }
+  }
+  OptionBytes {
+    rdp,
+    bor: BorLevel::from_bits(optcr >> OPTCR_BOR_SHIFT),
+    write_protected,
+  }
+}
+
+/// Raise readout protection to Level 1 (no-op if already there). Takes effect after a power cycle.
+pub fn set_rdp(level: RdpLevel) -> Result<(), OptionError> {
+  match (read().rdp, level) {
+    (RdpLevel::Level1, RdpLevel::Level1) => Ok(()),
+    (RdpLevel::Level0, RdpLevel::Level1) => {
+      defmt::warn!("Option bytes: setting RDP Level 1");
+      // Any value other than 0xAA / 0xCC is Level 1
+      program(|optcr, optcr1| ((optcr & !(0xFF << OPTCR_RDP_SHIFT)) | 0x55 << OPTCR_RDP_SHIFT, optcr1))
+    }
+    _ => Err(OptionError::Refused),
+  }
+}
+
+/// Set the brown-out reset threshold
+pub fn set_bor(level: BorLevel) -> Result<(), OptionError> {
+  program(|optcr, optcr1| ((optcr & !(0b11 << OPTCR_BOR_SHIFT)) | level.bits() << OPTCR_BOR_SHIFT, optcr1))
+}
+
+/// Write protect (or unprotect) one flash sector
+pub fn set_write_protect(sector: u8, protect: bool) -> Result<(), OptionError> {
+  if sector >= sector_count() {
+    return Err(OptionError::BadSector);
+  }
+  let (in_optcr1, bit) = if sector < NWRP_SECTORS { (false, sector) } else { (true, sector - NWRP_SECTORS) };
+  let mask = 1 << (NWRP_SHIFT + bit as u32);
+  let update = |reg: u32| if protect { reg & !mask } else { reg | mask };
+  program(|optcr, optcr1| if in_optcr1 { (optcr, update(optcr1)) } else { (update(optcr), optcr1) })
+}
+
+/// Write protect (or unprotect) the storage sector (`flash::start()`); it can not be erased or
+/// written while protected, so unprotect before updating stored data
+pub fn protect_storage(protect: bool) -> Result<(), OptionError> {
+  set_write_protect(storage_sector(), protect)
+}
+
+/// Sector holding the storage region
+pub fn storage_sector() -> u8 {
+  flash::get_sector_number(flash::start()).unwrap_or(0) as u8
+}
+
+fn sector_count() -> u8 {
+  flash::get_sector_number(0x0800_0000 + BoardConfig::FLASH_SIZE_KB * 1024 - 1).map_or(0, |last| last as u8 + 1)
+}
+
+// Unlock the option bytes, apply `f` to (OPTCR, OPTCR1), start programming and lock again
+fn program(f: impl FnOnce(u32, u32) -> (u32, u32)) -> Result<(), OptionError> {
+  // SAFETY: option registers are only written here; unlock, start and relock as in the reference manual
+  unsafe {
+    wait_ready();
+    if FLASH_OPTCR.read_volatile() & OPTCR_OPTLOCK != 0 {
+      FLASH_OPTKEYR.write_volatile(OPT_KEY1);
+      FLASH_OPTKEYR.write_volatile(OPT_KEY2);
+    }
+    if FLASH_OPTCR.read_volatile() & OPTCR_OPTLOCK != 0 {
+      return Err(OptionError::Locked);
+    }
+    let (optcr, optcr1) = f(FLASH_OPTCR.read_volatile(), read_optcr1());
+    if sector_count() > NWRP_SECTORS {
+      FLASH_OPTCR1.write_volatile(optcr1);
+    }
+    FLASH_OPTCR.write_volatile(optcr & !(OPTCR_OPTLOCK | OPTCR_OPTSTRT));
+    FLASH_OPTCR.write_volatile(FLASH_OPTCR.read_volatile() | OPTCR_OPTSTRT);
+    wait_ready();
+    FLASH_OPTCR.write_volatile(FLASH_OPTCR.read_volatile() | OPTCR_OPTLOCK);
+  }
+  defmt::info!("Option bytes: {}", read());
+  Ok(())
+}
+
+// OPTCR1 only exists on parts with more than 12 sectors (all unprotected otherwise)
+unsafe fn read_optcr1() -> u32 {
+  if sector_count() > NWRP_SECTORS {
+    unsafe { FLASH_OPTCR1.read_volatile() }
+  } else {
+    u32::MAX
+  }
+}
+
+unsafe fn wait_ready() {
+  unsafe { while FLASH_SR.read_volatile() & FLASH_SR_BSY != 0 {} }
+}
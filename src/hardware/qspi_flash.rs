@@ -0,0 +1,227 @@
+/// QSPI NOR Flash Hardware Abstraction Layer
+///
+/// This module drives common serial NOR flashes (Winbond W25Qxx, Macronix MX25Lxx) on the
+/// QUADSPI peripheral: JEDEC identification, 4 KB sector erase, page programming and quad-output
+/// fast reads, plus memory-mapped read mode (the whole chip appears at `QSPI_MAPPED_BASE`).
+/// `QspiFlash` implements the `embedded-storage` NOR flash traits, so it can back the same layers
+/// as the internal storage sector (e.g. `service::journal::Journal`) with megabytes of room.
+use embassy_stm32::mode::Blocking;
+use embassy_stm32::qspi::enums::{DummyCycles, QspiWidth};
+use embassy_stm32::qspi::{Instance, Qspi, TransferConfig};
+use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+
+/// Address of the chip in memory-mapped mode
+pub const QSPI_MAPPED_BASE: u32 = 0x9000_0000;
+/// Program page size
+pub const QSPI_PAGE_SIZE: usize = 256;
+/// Smallest erasable unit (sector)
+pub const QSPI_SECTOR_SIZE: usize = 4096;
+
+const CMD_WRITE_ENABLE: u8 = 0x06;
+const CMD_READ_STATUS1: u8 = 0x05;
+const CMD_WRITE_STATUS1: u8 = 0x01;
+const CMD_READ_STATUS2: u8 = 0x35;
+const CMD_WRITE_STATUS2: u8 = 0x31;
+const CMD_READ_JEDEC_ID: u8 = 0x9F;
+const CMD_PAGE_PROGRAM: u8 = 0x02;
+const CMD_SECTOR_ERASE: u8 = 0x20;
+const CMD_FAST_READ_QUAD_OUT: u8 = 0x6B; // 1-1-4 with 8 dummy cycles, same on both vendors
+const STATUS1_BUSY: u8 = 0x01;
+
+const MANUFACTURER_WINBOND: u8 = 0xEF;
+const MANUFACTURER_MACRONIX: u8 = 0xC2;
+
+/// QSPI flash errors
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum QspiFlashError {
+  /// JEDEC id unreadable or not a supported vendor
+  UnknownDevice,
+  /// Access beyond the end of the chip
+  OutOfBounds,
+  /// Erase not aligned to `QSPI_SECTOR_SIZE`
+  NotAligned,
+}
+
+impl NorFlashError for QspiFlashError {
+  fn kind(&self) -> NorFlashErrorKind {
+    match self {
+      QspiFlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+      QspiFlashError::NotAligned => NorFlashErrorKind::NotAligned,
+      QspiFlashError::UnknownDevice => NorFlashErrorKind::Other,
+    }
+  }
+}
+
+/// W25Q/MX25 NOR flash on QUADSPI bank 1 (24-bit addressing, up to 16 MB)
+pub struct QspiFlash<T: Instance> {
+  qspi: Qspi<'static, T, Blocking>,
+  jedec_id: [u8; 3],
+  capacity: usize,
+}
+
+impl<T: Instance> QspiFlash<T> {
+  /// Identify the chip and enable its quad data lines
+  pub fn new(qspi: Qspi<'static, T, Blocking>) -> Result<Self, QspiFlashError> {
+    let mut flash = Self {
+      qspi,
+      jedec_id: [0; 3],
+      capacity: 0,
+    };
+    let mut id = [0u8; 3];
+    flash.qspi.blocking_read(&mut id, Self::command(CMD_READ_JEDEC_ID));
+    // Capacity code is log2 of the size in bytes (0x18 = 16 MB)
+    if !matches!(id[0], MANUFACTURER_WINBOND | MANUFACTURER_MACRONIX) || !(0x10..=0x18).contains(&id[2]) {
+      defmt::warn!("QSPI flash: unsupported JEDEC id {=[u8]:02X}", id);
+      return Err(QspiFlashError::UnknownDevice);
+    }
+    flash.jedec_id = id;
+    flash.capacity = 1 << id[2];
+    flash.enable_quad();
+    defmt::info!("QSPI flash: JEDEC {=[u8]:02X}, {} KB", id, flash.capacity / 1024);
+    Ok(flash)
+  }
+
+  /// Manufacturer, memory type and capacity code
+  pub fn jedec_id(&self) -> [u8; 3] {
+    self.jedec_id
+  }
+
+  /// Switch to memory-mapped mode: the chip is readable as plain memory from then on
+  /// (consumes the driver, since indirect erase/program is not possible while mapped)
+  pub fn into_memory_mapped(mut self) -> &'static [u8] {
+    self.qspi.enable_memory_map(&Self::quad_read(0));
+    // SAFETY: the QUADSPI controller now decodes QSPI_MAPPED_BASE..+capacity; the driver is
+    // consumed, so nothing can leave memory-mapped mode while the slice is alive
+    unsafe { core::slice::from_raw_parts(QSPI_MAPPED_BASE as *const u8, self.capacity) }
+  }
+
+  // Quad enable lives in different status registers on the two vendors
+  fn enable_quad(&mut self) {
+    if self.jedec_id[0] == MANUFACTURER_WINBOND {
+      let status2 = self.read_register(CMD_READ_STATUS2);
+      if status2 & 0x02 == 0 {
+        self.write_register(CMD_WRITE_STATUS2, status2 | 0x02);
+      }
+    } else {
+      let status1 = self.read_register(CMD_READ_STATUS1);
+      if status1 & 0x40 == 0 {
+        self.write_register(CMD_WRITE_STATUS1, status1 | 0x40);
+      }
+    }
+  }
+
+  fn command(instruction: u8) -> TransferConfig {
+    TransferConfig {
+      iwidth: QspiWidth::SING,
+      awidth: QspiWidth::NONE,
+      dwidth: QspiWidth::SING,
+      instruction,
+      address: None,
+      dummy: DummyCycles::_0,
+    }
+  }
+
+  fn addressed(instruction: u8, address: u32, dwidth: QspiWidth) -> TransferConfig {
+    TransferConfig {
+      awidth: QspiWidth::SING,
+      dwidth,
+      address: Some(address),
+      ..Self::command(instruction)
+    }
+  }
+
+  fn quad_read(address: u32) -> TransferConfig {
+    TransferConfig {
+      dummy: DummyCycles::_8,
+      ..Self::addressed(CMD_FAST_READ_QUAD_OUT, address, QspiWidth::QUAD)
+    }
+  }
+
+  fn read_register(&mut self, instruction: u8) -> u8 {
+    let mut value = [0u8; 1];
+    self.qspi.blocking_read(&mut value, Self::command(instruction));
+    value[0]
+  }
+
+  fn write_register(&mut self, instruction: u8, value: u8) {
+    self.write_enable();
+    self.qspi.blocking_write(&[value], Self::command(instruction));
+    self.wait_ready();
+  }
+
+  fn write_enable(&mut self) {
+    self.qspi.blocking_command(TransferConfig {
+      dwidth: QspiWidth::NONE,
+      ..Self::command(CMD_WRITE_ENABLE)
+    });
+  }
+
+  fn wait_ready(&mut self) {
+    while self.read_register(CMD_READ_STATUS1) & STATUS1_BUSY != 0 {}
+  }
+
+  fn check(&self, offset: u32, len: usize) -> Result<(), QspiFlashError> {
+    match (offset as usize).checked_add(len) {
+      Some(end) if end <= self.capacity => Ok(()),
+      _ => Err(QspiFlashError::OutOfBounds),
+    }
+  }
+}
+
+impl<T: Instance> ErrorType for QspiFlash<T> {
+  type Error = QspiFlashError;
+}
+
+impl<T: Instance> ReadNorFlash for QspiFlash<T> {
+  const READ_SIZE: usize = 1;
+
+  fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+    self.check(offset, bytes.len())?;
+    if !bytes.is_empty() {
+      self.qspi.blocking_read(bytes, Self::quad_read(offset));
+    }
+    Ok(())
+  }
+
+  fn capacity(&self) -> usize {
+    self.capacity
+  }
+}
+
+impl<T: Instance> NorFlash for QspiFlash<T> {
+  const WRITE_SIZE: usize = 1;
+  const ERASE_SIZE: usize = QSPI_SECTOR_SIZE;
+
+  fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+    if to < from {
+      return Err(QspiFlashError::OutOfBounds);
+    }
+    self.check(from, (to - from) as usize)?;
+    if from as usize % QSPI_SECTOR_SIZE != 0 || to as usize % QSPI_SECTOR_SIZE != 0 {
+      return Err(QspiFlashError::NotAligned);
+    }
+    for sector in (from..to).step_by(QSPI_SECTOR_SIZE) {
+      self.write_enable();
+      self.qspi.blocking_command(Self::addressed(CMD_SECTOR_ERASE, sector, QspiWidth::NONE));
+      self.wait_ready();
+    }
+    Ok(())
+  }
+
+  fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+    self.check(offset, bytes.len())?;
+    let mut address = offset;
+    let mut rest = bytes;
+    while !rest.is_empty() {
+      // A page program wraps within its 256-byte page, so never cross a page boundary
+      let room = QSPI_PAGE_SIZE - address as usize % QSPI_PAGE_SIZE;
+      let (chunk, tail) = rest.split_at(room.min(rest.len()));
+      self.write_enable();
+      self.qspi.blocking_write(chunk, Self::addressed(CMD_PAGE_PROGRAM, address, QspiWidth::SING));
+      self.wait_ready();
+      address += chunk.len() as u32;
+      rest = tail;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,63 @@
+/// Hardware RNG Abstraction Layer (F413ZH; boards with the `has_rng` capability)
+///
+/// This module wraps the STM32 true random number generator (analog noise source, checked for
+/// seed and clock errors by the peripheral). `HwRng` fills buffers asynchronously or blocking,
+/// implements `rand_core` 0.6 `RngCore` + `CryptoRng` for crypto crates, and `RandomSource` for
+/// the jitter/backoff helpers; `seed_shared` reseeds `common::random` so message ids and backoff
+/// jitter stop depending on the (predictable) unique ID. The F446RE has no RNG unit.
+use embassy_stm32::rng::{Error, Instance, Rng};
+use rand_core::{CryptoRng, RngCore};
+
+use crate::common::random::{self, RandomSource};
+
+/// True random numbers from the RNG unit
+pub struct HwRng<T: Instance> {
+  rng: Rng<'static, T>,
+}
+
+impl<T: Instance> HwRng<T> {
+  pub fn new(rng: Rng<'static, T>) -> Self {
+    Self { rng }
+  }
+
+  /// Fill `buf` with random bytes, waiting on the RNG interrupt (Err on a seed or clock error)
+  pub async fn fill_bytes(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+    self.rng.async_fill_bytes(buf).await
+  }
+
+  /// Fill `buf` with random bytes, busy-waiting on the unit (recovers from seed errors)
+  pub fn blocking_fill_bytes(&mut self, buf: &mut [u8]) {
+    RngCore::fill_bytes(&mut self.rng, buf);
+  }
+
+  /// Reseed the shared `common::random` generator from true randomness
+  pub fn seed_shared(&mut self) {
+    random::seed(RngCore::next_u32(&mut self.rng));
+  }
+}
+
+impl<T: Instance> RngCore for HwRng<T> {
+  fn next_u32(&mut self) -> u32 {
+    self.rng.next_u32()
+  }
+
+  fn next_u64(&mut self) -> u64 {
+    self.rng.next_u64()
+  }
+
+  fn fill_bytes(&mut self, dest: &mut [u8]) {
+    self.rng.fill_bytes(dest)
+  }
+
+  fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+    self.rng.try_fill_bytes(dest)
+  }
+}
+
+impl<T: Instance> CryptoRng for HwRng<T> {}
+
+impl<T: Instance> RandomSource for HwRng<T> {
+  fn next_u32(&mut self) -> u32 {
+    RngCore::next_u32(&mut self.rng)
+  }
+}
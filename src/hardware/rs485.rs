@@ -0,0 +1,174 @@
+/// RS-485 half-duplex transmit with driver-enable (DE/RE) control
+///
+/// An RS-485 transceiver (MAX485, SN65HVD7x, ...) only drives the bus while DE is high; with /RE
+/// tied to DE its receiver is off for that time, so the UART does not hear its own frames. The
+/// STM32F4 USARTs have no hardware DE output (the DEM bit came with the F0/F7 USART), so `Rs485Tx`
+/// drives DE from a GPIO: it raises it `pre_delay_us` before the first start bit, writes, waits
+/// for the last stop bit to leave the shift register (USART TC) and releases the bus
+/// `post_delay_us` later.
+///
+/// Collision avoidance for buses with more than one node that may talk first: before taking the
+/// bus, a transmission waits until nothing was received for `idle_gap_us` (the aux serial RX task
+/// reports received bytes through `note_rx`; other receivers call it themselves) and asks the
+/// optional `carrier_sense` hook, e.g. a transceiver's bus-activity pin. A busy bus is retried
+/// after a random backoff, up to `max_attempts`. For Modbus RTU use `modbus_gap_us(baud)`.
+///
+/// `Rs485Tx` implements blocking `embedded_io::Write` (DE is held from the first `write` to
+/// `flush`, one frame per assertion), so `comm::write`, `send_pending` and `handle_builtin` take
+/// it in place of the UART `tx`; `send().await` is the async path.
+use core::sync::atomic::{AtomicU64, Ordering};
+use embassy_stm32::gpio::Output;
+use embassy_stm32::mode::Async;
+use embassy_stm32::usart::{self, UartTx};
+use embassy_time::{Duration, Instant, Timer, block_for};
+
+use crate::common::random::{Backoff, SharedRandom};
+
+/// Random backoff after finding the bus busy: up to this many ms on top of the idle gap
+pub const RS485_BACKOFF_SPREAD_MS: u32 = 5;
+
+// Time of the last byte seen on the bus (embassy-time ticks)
+static LAST_RX: AtomicU64 = AtomicU64::new(0);
+
+/// Note bus activity (called for every received burst)
+pub fn note_rx() {
+  LAST_RX.store(Instant::now().as_ticks(), Ordering::Relaxed);
+}
+
+/// Modbus RTU inter-frame gap: 3.5 character times (11 bits each), fixed at 1750 us above 19200 baud
+pub const fn modbus_gap_us(baud: u32) -> u32 {
+  if baud > 19_200 { 1_750 } else { 35 * 11 * 100_000 / baud }
+}
+
+/// DE timing and collision avoidance
+#[derive(Copy, Clone)]
+pub struct Rs485Config {
+  /// DE high before the first start bit (transceiver enable time)
+  pub pre_delay_us: u32,
+  /// DE kept high after the last stop bit (lets the line settle before the next talker)
+  pub post_delay_us: u32,
+  /// Quiet time required on the bus before transmitting (0: do not wait)
+  pub idle_gap_us: u32,
+  /// Extra check that the bus is free (true = busy); None relies on `idle_gap_us` alone
+  pub carrier_sense: Option<fn() -> bool>,
+  /// Tries before giving up with `Rs485Error::BusBusy`
+  pub max_attempts: u32,
+}
+
+impl Default for Rs485Config {
+  fn default() -> Self {
+    Self {
+      pre_delay_us: 10,
+      post_delay_us: 10,
+      idle_gap_us: 0,
+      carrier_sense: None,
+      max_attempts: 10,
+    }
+  }
+}
+
+/// Why a frame was not sent
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum Rs485Error {
+  /// The bus stayed busy for `max_attempts` tries
+  BusBusy,
+  /// UART error
+  Uart(usart::Error),
+}
+
+impl embedded_io::Error for Rs485Error {
+  fn kind(&self) -> embedded_io::ErrorKind {
+    match self {
+      Rs485Error::BusBusy => embedded_io::ErrorKind::TimedOut,
+      Rs485Error::Uart(_) => embedded_io::ErrorKind::Other,
+    }
+  }
+}
+
+/// UART transmitter driving an RS-485 transceiver's DE (and /RE) pin
+pub struct Rs485Tx<'d> {
+  tx: UartTx<'d, Async>,
+  de: Output<'d>,
+  config: Rs485Config,
+}
+
+impl<'d> Rs485Tx<'d> {
+  /// Wrap `tx` and the DE pin (set low: receiving)
+  pub fn new(tx: UartTx<'d, Async>, mut de: Output<'d>, config: Rs485Config) -> Self {
+    de.set_low();
+    Self { tx, de, config }
+  }
+
+  /// Send `data` as one bus transmission, waiting for a free bus first
+  pub async fn send(&mut self, data: &[u8]) -> Result<(), Rs485Error> {
+    let mut attempt = 0;
+    while let Some(wait_us) = self.busy_for_us() {
+      attempt += 1;
+      if attempt >= self.config.max_attempts {
+        return Err(Rs485Error::BusBusy);
+      }
+      Timer::after_micros(wait_us + Backoff::jitter_ms(&mut SharedRandom, 0, RS485_BACKOFF_SPREAD_MS) * 1_000).await;
+    }
+    self.de.set_high();
+    Timer::after_micros(self.config.pre_delay_us as u64).await;
+    let result = self.tx.write(data).await.and_then(|_| self.tx.blocking_flush());
+    Timer::after_micros(self.config.post_delay_us as u64).await;
+    self.de.set_low();
+    result.map_err(Rs485Error::Uart)
+  }
+
+  /// Whether DE is asserted (a blocking `write` not yet ended by `flush`)
+  pub fn is_driving(&self) -> bool {
+    self.de.is_set_high()
+  }
+
+  // None if the bus is free, else how long to wait (us) before looking again
+  fn busy_for_us(&self) -> Option<u64> {
+    let quiet_us = Instant::now().as_micros().saturating_sub(Instant::from_ticks(LAST_RX.load(Ordering::Relaxed)).as_micros());
+    if quiet_us < self.config.idle_gap_us as u64 {
+      return Some(self.config.idle_gap_us as u64 - quiet_us);
+    }
+    self.config.carrier_sense.filter(|busy| busy()).map(|_| self.config.idle_gap_us.max(100) as u64)
+  }
+
+  // Blocking counterpart of the wait in `send`
+  fn blocking_acquire(&mut self) -> Result<(), Rs485Error> {
+    let mut attempt = 0;
+    while let Some(wait_us) = self.busy_for_us() {
+      attempt += 1;
+      if attempt >= self.config.max_attempts {
+        return Err(Rs485Error::BusBusy);
+      }
+      block_for(Duration::from_micros(wait_us + Backoff::jitter_ms(&mut SharedRandom, 0, RS485_BACKOFF_SPREAD_MS) * 1_000));
+    }
+    self.de.set_high();
+    block_for(Duration::from_micros(self.config.pre_delay_us as u64));
+    Ok(())
+  }
+}
+
+impl embedded_io::ErrorType for Rs485Tx<'_> {
+  type Error = Rs485Error;
+}
+
+impl embedded_io::Write for Rs485Tx<'_> {
+  /// Takes the bus on the first write of a frame and keeps it until `flush`
+  fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+    if !self.is_driving() {
+      self.blocking_acquire()?;
+    }
+    self.tx.blocking_write(buf).map_err(Rs485Error::Uart)?;
+    Ok(buf.len())
+  }
+
+  /// Waits for the last stop bit, then releases the bus after `post_delay_us`
+  fn flush(&mut self) -> Result<(), Self::Error> {
+    if !self.is_driving() {
+      return Ok(());
+    }
+    let result = self.tx.blocking_flush().map_err(Rs485Error::Uart);
+    block_for(Duration::from_micros(self.config.post_delay_us as u64));
+    self.de.set_low();
+    result
+  }
+}
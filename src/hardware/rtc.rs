@@ -0,0 +1,364 @@
+/// RTC backup registers
+///
+/// The STM32F4 RTC has 20 32-bit backup registers in the backup domain. They keep their value
+/// across any reset (software, watchdog, pin) but not a power loss unless VBAT is backed (on the
+/// Nucleo boards VBAT is tied to VDD), which makes them the place for small hand-over data between
+/// one run and the next: the ROM bootloader request (`common::system`), the DFU install request
+/// (`service::dfu`), crash breadcrumbs. Reads work from reset; writes enable backup domain access
+/// (PWR_CR.DBP) first, so both can be used before `embassy_stm32::init` and do not need the `Rtc`.
+///
+/// Smooth calibration trims the RTC clock by -487 to +488 ppm (RTC_CALR: CALM pulses masked, CALP
+/// adding 512, per 2^20 RTCCLK cycles), which `set_calibration` takes in ppm. `calibrate` measures
+/// how fast the RTC runs against the embassy-time clock (derived from HSE/HSI through a timer, so
+/// only as good as that: use HSE, e.g. the ST-LINK's 8 MHz MCO, for this), corrects it and keeps
+/// the correction in the device configuration (`rtc_ppm_tenths`, written to flash by
+/// `config_task`). A 32.768 kHz tuning-fork crystal also slows down by about 0.034 ppm/°C² away
+/// from 25 °C; with a temperature at hand, `calibrate` stores the correction for 25 °C and
+/// `compensate(centi_c)` re-applies it for the current temperature. The LSI is off by percent
+/// rather than ppm and cannot be trimmed this far.
+///
+/// The RTC clock source is part of each board's clock profile (`BoardConfig::RTC_CLOCK` used by
+/// `embassy_config()`): the Nucleo's 32.768 kHz LSE crystal, or the internal ~32 kHz LSI, good to
+/// only a few percent. `ls_config` starts the LSE itself before `embassy_stm32::init` (which would
+/// wait forever for a crystal that does not oscillate) and falls back to the LSI with a warning
+/// when it does not come up within `LSE_STARTUP_MS`; `clock_source()` reports the outcome.
+/// Changing the source resets the backup domain at init (backup registers and calibration).
+///
+/// `now` reads the calendar straight from the shadow registers, and alarm A (`set_alarm`) raises
+/// the RTC_ALARM interrupt through EXTI line 17 at a given day of the month and time, which is
+/// what `service::scheduler` sleeps on. Both work without the `Rtc` (it only has to be running).
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_stm32::rcc::LsConfig;
+use embassy_time::{Instant, Timer};
+
+use crate::protocol::schedule::CalendarTime;
+use crate::service::config;
+
+/// Number of backup registers
+pub const BACKUP_REGISTERS: usize = 20;
+/// Register holding the ROM bootloader request (`common::system`)
+pub const BKP_BOOTLOADER: usize = 0;
+/// Register holding the DFU install request (`service::dfu`)
+pub const BKP_DFU: usize = 1;
+/// Register holding the reason of the last deliberate reset (`common::system::reboot`)
+pub const BKP_RESET_REASON: usize = 2;
+/// First register free for applications
+pub const BKP_USER: usize = 3;
+
+const RTC_BKP0R: u32 = 0x4000_2850;
+const RTC_TR: *mut u32 = 0x4000_2800 as *mut u32;
+const RTC_DR: *mut u32 = 0x4000_2804 as *mut u32;
+const RTC_CR: *mut u32 = 0x4000_2808 as *mut u32;
+const RTC_ISR: *mut u32 = 0x4000_280C as *mut u32;
+const RTC_ALRMAR: *mut u32 = 0x4000_281C as *mut u32;
+const RTC_WPR: *mut u32 = 0x4000_2824 as *mut u32;
+const RTC_CALR: *mut u32 = 0x4000_283C as *mut u32;
+// A smooth calibration write is still pending
+const RTC_ISR_RECALPF: u32 = 1 << 16;
+const RTC_CALR_CALP: u32 = 1 << 15;
+// Alarm A: enable, interrupt enable, registers writable, flag
+const RTC_CR_ALRAE: u32 = 1 << 8;
+const RTC_CR_ALRAIE: u32 = 1 << 12;
+const RTC_ISR_ALRAWF: u32 = 1 << 0;
+const RTC_ISR_INIT: u32 = 1 << 7;
+const RTC_ISR_ALRAF: u32 = 1 << 8;
+// The RTC alarms reach the NVIC through EXTI line 17 (rising edge)
+const EXTI_IMR: *mut u32 = 0x4001_3C00 as *mut u32;
+const EXTI_RTSR: *mut u32 = 0x4001_3C08 as *mut u32;
+const EXTI_PR: *mut u32 = 0x4001_3C14 as *mut u32;
+const EXTI_LINE_RTC_ALARM: u32 = 1 << 17;
+// Calendar years are kept from 2000 (the year register holds two BCD digits)
+const RTC_YEAR_BASE: u16 = 2000;
+const RTC_CALR_CALM: u32 = 0x1FF;
+// Calibration cycle: 2^20 RTCCLK cycles (32 s at 32.768 kHz)
+const CAL_CYCLE: f32 = 1_048_576.0;
+// Backup domain write access: PWR clock (RCC_APB1ENR.PWREN) and PWR_CR.DBP
+const RCC_APB1ENR: *mut u32 = 0x4002_3840 as *mut u32;
+const RCC_APB1ENR_PWREN: u32 = 1 << 28;
+const PWR_CR: *mut u32 = 0x4000_7000 as *mut u32;
+const PWR_CR_DBP: u32 = 1 << 8;
+const RCC_BDCR: *mut u32 = 0x4002_3870 as *mut u32;
+const RCC_BDCR_LSEON: u32 = 1 << 0;
+const RCC_BDCR_LSERDY: u32 = 1 << 1;
+
+/// Longest LSE start-up accepted before falling back to the LSI (crystals take up to ~2 s)
+pub const LSE_STARTUP_MS: u32 = 2_000;
+// Core clock before `embassy_stm32::init` (HSI), for the start-up wait
+const RESET_CLOCK_CYCLES_PER_MS: u32 = 16_000;
+
+/// RTC clock source
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum RtcClock {
+  /// 32.768 kHz crystal (X2 on the Nucleo boards): a few ppm, trimmable by `set_calibration`
+  Lse,
+  /// Internal RC oscillator (~32 kHz): no parts needed, but off by up to several percent
+  Lsi,
+}
+
+static ON_LSI: AtomicBool = AtomicBool::new(false);
+
+/// Backup register index past `BACKUP_REGISTERS`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct OutOfRange;
+
+fn register(index: usize) -> Result<*mut u32, OutOfRange> {
+  if index < BACKUP_REGISTERS {
+    Ok((RTC_BKP0R + 4 * index as u32) as *mut u32)
+  } else {
+    Err(OutOfRange)
+  }
+}
+
+/// Read backup register `index` (None if out of range)
+pub fn backup_read(index: usize) -> Option<u32> {
+  // SAFETY: word read of an always-readable RTC register
+  register(index).ok().map(|reg| unsafe { ptr::read_volatile(reg) })
+}
+
+/// Write backup register `index`
+pub fn backup_write(index: usize, value: u32) -> Result<(), OutOfRange> {
+  let reg = register(index)?;
+  unlock_backup_domain();
+  // SAFETY: single word write of a backup register
+  unsafe { ptr::write_volatile(reg, value) };
+  Ok(())
+}
+
+// Backup domain write access (PWR clock and PWR_CR.DBP)
+fn unlock_backup_domain() {
+  // SAFETY: setting PWREN/DBP only grants access (embassy's RTC setup sets the same bits)
+  unsafe {
+    ptr::write_volatile(RCC_APB1ENR, ptr::read_volatile(RCC_APB1ENR) | RCC_APB1ENR_PWREN);
+    ptr::write_volatile(PWR_CR, ptr::read_volatile(PWR_CR) | PWR_CR_DBP);
+  }
+}
+
+/// Start the LSE and wait up to `timeout_ms` for it to oscillate (true if it runs; an LSE
+/// already running from before the reset counts). Call before `embassy_stm32::init`.
+pub fn lse_starts(timeout_ms: u32) -> bool {
+  // SAFETY: BDCR is only written here before init, and by embassy's RCC setup after it
+  let ready = || unsafe { ptr::read_volatile(RCC_BDCR) } & RCC_BDCR_LSERDY != 0;
+  if ready() {
+    return true;
+  }
+  unlock_backup_domain();
+  // SAFETY: see above
+  unsafe { ptr::write_volatile(RCC_BDCR, ptr::read_volatile(RCC_BDCR) | RCC_BDCR_LSEON) };
+  for _ in 0..timeout_ms {
+    if ready() {
+      return true;
+    }
+    cortex_m::asm::delay(RESET_CLOCK_CYCLES_PER_MS);
+  }
+  // Leave it off, so a later init does not wait on it either
+  // SAFETY: see above
+  unsafe { ptr::write_volatile(RCC_BDCR, ptr::read_volatile(RCC_BDCR) & !RCC_BDCR_LSEON) };
+  false
+}
+
+/// Low-speed clock configuration for `embassy_stm32::Config::rcc.ls` with the RTC on `preferred`,
+/// falling back to the LSI (logged) if the LSE does not start. Call before `embassy_stm32::init`.
+pub fn ls_config(preferred: RtcClock) -> LsConfig {
+  let clock = match preferred {
+    RtcClock::Lse if lse_starts(LSE_STARTUP_MS) => RtcClock::Lse,
+    RtcClock::Lse => {
+      defmt::warn!("RTC: LSE crystal did not start, running on LSI (accuracy only a few percent)");
+      RtcClock::Lsi
+    }
+    RtcClock::Lsi => {
+      defmt::info!("RTC: on LSI (accuracy only a few percent)");
+      RtcClock::Lsi
+    }
+  };
+  ON_LSI.store(clock == RtcClock::Lsi, Ordering::Relaxed);
+  match clock {
+    RtcClock::Lse => LsConfig::default_lse(),
+    RtcClock::Lsi => LsConfig::default_lsi(),
+  }
+}
+
+/// Clock the RTC was set up on by `ls_config` (LSE unless it fell back or the board picked LSI)
+pub fn clock_source() -> RtcClock {
+  if ON_LSI.load(Ordering::Relaxed) { RtcClock::Lsi } else { RtcClock::Lse }
+}
+
+/// Why a smooth calibration was not applied
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum CalibrationError {
+  /// Correction beyond -487..=+488 ppm
+  OutOfRange,
+  /// The previous calibration write is still pending (RTC not running?)
+  Busy,
+}
+
+// Write protection key sequence, then the writes in `f`, then lock again
+fn rtc_unlocked<R>(f: impl FnOnce() -> R) -> R {
+  unlock_backup_domain();
+  // SAFETY: the key sequence only unlocks the RTC registers for the writes in `f`
+  unsafe {
+    ptr::write_volatile(RTC_WPR, 0xCA);
+    ptr::write_volatile(RTC_WPR, 0x53);
+  }
+  let result = f();
+  // SAFETY: see above
+  unsafe { ptr::write_volatile(RTC_WPR, 0xFF) };
+  result
+}
+
+fn rtc_write(reg: *mut u32, value: u32) {
+  // SAFETY: single write of an RTC register while it is unlocked
+  rtc_unlocked(|| unsafe { ptr::write_volatile(reg, value) });
+}
+
+/// Trim the RTC clock by `ppm` (positive: faster), to the nearest 0.95 ppm step
+pub fn set_calibration(ppm: f32) -> Result<(), CalibrationError> {
+  // Net pulses added per calibration cycle: 512 * CALP - CALM
+  let pulses = (ppm * CAL_CYCLE / 1_000_000.0 + if ppm < 0.0 { -0.5 } else { 0.5 }) as i32;
+  if !(-511..=512).contains(&pulses) {
+    return Err(CalibrationError::OutOfRange);
+  }
+  let calr = if pulses > 0 { RTC_CALR_CALP | (512 - pulses) as u32 } else { (-pulses) as u32 };
+  // RECALPF clears within 3 RTCCLK cycles of the last write (~100 us at 32.768 kHz)
+  let mut tries = 0;
+  // SAFETY: read of an always-readable RTC register
+  while unsafe { ptr::read_volatile(RTC_ISR) } & RTC_ISR_RECALPF != 0 {
+    tries += 1;
+    if tries > 10_000 {
+      return Err(CalibrationError::Busy);
+    }
+  }
+  rtc_write(RTC_CALR, calr);
+  Ok(())
+}
+
+/// Calibration currently applied, in ppm
+pub fn calibration() -> f32 {
+  // SAFETY: read of an always-readable RTC register
+  let calr = unsafe { ptr::read_volatile(RTC_CALR) };
+  let plus = if calr & RTC_CALR_CALP != 0 { 512 } else { 0 };
+  (plus - (calr & RTC_CALR_CALM) as i32) as f32 * 1_000_000.0 / CAL_CYCLE
+}
+
+/// Frequency offset of a 32.768 kHz tuning-fork crystal at `centi_c` (0.01 °C) relative to 25 °C, in ppm
+pub fn crystal_offset_ppm(centi_c: i32) -> f32 {
+  let delta = (centi_c - 2500) as f32 / 100.0;
+  -0.034 * delta * delta
+}
+
+// Time of the next change of the RTC seconds: sleep through most of the second, then poll
+async fn next_second() -> Instant {
+  // SAFETY: read of an always-readable RTC register (shadow copy, updated every RTCCLK cycles)
+  let tr = || unsafe { ptr::read_volatile(RTC_TR) } & 0x7F;
+  let start = tr();
+  while tr() == start {
+    Timer::after_micros(50).await;
+  }
+  Instant::now()
+}
+
+/// How fast the RTC runs against the embassy-time clock over `seconds`, in ppm (positive: fast).
+/// The result has about +/-100 us / `seconds` of jitter (1.7 ppm over a minute).
+pub async fn measure_drift(seconds: u32) -> f32 {
+  let start = next_second().await;
+  for _ in 1..seconds {
+    Timer::after_millis(900).await;
+    next_second().await;
+  }
+  Timer::after_millis(900).await;
+  let elapsed_us = (next_second().await - start).as_micros() as f32;
+  (seconds as f32 * 1_000_000.0 - elapsed_us) / elapsed_us * 1_000_000.0
+}
+
+/// Measure the drift over `seconds`, correct it and store the correction (for 25 °C when
+/// `centi_c`, the temperature during the measurement, is given) in the device configuration
+pub async fn calibrate(seconds: u32, centi_c: Option<i32>) -> Result<f32, CalibrationError> {
+  let drift = measure_drift(seconds).await;
+  let correction = calibration() - drift;
+  set_calibration(correction)?;
+  let at_25c = correction + centi_c.map_or(0.0, crystal_offset_ppm);
+  config::update(|c| c.rtc_ppm_tenths = (at_25c * 10.0) as i16);
+  defmt::info!("RTC: {} ppm drift, calibrated to {} ppm", drift, correction);
+  Ok(correction)
+}
+
+/// Apply the stored correction, adjusted for the crystal at `centi_c` if given (call at boot once
+/// the RTC runs, and again as the temperature changes)
+pub fn compensate(centi_c: Option<i32>) -> Result<(), CalibrationError> {
+  let stored = config::current().rtc_ppm_tenths as f32 / 10.0;
+  set_calibration(stored - centi_c.map_or(0.0, crystal_offset_ppm))
+}
+
+fn bcd(value: u32) -> u8 {
+  ((value >> 4) * 10 + (value & 0xF)) as u8
+}
+
+fn to_bcd(value: u8) -> u32 {
+  ((value / 10) << 4 | value % 10) as u32
+}
+
+/// Current RTC date and time (2000-01-01 00:00:00 until the calendar is set)
+pub fn now() -> CalendarTime {
+  // SAFETY: reads of always-readable RTC registers; reading TR freezes the DR shadow until DR
+  // is read, so the two are from the same second
+  let (tr, dr) = unsafe { (ptr::read_volatile(RTC_TR), ptr::read_volatile(RTC_DR)) };
+  CalendarTime {
+    year: RTC_YEAR_BASE + bcd((dr >> 16) & 0xFF) as u16,
+    month: bcd((dr >> 8) & 0x1F),
+    day: bcd(dr & 0x3F),
+    hour: bcd((tr >> 16) & 0x3F),
+    minute: bcd((tr >> 8) & 0x7F),
+    second: bcd(tr & 0x7F),
+  }
+}
+
+/// Arm alarm A for `at`'s day of the month, hour, minute and second (month and year are not
+/// compared: it fires at the first such moment) and route it to the RTC_ALARM interrupt
+pub fn set_alarm(at: &CalendarTime) {
+  // Every mask bit clear: date (WDSEL 0), hours, minutes and seconds all compared
+  let alrmar = to_bcd(at.day) << 24 | to_bcd(at.hour) << 16 | to_bcd(at.minute) << 8 | to_bcd(at.second);
+  rtc_unlocked(|| {
+    // SAFETY: alarm A is only programmed here; ALRAWF is set within 2 RTCCLK cycles of ALRAE
+    // clearing
+    unsafe {
+      ptr::write_volatile(RTC_CR, ptr::read_volatile(RTC_CR) & !(RTC_CR_ALRAE | RTC_CR_ALRAIE));
+      while ptr::read_volatile(RTC_ISR) & RTC_ISR_ALRAWF == 0 {}
+      ptr::write_volatile(RTC_ALRMAR, alrmar);
+      clear_alarm_flag();
+      ptr::write_volatile(RTC_CR, ptr::read_volatile(RTC_CR) | RTC_CR_ALRAE | RTC_CR_ALRAIE);
+    }
+  });
+  // SAFETY: read-modify-write of EXTI line 17 in a critical section (embassy's EXTI driver only
+  // owns lines 0-15)
+  cortex_m::interrupt::free(|_| unsafe {
+    ptr::write_volatile(EXTI_IMR, ptr::read_volatile(EXTI_IMR) | EXTI_LINE_RTC_ALARM);
+    ptr::write_volatile(EXTI_RTSR, ptr::read_volatile(EXTI_RTSR) | EXTI_LINE_RTC_ALARM);
+  });
+}
+
+/// Disarm alarm A
+pub fn disable_alarm() {
+  // SAFETY: clearing the alarm A enables only
+  rtc_unlocked(|| unsafe { ptr::write_volatile(RTC_CR, ptr::read_volatile(RTC_CR) & !(RTC_CR_ALRAE | RTC_CR_ALRAIE)) });
+}
+
+/// Acknowledge alarm A (from the RTC_ALARM handler); true if it had fired
+pub fn take_alarm() -> bool {
+  // SAFETY: read of an always-readable RTC register
+  let fired = unsafe { ptr::read_volatile(RTC_ISR) } & RTC_ISR_ALRAF != 0;
+  // ALRAF is not write-protected; the EXTI pending bit is cleared by writing it
+  clear_alarm_flag();
+  // SAFETY: write-1-to-clear of EXTI line 17 only
+  unsafe { ptr::write_volatile(EXTI_PR, EXTI_LINE_RTC_ALARM) };
+  fired
+}
+
+// Clear ALRAF: ISR flags clear on writing 0 and are left alone by writing 1, except INIT, which
+// keeps its value
+fn clear_alarm_flag() {
+  // SAFETY: only ALRAF changes
+  unsafe {
+    let init = ptr::read_volatile(RTC_ISR) & RTC_ISR_INIT;
+    ptr::write_volatile(RTC_ISR, (!(RTC_ISR_ALRAF | RTC_ISR_INIT) & 0x0001_FFFF) | init);
+  }
+}
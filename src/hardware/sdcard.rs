@@ -0,0 +1,85 @@
+/// SD Card Hardware Abstraction Layer (feature `sdcard`)
+///
+/// This module runs an SD/SDHC card in SPI mode on the shared blocking SPI bus and mounts its
+/// first FAT16/FAT32 partition with `embedded-sdmmc`. `SdStorage` keeps the volume and root
+/// directory open and offers whole-file helpers (append, read, size) on 8.3 file names in the
+/// root directory, which is all the data logger needs. The bus runs at 400 kHz, the highest
+/// clock every card accepts during initialization (~40 KB/s, plenty for CSV logging).
+use embedded_sdmmc::{Mode, RawDirectory, RawVolume, SdCard, SdCardError, TimeSource, Timestamp, VolumeIdx, VolumeManager};
+
+use crate::hardware::{BlockingSharedSpi, Timing};
+
+/// SPI clock for the card
+pub const SD_SPI_HZ: u32 = 400_000;
+
+/// Card in SPI mode on the shared bus
+pub type SdCardDevice = SdCard<BlockingSharedSpi, Timing>;
+/// Card, filesystem or file error
+pub type SdError = embedded_sdmmc::Error<SdCardError>;
+
+/// File timestamps: there is no calendar clock to hand, so files are stamped 2025-01-01
+pub struct FixedClock;
+
+impl TimeSource for FixedClock {
+  fn get_timestamp(&self) -> Timestamp {
+    Timestamp {
+      year_since_1970: 55,
+      zero_indexed_month: 0,
+      zero_indexed_day: 0,
+      hours: 0,
+      minutes: 0,
+      seconds: 0,
+    }
+  }
+}
+
+/// Mounted FAT volume on an SD card
+pub struct SdStorage {
+  volumes: VolumeManager<SdCardDevice, FixedClock>,
+  volume: RawVolume,
+  root: RawDirectory,
+}
+
+impl SdStorage {
+  /// Initialize the card on `spi` (its own chip select on the shared bus) and mount partition 0
+  pub fn new(spi: BlockingSharedSpi) -> Result<Self, SdError> {
+    let card = SdCard::new(spi, Timing);
+    let bytes = card.num_bytes().map_err(embedded_sdmmc::Error::DeviceError)?;
+    defmt::info!("SD card: {} MB", bytes / (1024 * 1024));
+    let volumes = VolumeManager::new(card, FixedClock);
+    let volume = volumes.open_raw_volume(VolumeIdx(0))?;
+    let root = volumes.open_root_dir(volume)?;
+    Ok(Self { volumes, volume, root })
+  }
+
+  /// Append `data` to `name` in the root directory (created if missing); returns the new file size
+  pub fn append(&mut self, name: &str, data: &[u8]) -> Result<u32, SdError> {
+    let file = self.volumes.open_file_in_dir(self.root, name, Mode::ReadWriteCreateOrAppend)?;
+    let result = self.volumes.write(file, data).and_then(|_| self.volumes.file_length(file));
+    // Closing flushes the directory entry, so the data survives a power cut after this returns
+    self.volumes.close_file(file)?;
+    result
+  }
+
+  /// Read from `name` starting at `offset` into `buf`; returns the number of bytes read
+  pub fn read(&mut self, name: &str, offset: u32, buf: &mut [u8]) -> Result<usize, SdError> {
+    let file = self.volumes.open_file_in_dir(self.root, name, Mode::ReadOnly)?;
+    let result = self.volumes.file_seek_from_start(file, offset).and_then(|_| self.volumes.read(file, buf));
+    self.volumes.close_file(file)?;
+    result
+  }
+
+  /// Size of `name` in bytes (None if it does not exist)
+  pub fn size(&mut self, name: &str) -> Option<u32> {
+    let file = self.volumes.open_file_in_dir(self.root, name, Mode::ReadOnly).ok()?;
+    let length = self.volumes.file_length(file).ok();
+    self.volumes.close_file(file).ok();
+    length
+  }
+
+  /// Close the root directory and volume (call before removing the card)
+  pub fn unmount(self) {
+    self.volumes.close_dir(self.root).ok();
+    self.volumes.close_volume(self.volume).ok();
+  }
+}
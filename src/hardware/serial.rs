@@ -1,19 +1,181 @@
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU32, Ordering};
 use embassy_executor::Spawner;
 use embassy_stm32::{
   Peri, bind_interrupts,
+  gpio::Pin,
+  interrupt::{Interrupt, typelevel::Interrupt as _},
   mode::Async,
-  usart::{self, Config as UartConfig, Instance, RxDma, RxPin, TxDma, TxPin, Uart, UartRx, UartTx},
+  usart::{self, CkPin, Config as UartConfig, Instance, Parity, RxDma, RxPin, StopBits, TxDma, TxPin, Uart, UartRx, UartTx},
 };
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::Channel;
 use embassy_sync::mutex::Mutex;
 use embassy_time::{Duration, Timer};
 use heapless::Vec;
 
+use crate::hardware::gpio;
+
 // Define a constant for buffer size
 const SERIAL_BUFFER_SIZE: usize = 256;
 const SERIAL_QUEUE_DEPTH: usize = 4;
-const SERIAL_BAUDRATE: u32 = 115_200;
+/// Default baud rate (until `set_baudrate`, e.g. from `service::config`)
+pub const SERIAL_BAUDRATE: u32 = 115_200;
+
+static BAUDRATE: AtomicU32 = AtomicU32::new(SERIAL_BAUDRATE);
+
+/// Baud rate for the next `init_serial` (call before `init_all_hardware`)
+pub fn set_baudrate(baud: u32) {
+  BAUDRATE.store(baud, Ordering::Relaxed);
+}
+
+static MODE: BlockingMutex<CriticalSectionRawMutex, Cell<SerialMode>> = BlockingMutex::new(Cell::new(SerialMode::Normal));
+
+/// Line mode for the next `init_serial` (call before `init_all_hardware`)
+pub fn set_mode(mode: SerialMode) {
+  MODE.lock(|m| m.set(mode));
+}
+
+/// USART line mode beyond plain asynchronous serial
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum SerialMode {
+  /// Plain UART
+  Normal,
+  /// IrDA SIR encoding for an IR transceiver: 3/16-bit pulses, or with `low_power` pulses of three
+  /// periods of PCLK / `prescaler` (keep that near 1.8 MHz); `prescaler` is 1 in normal mode
+  Irda { low_power: bool, prescaler: u8 },
+  /// ISO 7816-3 smartcard: half-duplex on TX (open drain, pulled up to the card I/O), 8 data bits,
+  /// even parity, 1.5 stop bits; card clock on CK at PCLK / (2 x `prescaler`, 1..=31),
+  /// `guard_time` bit times between characters and, with `nack`, a NACK on parity errors
+  Smartcard { prescaler: u8, guard_time: u8, nack: bool },
+}
+
+/// Line settings for `init_aux_serial` (the VCP takes `set_baudrate` and `set_mode`)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct SerialConfig {
+  pub baudrate: u32,
+  pub mode: SerialMode,
+}
+
+impl SerialConfig {
+  /// Plain UART at `baudrate` (8N1)
+  pub const fn new(baudrate: u32) -> Self {
+    Self {
+      baudrate,
+      mode: SerialMode::Normal,
+    }
+  }
+
+  /// Same baud rate in another line mode
+  pub const fn mode(mut self, mode: SerialMode) -> Self {
+    self.mode = mode;
+    self
+  }
+
+  // embassy settings for the mode (smartcard frames are 8E1.5)
+  fn uart_config(&self) -> UartConfig {
+    let mut cfg = UartConfig::default();
+    cfg.baudrate = self.baudrate;
+    if let SerialMode::Smartcard { .. } = self.mode {
+      cfg.parity = Parity::ParityEven;
+      cfg.stop_bits = StopBits::STOP1P5;
+    }
+    cfg
+  }
+}
+
+// USART register offsets and bits for the modes embassy does not configure
+const USART_CR1: u32 = 0x0C;
+const USART_CR2: u32 = 0x10;
+const USART_CR3: u32 = 0x14;
+const USART_GTPR: u32 = 0x18;
+const CR1_UE: u32 = 1 << 13;
+const CR2_CLKEN: u32 = 1 << 11;
+const CR2_LINEN: u32 = 1 << 14;
+const CR3_IREN: u32 = 1 << 1;
+const CR3_IRLP: u32 = 1 << 2;
+const CR3_HDSEL: u32 = 1 << 3;
+const CR3_NACK: u32 = 1 << 4;
+const CR3_SCEN: u32 = 1 << 5;
+
+/// USART register block, for the modes embassy-stm32 does not expose (IrDA, smartcard, LIN)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+#[repr(u32)]
+pub enum UsartPort {
+  Usart1 = 0x4001_1000,
+  Usart2 = 0x4000_4400,
+  Usart3 = 0x4000_4800,
+  Usart6 = 0x4001_1400,
+}
+
+impl UsartPort {
+  /// Register block of USART `T` (None for the UARTs, which have no smartcard or clock output)
+  pub fn of<T: Instance>() -> Option<Self> {
+    match T::Interrupt::IRQ {
+      Interrupt::USART1 => Some(UsartPort::Usart1),
+      Interrupt::USART2 => Some(UsartPort::Usart2),
+      Interrupt::USART3 => Some(UsartPort::Usart3),
+      Interrupt::USART6 => Some(UsartPort::Usart6),
+      _ => None,
+    }
+  }
+
+  pub(crate) fn reg(self, offset: u32) -> *mut u32 {
+    (self as u32 + offset) as *mut u32
+  }
+
+  /// Switch the (initialized) USART to `mode`; the USART is disabled while the mode bits change
+  pub fn set_mode(self, mode: SerialMode) {
+    let (cr2_set, cr3_set, gtpr) = match mode {
+      SerialMode::Normal => (0, 0, 0),
+      SerialMode::Irda { low_power, prescaler } => (0, CR3_IREN | if low_power { CR3_IRLP } else { 0 }, prescaler.max(1) as u32),
+      SerialMode::Smartcard { prescaler, guard_time, nack } => (
+        CR2_CLKEN,
+        CR3_SCEN | if nack { CR3_NACK } else { 0 },
+        (guard_time as u32) << 8 | (prescaler.clamp(1, 31) as u32),
+      ),
+    };
+    // SAFETY: configuration registers of a USART owned by the caller's `Uart`; UE is restored
+    unsafe {
+      let cr1 = self.reg(USART_CR1).read_volatile();
+      self.reg(USART_CR1).write_volatile(cr1 & !CR1_UE);
+      self.reg(USART_GTPR).write_volatile(gtpr);
+      let cr2 = self.reg(USART_CR2).read_volatile() & !(CR2_CLKEN | CR2_LINEN);
+      self.reg(USART_CR2).write_volatile(cr2 | cr2_set);
+      let cr3 = self.reg(USART_CR3).read_volatile() & !(CR3_IREN | CR3_IRLP | CR3_HDSEL | CR3_NACK | CR3_SCEN);
+      self.reg(USART_CR3).write_volatile(cr3 | cr3_set);
+      self.reg(USART_CR1).write_volatile(cr1);
+    }
+  }
+}
+
+/// Route USART `T`'s clock output (the smartcard clock) to `ck`; embassy's `Uart` leaves CK unused
+pub fn clock_output<T: Instance, CK: CkPin<T>>(ck: Peri<'static, CK>) {
+  // `ck` is owned here, so nothing else drives the pin
+  gpio::set_alternate(ck.port(), ck.pin(), ck.af_num());
+}
+
+// Apply the line mode to a USART embassy has just set up; `tx` is the TX pin (port, pin)
+fn apply_mode<T: Instance>(mode: SerialMode, tx: (u8, u8)) {
+  if mode == SerialMode::Normal {
+    return;
+  }
+  let Some(port) = UsartPort::of::<T>() else {
+    defmt::warn!("Serial: {} needs a USART, staying in normal mode", mode);
+    return;
+  };
+  if let SerialMode::Smartcard { .. } = mode {
+    // Smartcard I/O is open drain (the card and the USART both drive it)
+    gpio::set_open_drain(tx.0, tx.1);
+  }
+  port.set_mode(mode);
+}
+
+// USART1 for the auxiliary port (`init_aux_serial`, e.g. Nucleo-64 F446RE PA9/PA10)
+bind_interrupts!(pub struct IrqsUsart1 {
+    USART1 => usart::InterruptHandler<embassy_stm32::peripherals::USART1>;
+});
 
 // Bind USART2 interrupt handler for async operation
 bind_interrupts!(pub struct Irqs {
@@ -90,6 +252,8 @@ pub async fn serial_rx_task_dma(mut serial_rx: SerialReceiver<'static>) {
   loop {
     match serial_rx.read_until_idle().await {
       Ok(data) => {
+        #[cfg(feature = "task_metrics")]
+        let busy = crate::diagnostics::task_metrics::Iteration::begin(crate::diagnostics::task_metrics::Task::SerialRx);
         if !data.is_empty() {
           // Copy bytes into a bounded buffer and queue
           let mut bytes: Vec<u8, SERIAL_BUFFER_SIZE> = Vec::new();
@@ -97,6 +261,8 @@ pub async fn serial_rx_task_dma(mut serial_rx: SerialReceiver<'static>) {
           bytes.extend_from_slice(&data[..take]).ok();
           let _ = SERIAL_RX_QUEUE.try_send(bytes);
         }
+        #[cfg(feature = "task_metrics")]
+        drop(busy);
         serial_rx.clear_buffer().await;
       }
       Err(_e) => {
@@ -126,8 +292,31 @@ pub async fn recv_raw() -> Vec<u8, SERIAL_BUFFER_SIZE> {
   SERIAL_RX_QUEUE.receive().await
 }
 
+/// Loopback transport: bytes written are queued back into the RX path as if received.
+/// Lets the HDLC/comm pipeline run end-to-end without UART wiring (e.g. `comm::write(&mut Loopback, &msg)`).
+pub struct Loopback;
+
+impl embedded_io::ErrorType for Loopback {
+  type Error = embedded_io::ErrorKind;
+}
+
+impl embedded_io::Write for Loopback {
+  fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+    let take = core::cmp::min(buf.len(), SERIAL_BUFFER_SIZE);
+    let mut bytes: Vec<u8, SERIAL_BUFFER_SIZE> = Vec::new();
+    bytes.extend_from_slice(&buf[..take]).ok();
+    SERIAL_RX_QUEUE.try_send(bytes).map_err(|_| embedded_io::ErrorKind::OutOfMemory)?;
+    Ok(take)
+  }
+
+  fn flush(&mut self) -> Result<(), Self::Error> {
+    Ok(())
+  }
+}
+
 /// Get the interrupt handler type aliases for export to board configs
 pub use Irqs as Serial2Irqs;
+pub use IrqsUsart1 as Serial1Irqs;
 pub use IrqsUsart3 as Serial3Irqs;
 pub use IrqsUsart6 as Serial6Irqs;
 
@@ -148,16 +337,89 @@ where
   TXDMA: TxDma<T> + 'static,
   RXDMA: RxDma<T> + 'static,
 {
-  let mut cfg = UartConfig::default();
-  cfg.baudrate = SERIAL_BAUDRATE;
-
-  let uart = Uart::new(usart, rx, tx, irqs, tx_dma, rx_dma, cfg).unwrap();
+  let config = SerialConfig::new(BAUDRATE.load(Ordering::Relaxed)).mode(MODE.lock(|m| m.get()));
+  let tx_pin = (tx.port(), tx.pin());
+  let uart = Uart::new(usart, rx, tx, irqs, tx_dma, rx_dma, config.uart_config()).unwrap();
+  apply_mode::<T>(config.mode, tx_pin);
   let (tx, rx) = uart.split();
   let receiver = create_serial_receiver(rx);
-  let _ = spawner.spawn(serial_rx_task_dma(receiver));
+  // On the high-priority executor when the application started it (`common::executor`)
+  match crate::common::executor::high_priority() {
+    Some(high) => {
+      let _ = high.spawn(serial_rx_task_dma(receiver));
+    }
+    None => {
+      let _ = spawner.spawn(serial_rx_task_dma(receiver));
+    }
+  }
+  // With `shell` the raw RX queue is left for `service::shell::shell_task`
+  #[cfg(not(feature = "shell"))]
   let _ = spawner.spawn(crate::service::comm::serial_hdlc_consumer_task());
   tx
 }
 
 // Define a shared buffer to reduce RAM usage
 static SHARED_RX_BUFFER: Mutex<CriticalSectionRawMutex, [u8; SERIAL_BUFFER_SIZE]> = Mutex::new([0; SERIAL_BUFFER_SIZE]);
+
+// Auxiliary port: a second UART for the application (GPS, modem), with its own RX buffer, task and
+// queue so its traffic never reaches the comm protocol on the VCP
+const AUX_QUEUE_DEPTH: usize = 4;
+static AUX_RX_BUFFER: Mutex<CriticalSectionRawMutex, [u8; SERIAL_BUFFER_SIZE]> = Mutex::new([0; SERIAL_BUFFER_SIZE]);
+static AUX_RX_QUEUE: Channel<CriticalSectionRawMutex, Vec<u8, SERIAL_BUFFER_SIZE>, AUX_QUEUE_DEPTH> = Channel::new();
+
+/// Auxiliary serial initializer (see `BoardConfig::init_aux_serial`): like `init_serial` but with
+/// `config`, feeding `read_aux`/`recv_aux` instead of the comm protocol
+#[allow(clippy::too_many_arguments)]
+pub fn init_aux_serial<T, RX, TX, TXDMA, RXDMA>(
+  spawner: Spawner,
+  usart: Peri<'static, T>,
+  rx: Peri<'static, RX>,
+  tx: Peri<'static, TX>,
+  irqs: impl embassy_stm32::interrupt::typelevel::Binding<<T as Instance>::Interrupt, usart::InterruptHandler<T>> + 'static,
+  tx_dma: Peri<'static, TXDMA>,
+  rx_dma: Peri<'static, RXDMA>,
+  config: SerialConfig,
+) -> UartTx<'static, Async>
+where
+  T: Instance + 'static,
+  RX: RxPin<T> + 'static,
+  TX: TxPin<T> + 'static,
+  TXDMA: TxDma<T> + 'static,
+  RXDMA: RxDma<T> + 'static,
+{
+  let tx_pin = (tx.port(), tx.pin());
+  let uart = Uart::new(usart, rx, tx, irqs, tx_dma, rx_dma, config.uart_config()).unwrap();
+  apply_mode::<T>(config.mode, tx_pin);
+  let (tx, rx) = uart.split();
+  let _ = spawner.spawn(aux_serial_rx_task(SerialReceiver::new(rx, &AUX_RX_BUFFER)));
+  tx
+}
+
+/// Async task: queue bytes received on the auxiliary port (spawned by `init_aux_serial`)
+#[embassy_executor::task]
+async fn aux_serial_rx_task(mut serial_rx: SerialReceiver<'static>) {
+  loop {
+    match serial_rx.read_until_idle().await {
+      Ok(data) => {
+        // Bus activity for RS-485 collision avoidance
+        crate::hardware::rs485::note_rx();
+        // Dropped when the application falls behind by more than AUX_QUEUE_DEPTH bursts
+        if !data.is_empty() && AUX_RX_QUEUE.try_send(data).is_err() {
+          defmt::warn!("Aux serial: RX queue full, {} bytes dropped", serial_rx.buffer_pos);
+        }
+        serial_rx.clear_buffer().await;
+      }
+      Err(_e) => Timer::after(Duration::from_millis(10)).await,
+    }
+  }
+}
+
+/// Try to read bytes received on the auxiliary port (non-blocking)
+pub fn read_aux() -> Option<Vec<u8, SERIAL_BUFFER_SIZE>> {
+  AUX_RX_QUEUE.try_receive().ok()
+}
+
+/// Await bytes received on the auxiliary port
+pub async fn recv_aux() -> Vec<u8, SERIAL_BUFFER_SIZE> {
+  AUX_RX_QUEUE.receive().await
+}
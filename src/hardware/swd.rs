@@ -0,0 +1,297 @@
+/// Bit-Banged SWD Probe on Spare Pins
+///
+/// Turns the board into a minimal debug probe for field diagnostics: wire SWCLK, SWDIO (and
+/// optionally NRST) of a target to free GPIOs, share ground, and `SwdProbe` can switch the
+/// target's debug port to SWD, read its IDCODE, read/write words of its memory through the
+/// AHB-AP, and reset it. The clock is driven in software, so it is slow (~1 MHz with the default
+/// `SWD_HALF_PERIOD_CYCLES` at 180 MHz) but timing-insensitive: SWD is synchronous, so an
+/// interrupt in the middle of a transfer only stretches a clock cycle. Keep the wires short and
+/// add ~1k series resistors when probing boards with their own supply.
+///
+/// Each transfer: an 8-bit request (start, APnDP, RnW, A[3:2], parity, stop, park) sent LSB
+/// first, a turnaround cycle, a 3-bit ACK from the target, then 32 data bits plus parity in the
+/// request's direction (with another turnaround around the target's part). The host changes
+/// SWDIO while SWCLK is low and samples it just before the rising edge.
+use embassy_stm32::Peri;
+use embassy_stm32::gpio::{Flex, Level, Output, OutputOpenDrain, Pin, Pull, Speed};
+use embassy_time::Timer;
+
+/// Core cycles per half SWCLK period
+pub const SWD_HALF_PERIOD_CYCLES: u32 = 90;
+/// WAIT answers retried before a transfer gives up
+pub const SWD_WAIT_RETRIES: u32 = 100;
+/// NRST low time for `reset_target`
+pub const SWD_RESET_PULSE_MS: u64 = 10;
+/// DBGMCU_IDCODE of an STM32 F1/F2/F3/F4/F7/L1 target (device and revision id; 0x4001_5800 on F0/G0/L0)
+pub const SWD_STM32_DBGMCU_IDCODE: u32 = 0xE004_2000;
+
+// Debug port registers (A[3:2] << 2)
+const DP_IDCODE: u8 = 0x0;
+const DP_ABORT: u8 = 0x0;
+const DP_CTRL_STAT: u8 = 0x4;
+const DP_SELECT: u8 = 0x8;
+const DP_RDBUFF: u8 = 0xC;
+// AHB-AP registers (bank 0)
+const AP_CSW: u8 = 0x0;
+const AP_TAR: u8 = 0x4;
+const AP_DRW: u8 = 0xC;
+
+// ABORT: clear the sticky error flags (STKCMPCLR, STKERRCLR, WDERRCLR, ORUNERRCLR)
+const ABORT_CLEAR_ERRORS: u32 = 0x1E;
+// CTRL/STAT: CSYSPWRUPREQ | CDBGPWRUPREQ, and their ACK bits
+const CTRL_POWER_UP: u32 = (1 << 30) | (1 << 28);
+const CTRL_POWER_UP_ACK: u32 = (1 << 31) | (1 << 29);
+// CSW: 32-bit accesses, no address increment, privileged data access
+const CSW_WORD: u32 = 0x2300_0002;
+// Cortex-M AIRCR: VECTKEY | SYSRESETREQ
+const AIRCR: u32 = 0xE000_ED0C;
+const AIRCR_SYSRESETREQ: u32 = 0x05FA_0004;
+// JTAG-to-SWD switch sequence (sent LSB first)
+const JTAG_TO_SWD: u32 = 0xE79E;
+
+const ACK_OK: u32 = 0b001;
+const ACK_WAIT: u32 = 0b010;
+const ACK_FAULT: u32 = 0b100;
+
+/// SWD errors
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum SwdError {
+  /// No valid ACK (no target, not powered, wiring, or the target is not in SWD mode)
+  NoAck,
+  /// The target kept answering WAIT
+  Wait,
+  /// The target answered FAULT (sticky error set; `connect` clears it)
+  Fault,
+  /// Read data failed its parity check
+  Parity,
+  /// The debug port did not acknowledge the power-up request
+  PowerUp,
+}
+
+/// Debug port IDCODE (DPIDR)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct Idcode(pub u32);
+
+impl Idcode {
+  /// JEP106 designer code (0x23B for ARM)
+  pub fn designer(&self) -> u16 {
+    ((self.0 >> 1) & 0x7FF) as u16
+  }
+
+  /// Debug port part number
+  pub fn part(&self) -> u16 {
+    ((self.0 >> 12) & 0xFFFF) as u16
+  }
+
+  pub fn version(&self) -> u8 {
+    (self.0 >> 28) as u8
+  }
+}
+
+/// SWD host on two GPIOs, with an optional open-drain NRST line
+pub struct SwdProbe<'d> {
+  swclk: Output<'d>,
+  swdio: Flex<'d>,
+  nrst: Option<OutputOpenDrain<'d>>,
+  half_period: u32,
+}
+
+impl<'d> SwdProbe<'d> {
+  pub fn new(swclk: Peri<'d, impl Pin>, swdio: Peri<'d, impl Pin>) -> Self {
+    let mut swdio = Flex::new(swdio);
+    swdio.set_high();
+    swdio.set_as_output(Speed::VeryHigh);
+    Self {
+      swclk: Output::new(swclk, Level::High, Speed::VeryHigh),
+      swdio,
+      nrst: None,
+      half_period: SWD_HALF_PERIOD_CYCLES,
+    }
+  }
+
+  /// Drive the target's NRST for `reset_target` (open drain: the target keeps its own pull-up)
+  pub fn with_reset(mut self, nrst: Peri<'d, impl Pin>) -> Self {
+    self.nrst = Some(OutputOpenDrain::new(nrst, Level::High, Speed::Low));
+    self
+  }
+
+  /// Slower clock for long wires (`SWD_HALF_PERIOD_CYCLES` by default)
+  pub fn with_half_period(mut self, cycles: u32) -> Self {
+    self.half_period = cycles;
+    self
+  }
+
+  /// Switch the target to SWD, read its IDCODE and clear any sticky errors
+  pub fn connect(&mut self) -> Result<Idcode, SwdError> {
+    self.line_reset();
+    self.write_bits(JTAG_TO_SWD, 16);
+    self.line_reset();
+    // Idle cycles before the first request
+    self.write_bits(0, 8);
+    // IDCODE must be the first read after a line reset
+    let idcode = self.dp_read(DP_IDCODE)?;
+    self.dp_write(DP_ABORT, ABORT_CLEAR_ERRORS)?;
+    defmt::info!("SWD: target IDCODE 0x{:08X}", idcode);
+    Ok(Idcode(idcode))
+  }
+
+  /// Read the IDCODE again (connection check)
+  pub fn read_idcode(&mut self) -> Result<Idcode, SwdError> {
+    self.dp_read(DP_IDCODE).map(Idcode)
+  }
+
+  /// Power up the debug and system domains and set up the AHB-AP for word accesses (after `connect`)
+  pub fn power_up(&mut self) -> Result<(), SwdError> {
+    self.dp_write(DP_CTRL_STAT, CTRL_POWER_UP)?;
+    let mut powered = false;
+    for _ in 0..SWD_WAIT_RETRIES {
+      if self.dp_read(DP_CTRL_STAT)? & CTRL_POWER_UP_ACK == CTRL_POWER_UP_ACK {
+        powered = true;
+        break;
+      }
+    }
+    if !powered {
+      return Err(SwdError::PowerUp);
+    }
+    // AP 0, bank 0
+    self.dp_write(DP_SELECT, 0)?;
+    self.ap_write(AP_CSW, CSW_WORD)
+  }
+
+  /// Read a word of target memory (after `power_up`)
+  pub fn read_mem32(&mut self, addr: u32) -> Result<u32, SwdError> {
+    self.ap_write(AP_TAR, addr)?;
+    // AP reads are posted: the value comes with the next read, here RDBUFF
+    self.ap_read(AP_DRW)?;
+    self.dp_read(DP_RDBUFF)
+  }
+
+  /// Write a word of target memory (after `power_up`)
+  pub fn write_mem32(&mut self, addr: u32, value: u32) -> Result<(), SwdError> {
+    self.ap_write(AP_TAR, addr)?;
+    self.ap_write(AP_DRW, value)?;
+    // Wait for the posted write to complete
+    self.dp_read(DP_RDBUFF).map(|_| ())
+  }
+
+  /// Reset the target: an NRST pulse if `with_reset` gave a pin, else SYSRESETREQ through the
+  /// debug port (after `power_up`). Reconnect with `connect` afterwards.
+  pub async fn reset_target(&mut self) -> Result<(), SwdError> {
+    match &mut self.nrst {
+      Some(nrst) => {
+        nrst.set_low();
+        Timer::after_millis(SWD_RESET_PULSE_MS).await;
+        nrst.set_high();
+        Ok(())
+      }
+      None => self.write_mem32(AIRCR, AIRCR_SYSRESETREQ).or_else(|e| match e {
+        // The target may reset before acknowledging the flush
+        SwdError::NoAck => Ok(()),
+        e => Err(e),
+      }),
+    }
+  }
+
+  pub fn dp_read(&mut self, addr: u8) -> Result<u32, SwdError> {
+    self.transfer(false, true, addr, 0)
+  }
+
+  pub fn dp_write(&mut self, addr: u8, value: u32) -> Result<(), SwdError> {
+    self.transfer(false, false, addr, value).map(|_| ())
+  }
+
+  /// Read an AP register of the selected AP; the result is that of the previous AP read
+  pub fn ap_read(&mut self, addr: u8) -> Result<u32, SwdError> {
+    self.transfer(true, true, addr, 0)
+  }
+
+  pub fn ap_write(&mut self, addr: u8, value: u32) -> Result<(), SwdError> {
+    self.transfer(true, false, addr, value).map(|_| ())
+  }
+
+  fn transfer(&mut self, ap: bool, read: bool, addr: u8, value: u32) -> Result<u32, SwdError> {
+    for _ in 0..SWD_WAIT_RETRIES {
+      match self.transfer_once(ap, read, addr, value) {
+        Err(SwdError::Wait) => continue,
+        result => return result,
+      }
+    }
+    Err(SwdError::Wait)
+  }
+
+  fn transfer_once(&mut self, ap: bool, read: bool, addr: u8, value: u32) -> Result<u32, SwdError> {
+    let a = u32::from((addr >> 2) & 0b11);
+    let bits = u32::from(ap) | (u32::from(read) << 1) | (a << 2);
+    // Start, request bits, parity, stop (0), park (1)
+    let request = 1 | (bits << 1) | ((bits.count_ones() & 1) << 5) | (1 << 7);
+    self.write_bits(request, 8);
+    self.swdio.set_as_input(Pull::Up);
+    self.clock();
+    let ack = self.read_bits(3);
+    if ack != ACK_OK {
+      self.clock();
+      self.drive();
+      return Err(match ack {
+        ACK_WAIT => SwdError::Wait,
+        ACK_FAULT => SwdError::Fault,
+        _ => SwdError::NoAck,
+      });
+    }
+    let result = if read {
+      let data = self.read_bits(32);
+      let parity = self.read_bits(1);
+      self.clock();
+      self.drive();
+      if parity != data.count_ones() & 1 {
+        return Err(SwdError::Parity);
+      }
+      data
+    } else {
+      self.clock();
+      self.drive();
+      self.write_bits(value, 32);
+      self.write_bits(value.count_ones() & 1, 1);
+      0
+    };
+    // Idle cycles so the target finishes the transfer
+    self.write_bits(0, 8);
+    Ok(result)
+  }
+
+  // At least 50 cycles with SWDIO high, then idle
+  fn line_reset(&mut self) {
+    self.write_bits(u32::MAX, 32);
+    self.write_bits(u32::MAX, 24);
+  }
+
+  fn drive(&mut self) {
+    self.swdio.set_as_output(Speed::VeryHigh);
+  }
+
+  fn clock(&mut self) {
+    self.swclk.set_low();
+    cortex_m::asm::delay(self.half_period);
+    self.swclk.set_high();
+    cortex_m::asm::delay(self.half_period);
+  }
+
+  // `count` bits of `value`, LSB first
+  fn write_bits(&mut self, value: u32, count: u32) {
+    for i in 0..count {
+      self.swdio.set_level(Level::from((value >> i) & 1 != 0));
+      self.clock();
+    }
+  }
+
+  fn read_bits(&mut self, count: u32) -> u32 {
+    let mut value = 0;
+    for i in 0..count {
+      self.swclk.set_low();
+      cortex_m::asm::delay(self.half_period);
+      value |= u32::from(self.swdio.is_high()) << i;
+      self.swclk.set_high();
+      cortex_m::asm::delay(self.half_period);
+    }
+    value
+  }
+}
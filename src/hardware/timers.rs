@@ -2,7 +2,16 @@
 ///
 /// This module provides convenient abstractions for timer operations
 /// and timing utilities for the STM32F446RE microcontroller.
-use embassy_time::Timer;
+use embassy_stm32::Peri;
+use embassy_stm32::gpio::{OutputType, Pull};
+use embassy_stm32::interrupt::typelevel::{Binding, Interrupt};
+use embassy_stm32::time::Hertz;
+use embassy_stm32::timer::input_capture::{CapturePin, InputCapture};
+use embassy_stm32::timer::low_level::{CountingMode, Timer as LowLevelTimer};
+use embassy_stm32::timer::simple_pwm::{PwmPin, SimplePwm};
+use embassy_stm32::timer::{CaptureCompareInterruptHandler, Ch1, Channel, GeneralInstance4Channel, TimerPin};
+use embassy_time::{Duration, Instant, Ticker, Timer, block_for, with_timeout};
+use embedded_hal_async::delay::DelayNs;
 
 /// Common timing utilities and constants
 pub struct Timing;
@@ -20,8 +29,226 @@ impl Timing {
   /// RTC update interval
   pub const RTC_UPDATE_INTERVAL_MS: u64 = 1000;
 
-  /// Async delay in milliseconds
+  /// Encoder sampling interval (velocity is averaged over it)
+  pub const ENCODER_SAMPLE_MS: u64 = 100;
+
+  /// Async delay in milliseconds (a loop around it drifts by its own run time; see `every`)
   pub async fn delay_ms(ms: u64) {
     Timer::after_millis(ms).await;
   }
+
+  /// Drift-free schedule ticking every `period` from now, however long the work between ticks takes
+  pub fn every(period: Duration) -> Periodic {
+    Periodic {
+      ticker: Ticker::every(period),
+      period,
+      next: Instant::now() + period,
+      missed: 0,
+    }
+  }
+}
+
+/// Periodic schedule on an embassy `Ticker` (`Timing::every`)
+///
+/// Ticks fall on start + n * `period`, so time spent between `next` calls does not add up the
+/// way it does with a `delay_ms` loop. When the loop falls behind by whole periods (a long
+/// blocking call, a busy executor) the missed ticks are skipped, keeping the phase, instead of
+/// firing in a burst; `next` reports how many.
+pub struct Periodic {
+  ticker: Ticker,
+  period: Duration,
+  // Deadline of the coming tick
+  next: Instant,
+  missed: u32,
+}
+
+impl Periodic {
+  /// Wait for the next tick; returns the ticks missed since the previous one (0 when on time)
+  pub async fn next(&mut self) -> u32 {
+    let now = Instant::now();
+    let mut skipped = 0;
+    if now >= self.next + self.period {
+      skipped = ((now - self.next).as_ticks() / self.period.as_ticks().max(1)) as u32;
+      self.next += self.period * skipped;
+      self.ticker.reset_at(self.next);
+      self.missed = self.missed.saturating_add(skipped);
+    }
+    self.ticker.next().await;
+    self.next += self.period;
+    skipped
+  }
+
+  /// Ticks missed since the schedule started
+  pub fn missed(&self) -> u32 {
+    self.missed
+  }
+
+  pub fn period(&self) -> Duration {
+    self.period
+  }
+}
+
+/// Async delay for third-party `embedded-hal-async` drivers (e.g. `Driver::new(i2c, Timing)`)
+impl DelayNs for Timing {
+  async fn delay_ns(&mut self, ns: u32) {
+    Timer::after_nanos(ns as u64).await;
+  }
+
+  async fn delay_us(&mut self, us: u32) {
+    Timer::after_micros(us as u64).await;
+  }
+
+  async fn delay_ms(&mut self, ms: u32) {
+    Timer::after_millis(ms as u64).await;
+  }
+}
+
+/// Blocking delay for third-party `embedded-hal` 1.0 drivers (busy-waits: keep it to driver start-up)
+impl embedded_hal::delay::DelayNs for Timing {
+  fn delay_ns(&mut self, ns: u32) {
+    block_for(Duration::from_nanos(ns as u64));
+  }
+}
+
+/// General-purpose hardware timers (TIMx)
+///
+/// Thin constructors over embassy-stm32's timer drivers for the three common jobs: precise
+/// periodic interrupts, input capture and PWM generation. `BoardConfig::HW_TIMERS_FREE` lists
+/// the timers not already claimed on each board (TIM5 always drives embassy-time).
+pub struct HwTimer;
+
+impl HwTimer {
+  /// Start `tim` firing its update interrupt at `freq`; pair with `hw_timer_interrupt!` for the handler
+  pub fn periodic<T: GeneralInstance4Channel>(tim: Peri<'static, T>, freq: Hertz) -> LowLevelTimer<'static, T> {
+    let timer = LowLevelTimer::new(tim);
+    timer.set_frequency(freq);
+    timer.enable_update_interrupt(true);
+    T::UpdateInterrupt::unpend();
+    unsafe { T::UpdateInterrupt::enable() };
+    timer.start();
+    timer
+  }
+
+  /// PWM on channel 1 of `tim` at `freq` (starts disabled: `pwm.ch1().set_duty_cycle_percent(..)` then `.enable()`).
+  /// The channel from `pwm.ch1()` implements `embedded_hal::pwm::SetDutyCycle` for driver crates.
+  pub fn pwm<T: GeneralInstance4Channel>(tim: Peri<'static, T>, pin: Peri<'static, impl TimerPin<T, Ch1>>, freq: Hertz) -> SimplePwm<'static, T> {
+    let ch1 = PwmPin::new(pin, OutputType::PushPull);
+    SimplePwm::new(tim, Some(ch1), None, None, None, freq, CountingMode::EdgeAlignedUp)
+  }
+
+  /// Input capture on channel 1 of `tim`, counting at `freq` (`capture.wait_for_rising_edge(Channel::Ch1).await`)
+  pub fn capture<T: GeneralInstance4Channel>(
+    tim: Peri<'static, T>,
+    pin: Peri<'static, impl TimerPin<T, Ch1>>,
+    irqs: impl Binding<T::CaptureCompareInterrupt, CaptureCompareInterruptHandler<T>> + 'static,
+    freq: Hertz,
+  ) -> InputCapture<'static, T> {
+    let ch1 = CapturePin::new(pin, Pull::None);
+    InputCapture::new(tim, Some(ch1), None, None, None, irqs, freq, CountingMode::EdgeAlignedUp)
+  }
+}
+
+/// Define the update interrupt handler for a timer started with `HwTimer::periodic`
+///
+/// `hw_timer_interrupt!(TIM3, TIM3, on_tick);` clears the update flag and calls `on_tick()` in
+/// interrupt context every period (keep it short: set a flag, signal a task, toggle a pin).
+#[macro_export]
+macro_rules! hw_timer_interrupt {
+  ($irq:ident, $tim:ident, $callback:path) => {
+    const _: () = {
+      use embassy_stm32::interrupt;
+
+      #[interrupt]
+      fn $irq() {
+        embassy_stm32::pac::$tim.sr().modify(|r| r.set_uif(false));
+        $callback();
+      }
+    };
+  };
+}
+
+/// Frequency / duty-cycle / pulse-count measurement on channel 1 of a timer (flow meters, fan tach, encoders)
+///
+/// Built on input capture: each edge raises a capture interrupt, so it suits signals up to a few
+/// tens of kHz. Periods are measured in timer ticks at `tick` and must stay below 65536 ticks
+/// (pick `tick` so the slowest expected period fits).
+pub struct PulseCounter<T: GeneralInstance4Channel> {
+  capture: InputCapture<'static, T>,
+  tick_hz: u32,
+}
+
+/// Result of a gated pulse count
+#[derive(Copy, Clone, Debug, PartialEq, defmt::Format)]
+pub struct PulseMeasurement {
+  /// Rising edges seen during the gate
+  pub pulses: u32,
+  /// `pulses` scaled to one second
+  pub hz: f32,
+}
+
+impl<T: GeneralInstance4Channel> PulseCounter<T> {
+  pub fn new(
+    tim: Peri<'static, T>,
+    pin: Peri<'static, impl TimerPin<T, Ch1>>,
+    irqs: impl Binding<T::CaptureCompareInterrupt, CaptureCompareInterruptHandler<T>> + 'static,
+    tick: Hertz,
+  ) -> Self {
+    Self {
+      capture: HwTimer::capture(tim, pin, irqs, tick),
+      tick_hz: tick.0,
+    }
+  }
+
+  /// Ticks between captures (16-bit wrap-around safe)
+  fn ticks(from: u32, to: u32) -> u32 {
+    to.wrapping_sub(from) & 0xFFFF
+  }
+
+  /// Period of one cycle in ticks, or None if no full cycle arrives within `timeout`
+  pub async fn period_ticks(&mut self, timeout: Duration) -> Option<u32> {
+    with_timeout(timeout, async {
+      let start = self.capture.wait_for_rising_edge(Channel::Ch1).await;
+      let end = self.capture.wait_for_rising_edge(Channel::Ch1).await;
+      Self::ticks(start, end)
+    })
+    .await
+    .ok()
+  }
+
+  /// Frequency from a single period (best for low frequencies), or None without a signal
+  pub async fn frequency_hz(&mut self, timeout: Duration) -> Option<f32> {
+    let period = self.period_ticks(timeout).await?;
+    (period > 0).then(|| self.tick_hz as f32 / period as f32)
+  }
+
+  /// High time as a fraction of the period (0.0..=1.0), or None without a signal
+  pub async fn duty_cycle(&mut self, timeout: Duration) -> Option<f32> {
+    with_timeout(timeout, async {
+      let rise = self.capture.wait_for_rising_edge(Channel::Ch1).await;
+      let fall = self.capture.wait_for_falling_edge(Channel::Ch1).await;
+      let next = self.capture.wait_for_rising_edge(Channel::Ch1).await;
+      let period = Self::ticks(rise, next);
+      (period > 0).then(|| Self::ticks(rise, fall) as f32 / period as f32)
+    })
+    .await
+    .ok()
+    .flatten()
+  }
+
+  /// Count rising edges for `gate` (best for high frequencies / totalizing flow meters)
+  pub async fn measure(&mut self, gate: Duration) -> PulseMeasurement {
+    let deadline = Instant::now() + gate;
+    let mut pulses = 0;
+    loop {
+      let remaining = deadline.saturating_duration_since(Instant::now());
+      if with_timeout(remaining, self.capture.wait_for_rising_edge(Channel::Ch1)).await.is_err() {
+        break;
+      }
+      pulses += 1;
+    }
+    PulseMeasurement {
+      pulses,
+      hz: pulses as f32 * 1_000_000.0 / gate.as_micros().max(1) as f32,
+    }
+  }
 }
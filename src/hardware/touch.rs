@@ -0,0 +1,193 @@
+/// Capacitive Touch Pads on Plain GPIOs (charge timing)
+///
+/// A pad (copper area, foil, a screw head) on a GPIO forms a small capacitor to ground; a finger
+/// adds a few pF. Each measurement discharges the pad by driving the pin low, then releases it to
+/// an input with the internal pull-up (~40k) and counts core cycles (DWT cycle counter) until it
+/// reads high: the charge time grows with the capacitance. For weak pads, fit an external 1M
+/// pull-up to 3.3 V for longer, easier to tell apart times.
+///
+/// `TouchPad::calibrate` records the untouched baseline (keep fingers off at start-up); a reading
+/// `threshold_pct` above it counts as touched and the pad is released again below half that rise.
+/// While untouched the baseline follows slow drift (temperature, humidity). `TouchPads` polls a
+/// set of pads every `TOUCH_POLL_MS` and turns them into an async stream of `TouchEvent`s.
+use cortex_m::peripheral::DWT;
+use embassy_stm32::Peri;
+use embassy_stm32::gpio::{Flex, Pin, Pull, Speed};
+use embassy_time::Timer;
+use heapless::{Deque, Vec};
+
+/// Pads a `TouchPads` set can hold
+pub const TOUCH_MAX_PADS: usize = 8;
+/// Measurements averaged per reading
+pub const TOUCH_SAMPLES: u32 = 8;
+/// Readings averaged for the baseline
+pub const TOUCH_CALIBRATION_READINGS: u32 = 16;
+/// Give up on a measurement after this many cycles (pin shorted low or no pull-up)
+pub const TOUCH_TIMEOUT_CYCLES: u32 = 50_000;
+/// `TouchPads` polling interval
+pub const TOUCH_POLL_MS: u64 = 20;
+/// Default rise over the baseline that counts as a touch, in percent
+pub const TOUCH_THRESHOLD_PCT: u32 = 30;
+/// Cycles the pad is held low before a measurement
+const DISCHARGE_CYCLES: u32 = 200;
+/// Baseline drift tracking: move 1/2^n of the way to each untouched reading
+const DRIFT_SHIFT: u32 = 4;
+
+/// State change of one pad (index in the `TouchPads` set)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum TouchEvent {
+  Touched(u8),
+  Released(u8),
+}
+
+/// One touch pad on a GPIO
+pub struct TouchPad<'d> {
+  pin: Flex<'d>,
+  baseline: u32,
+  threshold_pct: u32,
+  touched: bool,
+}
+
+impl<'d> TouchPad<'d> {
+  /// Pad on `pin`, not calibrated yet (starts the DWT cycle counter)
+  pub fn new(pin: Peri<'d, impl Pin>) -> Self {
+    // SAFETY: only enables the cycle counter, as task_metrics and cs_monitor do; nothing resets it
+    let mut cp = unsafe { cortex_m::Peripherals::steal() };
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+    let mut pin = Flex::new(pin);
+    pin.set_low();
+    pin.set_as_output(Speed::Low);
+    Self {
+      pin,
+      baseline: 0,
+      threshold_pct: TOUCH_THRESHOLD_PCT,
+      touched: false,
+    }
+  }
+
+  /// Rise over the baseline that counts as a touch (`TOUCH_THRESHOLD_PCT` by default)
+  pub fn with_threshold(mut self, pct: u32) -> Self {
+    self.threshold_pct = pct;
+    self
+  }
+
+  /// One charge time in core cycles (`TOUCH_TIMEOUT_CYCLES` if the pin never rose)
+  pub fn measure(&mut self) -> u32 {
+    self.pin.set_low();
+    self.pin.set_as_output(Speed::Low);
+    cortex_m::asm::delay(DISCHARGE_CYCLES);
+    // Interrupts would add their run time to the count
+    cortex_m::interrupt::free(|_| {
+      let start = DWT::cycle_count();
+      self.pin.set_as_input(Pull::Up);
+      let mut elapsed = 0;
+      while self.pin.is_low() && elapsed < TOUCH_TIMEOUT_CYCLES {
+        elapsed = DWT::cycle_count().wrapping_sub(start);
+      }
+      self.pin.set_as_output(Speed::Low);
+      elapsed
+    })
+  }
+
+  /// Average of `TOUCH_SAMPLES` measurements
+  pub fn read(&mut self) -> u32 {
+    (0..TOUCH_SAMPLES).map(|_| self.measure()).sum::<u32>() / TOUCH_SAMPLES
+  }
+
+  /// Record the untouched baseline from `TOUCH_CALIBRATION_READINGS` readings 1 ms apart
+  pub async fn calibrate(&mut self) {
+    let mut sum = 0;
+    for _ in 0..TOUCH_CALIBRATION_READINGS {
+      sum += self.read();
+      Timer::after_millis(1).await;
+    }
+    self.baseline = (sum / TOUCH_CALIBRATION_READINGS).max(1);
+    self.touched = false;
+  }
+
+  /// Untouched charge time (0 before `calibrate`)
+  pub fn baseline(&self) -> u32 {
+    self.baseline
+  }
+
+  pub fn is_touched(&self) -> bool {
+    self.touched
+  }
+
+  /// Take a reading and update the touched state; returns the new state when it changed
+  pub fn update(&mut self) -> Option<bool> {
+    let reading = self.read();
+    let rise = reading.saturating_sub(self.baseline) * 100 / self.baseline.max(1);
+    let touched = if self.touched { rise > self.threshold_pct / 2 } else { rise > self.threshold_pct };
+    if !touched {
+      // Follow slow drift both ways (a reading below the baseline pulls it down at once)
+      self.baseline = if reading < self.baseline {
+        reading.max(1)
+      } else {
+        self.baseline + ((reading - self.baseline) >> DRIFT_SHIFT)
+      };
+    }
+    (touched != self.touched).then(|| {
+      self.touched = touched;
+      touched
+    })
+  }
+}
+
+/// A set of pads polled together, producing touched/released events
+pub struct TouchPads<'d> {
+  pads: Vec<TouchPad<'d>, TOUCH_MAX_PADS>,
+  pending: Deque<TouchEvent, TOUCH_MAX_PADS>,
+}
+
+impl<'d> TouchPads<'d> {
+  pub const fn new() -> Self {
+    Self {
+      pads: Vec::new(),
+      pending: Deque::new(),
+    }
+  }
+
+  /// Add a pad; returns its index in events, or gives it back if the set is full
+  pub fn add(&mut self, pad: TouchPad<'d>) -> Result<u8, TouchPad<'d>> {
+    self.pads.push(pad)?;
+    Ok(self.pads.len() as u8 - 1)
+  }
+
+  /// Calibrate every pad (untouched)
+  pub async fn calibrate(&mut self) {
+    for pad in self.pads.iter_mut() {
+      pad.calibrate().await;
+    }
+  }
+
+  pub fn pad(&self, index: u8) -> Option<&TouchPad<'d>> {
+    self.pads.get(index as usize)
+  }
+
+  /// Next touched/released event, polling the pads every `TOUCH_POLL_MS` until one changes
+  pub async fn next_event(&mut self) -> TouchEvent {
+    loop {
+      if let Some(event) = self.pending.pop_front() {
+        return event;
+      }
+      Timer::after_millis(TOUCH_POLL_MS).await;
+      for (index, pad) in self.pads.iter_mut().enumerate() {
+        let event = match pad.update() {
+          Some(true) => TouchEvent::Touched(index as u8),
+          Some(false) => TouchEvent::Released(index as u8),
+          None => continue,
+        };
+        // One event per pad per poll, so the queue (one slot per pad) cannot overflow
+        self.pending.push_back(event).ok();
+      }
+    }
+  }
+}
+
+impl Default for TouchPads<'_> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
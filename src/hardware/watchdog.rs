@@ -0,0 +1,134 @@
+/// Independent Watchdog (IWDG) Abstraction Layer
+///
+/// This module wraps the IWDG with the settings the board init applies (`WatchdogConfig`: timeout,
+/// whether to start it at all, and whether it pauses while a debugger halts the core, so
+/// breakpoints and single-stepping do not reset the MCU). With the `debug_no_watchdog` feature
+/// the board default leaves it off in debug builds altogether.
+/// Once started the IWDG cannot be stopped or retuned until the next reset, so the settings are
+/// fixed at init. `Watchdog::pet` records when it was last fed, which lets any task (a supervisor
+/// deciding whether to keep petting, a shell command) read the settings back with `config()` and
+/// estimate the time left before a reset with `wdt_remaining_hint()`.
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use embassy_stm32::Peri;
+use embassy_stm32::peripherals::IWDG;
+use embassy_stm32::wdg::IndependentWatchdog;
+use embassy_time::{Duration, Instant};
+
+// DBGMCU_APB1_FZ: stop the watchdog counters while the core is halted (STM32F4)
+const DBGMCU_APB1_FZ: *mut u32 = 0xE004_2008 as *mut u32;
+const DBG_WWDG_STOP: u32 = 1 << 11;
+const DBG_IWDG_STOP: u32 = 1 << 12;
+
+/// Watchdog settings applied by the board init
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct WatchdogConfig {
+  /// Time without a `pet` before the MCU resets
+  pub timeout_us: u32,
+  /// Start the watchdog (when false it is configured but never unleashed)
+  pub enabled: bool,
+  /// Pause the IWDG/WWDG counters while a debugger halts the core (no effect when running)
+  pub freeze_on_halt: bool,
+}
+
+impl WatchdogConfig {
+  /// Enabled, resetting after `timeout_us`, paused while halted
+  pub const fn new(timeout_us: u32) -> Self {
+    Self {
+      timeout_us,
+      enabled: true,
+      freeze_on_halt: true,
+    }
+  }
+
+  /// Same settings with another timeout
+  pub const fn timeout_us(mut self, timeout_us: u32) -> Self {
+    self.timeout_us = timeout_us;
+    self
+  }
+
+  /// Same settings, started or not (e.g. `.enabled(!cfg!(debug_assertions))`)
+  pub const fn enabled(mut self, enabled: bool) -> Self {
+    self.enabled = enabled;
+    self
+  }
+
+  /// Same settings, paused while halted or not
+  pub const fn freeze_on_halt(mut self, freeze: bool) -> Self {
+    self.freeze_on_halt = freeze;
+    self
+  }
+}
+
+static TIMEOUT_US: AtomicU32 = AtomicU32::new(0);
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static FREEZE_ON_HALT: AtomicBool = AtomicBool::new(false);
+// Uptime (ms, wrapping) of the last pet
+static LAST_PET_MS: AtomicU32 = AtomicU32::new(0);
+
+/// The IWDG as handed out by the board init
+pub struct Watchdog {
+  iwdg: IndependentWatchdog<'static, IWDG>,
+}
+
+impl Watchdog {
+  /// Configure the IWDG and start it if `config.enabled`
+  pub fn new(iwdg: Peri<'static, IWDG>, config: WatchdogConfig) -> Self {
+    set_freeze_on_halt(config.freeze_on_halt);
+    let mut iwdg = IndependentWatchdog::new(iwdg, config.timeout_us);
+    TIMEOUT_US.store(config.timeout_us, Ordering::Relaxed);
+    ENABLED.store(config.enabled, Ordering::Relaxed);
+    FREEZE_ON_HALT.store(config.freeze_on_halt, Ordering::Relaxed);
+    LAST_PET_MS.store(now_ms(), Ordering::Relaxed);
+    if config.enabled {
+      iwdg.unleash();
+    } else {
+      defmt::warn!("Watchdog: disabled");
+    }
+    Self { iwdg }
+  }
+
+  /// Reload the counter (harmless while disabled)
+  pub fn pet(&mut self) {
+    self.iwdg.pet();
+    LAST_PET_MS.store(now_ms(), Ordering::Relaxed);
+  }
+}
+
+/// Settings in force (timeout 0 before the board init has run)
+pub fn config() -> WatchdogConfig {
+  WatchdogConfig {
+    timeout_us: TIMEOUT_US.load(Ordering::Relaxed),
+    enabled: ENABLED.load(Ordering::Relaxed),
+    freeze_on_halt: FREEZE_ON_HALT.load(Ordering::Relaxed),
+  }
+}
+
+/// Roughly how long until the watchdog resets the MCU unless it is petted (None while disabled).
+/// Based on the last `Watchdog::pet`; the IWDG runs from the ~32 kHz LSI, which is only
+/// accurate to a few percent.
+pub fn wdt_remaining_hint() -> Option<Duration> {
+  if !ENABLED.load(Ordering::Relaxed) {
+    return None;
+  }
+  let elapsed_ms = now_ms().wrapping_sub(LAST_PET_MS.load(Ordering::Relaxed)) as u64;
+  let timeout_ms = TIMEOUT_US.load(Ordering::Relaxed) as u64 / 1000;
+  Some(Duration::from_millis(timeout_ms.saturating_sub(elapsed_ms)))
+}
+
+// Set or clear the DBGMCU watchdog freeze bits (kept across system resets, cleared at power-on)
+fn set_freeze_on_halt(freeze: bool) {
+  // SAFETY: DBGMCU_APB1_FZ is a debug-only register; nothing else in the firmware writes it
+  unsafe {
+    let mut value = DBGMCU_APB1_FZ.read_volatile();
+    if freeze {
+      value |= DBG_IWDG_STOP | DBG_WWDG_STOP;
+    } else {
+      value &= !(DBG_IWDG_STOP | DBG_WWDG_STOP);
+    }
+    DBGMCU_APB1_FZ.write_volatile(value);
+  }
+}
+
+fn now_ms() -> u32 {
+  Instant::now().as_millis() as u32
+}
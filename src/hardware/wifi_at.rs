@@ -0,0 +1,317 @@
+/// ESP-AT WiFi Co-processor Driver (ESP8266 / ESP32 on a second UART)
+///
+/// This module drives an ESP8266 or ESP32 running Espressif's stock AT firmware as a WiFi modem:
+/// `WifiAt` configures it (station mode, single connection), joins an access point, and opens,
+/// writes and reads a TCP connection, with the module's output parsed by `protocol::at`.
+/// `comm_link` bridges that connection into the comm transport the way `service::net::comm_port`
+/// does for Ethernet (received bytes go through a `comm::FrameReceiver`, frames written to
+/// `WifiCommTx` go out), giving boards without a MAC a wireless comm path. Wire the module to a
+/// free UART (`BoardConfig::WIFI_UART_PINS`) at its default 115200 baud and pass the RX/TX halves;
+/// the receiver must buffer between reads (`BufferedUart`, `RingBufferedUartRx`) or bytes are lost.
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pipe::Pipe;
+use embassy_time::{Duration, Instant, Timer, with_deadline, with_timeout};
+use embedded_io_async::{Read, Write};
+use heapless::{Deque, String};
+
+use crate::protocol::at::{AtEvent, AtParser};
+use crate::service::comm::{COMMS_FRAMED_MAX, COMMS_TX_POLL_MS, FrameReceiver};
+
+/// Timeout of a configuration command
+pub const WIFI_CMD_TIMEOUT_MS: u64 = 2_000;
+/// Timeout of joining an access point (DHCP included)
+pub const WIFI_JOIN_TIMEOUT_MS: u64 = 20_000;
+/// Timeout of opening a TCP connection
+pub const WIFI_CONNECT_TIMEOUT_MS: u64 = 10_000;
+/// Largest single `send` (the `AT+CIPSEND` limit)
+pub const WIFI_MAX_SEND: usize = 2048;
+/// Delay between `comm_link` connection attempts
+pub const WIFI_RECONNECT_MS: u64 = 5_000;
+
+// Longest response line kept (`+CIFSR:...`, ...), also the size of `+IPD` data parts
+const AT_LINE_MAX: usize = 128;
+// Socket data waiting for `read` (excess is dropped)
+const WIFI_RX_BUF: usize = 512;
+const AT_CMD_MAX: usize = 160;
+const READ_CHUNK: usize = 64;
+// Outgoing comm frames waiting for the connection
+const WIFI_TX_PIPE_SIZE: usize = 2 * COMMS_FRAMED_MAX;
+
+static COMM_TX: Pipe<CriticalSectionRawMutex, WIFI_TX_PIPE_SIZE> = Pipe::new();
+static TX_DROPS: AtomicU32 = AtomicU32::new(0);
+
+/// WiFi module errors
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum WifiError {
+  /// No result within the timeout (module missing, wrong baud rate or busy)
+  Timeout,
+  /// The module answered `ERROR`/`FAIL` (wrong credentials, host unreachable, ...)
+  Rejected,
+  /// Data was not sent (`SEND FAIL`, or the connection closed)
+  SendFailed,
+  /// No TCP connection open
+  NotConnected,
+  /// Command or data too long
+  TooLong,
+  /// UART read/write failed
+  Uart,
+}
+
+// Result a request waits for
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Expect {
+  Ok,
+  Prompt,
+  SendOk,
+}
+
+/// ESP-AT module on a UART
+pub struct WifiAt<R: Read, W: Write> {
+  rx: R,
+  tx: W,
+  parser: AtParser<AT_LINE_MAX>,
+  received: Deque<u8, WIFI_RX_BUF>,
+  connected: bool,
+}
+
+impl<R: Read, W: Write> WifiAt<R, W> {
+  pub fn new(rx: R, tx: W) -> Self {
+    Self {
+      rx,
+      tx,
+      parser: AtParser::new(),
+      received: Deque::new(),
+      connected: false,
+    }
+  }
+
+  /// Check the module answers, then set echo off, station mode and single-connection mode
+  pub async fn init(&mut self) -> Result<(), WifiError> {
+    // The first AT may be lost to a module still booting
+    let mut result = Err(WifiError::Timeout);
+    for _ in 0..3 {
+      result = self.command("AT", WIFI_CMD_TIMEOUT_MS).await;
+      if result.is_ok() {
+        break;
+      }
+    }
+    result?;
+    self.command("ATE0", WIFI_CMD_TIMEOUT_MS).await?;
+    self.command("AT+CWMODE=1", WIFI_CMD_TIMEOUT_MS).await?;
+    self.command("AT+CIPMUX=0", WIFI_CMD_TIMEOUT_MS).await
+  }
+
+  /// Join access point `ssid` (returns once the module has an address)
+  pub async fn join(&mut self, ssid: &str, password: &str) -> Result<(), WifiError> {
+    let mut cmd = String::<AT_CMD_MAX>::new();
+    cmd.push_str("AT+CWJAP=").map_err(|_| WifiError::TooLong)?;
+    push_quoted(&mut cmd, ssid)?;
+    cmd.push(',').map_err(|_| WifiError::TooLong)?;
+    push_quoted(&mut cmd, password)?;
+    self.command(&cmd, WIFI_JOIN_TIMEOUT_MS).await
+  }
+
+  /// Open a TCP connection to `host` (name or address) on `port`
+  pub async fn connect_tcp(&mut self, host: &str, port: u16) -> Result<(), WifiError> {
+    let mut cmd = String::<AT_CMD_MAX>::new();
+    cmd.push_str("AT+CIPSTART=\"TCP\",").map_err(|_| WifiError::TooLong)?;
+    push_quoted(&mut cmd, host)?;
+    write!(cmd, ",{}", port).map_err(|_| WifiError::TooLong)?;
+    self.command(&cmd, WIFI_CONNECT_TIMEOUT_MS).await?;
+    self.connected = true;
+    Ok(())
+  }
+
+  /// Close the TCP connection
+  pub async fn close(&mut self) -> Result<(), WifiError> {
+    self.connected = false;
+    self.command("AT+CIPCLOSE", WIFI_CMD_TIMEOUT_MS).await
+  }
+
+  /// True while the TCP connection is open (cleared when the module reports it closed)
+  pub fn is_connected(&self) -> bool {
+    self.connected
+  }
+
+  /// Send `data` (at most `WIFI_MAX_SEND` bytes) over the TCP connection
+  pub async fn send(&mut self, data: &[u8]) -> Result<(), WifiError> {
+    if !self.connected {
+      return Err(WifiError::NotConnected);
+    }
+    if data.len() > WIFI_MAX_SEND {
+      return Err(WifiError::TooLong);
+    }
+    if data.is_empty() {
+      return Ok(());
+    }
+    let mut cmd = String::<AT_CMD_MAX>::new();
+    write!(cmd, "AT+CIPSEND={}", data.len()).map_err(|_| WifiError::TooLong)?;
+    self.request(&cmd, Expect::Prompt, WIFI_CMD_TIMEOUT_MS).await?;
+    self.tx.write_all(data).await.map_err(|_| WifiError::Uart)?;
+    self.wait(Expect::SendOk, WIFI_CMD_TIMEOUT_MS).await
+  }
+
+  /// Process module output for up to `timeout_ms` (returns early once something arrives), so
+  /// received data becomes available to `read`
+  pub async fn poll(&mut self, timeout_ms: u64) -> Result<(), WifiError> {
+    let mut chunk = [0u8; READ_CHUNK];
+    match with_timeout(Duration::from_millis(timeout_ms), self.rx.read(&mut chunk)).await {
+      Ok(Ok(n)) => {
+        self.process(&chunk[..n], None);
+        Ok(())
+      }
+      Ok(Err(_)) => Err(WifiError::Uart),
+      Err(_) => Ok(()),
+    }
+  }
+
+  /// Move received TCP data into `buf`; returns the number of bytes (0: nothing pending)
+  pub fn read(&mut self, buf: &mut [u8]) -> usize {
+    let mut n = 0;
+    while n < buf.len() {
+      match self.received.pop_front() {
+        Some(b) => buf[n] = b,
+        None => break,
+      }
+      n += 1;
+    }
+    n
+  }
+
+  /// Carry the comm protocol over a TCP connection to `host:port`, reconnecting whenever it drops
+  /// (run from an application task after `init` and `join`)
+  pub async fn comm_link(&mut self, host: &str, port: u16) -> ! {
+    let mut receiver = FrameReceiver::new();
+    let mut chunk = [0u8; READ_CHUNK];
+    let mut frame = [0u8; COMMS_FRAMED_MAX];
+    loop {
+      if let Err(e) = self.connect_tcp(host, port).await {
+        defmt::warn!("WiFi: comm link connect failed: {}", e);
+        Timer::after_millis(WIFI_RECONNECT_MS).await;
+        continue;
+      }
+      defmt::info!("WiFi: comm link connected");
+      // Nothing queued for a previous connection is meant for this one
+      COMM_TX.clear();
+      receiver.reset();
+      while self.connected {
+        if self.poll(COMMS_TX_POLL_MS).await.is_err() {
+          break;
+        }
+        loop {
+          let n = self.read(&mut chunk);
+          if n == 0 {
+            break;
+          }
+          receiver.push(&chunk[..n]);
+        }
+        while let Ok(n) = COMM_TX.try_read(&mut frame) {
+          if self.send(&frame[..n]).await.is_err() {
+            let _ = self.close().await;
+            break;
+          }
+        }
+      }
+      defmt::info!("WiFi: comm link closed");
+    }
+  }
+
+  // Send `cmd` and wait for its result
+  async fn command(&mut self, cmd: &str, timeout_ms: u64) -> Result<(), WifiError> {
+    self.request(cmd, Expect::Ok, timeout_ms).await
+  }
+
+  async fn request(&mut self, cmd: &str, expect: Expect, timeout_ms: u64) -> Result<(), WifiError> {
+    self.tx.write_all(cmd.as_bytes()).await.map_err(|_| WifiError::Uart)?;
+    self.tx.write_all(b"\r\n").await.map_err(|_| WifiError::Uart)?;
+    self.wait(expect, timeout_ms).await
+  }
+
+  async fn wait(&mut self, expect: Expect, timeout_ms: u64) -> Result<(), WifiError> {
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+    let mut chunk = [0u8; READ_CHUNK];
+    loop {
+      let n = match with_deadline(deadline, self.rx.read(&mut chunk)).await {
+        Ok(Ok(n)) => n,
+        Ok(Err(_)) => return Err(WifiError::Uart),
+        Err(_) => return Err(WifiError::Timeout),
+      };
+      if let Some(result) = self.process(&chunk[..n], Some(expect)) {
+        return result;
+      }
+    }
+  }
+
+  // Feed module output to the parser, keeping socket data and link state; returns the outcome of
+  // the awaited result if it arrived (the rest of `bytes` is still processed)
+  fn process(&mut self, bytes: &[u8], expect: Option<Expect>) -> Option<Result<(), WifiError>> {
+    let mut result = None;
+    for &b in bytes {
+      let outcome = match self.parser.push(b) {
+        None | Some(AtEvent::Line(_)) => None,
+        Some(AtEvent::Data(data)) => {
+          for &d in data {
+            if self.received.push_back(d).is_err() {
+              break;
+            }
+          }
+          None
+        }
+        Some(AtEvent::Closed) => {
+          self.connected = false;
+          (expect == Some(Expect::SendOk)).then_some(Err(WifiError::SendFailed))
+        }
+        Some(AtEvent::Ok) => (expect == Some(Expect::Ok)).then_some(Ok(())),
+        Some(AtEvent::Prompt) => (expect == Some(Expect::Prompt)).then_some(Ok(())),
+        Some(AtEvent::SendOk) => (expect == Some(Expect::SendOk)).then_some(Ok(())),
+        Some(AtEvent::Error) | Some(AtEvent::SendFail) => expect.map(|e| Err(if e == Expect::SendOk { WifiError::SendFailed } else { WifiError::Rejected })),
+      };
+      if result.is_none() {
+        result = outcome;
+      }
+    }
+    result
+  }
+}
+
+/// Frames dropped because the WiFi comm link was not draining `WifiCommTx`
+pub fn tx_drop_count() -> u32 {
+  TX_DROPS.load(Ordering::Relaxed)
+}
+
+/// Writer onto the WiFi comm link (frames are dropped whole while it is full or not connected)
+pub struct WifiCommTx;
+
+impl embedded_io::ErrorType for WifiCommTx {
+  type Error = core::convert::Infallible;
+}
+
+impl embedded_io::Write for WifiCommTx {
+  fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+    // A frame cut short would desync the host, so it goes in whole or not at all
+    if COMM_TX.free_capacity() < buf.len() {
+      TX_DROPS.fetch_add(1, Ordering::Relaxed);
+      return Ok(buf.len());
+    }
+    Ok(COMM_TX.try_write(buf).unwrap_or(buf.len()))
+  }
+
+  fn flush(&mut self) -> Result<(), Self::Error> {
+    Ok(())
+  }
+}
+
+// Append `value` as a quoted AT string argument (`"`, `,` and `\` escaped with `\`)
+fn push_quoted(cmd: &mut String<AT_CMD_MAX>, value: &str) -> Result<(), WifiError> {
+  let mut push = |c| cmd.push(c).map_err(|_| WifiError::TooLong);
+  push('"')?;
+  for c in value.chars() {
+    if matches!(c, '"' | ',' | '\\') {
+      push('\\')?;
+    }
+    push(c)?;
+  }
+  push('"')
+}
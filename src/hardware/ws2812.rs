@@ -0,0 +1,101 @@
+/// WS2812 / NeoPixel Hardware Abstraction Layer
+///
+/// This module drives addressable RGB LEDs from channel 1 of a timer running 800 kHz PWM: the
+/// update DMA rewrites the compare register every period, so each PWM pulse is one bit
+/// (~0.4 us high = 0, ~0.8 us high = 1) and the CPU is free while a frame goes out.
+/// Frames are limited to `WS2812_MAX_PIXELS` (3 KB of DMA buffer).
+use embassy_stm32::Peri;
+use embassy_stm32::time::Hertz;
+use embassy_stm32::timer::simple_pwm::SimplePwm;
+use embassy_stm32::timer::{Ch1, Channel, GeneralInstance4Channel, TimerPin, UpDma};
+use heapless::Vec;
+
+use crate::hardware::HwTimer;
+
+/// Longest supported strip
+pub const WS2812_MAX_PIXELS: usize = 64;
+
+// Zero-duty periods after the data (>= 50 us low latches the frame)
+const RESET_SLOTS: usize = 48;
+const BITS_PER_PIXEL: usize = 24;
+
+/// 8-bit RGB color
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, defmt::Format)]
+pub struct Rgb {
+  pub r: u8,
+  pub g: u8,
+  pub b: u8,
+}
+
+impl Rgb {
+  pub const OFF: Rgb = Rgb::new(0, 0, 0);
+
+  pub const fn new(r: u8, g: u8, b: u8) -> Self {
+    Self { r, g, b }
+  }
+
+  /// Scale by `brightness` (0 = off, 255 = unchanged)
+  pub fn dim(self, brightness: u8) -> Self {
+    let scale = |c: u8| ((c as u16 * (brightness as u16 + 1)) >> 8) as u8;
+    Self::new(scale(self.r), scale(self.g), scale(self.b))
+  }
+
+  /// Color wheel: red -> green -> blue -> red as `pos` goes 0..=255
+  pub const fn wheel(pos: u8) -> Self {
+    match pos {
+      0..=84 => Self::new(255 - pos * 3, pos * 3, 0),
+      85..=169 => Self::new(0, 255 - (pos - 85) * 3, (pos - 85) * 3),
+      _ => Self::new((pos - 170) * 3, 0, 255 - (pos - 170) * 3),
+    }
+  }
+}
+
+/// WS2812 strip on channel 1 of a timer, fed by the timer's update DMA channel
+pub struct Ws2812<T: GeneralInstance4Channel, D: UpDma<T>> {
+  pwm: SimplePwm<'static, T>,
+  dma: Peri<'static, D>,
+  duty_0: u16,
+  duty_1: u16,
+  frame: Vec<u16, { WS2812_MAX_PIXELS * BITS_PER_PIXEL + RESET_SLOTS }>,
+}
+
+impl<T: GeneralInstance4Channel, D: UpDma<T>> Ws2812<T, D> {
+  /// Strip data line on `pin` (TIMx_CH1), `dma` is the DMA channel wired to TIMx_UP
+  pub fn new(tim: Peri<'static, T>, pin: Peri<'static, impl TimerPin<T, Ch1>>, dma: Peri<'static, D>) -> Self {
+    let mut pwm = HwTimer::pwm(tim, pin, Hertz(800_000));
+    let max = pwm.max_duty_cycle();
+    pwm.ch1().set_duty_cycle_fully_off();
+    pwm.ch1().enable();
+    Self {
+      pwm,
+      dma,
+      // 0: ~32% of the 1.25 us period high, 1: ~64%
+      duty_0: max * 8 / 25,
+      duty_1: max * 16 / 25,
+      frame: Vec::new(),
+    }
+  }
+
+  /// Send `pixels` to the strip (extra pixels beyond `WS2812_MAX_PIXELS` are ignored)
+  pub async fn set_pixels(&mut self, pixels: &[Rgb]) {
+    self.frame.clear();
+    for pixel in pixels.iter().take(WS2812_MAX_PIXELS) {
+      // WS2812 expects GRB, most significant bit first
+      let grb = ((pixel.g as u32) << 16) | ((pixel.r as u32) << 8) | pixel.b as u32;
+      for bit in (0..BITS_PER_PIXEL).rev() {
+        let duty = if grb & (1 << bit) != 0 { self.duty_1 } else { self.duty_0 };
+        self.frame.push(duty).ok();
+      }
+    }
+    for _ in 0..RESET_SLOTS {
+      self.frame.push(0).ok();
+    }
+    self.pwm.waveform_up(self.dma.reborrow(), Channel::Ch1, &self.frame).await;
+  }
+
+  /// Turn the first `count` pixels off
+  pub async fn clear(&mut self, count: usize) {
+    let off = [Rgb::OFF; WS2812_MAX_PIXELS];
+    self.set_pixels(&off[..count.min(WS2812_MAX_PIXELS)]).await;
+  }
+}
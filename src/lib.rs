@@ -1,8 +1,12 @@
 #![no_main]
 #![no_std]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 use cortex_m as _; // import to get the core peripherals
-use defmt_rtt as _; // global logger
+#[cfg(not(feature = "rtt_control"))]
+use defmt_rtt as _; // global logger (rtt_control sets up RTT itself)
 use panic_probe as _; // panic handler
 
 use embassy_stm32 as _; // import to get the interrupt vectors
@@ -13,11 +17,42 @@ pub use embassy_time::Timer; // re-export embassy time for convenience
 
 // Hardware abstraction layer modules
 pub mod hardware {
+  pub mod adc;
+  pub mod bus;
+  #[cfg(feature = "hw_crc")]
+  pub mod crc;
+  #[cfg(feature = "has_dfsdm")]
+  pub mod dfsdm;
+  #[cfg(feature = "display")]
+  pub mod display;
+  #[cfg(feature = "hw_dma")]
+  pub mod dma;
+  pub mod encoder;
+  pub mod env_sensor;
   pub mod flash;
   pub mod gpio;
   pub mod hardfault;
+  pub mod lin;
+  pub mod motor;
+  #[cfg(feature = "mpu")]
+  pub mod mpu;
+  pub mod onewire;
+  pub mod option_bytes;
+  pub mod qspi_flash;
+  #[cfg(feature = "has_rng")]
+  pub mod rng;
+  pub mod rs485;
+  pub mod rtc;
+  #[cfg(feature = "sdcard")]
+  pub mod sdcard;
   pub mod serial;
+  pub mod swd;
   pub mod timers;
+  pub mod touch;
+  pub mod watchdog;
+  pub mod wifi_at;
+  pub mod ws2812;
+  pub use bus::*;
   pub use flash::*;
   pub use gpio::*;
   pub use serial::*;
@@ -26,19 +61,92 @@ pub mod hardware {
 
 // Services layer
 pub mod service {
+  pub mod alarm;
   pub mod comm;
+  pub mod config;
+  #[cfg(feature = "comm_crypto")]
+  pub mod crypto;
+  #[cfg(feature = "sdcard")]
+  pub mod datalogger;
+  #[cfg(feature = "signed_dfu")]
+  pub mod dfu;
+  pub mod duty_cycle;
+  pub mod failsafe;
+  #[cfg(feature = "fs")]
+  pub mod fs;
+  pub mod journal;
+  #[cfg(feature = "display")]
+  pub mod menu;
+  #[cfg(feature = "net")]
+  pub mod net;
+  pub mod pubsub;
+  #[cfg(feature = "rtt_control")]
+  pub mod rtt_control;
+  pub mod rules;
+  pub mod scheduler;
+  pub mod sensors;
+  pub mod shell;
+  pub mod snapshot;
+  pub mod status_led;
+  pub mod telemetry;
+  pub mod timers;
+  pub mod ymodem;
   pub use comm::*;
 }
 
-// Protocol modules
+// Protocol modules: pure no_std (no hardware, no logging), unit tested on the host by tests/host
 pub mod protocol {
+  pub mod at;
+  #[cfg(feature = "cbor")]
+  pub mod cbor;
+  pub mod config_blob;
+  pub mod device_config;
   pub mod hdlc;
+  #[cfg(feature = "comm_compress")]
+  pub mod heatshrink;
+  #[cfg(feature = "signed_dfu")]
+  pub mod image;
+  pub mod lin;
+  pub mod message;
+  pub mod pubsub;
+  pub mod routing;
+  pub mod rules;
+  pub mod schedule;
+  #[cfg(feature = "comm_crypto")]
+  pub mod secure;
+  pub mod slip;
+  pub mod ymodem;
   pub use hdlc::*;
+  pub use message::*;
+  pub use routing::*;
+}
+
+// Diagnostics modules
+pub mod diagnostics {
+  #[cfg(feature = "cs_monitor")]
+  pub mod cs_monitor;
+  pub mod eventlog;
+  pub mod reset;
+  pub mod stack;
+  #[cfg(feature = "task_metrics")]
+  pub mod task_metrics;
+  pub use stack::stack_high_water;
 }
 
 // Common/shared functionality modules
 pub mod common {
+  pub mod buildinfo;
+  pub mod cooperative;
+  pub mod executor;
+  pub mod fsm;
+  #[cfg(feature = "alloc")]
+  pub mod heap;
+  pub mod noinit;
+  pub mod power;
+  pub mod random;
+  pub mod system;
   pub mod tasks;
+  pub use crate::yield_every;
   pub use tasks::*;
 }
 
@@ -47,8 +155,7 @@ pub mod prelude {
   pub use embedded_io::Write as _;
 }
 
-// Board configuration - included from root board.rs file (copied by setup.sh)
-#[path = "../board.rs"]
+// Board configuration - selected by a `board-*` cargo feature
 pub mod board;
 
 // Macro for compile-time board configuration validation
@@ -0,0 +1,136 @@
+//! ESP-AT response parsing (ESP8266/ESP32 running Espressif's AT firmware)
+// The module answers each command with CRLF-terminated lines ending in a final result (`OK`,
+// `ERROR`, `FAIL`, `SEND OK`, `SEND FAIL`), and prompts for `AT+CIPSEND` data with a bare `>`.
+// Unsolicited lines report link changes (`WIFI CONNECTED`, `CLOSED`, ...). Socket data arrives
+// as `+IPD,<len>:` followed by exactly `len` raw bytes (`+IPD,<id>,<len>:` with `AT+CIPMUX=1`),
+// which may contain CR/LF, so it is counted out rather than read as a line.
+
+use heapless::Vec;
+
+const IPD_PREFIX: &[u8] = b"+IPD,";
+
+/// One parsed item of module output
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AtEvent<'a> {
+  /// Final result `OK`
+  Ok,
+  /// Final result `ERROR` or `FAIL`
+  Error,
+  /// `AT+CIPSEND` data accepted
+  SendOk,
+  /// `AT+CIPSEND` data could not be sent
+  SendFail,
+  /// `>`: the module waits for `AT+CIPSEND` data
+  Prompt,
+  /// The TCP connection closed (`CLOSED`, `<id>,CLOSED`)
+  Closed,
+  /// Socket data from `+IPD` (a long `+IPD` arrives in several parts of up to `N` bytes)
+  Data(&'a [u8]),
+  /// Any other non-empty line (echo, `WIFI GOT IP`, `+CIFSR:...`, ...)
+  Line(&'a [u8]),
+}
+
+/// Incremental parser; feed it the module's output a byte at a time
+pub struct AtParser<const N: usize> {
+  buf: Vec<u8, N>,
+  // Raw `+IPD` bytes still to come
+  ipd_remaining: usize,
+  // The last event borrowed `buf`; clear it on the next byte
+  consumed: bool,
+  // The current line outgrew `buf`; drop it at its end
+  overflow: bool,
+}
+
+impl<const N: usize> AtParser<N> {
+  pub const fn new() -> Self {
+    Self {
+      buf: Vec::new(),
+      ipd_remaining: 0,
+      consumed: false,
+      overflow: false,
+    }
+  }
+
+  /// Forget any partial line or data (e.g. after a module reset)
+  pub fn reset(&mut self) {
+    *self = Self::new();
+  }
+
+  /// Feed one byte; returns an event when it completes one
+  pub fn push(&mut self, byte: u8) -> Option<AtEvent<'_>> {
+    if self.consumed {
+      self.buf.clear();
+      self.consumed = false;
+    }
+
+    if self.ipd_remaining > 0 {
+      // Counted data: never interpreted, handed out whenever the buffer fills or the data ends
+      self.buf.push(byte).ok();
+      self.ipd_remaining -= 1;
+      if self.ipd_remaining == 0 || self.buf.is_full() {
+        self.consumed = true;
+        return Some(AtEvent::Data(&self.buf));
+      }
+      return None;
+    }
+
+    if byte == b'\n' {
+      self.consumed = true;
+      if core::mem::take(&mut self.overflow) {
+        return None;
+      }
+      let line = trim_line(&self.buf);
+      return match line {
+        b"" => None,
+        b"OK" => Some(AtEvent::Ok),
+        b"ERROR" | b"FAIL" => Some(AtEvent::Error),
+        b"SEND OK" => Some(AtEvent::SendOk),
+        b"SEND FAIL" => Some(AtEvent::SendFail),
+        _ if line == b"CLOSED" || line.ends_with(b",CLOSED") => Some(AtEvent::Closed),
+        _ => Some(AtEvent::Line(line)),
+      };
+    }
+
+    if self.buf.push(byte).is_err() {
+      self.overflow = true;
+      return None;
+    }
+    if byte == b'>' && trim_line(&self.buf) == b">" {
+      self.consumed = true;
+      return Some(AtEvent::Prompt);
+    }
+    if byte == b':' && self.buf.starts_with(IPD_PREFIX) {
+      // `+IPD,<len>:` or `+IPD,<id>,<len>:` - the length is the last field
+      let fields = &self.buf[IPD_PREFIX.len()..self.buf.len() - 1];
+      if let Some(len) = fields.rsplit(|&b| b == b',').next().and_then(parse_decimal) {
+        self.ipd_remaining = len;
+        self.buf.clear();
+      }
+    }
+    None
+  }
+}
+
+impl<const N: usize> Default for AtParser<N> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+// Strip leading/trailing CR, LF and spaces
+fn trim_line(line: &[u8]) -> &[u8] {
+  let is_space = |b: &u8| matches!(b, b'\r' | b'\n' | b' ');
+  let start = line.iter().position(|b| !is_space(b)).unwrap_or(line.len());
+  let end = line.iter().rposition(|b| !is_space(b)).map_or(start, |i| i + 1);
+  &line[start..end]
+}
+
+fn parse_decimal(digits: &[u8]) -> Option<usize> {
+  if digits.is_empty() {
+    return None;
+  }
+  digits.iter().try_fold(0usize, |acc, &d| match d {
+    b'0'..=b'9' => acc.checked_mul(10)?.checked_add((d - b'0') as usize),
+    _ => None,
+  })
+}
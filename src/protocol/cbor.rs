@@ -0,0 +1,41 @@
+//! Typed Comms payloads encoded as CBOR (RFC 8949, feature `cbor`)
+// Derive `Encode`/`Decode` (re-exported from `minicbor`) on a struct, give each field an index
+// (`#[n(0)]`, `#[n(1)]`, ...), then build messages with `cbor::message` and read them back with
+// `cbor::from_payload`. Fields are encoded by index, so new optional fields can be appended
+// without breaking older peers. Any CBOR library on the host side (Python `cbor2`, serde_cbor,
+// ...) can produce and read these payloads.
+
+use minicbor::encode::write::Cursor;
+
+pub use minicbor::{Decode, Encode};
+
+use super::message::{COMMS_MAX_PAYLOAD, Command, CommsPayload, Message};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CborError {
+  /// Encoded value does not fit in `COMMS_MAX_PAYLOAD`
+  TooLong,
+  /// Payload is not valid CBOR for the requested type
+  Decode,
+}
+
+/// Encode `value` as a Comms payload
+pub fn to_payload<T: Encode<()>>(value: &T) -> Result<CommsPayload, CborError> {
+  let mut out = CommsPayload::new();
+  out.resize_default(COMMS_MAX_PAYLOAD).map_err(|_| CborError::TooLong)?;
+  let mut cursor = Cursor::new(&mut out[..]);
+  minicbor::encode(value, &mut cursor).map_err(|_| CborError::TooLong)?;
+  let len = cursor.position();
+  out.truncate(len);
+  Ok(out)
+}
+
+/// Decode a payload produced by `to_payload` (borrowed fields such as `&str` point into `payload`)
+pub fn from_payload<'b, T: Decode<'b, ()>>(payload: &'b [u8]) -> Result<T, CborError> {
+  minicbor::decode(payload).map_err(|_| CborError::Decode)
+}
+
+/// Build a message carrying `value` as its payload
+pub fn message<T: Encode<()>>(command: Command, value: &T) -> Result<Message, CborError> {
+  Ok(Message::new(command, &to_payload(value)?))
+}
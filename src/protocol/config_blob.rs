@@ -0,0 +1,136 @@
+//! Device configuration blob: every config section in one CRC-protected image
+// Blob format (little-endian):
+// - magic:        u16  (0xC0F1)
+// - version:      u8   (CONFIG_BLOB_VERSION)
+// - sections:     u8   (section count)
+// - per section:  tag: u8 | length: u16 | data [length]
+// - crc:          u16  (PPP FCS-16 over everything before it)
+//
+// Readers skip sections with unknown tags, so a blob exported by newer firmware can still be
+// imported by older firmware (and the other way round).
+
+use heapless::Vec;
+
+use super::hdlc::fcs16_ppp;
+
+/// Largest blob (fits in 4 fragments of `COMMS_MAX_PAYLOAD`)
+pub const CONFIG_BLOB_MAX: usize = 1024;
+pub const CONFIG_BLOB_VERSION: u8 = 1;
+
+const MAGIC: u16 = 0xC0F1;
+const HEADER_LEN: usize = 4;
+const SECTION_HEADER_LEN: usize = 3;
+const CRC_LEN: usize = 2;
+
+pub type ConfigBlob = Vec<u8, CONFIG_BLOB_MAX>;
+
+/// Section tags
+pub mod section {
+  /// `service::rules` rule set
+  pub const RULES: u8 = 0x01;
+  /// `service::config` device configuration record
+  pub const CONFIG: u8 = 0x02;
+  /// `service::scheduler` calendar entries
+  pub const SCHEDULE: u8 = 0x03;
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ConfigBlobError {
+  /// Too short, bad magic or section lengths running past the end
+  Malformed,
+  /// Blob written by an incompatible format version
+  Version,
+  /// CRC mismatch (corrupted in transit or storage)
+  Crc,
+  /// Does not fit in `CONFIG_BLOB_MAX`
+  TooLarge,
+}
+
+/// Builds a blob section by section
+pub struct BlobWriter {
+  blob: ConfigBlob,
+}
+
+impl Default for BlobWriter {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl BlobWriter {
+  pub fn new() -> Self {
+    let mut blob = ConfigBlob::new();
+    blob.extend_from_slice(&MAGIC.to_le_bytes()).ok();
+    blob.extend_from_slice(&[CONFIG_BLOB_VERSION, 0]).ok();
+    Self { blob }
+  }
+
+  pub fn section(&mut self, tag: u8, data: &[u8]) -> Result<(), ConfigBlobError> {
+    if self.blob.len() + SECTION_HEADER_LEN + data.len() + CRC_LEN > CONFIG_BLOB_MAX || data.len() > u16::MAX as usize {
+      return Err(ConfigBlobError::TooLarge);
+    }
+    self.blob.push(tag).ok();
+    self.blob.extend_from_slice(&(data.len() as u16).to_le_bytes()).ok();
+    self.blob.extend_from_slice(data).ok();
+    self.blob[3] += 1;
+    Ok(())
+  }
+
+  /// Append the CRC and return the finished blob
+  pub fn finish(mut self) -> ConfigBlob {
+    let crc = fcs16_ppp(&self.blob);
+    self.blob.extend_from_slice(&crc.to_le_bytes()).ok();
+    self.blob
+  }
+}
+
+/// Validate `blob` and return its `(tag, data)` sections
+pub fn sections(blob: &[u8]) -> Result<Sections<'_>, ConfigBlobError> {
+  if blob.len() < HEADER_LEN + CRC_LEN || u16::from_le_bytes([blob[0], blob[1]]) != MAGIC {
+    return Err(ConfigBlobError::Malformed);
+  }
+  if blob[2] != CONFIG_BLOB_VERSION {
+    return Err(ConfigBlobError::Version);
+  }
+  let (body, crc) = blob.split_at(blob.len() - CRC_LEN);
+  if fcs16_ppp(body) != u16::from_le_bytes([crc[0], crc[1]]) {
+    return Err(ConfigBlobError::Crc);
+  }
+  // Walk once up front so iteration cannot fail halfway
+  let sections = Sections {
+    data: &body[HEADER_LEN..],
+    remaining: body[3],
+  };
+  let mut walk = sections.clone();
+  for _ in walk.by_ref() {}
+  if walk.remaining != 0 || !walk.data.is_empty() {
+    return Err(ConfigBlobError::Malformed);
+  }
+  Ok(sections)
+}
+
+/// Iterator over the sections of a validated blob
+#[derive(Clone)]
+pub struct Sections<'a> {
+  data: &'a [u8],
+  remaining: u8,
+}
+
+impl<'a> Iterator for Sections<'a> {
+  type Item = (u8, &'a [u8]);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.remaining == 0 || self.data.len() < SECTION_HEADER_LEN {
+      return None;
+    }
+    let len = u16::from_le_bytes([self.data[1], self.data[2]]) as usize;
+    let end = SECTION_HEADER_LEN + len;
+    if self.data.len() < end {
+      return None;
+    }
+    let item = (self.data[0], &self.data[SECTION_HEADER_LEN..end]);
+    self.data = &self.data[end..];
+    self.remaining -= 1;
+    Some(item)
+  }
+}
@@ -0,0 +1,119 @@
+//! Persistent device configuration record
+// Record format (little-endian), used both in flash and on the comm link:
+// - magic:        u16  (0xDC0F)
+// - version:      u8   (DEVICE_CONFIG_VERSION)
+// - address:      u8   (bus node address with `comms_routing`, 0 = unset; reserved before version 3)
+// - baud:         u32  (serial baud rate)
+// - device_id:    u32
+// - flags:        u32  (application feature flags)
+// - rtc_ppm:      i16  (RTC calibration at 25 °C, 0.1 ppm; not in version 1 records)
+// - user_len:     u16
+// - user:         [u8; user_len] (opaque application data, up to DEVICE_CONFIG_USER_MAX)
+// - crc:          u16  (PPP FCS-16 over everything before it)
+// Version 1 records (without rtc_ppm) still decode, with no RTC calibration. The user blob gave
+// the two bytes to rtc_ppm, so records still fit the same flash slots. Version 1 and 2 records
+// decode with no address.
+
+use heapless::Vec;
+
+use super::hdlc::fcs16_ppp;
+
+pub const DEVICE_CONFIG_VERSION: u8 = 3;
+/// Largest user blob
+pub const DEVICE_CONFIG_USER_MAX: usize = 62;
+/// Longest encoded record
+pub const DEVICE_CONFIG_MAX: usize = HEADER_LEN + DEVICE_CONFIG_USER_MAX + CRC_LEN;
+
+const MAGIC: u16 = 0xDC0F;
+const HEADER_LEN: usize = 20;
+// Version 1 header: no rtc_ppm
+const HEADER_LEN_V1: usize = 18;
+// Version 2: same header as now, byte 3 still reserved
+const VERSION_NO_ADDRESS: u8 = 2;
+const CRC_LEN: usize = 2;
+
+pub type DeviceConfigBuf = Vec<u8, DEVICE_CONFIG_MAX>;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DeviceConfigError {
+  /// Too short, bad magic, or user length running past the end
+  Malformed,
+  /// Record written by an incompatible format version
+  Version,
+  /// CRC mismatch (torn write or corrupted in transit)
+  Crc,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DeviceConfig {
+  pub baud: u32,
+  pub device_id: u32,
+  pub flags: u32,
+  /// Node address on a shared bus (`comms_routing`; 0: keep `comm::COMMS_DEFAULT_ADDRESS`)
+  pub address: u8,
+  /// RTC smooth calibration at 25 °C in 0.1 ppm (`hardware::rtc::calibrate`)
+  pub rtc_ppm_tenths: i16,
+  pub user: Vec<u8, DEVICE_CONFIG_USER_MAX>,
+}
+
+impl DeviceConfig {
+  /// Factory defaults (used when flash holds no valid record)
+  pub const fn new(baud: u32) -> Self {
+    Self {
+      baud,
+      device_id: 0,
+      flags: 0,
+      address: 0,
+      rtc_ppm_tenths: 0,
+      user: Vec::new(),
+    }
+  }
+
+  pub fn encode(&self) -> DeviceConfigBuf {
+    let mut out = DeviceConfigBuf::new();
+    out.extend_from_slice(&MAGIC.to_le_bytes()).ok();
+    out.extend_from_slice(&[DEVICE_CONFIG_VERSION, self.address]).ok();
+    out.extend_from_slice(&self.baud.to_le_bytes()).ok();
+    out.extend_from_slice(&self.device_id.to_le_bytes()).ok();
+    out.extend_from_slice(&self.flags.to_le_bytes()).ok();
+    out.extend_from_slice(&self.rtc_ppm_tenths.to_le_bytes()).ok();
+    out.extend_from_slice(&(self.user.len() as u16).to_le_bytes()).ok();
+    out.extend_from_slice(&self.user).ok();
+    let crc = fcs16_ppp(&out);
+    out.extend_from_slice(&crc.to_le_bytes()).ok();
+    out
+  }
+
+  /// Decode a record from the start of `bytes` (trailing bytes are ignored)
+  pub fn decode(bytes: &[u8]) -> Result<Self, DeviceConfigError> {
+    if bytes.len() < HEADER_LEN_V1 + CRC_LEN || u16::from_le_bytes([bytes[0], bytes[1]]) != MAGIC {
+      return Err(DeviceConfigError::Malformed);
+    }
+    let header_len = match bytes[2] {
+      DEVICE_CONFIG_VERSION | VERSION_NO_ADDRESS => HEADER_LEN,
+      1 => HEADER_LEN_V1,
+      _ => return Err(DeviceConfigError::Version),
+    };
+    if bytes.len() < header_len + CRC_LEN {
+      return Err(DeviceConfigError::Malformed);
+    }
+    let u16_at = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+    let u32_at = |offset: usize| u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+    let user_len = u16_at(header_len - 2) as usize;
+    let end = header_len + user_len;
+    if user_len > DEVICE_CONFIG_USER_MAX || bytes.len() < end + CRC_LEN {
+      return Err(DeviceConfigError::Malformed);
+    }
+    if fcs16_ppp(&bytes[..end]) != u16::from_le_bytes([bytes[end], bytes[end + 1]]) {
+      return Err(DeviceConfigError::Crc);
+    }
+    Ok(Self {
+      baud: u32_at(4),
+      device_id: u32_at(8),
+      flags: u32_at(12),
+      address: if bytes[2] == DEVICE_CONFIG_VERSION { bytes[3] } else { 0 },
+      rtc_ppm_tenths: if header_len == HEADER_LEN { u16_at(16) as i16 } else { 0 },
+      user: Vec::from_slice(&bytes[header_len..end]).map_err(|_| DeviceConfigError::Malformed)?,
+    })
+  }
+}
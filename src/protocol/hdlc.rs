@@ -9,8 +9,13 @@ pub const HDLC_XOR: u8 = 0x20;
 /// Compute PPP/HDLC 16-bit FCS.
 /// Polynomial 0x8408 (reversed 0x1021), init 0xFFFF, reflected, final XOR 0xFFFF.
 /// Returns the 16-bit FCS value to append (already complemented).
-#[cfg(feature = "hdlc_fcs")]
-fn fcs16_ppp(data: &[u8]) -> u16 {
+/// Also used to protect data outside HDLC frames (e.g. `config_blob`), so it is built without `hdlc_fcs` too.
+/// With `hw_crc` it runs on the CRC unit where that unit is programmable (see `hardware::crc`).
+pub fn fcs16_ppp(data: &[u8]) -> u16 {
+  #[cfg(feature = "hw_crc")]
+  if let Some(fcs) = crate::hardware::crc::fcs16(data) {
+    return fcs;
+  }
   let mut fcs: u16 = 0xFFFF;
   for &b in data {
     let mut x = (fcs ^ (b as u16)) & 0x00FF;
@@ -67,81 +72,94 @@ pub fn hdlc_frame<const M: usize>(payload: &[u8], out: &mut heapless::Vec<u8, M>
 /// HDLC deframe error type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HdlcError {
-  FcsMismatch { received: u16, calculated: u16, len: usize },
+  /// No complete frame in the buffer yet (partial frame kept for the next call)
+  Incomplete,
+  FcsMismatch {
+    received: u16,
+    calculated: u16,
+    len: usize,
+  },
+}
+
+/// Remove the first `n` bytes of `buf`, shifting the remainder to the front
+fn discard_front<const N: usize>(buf: &mut heapless::Vec<u8, N>, n: usize) {
+  let n = core::cmp::min(n, buf.len());
+  let remaining = buf.len() - n;
+  for j in 0..remaining {
+    buf[j] = buf[n + j];
+  }
+  buf.truncate(remaining);
 }
 
 /// Deframe HDLC data (returns Ok(()) if a full frame is found and FCS is valid when enabled, Err(HdlcError) on error)
+///
+/// - Bytes before the first flag are treated as line noise and discarded.
+/// - Consecutive flags and runt frames (< 2 bytes) are skipped; the last flag opens the next frame.
+/// - A closing flag is left in `buf` so it can also open the next frame (shared open/close flag).
+/// - An escape followed by a flag aborts the current frame.
 pub fn hdlc_deframe<const N: usize, const M: usize>(buf: &mut heapless::Vec<u8, N>, out: &mut heapless::Vec<u8, M>) -> Result<(), HdlcError> {
-  let mut in_frame = false;
+  let mut open: Option<usize> = None; // index of the flag that opened the current frame
   let mut escape = false;
   out.clear();
   let mut i = 0;
   while i < buf.len() {
     let b = buf[i];
-    if !in_frame {
-      if b == HDLC_FLAG {
-        in_frame = true;
-        out.clear();
+    if b == HDLC_FLAG {
+      if open.is_some() && !escape && out.len() >= 2 {
+        // Complete frame: consume everything before the closing flag
+        discard_front(buf, i);
+
+        // Split payload and FCS
+        let payload_len = out.len() - 2;
+        let (payload, fcs_bytes) = out.split_at(payload_len);
+        let fcs_recv = u16::from_le_bytes([fcs_bytes[0], fcs_bytes[1]]);
+
+        #[cfg(feature = "hdlc_fcs")]
+        {
+          let fcs_calc = fcs16_ppp(payload);
+          if fcs_recv == fcs_calc {
+            out.truncate(payload_len);
+            return Ok(());
+          } else {
+            out.clear();
+            return Err(HdlcError::FcsMismatch {
+              received: fcs_recv,
+              calculated: fcs_calc,
+              len: payload_len,
+            });
+          }
+        }
+        #[cfg(not(feature = "hdlc_fcs"))]
+        {
+          let _ = payload; // suppress unused when FCS disabled
+          let _ = fcs_recv; // suppress unused when FCS disabled
+          // FCS disabled: accept frame without verification (strip trailing 2 bytes)
+          out.truncate(payload_len);
+          return Ok(());
+        }
       }
-    } else {
+      // Leading, back-to-back or aborting flag: this flag opens a new frame
+      open = Some(i);
+      escape = false;
+      out.clear();
+    } else if open.is_some() {
       if escape {
         out.push(b ^ HDLC_XOR).ok();
         escape = false;
       } else if b == HDLC_ESCAPE {
         escape = true;
-      } else if b == HDLC_FLAG {
-        if out.len() >= 2 {
-          // Remove processed bytes from buf (shift remaining bytes)
-          if i + 1 < buf.len() {
-            let remaining = buf.len() - (i + 1);
-            for j in 0..remaining {
-              buf[j] = buf[i + 1 + j];
-            }
-            buf.truncate(remaining);
-          } else {
-            buf.clear();
-          }
-          // Split payload and FCS
-          let payload_len = out.len() - 2;
-          let (payload, fcs_bytes) = out.split_at(payload_len);
-          let fcs_recv = u16::from_le_bytes([fcs_bytes[0], fcs_bytes[1]]);
-
-          #[cfg(feature = "hdlc_fcs")]
-          {
-            let fcs_calc = fcs16_ppp(payload);
-            if fcs_recv == fcs_calc {
-              out.truncate(payload_len);
-              return Ok(());
-            } else {
-              out.clear();
-              defmt::error!("HDLC FCS mismatch: recv={=u16}, calc={=u16}, len={}", fcs_recv, fcs_calc, payload_len);
-              return Err(HdlcError::FcsMismatch {
-                received: fcs_recv,
-                calculated: fcs_calc,
-                len: payload_len,
-              });
-            }
-          }
-          #[cfg(not(feature = "hdlc_fcs"))]
-          {
-            let _ = payload; // suppress unused when FCS disabled
-            let _ = fcs_recv; // suppress unused when FCS disabled
-            // FCS disabled: accept frame without verification (strip trailing 2 bytes)
-            out.truncate(payload_len);
-            return Ok(());
-          }
-        }
-        // else: empty frame, ignore
-        in_frame = false;
       } else {
         out.push(b).ok();
       }
     }
     i += 1;
   }
-  Err(HdlcError::FcsMismatch {
-    received: 0,
-    calculated: 0,
-    len: 0,
-  }) // No frame found or incomplete
+
+  // No complete frame: drop noise before the opening flag, keep the partial frame
+  match open {
+    Some(start) => discard_front(buf, start),
+    None => buf.clear(),
+  }
+  out.clear();
+  Err(HdlcError::Incomplete)
 }
@@ -0,0 +1,142 @@
+//! Heatshrink (LZSS) compression of Comms payloads
+// Bit stream format of heatshrink (github.com/atomicobject/heatshrink), MSB first, with an 8-bit
+// window and a 4-bit lookahead (`heatshrink -w 8 -l 4`):
+// - literal:      1, then the byte (8 bits)
+// - back-reference: 0, then offset - 1 (HS_WINDOW_BITS), then length - 1 (HS_LOOKAHEAD_BITS):
+//   copy `length` bytes starting `offset` bytes back in the output
+// The last byte is padded with zero bits, too few for another item, so the decoder stops there.
+// A message payload is at most 256 bytes, so the whole payload is the window: the encoder keeps
+// no state between payloads and each fragment decodes on its own.
+
+use heapless::Vec;
+
+/// log2 of the back-reference window (bytes)
+pub const HS_WINDOW_BITS: u32 = 8;
+/// log2 of the longest back-reference (bytes)
+pub const HS_LOOKAHEAD_BITS: u32 = 4;
+
+const WINDOW: usize = 1 << HS_WINDOW_BITS;
+const MAX_MATCH: usize = 1 << HS_LOOKAHEAD_BITS;
+// A back-reference (13 bits) only beats literals (9 bits each) from two bytes on
+const MIN_MATCH: usize = 2;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HeatshrinkError {
+  /// Output does not fit the buffer
+  Overflow,
+  /// Back-reference before the start of the output (corrupted stream)
+  BadReference,
+}
+
+/// Compress `input` into `out`; `Overflow` if the result would not fit
+pub fn compress<const N: usize>(input: &[u8], out: &mut Vec<u8, N>) -> Result<(), HeatshrinkError> {
+  out.clear();
+  let mut bits = BitWriter { out, byte: 0, used: 0 };
+  let mut pos = 0;
+  while pos < input.len() {
+    let (offset, len) = longest_match(input, pos);
+    if len >= MIN_MATCH {
+      bits.put(0, 1)?;
+      bits.put((offset - 1) as u32, HS_WINDOW_BITS)?;
+      bits.put((len - 1) as u32, HS_LOOKAHEAD_BITS)?;
+      pos += len;
+    } else {
+      bits.put(1, 1)?;
+      bits.put(input[pos] as u32, 8)?;
+      pos += 1;
+    }
+  }
+  bits.finish()
+}
+
+/// Decompress `input` into `out`
+pub fn decompress<const N: usize>(input: &[u8], out: &mut Vec<u8, N>) -> Result<(), HeatshrinkError> {
+  out.clear();
+  let mut bits = BitReader { input, pos: 0 };
+  loop {
+    match bits.get(1) {
+      Some(1) => {
+        let Some(byte) = bits.get(8) else { break };
+        out.push(byte as u8).map_err(|_| HeatshrinkError::Overflow)?;
+      }
+      Some(_) => {
+        let (Some(offset), Some(len)) = (bits.get(HS_WINDOW_BITS), bits.get(HS_LOOKAHEAD_BITS)) else {
+          break;
+        };
+        let (offset, len) = (offset as usize + 1, len as usize + 1);
+        if offset > out.len() {
+          return Err(HeatshrinkError::BadReference);
+        }
+        // Byte by byte: a reference may overlap the bytes it produces (runs)
+        for _ in 0..len {
+          out.push(out[out.len() - offset]).map_err(|_| HeatshrinkError::Overflow)?;
+        }
+      }
+      None => break,
+    }
+  }
+  Ok(())
+}
+
+// Longest earlier occurrence (offset back, length) of the bytes at `pos`, within the window
+fn longest_match(input: &[u8], pos: usize) -> (usize, usize) {
+  let limit = MAX_MATCH.min(input.len() - pos);
+  let mut best = (0, 0);
+  for start in pos.saturating_sub(WINDOW)..pos {
+    let len = (0..limit).take_while(|&i| input[start + i] == input[pos + i]).count();
+    // Ties go to the nearest match
+    if len >= best.1 {
+      best = (pos - start, len);
+    }
+  }
+  best
+}
+
+struct BitWriter<'a, const N: usize> {
+  out: &'a mut Vec<u8, N>,
+  byte: u8,
+  used: u32,
+}
+
+impl<const N: usize> BitWriter<'_, N> {
+  fn put(&mut self, value: u32, count: u32) -> Result<(), HeatshrinkError> {
+    for bit in (0..count).rev() {
+      self.byte = (self.byte << 1) | ((value >> bit) & 1) as u8;
+      self.used += 1;
+      if self.used == 8 {
+        self.out.push(self.byte).map_err(|_| HeatshrinkError::Overflow)?;
+        (self.byte, self.used) = (0, 0);
+      }
+    }
+    Ok(())
+  }
+
+  fn finish(mut self) -> Result<(), HeatshrinkError> {
+    if self.used > 0 {
+      self.put(0, 8 - self.used)?;
+    }
+    Ok(())
+  }
+}
+
+struct BitReader<'a> {
+  input: &'a [u8],
+  // Bits consumed so far
+  pos: usize,
+}
+
+impl BitReader<'_> {
+  // Next `count` bits, or None if the input ends first
+  fn get(&mut self, count: u32) -> Option<u32> {
+    if self.pos + count as usize > self.input.len() * 8 {
+      return None;
+    }
+    let mut value = 0;
+    for _ in 0..count {
+      let bit = (self.input[self.pos / 8] >> (7 - self.pos % 8)) & 1;
+      value = (value << 1) | bit as u32;
+      self.pos += 1;
+    }
+    Some(value)
+  }
+}
@@ -0,0 +1,135 @@
+//! Signed firmware image trailer and Ed25519 verification (feature `signed_dfu`)
+// A signed image is the firmware binary followed by a trailer (little-endian):
+// - magic:        u32  (0x4E474953, "SIGN")
+// - version:      u8   (IMAGE_TRAILER_VERSION)
+// - reserved:     [u8; 3] (0)
+// - image_len:    u32  (bytes of firmware before the trailer)
+// - signature:    [u8; 64]
+// The signature is Ed25519ph (RFC 8032, SHA-512 prehash, context `SIGNING_CONTEXT`) over the
+// image followed by the first 12 trailer bytes, so the length and format cannot be changed
+// without invalidating it. The prehash lets the image be streamed from flash in chunks.
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use sha2::{Digest, Sha512};
+
+pub const IMAGE_TRAILER_VERSION: u8 = 1;
+pub const IMAGE_TRAILER_LEN: usize = SIGNED_HEADER_LEN + SIGNATURE_LEN;
+pub const SIGNATURE_LEN: usize = 64;
+pub const PUBLIC_KEY_LEN: usize = 32;
+/// Ed25519ph context string; signer and verifier must agree on it
+pub const SIGNING_CONTEXT: &[u8] = b"embassy-stm32-starter image";
+
+const MAGIC: u32 = 0x4E47_4953;
+const SIGNED_HEADER_LEN: usize = 12;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ImageError {
+  /// Too short, or no trailer magic (unsigned image)
+  Unsigned,
+  /// Trailer written by an incompatible format version
+  Version,
+  /// Trailer length field does not match the uploaded size
+  Length,
+  /// The baked-in public key is not a valid Ed25519 point
+  BadKey,
+  /// Signature does not match the image and key
+  Signature,
+}
+
+/// Trailer found after the image
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ImageTrailer {
+  pub image_len: u32,
+  pub signature: [u8; SIGNATURE_LEN],
+}
+
+impl ImageTrailer {
+  /// Trailer bytes covered by the signature
+  pub fn signed_header(&self) -> [u8; SIGNED_HEADER_LEN] {
+    let mut out = [0u8; SIGNED_HEADER_LEN];
+    out[..4].copy_from_slice(&MAGIC.to_le_bytes());
+    out[4] = IMAGE_TRAILER_VERSION;
+    out[8..].copy_from_slice(&self.image_len.to_le_bytes());
+    out
+  }
+
+  pub fn encode(&self) -> [u8; IMAGE_TRAILER_LEN] {
+    let mut out = [0u8; IMAGE_TRAILER_LEN];
+    out[..SIGNED_HEADER_LEN].copy_from_slice(&self.signed_header());
+    out[SIGNED_HEADER_LEN..].copy_from_slice(&self.signature);
+    out
+  }
+
+  /// Decode the trailer of a signed image of `total_len` bytes (image plus trailer)
+  pub fn decode(bytes: &[u8], total_len: u32) -> Result<Self, ImageError> {
+    if bytes.len() < IMAGE_TRAILER_LEN || u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) != MAGIC {
+      return Err(ImageError::Unsigned);
+    }
+    if bytes[4] != IMAGE_TRAILER_VERSION {
+      return Err(ImageError::Version);
+    }
+    let image_len = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+    if image_len.checked_add(IMAGE_TRAILER_LEN as u32) != Some(total_len) {
+      return Err(ImageError::Length);
+    }
+    let mut signature = [0u8; SIGNATURE_LEN];
+    signature.copy_from_slice(&bytes[SIGNED_HEADER_LEN..IMAGE_TRAILER_LEN]);
+    Ok(Self { image_len, signature })
+  }
+}
+
+/// Streaming verifier: feed the image in order with `update`, then `finish` with its trailer
+pub struct ImageVerifier {
+  hash: Sha512,
+}
+
+impl ImageVerifier {
+  pub fn new() -> Self {
+    Self { hash: Sha512::new() }
+  }
+
+  pub fn update(&mut self, chunk: &[u8]) {
+    self.hash.update(chunk);
+  }
+
+  /// Check the trailer's signature over everything fed so far against `public_key`
+  pub fn finish(mut self, trailer: &ImageTrailer, public_key: &[u8; PUBLIC_KEY_LEN]) -> Result<(), ImageError> {
+    let key = VerifyingKey::from_bytes(public_key).map_err(|_| ImageError::BadKey)?;
+    self.hash.update(trailer.signed_header());
+    key
+      .verify_prehashed_strict(self.hash, Some(SIGNING_CONTEXT), &Signature::from_bytes(&trailer.signature))
+      .map_err(|_| ImageError::Signature)
+  }
+}
+
+impl Default for ImageVerifier {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Parse a 64-digit hex public key at compile time (None if absent or malformed)
+pub const fn parse_public_key(hex: &str) -> Option<[u8; PUBLIC_KEY_LEN]> {
+  const fn nibble(c: u8) -> Option<u8> {
+    match c {
+      b'0'..=b'9' => Some(c - b'0'),
+      b'a'..=b'f' => Some(c - b'a' + 10),
+      b'A'..=b'F' => Some(c - b'A' + 10),
+      _ => None,
+    }
+  }
+  let bytes = hex.as_bytes();
+  if bytes.len() != 2 * PUBLIC_KEY_LEN {
+    return None;
+  }
+  let mut out = [0u8; PUBLIC_KEY_LEN];
+  let mut i = 0;
+  while i < PUBLIC_KEY_LEN {
+    match (nibble(bytes[2 * i]), nibble(bytes[2 * i + 1])) {
+      (Some(hi), Some(lo)) => out[i] = hi << 4 | lo,
+      _ => return None,
+    }
+    i += 1;
+  }
+  Some(out)
+}
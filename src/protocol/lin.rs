@@ -0,0 +1,198 @@
+//! LIN 2.x slave node: header tracking, response and checksums
+// The master starts every frame with a header: a break (13+ dominant bits), the sync byte 0x55
+// and a protected identifier (6-bit id plus two parity bits). The node that publishes that id
+// (possibly the master itself) answers with 1-8 data bytes and a checksum. `LinSlave` follows the
+// headers in the received bytes (the break comes from the UART's break detection through
+// `on_break`) and, for the ids in its frame table, either asks the application for the response
+// (`LinEvent::Publish`, answered with `response`) or collects the response of another node and
+// checks it (`LinEvent::Received`). On the single-wire bus a node hears its own response; the
+// echo is skipped. Checksums are enhanced (LIN 2.x, covering the protected id), classic for LIN
+// 1.x tables and always for the diagnostic frames 0x3C/0x3D.
+
+use heapless::Vec;
+
+/// Sync byte following the break
+pub const SYNC: u8 = 0x55;
+/// Longest response data
+pub const MAX_DATA: usize = 8;
+/// Diagnostic master request frame id
+pub const MASTER_REQUEST: u8 = 0x3C;
+/// Diagnostic slave response frame id
+pub const SLAVE_RESPONSE: u8 = 0x3D;
+
+/// Checksum model
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Checksum {
+  /// LIN 1.x: data bytes only
+  Classic,
+  /// LIN 2.x: protected id and data bytes
+  Enhanced,
+}
+
+/// Who sends a frame's response
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Direction {
+  /// This node answers the header
+  Publish,
+  /// Another node answers; this node receives
+  Subscribe,
+}
+
+/// Entry of the node's frame table
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Frame {
+  /// Frame id (0..=0x3F)
+  pub id: u8,
+  /// Data bytes (1..=8)
+  pub len: u8,
+  pub direction: Direction,
+}
+
+/// Bus errors seen by the slave
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LinError {
+  /// A break not followed by the sync byte
+  Sync,
+  /// Protected identifier with wrong parity bits
+  Parity(u8),
+  /// Response to this id failed its checksum
+  Checksum(u8),
+}
+
+/// What a received byte completed
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LinEvent<'a> {
+  /// Header of a frame this node publishes: send `response` now
+  Publish(Frame),
+  /// Response to a subscribed frame, checksum verified
+  Received {
+    id: u8,
+    data: &'a [u8],
+  },
+  Error(LinError),
+}
+
+/// Protected identifier of `id`: bits 0-5 id, bit 6 P0 = ID0^ID1^ID2^ID4, bit 7 P1 = !(ID1^ID3^ID4^ID5)
+pub const fn protected_id(id: u8) -> u8 {
+  let id = id & 0x3F;
+  let p0 = (id ^ id >> 1 ^ id >> 2 ^ id >> 4) & 1;
+  let p1 = !(id >> 1 ^ id >> 3 ^ id >> 4 ^ id >> 5) & 1;
+  id | p0 << 6 | p1 << 7
+}
+
+/// Frame id of a protected identifier, None if its parity bits are wrong
+pub fn frame_id(pid: u8) -> Option<u8> {
+  let id = pid & 0x3F;
+  (protected_id(id) == pid).then_some(id)
+}
+
+/// Checksum of a response: inverted 8-bit sum with carry, over the protected id as well for
+/// `Checksum::Enhanced` (except diagnostic frames, always classic)
+pub fn checksum(model: Checksum, pid: u8, data: &[u8]) -> u8 {
+  let diagnostic = matches!(pid & 0x3F, MASTER_REQUEST | SLAVE_RESPONSE);
+  let mut sum = if model == Checksum::Enhanced && !diagnostic { pid as u16 } else { 0 };
+  for &byte in data {
+    sum += byte as u16;
+    if sum > 0xFF {
+      sum -= 0xFF;
+    }
+  }
+  !(sum as u8)
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum State {
+  // Waiting for a break
+  Idle,
+  Sync,
+  Pid,
+  // Collecting a subscribed response (data and checksum)
+  Data(Frame, u8),
+  // Skipping our own response as it comes back
+  Echo(usize),
+}
+
+/// Slave side of the bus for a table of frames
+pub struct LinSlave<'a> {
+  frames: &'a [Frame],
+  model: Checksum,
+  state: State,
+  buf: Vec<u8, { MAX_DATA + 1 }>,
+}
+
+impl<'a> LinSlave<'a> {
+  pub const fn new(frames: &'a [Frame], model: Checksum) -> Self {
+    Self {
+      frames,
+      model,
+      state: State::Idle,
+      buf: Vec::new(),
+    }
+  }
+
+  /// The UART detected a break: a new header starts (aborting any frame in progress)
+  pub fn on_break(&mut self) {
+    self.state = State::Sync;
+  }
+
+  /// Feed one received byte; returns an event when it completes one
+  pub fn push(&mut self, byte: u8) -> Option<LinEvent<'_>> {
+    match self.state {
+      State::Idle => None,
+      // The break itself may also arrive as a 0x00 byte
+      State::Sync if byte == 0x00 => None,
+      State::Sync if byte == SYNC => {
+        self.state = State::Pid;
+        None
+      }
+      State::Sync => {
+        self.state = State::Idle;
+        Some(LinEvent::Error(LinError::Sync))
+      }
+      State::Pid => {
+        self.state = State::Idle;
+        let Some(id) = frame_id(byte) else {
+          return Some(LinEvent::Error(LinError::Parity(byte)));
+        };
+        let frame = *self.frames.iter().find(|f| f.id == id)?;
+        match frame.direction {
+          Direction::Publish => Some(LinEvent::Publish(frame)),
+          Direction::Subscribe => {
+            self.buf.clear();
+            self.state = State::Data(frame, byte);
+            None
+          }
+        }
+      }
+      State::Data(frame, pid) => {
+        self.buf.push(byte).ok();
+        let len = (frame.len as usize).min(MAX_DATA);
+        if self.buf.len() <= len {
+          return None;
+        }
+        self.state = State::Idle;
+        let (data, sum) = self.buf.split_at(len);
+        if sum[0] == checksum(self.model, pid, data) {
+          Some(LinEvent::Received { id: frame.id, data })
+        } else {
+          Some(LinEvent::Error(LinError::Checksum(frame.id)))
+        }
+      }
+      State::Echo(remaining) => {
+        self.state = if remaining > 1 { State::Echo(remaining - 1) } else { State::Idle };
+        None
+      }
+    }
+  }
+
+  /// Response bytes for a `Publish` header of `id`: `data` (up to 8 bytes) and the checksum.
+  /// The echo of these bytes is skipped.
+  pub fn response(&mut self, id: u8, data: &[u8]) -> Vec<u8, { MAX_DATA + 1 }> {
+    let data = &data[..data.len().min(MAX_DATA)];
+    let mut out = Vec::new();
+    out.extend_from_slice(data).ok();
+    out.push(checksum(self.model, protected_id(id), data)).ok();
+    self.state = State::Echo(out.len());
+    out
+  }
+}
@@ -0,0 +1,449 @@
+//! Comms message layer carried inside HDLC frames
+// Comms message format (little-endian):
+// - command:      u16 (bit 15: payload compressed with `protocol::heatshrink`, `comm_compress`)
+// - id:           u8  (correlation: 0 = unassigned, `comm::send` assigns one; replies echo the request's)
+// - src:          u8  (`comms_routing` only: sender node address)
+// - dst:          u8  (`comms_routing` only: destination node address)
+// - hops:         u8  (`comms_routing` only: hops crossed so far)
+// - fragments:    u16 (total fragments)
+// - fragment:     u16 (0-based index)
+// - length:       u16  (payload length in bytes)
+// - payload:      [u8; length]
+// The addressed header (`comms_routing`) is protocol version 2: several nodes can share one
+// RS-485 style bus, each taking the frames whose dst is its address (or `BROADCAST`).
+
+use heapless::Vec;
+
+/// Header layout version: 1 = plain 9-byte header, 2 = addressed 12-byte header (`comms_routing`)
+pub const COMMS_PROTOCOL_VERSION: u8 = if cfg!(feature = "comms_routing") { 2 } else { 1 };
+
+pub const COMMS_HEADER_LEN: usize = if cfg!(feature = "comms_routing") { 12 } else { 9 };
+/// Command bit flagging a heatshrink-compressed payload (`comm::write` sets it and
+/// `comm::dispatch` clears it with feature `comm_compress`)
+pub const COMMS_COMPRESSED: u16 = 0x8000;
+/// Largest payload per message (`comms_small` halves it for parts with little RAM)
+pub const COMMS_MAX_PAYLOAD: usize = if cfg!(feature = "comms_small") { 128 } else { 256 };
+/// Worst-case HDLC frame of a full message: every byte escaped, plus FCS and flags
+pub const COMMS_FRAMED_MAX: usize = 2 * (COMMS_HEADER_LEN + COMMS_MAX_PAYLOAD + 2) + 2;
+
+pub type CommsPayload = Vec<u8, COMMS_MAX_PAYLOAD>;
+pub type CommsFrameBuf = Vec<u8, { COMMS_HEADER_LEN + COMMS_MAX_PAYLOAD }>;
+
+/// Command identifiers for Comms messages.
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+  Ack = 0x01,
+  Nak = 0x02,
+  Ping = 0x03,
+  Raw = 0x04,
+  Stats = 0x05,
+  Alarm = 0x06,
+  AlarmAck = 0x07,
+  Rules = 0x08,
+  ConfigExport = 0x09,
+  ConfigImport = 0x0A,
+  Telemetry = 0x0B,
+  Sensors = 0x0C,
+  GetConfig = 0x0D,
+  SetConfig = 0x0E,
+  Ident = 0x0F,
+  SetKey = 0x10,
+  Subscribe = 0x11,
+  Unsubscribe = 0x12,
+  Publish = 0x13,
+  Protection = 0x14,
+  Bootloader = 0x15,
+  EventLog = 0x16,
+  Schedule = 0x17,
+}
+
+impl From<Command> for u16 {
+  fn from(c: Command) -> Self {
+    c as u16
+  }
+}
+
+impl core::convert::TryFrom<u16> for Command {
+  type Error = ();
+  fn try_from(value: u16) -> Result<Self, Self::Error> {
+    match value {
+      0x01 => Ok(Command::Ack),
+      0x02 => Ok(Command::Nak),
+      0x03 => Ok(Command::Ping),
+      0x04 => Ok(Command::Raw),
+      0x05 => Ok(Command::Stats),
+      0x06 => Ok(Command::Alarm),
+      0x07 => Ok(Command::AlarmAck),
+      0x08 => Ok(Command::Rules),
+      0x09 => Ok(Command::ConfigExport),
+      0x0A => Ok(Command::ConfigImport),
+      0x0B => Ok(Command::Telemetry),
+      0x0C => Ok(Command::Sensors),
+      0x0D => Ok(Command::GetConfig),
+      0x0E => Ok(Command::SetConfig),
+      0x0F => Ok(Command::Ident),
+      0x10 => Ok(Command::SetKey),
+      0x11 => Ok(Command::Subscribe),
+      0x12 => Ok(Command::Unsubscribe),
+      0x13 => Ok(Command::Publish),
+      0x14 => Ok(Command::Protection),
+      0x15 => Ok(Command::Bootloader),
+      0x16 => Ok(Command::EventLog),
+      0x17 => Ok(Command::Schedule),
+      _ => Err(()),
+    }
+  }
+}
+
+/// Error codes carried in automatic `Command::Nak` replies.
+///
+/// NAK payload: `[code: u8, offending id: u8]`; the NAK message id also echoes the offending id.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum NakCode {
+  BadLength = 0x01,
+  BadCommand = 0x02,
+  QueueFull = 0x03,
+  FcsError = 0x04,
+  AuthFailed = 0x05,
+}
+
+impl From<NakCode> for u8 {
+  fn from(c: NakCode) -> Self {
+    c as u8
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct Message {
+  pub command: u16,
+  /// Correlation id (0: unassigned); a reply carries the id of its request
+  pub id: u8,
+  #[cfg(feature = "comms_routing")]
+  pub src: u8,
+  #[cfg(feature = "comms_routing")]
+  pub dst: u8,
+  #[cfg(feature = "comms_routing")]
+  pub hops: u8,
+  pub fragments: u16, // todo: future use
+  pub fragment: u16,  // todo: future use
+  pub length: u16,
+  pub payload: CommsPayload,
+}
+
+impl Default for Message {
+  fn default() -> Self {
+    Self {
+      command: 0,
+      id: 0,
+      #[cfg(feature = "comms_routing")]
+      src: 0,
+      #[cfg(feature = "comms_routing")]
+      dst: super::routing::BROADCAST,
+      #[cfg(feature = "comms_routing")]
+      hops: 0,
+      fragments: 1,
+      fragment: 0,
+      length: 0,
+      payload: Vec::new(),
+    }
+  }
+}
+
+impl Message {
+  /// Convenience constructor with defaults (id=0, fragments=1, fragment=1).
+  pub fn new<C: Into<u16>>(command: C, payload: &[u8]) -> Self {
+    let mut buf: Vec<u8, COMMS_MAX_PAYLOAD> = Vec::new();
+    let take = core::cmp::min(payload.len(), COMMS_MAX_PAYLOAD);
+    let _ = buf.extend_from_slice(&payload[..take]);
+    Self {
+      command: command.into(),
+      id: 0,
+      #[cfg(feature = "comms_routing")]
+      src: 0,
+      #[cfg(feature = "comms_routing")]
+      dst: super::routing::BROADCAST,
+      #[cfg(feature = "comms_routing")]
+      hops: 0,
+      fragments: 1,
+      fragment: 1,
+      length: take as u16,
+      payload: buf,
+    }
+  }
+
+  /// Encode header + payload (unframed) into `buf`
+  pub fn encode(&self, buf: &mut CommsFrameBuf) {
+    buf.clear();
+    let len_usize = core::cmp::min(self.payload.len(), COMMS_MAX_PAYLOAD);
+    let len: u16 = len_usize as u16; // Use actual payload length, not msg.length field
+
+    buf.extend_from_slice(&self.command.to_le_bytes()).ok();
+    buf.push(self.id).ok();
+    #[cfg(feature = "comms_routing")]
+    buf.extend_from_slice(&[self.src, self.dst, self.hops]).ok();
+    buf.extend_from_slice(&self.fragments.to_le_bytes()).ok();
+    buf.extend_from_slice(&self.fragment.to_le_bytes()).ok();
+    buf.extend_from_slice(&len.to_le_bytes()).ok();
+
+    buf.extend_from_slice(&self.payload[..len_usize]).ok();
+  }
+
+  /// Parse header + payload (unframed, little-endian) into an owned message
+  /// On failure returns the NAK code and the offending message id (0 if the header is incomplete)
+  pub fn parse(bytes: &[u8]) -> Result<Self, (NakCode, u8)> {
+    MessageRef::parse(bytes).map(|msg| msg.to_message())
+  }
+
+  /// Borrowed view of this message
+  pub fn view(&self) -> MessageRef<'_> {
+    MessageRef {
+      command: self.command,
+      id: self.id,
+      #[cfg(feature = "comms_routing")]
+      src: self.src,
+      #[cfg(feature = "comms_routing")]
+      dst: self.dst,
+      #[cfg(feature = "comms_routing")]
+      hops: self.hops,
+      fragments: self.fragments,
+      fragment: self.fragment,
+      payload: &self.payload,
+    }
+  }
+}
+
+/// A parsed message borrowing its payload from the frame buffer (no copy)
+#[derive(Copy, Clone, Debug)]
+pub struct MessageRef<'a> {
+  pub command: u16,
+  pub id: u8,
+  #[cfg(feature = "comms_routing")]
+  pub src: u8,
+  #[cfg(feature = "comms_routing")]
+  pub dst: u8,
+  #[cfg(feature = "comms_routing")]
+  pub hops: u8,
+  pub fragments: u16,
+  pub fragment: u16,
+  pub payload: &'a [u8],
+}
+
+impl<'a> MessageRef<'a> {
+  /// Parse header + payload (unframed, little-endian), borrowing the payload from `bytes`
+  /// On failure returns the NAK code and the offending message id (0 if the header is incomplete)
+  pub fn parse(bytes: &'a [u8]) -> Result<Self, (NakCode, u8)> {
+    if bytes.len() < COMMS_HEADER_LEN {
+      return Err((NakCode::BadLength, 0));
+    }
+    let cmd = u16::from_le_bytes([bytes[0], bytes[1]]);
+    let id = bytes[2];
+    // Fields after the id shift by 3 bytes when src/dst/hops are present
+    let h = COMMS_HEADER_LEN - 9;
+    let frags = u16::from_le_bytes([bytes[h + 3], bytes[h + 4]]);
+    let frag = u16::from_le_bytes([bytes[h + 5], bytes[h + 6]]);
+    let len = u16::from_le_bytes([bytes[h + 7], bytes[h + 8]]) as usize;
+    let total = COMMS_HEADER_LEN + len;
+
+    // The compression flag is carried on top of any command
+    if Command::try_from(cmd & !COMMS_COMPRESSED).is_err() {
+      return Err((NakCode::BadCommand, id));
+    }
+
+    // Check if frame has the expected length (header + payload)
+    if bytes.len() != total || len > COMMS_MAX_PAYLOAD {
+      return Err((NakCode::BadLength, id));
+    }
+
+    Ok(MessageRef {
+      command: cmd,
+      id,
+      #[cfg(feature = "comms_routing")]
+      src: bytes[3],
+      #[cfg(feature = "comms_routing")]
+      dst: bytes[4],
+      #[cfg(feature = "comms_routing")]
+      hops: bytes[5],
+      fragments: frags,
+      fragment: frag,
+      payload: &bytes[COMMS_HEADER_LEN..total],
+    })
+  }
+
+  /// Reply to this message: same id, addressed back to the sender with `comms_routing`
+  pub fn reply<C: Into<u16>>(&self, command: C, payload: &[u8]) -> Message {
+    let mut reply = Message::new(command, payload);
+    reply.id = self.id;
+    #[cfg(feature = "comms_routing")]
+    {
+      reply.dst = self.src;
+    }
+    reply
+  }
+
+  /// Owned copy (for queueing past the lifetime of the frame buffer)
+  pub fn to_message(&self) -> Message {
+    Message {
+      command: self.command,
+      id: self.id,
+      #[cfg(feature = "comms_routing")]
+      src: self.src,
+      #[cfg(feature = "comms_routing")]
+      dst: self.dst,
+      #[cfg(feature = "comms_routing")]
+      hops: self.hops,
+      fragments: self.fragments,
+      fragment: self.fragment,
+      length: self.payload.len().min(COMMS_MAX_PAYLOAD) as u16,
+      payload: Vec::from_slice(&self.payload[..self.payload.len().min(COMMS_MAX_PAYLOAD)]).unwrap_or_default(),
+    }
+  }
+}
+
+/// Recently received messages, to drop retransmitted copies instead of acting on them twice
+///
+/// A message is identified by command, id and fragment (and sender with `comms_routing`) and
+/// remembered for `window_ms`; the `N` most recent are kept. Id 0 (unassigned) is never a
+/// duplicate.
+pub struct RecentIds<const N: usize> {
+  // (key, received at ms); key 0 marks an empty slot
+  entries: [(u64, u32); N],
+  next: usize,
+  window_ms: u32,
+}
+
+impl<const N: usize> RecentIds<N> {
+  pub const fn new(window_ms: u32) -> Self {
+    Self {
+      entries: [(0, 0); N],
+      next: 0,
+      window_ms,
+    }
+  }
+
+  /// True if `msg` was already received within the window; otherwise remembers it
+  pub fn is_duplicate(&mut self, msg: &MessageRef<'_>, now_ms: u32) -> bool {
+    if msg.id == 0 || N == 0 {
+      return false;
+    }
+    let key = Self::key(msg);
+    if self.entries.iter().any(|&(k, at)| k == key && now_ms.wrapping_sub(at) < self.window_ms) {
+      return true;
+    }
+    self.entries[self.next] = (key, now_ms);
+    self.next = (self.next + 1) % N;
+    false
+  }
+
+  /// Forget `msg` after refusing it (NAK `QueueFull`), so its retransmitted copy is delivered
+  pub fn forget(&mut self, msg: &MessageRef<'_>) {
+    let key = Self::key(msg);
+    for entry in self.entries.iter_mut().filter(|(k, _)| *k == key) {
+      *entry = (0, 0);
+    }
+  }
+
+  /// True if a message with `id` from `src` (0 without `comms_routing`) was received within the
+  /// window, i.e. a message sent with that id answers it
+  pub fn answers(&self, id: u8, src: u8, now_ms: u32) -> bool {
+    id != 0
+      && self
+        .entries
+        .iter()
+        .any(|&(k, at)| k != 0 && (k >> 16) as u8 == id && (k >> 40) as u8 == src && now_ms.wrapping_sub(at) < self.window_ms)
+  }
+
+  fn key(msg: &MessageRef<'_>) -> u64 {
+    #[cfg(feature = "comms_routing")]
+    let src = msg.src as u64;
+    #[cfg(not(feature = "comms_routing"))]
+    let src = 0u64;
+    // Bit 56 keeps every key apart from an empty slot
+    1 << 56 | src << 40 | (msg.fragment as u64) << 24 | (msg.id as u64) << 16 | msg.command as u64
+  }
+}
+
+/// Replies to recently received messages, written again when a retransmitted copy of the
+/// message shows its reply was lost
+///
+/// The latest reply per id (and peer with `comms_routing`) is kept for `window_ms`; the `N` most
+/// recent replies are kept. A fragmented reply is not kept: a single fragment would stand in
+/// for all of them.
+pub struct ReplyCache<const N: usize> {
+  // (reply, sent at ms)
+  replies: [Option<(Message, u32)>; N],
+  next: usize,
+  window_ms: u32,
+}
+
+impl<const N: usize> ReplyCache<N> {
+  pub const fn new(window_ms: u32) -> Self {
+    Self {
+      replies: [const { None }; N],
+      next: 0,
+      window_ms,
+    }
+  }
+
+  /// Remember `reply`, replacing an earlier reply to the same message (a fragmented reply only
+  /// drops the earlier one)
+  pub fn store(&mut self, reply: &Message, now_ms: u32) {
+    if N == 0 {
+      return;
+    }
+    if reply.fragments > 1 {
+      for cached in self
+        .replies
+        .iter_mut()
+        .filter(|cached| cached.as_ref().is_some_and(|(cached, _)| Self::same_peer(cached, reply)))
+      {
+        *cached = None;
+      }
+      return;
+    }
+    let slot = match self
+      .replies
+      .iter()
+      .position(|cached| cached.as_ref().is_some_and(|(cached, _)| Self::same_peer(cached, reply)))
+    {
+      Some(slot) => slot,
+      None => {
+        let slot = self.next;
+        self.next = (self.next + 1) % N;
+        slot
+      }
+    };
+    self.replies[slot] = Some((reply.clone(), now_ms));
+  }
+
+  /// The reply sent to `request` within the window, if any
+  pub fn find(&self, request: &MessageRef<'_>, now_ms: u32) -> Option<&Message> {
+    self.replies.iter().flatten().find_map(|(reply, at)| {
+      let to_request = reply.id == request.id && Self::peer(reply) == Self::sender(request);
+      (to_request && now_ms.wrapping_sub(*at) < self.window_ms).then_some(reply)
+    })
+  }
+
+  fn same_peer(a: &Message, b: &Message) -> bool {
+    a.id == b.id && Self::peer(a) == Self::peer(b)
+  }
+
+  #[cfg(feature = "comms_routing")]
+  fn peer(reply: &Message) -> u8 {
+    reply.dst
+  }
+  #[cfg(not(feature = "comms_routing"))]
+  fn peer(_reply: &Message) -> u8 {
+    0
+  }
+
+  #[cfg(feature = "comms_routing")]
+  fn sender(request: &MessageRef<'_>) -> u8 {
+    request.src
+  }
+  #[cfg(not(feature = "comms_routing"))]
+  fn sender(_request: &MessageRef<'_>) -> u8 {
+    0
+  }
+}
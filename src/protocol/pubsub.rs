@@ -0,0 +1,89 @@
+//! Topic-based publish/subscribe carried in Comms messages
+// Topics are u16 ids agreed between the device and the host (`topic::*` are reserved for the
+// starter's own streams; applications number theirs from `topic::USER`).
+// - `Command::Subscribe`:   payload is topic ids (u16 LE, back to back) to receive
+// - `Command::Unsubscribe`: payload is topic ids to stop receiving (empty: all of them)
+// - `Command::Publish`:     payload is the topic id (u16 LE) followed by the data
+// Publish is fire-and-forget (no reply); Subscribe/Unsubscribe are answered with `Ack`.
+
+/// Bytes a `Publish` payload spends on the topic id
+pub const PUBLISH_HEADER_LEN: usize = 2;
+
+/// Well-known topic ids
+pub mod topic {
+  /// System health records
+  pub const TELEMETRY: u16 = 0x0001;
+  /// Log lines (UTF-8 text)
+  pub const LOG: u16 = 0x0002;
+  /// Application control values
+  pub const CONTROL: u16 = 0x0003;
+  /// Calendar events fired by `service::scheduler` (data: the entry's event id)
+  pub const SCHEDULE: u16 = 0x0004;
+  /// First id free for applications
+  pub const USER: u16 = 0x0100;
+}
+
+/// Split a `Publish` payload into topic id and data
+pub fn decode_publish(payload: &[u8]) -> Option<(u16, &[u8])> {
+  if payload.len() < PUBLISH_HEADER_LEN {
+    return None;
+  }
+  let (id, data) = payload.split_at(PUBLISH_HEADER_LEN);
+  Some((u16::from_le_bytes([id[0], id[1]]), data))
+}
+
+/// Build a `Publish` payload (None if `data` does not fit in `M` bytes after the topic id)
+pub fn encode_publish<const M: usize>(topic: u16, data: &[u8]) -> Option<heapless::Vec<u8, M>> {
+  let mut out = heapless::Vec::new();
+  out.extend_from_slice(&topic.to_le_bytes()).ok()?;
+  out.extend_from_slice(data).ok()?;
+  Some(out)
+}
+
+/// Topic ids of a `Subscribe`/`Unsubscribe` payload (None if its length is odd)
+pub fn decode_topics(payload: &[u8]) -> Option<impl Iterator<Item = u16> + '_> {
+  if payload.len() & 1 != 0 {
+    return None;
+  }
+  Some(payload.chunks_exact(2).map(|id| u16::from_le_bytes([id[0], id[1]])))
+}
+
+/// Set of subscribed topics
+#[derive(Clone, Debug, Default)]
+pub struct TopicSet<const N: usize> {
+  topics: heapless::Vec<u16, N>,
+}
+
+impl<const N: usize> TopicSet<N> {
+  pub const fn new() -> Self {
+    Self { topics: heapless::Vec::new() }
+  }
+
+  pub fn contains(&self, topic: u16) -> bool {
+    self.topics.contains(&topic)
+  }
+
+  /// Add `topic` (Err if the set is full; adding a member again is a no-op)
+  pub fn insert(&mut self, topic: u16) -> Result<(), u16> {
+    if self.contains(topic) {
+      return Ok(());
+    }
+    self.topics.push(topic)
+  }
+
+  pub fn remove(&mut self, topic: u16) {
+    self.topics.retain(|&t| t != topic);
+  }
+
+  pub fn clear(&mut self) {
+    self.topics.clear();
+  }
+
+  pub fn len(&self) -> usize {
+    self.topics.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.topics.is_empty()
+  }
+}
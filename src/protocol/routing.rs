@@ -0,0 +1,98 @@
+//! Node addressing and static routing for bridged links
+// With the `comms_routing` feature the Comms header carries src/dst node addresses and a
+// hop count. A node delivers messages addressed to itself (or broadcast) locally and forwards
+// everything else over the link its routing table names, dropping messages that exceed
+// `MAX_HOPS` so misconfigured tables cannot loop frames forever.
+
+use heapless::Vec;
+
+/// Destination address delivered to every node (never forwarded)
+pub const BROADCAST: u8 = 0xFF;
+
+/// Messages that have already crossed this many hops are dropped instead of forwarded
+pub const MAX_HOPS: u8 = 4;
+
+/// Application-defined link identifier (e.g. 0 = VCP, 1 = RS-485, 2 = LoRa)
+pub type LinkId = u8;
+
+/// Why a message was not delivered or forwarded
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DropReason {
+  HopLimit,
+  NoRoute,
+}
+
+/// Routing decision for a received message
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Route {
+  /// Addressed to this node (or broadcast): deliver to the application
+  Local,
+  /// Forward over the given link (caller increments the hop count)
+  Forward(LinkId),
+  Drop(DropReason),
+}
+
+/// Static routing table: destination address -> link, with an optional default route
+#[derive(Clone, Debug)]
+pub struct RoutingTable<const N: usize> {
+  address: u8,
+  routes: Vec<(u8, LinkId), N>,
+  default: Option<LinkId>,
+}
+
+impl<const N: usize> RoutingTable<N> {
+  pub const fn new(address: u8) -> Self {
+    Self {
+      address,
+      routes: Vec::new(),
+      default: None,
+    }
+  }
+
+  /// This node's address
+  pub fn address(&self) -> u8 {
+    self.address
+  }
+
+  pub fn set_address(&mut self, address: u8) {
+    self.address = address;
+  }
+
+  /// Add or replace the route for `dst`; returns the rejected route if the table is full
+  pub fn add(&mut self, dst: u8, link: LinkId) -> Result<(), (u8, LinkId)> {
+    match self.routes.iter_mut().find(|(d, _)| *d == dst) {
+      Some(route) => route.1 = link,
+      None => self.routes.push((dst, link))?,
+    }
+    Ok(())
+  }
+
+  /// Remove the route for `dst`
+  pub fn remove(&mut self, dst: u8) {
+    self.routes.retain(|(d, _)| *d != dst);
+  }
+
+  /// Link used for destinations without an explicit route
+  pub fn set_default(&mut self, link: Option<LinkId>) {
+    self.default = link;
+  }
+
+  /// Link for `dst` (explicit route first, then the default route)
+  pub fn lookup(&self, dst: u8) -> Option<LinkId> {
+    self.routes.iter().find(|(d, _)| *d == dst).map(|(_, link)| *link).or(self.default)
+  }
+
+  /// Decide what to do with a message addressed to `dst` that has crossed `hops` hops
+  pub fn route(&self, dst: u8, hops: u8) -> Route {
+    if dst == self.address || dst == BROADCAST {
+      Route::Local
+    } else if hops >= MAX_HOPS {
+      Route::Drop(DropReason::HopLimit)
+    } else {
+      match self.lookup(dst) {
+        Some(link) => Route::Forward(link),
+        None => Route::Drop(DropReason::NoRoute),
+      }
+    }
+  }
+}
@@ -0,0 +1,187 @@
+//! Host-configurable "if condition then action" rules
+// Rule wire format (8 bytes, carried back to back in `Command::Rules` payloads):
+// - condition:    u8   (1 input low, 2 input high, 3 ADC above, 4 ADC below)
+// - source:       u8   (input pin / ADC channel, numbered by the application)
+// - value:        u16  (hold time in ms for inputs, threshold for ADC; little-endian)
+// - action:       u8   (1 raise alarm, 2 set output)
+// - target:       u8   (alarm id / output pin)
+// - param:        u8   (alarm: severity | latching << 7; output: level 0/1)
+// - reserved:     u8   (0)
+//
+// Flash record (kept in the `service::config` region next to the device configuration):
+// - magic:        u16  (0x52C1)
+// - version:      u8   (RULES_RECORD_VERSION)
+// - count:        u8   (rules, up to RULES_MAX)
+// - rules:        [u8; 8 * count]
+// - crc:          u16  (PPP FCS-16 over everything before it)
+
+use heapless::Vec;
+
+use super::hdlc::fcs16_ppp;
+
+/// Encoded size of one rule
+pub const RULE_LEN: usize = 8;
+/// Maximum number of rules held by the engine
+pub const RULES_MAX: usize = 16;
+pub const RULES_RECORD_VERSION: u8 = 1;
+/// Longest encoded flash record
+pub const RULES_RECORD_MAX: usize = HEADER_LEN + RULES_MAX * RULE_LEN + CRC_LEN;
+
+const MAGIC: u16 = 0x52C1;
+const HEADER_LEN: usize = 4;
+const CRC_LEN: usize = 2;
+const LATCHING_BIT: u8 = 1 << 7;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Condition {
+  /// Input `pin` low for longer than `ms`
+  InputLow { pin: u8, ms: u16 },
+  /// Input `pin` high for longer than `ms`
+  InputHigh { pin: u8, ms: u16 },
+  /// ADC `channel` reading above `threshold`
+  AdcAbove { channel: u8, threshold: u16 },
+  /// ADC `channel` reading below `threshold`
+  AdcBelow { channel: u8, threshold: u16 },
+}
+
+/// What a condition samples
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Source {
+  Input(u8),
+  Adc(u8),
+}
+
+impl Condition {
+  pub fn source(&self) -> Source {
+    match *self {
+      Condition::InputLow { pin, .. } | Condition::InputHigh { pin, .. } => Source::Input(pin),
+      Condition::AdcAbove { channel, .. } | Condition::AdcBelow { channel, .. } => Source::Adc(channel),
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Action {
+  /// Raise alarm `id` while the condition holds, clear it when it stops holding
+  Alarm { id: u8, severity: u8, latching: bool },
+  /// Drive output `pin` to `level` while the condition holds, to the opposite level otherwise
+  Output { pin: u8, level: bool },
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Rule {
+  pub condition: Condition,
+  pub action: Action,
+}
+
+/// Per-rule evaluation state
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct RuleState {
+  // When the raw input condition started holding
+  since_ms: Option<u32>,
+  /// Whether the rule's action is currently applied
+  pub active: bool,
+}
+
+impl Rule {
+  pub fn to_bytes(&self) -> [u8; RULE_LEN] {
+    let (kind, source, value) = match self.condition {
+      Condition::InputLow { pin, ms } => (1, pin, ms),
+      Condition::InputHigh { pin, ms } => (2, pin, ms),
+      Condition::AdcAbove { channel, threshold } => (3, channel, threshold),
+      Condition::AdcBelow { channel, threshold } => (4, channel, threshold),
+    };
+    let (action, target, param) = match self.action {
+      Action::Alarm { id, severity, latching } => (1, id, severity | if latching { LATCHING_BIT } else { 0 }),
+      Action::Output { pin, level } => (2, pin, level as u8),
+    };
+    let value = value.to_le_bytes();
+    [kind, source, value[0], value[1], action, target, param, 0]
+  }
+
+  /// Decode one rule (None for unknown condition/action kinds)
+  pub fn from_bytes(bytes: &[u8; RULE_LEN]) -> Option<Self> {
+    let (source, value) = (bytes[1], u16::from_le_bytes([bytes[2], bytes[3]]));
+    let condition = match bytes[0] {
+      1 => Condition::InputLow { pin: source, ms: value },
+      2 => Condition::InputHigh { pin: source, ms: value },
+      3 => Condition::AdcAbove {
+        channel: source,
+        threshold: value,
+      },
+      4 => Condition::AdcBelow {
+        channel: source,
+        threshold: value,
+      },
+      _ => return None,
+    };
+    let action = match bytes[4] {
+      1 => Action::Alarm {
+        id: bytes[5],
+        severity: bytes[6] & !LATCHING_BIT,
+        latching: bytes[6] & LATCHING_BIT != 0,
+      },
+      2 => Action::Output {
+        pin: bytes[5],
+        level: bytes[6] != 0,
+      },
+      _ => return None,
+    };
+    Some(Rule { condition, action })
+  }
+
+  /// Feed a fresh sample of the condition's source (input: 0 low / 1 high, ADC: raw reading)
+  /// taken at `now_ms`; returns the new activation when it changes
+  pub fn evaluate(&self, state: &mut RuleState, sample: u16, now_ms: u32) -> Option<bool> {
+    let holds = match self.condition {
+      Condition::InputLow { ms, .. } | Condition::InputHigh { ms, .. } => {
+        let raw = (sample != 0) == matches!(self.condition, Condition::InputHigh { .. });
+        if raw {
+          let since = *state.since_ms.get_or_insert(now_ms);
+          // Once met, the hold stays met while the input does: the elapsed time would come
+          // back below the hold time after the u32 millisecond clock wraps (49.7 days)
+          state.active || now_ms.wrapping_sub(since) > ms as u32
+        } else {
+          state.since_ms = None;
+          false
+        }
+      }
+      Condition::AdcAbove { threshold, .. } => sample > threshold,
+      Condition::AdcBelow { threshold, .. } => sample < threshold,
+    };
+    (holds != state.active).then(|| {
+      state.active = holds;
+      holds
+    })
+  }
+}
+
+/// Encode `rules` (at most `RULES_MAX`) as a flash record
+pub fn encode_record(rules: &[Rule]) -> Vec<u8, RULES_RECORD_MAX> {
+  let rules = &rules[..rules.len().min(RULES_MAX)];
+  let mut out = Vec::new();
+  out.extend_from_slice(&MAGIC.to_le_bytes()).ok();
+  out.extend_from_slice(&[RULES_RECORD_VERSION, rules.len() as u8]).ok();
+  for rule in rules {
+    out.extend_from_slice(&rule.to_bytes()).ok();
+  }
+  let crc = fcs16_ppp(&out);
+  out.extend_from_slice(&crc.to_le_bytes()).ok();
+  out
+}
+
+/// Decode a flash record from the start of `bytes` (trailing bytes are ignored)
+pub fn decode_record(bytes: &[u8]) -> Option<Vec<Rule, RULES_MAX>> {
+  if bytes.len() < HEADER_LEN + CRC_LEN || u16::from_le_bytes([bytes[0], bytes[1]]) != MAGIC || bytes[2] != RULES_RECORD_VERSION {
+    return None;
+  }
+  let count = bytes[3] as usize;
+  let end = HEADER_LEN + count * RULE_LEN;
+  if count > RULES_MAX || bytes.len() < end + CRC_LEN || fcs16_ppp(&bytes[..end]) != u16::from_le_bytes([bytes[end], bytes[end + 1]]) {
+    return None;
+  }
+  bytes[HEADER_LEN..end]
+    .chunks_exact(RULE_LEN)
+    .map(|chunk| Rule::from_bytes(chunk.try_into().unwrap()))
+    .collect()
+}
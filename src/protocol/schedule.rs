@@ -0,0 +1,214 @@
+//! Calendar schedule entries: "every day at 02:00, event 3"
+// Entry wire format (6 bytes, carried back to back in `Command::Schedule` payloads):
+// - minute:       u8   (0-59)
+// - hour:         u8   (0-23, ANY_HOUR: every hour)
+// - weekdays:     u8   (bit 0 Monday .. bit 6 Sunday; 0: any day of the week)
+// - day:          u8   (day of the month 1-31; 0: any)
+// - event:        u8   (handed to the application when the entry fires)
+// - reserved:     u8   (0)
+// An entry fires at second 0 of every minute matching all of its fields, so weekdays and day
+// together mean both (Friday the 13th); a day the month does not have never matches.
+//
+// Flash record (kept in the `service::config` region next to the device configuration):
+// - magic:        u16  (0x5C4E)
+// - version:      u8   (SCHEDULE_RECORD_VERSION)
+// - count:        u8   (entries, up to SCHEDULE_MAX)
+// - entries:      [u8; 6 * count]
+// - crc:          u16  (PPP FCS-16 over everything before it)
+
+use heapless::Vec;
+
+use super::hdlc::fcs16_ppp;
+
+/// Encoded size of one entry
+pub const SCHEDULE_ENTRY_LEN: usize = 6;
+/// Maximum number of entries held by the scheduler
+pub const SCHEDULE_MAX: usize = 8;
+/// `hour` value of an entry firing every hour
+pub const ANY_HOUR: u8 = 0xFF;
+pub const SCHEDULE_RECORD_VERSION: u8 = 1;
+/// Longest encoded flash record
+pub const SCHEDULE_RECORD_MAX: usize = HEADER_LEN + SCHEDULE_MAX * SCHEDULE_ENTRY_LEN + CRC_LEN;
+
+const MAGIC: u16 = 0x5C4E;
+const HEADER_LEN: usize = 4;
+const CRC_LEN: usize = 2;
+// Days searched for an entry's next firing: four years reach every 29 February
+const SEARCH_DAYS: u32 = 4 * 365 + 1;
+
+/// Calendar date and time (24-hour clock), as kept by the RTC
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct CalendarTime {
+  pub year: u16,
+  /// 1-12
+  pub month: u8,
+  /// 1-31
+  pub day: u8,
+  pub hour: u8,
+  pub minute: u8,
+  pub second: u8,
+}
+
+impl CalendarTime {
+  /// Day of the week, 1 Monday .. 7 Sunday
+  pub fn weekday(&self) -> u8 {
+    // Sakamoto's method (0 Sunday), counting January and February with the previous year
+    const OFFSETS: [u16; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+    let year = if self.month < 3 { self.year - 1 } else { self.year };
+    let sunday_based = (year + year / 4 - year / 100 + year / 400 + OFFSETS[self.month as usize - 1] + self.day as u16) % 7;
+    if sunday_based == 0 { 7 } else { sunday_based as u8 }
+  }
+
+  /// Days in this month
+  pub fn days_in_month(&self) -> u8 {
+    match self.month {
+      2 if self.year.is_multiple_of(4) && (!self.year.is_multiple_of(100) || self.year.is_multiple_of(400)) => 29,
+      2 => 28,
+      4 | 6 | 9 | 11 => 30,
+      _ => 31,
+    }
+  }
+
+  // Midnight of the next day
+  fn next_day(&self) -> Self {
+    let mut next = Self {
+      day: self.day + 1,
+      hour: 0,
+      minute: 0,
+      second: 0,
+      ..*self
+    };
+    if next.day > self.days_in_month() {
+      next.day = 1;
+      next.month += 1;
+      if next.month > 12 {
+        next.month = 1;
+        next.year += 1;
+      }
+    }
+    next
+  }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ScheduleEntry {
+  pub minute: u8,
+  /// None: every hour
+  pub hour: Option<u8>,
+  /// Bit 0 Monday .. bit 6 Sunday (0: any)
+  pub weekdays: u8,
+  /// Day of the month (0: any)
+  pub day: u8,
+  pub event: u8,
+}
+
+impl ScheduleEntry {
+  /// Every day at `hour:minute`
+  pub const fn daily(hour: u8, minute: u8, event: u8) -> Self {
+    Self {
+      minute,
+      hour: Some(hour),
+      weekdays: 0,
+      day: 0,
+      event,
+    }
+  }
+
+  pub fn to_bytes(&self) -> [u8; SCHEDULE_ENTRY_LEN] {
+    [self.minute, self.hour.unwrap_or(ANY_HOUR), self.weekdays, self.day, self.event, 0]
+  }
+
+  /// Decode one entry; None if a field is out of range
+  pub fn from_bytes(bytes: &[u8; SCHEDULE_ENTRY_LEN]) -> Option<Self> {
+    let [minute, hour, weekdays, day, event, _] = *bytes;
+    let hour = match hour {
+      ANY_HOUR => None,
+      0..24 => Some(hour),
+      _ => return None,
+    };
+    (minute < 60 && weekdays < 0x80 && day <= 31).then_some(Self {
+      minute,
+      hour,
+      weekdays,
+      day,
+      event,
+    })
+  }
+
+  /// True if the entry fires in the minute of `at`
+  pub fn matches(&self, at: &CalendarTime) -> bool {
+    self.matches_day(at) && self.minute == at.minute && self.hour.is_none_or(|hour| hour == at.hour)
+  }
+
+  fn matches_day(&self, at: &CalendarTime) -> bool {
+    (self.day == 0 || self.day == at.day) && (self.weekdays == 0 || self.weekdays & (1 << (at.weekday() - 1)) != 0)
+  }
+
+  /// First time the entry fires after `now` (second 0 of a later minute); None if it does not
+  /// within four years
+  pub fn next_after(&self, now: &CalendarTime) -> Option<CalendarTime> {
+    let mut date = *now;
+    // Today only from the next minute on
+    let (mut hour, mut minute) = (now.hour, now.minute + 1);
+    for _ in 0..SEARCH_DAYS {
+      let first = if self.matches_day(&date) { self.first_hour_from(hour, minute) } else { None };
+      if let Some(hour) = first {
+        return Some(CalendarTime {
+          hour,
+          minute: self.minute,
+          second: 0,
+          ..date
+        });
+      }
+      date = date.next_day();
+      (hour, minute) = (0, 0);
+    }
+    None
+  }
+
+  // Hour of the entry's first firing at or after `hour:minute` on a matching day
+  fn first_hour_from(&self, hour: u8, minute: u8) -> Option<u8> {
+    match self.hour {
+      Some(at) => ((at, self.minute) >= (hour, minute)).then_some(at),
+      None => {
+        let at = if self.minute >= minute { hour } else { hour + 1 };
+        (at < 24).then_some(at)
+      }
+    }
+  }
+}
+
+/// Earliest next firing of any of `entries` after `now`
+pub fn next_due(entries: &[ScheduleEntry], now: &CalendarTime) -> Option<CalendarTime> {
+  entries.iter().filter_map(|entry| entry.next_after(now)).min()
+}
+
+/// Encode `entries` (at most `SCHEDULE_MAX`) as a flash record
+pub fn encode_record(entries: &[ScheduleEntry]) -> Vec<u8, SCHEDULE_RECORD_MAX> {
+  let entries = &entries[..entries.len().min(SCHEDULE_MAX)];
+  let mut out = Vec::new();
+  out.extend_from_slice(&MAGIC.to_le_bytes()).ok();
+  out.extend_from_slice(&[SCHEDULE_RECORD_VERSION, entries.len() as u8]).ok();
+  for entry in entries {
+    out.extend_from_slice(&entry.to_bytes()).ok();
+  }
+  let crc = fcs16_ppp(&out);
+  out.extend_from_slice(&crc.to_le_bytes()).ok();
+  out
+}
+
+/// Decode a flash record from the start of `bytes` (trailing bytes are ignored)
+pub fn decode_record(bytes: &[u8]) -> Option<Vec<ScheduleEntry, SCHEDULE_MAX>> {
+  if bytes.len() < HEADER_LEN + CRC_LEN || u16::from_le_bytes([bytes[0], bytes[1]]) != MAGIC || bytes[2] != SCHEDULE_RECORD_VERSION {
+    return None;
+  }
+  let count = bytes[3] as usize;
+  let end = HEADER_LEN + count * SCHEDULE_ENTRY_LEN;
+  if count > SCHEDULE_MAX || bytes.len() < end + CRC_LEN || fcs16_ppp(&bytes[..end]) != u16::from_le_bytes([bytes[end], bytes[end + 1]]) {
+    return None;
+  }
+  bytes[HEADER_LEN..end]
+    .chunks_exact(SCHEDULE_ENTRY_LEN)
+    .map(|chunk| ScheduleEntry::from_bytes(chunk.try_into().unwrap()))
+    .collect()
+}
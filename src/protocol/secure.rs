@@ -0,0 +1,215 @@
+//! Authenticated encryption of Comms payloads (AES-128-CCM, feature `comm_crypto`)
+// Sealed payload (replaces the plaintext payload; the header stays in clear):
+// - nonce:      [u8; 13] (direction: u8, salt: [u8; 8], counter: u32 LE)
+// - ciphertext: [u8; n]  (n = plaintext length)
+// - tag:        [u8; 8]
+// The header fields that survive forwarding (command, id, fragments, fragment) are the
+// associated data, so a sealed payload cannot be replayed under another command or id.
+// Each side picks a salt per session and counts messages from 1; receivers drop a counter that
+// is not above the last one seen for the sender's salt. The direction byte keeps the host's and
+// the device's nonces apart even if their salts collide.
+//
+// A host session is bound to the device: its salt starts with the device's current challenge
+// (CHALLENGE_LEN random bytes), which the device sends with NAK `AuthFailed` to a frame that does
+// not carry it (payload: code, id, challenge). Opening a session replaces the challenge, so
+// frames recorded from earlier sessions, or earlier boots, are refused instead of reopening them.
+//
+// Key record (kept in the device configuration flash area):
+// - magic:        u16  (0x4B59)
+// - version:      u8   (KEY_RECORD_VERSION)
+// - key:          [u8; 16]
+// - epoch:        u32  (bumped every boot; the device salt starts with it, so nonces never
+//                       repeat across resets)
+// - crc:          u16  (PPP FCS-16 over everything before it)
+
+use aes::Aes128;
+use ccm::Ccm;
+use ccm::aead::generic_array::GenericArray;
+use ccm::aead::{AeadInPlace, KeyInit};
+use ccm::consts::{U8, U13};
+
+use super::hdlc::fcs16_ppp;
+use super::message::{COMMS_MAX_PAYLOAD, CommsPayload, Message};
+
+pub const KEY_LEN: usize = 16;
+pub const NONCE_LEN: usize = 13;
+pub const TAG_LEN: usize = 8;
+/// Bytes a sealed payload adds to the plaintext
+pub const SECURE_OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+/// Largest payload that still fits in a message once sealed
+pub const SECURE_MAX_PLAINTEXT: usize = COMMS_MAX_PAYLOAD - SECURE_OVERHEAD;
+
+/// Nonce direction byte for messages from the host
+pub const DIRECTION_HOST: u8 = b'H';
+/// Nonce direction byte for messages from the device
+pub const DIRECTION_DEVICE: u8 = b'D';
+/// Leading salt bytes of a host session that must match the device's challenge
+pub const CHALLENGE_LEN: usize = 4;
+
+pub const KEY_RECORD_VERSION: u8 = 1;
+pub const KEY_RECORD_LEN: usize = 4 + KEY_LEN + 4 + 2;
+
+const KEY_MAGIC: u16 = 0x4B59;
+
+pub type Key = [u8; KEY_LEN];
+
+type Aes128Ccm = Ccm<Aes128, U8, U13>;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SecureError {
+  /// Plaintext longer than `SECURE_MAX_PLAINTEXT`
+  TooLong,
+  /// Shorter than a nonce plus a tag
+  Malformed,
+  /// Tag mismatch: wrong key, or the frame was altered
+  Auth,
+  /// Counter not above the last one seen for this salt, or not sealed by the peer
+  Replay,
+  /// A new session whose salt does not start with the current challenge
+  Challenge,
+}
+
+/// Per-message nonce
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Nonce {
+  pub direction: u8,
+  pub salt: [u8; 8],
+  pub counter: u32,
+}
+
+impl Nonce {
+  pub fn to_bytes(&self) -> [u8; NONCE_LEN] {
+    let mut out = [0u8; NONCE_LEN];
+    out[0] = self.direction;
+    out[1..9].copy_from_slice(&self.salt);
+    out[9..].copy_from_slice(&self.counter.to_le_bytes());
+    out
+  }
+
+  pub fn from_bytes(bytes: &[u8; NONCE_LEN]) -> Self {
+    let mut salt = [0u8; 8];
+    salt.copy_from_slice(&bytes[1..9]);
+    Self {
+      direction: bytes[0],
+      salt,
+      counter: u32::from_le_bytes([bytes[9], bytes[10], bytes[11], bytes[12]]),
+    }
+  }
+}
+
+/// Associated data of `msg`: command, id, fragments, fragment (little-endian)
+pub fn aad(msg: &Message) -> [u8; 7] {
+  let mut out = [0u8; 7];
+  out[..2].copy_from_slice(&msg.command.to_le_bytes());
+  out[2] = msg.id;
+  out[3..5].copy_from_slice(&msg.fragments.to_le_bytes());
+  out[5..].copy_from_slice(&msg.fragment.to_le_bytes());
+  out
+}
+
+/// Encrypt and authenticate `plaintext` as a sealed payload
+pub fn seal(key: &Key, nonce: &Nonce, aad: &[u8], plaintext: &[u8]) -> Result<CommsPayload, SecureError> {
+  if plaintext.len() > SECURE_MAX_PLAINTEXT {
+    return Err(SecureError::TooLong);
+  }
+  let nonce_bytes = nonce.to_bytes();
+  let mut out = CommsPayload::new();
+  out.extend_from_slice(&nonce_bytes).map_err(|_| SecureError::TooLong)?;
+  out.extend_from_slice(plaintext).map_err(|_| SecureError::TooLong)?;
+  let tag = Aes128Ccm::new(GenericArray::from_slice(key))
+    .encrypt_in_place_detached(GenericArray::from_slice(&nonce_bytes), aad, &mut out[NONCE_LEN..])
+    .map_err(|_| SecureError::TooLong)?;
+  out.extend_from_slice(&tag).map_err(|_| SecureError::TooLong)?;
+  Ok(out)
+}
+
+/// Check and decrypt a sealed payload; returns its nonce (for replay checks) and the plaintext
+pub fn open(key: &Key, aad: &[u8], sealed: &[u8]) -> Result<(Nonce, CommsPayload), SecureError> {
+  if sealed.len() < SECURE_OVERHEAD {
+    return Err(SecureError::Malformed);
+  }
+  let (nonce_bytes, rest) = sealed.split_at(NONCE_LEN);
+  let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+  let mut out = CommsPayload::from_slice(ciphertext).map_err(|_| SecureError::Malformed)?;
+  Aes128Ccm::new(GenericArray::from_slice(key))
+    .decrypt_in_place_detached(GenericArray::from_slice(nonce_bytes), aad, &mut out, GenericArray::from_slice(tag))
+    .map_err(|_| SecureError::Auth)?;
+  let mut nonce = [0u8; NONCE_LEN];
+  nonce.copy_from_slice(nonce_bytes);
+  Ok((Nonce::from_bytes(&nonce), out))
+}
+
+/// Replay filter for the host: its open session (salt, last counter) and the challenge the next
+/// session must start its salt with
+#[derive(Clone, Debug)]
+pub struct ReplayWindow {
+  challenge: [u8; CHALLENGE_LEN],
+  last: Option<([u8; 8], u32)>,
+}
+
+impl ReplayWindow {
+  /// No session open yet; the first must carry `challenge`
+  pub const fn new(challenge: [u8; CHALLENGE_LEN]) -> Self {
+    Self { challenge, last: None }
+  }
+
+  /// Challenge a new session must start its salt with
+  pub fn challenge(&self) -> [u8; CHALLENGE_LEN] {
+    self.challenge
+  }
+
+  /// Accept `nonce` if it counts past the last one of the open session, or opens a new session
+  /// with the current challenge; `next_challenge` then replaces it, so no earlier session can be
+  /// reopened
+  pub fn accept(&mut self, nonce: &Nonce, next_challenge: [u8; CHALLENGE_LEN]) -> Result<(), SecureError> {
+    match self.last {
+      Some((salt, counter)) if salt == nonce.salt => {
+        if nonce.counter <= counter {
+          return Err(SecureError::Replay);
+        }
+      }
+      _ if nonce.salt[..CHALLENGE_LEN] == self.challenge => self.challenge = next_challenge,
+      _ => return Err(SecureError::Challenge),
+    }
+    self.last = Some((nonce.salt, nonce.counter));
+    Ok(())
+  }
+}
+
+/// Stored link key and boot epoch
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct KeyRecord {
+  pub key: Key,
+  pub epoch: u32,
+}
+
+impl KeyRecord {
+  pub fn encode(&self) -> [u8; KEY_RECORD_LEN] {
+    let mut out = [0u8; KEY_RECORD_LEN];
+    out[..2].copy_from_slice(&KEY_MAGIC.to_le_bytes());
+    out[2] = KEY_RECORD_VERSION;
+    out[4..4 + KEY_LEN].copy_from_slice(&self.key);
+    out[4 + KEY_LEN..KEY_RECORD_LEN - 2].copy_from_slice(&self.epoch.to_le_bytes());
+    let crc = fcs16_ppp(&out[..KEY_RECORD_LEN - 2]);
+    out[KEY_RECORD_LEN - 2..].copy_from_slice(&crc.to_le_bytes());
+    out
+  }
+
+  /// Decode a record from the start of `bytes` (trailing bytes are ignored)
+  pub fn decode(bytes: &[u8]) -> Option<Self> {
+    if bytes.len() < KEY_RECORD_LEN || u16::from_le_bytes([bytes[0], bytes[1]]) != KEY_MAGIC || bytes[2] != KEY_RECORD_VERSION {
+      return None;
+    }
+    let end = KEY_RECORD_LEN - 2;
+    if fcs16_ppp(&bytes[..end]) != u16::from_le_bytes([bytes[end], bytes[end + 1]]) {
+      return None;
+    }
+    let mut key = [0u8; KEY_LEN];
+    key.copy_from_slice(&bytes[4..4 + KEY_LEN]);
+    let e = &bytes[4 + KEY_LEN..end];
+    Some(Self {
+      key,
+      epoch: u32::from_le_bytes([e[0], e[1], e[2], e[3]]),
+    })
+  }
+}
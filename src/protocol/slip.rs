@@ -0,0 +1,88 @@
+//! SLIP framing/deframing (RFC 1055) for serial communication
+// An alternative to HDLC for links whose other end already speaks SLIP (esp-style flashers,
+// tunslip, slattach). SLIP has no checksum: a corrupted frame is only caught by the Comms length
+// check, so prefer HDLC with `hdlc_fcs` where the tooling allows.
+
+pub const SLIP_END: u8 = 0xC0;
+pub const SLIP_ESC: u8 = 0xDB;
+pub const SLIP_ESC_END: u8 = 0xDC;
+pub const SLIP_ESC_ESC: u8 = 0xDD;
+
+/// Frame a payload into a SLIP packet (leading END to flush line noise, escapes, trailing END)
+pub fn slip_frame<const M: usize>(payload: &[u8], out: &mut heapless::Vec<u8, M>) {
+  out.clear();
+  out.push(SLIP_END).ok();
+  for &b in payload {
+    match b {
+      SLIP_END => {
+        out.push(SLIP_ESC).ok();
+        out.push(SLIP_ESC_END).ok();
+      }
+      SLIP_ESC => {
+        out.push(SLIP_ESC).ok();
+        out.push(SLIP_ESC_ESC).ok();
+      }
+      _ => {
+        out.push(b).ok();
+      }
+    }
+  }
+  out.push(SLIP_END).ok();
+}
+
+/// SLIP deframe error type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlipError {
+  /// No complete packet in the buffer yet (partial packet kept for the next call)
+  Incomplete,
+}
+
+/// Deframe SLIP data (returns Ok(()) with the first complete packet in `out`)
+///
+/// - Empty packets (back-to-back ENDs, including the leading END of each frame) are skipped.
+/// - Unlike HDLC there is no opening flag, so bytes before the first END are part of a packet.
+/// - An escape followed by anything but ESC_END/ESC_ESC keeps that byte, as RFC 1055 suggests.
+pub fn slip_deframe<const N: usize, const M: usize>(buf: &mut heapless::Vec<u8, N>, out: &mut heapless::Vec<u8, M>) -> Result<(), SlipError> {
+  let mut escape = false;
+  let mut start = 0; // first byte of the current packet
+  out.clear();
+  for i in 0..buf.len() {
+    let b = buf[i];
+    if b == SLIP_END {
+      if !out.is_empty() {
+        discard_front(buf, i + 1);
+        return Ok(());
+      }
+      start = i + 1;
+      escape = false;
+    } else if escape {
+      out
+        .push(match b {
+          SLIP_ESC_END => SLIP_END,
+          SLIP_ESC_ESC => SLIP_ESC,
+          other => other,
+        })
+        .ok();
+      escape = false;
+    } else if b == SLIP_ESC {
+      escape = true;
+    } else {
+      out.push(b).ok();
+    }
+  }
+
+  // No complete packet: drop the skipped ENDs, keep the partial packet
+  discard_front(buf, start);
+  out.clear();
+  Err(SlipError::Incomplete)
+}
+
+/// Remove the first `n` bytes of `buf`, shifting the remainder to the front
+fn discard_front<const N: usize>(buf: &mut heapless::Vec<u8, N>, n: usize) {
+  let n = core::cmp::min(n, buf.len());
+  let remaining = buf.len() - n;
+  for j in 0..remaining {
+    buf[j] = buf[n + j];
+  }
+  buf.truncate(remaining);
+}
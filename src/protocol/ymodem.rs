@@ -0,0 +1,289 @@
+//! XMODEM-CRC / YMODEM batch receiver
+// Lets a plain terminal program (`sb`/`sx` from lrzsz, Tera Term, ExtraPuTTY, minicom) push files
+// over the serial line. The sender transmits blocks of 128 (SOH) or 1024 (STX) data bytes:
+//   [SOH|STX] [seq] [255 - seq] [data] [CRC-16/XMODEM, big-endian]
+// acknowledged one by one with ACK (NAK asks for it again). The receiver starts by sending 'C'
+// (CRC mode) until the first block arrives; checksum-mode senders are not supported.
+// - YMODEM: block 0 carries "name\0size ..." and is answered with ACK and another 'C'; data
+//   follows from block 1. The sender ends a file with EOT, which is NAKed once and ACKed the second
+//   time, followed by 'C' for the next file header. A header with an empty name ends the batch.
+// - XMODEM: the first block is block 1 (no name, no size); a single EOT ends the transfer.
+// Data is handed out trimmed to the size in the YMODEM header (XMODEM files keep the sender's
+// 0x1A padding). Two CAN bytes in a row abort the transfer.
+//
+// `YmodemReceiver::push` takes received bytes one at a time and returns what they completed;
+// after acting on it (e.g. storing the data) the caller sends `reply()` to the sender, or
+// `cancel()` if it cannot go on. `timeout()` is for a line that stayed quiet for
+// `YMODEM_TIMEOUT_MS`.
+
+use heapless::Vec;
+
+pub const YMODEM_SOH: u8 = 0x01;
+pub const YMODEM_STX: u8 = 0x02;
+pub const YMODEM_EOT: u8 = 0x04;
+pub const YMODEM_ACK: u8 = 0x06;
+pub const YMODEM_NAK: u8 = 0x15;
+pub const YMODEM_CAN: u8 = 0x18;
+/// Receiver's request for CRC mode
+pub const YMODEM_CRC: u8 = b'C';
+
+/// Largest block payload (STX)
+pub const YMODEM_BLOCK_MAX: usize = 1024;
+/// Longest file name taken from a YMODEM header
+pub const YMODEM_NAME_MAX: usize = 64;
+/// Quiet time after which the caller should call `timeout`
+pub const YMODEM_TIMEOUT_MS: u64 = 3_000;
+/// Timeouts and bad blocks in a row before the receiver gives up
+pub const YMODEM_MAX_ERRORS: u8 = 10;
+
+// Start byte, seq, !seq, data, CRC
+const FRAME_MAX: usize = 3 + YMODEM_BLOCK_MAX + 2;
+
+/// What the bytes fed to `push` completed
+#[derive(Debug, Eq, PartialEq)]
+pub enum YmodemEvent<'a> {
+  /// A YMODEM file starts (`size` if the sender gave one)
+  File { name: &'a str, size: Option<u32> },
+  /// Next piece of the current file (for XMODEM, of the only file, without a `File` event first)
+  Data(&'a [u8]),
+  /// The current file is complete
+  FileEnd,
+  /// YMODEM end of batch (an XMODEM transfer is over with its `FileEnd`: see `is_finished`)
+  Done,
+  /// The sender cancelled, or the receiver gave up (too many errors, lost blocks)
+  Aborted,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum State {
+  /// Waiting for a YMODEM header (or the first XMODEM block)
+  Header,
+  /// Waiting for the next data block or EOT
+  Data,
+  /// YMODEM: first EOT NAKed, waiting for the second
+  Eot,
+  /// In a block (the start byte decided its length)
+  Block {
+    len: usize,
+    header: bool,
+  },
+  Finished,
+}
+
+/// XMODEM-CRC / YMODEM receiver state machine
+pub struct YmodemReceiver {
+  state: State,
+  xmodem: bool,
+  frame: Vec<u8, FRAME_MAX>,
+  expected: u8,
+  size: Option<u32>,
+  received: u32,
+  errors: u8,
+  cancels: u8,
+  reply: Vec<u8, 2>,
+}
+
+impl Default for YmodemReceiver {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl YmodemReceiver {
+  /// Receiver waiting for the first file; send `reply()` ('C') to start the sender
+  pub fn new() -> Self {
+    let mut reply = Vec::new();
+    reply.push(YMODEM_CRC).ok();
+    Self {
+      state: State::Header,
+      xmodem: false,
+      frame: Vec::new(),
+      expected: 0,
+      size: None,
+      received: 0,
+      errors: 0,
+      cancels: 0,
+      reply,
+    }
+  }
+
+  /// Whether the transfer is over (done or aborted)
+  pub fn is_finished(&self) -> bool {
+    self.state == State::Finished
+  }
+
+  /// Bytes of the current file handed out so far
+  pub fn received(&self) -> u32 {
+    self.received
+  }
+
+  /// Answer due to the sender (empty if none); taken by the call
+  pub fn reply(&mut self) -> Vec<u8, 2> {
+    core::mem::take(&mut self.reply)
+  }
+
+  /// Abort the transfer (e.g. the data cannot be stored): send the returned bytes to the sender
+  pub fn cancel(&mut self) -> &'static [u8] {
+    self.state = State::Finished;
+    self.reply.clear();
+    &[YMODEM_CAN; 5]
+  }
+
+  /// The line stayed quiet for `YMODEM_TIMEOUT_MS`: drops a partial block and asks again ('C' or
+  /// NAK in `reply()`); `Aborted` once `YMODEM_MAX_ERRORS` is reached
+  pub fn timeout(&mut self) -> Option<YmodemEvent<'static>> {
+    if self.state == State::Finished {
+      return None;
+    }
+    if let State::Block { header, .. } = self.state {
+      self.state = if header { State::Header } else { State::Data };
+    }
+    self.error(if self.state == State::Header { YMODEM_CRC } else { YMODEM_NAK })
+  }
+
+  /// Feed one received byte
+  pub fn push(&mut self, byte: u8) -> Option<YmodemEvent<'_>> {
+    let (len, header) = match self.state {
+      State::Finished => return None,
+      State::Block { len, header } => (len, header),
+      waiting => return self.start(waiting, byte),
+    };
+    self.frame.push(byte).ok();
+    if self.frame.len() < len + 5 {
+      return None;
+    }
+    self.state = if header { State::Header } else { State::Data };
+    self.block(len)
+  }
+
+  // A byte between blocks
+  fn start(&mut self, waiting: State, byte: u8) -> Option<YmodemEvent<'_>> {
+    if byte == YMODEM_CAN {
+      self.cancels += 1;
+      if self.cancels >= 2 {
+        self.state = State::Finished;
+        return Some(YmodemEvent::Aborted);
+      }
+      return None;
+    }
+    self.cancels = 0;
+    let header = waiting == State::Header;
+    match byte {
+      YMODEM_SOH | YMODEM_STX => {
+        let len = if byte == YMODEM_SOH { 128 } else { YMODEM_BLOCK_MAX };
+        self.frame.clear();
+        self.frame.push(byte).ok();
+        self.state = State::Block { len, header };
+        None
+      }
+      YMODEM_EOT if waiting == State::Data && !self.xmodem => {
+        self.state = State::Eot;
+        self.reply_with(&[YMODEM_NAK]);
+        None
+      }
+      YMODEM_EOT if waiting == State::Eot => {
+        self.state = State::Header;
+        self.reply_with(&[YMODEM_ACK, YMODEM_CRC]);
+        Some(YmodemEvent::FileEnd)
+      }
+      YMODEM_EOT if waiting == State::Data => {
+        self.state = State::Finished;
+        self.reply_with(&[YMODEM_ACK]);
+        Some(YmodemEvent::FileEnd)
+      }
+      // Line noise between blocks
+      _ => None,
+    }
+  }
+
+  // A complete block of `len` data bytes in `frame`
+  fn block(&mut self, len: usize) -> Option<YmodemEvent<'_>> {
+    let seq = self.frame[1];
+    let crc = u16::from_be_bytes([self.frame[len + 3], self.frame[len + 4]]);
+    if seq != !self.frame[2] || crc16_xmodem(&self.frame[3..len + 3]) != crc {
+      return self.error(YMODEM_NAK);
+    }
+    self.errors = 0;
+    if self.state == State::Header {
+      match seq {
+        0 => return self.header(len),
+        // XMODEM: data right away
+        1 => {
+          self.xmodem = true;
+          self.expected = 1;
+          self.state = State::Data;
+        }
+        _ => return self.error(YMODEM_CRC),
+      }
+    }
+    if seq == self.expected.wrapping_sub(1) {
+      // Our ACK was lost and the sender repeated the block
+      self.reply_with(&[YMODEM_ACK]);
+      return None;
+    }
+    if seq != self.expected {
+      // Blocks went missing: the file cannot be completed
+      self.reply_with(&[YMODEM_CAN, YMODEM_CAN]);
+      self.state = State::Finished;
+      return Some(YmodemEvent::Aborted);
+    }
+    self.expected = self.expected.wrapping_add(1);
+    let take = match self.size {
+      Some(size) => (size.saturating_sub(self.received) as usize).min(len),
+      None => len,
+    };
+    self.received += take as u32;
+    self.reply_with(&[YMODEM_ACK]);
+    Some(YmodemEvent::Data(&self.frame[3..3 + take]))
+  }
+
+  // YMODEM block 0: "name\0size[ mtime mode ...]\0..."
+  fn header(&mut self, len: usize) -> Option<YmodemEvent<'_>> {
+    let info = &self.frame[3..3 + len];
+    if info[0] == 0 {
+      self.state = State::Finished;
+      self.reply_with(&[YMODEM_ACK]);
+      return Some(YmodemEvent::Done);
+    }
+    let name_len = info.iter().position(|&b| b == 0).unwrap_or(len);
+    let rest = info.get(name_len + 1..).unwrap_or(&[]);
+    let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+    self.size = core::str::from_utf8(&rest[..digits]).ok().and_then(|s| s.parse().ok());
+    self.received = 0;
+    self.expected = 1;
+    self.state = State::Data;
+    self.reply_with(&[YMODEM_ACK, YMODEM_CRC]);
+    // A name that is not UTF-8 is handed out empty
+    let name = core::str::from_utf8(&self.frame[3..3 + name_len.min(YMODEM_NAME_MAX)]).unwrap_or("");
+    Some(YmodemEvent::File { name, size: self.size })
+  }
+
+  // A bad block or a timeout: ask again with `ask`, or give up
+  fn error(&mut self, ask: u8) -> Option<YmodemEvent<'static>> {
+    self.errors += 1;
+    if self.errors >= YMODEM_MAX_ERRORS {
+      self.state = State::Finished;
+      self.reply_with(&[YMODEM_CAN, YMODEM_CAN]);
+      return Some(YmodemEvent::Aborted);
+    }
+    self.reply_with(&[ask]);
+    None
+  }
+
+  fn reply_with(&mut self, bytes: &[u8]) {
+    self.reply.clear();
+    self.reply.extend_from_slice(bytes).ok();
+  }
+}
+
+/// CRC-16/XMODEM: polynomial 0x1021, init 0, not reflected
+pub fn crc16_xmodem(data: &[u8]) -> u16 {
+  let mut crc: u16 = 0;
+  for &b in data {
+    crc ^= (b as u16) << 8;
+    for _ in 0..8 {
+      crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+    }
+  }
+  crc
+}
@@ -0,0 +1,255 @@
+//! Alarm manager: raise/clear with severity, latching and host acknowledgment
+// Process-control style alarm model:
+// - `raise(id, severity, latching)` activates an alarm, `clear(id)` deactivates it.
+// - A latching alarm stays annunciated after it clears until the host acknowledges it;
+//   a non-latching alarm disappears as soon as it clears.
+// - The host acknowledges with `Command::AlarmAck` (payload `[id]`, or empty for all alarms),
+//   answered with `Command::Ack` by `comm::handle_builtin`.
+// - Every change is reported to the host as `Command::Alarm` with payload
+//   `[id, severity, flags]` (flags: bit0 active, bit1 acknowledged, bit2 latching), queued
+//   here and sent by `comm::send_pending`.
+// - Latched alarms not yet acknowledged persist in the configuration flash region
+//   (`ConfigStore::save_alarms`, written by `config_task` whenever that set changes) and
+//   `config::load` restores them at boot, inactive, so a reset never hides one from the operator.
+//   Active conditions are not stored: their source raises them again after the reset, and a
+//   chattering input would otherwise write the flash on every change.
+//
+// `common::tasks::alarm_indicator` turns the most severe unacknowledged alarm into LED/buzzer output.
+
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use heapless::Vec;
+
+use crate::protocol::hdlc::fcs16_ppp;
+use crate::service::comm::{Command, Message};
+use crate::service::config;
+
+/// Maximum number of simultaneously annunciated alarms
+pub const ALARM_MAX: usize = 16;
+/// Bytes per alarm in a flash record
+pub const ALARM_RECORD_LEN: usize = 3;
+/// Longest encoded flash record
+pub const ALARM_RECORD_MAX: usize = RECORD_HEADER_LEN + ALARM_MAX * ALARM_RECORD_LEN + RECORD_CRC_LEN;
+
+const ALARM_NOTIFY_DEPTH: usize = 4;
+
+// Flash record: magic u16, version u8, count u8, alarms, PPP FCS-16 over everything before it
+const RECORD_MAGIC: u16 = 0xA1A7;
+const RECORD_VERSION: u8 = 1;
+const RECORD_HEADER_LEN: usize = 4;
+const RECORD_CRC_LEN: usize = 2;
+
+const FLAG_ACTIVE: u8 = 1 << 0;
+const FLAG_ACKED: u8 = 1 << 1;
+const FLAG_LATCHING: u8 = 1 << 2;
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, defmt::Format)]
+pub enum Severity {
+  Info = 0x00,
+  Warning = 0x01,
+  Critical = 0x02,
+}
+
+impl TryFrom<u8> for Severity {
+  type Error = ();
+  fn try_from(value: u8) -> Result<Self, Self::Error> {
+    match value {
+      0x00 => Ok(Severity::Info),
+      0x01 => Ok(Severity::Warning),
+      0x02 => Ok(Severity::Critical),
+      _ => Err(()),
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct Alarm {
+  pub id: u8,
+  pub severity: Severity,
+  pub latching: bool,
+  /// Condition currently present
+  pub active: bool,
+  /// Acknowledged by the host
+  pub acked: bool,
+}
+
+impl Alarm {
+  fn flags(&self) -> u8 {
+    (self.active as u8 * FLAG_ACTIVE) | (self.acked as u8 * FLAG_ACKED) | (self.latching as u8 * FLAG_LATCHING)
+  }
+
+  fn to_bytes(self) -> [u8; ALARM_RECORD_LEN] {
+    [self.id, self.severity as u8, self.flags()]
+  }
+
+  fn from_bytes(bytes: &[u8]) -> Option<Self> {
+    Some(Self {
+      id: bytes[0],
+      severity: Severity::try_from(bytes[1]).ok()?,
+      active: bytes[2] & FLAG_ACTIVE != 0,
+      acked: bytes[2] & FLAG_ACKED != 0,
+      latching: bytes[2] & FLAG_LATCHING != 0,
+    })
+  }
+
+  /// Still shown to the operator (active, or latched and not yet acknowledged)
+  fn annunciated(&self) -> bool {
+    self.active || (self.latching && !self.acked)
+  }
+
+  /// Severity kept in flash, if the alarm persists (latched and not yet acknowledged)
+  fn persisted(self) -> Option<Severity> {
+    (self.latching && !self.acked).then_some(self.severity)
+  }
+}
+
+pub type AlarmTable = Vec<Alarm, ALARM_MAX>;
+
+static ALARMS: Mutex<CriticalSectionRawMutex, RefCell<AlarmTable>> = Mutex::new(RefCell::new(Vec::new()));
+
+// Change notifications waiting for `comm::send_pending`
+static ALARM_NOTIFY: Channel<CriticalSectionRawMutex, Message, ALARM_NOTIFY_DEPTH> = Channel::new();
+
+fn notify(alarm: &Alarm) {
+  defmt::info!("Alarm: {}", alarm);
+  let _ = ALARM_NOTIFY.try_send(Message::new(Command::Alarm, &alarm.to_bytes()));
+}
+
+/// Apply `f` to alarm `id` (created from `new` if absent), notify on change and drop it once no
+/// longer annunciated; a change to what persists is saved
+fn update(id: u8, new: Option<Alarm>, f: impl FnOnce(&mut Alarm)) {
+  let changed = ALARMS.lock(|alarms| {
+    let mut alarms = alarms.borrow_mut();
+    let (index, inserted) = match alarms.iter().position(|a| a.id == id) {
+      Some(index) => (index, false),
+      None => {
+        if alarms.push(new?).is_err() {
+          defmt::warn!("Alarm table full, dropping alarm {}", id);
+          return None;
+        }
+        (alarms.len() - 1, true)
+      }
+    };
+    let before = (!inserted).then_some(alarms[index]);
+    f(&mut alarms[index]);
+    let after = alarms[index];
+    if !after.annunciated() {
+      alarms.swap_remove(index);
+    }
+    (before != Some(after)).then(|| (after, before.and_then(Alarm::persisted) != after.persisted()))
+  });
+  if let Some((alarm, save)) = changed {
+    notify(&alarm);
+    if save {
+      config::save_alarms();
+    }
+  }
+}
+
+/// Activate alarm `id` (re-raising an acknowledged alarm requires a new acknowledgment)
+pub fn raise(id: u8, severity: Severity, latching: bool) {
+  let new = Alarm {
+    id,
+    severity,
+    latching,
+    active: true,
+    acked: false,
+  };
+  update(id, Some(new), |a| {
+    if !a.active {
+      a.acked = false;
+    }
+    a.active = true;
+    a.severity = severity;
+    a.latching = latching;
+  });
+}
+
+/// Deactivate alarm `id` (latching alarms stay annunciated until acknowledged)
+pub fn clear(id: u8) {
+  update(id, None, |a| a.active = false);
+}
+
+/// Acknowledge alarm `id`, or every alarm when `id` is None
+pub fn ack(id: Option<u8>) {
+  match id {
+    Some(id) => update(id, None, |a| a.acked = true),
+    None => {
+      for alarm in alarms() {
+        update(alarm.id, None, |a| a.acked = true);
+      }
+    }
+  }
+}
+
+/// Copy of the annunciated alarms
+pub fn alarms() -> AlarmTable {
+  ALARMS.lock(|alarms| alarms.borrow().clone())
+}
+
+/// Highest severity among alarms not yet acknowledged
+pub fn highest_unacked() -> Option<Severity> {
+  ALARMS.lock(|alarms| alarms.borrow().iter().filter(|a| !a.acked).map(|a| a.severity).max())
+}
+
+/// Next change notification for the host (drained by `comm::send_pending`)
+pub fn next_notification() -> Option<Message> {
+  ALARM_NOTIFY.try_receive().ok()
+}
+
+/// The latched alarms not yet acknowledged (what persists across resets)
+pub fn latched() -> AlarmTable {
+  alarms().into_iter().filter(|alarm| alarm.persisted().is_some()).collect()
+}
+
+/// Install alarms loaded from flash (`config::load`) without writing them back; they come back
+/// inactive and are announced to the host again
+pub fn install(table: AlarmTable) {
+  for mut alarm in table {
+    alarm.active = false;
+    let added = ALARMS.lock(|alarms| {
+      let mut alarms = alarms.borrow_mut();
+      !alarms.iter().any(|a| a.id == alarm.id) && alarms.push(alarm).is_ok()
+    });
+    if added {
+      notify(&alarm);
+    }
+  }
+}
+
+/// Encode `alarms` (at most `ALARM_MAX`) as a flash record
+pub fn encode_record(alarms: &[Alarm]) -> Vec<u8, ALARM_RECORD_MAX> {
+  let alarms = &alarms[..alarms.len().min(ALARM_MAX)];
+  let mut out = Vec::new();
+  out.extend_from_slice(&RECORD_MAGIC.to_le_bytes()).ok();
+  out.extend_from_slice(&[RECORD_VERSION, alarms.len() as u8]).ok();
+  for alarm in alarms {
+    out.extend_from_slice(&alarm.to_bytes()).ok();
+  }
+  let crc = fcs16_ppp(&out);
+  out.extend_from_slice(&crc.to_le_bytes()).ok();
+  out
+}
+
+/// Decode a flash record from the start of `bytes` (trailing bytes are ignored); only alarms
+/// that persist are returned
+pub fn decode_record(bytes: &[u8]) -> Option<AlarmTable> {
+  if bytes.len() < RECORD_HEADER_LEN + RECORD_CRC_LEN || u16::from_le_bytes([bytes[0], bytes[1]]) != RECORD_MAGIC || bytes[2] != RECORD_VERSION {
+    return None;
+  }
+  let count = bytes[3] as usize;
+  let end = RECORD_HEADER_LEN + count * ALARM_RECORD_LEN;
+  if count > ALARM_MAX || bytes.len() < end + RECORD_CRC_LEN || fcs16_ppp(&bytes[..end]) != u16::from_le_bytes([bytes[end], bytes[end + 1]]) {
+    return None;
+  }
+  Some(
+    bytes[RECORD_HEADER_LEN..end]
+      .chunks_exact(ALARM_RECORD_LEN)
+      .filter_map(Alarm::from_bytes)
+      .filter(|alarm| alarm.persisted().is_some())
+      .collect(),
+  )
+}
@@ -1,171 +1,965 @@
-use cortex_m::peripheral::SCB;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, TimeoutError, Timer, with_timeout};
 use heapless::Vec;
 
-use crate::hardware::serial;
-use crate::protocol::hdlc;
-use core::sync::atomic::{AtomicU8, Ordering};
+use crate::board::BoardConfig;
+use crate::common::system::{self, RebootReason};
+use crate::common::{buildinfo, cooperative};
+use crate::diagnostics::eventlog::{self, Event};
+use crate::hardware::{option_bytes, serial};
+#[cfg(feature = "comm_compress")]
+use crate::protocol::heatshrink;
+pub use crate::protocol::message::{
+  COMMS_COMPRESSED, COMMS_FRAMED_MAX, COMMS_HEADER_LEN, COMMS_MAX_PAYLOAD, COMMS_PROTOCOL_VERSION, Command, CommsFrameBuf, CommsPayload, Message, MessageRef, NakCode,
+};
+use crate::protocol::message::{RecentIds, ReplyCache};
+#[cfg(feature = "comms_routing")]
+use crate::protocol::routing::{BROADCAST, DropReason, LinkId, Route, RoutingTable};
+#[cfg(feature = "comm_crypto")]
+use crate::protocol::secure::{SECURE_MAX_PLAINTEXT, SecureError};
+use crate::protocol::{hdlc, slip};
+#[cfg(feature = "comm_crypto")]
+use crate::service::crypto;
+use crate::service::{alarm, config, duty_cycle, journal, pubsub, rules, scheduler, sensors, snapshot, telemetry};
+use core::cell::Cell;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering};
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
 // FCS error counter
-static FCS_ERROR_COUNT: AtomicU8 = AtomicU8::new(0);
+static FCS_ERROR_COUNT: AtomicU32 = AtomicU32::new(0);
 
-/// Get the current FCS error count
-pub fn fcs_error_count() -> u8 {
+/// Number of received HDLC frames that failed the FCS check since boot (wrapping)
+pub fn fcs_error_count() -> u32 {
   FCS_ERROR_COUNT.load(Ordering::Relaxed)
 }
 
-// Define constants for queue depth and byte vector sizes
-const COMMS_BYTE_VEC_SIZE: usize = 512;
-const COMMS_QUEUE_DEPTH: usize = 3;
-pub const COMMS_MAX_PAYLOAD: usize = 256; // half to account for escaping
+// Link statistics counters
+static RX_FRAMES: AtomicU32 = AtomicU32::new(0);
+static TX_FRAMES: AtomicU32 = AtomicU32::new(0);
+static PARSE_ERRORS: AtomicU32 = AtomicU32::new(0);
+static QUEUE_DROPS: AtomicU32 = AtomicU32::new(0);
+static RX_BYTES: AtomicU32 = AtomicU32::new(0);
+static TX_BYTES: AtomicU32 = AtomicU32::new(0);
+#[cfg(feature = "comm_crypto")]
+static AUTH_FAILURES: AtomicU32 = AtomicU32::new(0);
+
+// Retransmitted copies dropped by the receive path
+static DUPLICATES: AtomicU32 = AtomicU32::new(0);
+
+/// Number of received messages dropped as retransmitted copies (same command and id within
+/// `COMMS_DUPLICATE_WINDOW_MS`)
+pub fn duplicate_count() -> u32 {
+  DUPLICATES.load(Ordering::Relaxed)
+}
+
+/// Number of received messages dropped for failing authentication (`comm_crypto`)
+#[cfg(feature = "comm_crypto")]
+pub fn auth_failure_count() -> u32 {
+  AUTH_FAILURES.load(Ordering::Relaxed)
+}
+
+/// Snapshot of the link statistics counters
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, defmt::Format)]
+pub struct Stats {
+  pub rx_frames: u32,
+  pub tx_frames: u32,
+  pub fcs_errors: u32,
+  pub parse_errors: u32,
+  pub queue_drops: u32,
+  pub rx_bytes: u32,
+  pub tx_bytes: u32,
+}
+
+impl Stats {
+  /// Encoded size of a `Stats` reply payload
+  pub const LEN: usize = 7 * 4;
+
+  /// Encode as the `Command::Stats` reply payload (u32 fields, little-endian, declaration order)
+  pub fn to_bytes(&self) -> [u8; Self::LEN] {
+    let mut out = [0u8; Self::LEN];
+    let fields = [
+      self.rx_frames,
+      self.tx_frames,
+      self.fcs_errors,
+      self.parse_errors,
+      self.queue_drops,
+      self.rx_bytes,
+      self.tx_bytes,
+    ];
+    for (chunk, value) in out.chunks_exact_mut(4).zip(fields) {
+      chunk.copy_from_slice(&value.to_le_bytes());
+    }
+    out
+  }
+}
+
+/// Get a snapshot of the current link statistics
+pub fn stats() -> Stats {
+  Stats {
+    rx_frames: RX_FRAMES.load(Ordering::Relaxed),
+    tx_frames: TX_FRAMES.load(Ordering::Relaxed),
+    fcs_errors: fcs_error_count(),
+    parse_errors: PARSE_ERRORS.load(Ordering::Relaxed),
+    queue_drops: QUEUE_DROPS.load(Ordering::Relaxed),
+    rx_bytes: RX_BYTES.load(Ordering::Relaxed),
+    tx_bytes: TX_BYTES.load(Ordering::Relaxed),
+  }
+}
+
+/// Largest payload an application can send (sealing takes room with `comm_crypto`)
+#[cfg(feature = "comm_crypto")]
+pub const COMMS_MAX_PLAINTEXT: usize = SECURE_MAX_PLAINTEXT;
+#[cfg(not(feature = "comm_crypto"))]
+pub const COMMS_MAX_PLAINTEXT: usize = COMMS_MAX_PAYLOAD;
+
+/// Byte framing on the wire: HDLC (the default; checked with `hdlc_fcs`) or SLIP (RFC 1055, for
+/// existing SLIP tooling; no checksum)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum Framing {
+  Hdlc,
+  Slip,
+}
+
+impl Framing {
+  /// Frame an encoded message into `out`
+  pub fn frame<const M: usize>(self, payload: &[u8], out: &mut Vec<u8, M>) {
+    match self {
+      Framing::Hdlc => hdlc::hdlc_frame(payload, out),
+      Framing::Slip => slip::slip_frame(payload, out),
+    }
+  }
+}
+
+// Framing of the serial link (`Framing` as u8)
+static COMMS_FRAMING: AtomicU8 = AtomicU8::new(Framing::Hdlc as u8);
+
+/// Select the framing of the serial link (both directions); other transports pick their own
+/// and call `Framing::frame` / `dispatch` themselves
+pub fn set_framing(framing: Framing) {
+  COMMS_FRAMING.store(framing as u8, Ordering::Relaxed);
+}
+
+/// Framing of the serial link
+pub fn framing() -> Framing {
+  if COMMS_FRAMING.load(Ordering::Relaxed) == Framing::Slip as u8 {
+    Framing::Slip
+  } else {
+    Framing::Hdlc
+  }
+}
+
+/// What the receive path does after an HDLC frame fails the FCS check (it is always counted and
+/// NAKed first)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum ErrorPolicy {
+  /// Keep deframing whatever follows the bad frame
+  Ignore,
+  /// Drop the rest of the receive buffer and wait for the next flag (default)
+  Resync,
+  /// Resync, and reset the MCU once `errors` FCS errors arrive within `window_ms` (a link that
+  /// stays corrupt, e.g. a UART left in a bad state)
+  ResetAfter { errors: u32, window_ms: u32 },
+}
+
+static ERROR_POLICY: BlockingMutex<CriticalSectionRawMutex, Cell<ErrorPolicy>> = BlockingMutex::new(Cell::new(ErrorPolicy::Resync));
+// `ResetAfter` bookkeeping: start of the current window (uptime ms) and FCS errors in it
+static ERROR_WINDOW_START_MS: AtomicU32 = AtomicU32::new(0);
+static ERROR_WINDOW_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Select how the receive path reacts to FCS errors
+pub fn set_error_policy(policy: ErrorPolicy) {
+  ERROR_POLICY.lock(|p| p.set(policy));
+  ERROR_WINDOW_COUNT.store(0, Ordering::Relaxed);
+}
+
+/// Current FCS error policy
+pub fn error_policy() -> ErrorPolicy {
+  ERROR_POLICY.lock(|p| p.get())
+}
+
+// Apply the error policy to one FCS error; true if the receive buffer should be dropped
+fn on_fcs_error() -> bool {
+  match error_policy() {
+    ErrorPolicy::Ignore => false,
+    ErrorPolicy::Resync => true,
+    ErrorPolicy::ResetAfter { errors, window_ms } => {
+      let now = embassy_time::Instant::now().as_millis() as u32;
+      let count = if now.wrapping_sub(ERROR_WINDOW_START_MS.load(Ordering::Relaxed)) > window_ms || ERROR_WINDOW_COUNT.load(Ordering::Relaxed) == 0 {
+        ERROR_WINDOW_START_MS.store(now, Ordering::Relaxed);
+        ERROR_WINDOW_COUNT.store(1, Ordering::Relaxed);
+        1
+      } else {
+        ERROR_WINDOW_COUNT.fetch_add(1, Ordering::Relaxed) + 1
+      };
+      if count >= errors {
+        defmt::error!("{} FCS errors within {} ms, resetting", count, window_ms);
+        eventlog::record(eventlog::EVENT_COMM_RESET, count);
+        system::reset(RebootReason::CommErrors);
+      }
+      true
+    }
+  }
+}
+
+// Buffers hold one fully escaped frame; queue depths come from the board
+const COMMS_BYTE_VEC_SIZE: usize = COMMS_FRAMED_MAX;
+const COMMS_QUEUE_DEPTH: usize = BoardConfig::COMMS_QUEUE_DEPTH;
 
 // Byte vector aliases used throughout this module
-// Allow room for larger inbound/outbound frames (escaping can ~double size)
 pub type ByteVec = Vec<u8, COMMS_BYTE_VEC_SIZE>;
 pub type FramedBuf = Vec<u8, COMMS_BYTE_VEC_SIZE>;
-pub type CommsPayload = Vec<u8, COMMS_MAX_PAYLOAD>;
-pub type CommsFrameBuf = Vec<u8, { COMMS_HEADER_LEN + COMMS_MAX_PAYLOAD }>; // COMMS_HEADER_LEN=9 now
 
-/// Command identifiers for Comms messages.
-#[repr(u16)]
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub enum Command {
-  Ack = 0x01,
-  Nak = 0x02,
-  Ping = 0x03,
-  Raw = 0x04,
+// Queue of parsed Comms messages
+static COMMS_MSG_QUEUE: Channel<CriticalSectionRawMutex, Message, COMMS_QUEUE_DEPTH> = Channel::new();
+
+// Queue of automatic replies (NAKs, replies repeated for duplicates) produced by the receive path, sent via `send_pending`
+static COMMS_NAK_QUEUE: Channel<CriticalSectionRawMutex, Message, COMMS_QUEUE_DEPTH> = Channel::new();
+
+/// Receive-path callback: sees each message for this node as a borrowed view, straight from the
+/// frame buffer; returns true if it consumed the message (nothing is copied or queued)
+pub type MessageHandler = fn(&MessageRef<'_>) -> bool;
+
+/// Received messages remembered for duplicate suppression
+pub const COMMS_RECENT_IDS: usize = 8;
+/// A message repeating the command, id and fragment of one received this recently is a
+/// retransmitted copy and is dropped
+pub const COMMS_DUPLICATE_WINDOW_MS: u32 = 2_000;
+
+/// Replies kept to answer a retransmitted copy whose original reply was lost
+pub const COMMS_REPLY_CACHE: usize = 2;
+
+static RECENT_IDS: BlockingMutex<CriticalSectionRawMutex, RefCell<RecentIds<COMMS_RECENT_IDS>>> = BlockingMutex::new(RefCell::new(RecentIds::new(COMMS_DUPLICATE_WINDOW_MS)));
+static REPLIES: BlockingMutex<CriticalSectionRawMutex, RefCell<ReplyCache<COMMS_REPLY_CACHE>>> = BlockingMutex::new(RefCell::new(ReplyCache::new(COMMS_DUPLICATE_WINDOW_MS)));
+// Next id `next_message_id` hands out
+static NEXT_ID: AtomicU8 = AtomicU8::new(1);
+
+/// Requests that can await their response at the same time (`request`)
+pub const COMMS_PENDING_REQUESTS: usize = 4;
+
+// Id each `request` slot waits for (0: free) and where its response is delivered
+static PENDING_IDS: BlockingMutex<CriticalSectionRawMutex, Cell<[u8; COMMS_PENDING_REQUESTS]>> = BlockingMutex::new(Cell::new([0; COMMS_PENDING_REQUESTS]));
+static RESPONSES: [Signal<CriticalSectionRawMutex, Message>; COMMS_PENDING_REQUESTS] = [const { Signal::new() }; COMMS_PENDING_REQUESTS];
+
+static COMMS_HANDLER: BlockingMutex<CriticalSectionRawMutex, Cell<Option<MessageHandler>>> = BlockingMutex::new(Cell::new(None));
+
+/// Depth of the normal-priority outgoing queue behind `send` / `sender()` (`BoardConfig::COMMS_TX_QUEUE_DEPTH`)
+pub const COMMS_TX_QUEUE_DEPTH: usize = BoardConfig::COMMS_TX_QUEUE_DEPTH;
+/// Depth of the high-priority outgoing queue (control traffic is small and bursty)
+pub const COMMS_TX_HIGH_QUEUE_DEPTH: usize = BoardConfig::COMMS_QUEUE_DEPTH;
+/// Payloads at least this long are sent compressed when that makes them shorter (`comm_compress`)
+#[cfg(feature = "comm_compress")]
+pub const COMMS_COMPRESS_MIN: usize = 64;
+/// Received bytes `serial_hdlc_consumer_task` deframes and handles between two yields
+pub const COMMS_RX_SLICE_LEN: usize = 64;
+/// Longest `tx_task` waits before flushing NAKs and notifications
+pub const COMMS_TX_POLL_MS: u64 = 10;
+
+/// Outgoing queue tier: `High` messages are written before any queued `Normal` message
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum Priority {
+  /// Acks, Naks and control commands
+  High,
+  /// Data: `Raw` payloads, `Publish` and fragmented transfers
+  Normal,
 }
 
-impl From<Command> for u16 {
-  fn from(c: Command) -> Self {
-    c as u16
+impl Priority {
+  /// Tier `send` picks for `msg` when none is given
+  pub fn of(msg: &Message) -> Self {
+    match Command::try_from(msg.command) {
+      Ok(Command::Ack | Command::Nak) => Priority::High,
+      Ok(Command::Raw | Command::Publish) | Err(_) => Priority::Normal,
+      Ok(_) if msg.fragments > 1 => Priority::Normal,
+      Ok(_) => Priority::High,
+    }
   }
 }
 
-impl core::convert::TryFrom<u16> for Command {
-  type Error = ();
-  fn try_from(value: u16) -> Result<Self, Self::Error> {
-    match value {
-      0x01 => Ok(Command::Ack),
-      0x02 => Ok(Command::Nak),
-      0x03 => Ok(Command::Ping),
-      0x04 => Ok(Command::Raw),
-      _ => Err(()),
+// Messages queued by any task, written by the TX owner (`send_pending` or `tx_task`): the high
+// tier first, FIFO within each tier
+static COMMS_TX_HIGH_QUEUE: Channel<CriticalSectionRawMutex, Message, COMMS_TX_HIGH_QUEUE_DEPTH> = Channel::new();
+static COMMS_TX_QUEUE: Channel<CriticalSectionRawMutex, Message, COMMS_TX_QUEUE_DEPTH> = Channel::new();
+// Wakes `tx_task` when either tier gets a message
+static COMMS_TX_WAKE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Whether every queued outgoing message has been written, those held in the duty-cycle outbox
+/// included (`system::reboot` waits for this)
+pub fn tx_idle() -> bool {
+  COMMS_TX_HIGH_QUEUE.is_empty() && COMMS_TX_QUEUE.is_empty() && COMMS_NAK_QUEUE.is_empty() && duty_cycle::pending() == 0
+}
+
+/// Handle onto the shared outgoing queues (for tasks that keep a sender around)
+#[derive(Copy, Clone)]
+pub struct CommsSender {
+  _private: (),
+}
+
+impl CommsSender {
+  /// See `comm::send`
+  pub async fn send(&self, msg: Message) {
+    send(msg).await
+  }
+
+  /// See `comm::send_with`
+  pub async fn send_with(&self, priority: Priority, msg: Message) {
+    send_with(priority, msg).await
+  }
+
+  /// See `comm::try_send`
+  pub fn try_send(&self, msg: Message) -> Result<(), Message> {
+    try_send(msg)
+  }
+}
+
+// Node address used until `routing()` changes it
+#[cfg(feature = "comms_routing")]
+pub const COMMS_DEFAULT_ADDRESS: u8 = 0x01;
+#[cfg(feature = "comms_routing")]
+const COMMS_ROUTE_CAPACITY: usize = 16;
+
+/// Routing table type used by the comm service
+#[cfg(feature = "comms_routing")]
+pub type CommsRoutingTable = RoutingTable<COMMS_ROUTE_CAPACITY>;
+
+// Static routing table and the queue of messages waiting to be forwarded to other links
+#[cfg(feature = "comms_routing")]
+static COMMS_ROUTES: BlockingMutex<CriticalSectionRawMutex, RefCell<CommsRoutingTable>> = BlockingMutex::new(RefCell::new(RoutingTable::new(COMMS_DEFAULT_ADDRESS)));
+#[cfg(feature = "comms_routing")]
+static COMMS_FWD_QUEUE: Channel<CriticalSectionRawMutex, (LinkId, Message), COMMS_QUEUE_DEPTH> = Channel::new();
+
+/// Access the routing table (set node address, add routes)
+#[cfg(feature = "comms_routing")]
+pub fn routing<R>(f: impl FnOnce(&mut CommsRoutingTable) -> R) -> R {
+  COMMS_ROUTES.lock(|table| f(&mut table.borrow_mut()))
+}
+
+/// This node's address
+#[cfg(feature = "comms_routing")]
+pub fn node_address() -> u8 {
+  routing(|table| table.address())
+}
+
+/// Next message to forward and the link it must be written to (non-blocking).
+#[cfg(feature = "comms_routing")]
+pub fn read_forward() -> Option<(LinkId, Message)> {
+  COMMS_FWD_QUEUE.try_receive().ok()
+}
+
+/// Id for a message this node originates (wraps, skipping 0); `send` assigns one to messages
+/// with id 0, so call this only to know the id before sending (e.g. to match the reply)
+pub fn next_message_id() -> u8 {
+  loop {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    if id != 0 {
+      return id;
     }
   }
 }
 
-// Comms message format (little-endian):
-// - command:      u16
-// - id:           u8
-// - fragments:    u16 (total fragments)
-// - fragment:     u16 (0-based index)
-// - length:       u16  (payload length in bytes)
-// - payload:      [u8; length]
+fn assign_id(msg: &mut Message) {
+  if msg.id == 0 {
+    msg.id = next_message_id();
+  }
+}
 
-pub const COMMS_HEADER_LEN: usize = 9;
+/// Reply to `msg`: same id, from this node back to the sender with `comms_routing`
+pub fn reply<C: Into<u16>>(msg: &MessageRef<'_>, command: C, payload: &[u8]) -> Message {
+  let reply = msg.reply(command, payload);
+  #[cfg(feature = "comms_routing")]
+  let reply = Message { src: node_address(), ..reply };
+  reply
+}
 
-#[derive(Clone, Debug)]
-pub struct Message {
-  pub command: u16,
-  pub id: u8,         // todo: future use
-  pub fragments: u16, // todo: future use
-  pub fragment: u16,  // todo: future use
-  pub length: u16,
-  pub payload: CommsPayload,
+/// Sender for the shared outgoing queues; any task can use it without owning the UART
+pub fn sender() -> CommsSender {
+  CommsSender { _private: () }
 }
 
-impl Default for Message {
-  fn default() -> Self {
-    Self {
-      command: 0,
-      id: 0,
-      fragments: 1,
-      fragment: 0,
-      length: 0,
-      payload: Vec::new(),
+/// Queue `msg` for transmission in its default tier (`Priority::of`), waiting while that tier is full
+/// (an id of 0 is replaced by `next_message_id()`)
+pub async fn send(msg: Message) {
+  send_with(Priority::of(&msg), msg).await
+}
+
+/// Queue `msg` for transmission in the given tier, waiting while it is full
+pub async fn send_with(priority: Priority, mut msg: Message) {
+  assign_id(&mut msg);
+  match priority {
+    Priority::High => COMMS_TX_HIGH_QUEUE.send(msg).await,
+    Priority::Normal => COMMS_TX_QUEUE.send(msg).await,
+  }
+  COMMS_TX_WAKE.signal(());
+}
+
+/// Queue `msg` for transmission in its default tier without waiting; gives it back if the tier is full
+pub fn try_send(mut msg: Message) -> Result<(), Message> {
+  assign_id(&mut msg);
+  let result = match Priority::of(&msg) {
+    Priority::High => COMMS_TX_HIGH_QUEUE.try_send(msg),
+    Priority::Normal => COMMS_TX_QUEUE.try_send(msg),
+  };
+  match result {
+    Ok(()) => {
+      COMMS_TX_WAKE.signal(());
+      Ok(())
+    }
+    Err(embassy_sync::channel::TrySendError::Full(msg)) => {
+      QUEUE_DROPS.fetch_add(1, Ordering::Relaxed);
+      Err(msg)
     }
   }
 }
 
-impl Message {
-  /// Convenience constructor with defaults (id=0, fragments=1, fragment=1).
-  pub fn new<C: Into<u16>>(command: C, payload: &[u8]) -> Self {
-    let mut buf: Vec<u8, COMMS_MAX_PAYLOAD> = Vec::new();
-    let take = core::cmp::min(payload.len(), COMMS_MAX_PAYLOAD);
-    let _ = buf.extend_from_slice(&payload[..take]);
-    Self {
-      command: command.into(),
-      id: 0,
-      fragments: 1,
-      fragment: 1,
-      length: take as u16,
-      payload: buf,
+/// Send `msg` and wait for the response carrying its id (any command, `Nak` included), e.g.
+/// `let config = comm::request(Message::new(Command::GetConfig, &[]), Duration::from_millis(500)).await?;`
+///
+/// An id of 0 is replaced by `next_message_id()`. The response is taken off the receive path
+/// before the handler and `read()` see it (so is a peer's own message that happens to reuse the
+/// id while the request is open). Waiting for a free slot (`COMMS_PENDING_REQUESTS`) and
+/// for room in the outgoing queue count against `timeout`.
+pub async fn request(mut msg: Message, timeout: Duration) -> Result<Message, TimeoutError> {
+  assign_id(&mut msg);
+  let id = msg.id;
+  with_timeout(timeout, async {
+    let pending = PendingRequest::claim(id).await;
+    send(msg).await;
+    RESPONSES[pending.slot].wait().await
+  })
+  .await
+}
+
+// A claimed `request` slot, freed when the request completes or is dropped (timeout)
+struct PendingRequest {
+  slot: usize,
+}
+
+impl PendingRequest {
+  async fn claim(id: u8) -> Self {
+    loop {
+      let slot = PENDING_IDS.lock(|ids| {
+        let mut all = ids.get();
+        let slot = all.iter().position(|&pending| pending == 0)?;
+        all[slot] = id;
+        ids.set(all);
+        Some(slot)
+      });
+      if let Some(slot) = slot {
+        RESPONSES[slot].reset();
+        return Self { slot };
+      }
+      Timer::after_millis(COMMS_TX_POLL_MS).await;
     }
   }
 }
 
-// Queue of parsed Comms messages
-static COMMS_MSG_QUEUE: Channel<CriticalSectionRawMutex, Message, COMMS_QUEUE_DEPTH> = Channel::new();
+impl Drop for PendingRequest {
+  fn drop(&mut self) {
+    PENDING_IDS.lock(|ids| {
+      let mut all = ids.get();
+      all[self.slot] = 0;
+      ids.set(all);
+    });
+  }
+}
 
-/// Encode a Message and send over HDLC
+/// Async task owning TX for applications whose other tasks only send: writes queued messages as
+/// they arrive, and NAKs/notifications (`send_pending`) at least every `COMMS_TX_POLL_MS`
+#[embassy_executor::task]
+pub async fn tx_task(mut tx: embassy_stm32::usart::UartTx<'static, embassy_stm32::mode::Async>) {
+  loop {
+    let _ = with_timeout(Duration::from_millis(COMMS_TX_POLL_MS), COMMS_TX_WAKE.wait()).await;
+    send_pending(&mut tx);
+  }
+}
+
+/// Encode a Message and send it framed (compressed first with `comm_compress` when that pays off,
+/// then sealed with `comm_crypto` once a key is installed)
 pub fn write<W: embedded_io::Write>(serial: &mut W, msg: &Message) {
-  // Build unframed message (header + payload)
-  let mut buf: CommsFrameBuf = Vec::new();
-  let len_usize = core::cmp::min(msg.payload.len(), COMMS_MAX_PAYLOAD);
-  let len: u16 = len_usize as u16; // Use actual payload length, not msg.length field
+  #[cfg(feature = "comm_compress")]
+  let compressed = compress(msg);
+  #[cfg(feature = "comm_compress")]
+  let msg = compressed.as_ref().unwrap_or(msg);
+  #[cfg(feature = "comm_crypto")]
+  let msg = &{
+    let mut sealed = msg.clone();
+    if crypto::seal(&mut sealed).is_err() {
+      defmt::error!("Comm crypto: cannot seal message id {} ({} bytes), dropped", msg.id, msg.payload.len());
+      return;
+    }
+    sealed
+  };
+  forward(serial, msg);
+}
 
-  buf.extend_from_slice(&msg.command.to_le_bytes()).ok();
-  buf.push(msg.id).ok();
-  buf.extend_from_slice(&msg.fragments.to_le_bytes()).ok();
-  buf.extend_from_slice(&msg.fragment.to_le_bytes()).ok();
-  buf.extend_from_slice(&len.to_le_bytes()).ok();
+/// `write` a reply built with `reply` and keep it to answer retransmitted copies of the request
+pub fn write_reply<W: embedded_io::Write>(serial: &mut W, reply: &Message) {
+  remember_reply(reply);
+  write(serial, reply);
+}
 
-  buf.extend_from_slice(&msg.payload[..len_usize]).ok();
+/// Encode a Message and send it framed as it is (messages from `read_forward`, already sealed by their sender)
+pub fn forward<W: embedded_io::Write>(serial: &mut W, msg: &Message) {
+  // Build unframed message (header + payload)
+  let mut buf: CommsFrameBuf = Vec::new();
+  msg.encode(&mut buf);
 
-  // HDLC-frame and write
+  // Frame (HDLC or SLIP, see `set_framing`) and write
   let mut framed: FramedBuf = Vec::new();
-  hdlc::hdlc_frame(&buf, &mut framed);
+  framing().frame(&buf, &mut framed);
   serial::write(serial, &framed);
+  TX_FRAMES.fetch_add(1, Ordering::Relaxed);
+  TX_BYTES.fetch_add(framed.len() as u32, Ordering::Relaxed);
+}
+
+/// Handle built-in commands (`Stats`, `AlarmAck`, `Rules`, `ConfigExport`, `ConfigImport`, `Telemetry`, `Sensors`, `GetConfig`,
+/// `SetConfig` and the `Ack` committing it, `Ident`, `SetKey`, `Subscribe`, `Unsubscribe`, `Publish`, `Protection`, `Bootloader`, `EventLog`, `Schedule`); returns
+/// true if the message was consumed
+pub fn handle_builtin<W: embedded_io::Write>(serial: &mut W, msg: &Message) -> bool {
+  match Command::try_from(msg.command) {
+    Ok(Command::Stats) => {
+      write_reply(serial, &reply_to(msg, Command::Stats, &stats().to_bytes()));
+      true
+    }
+    Ok(Command::AlarmAck) => {
+      alarm::ack(msg.payload.first().copied());
+      write_reply(serial, &reply_to(msg, Command::Ack, &[]));
+      true
+    }
+    Ok(Command::Rules) if msg.payload.is_empty() => {
+      write_reply(serial, &reply_to(msg, Command::Rules, &rules::snapshot()));
+      true
+    }
+    Ok(Command::Rules) => {
+      let reply = match rules::restore(&msg.payload) {
+        Ok(()) => reply_to(msg, Command::Ack, &[]),
+        Err(_) => reply_to(msg, Command::Nak, &[NakCode::BadLength.into(), msg.id]),
+      };
+      write_reply(serial, &reply);
+      true
+    }
+    Ok(Command::Schedule) if msg.payload.is_empty() => {
+      write_reply(serial, &reply_to(msg, Command::Schedule, &scheduler::snapshot()));
+      true
+    }
+    Ok(Command::Schedule) => {
+      let reply = match scheduler::restore(&msg.payload) {
+        Ok(()) => reply_to(msg, Command::Ack, &[]),
+        Err(_) => reply_to(msg, Command::Nak, &[NakCode::BadLength.into(), msg.id]),
+      };
+      write_reply(serial, &reply);
+      true
+    }
+    Ok(Command::ConfigExport) => {
+      let blob = snapshot::export();
+      let fragments = blob.len().div_ceil(COMMS_MAX_PLAINTEXT) as u16;
+      for (index, chunk) in blob.chunks(COMMS_MAX_PLAINTEXT).enumerate() {
+        let mut reply = reply_to(msg, Command::ConfigExport, chunk);
+        reply.fragments = fragments;
+        reply.fragment = index as u16;
+        write_reply(serial, &reply);
+      }
+      true
+    }
+    Ok(Command::ConfigImport) => {
+      // A single unfragmented message carries the whole blob
+      let (fragment, fragments) = if msg.fragments <= 1 { (0, 1) } else { (msg.fragment, msg.fragments) };
+      match snapshot::import_fragment(fragment, fragments, &msg.payload) {
+        Some(Ok(())) => write_reply(serial, &reply_to(msg, Command::Ack, &[])),
+        Some(Err(e)) => {
+          defmt::warn!("Config import rejected: {}", e);
+          write_reply(serial, &reply_to(msg, Command::Nak, &[NakCode::BadLength.into(), msg.id]));
+        }
+        None => {}
+      }
+      true
+    }
+    Ok(Command::Telemetry) => {
+      write_reply(serial, &reply_to(msg, Command::Telemetry, &telemetry::latest().to_bytes()));
+      true
+    }
+    Ok(Command::Sensors) => {
+      match sensors::latest() {
+        Some(reading) => write_reply(serial, &reply_to(msg, Command::Sensors, &sensors::encode(&reading))),
+        None => write_reply(serial, &reply_to(msg, Command::Sensors, &[])),
+      }
+      true
+    }
+    Ok(Command::GetConfig) => {
+      write_reply(serial, &reply_to(msg, Command::GetConfig, &config::current().encode()));
+      true
+    }
+    Ok(Command::SetConfig) => {
+      let reply = match config::stage(msg.id, &msg.payload) {
+        Ok(staged) => reply_to(msg, Command::SetConfig, &staged.encode()),
+        Err(_) => reply_to(msg, Command::Nak, &[NakCode::BadLength.into(), msg.id]),
+      };
+      write_reply(serial, &reply);
+      true
+    }
+    Ok(Command::Ident) => {
+      write_reply(serial, &reply_to(msg, Command::Ident, &buildinfo::get().to_bytes()));
+      true
+    }
+    // The reply is sealed under the new key, which proves the switch to the host
+    #[cfg(feature = "comm_crypto")]
+    Ok(Command::SetKey) => {
+      match crypto::set_key(&msg.payload) {
+        Ok(()) => write_reply(serial, &reply_to(msg, Command::SetKey, &[])),
+        Err(_) => write_reply(serial, &reply_to(msg, Command::Nak, &[NakCode::BadLength.into(), msg.id])),
+      }
+      true
+    }
+    Ok(Command::Subscribe) => {
+      let reply = match pubsub::handle_subscribe(&msg.payload) {
+        Ok(()) => reply_to(msg, Command::Ack, &[]),
+        Err(()) => reply_to(msg, Command::Nak, &[NakCode::BadLength.into(), msg.id]),
+      };
+      write_reply(serial, &reply);
+      true
+    }
+    Ok(Command::Unsubscribe) => {
+      let reply = match pubsub::handle_unsubscribe(&msg.payload) {
+        Ok(()) => reply_to(msg, Command::Ack, &[]),
+        Err(()) => reply_to(msg, Command::Nak, &[NakCode::BadLength.into(), msg.id]),
+      };
+      write_reply(serial, &reply);
+      true
+    }
+    // Fire-and-forget: only a malformed publish is answered
+    Ok(Command::Publish) => {
+      if pubsub::handle_publish(&msg.payload).is_err() {
+        write_reply(serial, &reply_to(msg, Command::Nak, &[NakCode::BadLength.into(), msg.id]));
+      }
+      true
+    }
+    Ok(Command::Protection) => {
+      write_reply(serial, &reply_to(msg, Command::Protection, &option_bytes::read().to_bytes()));
+      true
+    }
+    // Acknowledged before the reset; the host then talks to the ROM bootloader instead
+    Ok(Command::Bootloader) => {
+      write_reply(serial, &reply_to(msg, Command::Ack, &[]));
+      system::enter_rom_bootloader();
+    }
+    Ok(Command::EventLog) => {
+      // Whole events per fragment; an empty log still gets one (empty) reply
+      let events = eventlog::events();
+      let per_fragment = COMMS_MAX_PLAINTEXT / Event::LEN;
+      let fragments = events.len().div_ceil(per_fragment).max(1) as u16;
+      for index in 0..fragments as usize {
+        let mut payload: Vec<u8, COMMS_MAX_PAYLOAD> = Vec::new();
+        for event in events.iter().skip(index * per_fragment).take(per_fragment) {
+          let _ = payload.extend_from_slice(&event.to_bytes());
+        }
+        let mut reply = reply_to(msg, Command::EventLog, &payload);
+        reply.fragments = fragments;
+        reply.fragment = index as u16;
+        write_reply(serial, &reply);
+      }
+      true
+    }
+    // Only the ACK of a staged `SetConfig` is consumed; other ACKs are left to the application
+    Ok(Command::Ack) if config::commit(msg.id) => {
+      write_reply(serial, &reply_to(msg, Command::GetConfig, &config::current().encode()));
+      true
+    }
+    _ => false,
+  }
+}
+
+/// Write any automatic replies (NAKs), messages queued with `send`, alarm notifications, telemetry and sensor readings;
+/// call regularly from the task owning TX (between `duty_cycle` windows they are held instead)
+pub fn send_pending<W: embedded_io::Write>(serial: &mut W) {
+  if duty_cycle::is_closed() {
+    hold_pending();
+    return;
+  }
+  // Held since the last window, so ahead of anything queued meanwhile
+  duty_cycle::flush(serial);
+  send_high(serial);
+  // At most one queue's worth of data per call, so a busy sender cannot hold up the caller's own
+  // traffic; control queued meanwhile goes out between data messages
+  for _ in 0..COMMS_TX_QUEUE_DEPTH {
+    match COMMS_TX_QUEUE.try_receive() {
+      Ok(msg) => write(serial, &msg),
+      Err(_) => break,
+    }
+    send_high(serial);
+  }
+  while let Some(notification) = alarm::next_notification() {
+    notify(serial, &notification);
+  }
+  if let Some(record) = telemetry::next_notification() {
+    notify(serial, &record);
+  }
+  if let Some(reading) = sensors::next_notification() {
+    write(serial, &reading);
+  }
+}
+
+// Write a notification, or leave it to the journal while the host is away
+fn notify<W: embedded_io::Write>(serial: &mut W, msg: &Message) {
+  if !journal::hold(msg) {
+    write(serial, msg);
+  }
+}
+
+// `send_pending` between duty-cycle windows: move the queued messages and notifications to the
+// outbox (notifications to the journal first) while it has room; the rest wait in their own queues
+// and channels. NAKs wait in their queue: nothing is received meanwhile.
+fn hold_pending() {
+  while duty_cycle::has_room() {
+    let Ok(msg) = COMMS_TX_HIGH_QUEUE.try_receive().or_else(|_| COMMS_TX_QUEUE.try_receive()) else {
+      break;
+    };
+    duty_cycle::queue(msg).ok();
+  }
+  while let Some(notification) = duty_cycle::has_room().then(alarm::next_notification).flatten() {
+    hold_notification(notification);
+  }
+  if let Some(record) = duty_cycle::has_room().then(telemetry::next_notification).flatten() {
+    hold_notification(record);
+  }
+  if let Some(reading) = duty_cycle::has_room().then(sensors::next_notification).flatten() {
+    duty_cycle::queue(reading).ok();
+  }
+}
+
+fn hold_notification(msg: Message) {
+  if !journal::hold(&msg) {
+    duty_cycle::queue(msg).ok();
+  }
+}
+
+// Write the automatic NAKs and the high-priority tier
+fn send_high<W: embedded_io::Write>(serial: &mut W) {
+  while let Ok(reply) = COMMS_NAK_QUEUE.try_receive() {
+    write(serial, &reply);
+  }
+  while let Ok(msg) = COMMS_TX_HIGH_QUEUE.try_receive() {
+    write(serial, &msg);
+  }
 }
 
 /// Async task: read bytes from serial queue, deframe, and publish decoded payloads
 #[embassy_executor::task]
 pub async fn serial_hdlc_consumer_task() {
-  let mut rx_buf: ByteVec = Vec::new();
-  let mut decoded: ByteVec = Vec::new();
+  let mut receiver = FrameReceiver::new();
   loop {
     // Wait for a new message from the serial RX queue
     let msg = serial::recv_raw().await;
-    // Append to buffer
-    rx_buf.extend_from_slice(&msg).ok();
+    // A buffer can hold several frames, each handled on the spot; slice it so the handlers do
+    // not keep the serial RX task from its DMA buffer (each slice is one metrics iteration)
+    cooperative::for_each_chunk(&msg, COMMS_RX_SLICE_LEN, |bytes| {
+      #[cfg(feature = "task_metrics")]
+      let _busy = crate::diagnostics::task_metrics::Iteration::begin(crate::diagnostics::task_metrics::Task::CommRx);
+      receiver.push(bytes);
+    })
+    .await;
+  }
+}
 
-    // Safety check: clear buffer if it grows too large
-    if rx_buf.len() >= COMMS_BYTE_VEC_SIZE {
-      defmt::warn!("serial_hdlc_consumer_task: rx_buf overflow ({} bytes), clearing buffer", rx_buf.len());
-      rx_buf.clear();
+/// Whether the peer (host) is still talking to this node
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum LinkState {
+  /// Nothing received within the timeout (or nothing yet since boot)
+  Down,
+  /// A message arrived within the timeout
+  Up,
+}
+
+/// Link state changes buffered for `link_event` (further changes are dropped until it is read)
+pub const LINK_EVENT_DEPTH: usize = 4;
+
+// Uptime (ms, wrapping) of the last message received
+static LAST_RX_MS: AtomicU32 = AtomicU32::new(0);
+static LINK_UP: AtomicBool = AtomicBool::new(false);
+static LINK_EVENTS: Channel<CriticalSectionRawMutex, LinkState, LINK_EVENT_DEPTH> = Channel::new();
+
+/// Current link state: Up on every received message, Down after `link_monitor_task`'s timeout
+pub fn link_state() -> LinkState {
+  if LINK_UP.load(Ordering::Relaxed) { LinkState::Up } else { LinkState::Down }
+}
+
+/// Wait for the next link state change (e.g. to put outputs in a safe state when the host goes away)
+pub async fn link_event() -> LinkState {
+  LINK_EVENTS.receive().await
+}
+
+fn set_link_state(state: LinkState) {
+  let up = state == LinkState::Up;
+  if LINK_UP.swap(up, Ordering::Relaxed) != up {
+    defmt::info!("Comm link {}", state);
+    eventlog::record(eventlog::EVENT_LINK, up as u32);
+    let _ = LINK_EVENTS.try_send(state);
+  }
+}
+
+// A message arrived: the peer is alive
+fn note_rx() {
+  LAST_RX_MS.store(embassy_time::Instant::now().as_millis() as u32, Ordering::Relaxed);
+  set_link_state(LinkState::Up);
+}
+
+/// Async task: declares the link Down when nothing has been received for `timeout_ms`. Any message
+/// counts as traffic, so a host that is otherwise quiet keeps the link up by pinging (e.g.
+/// `ping --count 0 --interval 1000` from the host CLI) at a shorter interval.
+#[embassy_executor::task]
+pub async fn link_monitor_task(timeout_ms: u32) {
+  loop {
+    Timer::after_millis((timeout_ms / 4).max(1) as u64).await;
+    let now = embassy_time::Instant::now().as_millis() as u32;
+    if link_state() == LinkState::Up && now.wrapping_sub(LAST_RX_MS.load(Ordering::Relaxed)) > timeout_ms {
+      set_link_state(LinkState::Down);
+    }
+  }
+}
+
+/// Receive side of a byte-stream link: deframes (in `framing()`) and dispatches what arrives.
+/// The serial consumer owns one; other stream transports (e.g. `service::net`) keep their own.
+pub struct FrameReceiver {
+  rx_buf: ByteVec,
+  decoded: ByteVec,
+}
+
+impl FrameReceiver {
+  pub const fn new() -> Self {
+    Self {
+      rx_buf: Vec::new(),
+      decoded: Vec::new(),
     }
+  }
+
+  /// Drop any partial frame (e.g. when a new connection starts)
+  pub fn reset(&mut self) {
+    self.rx_buf.clear();
+  }
 
-    // Try to decode HDLC frame(s)
-    let mut had_fcs_error = false;
-    while try_decode_hdlc(&mut rx_buf, &mut decoded) {
-      // Try to parse as a Comms frame and publish
-      if let Some(msg) = try_parse_comms_frame(&decoded) {
-        let _ = COMMS_MSG_QUEUE.try_send(msg);
+  /// Feed received bytes; every complete frame is parsed and dispatched
+  pub fn push(&mut self, mut bytes: &[u8]) {
+    RX_BYTES.fetch_add(bytes.len() as u32, Ordering::Relaxed);
+    while !bytes.is_empty() {
+      // Append what fits to the buffer (a long burst on the DMA with `hw_dma`)
+      let (chunk, rest) = bytes.split_at(bytes.len().min(COMMS_BYTE_VEC_SIZE - self.rx_buf.len()));
+      bytes = rest;
+      #[cfg(feature = "hw_dma")]
+      {
+        let at = self.rx_buf.len();
+        // Always fits: `chunk` was cut to the free space
+        self.rx_buf.resize_default(at + chunk.len()).ok();
+        crate::hardware::dma::copy(&mut self.rx_buf[at..], chunk);
       }
-      // If the last FCS error count increased, set flag
-      if fcs_error_count() > 0 {
-        had_fcs_error = true;
+      #[cfg(not(feature = "hw_dma"))]
+      self.rx_buf.extend_from_slice(chunk).ok();
+      self.process();
+
+      // Safety check: clear buffer if it fills up without completing a frame
+      if self.rx_buf.len() >= COMMS_BYTE_VEC_SIZE {
+        defmt::warn!("FrameReceiver: rx_buf overflow ({} bytes), clearing buffer", self.rx_buf.len());
+        self.rx_buf.clear();
       }
     }
-    // If an FCS error occurred, clear the RX buffer to resync
-    if had_fcs_error {
-      defmt::warn!("Clearing RX buffer due to FCS error (frame resync)");
-      rx_buf.clear();
+  }
+
+  fn process(&mut self) {
+    // Try to decode HDLC/SLIP frame(s)
+    loop {
+      match try_deframe(&mut self.rx_buf, &mut self.decoded) {
+        Deframed::Frame => {
+          RX_FRAMES.fetch_add(1, Ordering::Relaxed);
+          // Try to parse as a Comms frame and publish
+          match MessageRef::parse(&self.decoded) {
+            Ok(msg) => dispatch_ref(msg),
+            Err((code, id)) => {
+              PARSE_ERRORS.fetch_add(1, Ordering::Relaxed);
+              nak(code, id);
+            }
+          }
+        }
+        Deframed::Incomplete => break,
+        Deframed::FcsError => {
+          if on_fcs_error() {
+            defmt::warn!("Clearing RX buffer due to FCS error (frame resync)");
+            self.rx_buf.clear();
+            break;
+          }
+        }
+      }
     }
   }
 }
 
+impl Default for FrameReceiver {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Deliver a decoded message: queue it for `read()` (or, with `comms_routing`, forward it)
+/// Other transports (RS-485, radio, ...) call this with the messages they decode.
+/// With `comm_crypto` and a key installed, messages for this node that fail authentication are dropped.
+pub fn dispatch(msg: Message) {
+  note_rx();
+  #[cfg(feature = "comms_routing")]
+  let msg = match route(msg) {
+    Some(msg) => msg,
+    None => return,
+  };
+  #[cfg(feature = "comm_crypto")]
+  let mut msg = msg;
+  #[cfg(feature = "comm_crypto")]
+  if let Err(error) = crypto::open(&mut msg) {
+    AUTH_FAILURES.fetch_add(1, Ordering::Relaxed);
+    // A session the device did not open gets the challenge to open one with
+    let challenge = crypto::challenge().filter(|_| error == SecureError::Challenge);
+    nak_with(NakCode::AuthFailed, msg.id, challenge.as_ref().map_or(&[], |c| &c[..]));
+    return;
+  }
+  #[cfg(feature = "comm_compress")]
+  let Some(msg) = decompress(msg) else {
+    return;
+  };
+  if is_duplicate(&msg.view()) || is_response(&msg.view()) {
+    return;
+  }
+  if !handled(&msg.view()) {
+    enqueue(msg);
+  }
+}
+
+/// Deliver a decoded message still borrowed from its frame buffer: the handler (`set_handler`)
+/// sees it without a copy, and only messages it leaves are copied into the `read()` queue
+pub fn dispatch_ref(msg: MessageRef<'_>) {
+  note_rx();
+  // Forwarding, decryption and decompression need an owned copy
+  #[cfg(feature = "comms_routing")]
+  if !matches!(routing(|table| table.route(msg.dst, msg.hops)), Route::Local) {
+    return dispatch(msg.to_message());
+  }
+  #[cfg(feature = "comm_crypto")]
+  if crypto::enabled() {
+    return dispatch(msg.to_message());
+  }
+  #[cfg(feature = "comm_compress")]
+  if msg.command & COMMS_COMPRESSED != 0 {
+    return dispatch(msg.to_message());
+  }
+  if is_duplicate(&msg) || is_response(&msg) {
+    return;
+  }
+  if !handled(&msg) {
+    enqueue(msg.to_message());
+  }
+}
+
+/// Install the receive-path handler (replaces any previous one). It runs in the receive task, so
+/// it must not block; it can answer with `try_send`.
+pub fn set_handler(handler: MessageHandler) {
+  COMMS_HANDLER.lock(|h| h.set(Some(handler)));
+}
+
 /// Read next parsed Comms message (non-blocking).
 pub fn read() -> Option<Message> {
   COMMS_MSG_QUEUE.try_receive().ok()
@@ -173,69 +967,174 @@ pub fn read() -> Option<Message> {
 
 // --- Internal helpers ---
 
-/// Try to decode an HDLC frame from a buffer of received serial data
-fn try_decode_hdlc(buf: &mut ByteVec, out: &mut ByteVec) -> bool {
-  match hdlc::hdlc_deframe(buf, out) {
-    Ok(()) => true,
-    Err(hdlc::HdlcError::FcsMismatch { received, calculated, len }) => {
-      // Suppress warning for empty/incomplete frames (all zero)
-      if received != 0 || calculated != 0 || len != 0 {
-        FCS_ERROR_COUNT.fetch_add(1, Ordering::Relaxed);
-        defmt::warn!("HDLC FCS error: recv={=u16}, calc={=u16}, len={}", received, calculated, len);
-        // Reset the board immediately on FCS error
-        SCB::sys_reset();
-      }
-      false
-    }
+/// Drop a retransmitted copy of a recently received message (counted in `duplicate_count`),
+/// writing its reply again if it was already answered
+fn is_duplicate(msg: &MessageRef<'_>) -> bool {
+  let now = embassy_time::Instant::now().as_millis() as u32;
+  if !RECENT_IDS.lock(|recent| recent.borrow_mut().is_duplicate(msg, now)) {
+    return false;
   }
+  DUPLICATES.fetch_add(1, Ordering::Relaxed);
+  defmt::debug!("Dropping duplicate of message id {} (command {=u16:#x})", msg.id, msg.command);
+  let Some(reply) = REPLIES.lock(|replies| replies.borrow().find(msg, now).cloned()) else {
+    return true;
+  };
+  if COMMS_NAK_QUEUE.try_send(reply).is_ok() {
+    COMMS_TX_WAKE.signal(());
+  }
+  true
 }
 
-/// Try to parse a Comms message from a byte slice (little-endian)
-fn try_parse_comms_frame(bytes: &[u8]) -> Option<Message> {
-  if bytes.len() < COMMS_HEADER_LEN {
+/// Keep the reply `msg` in the reply cache while its request is recent
+fn remember_reply(msg: &Message) {
+  #[cfg(feature = "comms_routing")]
+  let peer = msg.dst;
+  #[cfg(not(feature = "comms_routing"))]
+  let peer = 0;
+  let now = embassy_time::Instant::now().as_millis() as u32;
+  if RECENT_IDS.lock(|recent| recent.borrow().answers(msg.id, peer, now)) {
+    REPLIES.lock(|replies| replies.borrow_mut().store(msg, now));
+  }
+}
+
+/// Heatshrink copy of `msg` if its payload is long enough and gets shorter
+#[cfg(feature = "comm_compress")]
+fn compress(msg: &Message) -> Option<Message> {
+  if msg.payload.len() < COMMS_COMPRESS_MIN || msg.command & COMMS_COMPRESSED != 0 {
     return None;
   }
-  let cmd = u16::from_le_bytes([bytes[0], bytes[1]]);
-  let id = bytes[2];
-  let frags = u16::from_le_bytes([bytes[3], bytes[4]]);
-  let frag = u16::from_le_bytes([bytes[5], bytes[6]]);
-  let len = u16::from_le_bytes([bytes[7], bytes[8]]) as usize;
-  let total = COMMS_HEADER_LEN + len;
+  let mut payload = CommsPayload::new();
+  heatshrink::compress(&msg.payload, &mut payload).ok().filter(|_| payload.len() < msg.payload.len())?;
+  Some(Message {
+    command: msg.command | COMMS_COMPRESSED,
+    payload,
+    ..msg.clone()
+  })
+}
 
-  // Check if frame has the expected length (header + payload)
-  if bytes.len() != total {
-    // Handle common case: extra 0x00 byte inserted after header
-    if bytes.len() == total + 1 && bytes.len() > COMMS_HEADER_LEN && bytes[COMMS_HEADER_LEN] == 0x00 {
-      defmt::warn!("Found extra 0x00 byte at position {}, skipping it", COMMS_HEADER_LEN);
-    } else {
-      defmt::warn!("Frame length mismatch: got {}, expected {}", bytes.len(), total);
-      return None;
+/// Restore a compressed payload (NAK `BadLength` and `None` if it does not decompress)
+#[cfg(feature = "comm_compress")]
+fn decompress(msg: Message) -> Option<Message> {
+  if msg.command & COMMS_COMPRESSED == 0 {
+    return Some(msg);
+  }
+  let mut payload = CommsPayload::new();
+  if heatshrink::decompress(&msg.payload, &mut payload).is_err() {
+    PARSE_ERRORS.fetch_add(1, Ordering::Relaxed);
+    nak(NakCode::BadLength, msg.id);
+    return None;
+  }
+  Some(Message {
+    command: msg.command & !COMMS_COMPRESSED,
+    payload,
+    ..msg
+  })
+}
+
+/// Hand the response to a waiting `request`; true if one took it
+fn is_response(msg: &MessageRef<'_>) -> bool {
+  if msg.id == 0 {
+    return false;
+  }
+  let Some(slot) = PENDING_IDS.lock(|ids| ids.get().iter().position(|&pending| pending == msg.id)) else {
+    return false;
+  };
+  RESPONSES[slot].signal(msg.to_message());
+  true
+}
+
+/// Offer a message to the installed handler; true if it was consumed
+fn handled(msg: &MessageRef<'_>) -> bool {
+  COMMS_HANDLER.lock(|h| h.get()).is_some_and(|handler| handler(msg))
+}
+
+/// Queue a message for `read()` (NAK `QueueFull` if there is no room; the message is then
+/// forgotten, so the sender's retransmission is delivered)
+fn enqueue(msg: Message) {
+  if let Err(embassy_sync::channel::TrySendError::Full(msg)) = COMMS_MSG_QUEUE.try_send(msg) {
+    QUEUE_DROPS.fetch_add(1, Ordering::Relaxed);
+    RECENT_IDS.lock(|recent| recent.borrow_mut().forget(&msg.view()));
+    nak(NakCode::QueueFull, msg.id);
+  }
+}
+
+// Outcome of one `try_deframe`
+enum Deframed {
+  Frame,
+  Incomplete,
+  FcsError,
+}
+
+/// Try to decode a frame (in the link's `framing()`) from a buffer of received serial data
+fn try_deframe(buf: &mut ByteVec, out: &mut ByteVec) -> Deframed {
+  if framing() == Framing::Slip {
+    return match slip::slip_deframe(buf, out) {
+      Ok(()) => Deframed::Frame,
+      Err(_) => Deframed::Incomplete,
+    };
+  }
+  match hdlc::hdlc_deframe(buf, out) {
+    Ok(()) => Deframed::Frame,
+    Err(hdlc::HdlcError::Incomplete) => Deframed::Incomplete,
+    Err(hdlc::HdlcError::FcsMismatch { received, calculated, len }) => {
+      FCS_ERROR_COUNT.fetch_add(1, Ordering::Relaxed);
+      defmt::warn!("HDLC FCS error: recv={=u16}, calc={=u16}, len={}", received, calculated, len);
+      // Frame content is untrusted, so the offending id is unknown (0)
+      nak(NakCode::FcsError, 0);
+      Deframed::FcsError
     }
   }
+}
 
-  let mut payload: CommsPayload = Vec::new();
-  let copy = core::cmp::min(len, COMMS_MAX_PAYLOAD);
+/// Build a reply to `msg` (same id; addressed back to the sender with `comms_routing`)
+fn reply_to(msg: &Message, command: Command, payload: &[u8]) -> Message {
+  reply(&msg.view(), command, payload)
+}
 
-  // Skip extra 0x00 byte if present (workaround for HDLC deframing issue)
-  let payload_start = if bytes.len() == total + 1 && bytes.len() > COMMS_HEADER_LEN && bytes[COMMS_HEADER_LEN] == 0x00 {
-    COMMS_HEADER_LEN + 1 // Skip the extra byte
-  } else {
-    COMMS_HEADER_LEN // Normal case
-  };
+/// Queue an automatic NAK reply (dropped if the reply queue is full)
+fn nak(code: NakCode, id: u8) {
+  nak_with(code, id, &[]);
+}
 
-  if bytes.len() >= payload_start + copy {
-    payload.extend_from_slice(&bytes[payload_start..payload_start + copy]).ok()?;
-  } else {
-    defmt::warn!("Not enough bytes for payload: need {}, have {}", payload_start + copy, bytes.len());
-    return None;
+/// Queue an automatic NAK reply carrying `detail` after the code and id
+fn nak_with(code: NakCode, id: u8, detail: &[u8]) {
+  defmt::warn!("NAK code {} for message id {}", u8::from(code), id);
+  let mut payload = CommsPayload::new();
+  payload.extend_from_slice(&[code.into(), id]).ok();
+  payload.extend_from_slice(detail).ok();
+  let mut reply = Message::new(Command::Nak, &payload);
+  reply.id = id;
+  #[cfg(feature = "comms_routing")]
+  {
+    // Sender is unknown for frames that failed to parse
+    reply.src = node_address();
+    reply.dst = BROADCAST;
   }
+  if COMMS_NAK_QUEUE.try_send(reply).is_ok() {
+    COMMS_TX_WAKE.signal(());
+  }
+}
 
-  Some(Message {
-    command: cmd,
-    id,
-    fragments: frags,
-    fragment: frag,
-    length: len as u16,
-    payload,
-  })
+/// Apply the routing table: returns the message if it is for this node, otherwise forwards or drops it
+#[cfg(feature = "comms_routing")]
+fn route(mut msg: Message) -> Option<Message> {
+  match routing(|table| table.route(msg.dst, msg.hops)) {
+    Route::Local => Some(msg),
+    Route::Forward(link) => {
+      msg.hops = msg.hops.saturating_add(1);
+      if COMMS_FWD_QUEUE.try_send((link, msg)).is_err() {
+        QUEUE_DROPS.fetch_add(1, Ordering::Relaxed);
+      }
+      None
+    }
+    Route::Drop(DropReason::HopLimit) => {
+      defmt::warn!("Dropping message id {} for node {}: hop limit", msg.id, msg.dst);
+      None
+    }
+    // On a multi-drop bus every node hears the frames for the others: not worth a warning
+    Route::Drop(DropReason::NoRoute) => {
+      defmt::debug!("Ignoring message id {} for node {}", msg.id, msg.dst);
+      None
+    }
+  }
 }
@@ -0,0 +1,408 @@
+//! Persistent device configuration (serial baud, device id, feature flags, user blob)
+// The active configuration lives in RAM; `ConfigStore` keeps it in flash as a
+// `protocol::device_config` record (versioned, CRC-protected). Records are appended in fixed
+// slots so an update needs no erase until the region is full; at boot the last record that
+// passes its CRC wins, so a write torn by a reset falls back to the previous configuration,
+// and a blank region to the defaults.
+//
+// Comms (handled by `comm::handle_builtin`), with commit-on-ACK:
+// - `GetConfig` (empty payload): replied with the active record
+// - `SetConfig` (record): validated and staged, replied with the staged record as `SetConfig`
+//   (or NAK `BadLength`); nothing changes yet
+// - `Ack` with the `SetConfig` id within `CONFIG_COMMIT_TIMEOUT_MS`: the staged record becomes
+//   active, is written to flash by `config_task`, and is replied as `GetConfig`
+// The baud rate takes effect at the next boot (`load` hands it to `serial::set_baudrate`), and
+// so does the bus node address with `comms_routing` (`load` sets it in `comm::routing()`), so
+// the reply to a commit still comes from the address the host sent to.
+//
+// The same region also holds the calendar schedule (`protocol::schedule` record, see
+// `service::scheduler`), the rule set (`protocol::rules` record, see `service::rules`), the
+// latched alarms (see `service::alarm`) and, with `comm_crypto`, the comm link key
+// (`protocol::secure::KeyRecord`), each in its own slots; the newest record of each kind survives
+// the erase when the region fills up.
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU8, Ordering};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant};
+use embedded_storage::nor_flash::NorFlash;
+use heapless::Vec;
+
+#[cfg(feature = "fs")]
+use crate::board::BoardConfig;
+use crate::hardware::flash::Storage;
+use crate::hardware::serial::{self, SERIAL_BAUDRATE};
+use crate::protocol::device_config::{DEVICE_CONFIG_MAX, DeviceConfig, DeviceConfigError};
+use crate::protocol::rules::{self as rule_record, RULES_MAX, RULES_RECORD_MAX, Rule};
+use crate::protocol::schedule::{self, SCHEDULE_RECORD_MAX};
+#[cfg(feature = "comm_crypto")]
+use crate::protocol::secure::KeyRecord;
+use crate::service::alarm::{self, ALARM_RECORD_MAX, Alarm, AlarmTable};
+#[cfg(feature = "comms_routing")]
+use crate::service::comm;
+#[cfg(feature = "comm_crypto")]
+use crate::service::crypto;
+use crate::service::rules;
+use crate::service::scheduler::{self, ScheduleTable};
+
+/// Time the host has to ACK a `SetConfig` before the staged record is dropped
+pub const CONFIG_COMMIT_TIMEOUT_MS: u64 = 5000;
+
+// Slots are sized for the largest record, a full rule set
+const SLOT_LEN: u32 = RULES_RECORD_MAX as u32;
+const ERASED: [u8; 2] = [0xFF; 2];
+const _: () = assert!(DEVICE_CONFIG_MAX <= RULES_RECORD_MAX, "config record must fit a slot");
+const _: () = assert!(SCHEDULE_RECORD_MAX <= RULES_RECORD_MAX, "schedule record must fit a slot");
+const _: () = assert!(ALARM_RECORD_MAX <= RULES_RECORD_MAX, "alarm record must fit a slot");
+// littlefs and the config records would corrupt each other
+#[cfg(feature = "fs")]
+const _: () = assert!(
+  BoardConfig::FLASH_STORAGE_END <= BoardConfig::FS_STORAGE_START || BoardConfig::FS_STORAGE_START + BoardConfig::FS_STORAGE_SIZE as u32 <= BoardConfig::FLASH_STORAGE_START,
+  "the storage region overlaps the fs region"
+);
+
+// What `config_task` has to write
+const SAVE_CONFIG: u8 = 1 << 0;
+#[cfg(feature = "comm_crypto")]
+const SAVE_KEY: u8 = 1 << 1;
+const SAVE_SCHEDULE: u8 = 1 << 2;
+const SAVE_RULES: u8 = 1 << 3;
+const SAVE_ALARMS: u8 = 1 << 4;
+
+type SlotBuf = Vec<u8, { SLOT_LEN as usize }>;
+
+// Kinds of records sharing the region
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Kind {
+  Config,
+  #[cfg(feature = "comm_crypto")]
+  Key,
+  Schedule,
+  Rules,
+  Alarms,
+}
+
+struct Staged {
+  id: u8,
+  at: Instant,
+  config: DeviceConfig,
+}
+
+static ACTIVE: Mutex<CriticalSectionRawMutex, RefCell<DeviceConfig>> = Mutex::new(RefCell::new(DeviceConfig::new(SERIAL_BAUDRATE)));
+static STAGED: Mutex<CriticalSectionRawMutex, RefCell<Option<Staged>>> = Mutex::new(RefCell::new(None));
+static SAVE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static PENDING: AtomicU8 = AtomicU8::new(0);
+
+/// Configuration records over a flash region
+pub struct ConfigStore<F: NorFlash> {
+  flash: F,
+  size: u32,
+  // Next free slot
+  next: u32,
+}
+
+impl<F: NorFlash> ConfigStore<F> {
+  /// Open the store on the first `size` bytes of `flash` (a whole number of erase blocks)
+  pub fn open(flash: F, size: u32) -> Self {
+    const { assert!(F::WRITE_SIZE == 1, "config store needs byte-writable flash") };
+    Self { flash, size, next: 0 }
+  }
+
+  /// Newest valid record, if any (also finds the next free slot)
+  pub fn load(&mut self) -> Result<Option<DeviceConfig>, F::Error> {
+    self.newest(|slot| DeviceConfig::decode(slot).ok())
+  }
+
+  /// Append `config` as the newest record, erasing the region first when it is full
+  pub fn save(&mut self, config: &DeviceConfig) -> Result<(), F::Error> {
+    self.append(&config.encode(), Kind::Config)
+  }
+
+  /// Newest valid link key record, if any
+  #[cfg(feature = "comm_crypto")]
+  pub fn load_key(&mut self) -> Result<Option<KeyRecord>, F::Error> {
+    self.newest(KeyRecord::decode)
+  }
+
+  /// Append `record` as the newest key record, erasing the region first when it is full
+  #[cfg(feature = "comm_crypto")]
+  pub fn save_key(&mut self, record: &KeyRecord) -> Result<(), F::Error> {
+    self.append(&record.encode(), Kind::Key)
+  }
+
+  /// Newest valid schedule record, if any
+  pub fn load_schedule(&mut self) -> Result<Option<ScheduleTable>, F::Error> {
+    self.newest(schedule::decode_record)
+  }
+
+  /// Append `entries` as the newest schedule record, erasing the region first when it is full
+  pub fn save_schedule(&mut self, entries: &[schedule::ScheduleEntry]) -> Result<(), F::Error> {
+    self.append(&schedule::encode_record(entries), Kind::Schedule)
+  }
+
+  /// Newest valid rule set record, if any
+  pub fn load_rules(&mut self) -> Result<Option<Vec<Rule, RULES_MAX>>, F::Error> {
+    self.newest(rule_record::decode_record)
+  }
+
+  /// Append `rules` as the newest rule set record, erasing the region first when it is full
+  pub fn save_rules(&mut self, rules: &[Rule]) -> Result<(), F::Error> {
+    self.append(&rule_record::encode_record(rules), Kind::Rules)
+  }
+
+  /// Newest valid alarm record, if any
+  pub fn load_alarms(&mut self) -> Result<Option<AlarmTable>, F::Error> {
+    self.newest(alarm::decode_record)
+  }
+
+  /// Append `alarms` as the newest alarm record, erasing the region first when it is full
+  pub fn save_alarms(&mut self, alarms: &[Alarm]) -> Result<(), F::Error> {
+    self.append(&alarm::encode_record(alarms), Kind::Alarms)
+  }
+
+  // Scan the slots for the newest one `decode` accepts, and find the next free slot
+  fn newest<T>(&mut self, decode: impl Fn(&[u8]) -> Option<T>) -> Result<Option<T>, F::Error> {
+    let mut found = None;
+    let mut slot = [0u8; SLOT_LEN as usize];
+    let mut offset = 0;
+    while offset + SLOT_LEN <= self.size {
+      self.flash.read(offset, &mut slot)?;
+      if slot[..2] == ERASED {
+        break;
+      }
+      // Torn or corrupt slots (and records of the other kind) are skipped
+      if let Some(record) = decode(&slot) {
+        found = Some(record);
+      }
+      offset += SLOT_LEN;
+    }
+    self.next = offset;
+    Ok(found)
+  }
+
+  // Write `record` (of `kind`) in the next slot; when the region is full, erase it and write
+  // back the newest record of every other kind first. A next slot that is not blank (a region
+  // written with narrower slots by an older firmware) is handled like a full region.
+  fn append(&mut self, record: &[u8], kind: Kind) -> Result<(), F::Error> {
+    if self.next + SLOT_LEN > self.size || !self.blank(self.next)? {
+      let carried = self.carried(kind)?;
+      self.flash.erase(0, self.size)?;
+      self.next = 0;
+      for carried in carried {
+        self.flash.write(self.next, &carried)?;
+        self.next += SLOT_LEN;
+      }
+    }
+    self.flash.write(self.next, record)?;
+    self.next += SLOT_LEN;
+    Ok(())
+  }
+
+  fn blank(&mut self, offset: u32) -> Result<bool, F::Error> {
+    let mut slot = [0u8; SLOT_LEN as usize];
+    self.flash.read(offset, &mut slot)?;
+    Ok(slot.iter().all(|&byte| byte == 0xFF))
+  }
+
+  // The newest record of each kind other than `kind`, encoded
+  fn carried(&mut self, kind: Kind) -> Result<Vec<SlotBuf, 4>, F::Error> {
+    let mut carried = Vec::new();
+    if kind != Kind::Config {
+      carried.extend(self.load()?.map(|config| SlotBuf::from_slice(&config.encode()).unwrap()));
+    }
+    #[cfg(feature = "comm_crypto")]
+    if kind != Kind::Key {
+      carried.extend(self.load_key()?.map(|record| SlotBuf::from_slice(&record.encode()).unwrap()));
+    }
+    if kind != Kind::Schedule {
+      carried.extend(self.load_schedule()?.map(|entries| SlotBuf::from_slice(&schedule::encode_record(&entries)).unwrap()));
+    }
+    if kind != Kind::Rules {
+      carried.extend(self.load_rules()?.map(|rules| SlotBuf::from_slice(&rule_record::encode_record(&rules)).unwrap()));
+    }
+    if kind != Kind::Alarms {
+      carried.extend(self.load_alarms()?.map(|alarms| SlotBuf::from_slice(&alarm::encode_record(&alarms)).unwrap()));
+    }
+    Ok(carried)
+  }
+}
+
+/// Load the stored configuration (defaults if there is none) and apply the baud rate (and node
+/// address); call at boot
+pub fn load<F: NorFlash>(store: &mut ConfigStore<F>) -> DeviceConfig {
+  let config = match store.load() {
+    Ok(Some(config)) => config,
+    Ok(None) => {
+      defmt::info!("Config: none stored, using defaults");
+      DeviceConfig::new(SERIAL_BAUDRATE)
+    }
+    Err(_) => {
+      defmt::warn!("Config: flash read failed, using defaults");
+      DeviceConfig::new(SERIAL_BAUDRATE)
+    }
+  };
+  defmt::info!("Config: id {} baud {} flags 0x{:08X}", config.device_id, config.baud, config.flags);
+  serial::set_baudrate(config.baud);
+  #[cfg(feature = "comms_routing")]
+  if config.address != 0 {
+    comm::routing(|table| table.set_address(config.address));
+    defmt::info!("Config: node address {=u8:#x}", config.address);
+  }
+  ACTIVE.lock(|active| active.replace(config.clone()));
+  #[cfg(feature = "comm_crypto")]
+  load_key(store);
+  match store.load_schedule() {
+    Ok(Some(entries)) => scheduler::install(entries),
+    Ok(None) => {}
+    Err(_) => defmt::warn!("Config: flash read failed, no schedule"),
+  }
+  match store.load_rules() {
+    Ok(Some(set)) => rules::install(set),
+    Ok(None) => {}
+    Err(_) => defmt::warn!("Config: flash read failed, no rules"),
+  }
+  match store.load_alarms() {
+    Ok(Some(alarms)) => alarm::install(alarms),
+    Ok(None) => {}
+    Err(_) => defmt::warn!("Config: flash read failed, latched alarms lost"),
+  }
+  config
+}
+
+// Install the stored link key, bumping its epoch first so this boot's nonces are new
+#[cfg(feature = "comm_crypto")]
+fn load_key<F: NorFlash>(store: &mut ConfigStore<F>) {
+  match store.load_key() {
+    Ok(Some(mut record)) => {
+      record.epoch = record.epoch.wrapping_add(1);
+      if store.save_key(&record).is_err() {
+        // Sealing with a stale epoch could repeat a nonce, so stay without a key
+        defmt::error!("Comm crypto: epoch write failed, link disabled");
+        return;
+      }
+      crypto::install(record);
+    }
+    Ok(None) => defmt::warn!("Comm crypto: no key provisioned, link in clear"),
+    Err(_) => defmt::error!("Comm crypto: flash read failed, link in clear"),
+  }
+}
+
+/// The active configuration
+pub fn current() -> DeviceConfig {
+  ACTIVE.lock(|active| active.borrow().clone())
+}
+
+/// Validate `bytes` and hold them until `commit(id)`; returns the staged configuration
+pub fn stage(id: u8, bytes: &[u8]) -> Result<DeviceConfig, DeviceConfigError> {
+  let config = DeviceConfig::decode(bytes)?;
+  STAGED.lock(|staged| {
+    staged.replace(Some(Staged {
+      id,
+      at: Instant::now(),
+      config: config.clone(),
+    }))
+  });
+  Ok(config)
+}
+
+/// Activate and persist the configuration staged by `SetConfig` `id`; false if there is none
+/// (wrong id, or the commit window has passed)
+pub fn commit(id: u8) -> bool {
+  let config = STAGED.lock(|staged| {
+    let mut staged = staged.borrow_mut();
+    match staged.as_ref() {
+      Some(s) if s.id == id && s.at.elapsed() <= Duration::from_millis(CONFIG_COMMIT_TIMEOUT_MS) => staged.take().map(|s| s.config),
+      _ => None,
+    }
+  });
+  match config {
+    Some(config) => {
+      replace(config);
+      true
+    }
+    None => false,
+  }
+}
+
+/// Replace the active configuration with `bytes` straight away (snapshot import)
+pub fn restore(bytes: &[u8]) -> Result<(), DeviceConfigError> {
+  replace(DeviceConfig::decode(bytes)?);
+  Ok(())
+}
+
+/// Change the active configuration in place and persist it (local UI, application code)
+pub fn update(f: impl FnOnce(&mut DeviceConfig)) {
+  let mut config = current();
+  f(&mut config);
+  replace(config);
+}
+
+fn replace(config: DeviceConfig) {
+  defmt::info!("Config: id {} baud {} flags 0x{:08X} committed", config.device_id, config.baud, config.flags);
+  ACTIVE.lock(|active| active.replace(config));
+  PENDING.fetch_or(SAVE_CONFIG, Ordering::Relaxed);
+  SAVE.signal(());
+}
+
+/// Have `config_task` write the installed link key (`crypto::set_key`)
+#[cfg(feature = "comm_crypto")]
+pub fn save_key() {
+  PENDING.fetch_or(SAVE_KEY, Ordering::Relaxed);
+  SAVE.signal(());
+}
+
+/// Have `config_task` write the scheduler's entries
+pub fn save_schedule() {
+  PENDING.fetch_or(SAVE_SCHEDULE, Ordering::Relaxed);
+  SAVE.signal(());
+}
+
+/// Have `config_task` write the rule set
+pub fn save_rules() {
+  PENDING.fetch_or(SAVE_RULES, Ordering::Relaxed);
+  SAVE.signal(());
+}
+
+/// Have `config_task` write the latched alarms
+pub fn save_alarms() {
+  PENDING.fetch_or(SAVE_ALARMS, Ordering::Relaxed);
+  SAVE.signal(());
+}
+
+/// Whether a configuration, key, schedule, rule set or alarm record is still waiting to be written by `config_task`
+pub fn save_pending() -> bool {
+  // `config_task` writes without yielding, so once it has taken the bits the write is done
+  PENDING.load(Ordering::Relaxed) != 0
+}
+
+/// Write each committed configuration, schedule, rule set or latched alarm change (and, with `comm_crypto`, each new link
+/// key) to flash
+#[embassy_executor::task]
+pub async fn config_task(mut store: ConfigStore<Storage>) {
+  loop {
+    SAVE.wait().await;
+    let pending = PENDING.swap(0, Ordering::Relaxed);
+    if pending & SAVE_CONFIG != 0 && store.save(&current()).is_err() {
+      defmt::error!("Config: flash write failed");
+    }
+    #[cfg(feature = "comm_crypto")]
+    if pending & SAVE_KEY != 0 {
+      if let Some(record) = crypto::key_record() {
+        if store.save_key(&record).is_err() {
+          defmt::error!("Comm crypto: key write failed");
+        }
+      }
+    }
+    if pending & SAVE_SCHEDULE != 0 && store.save_schedule(&scheduler::entries()).is_err() {
+      defmt::error!("Config: schedule write failed");
+    }
+    if pending & SAVE_RULES != 0 && store.save_rules(&rules::rules()).is_err() {
+      defmt::error!("Config: rules write failed");
+    }
+    if pending & SAVE_ALARMS != 0 && store.save_alarms(&alarm::latched()).is_err() {
+      defmt::error!("Config: alarm write failed");
+    }
+  }
+}
@@ -0,0 +1,116 @@
+//! Comm link encryption and authentication (feature `comm_crypto`)
+// Once a key is installed every Comms payload is sealed with AES-128-CCM (`protocol::secure`):
+// `comm::write` seals outgoing messages and `comm::dispatch` opens incoming ones, dropping (and
+// NAKing with `AuthFailed`) anything that is unauthenticated, altered or replayed. Without a key
+// the link stays in clear so a new unit can be provisioned. Replays are tracked for one host
+// session at a time, which suits one host per device: a session must open with the device's
+// current challenge (sent with the NAK), and opening one draws a new challenge, so a recorded
+// session cannot be played back. Messages forwarded by `comms_routing` are passed on sealed
+// (`comm::forward`), so every node on a route shares the key.
+//
+// The key lives in the device configuration flash area as a `KeyRecord` (`config::load` reads
+// it at boot). Its epoch is bumped and written back on every boot and forms the first half of
+// this session's nonce salt, so nonces never repeat under a key even on parts whose random
+// generator is seeded predictably; the other half comes from `common::random`.
+//
+// Comms (handled by `comm::handle_builtin`):
+// - `SetKey` (16-byte key): installs and persists the key, replied with an empty `SetKey` that is
+//   already sealed under the new key. Once a key is set only a sealed `SetKey` gets through, so
+//   changing it needs the current key.
+//
+// AES runs in software: the F446RE and F413ZH have no CRYP unit.
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+use crate::common::random;
+use crate::protocol::message::Message;
+use crate::protocol::secure::{self, CHALLENGE_LEN, DIRECTION_DEVICE, DIRECTION_HOST, Key, KeyRecord, Nonce, ReplayWindow, SecureError};
+
+struct Session {
+  record: KeyRecord,
+  salt: [u8; 8],
+  peer: ReplayWindow,
+}
+
+static SESSION: Mutex<CriticalSectionRawMutex, RefCell<Option<Session>>> = Mutex::new(RefCell::new(None));
+static TX_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// Install a stored key for this boot (`record.epoch` must already be bumped and saved)
+pub fn install(record: KeyRecord) {
+  let mut salt = [0u8; 8];
+  salt[..4].copy_from_slice(&record.epoch.to_le_bytes());
+  salt[4..].copy_from_slice(&random::next_u32().to_le_bytes());
+  SESSION.lock(|session| {
+    session.replace(Some(Session {
+      record,
+      salt,
+      peer: ReplayWindow::new(random::next_u32().to_le_bytes()),
+    }))
+  });
+  defmt::info!("Comm crypto: key installed (epoch {})", record.epoch);
+}
+
+/// True once a key is installed (payloads are sealed)
+pub fn enabled() -> bool {
+  SESSION.lock(|session| session.borrow().is_some())
+}
+
+/// Challenge a new host session must start its salt with (sent with NAK `AuthFailed`)
+pub fn challenge() -> Option<[u8; CHALLENGE_LEN]> {
+  SESSION.lock(|session| session.borrow().as_ref().map(|s| s.peer.challenge()))
+}
+
+/// The installed key record, if any
+pub fn key_record() -> Option<KeyRecord> {
+  SESSION.lock(|session| session.borrow().as_ref().map(|s| s.record))
+}
+
+/// Switch to `key` (from a `SetKey` payload) and have `config_task` persist it
+pub fn set_key(bytes: &[u8]) -> Result<(), SecureError> {
+  let key: Key = bytes.try_into().map_err(|_| SecureError::Malformed)?;
+  let epoch = key_record().map_or(0, |record| record.epoch);
+  install(KeyRecord { key, epoch });
+  crate::service::config::save_key();
+  Ok(())
+}
+
+/// Seal the payload of an outgoing message in place (no-op without a key)
+pub fn seal(msg: &mut Message) -> Result<(), SecureError> {
+  let Some((key, salt)) = SESSION.lock(|session| session.borrow().as_ref().map(|s| (s.record.key, s.salt))) else {
+    return Ok(());
+  };
+  // Stop sealing rather than wrap the counter and reuse a nonce
+  let counter = TX_COUNTER
+    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| c.checked_add(1))
+    .map_err(|_| SecureError::TooLong)?
+    + 1;
+  let nonce = Nonce {
+    direction: DIRECTION_DEVICE,
+    salt,
+    counter,
+  };
+  msg.payload = secure::seal(&key, &nonce, &secure::aad(msg), &msg.payload)?;
+  msg.length = msg.payload.len() as u16;
+  Ok(())
+}
+
+/// Check and decrypt the payload of an incoming message in place (no-op without a key)
+pub fn open(msg: &mut Message) -> Result<(), SecureError> {
+  let Some(key) = SESSION.lock(|session| session.borrow().as_ref().map(|s| s.record.key)) else {
+    return Ok(());
+  };
+  let (nonce, plaintext) = secure::open(&key, &secure::aad(msg), &msg.payload)?;
+  if nonce.direction != DIRECTION_HOST {
+    // A device's frame (our own reflected back, or another node's) is never a request
+    return Err(SecureError::Replay);
+  }
+  // Replaces the challenge if this frame opens a session
+  let next_challenge = random::next_u32().to_le_bytes();
+  SESSION.lock(|session| session.borrow_mut().as_mut().map_or(Ok(()), |s| s.peer.accept(&nonce, next_challenge)))?;
+  msg.payload = plaintext;
+  msg.length = msg.payload.len() as u16;
+  Ok(())
+}
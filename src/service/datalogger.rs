@@ -0,0 +1,105 @@
+//! Data logger: CSV files on the SD card (feature `sdcard`)
+// Appends samples to CSV files in the card's root directory:
+//   ADC.CSV    uptime_ms,channel,raw           (queued by `log_adc` from any task)
+//   TELEM.CSV  uptime_s,reset_cause,...        (`service::telemetry::latest()` every period)
+// A header line is written when a file is created. Samples are queued and written by
+// `datalogger_task`, so callers never block on the card; when the queue is full new samples
+// are dropped and counted (`dropped()`).
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, with_timeout};
+use heapless::String;
+
+use crate::hardware::sdcard::SdStorage;
+use crate::service::telemetry;
+
+/// ADC sample file
+pub const ADC_FILE: &str = "ADC.CSV";
+/// Telemetry file
+pub const TELEMETRY_FILE: &str = "TELEM.CSV";
+
+const ADC_HEADER: &str = "uptime_ms,channel,raw\n";
+const TELEMETRY_HEADER: &str =
+  "uptime_s,reset_cause,stack_high_water,heap_used,fcs_errors,vdda_mv,cs_max_us,idle_pct,serial_rx_max_us,comm_rx_max_us,telemetry_max_us,sleep_pct,current_ua\n";
+const QUEUE_DEPTH: usize = 32;
+// Longest CSV line, including the newline
+const LINE_MAX: usize = 160;
+
+#[derive(Copy, Clone)]
+struct AdcSample {
+  uptime_ms: u64,
+  channel: u8,
+  raw: u16,
+}
+
+static QUEUE: Channel<CriticalSectionRawMutex, AdcSample, QUEUE_DEPTH> = Channel::new();
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+/// Queue an ADC reading for ADC.CSV (non-blocking)
+pub fn log_adc(channel: u8, raw: u16) {
+  let sample = AdcSample {
+    uptime_ms: Instant::now().as_millis(),
+    channel,
+    raw,
+  };
+  if QUEUE.try_send(sample).is_err() {
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+  }
+}
+
+/// Samples lost because the queue was full
+pub fn dropped() -> u32 {
+  DROPPED.load(Ordering::Relaxed)
+}
+
+fn append(storage: &mut SdStorage, file: &str, header: &str, line: &str) {
+  // A missing or empty file gets the header first
+  if storage.size(file).unwrap_or(0) == 0 {
+    storage.append(file, header.as_bytes()).ok();
+  }
+  if let Err(e) = storage.append(file, line.as_bytes()) {
+    defmt::warn!("Datalogger: write to {} failed: {}", file, defmt::Debug2Format(&e));
+  }
+}
+
+/// Write queued ADC samples as they arrive and a telemetry line every `telemetry_period_s`
+#[embassy_executor::task]
+pub async fn datalogger_task(mut storage: SdStorage, telemetry_period_s: u64) {
+  let period = Duration::from_secs(telemetry_period_s);
+  let mut next_telemetry = Instant::now() + period;
+  loop {
+    let wait = next_telemetry.saturating_duration_since(Instant::now());
+    if let Ok(sample) = with_timeout(wait, QUEUE.receive()).await {
+      let mut line: String<LINE_MAX> = String::new();
+      writeln!(line, "{},{},{}", sample.uptime_ms, sample.channel, sample.raw).ok();
+      append(&mut storage, ADC_FILE, ADC_HEADER, &line);
+    }
+    if Instant::now() >= next_telemetry {
+      next_telemetry += period;
+      let t = telemetry::latest();
+      let mut line: String<LINE_MAX> = String::new();
+      writeln!(
+        line,
+        "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+        t.uptime_s,
+        t.reset_cause,
+        t.stack_high_water,
+        t.heap_used,
+        t.fcs_errors,
+        t.vdda_mv,
+        t.cs_max_us,
+        t.idle_pct,
+        t.serial_rx_max_us,
+        t.comm_rx_max_us,
+        t.telemetry_max_us,
+        t.sleep_pct,
+        t.current_ua
+      )
+      .ok();
+      append(&mut storage, TELEMETRY_FILE, TELEMETRY_HEADER, &line);
+    }
+  }
+}
@@ -0,0 +1,139 @@
+//! Firmware update image checks (feature `signed_dfu`)
+// An uploaded update is a signed file (`protocol::image`: firmware binary, then a trailer with
+// an Ed25519ph signature) written to a flash staging region. Before a staged image may be
+// committed (marked bootable), `verify_staged` streams it back from flash and checks the
+// signature against `PUBLIC_KEY`; anything unsigned, truncated, altered or signed with another
+// key is rejected. The transfer and commit steps call this and refuse to commit on `Err`.
+//
+// Reboot-into-update handshake: `reboot_into_update` verifies the staged image, leaves
+// `UPDATE_REQUEST_MAGIC` in the `rtc::BKP_DFU` backup register and resets. The code that
+// installs images at boot checks `update_requested()` and calls `clear_update_request()` once
+// the image is in place (or given up on), so a failed install does not loop. The register keeps
+// its value across resets but not a power loss, which drops a pending request.
+//
+// The public key is baked in at build time from the `DFU_PUBLIC_KEY` environment variable (64
+// hex digits, exported by `build.rs`); a build without one rejects every image. Sign images with
+// the host tool (`cargo run --bin sign-image`).
+
+use core::convert::Infallible;
+use embedded_storage::nor_flash::ReadNorFlash;
+
+use crate::common::cooperative::WORK_CHUNK_LEN;
+use crate::common::system::{self, RebootReason};
+use crate::hardware::rtc::{self, BKP_DFU};
+use crate::protocol::image::{IMAGE_TRAILER_LEN, ImageError, ImageTrailer, ImageVerifier, PUBLIC_KEY_LEN, parse_public_key};
+
+/// Update signing key, from `DFU_PUBLIC_KEY` at build time (None: not set or malformed)
+pub const PUBLIC_KEY: Option<[u8; PUBLIC_KEY_LEN]> = parse_public_key(env!("DFU_PUBLIC_KEY"));
+
+/// Marker in `rtc::BKP_DFU`: a verified update is staged for installation ("UPDT")
+pub const UPDATE_REQUEST_MAGIC: u32 = 0x5444_5055;
+
+// Flash read size while hashing (`verify_staged_sliced` yields every `WORK_CHUNK_LEN` bytes)
+const CHUNK_LEN: usize = 256;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DfuError {
+  /// Firmware built without `DFU_PUBLIC_KEY`
+  NoKey,
+  /// Reading the staging region failed
+  Flash,
+  /// Image rejected
+  Image(ImageError),
+}
+
+impl defmt::Format for DfuError {
+  fn format(&self, f: defmt::Formatter) {
+    match self {
+      DfuError::NoKey => defmt::write!(f, "no public key built in"),
+      DfuError::Flash => defmt::write!(f, "flash read failed"),
+      DfuError::Image(ImageError::Unsigned) => defmt::write!(f, "unsigned image"),
+      DfuError::Image(ImageError::Version) => defmt::write!(f, "unknown trailer version"),
+      DfuError::Image(ImageError::Length) => defmt::write!(f, "length mismatch"),
+      DfuError::Image(ImageError::BadKey) => defmt::write!(f, "invalid public key"),
+      DfuError::Image(ImageError::Signature) => defmt::write!(f, "bad signature"),
+    }
+  }
+}
+
+/// Verify the signed file of `total_len` bytes staged at `offset` in `flash`; returns the
+/// firmware length (without the trailer) if the signature checks out
+pub fn verify_staged<F: ReadNorFlash>(flash: &mut F, offset: u32, total_len: u32) -> Result<u32, DfuError> {
+  report(verify(flash, offset, total_len))
+}
+
+/// `verify_staged` in `WORK_CHUNK_LEN` slices with a yield in between, so that hashing a large
+/// image does not hold up the other tasks (serial RX, comm) for its whole duration
+pub async fn verify_staged_sliced<F: ReadNorFlash>(flash: &mut F, offset: u32, total_len: u32) -> Result<u32, DfuError> {
+  report(verify_sliced(flash, offset, total_len).await)
+}
+
+/// Verify the staged image and reset with an update request for the installer; only returns
+/// (with the error) if the image is rejected
+pub fn reboot_into_update<F: ReadNorFlash>(flash: &mut F, offset: u32, total_len: u32) -> Result<Infallible, DfuError> {
+  verify_staged(flash, offset, total_len)?;
+  let _ = rtc::backup_write(BKP_DFU, UPDATE_REQUEST_MAGIC);
+  defmt::warn!("DFU: rebooting to install update");
+  system::reset(RebootReason::Update)
+}
+
+/// Whether `reboot_into_update` asked for the staged image to be installed
+pub fn update_requested() -> bool {
+  rtc::backup_read(BKP_DFU) == Some(UPDATE_REQUEST_MAGIC)
+}
+
+/// Drop the update request (after installing the image, or giving up on it)
+pub fn clear_update_request() {
+  let _ = rtc::backup_write(BKP_DFU, 0);
+}
+
+fn report(result: Result<u32, DfuError>) -> Result<u32, DfuError> {
+  match result {
+    Ok(len) => defmt::info!("DFU: staged image verified ({} bytes)", len),
+    Err(e) => defmt::warn!("DFU: staged image rejected: {}", e),
+  }
+  result
+}
+
+fn verify<F: ReadNorFlash>(flash: &mut F, offset: u32, total_len: u32) -> Result<u32, DfuError> {
+  let (trailer, key) = read_trailer(flash, offset, total_len)?;
+  let mut verifier = ImageVerifier::new();
+  let mut done = 0;
+  while done < trailer.image_len {
+    done += hash_chunk(flash, offset, done, trailer.image_len, &mut verifier)?;
+  }
+  verifier.finish(&trailer, &key).map_err(DfuError::Image)?;
+  Ok(trailer.image_len)
+}
+
+async fn verify_sliced<F: ReadNorFlash>(flash: &mut F, offset: u32, total_len: u32) -> Result<u32, DfuError> {
+  let (trailer, key) = read_trailer(flash, offset, total_len)?;
+  let mut verifier = ImageVerifier::new();
+  let mut done = 0;
+  while done < trailer.image_len {
+    done += hash_chunk(flash, offset, done, trailer.image_len, &mut verifier)?;
+    crate::yield_every!(WORK_CHUNK_LEN / CHUNK_LEN);
+  }
+  verifier.finish(&trailer, &key).map_err(DfuError::Image)?;
+  Ok(trailer.image_len)
+}
+
+// Trailer of the staged file and the key to check it with
+fn read_trailer<F: ReadNorFlash>(flash: &mut F, offset: u32, total_len: u32) -> Result<(ImageTrailer, [u8; PUBLIC_KEY_LEN]), DfuError> {
+  let key = PUBLIC_KEY.ok_or(DfuError::NoKey)?;
+  if (total_len as usize) < IMAGE_TRAILER_LEN {
+    return Err(DfuError::Image(ImageError::Unsigned));
+  }
+  let mut trailer = [0u8; IMAGE_TRAILER_LEN];
+  flash.read(offset + total_len - IMAGE_TRAILER_LEN as u32, &mut trailer).map_err(|_| DfuError::Flash)?;
+  Ok((ImageTrailer::decode(&trailer, total_len).map_err(DfuError::Image)?, key))
+}
+
+// Hash the image bytes from `done` (one chunk at most); returns how many
+fn hash_chunk<F: ReadNorFlash>(flash: &mut F, offset: u32, done: u32, image_len: u32, verifier: &mut ImageVerifier) -> Result<u32, DfuError> {
+  let mut chunk = [0u8; CHUNK_LEN];
+  let len = (image_len - done).min(CHUNK_LEN as u32) as usize;
+  flash.read(offset + done, &mut chunk[..len]).map_err(|_| DfuError::Flash)?;
+  verifier.update(&chunk[..len]);
+  Ok(len as u32)
+}
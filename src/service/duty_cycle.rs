@@ -0,0 +1,167 @@
+//! Scheduled duty cycling of the comm link for battery nodes
+// The link is only used during periodic windows (e.g. 20 s every 15 min). Once `start` makes a
+// schedule active, `comm::send_pending` writes nothing between windows: messages queued with
+// `comm::send` and the alarm/telemetry/sensor notifications move to an outbox (while it has room;
+// their queues and channels hold the rest), and the outbox goes out ahead of everything else when
+// the next window opens. `comm::tx_idle` counts the outbox, so a reboot waits for it (up to
+// `system::REBOOT_FLUSH_MS`, then logs what it drops). The rest of the time the application sleeps (the executor idles in WFE while
+// every task awaits a timer). Windows are aligned to a wall clock that is synced from the RTC or
+// a time-sync message via `sync_clock`, so all nodes of a network wake together.
+//
+// Typical loop of the task owning the link:
+//   duty_cycle::start(SCHEDULE);
+//   loop {
+//     duty_cycle::wait_open(&SCHEDULE).await;
+//     power.set_enabled(true);
+//     while SCHEDULE.is_open(duty_cycle::now_s()) { comm::send_pending(&mut tx); /* handle comm::read() */ }
+//     power.set_enabled(false);
+//   }
+// With `comm::tx_task` owning TX, only the power switching is left to the application.
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Instant, Timer};
+
+use crate::service::comm::{self, Message};
+
+/// Messages held between windows
+pub const DUTY_OUTBOX_DEPTH: usize = 8;
+
+// Outbound messages waiting for the next window
+static OUTBOX: Channel<CriticalSectionRawMutex, Message, DUTY_OUTBOX_DEPTH> = Channel::new();
+static OUTBOX_DROPS: AtomicU32 = AtomicU32::new(0);
+
+// Schedule `comm::send_pending` follows (None: duty cycling off, the link always open)
+static ACTIVE: Mutex<CriticalSectionRawMutex, Cell<Option<DutySchedule>>> = Mutex::new(Cell::new(None));
+
+// Wall clock minus uptime, in seconds (set by `sync_clock`)
+static CLOCK_OFFSET_S: AtomicU32 = AtomicU32::new(0);
+
+/// Link window schedule: open for `window_s` seconds every `period_s` seconds, starting `offset_s` into each period
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct DutySchedule {
+  pub period_s: u32,
+  pub window_s: u32,
+  pub offset_s: u32,
+}
+
+impl DutySchedule {
+  pub const fn new(period_s: u32, window_s: u32, offset_s: u32) -> Self {
+    Self { period_s, window_s, offset_s }
+  }
+
+  /// Seconds since the start of the current window's period slot
+  fn phase(&self, now_s: u32) -> u32 {
+    let period = self.period_s.max(1) as u64;
+    ((now_s as u64 % period + period - self.offset_s as u64 % period) % period) as u32
+  }
+
+  /// Whether the link window is open at wall-clock time `now_s`
+  pub fn is_open(&self, now_s: u32) -> bool {
+    self.window_s >= self.period_s || self.phase(now_s) < self.window_s
+  }
+
+  /// Seconds until the next window opens (0 if open now)
+  pub fn until_open(&self, now_s: u32) -> u32 {
+    if self.is_open(now_s) { 0 } else { self.period_s - self.phase(now_s) }
+  }
+
+  /// Seconds until the current window closes (0 if closed)
+  pub fn until_close(&self, now_s: u32) -> u32 {
+    if self.window_s >= self.period_s {
+      u32::MAX
+    } else if self.is_open(now_s) {
+      self.window_s - self.phase(now_s)
+    } else {
+      0
+    }
+  }
+}
+
+/// Hook for powering a link's transceiver up/down around windows (UART level shifter, radio, ...)
+pub trait LinkPower {
+  fn set_enabled(&mut self, enabled: bool);
+}
+
+/// `LinkPower` for links that stay powered (e.g. the ST-LINK VCP)
+pub struct AlwaysOn;
+
+impl LinkPower for AlwaysOn {
+  fn set_enabled(&mut self, _enabled: bool) {}
+}
+
+/// Wall-clock seconds (uptime until `sync_clock` is called)
+pub fn now_s() -> u32 {
+  (Instant::now().as_secs() as u32).wrapping_add(CLOCK_OFFSET_S.load(Ordering::Relaxed))
+}
+
+/// Align the schedule clock to wall-clock time `wall_s` (from the RTC or a time-sync message)
+pub fn sync_clock(wall_s: u32) {
+  CLOCK_OFFSET_S.store(wall_s.wrapping_sub(Instant::now().as_secs() as u32), Ordering::Relaxed);
+}
+
+/// Hold the comm link's outgoing traffic outside the windows of `schedule`
+pub fn start(schedule: DutySchedule) {
+  defmt::info!("Duty cycle: {}", schedule);
+  ACTIVE.lock(|active| active.set(Some(schedule)));
+}
+
+/// Stop duty cycling: the link is always open again (the outbox goes out with the next `comm::send_pending`)
+pub fn stop() {
+  ACTIVE.lock(|active| active.set(None));
+}
+
+/// Whether duty cycling is active and its window is closed now (nothing may be written)
+pub fn is_closed() -> bool {
+  ACTIVE.lock(|active| active.get()).is_some_and(|schedule| !schedule.is_open(now_s()))
+}
+
+/// Sleep until the next window of `schedule` opens (returns immediately if open)
+pub async fn wait_open(schedule: &DutySchedule) {
+  loop {
+    let wait = schedule.until_open(now_s());
+    if wait == 0 {
+      return;
+    }
+    // Re-check after waking in case the clock was re-synced while sleeping
+    Timer::after_secs(wait as u64).await;
+  }
+}
+
+/// Hold a message for the next window; returns it back if the outbox is full
+pub fn queue(msg: Message) -> Result<(), Message> {
+  OUTBOX.try_send(msg).map_err(|e| {
+    OUTBOX_DROPS.fetch_add(1, Ordering::Relaxed);
+    match e {
+      embassy_sync::channel::TrySendError::Full(msg) => msg,
+    }
+  })
+}
+
+/// Number of messages waiting for a window
+pub fn pending() -> usize {
+  OUTBOX.len()
+}
+
+/// Whether the outbox can take another message
+pub fn has_room() -> bool {
+  !OUTBOX.is_full()
+}
+
+/// Messages rejected because the outbox was full
+pub fn dropped() -> u32 {
+  OUTBOX_DROPS.load(Ordering::Relaxed)
+}
+
+/// Send every buffered message (`comm::send_pending` does once the window is open); returns how many were sent
+pub fn flush<W: embedded_io::Write>(serial: &mut W) -> usize {
+  let mut sent = 0;
+  while let Ok(msg) = OUTBOX.try_receive() {
+    comm::write(serial, &msg);
+    sent += 1;
+  }
+  sent
+}
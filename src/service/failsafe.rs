@@ -0,0 +1,194 @@
+//! Fail-safe outputs: registered pins are driven to a defined safe level when something goes wrong
+// Any task (or interrupt) can trip it; the triggers wired up here are:
+// - link-down: `comm::link_state()` falls to Down (needs `comm::link_monitor_task`),
+// - watchdog: the IWDG is about to expire because nothing pets it (`watchdog::wdt_remaining_hint`),
+// - hard fault: `hardware::hardfault` applies it before the delayed reset,
+// - brown-out: the PVD interrupt (`enable_brownout`) reports VDD below the threshold.
+// `failsafe_task` polls the first two. The pin table is lock-free atomics written straight to
+// the GPIO BSRR registers, so it also works from the fault handler. Tasks that own the outputs
+// keep them; once the cause is gone (e.g. the host is back) they drive them again as usual.
+//
+// After any reset the pins are inputs again, so the hardware should also pull relay drivers to
+// their safe level.
+
+use core::sync::atomic::{AtomicU8, AtomicU32, Ordering};
+use embassy_stm32::gpio::{Level, Pin};
+use embassy_stm32::interrupt;
+use embassy_stm32::interrupt::InterruptExt;
+use embassy_time::Timer;
+
+use crate::diagnostics::eventlog;
+use crate::hardware::watchdog;
+use crate::service::comm::{self, LinkState};
+
+/// Number of outputs that can be registered
+pub const FAILSAFE_MAX_OUTPUTS: usize = 8;
+/// `failsafe_task` polling interval
+pub const FAILSAFE_POLL_MS: u64 = 50;
+/// Trip when the watchdog is this close to resetting the MCU
+pub const WATCHDOG_MARGIN_MS: u64 = 100;
+
+/// Why the safe state was applied
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum Trigger {
+  /// Requested by the application
+  Manual = 1,
+  /// Host link went down
+  LinkDown = 2,
+  /// Watchdog about to expire
+  Watchdog = 3,
+  /// Hard fault, before the reset
+  HardFault = 4,
+  /// Supply below the PVD threshold
+  BrownOut = 5,
+}
+
+impl Trigger {
+  fn from_u8(value: u8) -> Option<Self> {
+    match value {
+      1 => Some(Trigger::Manual),
+      2 => Some(Trigger::LinkDown),
+      3 => Some(Trigger::Watchdog),
+      4 => Some(Trigger::HardFault),
+      5 => Some(Trigger::BrownOut),
+      _ => None,
+    }
+  }
+}
+
+/// PVD threshold (PWR_CR.PLS); the supply falling below it counts as a brown-out
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum PvdThreshold {
+  V2_0 = 0,
+  V2_1 = 1,
+  V2_3 = 2,
+  V2_5 = 3,
+  V2_6 = 4,
+  V2_7 = 5,
+  V2_8 = 6,
+  V2_9 = 7,
+}
+
+// GPIO ports are 0x400 apart from GPIOA; BSRR sets bit n and resets bit n + 16
+const GPIOA_BASE: u32 = 0x4002_0000;
+const GPIO_PORT_STRIDE: u32 = 0x400;
+const GPIO_BSRR: u32 = 0x18;
+// PVD: PWR clock enable, PWR_CR / PWR_CSR, EXTI line 16
+const RCC_APB1ENR: *mut u32 = 0x4002_3840 as *mut u32;
+const RCC_APB1ENR_PWREN: u32 = 1 << 28;
+const PWR_CR: *mut u32 = 0x4000_7000 as *mut u32;
+const PWR_CR_PVDE: u32 = 1 << 4;
+const PWR_CR_PLS_SHIFT: u32 = 5;
+const PWR_CSR: *const u32 = 0x4000_7004 as *const u32;
+const PWR_CSR_PVDO: u32 = 1 << 2;
+const EXTI_IMR: *mut u32 = 0x4001_3C00 as *mut u32;
+const EXTI_RTSR: *mut u32 = 0x4001_3C08 as *mut u32;
+const EXTI_PR: *mut u32 = 0x4001_3C14 as *mut u32;
+const EXTI_PVD: u32 = 1 << 16;
+
+// Registered outputs: USED | safe level << 16 | port << 8 | pin (0 = free slot)
+const USED: u32 = 1 << 31;
+const SAFE_HIGH: u32 = 1 << 16;
+static OUTPUTS: [AtomicU32; FAILSAFE_MAX_OUTPUTS] = [const { AtomicU32::new(0) }; FAILSAFE_MAX_OUTPUTS];
+// Most recent trigger (`Trigger` as u8, 0 = none) and number of times the safe state was applied
+static LAST_TRIGGER: AtomicU8 = AtomicU8::new(0);
+static TRIP_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Error from `register`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct TableFull;
+
+/// Give `pin` a safe level (register it before handing the pin to its `Output`)
+pub fn register<P: Pin>(pin: &P, safe: Level) -> Result<(), TableFull> {
+  let level = if safe == Level::High { SAFE_HIGH } else { 0 };
+  let entry = USED | level | (pin.port() as u32) << 8 | pin.pin() as u32;
+  for slot in &OUTPUTS {
+    if slot.compare_exchange(0, entry, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+      return Ok(());
+    }
+  }
+  Err(TableFull)
+}
+
+/// Drive every registered output to its safe level (callable from any context, including faults)
+pub fn apply(trigger: Trigger) {
+  for slot in &OUTPUTS {
+    let entry = slot.load(Ordering::Acquire);
+    if entry & USED == 0 {
+      continue;
+    }
+    let port = (entry >> 8) & 0xFF;
+    let pin = entry & 0xFF;
+    let bit = if entry & SAFE_HIGH != 0 { 1 << pin } else { 1 << (pin + 16) };
+    let bsrr = (GPIOA_BASE + port * GPIO_PORT_STRIDE + GPIO_BSRR) as *mut u32;
+    // SAFETY: BSRR writes are atomic per bit and only touch the registered pin
+    unsafe { bsrr.write_volatile(bit) };
+  }
+  LAST_TRIGGER.store(trigger as u8, Ordering::Relaxed);
+  TRIP_COUNT.fetch_add(1, Ordering::Relaxed);
+  eventlog::record(eventlog::EVENT_FAILSAFE, trigger as u32);
+}
+
+/// Most recent reason the safe state was applied
+pub fn last_trigger() -> Option<Trigger> {
+  Trigger::from_u8(LAST_TRIGGER.load(Ordering::Relaxed))
+}
+
+/// Number of times the safe state was applied since boot
+pub fn trip_count() -> u32 {
+  TRIP_COUNT.load(Ordering::Relaxed)
+}
+
+/// Watch VDD with the PVD and apply the safe state when it falls below `threshold`
+pub fn enable_brownout(threshold: PvdThreshold) {
+  // SAFETY: PWR_CR.PVDE/PLS, EXTI line 16 and the PVD interrupt are not used elsewhere in the crate
+  unsafe {
+    RCC_APB1ENR.write_volatile(RCC_APB1ENR_PWREN | RCC_APB1ENR.read_volatile());
+    let cr = PWR_CR.read_volatile() & !(0b111 << PWR_CR_PLS_SHIFT);
+    PWR_CR.write_volatile(cr | (threshold as u32) << PWR_CR_PLS_SHIFT | PWR_CR_PVDE);
+    // PVDO rises when VDD drops below the threshold
+    EXTI_RTSR.write_volatile(EXTI_RTSR.read_volatile() | EXTI_PVD);
+    EXTI_PR.write_volatile(EXTI_PVD);
+    EXTI_IMR.write_volatile(EXTI_IMR.read_volatile() | EXTI_PVD);
+    interrupt::PVD.enable();
+  }
+  defmt::info!("Failsafe: brown-out detection at {}", threshold);
+}
+
+#[interrupt]
+fn PVD() {
+  // SAFETY: write-one-to-clear of the PVD pending bit; PWR_CSR is read-only here
+  let low = unsafe {
+    EXTI_PR.write_volatile(EXTI_PVD);
+    PWR_CSR.read_volatile() & PWR_CSR_PVDO != 0
+  };
+  if low {
+    apply(Trigger::BrownOut);
+  }
+}
+
+/// Async task: applies the safe state when the host link goes down or the watchdog is about to
+/// expire (once per occurrence)
+#[embassy_executor::task]
+pub async fn failsafe_task() {
+  let mut link = comm::link_state();
+  let mut watchdog_tripped = false;
+  loop {
+    Timer::after_millis(FAILSAFE_POLL_MS).await;
+    let state = comm::link_state();
+    if state != link {
+      link = state;
+      if state == LinkState::Down {
+        defmt::warn!("Failsafe: host link down, outputs to safe state");
+        apply(Trigger::LinkDown);
+      }
+    }
+    let expiring = watchdog::wdt_remaining_hint().is_some_and(|left| left.as_millis() < WATCHDOG_MARGIN_MS);
+    if expiring && !watchdog_tripped {
+      defmt::warn!("Failsafe: watchdog about to expire, outputs to safe state");
+      apply(Trigger::Watchdog);
+    }
+    watchdog_tripped = expiring;
+  }
+}
@@ -0,0 +1,146 @@
+//! Filesystem: littlefs2 on NOR flash (feature `fs`)
+// littlefs gives configuration files and logs power-loss resilience (copy-on-write metadata,
+// no torn files after a reset) and wear leveling across the region, instead of raw block writes.
+// `FlashBlocks` presents any `NorFlash` as littlefs blocks of its erase size:
+//   - internal flash: `InternalBlocks` over `flash::Sectors::filesystem()` (128 KB blocks)
+//   - external flash: `FlashBlocks<QspiFlash<QUADSPI>, N>` (4 KB blocks, F413ZH)
+// `Fs` wraps the mounted filesystem with whole-file helpers on path strings; `filesystem()`
+// gives the full littlefs2 API (directories, attributes, open file handles).
+
+use embedded_storage::nor_flash::NorFlash;
+use heapless::Vec;
+use littlefs2::consts::{U1, U256};
+use littlefs2::driver::Storage;
+use littlefs2::fs::{Allocation, File, Filesystem};
+use littlefs2::io::{Error, Result};
+use littlefs2::path::PathBuf;
+
+use crate::board::BoardConfig;
+use crate::hardware::flash::{SECTOR_SIZE, Sectors};
+
+/// Erase cycles before littlefs moves a metadata block (wear leveling)
+pub const FS_BLOCK_CYCLES: isize = 500;
+
+/// Any `NorFlash` with byte-sized reads and writes as `BLOCKS` littlefs blocks of `F::ERASE_SIZE`
+pub struct FlashBlocks<F: NorFlash, const BLOCKS: usize> {
+  flash: F,
+}
+
+impl<F: NorFlash, const BLOCKS: usize> FlashBlocks<F, BLOCKS> {
+  pub fn new(flash: F) -> Self {
+    Self { flash }
+  }
+
+  /// Hand the flash back (unmount first)
+  pub fn release(self) -> F {
+    self.flash
+  }
+}
+
+impl<F: NorFlash, const BLOCKS: usize> Storage for FlashBlocks<F, BLOCKS> {
+  const READ_SIZE: usize = 16;
+  const WRITE_SIZE: usize = 16;
+  const BLOCK_SIZE: usize = F::ERASE_SIZE;
+  const BLOCK_COUNT: usize = BLOCKS;
+  const BLOCK_CYCLES: isize = FS_BLOCK_CYCLES;
+  type CACHE_SIZE = U256;
+  type LOOKAHEAD_SIZE = U1;
+
+  fn read(&mut self, off: usize, buf: &mut [u8]) -> Result<usize> {
+    self.flash.read(off as u32, buf).map_err(|_| Error::IO)?;
+    Ok(buf.len())
+  }
+
+  fn write(&mut self, off: usize, data: &[u8]) -> Result<usize> {
+    self.flash.write(off as u32, data).map_err(|_| Error::IO)?;
+    Ok(data.len())
+  }
+
+  fn erase(&mut self, off: usize, len: usize) -> Result<usize> {
+    self.flash.erase(off as u32, (off + len) as u32).map_err(|_| Error::IO)?;
+    Ok(len)
+  }
+}
+
+/// The board's internal filesystem region (`BoardConfig::FS_STORAGE_START`/`FS_STORAGE_SIZE`)
+pub type InternalBlocks = FlashBlocks<Sectors, { BoardConfig::FS_STORAGE_SIZE / SECTOR_SIZE }>;
+
+impl InternalBlocks {
+  pub fn internal() -> Self {
+    Self::new(Sectors::filesystem())
+  }
+}
+
+fn path(name: &str) -> Result<PathBuf> {
+  PathBuf::try_from(name).map_err(|_| Error::INVALID)
+}
+
+/// Mounted littlefs filesystem
+pub struct Fs<'a, S: Storage> {
+  fs: Filesystem<'a, S>,
+}
+
+impl<'a, S: Storage> Fs<'a, S> {
+  /// Mount `storage`, formatting it first if it holds no filesystem (first boot or corrupt region)
+  pub fn mount(alloc: &'a mut Allocation<S>, storage: &'a mut S) -> Result<Self> {
+    if !Filesystem::is_mountable(storage) {
+      defmt::warn!("FS: no filesystem found, formatting");
+      Filesystem::format(storage)?;
+    }
+    let fs = Filesystem::mount(alloc, storage)?;
+    defmt::info!("FS: mounted, {} bytes free", fs.available_space().unwrap_or(0));
+    Ok(Self { fs })
+  }
+
+  /// Whole contents of `name` (error if larger than `N`)
+  pub fn read<const N: usize>(&self, name: &str) -> Result<Vec<u8, N>> {
+    self.fs.read(&path(name)?)
+  }
+
+  /// Replace `name` with `data`; the old contents stay intact until the new file is committed
+  pub fn write(&self, name: &str, data: &[u8]) -> Result<()> {
+    self.fs.write(&path(name)?, data)
+  }
+
+  /// Append `data` to `name` (created if missing)
+  pub fn append(&self, name: &str, data: &[u8]) -> Result<()> {
+    self.fs.open_file_with_options_and_then(
+      |options| options.write(true).create(true).append(true),
+      &path(name)?,
+      |file| {
+        file.write(data)?;
+        Ok(())
+      },
+    )
+  }
+
+  /// Open `name` for reading and writing (created if missing) and run `f` on the handle
+  pub fn open<R>(&self, name: &str, f: impl FnOnce(&File<'_, '_, S>) -> Result<R>) -> Result<R> {
+    self
+      .fs
+      .open_file_with_options_and_then(|options| options.read(true).write(true).create(true), &path(name)?, f)
+  }
+
+  /// Atomically rename `from` to `to`, replacing `to` if it exists
+  pub fn rename(&self, from: &str, to: &str) -> Result<()> {
+    self.fs.rename(&path(from)?, &path(to)?)
+  }
+
+  pub fn remove(&self, name: &str) -> Result<()> {
+    self.fs.remove(&path(name)?)
+  }
+
+  pub fn exists(&self, name: &str) -> bool {
+    path(name).is_ok_and(|p| self.fs.exists(&p))
+  }
+
+  /// Size of `name` in bytes
+  pub fn size(&self, name: &str) -> Result<usize> {
+    Ok(self.fs.metadata(&path(name)?)?.len())
+  }
+
+  /// The full littlefs2 API
+  pub fn filesystem(&self) -> &Filesystem<'a, S> {
+    &self.fs
+  }
+}
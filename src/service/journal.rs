@@ -0,0 +1,331 @@
+//! Flash journal for outbound messages across link outages and resets
+// Telemetry/alarm messages that cannot be sent while the link is down are appended to flash
+// and replayed in order once it comes back, so nothing is lost to an outage or a reboot.
+// Each record gets a sequence number that also sets the message id (mapped onto 1..=255, as id 0
+// means unassigned), letting the receiver suppress duplicates when a replay is interrupted and
+// repeated.
+//
+// `comm::send_pending` offers alarm and telemetry notifications to `hold` while the link is down
+// (`comm::link_monitor_task`); the application task owning the region runs `run`, which journals
+// them and replays them through `comm::send` once the link is back. A replayed record is marked
+// delivered only once the TX owner has written it (`comm::tx_idle`) with the link still up, so a
+// reset or outage in between replays it again (the receiver drops the copy by its id). What
+// `hold` cannot queue while `run` is busy is dropped and counted (`dropped`), never written out
+// of order onto a down link.
+//
+// Record layout (appended at increasing offsets from the start of the region):
+//   len: u16 | seq: u16 | encoded message [len] | commit: u8 | state: u8
+// `commit` is programmed after the body so a record torn by a reset is skipped, and `state`
+// is cleared in place when the record is delivered (1 -> 0 bits need no erase). The region
+// is erased only once it is full and every record has been delivered.
+//
+// Works on any byte-writable `NorFlash` (e.g. `hardware::flash::Storage`, or an external
+// `hardware::qspi_flash::QspiFlash` for a much larger backlog); the journal owns
+// the region, so do not share it with other flash users.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Timer, with_timeout};
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::service::comm::{self, COMMS_HEADER_LEN, COMMS_MAX_PAYLOAD, COMMS_TX_POLL_MS, CommsFrameBuf, LinkState, Message};
+
+/// Bytes added to each encoded message (len, seq, commit, state)
+pub const JOURNAL_RECORD_OVERHEAD: u32 = 6;
+/// Messages `hold` can take before `run` journals them
+pub const JOURNAL_QUEUE_DEPTH: usize = 4;
+/// Longest `run` waits before checking whether the link is back
+pub const JOURNAL_POLL_MS: u64 = 250;
+
+const HEADER_LEN: u32 = 4;
+const COMMIT: u8 = 0xA5;
+const PENDING: u8 = 0xFF;
+const DELIVERED: u8 = 0x00;
+const ERASED_LEN: u16 = 0xFFFF;
+
+static OUTBOX: Channel<CriticalSectionRawMutex, Message, JOURNAL_QUEUE_DEPTH> = Channel::new();
+// `run` is active, and has messages the host has not had yet
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static BACKLOG: AtomicBool = AtomicBool::new(false);
+static DROPS: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum JournalError<E> {
+  Flash(E),
+  /// No room left and records are still pending delivery
+  Full,
+}
+
+impl<E> From<E> for JournalError<E> {
+  fn from(e: E) -> Self {
+    JournalError::Flash(e)
+  }
+}
+
+/// Location of a journaled message, used to mark it delivered
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct JournalEntry {
+  pub offset: u32,
+  pub len: u16,
+  pub seq: u16,
+}
+
+impl JournalEntry {
+  fn commit_offset(&self) -> u32 {
+    self.offset + HEADER_LEN + self.len as u32
+  }
+
+  fn end(&self) -> u32 {
+    self.offset + JOURNAL_RECORD_OVERHEAD + self.len as u32
+  }
+}
+
+enum Slot {
+  /// Erased flash: end of the journal
+  End,
+  /// Unreadable header (torn length): nothing may be appended past this point
+  Corrupt,
+  Record {
+    entry: JournalEntry,
+    committed: bool,
+    state: u8,
+  },
+}
+
+/// Append-only message journal over a flash region
+pub struct Journal<F: NorFlash> {
+  flash: F,
+  size: u32,
+  // First record that may still be pending
+  head: u32,
+  // Next append offset
+  tail: u32,
+  next_seq: u16,
+}
+
+impl<F: NorFlash> Journal<F> {
+  /// Open the journal on the first `size` bytes of `flash`, picking up records from before a reset
+  pub fn open(flash: F, size: u32) -> Result<Self, F::Error> {
+    const { assert!(F::WRITE_SIZE == 1, "journal needs byte-writable flash") };
+    let mut journal = Self {
+      flash,
+      size,
+      head: 0,
+      tail: 0,
+      next_seq: 0,
+    };
+    journal.scan()?;
+    defmt::info!("Journal: {} pending, {} of {} bytes used", journal.pending()?, journal.tail, journal.size);
+    Ok(journal)
+  }
+
+  /// Find the append offset and next sequence number
+  fn scan(&mut self) -> Result<(), F::Error> {
+    let mut offset = 0;
+    loop {
+      match self.slot(offset)? {
+        Slot::End => break,
+        Slot::Corrupt => {
+          offset = self.size;
+          break;
+        }
+        Slot::Record { entry, .. } => {
+          self.next_seq = entry.seq.wrapping_add(1);
+          offset = entry.end();
+        }
+      }
+    }
+    self.tail = offset;
+    Ok(())
+  }
+
+  fn slot(&mut self, offset: u32) -> Result<Slot, F::Error> {
+    if offset + HEADER_LEN > self.size {
+      return Ok(Slot::End);
+    }
+    let mut header = [0u8; HEADER_LEN as usize];
+    self.flash.read(offset, &mut header)?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let seq = u16::from_le_bytes([header[2], header[3]]);
+    if len == ERASED_LEN {
+      return Ok(Slot::End);
+    }
+    let entry = JournalEntry { offset, len, seq };
+    if len as usize > COMMS_HEADER_LEN + COMMS_MAX_PAYLOAD || entry.end() > self.size {
+      return Ok(Slot::Corrupt);
+    }
+    let mut trailer = [0u8; 2];
+    self.flash.read(entry.commit_offset(), &mut trailer)?;
+    Ok(Slot::Record {
+      entry,
+      committed: trailer[0] == COMMIT,
+      state: trailer[1],
+    })
+  }
+
+  /// Journal `msg` for later delivery; returns its sequence number (`message_id` gives its id)
+  pub fn append(&mut self, msg: &Message) -> Result<u16, JournalError<F::Error>> {
+    let mut msg = msg.clone();
+    msg.id = message_id(self.next_seq);
+    let mut body = CommsFrameBuf::new();
+    msg.encode(&mut body);
+
+    let need = JOURNAL_RECORD_OVERHEAD + body.len() as u32;
+    if self.tail + need > self.size {
+      // Reclaim the region once everything in it has been delivered
+      if self.pending()? > 0 {
+        return Err(JournalError::Full);
+      }
+      self.clear()?;
+      if need > self.size {
+        return Err(JournalError::Full);
+      }
+    }
+
+    let seq = self.next_seq;
+    let mut header = [0u8; HEADER_LEN as usize];
+    header[..2].copy_from_slice(&(body.len() as u16).to_le_bytes());
+    header[2..].copy_from_slice(&seq.to_le_bytes());
+    self.flash.write(self.tail, &header)?;
+    self.flash.write(self.tail + HEADER_LEN, &body)?;
+    self.flash.write(self.tail + HEADER_LEN + body.len() as u32, &[COMMIT])?;
+
+    self.tail += need;
+    self.next_seq = seq.wrapping_add(1);
+    Ok(seq)
+  }
+
+  /// Oldest message not yet delivered
+  pub fn next_pending(&mut self) -> Result<Option<(JournalEntry, Message)>, F::Error> {
+    let mut offset = self.head;
+    while offset < self.tail {
+      let Slot::Record { entry, committed, state } = self.slot(offset)? else {
+        break;
+      };
+      if committed && state == PENDING {
+        match Message::parse(&self.read_body(&entry)?) {
+          Ok(msg) => {
+            self.head = entry.offset;
+            return Ok(Some((entry, msg)));
+          }
+          // Undecodable record: retire it so it does not block the queue
+          Err(_) => self.mark_delivered(&entry)?,
+        }
+      }
+      offset = entry.end();
+    }
+    self.head = self.tail;
+    Ok(None)
+  }
+
+  fn read_body(&mut self, entry: &JournalEntry) -> Result<CommsFrameBuf, F::Error> {
+    let mut body = CommsFrameBuf::new();
+    body.resize(entry.len as usize, 0).ok();
+    self.flash.read(entry.offset + HEADER_LEN, &mut body)?;
+    Ok(body)
+  }
+
+  /// Record that `entry` reached the peer (e.g. after its ACK)
+  pub fn mark_delivered(&mut self, entry: &JournalEntry) -> Result<(), F::Error> {
+    self.flash.write(entry.commit_offset() + 1, &[DELIVERED])
+  }
+
+  /// Number of messages waiting for delivery
+  pub fn pending(&mut self) -> Result<usize, F::Error> {
+    let mut count = 0;
+    let mut offset = self.head;
+    while let Slot::Record { entry, committed, state } = self.slot(offset)? {
+      if committed && state == PENDING {
+        count += 1;
+      }
+      offset = entry.end();
+    }
+    Ok(count)
+  }
+
+  /// Send every pending message in order, marking each delivered once written; returns how many were sent
+  ///
+  /// Use `next_pending` / `mark_delivered` instead when delivery should wait for an acknowledgement.
+  pub fn replay<W: embedded_io::Write>(&mut self, serial: &mut W) -> Result<usize, F::Error> {
+    let mut sent = 0;
+    while let Some((entry, msg)) = self.next_pending()? {
+      comm::write(serial, &msg);
+      self.mark_delivered(&entry)?;
+      sent += 1;
+    }
+    Ok(sent)
+  }
+
+  /// Erase the region, dropping every record
+  pub fn clear(&mut self) -> Result<(), F::Error> {
+    self.flash.erase(0, self.size)?;
+    self.head = 0;
+    self.tail = 0;
+    Ok(())
+  }
+}
+
+/// Message id of the record with sequence number `seq` (1..=255: id 0 is never a duplicate)
+pub fn message_id(seq: u16) -> u8 {
+  (seq % 255) as u8 + 1
+}
+
+/// Take `msg` for the journal instead of sending it now: while the link is down, or while older
+/// messages are still waiting (so the host gets them in order). False if `run` is not going:
+/// send it now. When `run`'s queue is full the message is dropped (counted in `dropped`).
+pub fn hold(msg: &Message) -> bool {
+  if !RUNNING.load(Ordering::Relaxed) || (comm::link_state() == LinkState::Up && !BACKLOG.load(Ordering::Relaxed)) {
+    return false;
+  }
+  if OUTBOX.try_send(msg.clone()).is_err() {
+    DROPS.fetch_add(1, Ordering::Relaxed);
+    defmt::warn!("Journal: queue full, message dropped");
+  }
+  true
+}
+
+/// Messages `hold` dropped because `run` had not journaled the earlier ones yet
+pub fn dropped() -> u32 {
+  DROPS.load(Ordering::Relaxed)
+}
+
+/// Journal what `hold` takes and replay it in order through `comm::send` whenever the link is up
+/// (await it from the application task that owns the journal's region)
+pub async fn run<F: NorFlash>(mut journal: Journal<F>) -> ! {
+  BACKLOG.store(journal.pending().map_or(true, |count| count > 0), Ordering::Relaxed);
+  RUNNING.store(true, Ordering::Relaxed);
+  loop {
+    while let Ok(msg) = OUTBOX.try_receive() {
+      store(&mut journal, &msg);
+    }
+    if comm::link_state() == LinkState::Up {
+      match journal.next_pending() {
+        Ok(Some((entry, msg))) => {
+          comm::send(msg).await;
+          while !comm::tx_idle() {
+            Timer::after_millis(COMMS_TX_POLL_MS).await;
+          }
+          // Written onto a link that went down meanwhile: keep it for the next replay
+          if comm::link_state() == LinkState::Up && journal.mark_delivered(&entry).is_err() {
+            defmt::warn!("Journal: cannot mark record {} delivered", entry.seq);
+          }
+          continue;
+        }
+        Ok(None) => BACKLOG.store(false, Ordering::Relaxed),
+        Err(_) => defmt::warn!("Journal: read failed"),
+      }
+    }
+    if let Ok(msg) = with_timeout(Duration::from_millis(JOURNAL_POLL_MS), OUTBOX.receive()).await {
+      store(&mut journal, &msg);
+    }
+  }
+}
+
+fn store<F: NorFlash>(journal: &mut Journal<F>, msg: &Message) {
+  match journal.append(msg) {
+    Ok(_) => BACKLOG.store(true, Ordering::Relaxed),
+    Err(JournalError::Full) => defmt::warn!("Journal: full, message dropped"),
+    Err(JournalError::Flash(_)) => defmt::warn!("Journal: write failed, message dropped"),
+  }
+}
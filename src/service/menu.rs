@@ -0,0 +1,210 @@
+//! Local settings menu on a status display, driven by a rotary encoder and a button (feature `display`)
+// A `Menu` is a static table of `MenuItem`s shown one per line on the `hardware::display` status
+// screen, with `>` on the selected one. Turning the encoder moves the cursor (one step per
+// `MENU_COUNTS_PER_STEP` counts, a mechanical knob's detent), a short press selects, a long press
+// (`MENU_LONG_PRESS_MS`) goes back. What selecting does depends on the item:
+// - `View`: a read-only value, e.g. from the latest telemetry record (`uptime`, `supply`)
+// - `Toggle`: flips an application output through its get/set functions
+// - `Value`: edits a `service::config` field; turning changes it within min..=max, a press
+//   commits it (`config::update`, written to flash by `config_task`), a long press cancels
+// `run` polls the inputs and redraws whenever something changed (and every
+// `MENU_REFRESH_MS` for live values); `Menu::input`/`render` drive other inputs or screens.
+
+use core::fmt::Write;
+use embassy_stm32::timer::GeneralInstance4Channel;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal::digital::InputPin;
+
+use crate::hardware::ButtonReader;
+use crate::hardware::display::{STATUS_LINE_LEN, STATUS_LINES, Ssd1306Display, StatusLine, StatusScreen};
+use crate::hardware::encoder::Encoder;
+use crate::protocol::device_config::DeviceConfig;
+use crate::service::{config, telemetry};
+
+/// Encoder counts per menu step (4 for a knob with one detent per quadrature cycle)
+pub const MENU_COUNTS_PER_STEP: i32 = 4;
+/// Button held this long goes back instead of selecting
+pub const MENU_LONG_PRESS_MS: u64 = 600;
+/// Input polling interval of `run`
+pub const MENU_POLL_MS: u64 = 20;
+/// Redraw interval of `run` without input (live values)
+pub const MENU_REFRESH_MS: u64 = 1000;
+
+/// User input to the menu
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum MenuInput {
+  Next,
+  Prev,
+  Select,
+  Back,
+}
+
+/// What an item shows and does
+#[derive(Copy, Clone)]
+pub enum ItemKind {
+  /// Read-only value written by the function
+  View(fn(&mut StatusLine)),
+  /// On/off output
+  Toggle { get: fn() -> bool, set: fn(bool) },
+  /// Configuration value edited in `step`s within `min..=max` and persisted on commit
+  Value {
+    get: fn(&DeviceConfig) -> u32,
+    set: fn(&mut DeviceConfig, u32),
+    min: u32,
+    max: u32,
+    step: u32,
+  },
+}
+
+/// One line of the menu
+#[derive(Copy, Clone)]
+pub struct MenuItem {
+  /// Shown on the left (keep it short: the value goes on the right of the same line)
+  pub label: &'static str,
+  pub kind: ItemKind,
+}
+
+/// `View` showing the uptime as hh:mm:ss
+pub fn uptime(line: &mut StatusLine) {
+  let s = Instant::now().as_secs();
+  write!(line, "{:02}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60).ok();
+}
+
+/// `View` showing the supply voltage of the latest telemetry record
+pub fn supply(line: &mut StatusLine) {
+  write!(line, "{} mV", telemetry::latest().vdda_mv).ok();
+}
+
+/// Cursor, scroll position and value being edited over a table of items
+pub struct Menu<'a> {
+  items: &'a [MenuItem],
+  cursor: usize,
+  top: usize,
+  // Value of the `Value` item under the cursor while it is being edited
+  editing: Option<u32>,
+}
+
+impl<'a> Menu<'a> {
+  pub const fn new(items: &'a [MenuItem]) -> Self {
+    Self {
+      items,
+      cursor: 0,
+      top: 0,
+      editing: None,
+    }
+  }
+
+  /// Index of the selected item
+  pub fn cursor(&self) -> usize {
+    self.cursor
+  }
+
+  pub fn is_editing(&self) -> bool {
+    self.editing.is_some()
+  }
+
+  /// Apply one input; true if the screen changed
+  pub fn input(&mut self, input: MenuInput) -> bool {
+    let Some(item) = self.items.get(self.cursor) else {
+      return false;
+    };
+    if let (Some(value), ItemKind::Value { set, min, max, step, .. }) = (self.editing, item.kind) {
+      match input {
+        MenuInput::Next => self.editing = Some(value.saturating_add(step).min(max)),
+        MenuInput::Prev => self.editing = Some(value.saturating_sub(step).max(min)),
+        MenuInput::Select => {
+          config::update(|c| set(c, value));
+          defmt::info!("Menu: {} = {}", item.label, value);
+          self.editing = None;
+        }
+        MenuInput::Back => self.editing = None,
+      }
+      return true;
+    }
+    match input {
+      MenuInput::Next if self.cursor + 1 < self.items.len() => self.cursor += 1,
+      MenuInput::Prev if self.cursor > 0 => self.cursor -= 1,
+      MenuInput::Select => match item.kind {
+        ItemKind::Toggle { get, set } => set(!get()),
+        ItemKind::Value { get, .. } => self.editing = Some(get(&config::current())),
+        ItemKind::View(_) => return false,
+      },
+      _ => return false,
+    }
+    // Scroll to keep the cursor on screen
+    self.top = self.top.min(self.cursor).max((self.cursor + 1).saturating_sub(STATUS_LINES));
+    true
+  }
+
+  /// Lines for the status screen: `>` on the selected item, the edited value in brackets
+  pub fn render(&self) -> [StatusLine; STATUS_LINES] {
+    let mut lines: [StatusLine; STATUS_LINES] = Default::default();
+    let config = config::current();
+    for (line, (index, item)) in lines.iter_mut().zip(self.items.iter().enumerate().skip(self.top)) {
+      let mut value = StatusLine::new();
+      match item.kind {
+        ItemKind::View(show) => show(&mut value),
+        ItemKind::Toggle { get, .. } => {
+          value.push_str(if get() { "on" } else { "off" }).ok();
+        }
+        ItemKind::Value { get, .. } => {
+          match self.editing.filter(|_| index == self.cursor) {
+            Some(edited) => write!(value, "[{}]", edited),
+            None => write!(value, "{}", get(&config)),
+          }
+          .ok();
+        }
+      }
+      line.push(if index == self.cursor { '>' } else { ' ' }).ok();
+      line.push_str(item.label).ok();
+      // Value right-aligned, the label cut short if both do not fit
+      let room = STATUS_LINE_LEN.saturating_sub(value.len() + 1);
+      while line.len() > room {
+        line.pop();
+      }
+      while line.len() < STATUS_LINE_LEN - value.len() {
+        line.push(' ').ok();
+      }
+      line.push_str(&value).ok();
+    }
+    lines
+  }
+}
+
+/// Run `menu` on `oled` with `encoder` and an active-high `button` (never returns)
+pub async fn run<T: GeneralInstance4Channel, B: InputPin>(menu: &mut Menu<'_>, encoder: &mut Encoder<T>, button: &mut B, oled: &mut Ssd1306Display) -> ! {
+  let screen = StatusScreen::mono();
+  let mut last_position = encoder.position();
+  let mut pressed_at: Option<Instant> = None;
+  let mut last_draw: Option<Instant> = None;
+  loop {
+    let mut changed = false;
+    // Encoder: whole steps only, the remainder stays for the next poll
+    let steps = (encoder.position() - last_position) / MENU_COUNTS_PER_STEP;
+    last_position += steps * MENU_COUNTS_PER_STEP;
+    let input = if steps > 0 { MenuInput::Next } else { MenuInput::Prev };
+    for _ in 0..steps.unsigned_abs() {
+      changed |= menu.input(input);
+    }
+    // Button: act on release, by how long it was held
+    match (ButtonReader::is_pressed(button), pressed_at) {
+      (true, None) => pressed_at = Some(Instant::now()),
+      (false, Some(at)) => {
+        pressed_at = None;
+        changed |= menu.input(if at.elapsed() >= Duration::from_millis(MENU_LONG_PRESS_MS) {
+          MenuInput::Back
+        } else {
+          MenuInput::Select
+        });
+      }
+      _ => {}
+    }
+    if changed || last_draw.is_none_or(|at| at.elapsed() >= Duration::from_millis(MENU_REFRESH_MS)) {
+      if !screen.show(oled, &menu.render()) {
+        defmt::warn!("Menu: display update failed");
+      }
+      last_draw = Some(Instant::now());
+    }
+    Timer::after_millis(MENU_POLL_MS).await;
+  }
+}
@@ -0,0 +1,199 @@
+//! Networking over embassy-net (feature `net`)
+// For boards with an Ethernet MAC (Nucleo-144 F429ZI / F767ZI / H743ZI; the F446RE and F413ZH have
+// none). The board brings up its `embassy_stm32::eth::Ethernet` driver and hands it to `init`,
+// the application spawns a task running the returned `Runner`, and then:
+// - `comm_port(stack, COMM_PORT)` carries the comm protocol over TCP instead of (or besides) the
+//   UART: received bytes go through a `comm::FrameReceiver` (same framing, queues and
+//   `comm::read()` as the serial link), and whatever the application writes to `NetCommTx` (in
+//   place of the UART `tx` for `send_pending`, `handle_builtin`, `write`) goes to the client.
+// - `debug_port(stack, DEBUG_PORT, io)` serves the text shell (`service::shell`) to telnet/netcat.
+// Both accept one client at a time and drop it after `NET_IDLE_TIMEOUT_S` of silence. Addressing
+// comes from DHCP (`dhcp_config`) or a static `embassy_net::Config`.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_net::driver::Driver;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{Config, DhcpConfig, Runner, Stack, StackResources};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pipe::Pipe;
+use embassy_time::{Duration, with_timeout};
+use embedded_io_async::Write as _;
+use heapless::Vec;
+
+use crate::common::random;
+use crate::service::comm::{COMMS_FRAMED_MAX, COMMS_TX_POLL_MS, FrameReceiver};
+use crate::service::shell::{Shell, ShellIo};
+
+/// TCP port of the comm protocol
+pub const COMM_PORT: u16 = 5000;
+/// TCP port of the text shell
+pub const DEBUG_PORT: u16 = 23;
+/// A client that sends nothing for this long is disconnected
+pub const NET_IDLE_TIMEOUT_S: u64 = 300;
+
+const SOCKET_BUFFER_SIZE: usize = 1024;
+const READ_CHUNK: usize = 256;
+// Outgoing comm frames waiting for the TCP client
+const NET_TX_PIPE_SIZE: usize = 2 * COMMS_FRAMED_MAX;
+// Shell output per received chunk (a `flash dump` reply fits)
+const SHELL_OUT_MAX: usize = 1280;
+// Telnet "interpret as command" byte; negotiation sequences are IAC, verb, option
+const TELNET_IAC: u8 = 0xFF;
+
+static COMM_TX: Pipe<CriticalSectionRawMutex, NET_TX_PIPE_SIZE> = Pipe::new();
+static TX_DROPS: AtomicU32 = AtomicU32::new(0);
+
+/// DHCPv4 addressing with default options
+pub fn dhcp_config() -> Config {
+  Config::dhcpv4(DhcpConfig::default())
+}
+
+/// Create the network stack for `driver` (seeded from `common::random`); spawn a task that
+/// runs the returned `Runner` forever
+pub fn init<D: Driver, const SOCKETS: usize>(driver: D, config: Config, resources: &'static mut StackResources<SOCKETS>) -> (Stack<'static>, Runner<'static, D>) {
+  let seed = ((random::next_u32() as u64) << 32) | random::next_u32() as u64;
+  embassy_net::new(driver, config, resources, seed)
+}
+
+/// Wait until the link is up and has an address (logged)
+pub async fn wait_up(stack: Stack<'static>) {
+  stack.wait_config_up().await;
+  if let Some(config) = stack.config_v4() {
+    defmt::info!("Net: up, address {}", config.address);
+  }
+}
+
+/// Frames dropped because no client was draining `NetCommTx`
+pub fn tx_drop_count() -> u32 {
+  TX_DROPS.load(Ordering::Relaxed)
+}
+
+/// Writer onto the TCP comm link (frames are dropped whole while it is full or nobody is connected)
+pub struct NetCommTx;
+
+impl embedded_io::ErrorType for NetCommTx {
+  type Error = core::convert::Infallible;
+}
+
+impl embedded_io::Write for NetCommTx {
+  fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+    // A frame cut short would desync the client, so it goes in whole or not at all
+    if COMM_TX.free_capacity() < buf.len() {
+      TX_DROPS.fetch_add(1, Ordering::Relaxed);
+      return Ok(buf.len());
+    }
+    Ok(COMM_TX.try_write(buf).unwrap_or(buf.len()))
+  }
+
+  fn flush(&mut self) -> Result<(), Self::Error> {
+    Ok(())
+  }
+}
+
+/// Serve the comm protocol on TCP `port`, one client at a time (run from an application task)
+pub async fn comm_port(stack: Stack<'static>, port: u16) -> ! {
+  let mut rx_buffer = [0u8; SOCKET_BUFFER_SIZE];
+  let mut tx_buffer = [0u8; SOCKET_BUFFER_SIZE];
+  let mut receiver = FrameReceiver::new();
+  let mut chunk = [0u8; READ_CHUNK];
+  loop {
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    socket.set_timeout(Some(Duration::from_secs(NET_IDLE_TIMEOUT_S)));
+    if socket.accept(port).await.is_err() {
+      continue;
+    }
+    defmt::info!("Net: comm client {} connected", socket.remote_endpoint());
+    // Nothing queued for a previous client is meant for this one
+    COMM_TX.clear();
+    receiver.reset();
+    'connection: loop {
+      match with_timeout(Duration::from_millis(COMMS_TX_POLL_MS), socket.read(&mut chunk)).await {
+        Ok(Ok(0)) | Ok(Err(_)) => break,
+        Ok(Ok(n)) => receiver.push(&chunk[..n]),
+        Err(_) => {}
+      }
+      while let Ok(n) = COMM_TX.try_read(&mut chunk) {
+        if socket.write_all(&chunk[..n]).await.is_err() {
+          break 'connection;
+        }
+      }
+    }
+    defmt::info!("Net: comm client disconnected");
+    socket.abort();
+    let _ = socket.flush().await;
+  }
+}
+
+/// Serve the text shell on TCP `port`, one client at a time (run from an application task)
+pub async fn debug_port<I: ShellIo>(stack: Stack<'static>, port: u16, io: &mut I) -> ! {
+  let mut rx_buffer = [0u8; SOCKET_BUFFER_SIZE];
+  let mut tx_buffer = [0u8; SOCKET_BUFFER_SIZE];
+  let mut chunk = [0u8; READ_CHUNK];
+  loop {
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    socket.set_timeout(Some(Duration::from_secs(NET_IDLE_TIMEOUT_S)));
+    if socket.accept(port).await.is_err() {
+      continue;
+    }
+    defmt::info!("Net: debug client {} connected", socket.remote_endpoint());
+    let mut shell = Shell::new();
+    let mut out = ShellOut(Vec::new());
+    let mut telnet = TelnetFilter::default();
+    shell.prompt(&mut out);
+    while socket.write_all(&out.0).await.is_ok() {
+      out.0.clear();
+      let n = match socket.read(&mut chunk).await {
+        Ok(0) | Err(_) => break,
+        Ok(n) => telnet.strip(&mut chunk[..n]),
+      };
+      shell.feed(&chunk[..n], &mut out, io);
+    }
+    defmt::info!("Net: debug client disconnected");
+    socket.abort();
+    let _ = socket.flush().await;
+  }
+}
+
+// Shell output collected per received chunk, then written to the socket (excess is cut off)
+struct ShellOut(Vec<u8, SHELL_OUT_MAX>);
+
+impl embedded_io::ErrorType for ShellOut {
+  type Error = core::convert::Infallible;
+}
+
+impl embedded_io::Write for ShellOut {
+  fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+    let take = buf.len().min(SHELL_OUT_MAX - self.0.len());
+    self.0.extend_from_slice(&buf[..take]).ok();
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> Result<(), Self::Error> {
+    Ok(())
+  }
+}
+
+// Drops telnet option negotiation (IAC, verb, option) so telnet clients work as well as netcat
+#[derive(Default)]
+struct TelnetFilter {
+  skip: u8,
+}
+
+impl TelnetFilter {
+  /// Compact `bytes` in place without negotiation bytes; returns the kept length
+  fn strip(&mut self, bytes: &mut [u8]) -> usize {
+    let mut kept = 0;
+    for i in 0..bytes.len() {
+      let b = bytes[i];
+      if self.skip > 0 {
+        self.skip -= 1;
+      } else if b == TELNET_IAC {
+        self.skip = 2;
+      } else {
+        bytes[kept] = b;
+        kept += 1;
+      }
+    }
+    kept
+  }
+}
@@ -0,0 +1,109 @@
+//! Publish/subscribe over the comm link
+// Multiplexes logical data streams (telemetry, logs, control, application data) over the one
+// UART by u16 topic id (`protocol::pubsub`) instead of overloading `Command::Raw`:
+// - The host subscribes with `Command::Subscribe` / `Command::Unsubscribe`; `publish(topic, data)`
+//   sends to the host only topics it has subscribed to, through the normal-priority `comm` queue.
+// - Firmware tasks register for topics with `subscribe(topic, handler)`; `Command::Publish`
+//   messages from the host, and local `publish` calls, are handed to every matching handler.
+//
+// All three commands are handled by `comm::handle_builtin`. Handlers run in the caller's context
+// (the task owning TX for host publishes), so they should only copy the data or signal a task.
+
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use heapless::Vec;
+
+pub use crate::protocol::pubsub::topic;
+use crate::protocol::pubsub::{self, TopicSet};
+use crate::service::comm::{self, COMMS_MAX_PLAINTEXT, Command, CommsPayload, Message};
+
+/// Topics the host can subscribe to at once
+pub const PUBSUB_HOST_TOPICS: usize = 16;
+/// Local (firmware) topic handlers
+pub const PUBSUB_HANDLERS: usize = 8;
+/// Largest data `publish` can send to the host
+pub const PUBSUB_MAX_DATA: usize = COMMS_MAX_PLAINTEXT - pubsub::PUBLISH_HEADER_LEN;
+
+/// Local subscriber: gets the data of each message published on its topic
+pub type TopicHandler = fn(&[u8]);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum PubSubError {
+  /// Data longer than `PUBSUB_MAX_DATA`
+  TooLong,
+  /// Outgoing queue full (message dropped)
+  QueueFull,
+  /// No room for another topic or handler
+  Full,
+}
+
+static HOST_TOPICS: Mutex<CriticalSectionRawMutex, RefCell<TopicSet<PUBSUB_HOST_TOPICS>>> = Mutex::new(RefCell::new(TopicSet::new()));
+static HANDLERS: Mutex<CriticalSectionRawMutex, RefCell<Vec<(u16, TopicHandler), PUBSUB_HANDLERS>>> = Mutex::new(RefCell::new(Vec::new()));
+
+/// Call `handler` for every message published on `topic` (by the host or locally)
+pub fn subscribe(topic: u16, handler: TopicHandler) -> Result<(), PubSubError> {
+  HANDLERS.lock(|handlers| handlers.borrow_mut().push((topic, handler)).map_err(|_| PubSubError::Full))
+}
+
+/// True if the host has subscribed to `topic`
+pub fn host_subscribed(topic: u16) -> bool {
+  HOST_TOPICS.lock(|topics| topics.borrow().contains(topic))
+}
+
+/// Publish `data` on `topic`: to local handlers, and to the host if it subscribed (without waiting
+/// for queue space)
+pub fn publish(topic: u16, data: &[u8]) -> Result<(), PubSubError> {
+  if data.len() > PUBSUB_MAX_DATA {
+    return Err(PubSubError::TooLong);
+  }
+  deliver(topic, data);
+  if !host_subscribed(topic) {
+    return Ok(());
+  }
+  let payload: CommsPayload = pubsub::encode_publish(topic, data).ok_or(PubSubError::TooLong)?;
+  comm::try_send(Message::new(Command::Publish, &payload)).map_err(|_| PubSubError::QueueFull)
+}
+
+/// Handle a host `Subscribe` payload (Err: odd length, or more topics than fit)
+pub fn handle_subscribe(payload: &[u8]) -> Result<(), ()> {
+  let ids = pubsub::decode_topics(payload).ok_or(())?;
+  HOST_TOPICS.lock(|topics| {
+    let mut topics = topics.borrow_mut();
+    for id in ids {
+      topics.insert(id).map_err(|_| ())?;
+    }
+    defmt::info!("PubSub: host subscribed to {} topics", topics.len());
+    Ok(())
+  })
+}
+
+/// Handle a host `Unsubscribe` payload (empty: every topic)
+pub fn handle_unsubscribe(payload: &[u8]) -> Result<(), ()> {
+  let ids = pubsub::decode_topics(payload).ok_or(())?;
+  HOST_TOPICS.lock(|topics| {
+    let mut topics = topics.borrow_mut();
+    if payload.is_empty() {
+      topics.clear();
+    }
+    for id in ids {
+      topics.remove(id);
+    }
+  });
+  Ok(())
+}
+
+/// Handle a host `Publish` payload (Err: shorter than a topic id)
+pub fn handle_publish(payload: &[u8]) -> Result<(), ()> {
+  let (topic, data) = pubsub::decode_publish(payload).ok_or(())?;
+  deliver(topic, data);
+  Ok(())
+}
+
+// Hand `data` to the local handlers of `topic` (copied out so none runs under the lock)
+fn deliver(topic: u16, data: &[u8]) {
+  let handlers = HANDLERS.lock(|handlers| handlers.borrow().clone());
+  for (_, handler) in handlers.iter().filter(|(t, _)| *t == topic) {
+    handler(data);
+  }
+}
@@ -0,0 +1,109 @@
+//! Debug command channel over an RTT down-channel (feature `rtt_control`)
+// Gives a probe-connected developer a control path that does not consume the UART:
+//   reboot   reset the MCU
+//   stats    log the comm link statistics
+//   led      toggle the debug LED request (applications read it via `led_on()`)
+//
+// The feature swaps defmt-rtt for rtt-target so the defmt up-channel and the control
+// down-channel live in one RTT control block. Call `init()` first thing in `main`, before
+// anything is logged, then spawn `rtt_control_task`. Commands are newline-terminated text,
+// e.g. typed into the `cargo embed` RTT terminal.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use heapless::Vec;
+use rtt_target::{DownChannel, rtt_init};
+
+use crate::common::system::{self, RebootReason};
+use crate::hardware::Timing;
+use crate::service::comm;
+
+const RTT_DEFMT_BUFFER_SIZE: usize = 1024;
+const RTT_CONTROL_BUFFER_SIZE: usize = 64;
+const RTT_LINE_MAX: usize = 32;
+const RTT_POLL_MS: u64 = 50;
+
+// Debug LED state requested over RTT
+static LED_ON: AtomicBool = AtomicBool::new(false);
+
+/// Debug LED state last requested with the `led` command
+pub fn led_on() -> bool {
+  LED_ON.load(Ordering::Relaxed)
+}
+
+/// Commands accepted on the control channel
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum RttCommand {
+  Reboot,
+  Stats,
+  Led,
+}
+
+impl RttCommand {
+  pub fn parse(line: &str) -> Option<Self> {
+    match line.trim() {
+      "reboot" => Some(Self::Reboot),
+      "stats" => Some(Self::Stats),
+      "led" => Some(Self::Led),
+      _ => None,
+    }
+  }
+}
+
+/// Set up RTT: channel 0 up carries defmt, channel 0 down carries control commands
+pub fn init() -> DownChannel {
+  let channels = rtt_init! {
+    up: {
+      0: {
+        size: RTT_DEFMT_BUFFER_SIZE,
+        name: "defmt"
+      }
+    }
+    down: {
+      0: {
+        size: RTT_CONTROL_BUFFER_SIZE,
+        name: "control"
+      }
+    }
+  };
+  rtt_target::set_defmt_channel(channels.up.0);
+  channels.down.0
+}
+
+/// Poll the down-channel and run complete command lines
+#[embassy_executor::task]
+pub async fn rtt_control_task(mut down: DownChannel) {
+  let mut line: Vec<u8, RTT_LINE_MAX> = Vec::new();
+  let mut buf = [0u8; RTT_CONTROL_BUFFER_SIZE];
+  loop {
+    let n = down.read(&mut buf);
+    for &b in &buf[..n] {
+      if b == b'\r' || b == b'\n' {
+        match core::str::from_utf8(&line).ok().and_then(RttCommand::parse) {
+          Some(cmd) => execute(cmd).await,
+          None if !line.is_empty() => defmt::warn!("RTT: unknown command (try reboot, stats, led)"),
+          None => {}
+        }
+        line.clear();
+      } else if line.push(b).is_err() {
+        // Overlong line: drop it
+        line.clear();
+      }
+    }
+    Timing::delay_ms(RTT_POLL_MS).await;
+  }
+}
+
+async fn execute(cmd: RttCommand) {
+  defmt::info!("RTT command: {}", cmd);
+  match cmd {
+    RttCommand::Reboot => {
+      // Give the probe a moment to drain the log before resetting
+      Timing::delay_ms(RTT_POLL_MS).await;
+      system::reboot(RebootReason::Command).await;
+    }
+    RttCommand::Stats => defmt::info!("{}", comm::stats()),
+    RttCommand::Led => {
+      LED_ON.fetch_xor(true, Ordering::Relaxed);
+    }
+  }
+}
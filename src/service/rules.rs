@@ -0,0 +1,124 @@
+//! Rule engine: host-configured "if condition then action" behaviors
+// Lets simple behaviors change without a firmware update, e.g.
+//   "if input 2 low for > 500 ms, raise alarm 7 (critical, latching)"
+//   "if ADC channel 1 > 3000, set output 0 high"
+// Rules are defined by `protocol::rules` (wire format and evaluation) and evaluated by `run`,
+// which samples pins/channels through the same `ShellIo` hooks the shell uses, so the
+// application decides what pin/channel numbers mean.
+//
+// Configuration over the comm link (`Command::Rules`, handled by `comm::handle_builtin`):
+// - empty payload: reply `Command::Rules` with the current rule set (8 bytes per rule)
+// - rule payload: replace and persist the rule set, reply `Command::Ack` (NAK `BadLength` if malformed)
+// The rule set persists in the configuration flash region (`ConfigStore::save_rules`, written
+// by `config_task`); `config::load` installs it at boot.
+
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant, Ticker};
+use heapless::Vec;
+
+use crate::protocol::rules::{Action, RULE_LEN, RULES_MAX, Rule, RuleState, Source};
+use crate::service::alarm::{self, Severity};
+use crate::service::config;
+use crate::service::shell::ShellIo;
+
+type RuleTable = Vec<(Rule, RuleState), RULES_MAX>;
+
+static RULES: Mutex<CriticalSectionRawMutex, RefCell<RuleTable>> = Mutex::new(RefCell::new(Vec::new()));
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum RulesError {
+  /// Not a whole number of rules, or more than `RULES_MAX`
+  BadLength,
+  /// Unknown condition or action kind
+  BadRule,
+}
+
+/// Replace the rule set with `bytes` (`RULE_LEN` bytes per rule) and persist it; rejected whole
+/// if any rule is malformed
+pub fn restore(bytes: &[u8]) -> Result<(), RulesError> {
+  if bytes.len() % RULE_LEN != 0 || bytes.len() / RULE_LEN > RULES_MAX {
+    return Err(RulesError::BadLength);
+  }
+  let mut rules = Vec::<Rule, RULES_MAX>::new();
+  for chunk in bytes.chunks_exact(RULE_LEN) {
+    rules.push(Rule::from_bytes(chunk.try_into().unwrap()).ok_or(RulesError::BadRule)?).ok();
+  }
+  install(rules);
+  config::save_rules();
+  Ok(())
+}
+
+/// Install a rule set loaded from flash (`config::load`) without writing it back
+pub fn install(rules: Vec<Rule, RULES_MAX>) {
+  let table: RuleTable = rules.into_iter().map(|rule| (rule, RuleState::default())).collect();
+  defmt::info!("Rules: {} loaded", table.len());
+  let old = RULES.lock(|current| current.replace(table));
+  // Don't leave alarms raised by rules that no longer exist
+  for (rule, state) in old {
+    if let (Action::Alarm { id, .. }, true) = (rule.action, state.active) {
+      alarm::clear(id);
+    }
+  }
+}
+
+/// The current rule set
+pub fn rules() -> Vec<Rule, RULES_MAX> {
+  RULES.lock(|table| table.borrow().iter().map(|(rule, _)| *rule).collect())
+}
+
+/// Serialize the rule set (`RULE_LEN` bytes per rule) for a host query or snapshot
+pub fn snapshot() -> Vec<u8, { RULES_MAX * RULE_LEN }> {
+  let mut out = Vec::new();
+  for rule in rules() {
+    out.extend_from_slice(&rule.to_bytes()).ok();
+  }
+  out
+}
+
+/// Evaluate every rule once against fresh samples from `io`
+pub fn poll<I: ShellIo>(io: &mut I) {
+  let now_ms = Instant::now().as_millis() as u32;
+  // Collect changes first so actions run outside the table lock
+  let mut changes: Vec<(Action, bool), RULES_MAX> = Vec::new();
+  RULES.lock(|rules| {
+    for (rule, state) in rules.borrow_mut().iter_mut() {
+      let sample = match rule.condition.source() {
+        Source::Input(pin) => io.gpio_get(pin).map(u16::from),
+        Source::Adc(channel) => io.adc_read(channel),
+      };
+      // Unknown pin/channel: leave the rule as it is
+      let Some(sample) = sample else { continue };
+      if let Some(active) = rule.evaluate(state, sample, now_ms) {
+        changes.push((rule.action, active)).ok();
+      }
+    }
+  });
+  for (action, active) in changes {
+    apply(io, action, active);
+  }
+}
+
+fn apply<I: ShellIo>(io: &mut I, action: Action, active: bool) {
+  match action {
+    Action::Alarm { id, severity, latching } if active => {
+      alarm::raise(id, Severity::try_from(severity).unwrap_or(Severity::Critical), latching);
+    }
+    Action::Alarm { id, .. } => alarm::clear(id),
+    Action::Output { pin, level } => {
+      if !io.gpio_set(pin, level == active) {
+        defmt::warn!("Rules: no output pin {}", pin);
+      }
+    }
+  }
+}
+
+/// Evaluate the rules every `period` forever (call from an application task that owns the pins)
+pub async fn run<I: ShellIo>(io: &mut I, period: Duration) -> ! {
+  let mut ticker = Ticker::every(period);
+  loop {
+    poll(io);
+    ticker.next().await;
+  }
+}
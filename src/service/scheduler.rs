@@ -0,0 +1,153 @@
+//! Calendar scheduler: cron-like entries fired by the RTC alarm
+// "every day at 02:00, event 3", "Mondays at 07:30", "every hour at :15": entries
+// (`protocol::schedule`) fire at second 0 of each RTC calendar minute they match.
+// `scheduler_task` arms RTC alarm A for the earliest next firing and sleeps until its interrupt
+// (RTC_ALARM, handled here); every entry due at that minute is then delivered as an event:
+// - on pubsub `topic::SCHEDULE` (data: `[event]`), to the callbacks registered with
+//   `pubsub::subscribe(topic::SCHEDULE, handler)` and to the host if it subscribed
+// - in the black box as `EVENT_SCHEDULE` (arg: the event)
+// Callbacks run in the scheduler task: like other pubsub handlers, they should only signal the
+// task that does the work (start the pump, open a log file).
+//
+// Alarm A compares the day of the month but not the month, so an entry more than a month away
+// wakes the task early; it finds nothing due and re-arms. The task also re-reads the RTC every
+// `SCHEDULE_RECHECK_S` and on `time_changed()` (call it after setting the calendar), so a
+// stepped clock never leaves it waiting on an alarm that has passed. Firings skipped by a step
+// forward, or while the device was off, are not delivered.
+//
+// The entries persist in the configuration flash region (`ConfigStore::save_schedule`, written
+// by `config_task`); `config::load` installs them at boot.
+// Configuration over the comm link (`Command::Schedule`, handled by `comm::handle_builtin`):
+// - empty payload: reply `Command::Schedule` with the entries (6 bytes each)
+// - entries payload: replace and persist them, reply `Command::Ack` (NAK `BadLength` if malformed)
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_stm32::interrupt;
+use embassy_stm32::interrupt::{Interrupt, InterruptExt, Priority};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, with_timeout};
+use heapless::Vec;
+
+use crate::diagnostics::eventlog::{self, EVENT_SCHEDULE};
+use crate::hardware::rtc;
+use crate::protocol::schedule::{self, CalendarTime, SCHEDULE_ENTRY_LEN, SCHEDULE_MAX, ScheduleEntry};
+use crate::service::config;
+use crate::service::pubsub::{self, topic};
+
+/// Longest the task waits without re-reading the RTC
+pub const SCHEDULE_RECHECK_S: u64 = 3600;
+
+pub type ScheduleTable = Vec<ScheduleEntry, SCHEDULE_MAX>;
+
+static ENTRIES: Mutex<CriticalSectionRawMutex, RefCell<ScheduleTable>> = Mutex::new(RefCell::new(Vec::new()));
+// Alarm fired, entries replaced or calendar set
+static WAKE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static CLOCK_SET: AtomicBool = AtomicBool::new(false);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum ScheduleError {
+  /// Not a whole number of entries, or more than `SCHEDULE_MAX`
+  BadLength,
+  /// A field out of range
+  BadEntry,
+}
+
+/// Replace the entries with `bytes` (`SCHEDULE_ENTRY_LEN` bytes per entry) and persist them;
+/// rejected whole if any entry is malformed
+pub fn restore(bytes: &[u8]) -> Result<(), ScheduleError> {
+  if bytes.len() % SCHEDULE_ENTRY_LEN != 0 || bytes.len() / SCHEDULE_ENTRY_LEN > SCHEDULE_MAX {
+    return Err(ScheduleError::BadLength);
+  }
+  let mut table = ScheduleTable::new();
+  for chunk in bytes.chunks_exact(SCHEDULE_ENTRY_LEN) {
+    let entry = ScheduleEntry::from_bytes(chunk.try_into().unwrap()).ok_or(ScheduleError::BadEntry)?;
+    table.push(entry).ok();
+  }
+  set(&table)
+}
+
+/// Replace the entries and persist them (application code, local UI)
+pub fn set(entries: &[ScheduleEntry]) -> Result<(), ScheduleError> {
+  let table = ScheduleTable::from_slice(entries).map_err(|_| ScheduleError::BadLength)?;
+  install(table);
+  config::save_schedule();
+  Ok(())
+}
+
+/// Install entries loaded from flash (`config::load`) without writing them back
+pub fn install(entries: ScheduleTable) {
+  defmt::info!("Scheduler: {} entries", entries.len());
+  ENTRIES.lock(|table| table.replace(entries));
+  WAKE.signal(());
+}
+
+/// The current entries
+pub fn entries() -> ScheduleTable {
+  ENTRIES.lock(|table| table.borrow().clone())
+}
+
+/// Serialize the entries (`SCHEDULE_ENTRY_LEN` bytes per entry) for a host query or snapshot
+pub fn snapshot() -> Vec<u8, { SCHEDULE_MAX * SCHEDULE_ENTRY_LEN }> {
+  let mut out = Vec::new();
+  for entry in entries() {
+    out.extend_from_slice(&entry.to_bytes()).ok();
+  }
+  out
+}
+
+/// Plan again from the RTC's new time (call after setting the calendar); firings the step
+/// skipped are dropped
+pub fn time_changed() {
+  CLOCK_SET.store(true, Ordering::Relaxed);
+  WAKE.signal(());
+}
+
+#[interrupt]
+fn RTC_ALARM() {
+  if rtc::take_alarm() {
+    WAKE.signal(());
+  }
+}
+
+/// Deliver the schedule's events (spawn once; the RTC must be running)
+#[embassy_executor::task]
+pub async fn scheduler_task() {
+  let irq = Interrupt::RTC_ALARM;
+  // The handler only signals this task
+  irq.set_priority(Priority::P15);
+  irq.unpend();
+  // SAFETY: RTC_ALARM is handled above and used by nothing else
+  unsafe { irq.enable() };
+  // Firing the alarm is armed for
+  let mut armed: Option<CalendarTime> = None;
+  loop {
+    if CLOCK_SET.swap(false, Ordering::Relaxed) {
+      armed = None;
+    }
+    let now = rtc::now();
+    if let Some(due) = armed.filter(|due| *due <= now) {
+      fire(&due);
+    }
+    // Planned from the minute after `now`, so the firing just delivered is not repeated
+    armed = schedule::next_due(&entries(), &now);
+    match &armed {
+      Some(next) => rtc::set_alarm(next),
+      None => rtc::disable_alarm(),
+    }
+    with_timeout(Duration::from_secs(SCHEDULE_RECHECK_S), WAKE.wait()).await.ok();
+  }
+}
+
+// Deliver the event of every entry due at `at`
+fn fire(at: &CalendarTime) {
+  for entry in entries().iter().filter(|entry| entry.matches(at)) {
+    defmt::info!("Scheduler: event {} ({}:{})", entry.event, at.hour, at.minute);
+    eventlog::record(EVENT_SCHEDULE, entry.event as u32);
+    if pubsub::publish(topic::SCHEDULE, &[entry.event]).is_err() {
+      defmt::warn!("Scheduler: event {} not sent to the host", entry.event);
+    }
+  }
+}
@@ -0,0 +1,69 @@
+//! Environmental sensor polling and publishing
+// `sensors_task` reads a `hardware::env_sensor::EnvSensor` (BME280 or SHT31 on the shared I2C
+// bus) every period and sends each reading to the host as an unsolicited `Command::Sensors`
+// message (queued here, sent by `comm::send_pending`). An empty `Command::Sensors` request
+// returns the latest reading (empty payload if there is none yet).
+//
+// Payload (little-endian): temperature i32 (0.01 °C), humidity u32 (0.01 %RH), pressure u32
+// (Pa, 0 if the sensor has no barometer).
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::Timer;
+
+use crate::hardware::SharedI2c;
+use crate::hardware::env_sensor::{EnvReading, EnvSensor};
+use crate::service::comm::{Command, Message};
+
+/// Encoded size of a `Command::Sensors` payload
+pub const SENSOR_READING_LEN: usize = 12;
+
+static LATEST: Mutex<CriticalSectionRawMutex, RefCell<Option<EnvReading>>> = Mutex::new(RefCell::new(None));
+// A reading is waiting for `comm::send_pending`
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Encode `reading` as the `Command::Sensors` payload
+pub fn encode(reading: &EnvReading) -> [u8; SENSOR_READING_LEN] {
+  let mut out = [0u8; SENSOR_READING_LEN];
+  out[0..4].copy_from_slice(&reading.temperature_centi_c.to_le_bytes());
+  out[4..8].copy_from_slice(&reading.humidity_centi_pct.to_le_bytes());
+  out[8..12].copy_from_slice(&reading.pressure_pa.unwrap_or(0).to_le_bytes());
+  out
+}
+
+/// Store `reading` as the latest and queue it for the host
+pub fn publish(reading: EnvReading) {
+  LATEST.lock(|latest| *latest.borrow_mut() = Some(reading));
+  PENDING.store(true, Ordering::Relaxed);
+}
+
+/// Most recent reading (None until the first successful read)
+pub fn latest() -> Option<EnvReading> {
+  LATEST.lock(|latest| *latest.borrow())
+}
+
+/// Unsolicited reading for the host, if one is due (drained by `comm::send_pending`)
+pub fn next_notification() -> Option<Message> {
+  if !PENDING.swap(false, Ordering::Relaxed) {
+    return None;
+  }
+  latest().map(|reading| Message::new(Command::Sensors, &encode(&reading)))
+}
+
+/// Poll `sensor` every `period_s` seconds and publish each reading
+#[embassy_executor::task]
+pub async fn sensors_task(mut sensor: EnvSensor<SharedI2c>, period_s: u64) {
+  defmt::info!("Sensors: polling {} every {} s", sensor.name(), period_s);
+  loop {
+    match sensor.read().await {
+      Ok(reading) => {
+        defmt::debug!("Sensors: {}", reading);
+        publish(reading);
+      }
+      Err(e) => defmt::warn!("Sensors: {} read failed: {}", sensor.name(), e),
+    }
+    Timer::after_secs(period_s).await;
+  }
+}
@@ -0,0 +1,345 @@
+//! Line-based shell for plain serial terminals
+// Alternative to the binary HDLC comm for poking at a board from screen/minicom/picocom.
+//
+// Bytes reach the shell either from the VCP (feature `shell`: the serial RX queue is left to
+// the shell instead of the HDLC consumer, see `shell_task`) or from any other UART whose
+// received bytes the application passes to `Shell::feed`. Board-specific commands (gpio, adc)
+// are delegated to the application through `ShellIo`, as are the destinations of `rb` uploads
+// (`ShellIo::file_sink`, see `service::ymodem`). While an upload runs the shell passes every byte
+// to the transfer instead of the line editor; the caller calls `Shell::timeout` whenever the line
+// stays quiet for `YMODEM_TIMEOUT_MS` during it (`is_receiving`).
+//
+// Commands:
+//   help                        list commands
+//   gpio set <pin> <0|1>        drive an application output
+//   gpio get <pin>              read an application pin
+//   adc read <channel>          read an application ADC channel
+//   flash dump [offset] [len]   hex dump of the storage region (numbers accept 0x prefix)
+//   stats                       comm link statistics
+//   rb [staging|fs]             receive files with YMODEM or XMODEM-CRC (sb/sx, Tera Term)
+//   reboot                      reset the MCU
+
+use heapless::Vec;
+
+use crate::board::BoardConfig;
+use crate::common::system::{self, RebootReason};
+use crate::hardware::{flash, serial};
+use crate::service::comm;
+use crate::service::ymodem::{FileSink, TransferStatus, YmodemTransfer};
+
+/// Longest accepted command line
+pub const SHELL_LINE_MAX: usize = 64;
+// Upper bound and default for `flash dump`
+const SHELL_DUMP_MAX: usize = 256;
+const SHELL_DUMP_DEFAULT: usize = 64;
+const SHELL_PROMPT: &str = "> ";
+
+const HELP: &str =
+  "commands:\r\n  help\r\n  gpio set <pin> <0|1>\r\n  gpio get <pin>\r\n  adc read <channel>\r\n  flash dump [offset] [len]\r\n  stats\r\n  rb [staging|fs]\r\n  reboot\r\n";
+
+/// Destination of an `rb` upload
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ReceiveTarget {
+  /// Raw image into a flash staging region (`ymodem::FlashStaging`)
+  Staging,
+  /// Files in the filesystem (`ymodem::FsSink`)
+  Fs,
+}
+
+/// Application hooks for board-specific commands (pins/channels the app chooses to expose)
+pub trait ShellIo {
+  /// Drive output `pin`; returns false if there is no such pin
+  fn gpio_set(&mut self, _pin: u8, _high: bool) -> bool {
+    false
+  }
+
+  /// Level of `pin`, or None if there is no such pin
+  fn gpio_get(&mut self, _pin: u8) -> Option<bool> {
+    None
+  }
+
+  /// Raw reading of ADC `channel`, or None if there is no such channel
+  fn adc_read(&mut self, _channel: u8) -> Option<u16> {
+    None
+  }
+
+  /// Where `rb` stores uploads for `target`, or None if the application offers no such target
+  fn file_sink(&mut self, _target: ReceiveTarget) -> Option<&mut dyn FileSink> {
+    None
+  }
+}
+
+/// `ShellIo` that exposes no pins or channels
+pub struct NoShellIo;
+
+impl ShellIo for NoShellIo {}
+
+// Stands in for a sink the application stopped offering mid-transfer: refuses everything
+struct NoSink;
+
+impl FileSink for NoSink {
+  fn open(&mut self, _name: &str, _size: Option<u32>) -> bool {
+    false
+  }
+
+  fn write(&mut self, _data: &[u8]) -> bool {
+    false
+  }
+}
+
+/// Line editor + command interpreter
+pub struct Shell {
+  line: Vec<u8, SHELL_LINE_MAX>,
+  overflow: bool,
+  last: u8,
+  transfer: Option<(ReceiveTarget, YmodemTransfer)>,
+}
+
+impl Default for Shell {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Shell {
+  pub const fn new() -> Self {
+    Self {
+      line: Vec::new(),
+      overflow: false,
+      last: 0,
+      transfer: None,
+    }
+  }
+
+  /// Print the prompt
+  pub fn prompt<W: embedded_io::Write>(&self, out: &mut W) {
+    serial::write(out, SHELL_PROMPT.as_bytes());
+  }
+
+  /// Whether an `rb` upload is in progress
+  pub fn is_receiving(&self) -> bool {
+    self.transfer.is_some()
+  }
+
+  /// No byte arrived for `YMODEM_TIMEOUT_MS` during an upload: asks the sender again
+  pub fn timeout<W: embedded_io::Write>(&mut self, out: &mut W) {
+    if let Some((_, transfer)) = &mut self.transfer {
+      transfer.timeout(out);
+      self.end_transfer(out);
+    }
+  }
+
+  /// Feed received bytes: echoes input, handles backspace and runs each completed line
+  pub fn feed<W: embedded_io::Write, I: ShellIo>(&mut self, bytes: &[u8], out: &mut W, io: &mut I) {
+    for &b in bytes {
+      if let Some((target, transfer)) = &mut self.transfer {
+        let mut none = NoSink;
+        let sink: &mut dyn FileSink = match io.file_sink(*target) {
+          Some(sink) => sink,
+          None => &mut none,
+        };
+        transfer.push(b, sink, out);
+        self.end_transfer(out);
+        continue;
+      }
+      match b {
+        // CR, LF or CRLF ends a line
+        b'\n' if self.last == b'\r' => {}
+        b'\r' | b'\n' => {
+          serial::write(out, b"\r\n");
+          let mut receiving = false;
+          if self.overflow {
+            serial::write(out, b"error: line too long\r\n");
+          } else if let Some(target) = receive_command(&self.line) {
+            match target {
+              Some(target) => receiving = self.receive(target, out, io),
+              None => serial::write(out, b"usage: rb [staging|fs]\r\n"),
+            }
+          } else if let Ok(line) = core::str::from_utf8(&self.line) {
+            execute(line, out, io);
+          }
+          self.line.clear();
+          self.overflow = false;
+          if !receiving {
+            self.prompt(out);
+          }
+        }
+        // Backspace / DEL (ignored on an empty line)
+        0x08 | 0x7F if self.line.pop().is_some() => serial::write(out, b"\x08 \x08"),
+        0x20..=0x7E => {
+          if self.line.push(b).is_ok() {
+            serial::write(out, &[b]);
+          } else {
+            self.overflow = true;
+          }
+        }
+        _ => {}
+      }
+      self.last = b;
+    }
+  }
+
+  // Start an `rb` upload into `target`; false if the application offers no such target
+  fn receive<W: embedded_io::Write, I: ShellIo>(&mut self, target: ReceiveTarget, out: &mut W, io: &mut I) -> bool {
+    if io.file_sink(target).is_none() {
+      let name = match target {
+        ReceiveTarget::Staging => "staging",
+        ReceiveTarget::Fs => "fs",
+      };
+      write!(out, "error: no {} target\r\n", name).ok();
+      return false;
+    }
+    serial::write(out, b"receiving: start the YMODEM or XMODEM upload (CAN CAN aborts)\r\n");
+    let mut transfer = YmodemTransfer::new();
+    transfer.start(out);
+    self.transfer = Some((target, transfer));
+    self.last = 0;
+    true
+  }
+
+  // Report a finished upload and return to the prompt
+  fn end_transfer<W: embedded_io::Write>(&mut self, out: &mut W) {
+    let Some((_, transfer)) = &self.transfer else {
+      return;
+    };
+    match transfer.status() {
+      TransferStatus::Receiving => return,
+      TransferStatus::Done { files, bytes } => {
+        write!(out, "\r\nreceived {} file(s), {} bytes\r\n", files, bytes).ok();
+      }
+      TransferStatus::Aborted { bytes } => {
+        write!(out, "\r\nerror: upload aborted after {} bytes\r\n", bytes).ok();
+      }
+    }
+    self.transfer = None;
+    self.prompt(out);
+  }
+}
+
+/// `rb [staging|fs]`: Some(target), Some(None) for bad arguments, None for another command
+fn receive_command(line: &[u8]) -> Option<Option<ReceiveTarget>> {
+  let mut words = core::str::from_utf8(line).ok()?.split_ascii_whitespace();
+  if words.next() != Some("rb") {
+    return None;
+  }
+  match (words.next(), words.next()) {
+    (None | Some("staging"), None) => Some(Some(ReceiveTarget::Staging)),
+    (Some("fs"), None) => Some(Some(ReceiveTarget::Fs)),
+    _ => Some(None),
+  }
+}
+
+/// Run a single command line
+pub fn execute<W: embedded_io::Write, I: ShellIo>(line: &str, out: &mut W, io: &mut I) {
+  let mut words = line.split_ascii_whitespace();
+  let Some(cmd) = words.next() else {
+    return;
+  };
+  let (sub, a, b) = (words.next(), words.next(), words.next());
+  match (cmd, sub) {
+    ("help", None) => serial::write(out, HELP.as_bytes()),
+    ("gpio", Some("set")) => match (number(a), b) {
+      (Some(pin), Some(level @ ("0" | "1"))) => {
+        if io.gpio_set(pin as u8, level == "1") {
+          serial::write(out, b"ok\r\n");
+        } else {
+          write!(out, "error: no gpio {}\r\n", pin).ok();
+        }
+      }
+      _ => serial::write(out, b"usage: gpio set <pin> <0|1>\r\n"),
+    },
+    ("gpio", Some("get")) => match number(a) {
+      Some(pin) => match io.gpio_get(pin as u8) {
+        Some(high) => {
+          write!(out, "{}\r\n", u8::from(high)).ok();
+        }
+        None => {
+          write!(out, "error: no gpio {}\r\n", pin).ok();
+        }
+      },
+      None => serial::write(out, b"usage: gpio get <pin>\r\n"),
+    },
+    ("adc", Some("read")) => match number(a) {
+      Some(ch) => match io.adc_read(ch as u8) {
+        Some(value) => {
+          write!(out, "{}\r\n", value).ok();
+        }
+        None => {
+          write!(out, "error: no adc channel {}\r\n", ch).ok();
+        }
+      },
+      None => serial::write(out, b"usage: adc read <channel>\r\n"),
+    },
+    ("flash", Some("dump")) => {
+      let offset = a.map_or(Some(0), |s| number(Some(s)));
+      let len = b.map_or(Some(SHELL_DUMP_DEFAULT as u32), |s| number(Some(s)));
+      match (offset, len) {
+        (Some(offset), Some(len)) => dump(offset as usize, len as usize, out),
+        _ => serial::write(out, b"usage: flash dump [offset] [len]\r\n"),
+      }
+    }
+    ("stats", None) => {
+      let s = comm::stats();
+      write!(
+        out,
+        "rx_frames {}\r\ntx_frames {}\r\nfcs_errors {}\r\nparse_errors {}\r\nqueue_drops {}\r\nrx_bytes {}\r\ntx_bytes {}\r\n",
+        s.rx_frames, s.tx_frames, s.fcs_errors, s.parse_errors, s.queue_drops, s.rx_bytes, s.tx_bytes
+      )
+      .ok();
+    }
+    ("reboot", None) => {
+      // Replies are written blocking, so this one is out; nothing else is waited for here
+      serial::write(out, b"rebooting\r\n");
+      system::reset(RebootReason::Command);
+    }
+    _ => {
+      write!(out, "unknown command '{}' (try 'help')\r\n", line.trim()).ok();
+    }
+  }
+}
+
+/// Parse a decimal or 0x-prefixed hex number
+fn number(word: Option<&str>) -> Option<u32> {
+  let word = word?;
+  match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+    None => word.parse().ok(),
+  }
+}
+
+/// Hex dump `len` bytes of the storage region starting at `offset`, 16 per row
+fn dump<W: embedded_io::Write>(offset: usize, len: usize, out: &mut W) {
+  let size = BoardConfig::FLASH_STORAGE_SIZE;
+  if offset >= size {
+    write!(out, "error: offset beyond storage ({} bytes)\r\n", size).ok();
+    return;
+  }
+  let end = offset + len.min(SHELL_DUMP_MAX).min(size - offset);
+  let mut row = [0u8; 16];
+  for row_start in (offset..end).step_by(row.len()) {
+    let chunk = &mut row[..(end - row_start).min(16)];
+    if flash::read_block(row_start, chunk).is_err() {
+      serial::write(out, b"error: flash read failed\r\n");
+      return;
+    }
+    write!(out, "{:08X}:", flash::start() as usize + row_start).ok();
+    for byte in chunk.iter() {
+      write!(out, " {:02X}", byte).ok();
+    }
+    serial::write(out, b"\r\n");
+  }
+}
+
+/// Shell over the VCP in place of the HDLC consumer (feature `shell`); spawn with the `UartTx`
+/// returned by `init_serial`. No `ShellIo` is attached, so applications exposing pins/channels
+/// drive a `Shell` themselves from `serial::recv_raw()` instead.
+#[cfg(feature = "shell")]
+#[embassy_executor::task]
+pub async fn shell_task(mut tx: embassy_stm32::usart::UartTx<'static, embassy_stm32::mode::Async>) {
+  let mut shell = Shell::new();
+  shell.prompt(&mut tx);
+  loop {
+    let bytes = serial::recv_raw().await;
+    shell.feed(&bytes, &mut tx, &mut NoShellIo);
+  }
+}
@@ -0,0 +1,86 @@
+//! Snapshot/restore of the full device configuration over the comm link
+// Every configuration section (the `rules` rule set, the `config` device record and the
+// `scheduler` calendar entries) is exported as one CRC-protected `protocol::config_blob`, so a
+// configuration can be backed up before a firmware update or cloned across a fleet. A new configuration source joins by
+// adding its section to `export()` and `apply()`.
+//
+// Comms (handled by `comm::handle_builtin`):
+// - `ConfigExport` (empty payload): replied with the blob as `ConfigExport` fragments
+//   (`fragments` = total, `fragment` = 0-based index, same id as the request)
+// - `ConfigImport`: blob sent as fragments 0..n; after the last fragment the blob is validated
+//   and applied, then answered with `Ack` (or `Nak` `BadLength` if it is rejected)
+
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+use crate::protocol::config_blob::{self, BlobWriter, ConfigBlob, ConfigBlobError, section};
+use crate::service::{config, rules, scheduler};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum SnapshotError {
+  /// Blob failed validation (see `ConfigBlobError`)
+  Blob,
+  /// A section was rejected by its owner
+  Section(u8),
+  /// Import fragments arrived out of order or overflowed the buffer
+  Fragment,
+}
+
+impl From<ConfigBlobError> for SnapshotError {
+  fn from(_: ConfigBlobError) -> Self {
+    SnapshotError::Blob
+  }
+}
+
+// Import blob being reassembled from fragments, with the next expected fragment index
+static IMPORT: Mutex<CriticalSectionRawMutex, RefCell<(u16, ConfigBlob)>> = Mutex::new(RefCell::new((0, ConfigBlob::new())));
+
+/// Serialize every configuration section
+pub fn export() -> ConfigBlob {
+  let mut writer = BlobWriter::new();
+  writer.section(section::RULES, &rules::snapshot()).ok();
+  writer.section(section::CONFIG, &config::current().encode()).ok();
+  writer.section(section::SCHEDULE, &scheduler::snapshot()).ok();
+  writer.finish()
+}
+
+/// Validate `blob` and hand each section to its owner (unknown sections are skipped)
+pub fn apply(blob: &[u8]) -> Result<(), SnapshotError> {
+  let sections = config_blob::sections(blob)?;
+  for (tag, data) in sections {
+    let result = match tag {
+      section::RULES => rules::restore(data).map_err(|_| SnapshotError::Section(tag)),
+      section::CONFIG => config::restore(data).map_err(|_| SnapshotError::Section(tag)),
+      section::SCHEDULE => scheduler::restore(data).map_err(|_| SnapshotError::Section(tag)),
+      _ => {
+        defmt::debug!("Snapshot: skipping unknown section {}", tag);
+        Ok(())
+      }
+    };
+    result?;
+  }
+  defmt::info!("Snapshot: configuration imported ({} bytes)", blob.len());
+  Ok(())
+}
+
+/// Add import fragment `fragment` of `fragments`; applies the blob once the last one arrives
+///
+/// Returns None while more fragments are expected.
+pub fn import_fragment(fragment: u16, fragments: u16, data: &[u8]) -> Option<Result<(), SnapshotError>> {
+  let complete = IMPORT.lock(|import| {
+    let (next, blob) = &mut *import.borrow_mut();
+    if fragment == 0 {
+      blob.clear();
+      *next = 0;
+    }
+    if fragment != *next || blob.extend_from_slice(data).is_err() {
+      blob.clear();
+      *next = 0;
+      return Some(Err(SnapshotError::Fragment));
+    }
+    *next += 1;
+    (*next >= fragments).then(|| Ok(core::mem::take(blob)))
+  })?;
+  Some(complete.and_then(|blob| apply(&blob)))
+}
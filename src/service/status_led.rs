@@ -0,0 +1,106 @@
+//! Status LED: named blink patterns requested by any task, highest priority wins
+// One task owns the LED (`status_led_task`); everything else only requests patterns:
+//   status_led::request(Pattern::FastBlink);   // e.g. comm error detected
+//   status_led::release(Pattern::FastBlink);   // condition cleared
+// Requests are a set, so several conditions can be active at once and the most important
+// one is shown (Solid > DoubleBlink > FastBlink > SlowBlink). With no request the LED is off.
+// `activity()` adds a short flash on top of the idle pattern to show traffic.
+
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use embassy_stm32::gpio::Output;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer, with_deadline};
+
+/// Blink patterns, in increasing priority
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, defmt::Format)]
+pub enum Pattern {
+  /// Idle / running normally
+  SlowBlink = 0,
+  /// Comm error
+  FastBlink = 1,
+  /// Firmware update (DFU) in progress
+  DoubleBlink = 2,
+  /// Fault
+  Solid = 3,
+}
+
+impl Pattern {
+  const ALL: [Pattern; 4] = [Pattern::SlowBlink, Pattern::FastBlink, Pattern::DoubleBlink, Pattern::Solid];
+
+  /// (on, duration ms) steps of one cycle
+  fn steps(self) -> &'static [(bool, u64)] {
+    match self {
+      Pattern::SlowBlink => &[(true, 500), (false, 500)],
+      Pattern::FastBlink => &[(true, 100), (false, 100)],
+      Pattern::DoubleBlink => &[(true, 100), (false, 150), (true, 100), (false, 650)],
+      Pattern::Solid => &[(true, 1000)],
+    }
+  }
+}
+
+// Bit n set = Pattern with discriminant n requested
+static REQUESTS: AtomicU8 = AtomicU8::new(0);
+static ACTIVITY: AtomicBool = AtomicBool::new(false);
+// Wakes the task on request changes and activity
+static WAKE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Ask for `pattern` until `release` (requesting twice is the same as once)
+pub fn request(pattern: Pattern) {
+  if REQUESTS.fetch_or(1 << pattern as u8, Ordering::Relaxed) & (1 << pattern as u8) == 0 {
+    WAKE.signal(());
+  }
+}
+
+/// Withdraw a request made with `request`
+pub fn release(pattern: Pattern) {
+  if REQUESTS.fetch_and(!(1 << pattern as u8), Ordering::Relaxed) & (1 << pattern as u8) != 0 {
+    WAKE.signal(());
+  }
+}
+
+/// `request` or `release` depending on `on`
+pub fn set(pattern: Pattern, on: bool) {
+  if on { request(pattern) } else { release(pattern) }
+}
+
+/// Pattern currently shown (None = LED off)
+pub fn current() -> Option<Pattern> {
+  let requests = REQUESTS.load(Ordering::Relaxed);
+  Pattern::ALL.into_iter().rev().find(|p| requests & (1 << *p as u8) != 0)
+}
+
+/// Flash briefly to show traffic (ignored while an error/DFU/fault pattern is shown)
+pub fn activity() {
+  ACTIVITY.store(true, Ordering::Relaxed);
+  WAKE.signal(());
+}
+
+/// Render the requested patterns on `led`
+#[embassy_executor::task]
+pub async fn status_led_task(mut led: Output<'static>) {
+  'render: loop {
+    let pattern = current();
+    let steps: &[(bool, u64)] = match pattern {
+      Some(pattern) => pattern.steps(),
+      None => &[(false, 1000)],
+    };
+    for &(on, ms) in steps {
+      led.set_level(on.into());
+      let deadline = Instant::now() + Duration::from_millis(ms);
+      while with_deadline(deadline, WAKE.wait()).await.is_ok() {
+        // Restart the cycle as soon as the requests change
+        if current() != pattern {
+          continue 'render;
+        }
+        if ACTIVITY.swap(false, Ordering::Relaxed) && pattern <= Some(Pattern::SlowBlink) {
+          // Invert for a moment so the flash is visible whether the LED is on or off
+          led.set_level((!on).into());
+          Timer::after_millis(30).await;
+          led.set_level(on.into());
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,160 @@
+//! System telemetry: periodic health record for the host and the log
+// `common::tasks::telemetry_task` gathers a `Telemetry` record every period and `publish`es it:
+// - over comm as an unsolicited `Command::Telemetry` message (queued here, sent by
+//   `comm::send_pending`), and/or
+// - to the defmt log.
+// The host can also ask for the latest record with an empty `Command::Telemetry` request,
+// answered by `comm::handle_builtin`.
+//
+// Record payload: thirteen u32 fields, little-endian, in declaration order. Figures from disabled
+// features (heap without `alloc`, interrupt latency without `cs_monitor`, idle and iteration
+// times without `task_metrics`, sleep and current without `common::power::Executor`) are 0.
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+use crate::common::power;
+use crate::diagnostics::reset::reset_cause;
+use crate::diagnostics::stack_high_water;
+#[cfg(feature = "task_metrics")]
+use crate::diagnostics::task_metrics::{self, Task};
+use crate::service::comm::{self, Command, Message};
+
+/// Where `publish` sends each record
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum TelemetryOutput {
+  Comm,
+  Log,
+  Both,
+}
+
+/// One telemetry record
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, defmt::Format)]
+pub struct Telemetry {
+  pub uptime_s: u32,
+  /// `ResetCause` as u8
+  pub reset_cause: u32,
+  pub stack_high_water: u32,
+  pub heap_used: u32,
+  pub fcs_errors: u32,
+  /// Supply voltage (VDDA) from the internal reference, 0 if not measured
+  pub vdda_mv: u32,
+  /// Longest interrupt-disabled window in the last second
+  pub cs_max_us: u32,
+  /// Executor time not spent in instrumented task iterations in the last second
+  pub idle_pct: u32,
+  /// Longest serial RX, comm consumer and telemetry task iteration in the last second
+  pub serial_rx_max_us: u32,
+  pub comm_rx_max_us: u32,
+  pub telemetry_max_us: u32,
+  /// Time the core spent in WFI in the last second, and the resulting average supply current
+  pub sleep_pct: u32,
+  pub current_ua: u32,
+}
+
+impl Telemetry {
+  /// Encoded size of a `Command::Telemetry` payload
+  pub const LEN: usize = 13 * 4;
+
+  /// Collect the current figures (`vdda_mv` is measured by the caller, which owns the ADC)
+  pub fn gather(vdda_mv: u32) -> Self {
+    Self {
+      uptime_s: embassy_time::Instant::now().as_secs() as u32,
+      reset_cause: reset_cause() as u32,
+      stack_high_water: stack_high_water(),
+      #[cfg(feature = "alloc")]
+      heap_used: crate::common::heap::usage().used as u32,
+      #[cfg(not(feature = "alloc"))]
+      heap_used: 0,
+      fcs_errors: comm::stats().fcs_errors,
+      vdda_mv,
+      #[cfg(feature = "cs_monitor")]
+      cs_max_us: crate::diagnostics::cs_monitor::last_second_max_us(),
+      #[cfg(not(feature = "cs_monitor"))]
+      cs_max_us: 0,
+      #[cfg(feature = "task_metrics")]
+      idle_pct: task_metrics::idle_pct(),
+      #[cfg(feature = "task_metrics")]
+      serial_rx_max_us: task_metrics::metrics(Task::SerialRx).last_second_max_us,
+      #[cfg(feature = "task_metrics")]
+      comm_rx_max_us: task_metrics::metrics(Task::CommRx).last_second_max_us,
+      #[cfg(feature = "task_metrics")]
+      telemetry_max_us: task_metrics::metrics(Task::Telemetry).last_second_max_us,
+      #[cfg(not(feature = "task_metrics"))]
+      idle_pct: 0,
+      #[cfg(not(feature = "task_metrics"))]
+      serial_rx_max_us: 0,
+      #[cfg(not(feature = "task_metrics"))]
+      comm_rx_max_us: 0,
+      #[cfg(not(feature = "task_metrics"))]
+      telemetry_max_us: 0,
+      sleep_pct: power::sleep_pct(),
+      current_ua: power::average_current_ua(),
+    }
+  }
+
+  /// Encode as the `Command::Telemetry` payload
+  pub fn to_bytes(&self) -> [u8; Self::LEN] {
+    let mut out = [0u8; Self::LEN];
+    let fields = [
+      self.uptime_s,
+      self.reset_cause,
+      self.stack_high_water,
+      self.heap_used,
+      self.fcs_errors,
+      self.vdda_mv,
+      self.cs_max_us,
+      self.idle_pct,
+      self.serial_rx_max_us,
+      self.comm_rx_max_us,
+      self.telemetry_max_us,
+      self.sleep_pct,
+      self.current_ua,
+    ];
+    for (chunk, value) in out.chunks_exact_mut(4).zip(fields) {
+      chunk.copy_from_slice(&value.to_le_bytes());
+    }
+    out
+  }
+}
+
+static LATEST: Mutex<CriticalSectionRawMutex, RefCell<Telemetry>> = Mutex::new(RefCell::new(Telemetry {
+  uptime_s: 0,
+  reset_cause: 0,
+  stack_high_water: 0,
+  heap_used: 0,
+  fcs_errors: 0,
+  vdda_mv: 0,
+  cs_max_us: 0,
+  idle_pct: 0,
+  serial_rx_max_us: 0,
+  comm_rx_max_us: 0,
+  telemetry_max_us: 0,
+  sleep_pct: 0,
+  current_ua: 0,
+}));
+// A record is waiting for `comm::send_pending`
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Store `record` as the latest and send it to `output`
+pub fn publish(record: Telemetry, output: TelemetryOutput) {
+  LATEST.lock(|latest| *latest.borrow_mut() = record);
+  if output != TelemetryOutput::Comm {
+    defmt::info!("Telemetry: {}", record);
+  }
+  if output != TelemetryOutput::Log {
+    PENDING.store(true, Ordering::Relaxed);
+  }
+}
+
+/// Most recently published record
+pub fn latest() -> Telemetry {
+  LATEST.lock(|latest| *latest.borrow())
+}
+
+/// Unsolicited record for the host, if one is due (drained by `comm::send_pending`)
+pub fn next_notification() -> Option<Message> {
+  PENDING.swap(false, Ordering::Relaxed).then(|| Message::new(Command::Telemetry, &latest().to_bytes()))
+}
@@ -0,0 +1,156 @@
+//! Software timers: one-shot and repeating callbacks multiplexed on one task
+// Protocol timeouts (ACK retransmit, link keepalive, reply deadlines) need many short-lived
+// timers; spawning a task per timeout costs a task slot each. Here any task arms a timer by an
+// id it chooses and one `soft_timer_task` sleeps until the earliest deadline and calls the
+// callbacks that are due:
+//   timers::one_shot(RETRY_TIMER, 500, on_retry_timeout).ok();   // fires once in 500 ms
+//   timers::repeating(KEEPALIVE_TIMER, 2_000, send_keepalive).ok();
+//   timers::reschedule(RETRY_TIMER, 500);                          // push the deadline back
+//   timers::disarm(RETRY_TIMER);                                   // ACK arrived in time
+// Arming an id that is already armed replaces it. Repeating timers keep their phase (the next
+// deadline is the previous one plus the period, not the callback time plus the period).
+//
+// Callbacks run in `soft_timer_task` with no lock held, so they may arm, disarm and reschedule
+// timers (their own included), but must stay short: signal a task, queue a message, set a flag.
+
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, with_deadline};
+use heapless::Vec;
+
+/// Timers that can be armed at the same time
+pub const SOFT_TIMER_MAX: usize = 16;
+
+/// Called with the timer id when it expires
+pub type TimerCallback = fn(u8);
+
+/// No free timer slot
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub struct TimersFull;
+
+#[derive(Copy, Clone)]
+struct SoftTimer {
+  id: u8,
+  deadline: Instant,
+  // None: one-shot
+  period: Option<Duration>,
+  callback: TimerCallback,
+}
+
+static TIMERS: Mutex<CriticalSectionRawMutex, RefCell<Vec<SoftTimer, SOFT_TIMER_MAX>>> = Mutex::new(RefCell::new(Vec::new()));
+// Wakes `soft_timer_task` when the set of deadlines changed
+static CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Call `callback` once, `delay_ms` from now
+pub fn one_shot(id: u8, delay_ms: u32, callback: TimerCallback) -> Result<(), TimersFull> {
+  arm(id, Duration::from_millis(delay_ms as u64), None, callback)
+}
+
+/// Call `callback` every `period_ms` (first call one period from now)
+pub fn repeating(id: u8, period_ms: u32, callback: TimerCallback) -> Result<(), TimersFull> {
+  let period = Duration::from_millis(period_ms.max(1) as u64);
+  arm(id, period, Some(period), callback)
+}
+
+fn arm(id: u8, delay: Duration, period: Option<Duration>, callback: TimerCallback) -> Result<(), TimersFull> {
+  let timer = SoftTimer {
+    id,
+    deadline: Instant::now() + delay,
+    period,
+    callback,
+  };
+  TIMERS.lock(|timers| {
+    let mut timers = timers.borrow_mut();
+    match timers.iter_mut().find(|t| t.id == id) {
+      Some(slot) => *slot = timer,
+      None => timers.push(timer).map_err(|_| TimersFull)?,
+    }
+    Ok(())
+  })?;
+  CHANGED.signal(());
+  Ok(())
+}
+
+/// Stop timer `id`; false if it was not armed
+pub fn disarm(id: u8) -> bool {
+  let removed = TIMERS.lock(|timers| {
+    let mut timers = timers.borrow_mut();
+    let index = timers.iter().position(|t| t.id == id);
+    index.map(|index| timers.swap_remove(index)).is_some()
+  });
+  if removed {
+    CHANGED.signal(());
+  }
+  removed
+}
+
+/// Move timer `id`'s next expiry to `delay_ms` from now (a repeating one keeps its period from
+/// there); false if it is not armed
+pub fn reschedule(id: u8, delay_ms: u32) -> bool {
+  let found = TIMERS.lock(|timers| match timers.borrow_mut().iter_mut().find(|t| t.id == id) {
+    Some(timer) => {
+      timer.deadline = Instant::now() + Duration::from_millis(delay_ms as u64);
+      true
+    }
+    None => false,
+  });
+  if found {
+    CHANGED.signal(());
+  }
+  found
+}
+
+pub fn is_armed(id: u8) -> bool {
+  TIMERS.lock(|timers| timers.borrow().iter().any(|t| t.id == id))
+}
+
+/// Time left until timer `id` expires (0 when due), `None` if it is not armed
+pub fn remaining_ms(id: u8) -> Option<u32> {
+  let deadline = TIMERS.lock(|timers| timers.borrow().iter().find(|t| t.id == id).map(|t| t.deadline))?;
+  Some(deadline.saturating_duration_since(Instant::now()).as_millis() as u32)
+}
+
+// Take the callbacks that are due: one-shots are removed, repeating timers move on by whole
+// periods (skipping any they missed)
+fn take_due(now: Instant) -> Vec<(u8, TimerCallback), SOFT_TIMER_MAX> {
+  TIMERS.lock(|timers| {
+    let mut timers = timers.borrow_mut();
+    let mut due = Vec::new();
+    timers.retain_mut(|t| {
+      if t.deadline > now {
+        return true;
+      }
+      due.push((t.id, t.callback)).ok();
+      match t.period {
+        Some(period) => {
+          while t.deadline <= now {
+            t.deadline += period;
+          }
+          true
+        }
+        None => false,
+      }
+    });
+    due
+  })
+}
+
+/// Run the software timers (spawn once)
+#[embassy_executor::task]
+pub async fn soft_timer_task() {
+  loop {
+    for (id, callback) in take_due(Instant::now()) {
+      callback(id);
+    }
+    let next = TIMERS.lock(|timers| timers.borrow().iter().map(|t| t.deadline).min());
+    match next {
+      // Either the deadline passes or the timers changed; both mean look again
+      Some(deadline) => {
+        let _ = with_deadline(deadline, CHANGED.wait()).await;
+      }
+      None => CHANGED.wait().await,
+    }
+  }
+}
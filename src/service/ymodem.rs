@@ -0,0 +1,243 @@
+//! YMODEM/XMODEM file upload into flash staging or the filesystem
+// A simpler alternative to the HDLC DFU transfer: a terminal program sends the file with YMODEM
+// (or XMODEM-CRC) over the shell's serial line (`rb` shell command), no host tool needed.
+// `YmodemTransfer` runs `protocol::ymodem::YmodemReceiver` and hands each file to a `FileSink`:
+//   - `FlashStaging`: raw image into a `NorFlash` region (e.g. a `flash::Sectors` run), erased
+//     sector by sector ahead of the writes; check it with `dfu::verify_staged(flash, 0, len())`
+//   - `FsSink` (feature `fs`): a file of the same name in the littlefs filesystem
+// A refused file or a failed write cancels the transfer. Data already written stays where it is
+// (a partial staging image fails verification; a partial fs file keeps its name).
+
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::hardware::serial;
+use crate::protocol::ymodem::{YmodemEvent, YmodemReceiver};
+
+/// Name given to an XMODEM upload (the protocol carries none)
+pub const YMODEM_DEFAULT_NAME: &str = "upload.bin";
+// Largest flash write granularity `FlashStaging` pads a file's tail to
+const STAGING_PAD_MAX: usize = 32;
+
+/// Destination of uploaded files
+pub trait FileSink {
+  /// A file starts (`size` if the sender gave one); false refuses it and cancels the transfer
+  fn open(&mut self, name: &str, size: Option<u32>) -> bool;
+
+  /// Next bytes of the current file; false cancels the transfer
+  fn write(&mut self, data: &[u8]) -> bool;
+
+  /// The current file is complete; false cancels the transfer
+  fn close(&mut self) -> bool {
+    true
+  }
+}
+
+/// Where `YmodemTransfer` stands
+#[derive(Copy, Clone, Debug, Eq, PartialEq, defmt::Format)]
+pub enum TransferStatus {
+  Receiving,
+  /// Batch complete: files closed and total bytes written
+  Done {
+    files: u16,
+    bytes: u32,
+  },
+  /// Cancelled by either side after `bytes` were written
+  Aborted {
+    bytes: u32,
+  },
+}
+
+/// One YMODEM/XMODEM upload in progress
+pub struct YmodemTransfer {
+  rx: YmodemReceiver,
+  open: bool,
+  files: u16,
+  bytes: u32,
+  status: TransferStatus,
+}
+
+impl Default for YmodemTransfer {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl YmodemTransfer {
+  pub fn new() -> Self {
+    Self {
+      rx: YmodemReceiver::new(),
+      open: false,
+      files: 0,
+      bytes: 0,
+      status: TransferStatus::Receiving,
+    }
+  }
+
+  pub fn status(&self) -> TransferStatus {
+    self.status
+  }
+
+  /// Ask the sender to start ('C'); repeated by `timeout` until it does
+  pub fn start<W: embedded_io::Write>(&mut self, out: &mut W) {
+    serial::write(out, &self.rx.reply());
+  }
+
+  /// Feed one received byte, storing completed data in `sink` and answering the sender on `out`
+  pub fn push<W: embedded_io::Write>(&mut self, byte: u8, sink: &mut dyn FileSink, out: &mut W) {
+    if self.status != TransferStatus::Receiving {
+      return;
+    }
+    let stored = match self.rx.push(byte) {
+      Some(event) => store(event, sink, &mut self.open, &mut self.files, &mut self.bytes),
+      None => Some(true),
+    };
+    self.answer(stored, out);
+  }
+
+  /// The line stayed quiet for `YMODEM_TIMEOUT_MS`
+  pub fn timeout<W: embedded_io::Write>(&mut self, out: &mut W) {
+    if self.status != TransferStatus::Receiving {
+      return;
+    }
+    let stored = self.rx.timeout().map(|_| false);
+    self.answer(stored.or(Some(true)), out);
+  }
+
+  // Reply to the sender, or cancel if the event could not be stored (`stored`: None once the
+  // batch is complete)
+  fn answer<W: embedded_io::Write>(&mut self, stored: Option<bool>, out: &mut W) {
+    if stored == Some(false) && !self.rx.is_finished() {
+      serial::write(out, self.rx.cancel());
+    } else {
+      serial::write(out, &self.rx.reply());
+    }
+    if stored == Some(false) {
+      self.status = TransferStatus::Aborted { bytes: self.bytes };
+    } else if stored.is_none() || self.rx.is_finished() {
+      // An XMODEM transfer ends with its file
+      self.status = TransferStatus::Done {
+        files: self.files,
+        bytes: self.bytes,
+      };
+    }
+  }
+}
+
+// Act on a receiver event: Some(false) to cancel, None once the batch is complete
+fn store(event: YmodemEvent<'_>, sink: &mut dyn FileSink, open: &mut bool, files: &mut u16, bytes: &mut u32) -> Option<bool> {
+  match event {
+    YmodemEvent::File { name, size } => {
+      *open = sink.open(name, size);
+      Some(*open)
+    }
+    YmodemEvent::Data(data) => {
+      if !*open {
+        // XMODEM: no header announced the file
+        *open = sink.open("", None);
+      }
+      let written = *open && sink.write(data);
+      if written {
+        *bytes += data.len() as u32;
+      }
+      Some(written)
+    }
+    YmodemEvent::FileEnd => {
+      let closed = !*open || sink.close();
+      *files += u16::from(*open);
+      *open = false;
+      Some(closed)
+    }
+    YmodemEvent::Done => None,
+    YmodemEvent::Aborted => Some(false),
+  }
+}
+
+/// Upload into a raw `NorFlash` region from offset 0 (a later file in the batch replaces it)
+pub struct FlashStaging<'f, F: NorFlash> {
+  flash: &'f mut F,
+  len: u32,
+  // Offset up to which the region is erased
+  erased: u32,
+}
+
+impl<'f, F: NorFlash> FlashStaging<'f, F> {
+  pub fn new(flash: &'f mut F) -> Self {
+    Self { flash, len: 0, erased: 0 }
+  }
+
+  /// Bytes of the last file written (for `dfu::verify_staged`)
+  pub fn len(&self) -> u32 {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+}
+
+impl<F: NorFlash> FileSink for FlashStaging<'_, F> {
+  fn open(&mut self, _name: &str, size: Option<u32>) -> bool {
+    self.len = 0;
+    self.erased = 0;
+    size.is_none_or(|size| size as usize <= self.flash.capacity())
+  }
+
+  fn write(&mut self, data: &[u8]) -> bool {
+    // Only the last piece of a file may be unaligned: its tail is padded with erased bytes
+    let body = data.len() - data.len() % F::WRITE_SIZE;
+    let padded = if body < data.len() { body + F::WRITE_SIZE } else { body };
+    let end = self.len as usize + padded;
+    if end > self.flash.capacity() || F::WRITE_SIZE > STAGING_PAD_MAX {
+      return false;
+    }
+    while (self.erased as usize) < end {
+      if self.flash.erase(self.erased, self.erased + F::ERASE_SIZE as u32).is_err() {
+        return false;
+      }
+      self.erased += F::ERASE_SIZE as u32;
+    }
+    if self.flash.write(self.len, &data[..body]).is_err() {
+      return false;
+    }
+    if body < data.len() {
+      let mut tail = [0xFF; STAGING_PAD_MAX];
+      tail[..data.len() - body].copy_from_slice(&data[body..]);
+      if self.flash.write(self.len + body as u32, &tail[..F::WRITE_SIZE]).is_err() {
+        return false;
+      }
+    }
+    self.len += data.len() as u32;
+    true
+  }
+}
+
+/// Upload into the littlefs filesystem under the sender's file name (feature `fs`)
+#[cfg(feature = "fs")]
+pub struct FsSink<'s, 'a, S: littlefs2::driver::Storage> {
+  fs: &'s crate::service::fs::Fs<'a, S>,
+  name: heapless::String<{ crate::protocol::ymodem::YMODEM_NAME_MAX }>,
+}
+
+#[cfg(feature = "fs")]
+impl<'s, 'a, S: littlefs2::driver::Storage> FsSink<'s, 'a, S> {
+  pub fn new(fs: &'s crate::service::fs::Fs<'a, S>) -> Self {
+    Self {
+      fs,
+      name: heapless::String::new(),
+    }
+  }
+}
+
+#[cfg(feature = "fs")]
+impl<S: littlefs2::driver::Storage> FileSink for FsSink<'_, '_, S> {
+  fn open(&mut self, name: &str, _size: Option<u32>) -> bool {
+    self.name.clear();
+    let name = if name.is_empty() { YMODEM_DEFAULT_NAME } else { name };
+    // Replace any old file of that name
+    self.name.push_str(name).is_ok() && self.fs.write(name, &[]).is_ok()
+  }
+
+  fn write(&mut self, data: &[u8]) -> bool {
+    self.fs.append(&self.name, data).is_ok()
+  }
+}
@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+use embassy_stm32::adc::SampleTime;
+use embassy_stm32_starter::board::BoardConfig;
+use embassy_stm32_starter::hardware::adc::{self, ADC_MAX, AdcReader, RATIO_ONE};
+
+#[defmt_test::tests]
+mod tests {
+  use super::*;
+
+  #[init]
+  fn init() -> AdcReader<'static> {
+    embassy_stm32::init(Default::default());
+    let mut adc = AdcReader::new(BoardConfig::init_supply_adc());
+    adc.set_sample_time(SampleTime::CYCLES480);
+    adc
+  }
+
+  #[test]
+  fn vrefint_calibration_is_plausible() {
+    // VREFINT is 1.18-1.24 V, read at 3.3 V
+    let cal = adc::vrefint_cal() as u32;
+    defmt::assert!((1464..=1540).contains(&cal), "VREFINT_CAL {}", cal);
+  }
+
+  #[test]
+  fn supply_within_operating_range(adc: &mut AdcReader<'static>) {
+    let vdda = adc.read_vdda_mv();
+    defmt::info!("VDDA {} mV", vdda);
+    defmt::assert!((1700..=3600).contains(&vdda), "VDDA {} mV", vdda);
+  }
+
+  #[test]
+  fn vrefint_is_stable(adc: &mut AdcReader<'static>) {
+    let (a, b) = (adc.read_vrefint(), adc.read_vrefint());
+    defmt::assert!(a.abs_diff(b) < 16, "{} vs {}", a, b);
+  }
+
+  #[test]
+  fn fixed_point_helpers() {
+    defmt::assert_eq!(adc::ratio_q16(1000, 1000), RATIO_ONE);
+    defmt::assert_eq!(adc::ratio_q16(1024, 4096), RATIO_ONE / 4);
+    defmt::assert_eq!(adc::ratio_q16(1, 0), u32::MAX);
+    defmt::assert_eq!(adc::differential_q16(1000, 1500, 2000), -(RATIO_ONE as i32) / 4);
+    defmt::assert_eq!(adc::scale_q16(RATIO_ONE / 2, 3300), 1650);
+    defmt::assert_eq!(adc::vdda_mv(1500, 1500), 3300);
+    defmt::assert_eq!(adc::vdda_mv(0, 1500), 0);
+    defmt::assert_eq!(adc::ratio_q16(ADC_MAX, ADC_MAX), RATIO_ONE);
+  }
+}
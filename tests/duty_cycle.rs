@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+
+// Windows of a 15 min period, and the comm TX path holding messages between them. The sink
+// stands in for the UART, so nothing goes out on the board's serial port.
+
+use embassy_stm32_starter::board::BoardConfig;
+use embassy_stm32_starter::service::comm::{self, Command, Message};
+use embassy_stm32_starter::service::duty_cycle::{self, DutySchedule};
+use heapless::Vec;
+
+// 20 s at the start of every 15 min, and the same 5 min later
+const SCHEDULE: DutySchedule = DutySchedule::new(900, 20, 0);
+const OFFSET: DutySchedule = DutySchedule::new(900, 20, 300);
+// Wall-clock seconds inside and outside a window of `SCHEDULE`
+const IN_WINDOW_S: u32 = 10 * 900 + 5;
+const BETWEEN_WINDOWS_S: u32 = 10 * 900 + 100;
+
+// Collects what the TX owner writes
+struct Sink(Vec<u8, 512>);
+
+impl embedded_io::ErrorType for Sink {
+  type Error = core::convert::Infallible;
+}
+
+impl embedded_io::Write for Sink {
+  fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+    let len = buf.len().min(self.0.capacity() - self.0.len());
+    self.0.extend_from_slice(&buf[..len]).ok();
+    Ok(len)
+  }
+
+  fn flush(&mut self) -> Result<(), Self::Error> {
+    Ok(())
+  }
+}
+
+#[defmt_test::tests]
+mod tests {
+  use super::*;
+
+  #[init]
+  fn init() {
+    embassy_stm32::init(BoardConfig::embassy_config());
+  }
+
+  #[test]
+  fn windows_follow_the_period() {
+    defmt::assert!(SCHEDULE.is_open(900));
+    defmt::assert!(SCHEDULE.is_open(919));
+    defmt::assert!(!SCHEDULE.is_open(920));
+    defmt::assert_eq!(SCHEDULE.until_close(905), 15);
+    defmt::assert_eq!(SCHEDULE.until_open(920), 880);
+    defmt::assert_eq!(SCHEDULE.until_open(905), 0);
+    defmt::assert!(OFFSET.is_open(1200));
+    defmt::assert!(!OFFSET.is_open(900));
+    defmt::assert_eq!(OFFSET.until_open(1199), 1);
+    // A window as long as the period never closes
+    defmt::assert_eq!(DutySchedule::new(60, 60, 0).until_close(30), u32::MAX);
+  }
+
+  #[test]
+  fn link_is_open_until_started() {
+    duty_cycle::stop();
+    duty_cycle::sync_clock(BETWEEN_WINDOWS_S);
+    defmt::assert!(!duty_cycle::is_closed());
+    duty_cycle::start(SCHEDULE);
+    defmt::assert!(duty_cycle::is_closed());
+    duty_cycle::sync_clock(IN_WINDOW_S);
+    defmt::assert!(!duty_cycle::is_closed());
+    duty_cycle::stop();
+  }
+
+  #[test]
+  fn messages_wait_for_the_next_window() {
+    let mut sink = Sink(Vec::new());
+    duty_cycle::sync_clock(BETWEEN_WINDOWS_S);
+    duty_cycle::start(SCHEDULE);
+    defmt::assert!(comm::try_send(Message::new(Command::Raw, b"reading")).is_ok());
+    comm::send_pending(&mut sink);
+    defmt::assert!(sink.0.is_empty());
+    defmt::assert_eq!(duty_cycle::pending(), 1);
+    // Held, not written: a reboot waits for it
+    defmt::assert!(!comm::tx_idle());
+
+    duty_cycle::sync_clock(IN_WINDOW_S);
+    comm::send_pending(&mut sink);
+    defmt::assert!(!sink.0.is_empty());
+    defmt::assert_eq!(duty_cycle::pending(), 0);
+    defmt::assert!(comm::tx_idle());
+    duty_cycle::stop();
+  }
+}
@@ -0,0 +1,133 @@
+#![no_std]
+#![no_main]
+
+use embassy_stm32_starter::protocol::hdlc::{self, HdlcError};
+use heapless::Vec;
+
+// Host-generated corpus: frames built on the PC side (PPP FCS-16, little-endian, 0x7E/0x7D escaping)
+// wrapping Comms messages (9-byte header + payload).
+// Ping (id=1, no payload)
+const PING_FRAME: &[u8] = &[0x7E, 0x03, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1E, 0xFC, 0x7E];
+// Raw (id=2) payload [0xD8, 0x01]
+const RAW_FRAME: &[u8] = &[0x7E, 0x04, 0x00, 0x02, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0xD8, 0x01, 0xC0, 0xF2, 0x7E];
+// Raw (id=3) payload [0x7E, 0x7D, 0x00, 0x20] - flag/escape bytes in payload
+const ESCAPED_FRAME: &[u8] = &[
+  0x7E, 0x04, 0x00, 0x03, 0x01, 0x00, 0x00, 0x00, 0x04, 0x00, 0x7D, 0x5E, 0x7D, 0x5D, 0x00, 0x20, 0xC5, 0xB1, 0x7E,
+];
+// Raw (id=0xDF) payload [0xDF, 0x00] - FCS low byte 0x7E is escaped
+const ESCAPED_FCS_FRAME: &[u8] = &[0x7E, 0x04, 0x00, 0xDF, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0xDF, 0x00, 0xB3, 0x7D, 0x5E, 0x7E];
+// Ping + Raw sharing one flag between frames
+const SHARED_FLAGS: &[u8] = &[
+  0x7E, 0x03, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1E, 0xFC, 0x7E, 0x04, 0x00, 0x02, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0xD8, 0x01, 0xC0, 0xF2, 0x7E,
+];
+// Ping surrounded by repeated flags
+const CONSECUTIVE_FLAGS: &[u8] = &[0x7E, 0x7E, 0x7E, 0x03, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1E, 0xFC, 0x7E, 0x7E];
+// Line noise (incl. a stray escape) before a Raw frame
+const LEADING_NOISE: &[u8] = &[
+  0x00, 0x55, 0xAA, 0x7D, 0x7E, 0x04, 0x00, 0x02, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0xD8, 0x01, 0xC0, 0xF2, 0x7E,
+];
+// Partial frame aborted by escape+flag, followed by a Ping
+const ABORTED_FRAME: &[u8] = &[0x7E, 0x03, 0x00, 0x7D, 0x7E, 0x03, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1E, 0xFC, 0x7E];
+// Raw frame with a corrupted header byte (FCS no longer matches)
+#[cfg(feature = "hdlc_fcs")]
+const BAD_FCS_FRAME: &[u8] = &[0x7E, 0x04, 0x00, 0x03, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0xD8, 0x01, 0xC0, 0xF2, 0x7E];
+
+const PING: &[u8] = &[0x03, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00];
+const RAW: &[u8] = &[0x04, 0x00, 0x02, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0xD8, 0x01];
+const ESCAPED: &[u8] = &[0x04, 0x00, 0x03, 0x01, 0x00, 0x00, 0x00, 0x04, 0x00, 0x7E, 0x7D, 0x00, 0x20];
+const ESCAPED_FCS: &[u8] = &[0x04, 0x00, 0xDF, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0xDF, 0x00];
+
+type Buf = Vec<u8, 128>;
+
+/// Feed `chunks` through the deframer and collect every decoded frame (or error)
+fn deframe_all(chunks: &[&[u8]]) -> Vec<Result<Buf, HdlcError>, 4> {
+  let mut rx: Buf = Vec::new();
+  let mut out: Buf = Vec::new();
+  let mut results = Vec::new();
+  for chunk in chunks {
+    rx.extend_from_slice(chunk).ok();
+    loop {
+      match hdlc::hdlc_deframe(&mut rx, &mut out) {
+        Ok(()) => results.push(Ok(out.clone())).ok(),
+        Err(HdlcError::Incomplete) => break,
+        Err(e) => results.push(Err(e)).ok(),
+      };
+    }
+  }
+  results
+}
+
+/// Assert that `chunks` decode to exactly the `expected` payloads
+fn check(chunks: &[&[u8]], expected: &[&[u8]]) {
+  let results = deframe_all(chunks);
+  defmt::assert_eq!(results.len(), expected.len(), "frames decoded");
+  for (result, payload) in results.iter().zip(expected) {
+    defmt::assert!(matches!(result, Ok(p) if p.as_slice() == *payload));
+  }
+}
+
+#[defmt_test::tests]
+mod tests {
+  use super::*;
+
+  #[init]
+  fn init() {
+    embassy_stm32::init(Default::default());
+  }
+
+  /// The encoder must reproduce the host framing byte-for-byte
+  #[test]
+  fn frame_encoding() {
+    let mut framed: Vec<u8, 64> = Vec::new();
+    hdlc::hdlc_frame(RAW, &mut framed);
+    defmt::assert_eq!(framed.as_slice(), RAW_FRAME);
+  }
+
+  #[test]
+  fn single_frame() {
+    check(&[PING_FRAME], &[PING]);
+  }
+
+  #[test]
+  fn escaped_payload() {
+    check(&[ESCAPED_FRAME], &[ESCAPED]);
+  }
+
+  #[test]
+  fn escaped_fcs() {
+    check(&[ESCAPED_FCS_FRAME], &[ESCAPED_FCS]);
+  }
+
+  #[test]
+  fn shared_open_close_flag() {
+    check(&[SHARED_FLAGS], &[PING, RAW]);
+  }
+
+  #[test]
+  fn consecutive_flags() {
+    check(&[CONSECUTIVE_FLAGS], &[PING]);
+  }
+
+  #[test]
+  fn leading_noise() {
+    check(&[LEADING_NOISE], &[RAW]);
+  }
+
+  #[test]
+  fn aborted_frame() {
+    check(&[ABORTED_FRAME], &[PING]);
+  }
+
+  #[test]
+  fn frame_split_across_reads() {
+    check(&[&RAW_FRAME[..5], &RAW_FRAME[5..]], &[RAW]);
+  }
+
+  #[cfg(feature = "hdlc_fcs")]
+  #[test]
+  fn fcs_mismatch_then_recovery() {
+    let results = deframe_all(&[BAD_FCS_FRAME, RAW_FRAME]);
+    defmt::assert!(matches!(results.first(), Some(Err(HdlcError::FcsMismatch { .. }))));
+    defmt::assert!(matches!(results.get(1), Some(Ok(p)) if p.as_slice() == RAW));
+  }
+}
@@ -0,0 +1,95 @@
+//! ESP-AT response parsing
+//!
+//! Run with `cd tests/host && cargo test`.
+
+use embassy_stm32_starter_host_tests::at::{AtEvent, AtParser};
+
+/// Owned copy of an event, so a whole transcript can be collected
+#[derive(Debug, PartialEq)]
+enum Event {
+  Ok,
+  Error,
+  SendOk,
+  SendFail,
+  Prompt,
+  Closed,
+  Data(Vec<u8>),
+  Line(String),
+}
+
+fn events<const N: usize>(parser: &mut AtParser<N>, input: &[u8]) -> Vec<Event> {
+  input
+    .iter()
+    .filter_map(|&b| {
+      parser.push(b).map(|e| match e {
+        AtEvent::Ok => Event::Ok,
+        AtEvent::Error => Event::Error,
+        AtEvent::SendOk => Event::SendOk,
+        AtEvent::SendFail => Event::SendFail,
+        AtEvent::Prompt => Event::Prompt,
+        AtEvent::Closed => Event::Closed,
+        AtEvent::Data(d) => Event::Data(d.to_vec()),
+        AtEvent::Line(l) => Event::Line(String::from_utf8(l.to_vec()).unwrap()),
+      })
+    })
+    .collect()
+}
+
+#[test]
+fn final_results_and_info_lines() {
+  let mut parser: AtParser<64> = AtParser::new();
+  let out = events(&mut parser, b"AT+CWJAP=\"lab\",\"pw\"\r\r\nWIFI CONNECTED\r\nWIFI GOT IP\r\n\r\nOK\r\nERROR\r\nFAIL\r\n");
+  assert_eq!(
+    out,
+    [
+      Event::Line("AT+CWJAP=\"lab\",\"pw\"".into()),
+      Event::Line("WIFI CONNECTED".into()),
+      Event::Line("WIFI GOT IP".into()),
+      Event::Ok,
+      Event::Error,
+      Event::Error,
+    ]
+  );
+}
+
+#[test]
+fn cipsend_prompt_and_result() {
+  let mut parser: AtParser<64> = AtParser::new();
+  assert_eq!(events(&mut parser, b"\r\nOK\r\n> "), [Event::Ok, Event::Prompt]);
+  assert_eq!(
+    events(&mut parser, b"\r\nRecv 5 bytes\r\n\r\nSEND OK\r\n"),
+    [Event::Line("Recv 5 bytes".into()), Event::SendOk]
+  );
+  assert_eq!(events(&mut parser, b"SEND FAIL\r\n0,CLOSED\r\nCLOSED\r\n"), [Event::SendFail, Event::Closed, Event::Closed]);
+}
+
+#[test]
+fn ipd_data_is_counted_not_parsed() {
+  let mut parser: AtParser<64> = AtParser::new();
+  // The data contains CRLF, "OK" and a colon; none of it is interpreted
+  let out = events(&mut parser, b"\r\n+IPD,8:a\r\nOK\r:b\r\nOK\r\n+IPD,0,2:\x7e\x7e");
+  assert_eq!(out, [Event::Data(b"a\r\nOK\r:b".to_vec()), Event::Ok, Event::Data(vec![0x7E, 0x7E])]);
+}
+
+#[test]
+fn long_ipd_arrives_in_parts() {
+  let mut parser: AtParser<8> = AtParser::new();
+  let out = events(&mut parser, b"+IPD,20:0123456789abcdefghijOK\r\n");
+  assert_eq!(
+    out,
+    [
+      Event::Data(b"01234567".to_vec()),
+      Event::Data(b"89abcdef".to_vec()),
+      Event::Data(b"ghij".to_vec()),
+      Event::Ok
+    ]
+  );
+}
+
+#[test]
+fn overlong_lines_are_dropped() {
+  let mut parser: AtParser<8> = AtParser::new();
+  assert_eq!(events(&mut parser, b"+CIFSR:STAIP,\"192.168.1.20\"\r\nOK\r\n"), [Event::Ok]);
+  // A malformed length is not data
+  assert_eq!(events(&mut parser, b"+IPD,x:\r\n"), [Event::Line("+IPD,x:".into())]);
+}
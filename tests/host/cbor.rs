@@ -0,0 +1,66 @@
+//! Typed CBOR payloads
+//!
+//! Run with `cd tests/host && cargo test`.
+
+use embassy_stm32_starter_host_tests::cbor::{self, CborError, Decode, Encode};
+use embassy_stm32_starter_host_tests::message::{COMMS_MAX_PAYLOAD, Command, CommsFrameBuf, Message};
+use heapless::Vec;
+
+#[derive(Debug, PartialEq, Encode, Decode)]
+struct PinCommand {
+  #[n(0)]
+  pin: u8,
+  #[n(1)]
+  on: bool,
+}
+
+#[derive(Debug, PartialEq, Encode, Decode)]
+struct Labelled<'a> {
+  #[n(0)]
+  id: u16,
+  #[b(1)]
+  label: &'a str,
+  #[n(2)]
+  scale: Option<i32>,
+}
+
+#[test]
+fn struct_round_trips_through_a_message() {
+  let cmd = PinCommand { pin: 8, on: true };
+  let msg = cbor::message(Command::Raw, &cmd).unwrap();
+  let mut buf: CommsFrameBuf = Vec::new();
+  msg.encode(&mut buf);
+  let parsed = Message::parse(&buf).unwrap();
+  assert_eq!(cbor::from_payload::<PinCommand>(&parsed.payload), Ok(cmd));
+}
+
+#[test]
+fn encoding_is_compact_and_standard() {
+  // Array of two: [8, true]
+  assert_eq!(&cbor::to_payload(&PinCommand { pin: 8, on: true }).unwrap()[..], &[0x82, 0x08, 0xF5]);
+}
+
+#[test]
+fn borrowed_fields_and_missing_optionals() {
+  let value = Labelled {
+    id: 7,
+    label: "pump",
+    scale: None,
+  };
+  let payload = cbor::to_payload(&value).unwrap();
+  assert_eq!(cbor::from_payload::<Labelled>(&payload), Ok(value));
+  // An older peer that stops after `label` still decodes, with `scale` absent
+  assert_eq!(cbor::from_payload::<Labelled>(&[0x82, 0x07, 0x64, b'p', b'u', b'm', b'p']).map(|v| v.scale), Ok(None));
+}
+
+#[test]
+fn rejects_bad_input_and_oversized_values() {
+  assert_eq!(cbor::from_payload::<PinCommand>(&[0xD8, 0x01]), Err(CborError::Decode));
+  let label = "x".repeat(COMMS_MAX_PAYLOAD);
+  let value = Labelled {
+    id: 1,
+    label: &label,
+    scale: None,
+  };
+  assert_eq!(cbor::to_payload(&value), Err(CborError::TooLong));
+}
@@ -0,0 +1,42 @@
+//! Configuration blob build/validate
+
+use embassy_stm32_starter_host_tests::config_blob::{BlobWriter, CONFIG_BLOB_MAX, ConfigBlobError, sections};
+
+fn sample() -> std::vec::Vec<u8> {
+  let mut writer = BlobWriter::new();
+  writer.section(0x01, &[1, 2, 0xF4, 0x01, 1, 7, 0x82, 0]).unwrap();
+  writer.section(0x42, &[]).unwrap();
+  writer.finish().to_vec()
+}
+
+#[test]
+fn sections_roundtrip() {
+  let blob = sample();
+  let found: std::vec::Vec<(u8, &[u8])> = sections(&blob).unwrap().collect();
+  assert_eq!(found, [(0x01, &[1, 2, 0xF4, 0x01, 1, 7, 0x82, 0][..]), (0x42, &[][..])]);
+}
+
+#[test]
+fn corruption_is_detected() {
+  let mut blob = sample();
+  blob[6] ^= 0x01;
+  assert_eq!(sections(&blob).err(), Some(ConfigBlobError::Crc));
+  assert_eq!(sections(&blob[..3]).err(), Some(ConfigBlobError::Malformed));
+  let mut blob = sample();
+  blob[0] = 0;
+  assert_eq!(sections(&blob).err(), Some(ConfigBlobError::Malformed));
+}
+
+#[test]
+fn version_mismatch_is_rejected() {
+  let mut blob = BlobWriter::new().finish().to_vec();
+  blob[2] = 99;
+  assert_eq!(sections(&blob).err(), Some(ConfigBlobError::Version));
+}
+
+#[test]
+fn oversized_section_is_rejected() {
+  let mut writer = BlobWriter::new();
+  assert_eq!(writer.section(0x01, &[0; CONFIG_BLOB_MAX]), Err(ConfigBlobError::TooLarge));
+  assert!(sections(&writer.finish()).unwrap().next().is_none());
+}
@@ -0,0 +1,79 @@
+//! Device configuration record encode/decode
+
+use embassy_stm32_starter_host_tests::device_config::{DEVICE_CONFIG_MAX, DEVICE_CONFIG_USER_MAX, DeviceConfig, DeviceConfigError};
+use embassy_stm32_starter_host_tests::hdlc::fcs16_ppp;
+use heapless::Vec;
+
+fn sample() -> DeviceConfig {
+  DeviceConfig {
+    baud: 921_600,
+    device_id: 0x1234_5678,
+    flags: 0x8000_0001,
+    address: 0x12,
+    rtc_ppm_tenths: -123,
+    user: Vec::from_slice(b"site-7").unwrap(),
+  }
+}
+
+#[test]
+fn roundtrip() {
+  let config = sample();
+  let bytes = config.encode();
+  assert_eq!(DeviceConfig::decode(&bytes), Ok(config));
+  // Flash slots are read whole, so trailing erased bytes must be ignored
+  let mut slot = bytes.to_vec();
+  slot.resize(DEVICE_CONFIG_MAX, 0xFF);
+  assert_eq!(DeviceConfig::decode(&slot), Ok(sample()));
+}
+
+#[test]
+fn largest_record_fits() {
+  let mut config = sample();
+  config.user = Vec::from_slice(&[0xA5; DEVICE_CONFIG_USER_MAX]).unwrap();
+  let bytes = config.encode();
+  assert_eq!(bytes.len(), DEVICE_CONFIG_MAX);
+  assert_eq!(DeviceConfig::decode(&bytes), Ok(config));
+}
+
+#[test]
+fn rejects_corruption() {
+  let bytes = sample().encode();
+  let mut torn = bytes.to_vec();
+  torn[9] ^= 0x40;
+  assert_eq!(DeviceConfig::decode(&torn), Err(DeviceConfigError::Crc));
+  assert_eq!(DeviceConfig::decode(&bytes[..bytes.len() - 1]), Err(DeviceConfigError::Malformed));
+  assert_eq!(DeviceConfig::decode(&[0xFF; DEVICE_CONFIG_MAX]), Err(DeviceConfigError::Malformed));
+  let mut newer = bytes.to_vec();
+  newer[2] = 4;
+  assert_eq!(DeviceConfig::decode(&newer), Err(DeviceConfigError::Version));
+}
+
+#[test]
+fn decodes_version_1_records() {
+  // Version 1: no rtc_ppm field, user length right after the flags
+  let mut v1 = vec![0x0F, 0xDC, 1, 0];
+  v1.extend_from_slice(&9600u32.to_le_bytes());
+  v1.extend_from_slice(&7u32.to_le_bytes());
+  v1.extend_from_slice(&0x3u32.to_le_bytes());
+  v1.extend_from_slice(&[2, 0, 0xDE, 0xAD]);
+  let crc = fcs16_ppp(&v1);
+  v1.extend_from_slice(&crc.to_le_bytes());
+  let config = DeviceConfig::decode(&v1).unwrap();
+  assert_eq!((config.baud, config.device_id, config.flags, config.rtc_ppm_tenths), (9600, 7, 0x3, 0));
+  assert_eq!(config.user.as_slice(), &[0xDE, 0xAD]);
+  // Re-encoded as the current version, same size class
+  assert_eq!(config.encode()[2], 3);
+}
+
+#[test]
+fn version_2_records_have_no_address() {
+  // Version 2 has the current layout with byte 3 reserved: whatever is there is not an address
+  let mut v2 = sample().encode().to_vec();
+  v2.truncate(v2.len() - 2);
+  v2[2] = 2;
+  let crc = fcs16_ppp(&v2);
+  v2.extend_from_slice(&crc.to_le_bytes());
+  let config = DeviceConfig::decode(&v2).unwrap();
+  assert_eq!(config.address, 0);
+  assert_eq!(DeviceConfig { address: 0x12, ..config }, sample());
+}
@@ -0,0 +1,88 @@
+//! Heatshrink payload compression (window 8, lookahead 4)
+//!
+//! Run with `cd tests/host && cargo test`.
+
+use embassy_stm32_starter_host_tests::heatshrink::{self, HeatshrinkError};
+use embassy_stm32_starter_host_tests::message::{COMMS_COMPRESSED, COMMS_MAX_PAYLOAD, Command, CommsFrameBuf, Message, MessageRef};
+use heapless::Vec;
+
+type Payload = Vec<u8, COMMS_MAX_PAYLOAD>;
+
+// Noise grows by an eighth when compressed, so this much still fits a payload
+const NOISE_LEN: usize = COMMS_MAX_PAYLOAD * 3 / 4;
+
+fn roundtrip(input: &[u8]) -> usize {
+  let mut packed: Payload = Vec::new();
+  heatshrink::compress(input, &mut packed).unwrap();
+  let mut unpacked: Payload = Vec::new();
+  heatshrink::decompress(&packed, &mut unpacked).unwrap();
+  assert_eq!(unpacked, input);
+  packed.len()
+}
+
+#[test]
+fn bit_stream_matches_heatshrink() {
+  // Literal 'a' (1 0x61), then a 3-byte run back-reference (0, offset 1 - 1, length 3 - 1), zero padded
+  let mut packed: Payload = Vec::new();
+  heatshrink::compress(b"aaaa", &mut packed).unwrap();
+  assert_eq!(packed, [0xB0, 0x80, 0x08]);
+}
+
+#[test]
+fn roundtrips_and_shrinks_repetitive_data() {
+  assert_eq!(roundtrip(&[]), 0);
+  assert_eq!(roundtrip(b"x"), 2);
+  let log: std::vec::Vec<u8> = b"t=1024 temp=21.5 rh=40\n".iter().cycle().take(COMMS_MAX_PAYLOAD).copied().collect();
+  assert!(roundtrip(&log) < COMMS_MAX_PAYLOAD / 3);
+  let erased = [0xFF; COMMS_MAX_PAYLOAD];
+  assert!(roundtrip(&erased) < 40);
+}
+
+#[test]
+fn random_data_grows_and_overflows_a_tight_buffer() {
+  // xorshift noise: no matches, 9 bits per byte
+  let mut state = 0x2545_F491u32;
+  let noise: std::vec::Vec<u8> = (0..NOISE_LEN)
+    .map(|_| {
+      state ^= state << 13;
+      state ^= state >> 17;
+      state ^= state << 5;
+      state as u8
+    })
+    .collect();
+  assert!(roundtrip(&noise) > noise.len());
+  let mut tight: Vec<u8, NOISE_LEN> = Vec::new();
+  assert_eq!(heatshrink::compress(&noise, &mut tight), Err(HeatshrinkError::Overflow));
+}
+
+#[test]
+fn rejects_corrupt_streams() {
+  let mut out: Payload = Vec::new();
+  // Back-reference as the first item: nothing to copy from
+  assert_eq!(heatshrink::decompress(&[0x00, 0x00], &mut out), Err(HeatshrinkError::BadReference));
+  // A run longer than the output buffer
+  let mut small: Vec<u8, 4> = Vec::new();
+  assert_eq!(heatshrink::decompress(&[0xB0, 0x80, 0x3C], &mut small), Err(HeatshrinkError::Overflow));
+}
+
+#[test]
+fn compressed_frame_parses_and_roundtrips() {
+  let log: std::vec::Vec<u8> = b"t=1024 temp=21.5 rh=40\n".iter().cycle().take(COMMS_MAX_PAYLOAD).copied().collect();
+  let mut packed: Payload = Vec::new();
+  heatshrink::compress(&log, &mut packed).unwrap();
+  let mut msg = Message::new(u16::from(Command::Raw) | COMMS_COMPRESSED, &packed);
+  msg.id = 7;
+  let mut frame = CommsFrameBuf::new();
+  msg.encode(&mut frame);
+
+  let parsed = MessageRef::parse(&frame).unwrap();
+  assert_eq!(parsed.command, 0x8004);
+  assert_eq!(Command::try_from(parsed.command & !COMMS_COMPRESSED), Ok(Command::Raw));
+  let mut unpacked: Payload = Vec::new();
+  heatshrink::decompress(parsed.payload, &mut unpacked).unwrap();
+  assert_eq!(unpacked, log[..]);
+  // Only the flag is let through: an unknown command stays unknown when flagged
+  let mut bad = frame.clone();
+  bad[1] = 0xFF;
+  assert!(MessageRef::parse(&bad).is_err());
+}
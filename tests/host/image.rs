@@ -0,0 +1,84 @@
+//! Signed firmware image trailer and Ed25519ph verification
+
+use ed25519_dalek::SigningKey;
+use embassy_stm32_starter_host_tests::image::{IMAGE_TRAILER_LEN, ImageError, ImageTrailer, ImageVerifier, SIGNING_CONTEXT, parse_public_key};
+use sha2::{Digest, Sha512};
+
+const SECRET: [u8; 32] = [7; 32];
+
+/// Sign `image` the way the host signing tool does: image followed by its trailer
+fn sign(image: &[u8], secret: &[u8; 32]) -> Vec<u8> {
+  let mut trailer = ImageTrailer {
+    image_len: image.len() as u32,
+    signature: [0; 64],
+  };
+  let mut hash = Sha512::new();
+  hash.update(image);
+  hash.update(trailer.signed_header());
+  let signature = SigningKey::from_bytes(secret).sign_prehashed(hash, Some(SIGNING_CONTEXT)).unwrap();
+  trailer.signature = signature.to_bytes();
+  [image, &trailer.encode()].concat()
+}
+
+fn public_key() -> [u8; 32] {
+  SigningKey::from_bytes(&SECRET).verifying_key().to_bytes()
+}
+
+/// Verify a signed file as the firmware does, streaming the image in 256-byte chunks
+fn verify(signed: &[u8], key: &[u8; 32]) -> Result<(), ImageError> {
+  let total = signed.len() as u32;
+  let split = signed.len().saturating_sub(IMAGE_TRAILER_LEN);
+  let trailer = ImageTrailer::decode(&signed[split..], total)?;
+  let mut verifier = ImageVerifier::new();
+  for chunk in signed[..trailer.image_len as usize].chunks(256) {
+    verifier.update(chunk);
+  }
+  verifier.finish(&trailer, key)
+}
+
+fn image() -> Vec<u8> {
+  (0..5000u32).map(|i| (i * 31 % 251) as u8).collect()
+}
+
+#[test]
+fn accepts_signed_image() {
+  let signed = sign(&image(), &SECRET);
+  assert_eq!(signed.len(), image().len() + IMAGE_TRAILER_LEN);
+  assert_eq!(verify(&signed, &public_key()), Ok(()));
+}
+
+#[test]
+fn rejects_unsigned_and_tampered_images() {
+  // Plain binary: no trailer
+  assert_eq!(verify(&image(), &public_key()), Err(ImageError::Unsigned));
+  assert_eq!(verify(&[0; 10], &public_key()), Err(ImageError::Unsigned));
+
+  let signed = sign(&image(), &SECRET);
+  let mut patched = signed.clone();
+  patched[100] ^= 0x01;
+  assert_eq!(verify(&patched, &public_key()), Err(ImageError::Signature));
+
+  // Signed with another key
+  assert_eq!(verify(&sign(&image(), &[9; 32]), &public_key()), Err(ImageError::Signature));
+
+  // Truncated upload: the length field no longer matches
+  let mut truncated = signed[..100].to_vec();
+  truncated.extend_from_slice(&signed[signed.len() - IMAGE_TRAILER_LEN..]);
+  assert_eq!(verify(&truncated, &public_key()), Err(ImageError::Length));
+
+  let mut future = signed.clone();
+  let version = signed.len() - IMAGE_TRAILER_LEN + 4;
+  future[version] = 2;
+  assert_eq!(verify(&future, &public_key()), Err(ImageError::Version));
+}
+
+#[test]
+fn parses_hex_public_key() {
+  let key = public_key();
+  let hex: String = key.iter().map(|b| format!("{b:02X}")).collect();
+  assert_eq!(parse_public_key(&hex), Some(key));
+  assert_eq!(parse_public_key(&hex.to_lowercase()), Some(key));
+  assert_eq!(parse_public_key(""), None);
+  assert_eq!(parse_public_key(&hex[1..]), None);
+  assert_eq!(parse_public_key(&format!("zz{}", &hex[2..])), None);
+}
@@ -0,0 +1,53 @@
+//! Host build of the firmware protocol modules
+//!
+//! The protocol modules are pure no_std code, so they are compiled here straight from
+//! `src/protocol/` and exercised with `cargo test` - no board required.
+#![no_std]
+
+#[path = "../../src/protocol/at.rs"]
+pub mod at;
+
+#[path = "../../src/protocol/cbor.rs"]
+pub mod cbor;
+
+#[path = "../../src/protocol/config_blob.rs"]
+pub mod config_blob;
+
+#[path = "../../src/protocol/device_config.rs"]
+pub mod device_config;
+
+#[path = "../../src/protocol/hdlc.rs"]
+pub mod hdlc;
+
+#[path = "../../src/protocol/heatshrink.rs"]
+pub mod heatshrink;
+
+#[path = "../../src/protocol/image.rs"]
+pub mod image;
+
+#[path = "../../src/protocol/lin.rs"]
+pub mod lin;
+
+#[path = "../../src/protocol/message.rs"]
+pub mod message;
+
+#[path = "../../src/protocol/pubsub.rs"]
+pub mod pubsub;
+
+#[path = "../../src/protocol/routing.rs"]
+pub mod routing;
+
+#[path = "../../src/protocol/rules.rs"]
+pub mod rules;
+
+#[path = "../../src/protocol/schedule.rs"]
+pub mod schedule;
+
+#[path = "../../src/protocol/secure.rs"]
+pub mod secure;
+
+#[path = "../../src/protocol/slip.rs"]
+pub mod slip;
+
+#[path = "../../src/protocol/ymodem.rs"]
+pub mod ymodem;
@@ -0,0 +1,100 @@
+//! LIN slave framing: protected ids, checksums and the header/response state machine
+//!
+//! Run with `cd tests/host && cargo test`.
+
+use embassy_stm32_starter_host_tests::lin::{Checksum, Direction, Frame, LinError, LinEvent, LinSlave, checksum, frame_id, protected_id};
+
+const FRAMES: [Frame; 2] = [
+  Frame {
+    id: 0x10,
+    len: 2,
+    direction: Direction::Publish,
+  },
+  Frame {
+    id: 0x22,
+    len: 3,
+    direction: Direction::Subscribe,
+  },
+];
+
+/// Break, sync and protected id of `id`, fed to `slave`; returns the event of the last byte
+fn header<'s>(slave: &'s mut LinSlave<'_>, id: u8) -> Option<LinEvent<'s>> {
+  slave.on_break();
+  assert_eq!(slave.push(0x00), None);
+  assert_eq!(slave.push(0x55), None);
+  slave.push(protected_id(id))
+}
+
+#[test]
+fn protected_ids_match_the_spec() {
+  assert_eq!(protected_id(0x00), 0x80);
+  assert_eq!(protected_id(0x3C), 0x3C);
+  assert_eq!(protected_id(0x3D), 0x7D);
+  assert_eq!(protected_id(0x01), 0xC1);
+  assert_eq!(protected_id(0x3F), 0xBF);
+  for id in 0..0x40 {
+    assert_eq!(frame_id(protected_id(id)), Some(id));
+    assert_eq!(frame_id(protected_id(id) ^ 0x40), None);
+  }
+}
+
+#[test]
+fn checksums_match_the_spec() {
+  // LIN 2.x specification example: PID 0x4A, data 55 93 E5 (sum with carry 0x19 / 0xCE)
+  assert_eq!(checksum(Checksum::Enhanced, 0x4A, &[0x55, 0x93, 0xE5]), 0xE6);
+  assert_eq!(checksum(Checksum::Classic, 0x4A, &[0x55, 0x93, 0xE5]), 0x31);
+  // Diagnostic frames are always classic
+  assert_eq!(checksum(Checksum::Enhanced, 0x3C, &[1, 2]), checksum(Checksum::Classic, 0x3C, &[1, 2]));
+}
+
+#[test]
+fn publish_header_asks_for_a_response_and_skips_its_echo() {
+  let mut slave = LinSlave::new(&FRAMES, Checksum::Enhanced);
+  assert_eq!(header(&mut slave, 0x10), Some(LinEvent::Publish(FRAMES[0])));
+  let response = slave.response(0x10, &[0xAB, 0xCD]);
+  assert_eq!(&response[..], &[0xAB, 0xCD, checksum(Checksum::Enhanced, protected_id(0x10), &[0xAB, 0xCD])]);
+  // Our own bytes come back on the bus and are not taken for a new frame
+  for &byte in response.iter() {
+    assert_eq!(slave.push(byte), None);
+  }
+  assert_eq!(slave.push(0x55), None);
+}
+
+#[test]
+fn subscribed_response_is_checked() {
+  let mut slave = LinSlave::new(&FRAMES, Checksum::Enhanced);
+  let pid = protected_id(0x22);
+  assert_eq!(header(&mut slave, 0x22), None);
+  slave.push(1);
+  slave.push(2);
+  slave.push(3);
+  assert_eq!(
+    slave.push(checksum(Checksum::Enhanced, pid, &[1, 2, 3])),
+    Some(LinEvent::Received { id: 0x22, data: &[1, 2, 3] })
+  );
+
+  assert_eq!(header(&mut slave, 0x22), None);
+  for byte in [1, 2, 3] {
+    slave.push(byte);
+  }
+  assert_eq!(slave.push(0), Some(LinEvent::Error(LinError::Checksum(0x22))));
+}
+
+#[test]
+fn bad_headers_and_unknown_ids() {
+  let mut slave = LinSlave::new(&FRAMES, Checksum::Enhanced);
+  // Not in the table: ignored until the next break
+  assert_eq!(header(&mut slave, 0x11), None);
+  assert_eq!(slave.push(0x55), None);
+  // Parity error
+  slave.on_break();
+  slave.push(0x55);
+  assert_eq!(slave.push(protected_id(0x10) ^ 0x80), Some(LinEvent::Error(LinError::Parity(protected_id(0x10) ^ 0x80))));
+  // No sync byte after the break
+  slave.on_break();
+  assert_eq!(slave.push(0x54), Some(LinEvent::Error(LinError::Sync)));
+  // A break aborts a response in progress
+  assert_eq!(header(&mut slave, 0x22), None);
+  slave.push(1);
+  assert_eq!(header(&mut slave, 0x10), Some(LinEvent::Publish(FRAMES[0])));
+}
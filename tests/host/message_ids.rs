@@ -0,0 +1,128 @@
+//! Reply id echo and duplicate suppression by message id
+//!
+//! Run with `cd tests/host && cargo test`.
+
+use embassy_stm32_starter_host_tests::message::{Command, Message, RecentIds, ReplyCache};
+
+const WINDOW_MS: u32 = 2_000;
+
+fn msg(command: Command, id: u8) -> Message {
+  let mut msg = Message::new(command, &[1, 2, 3]);
+  msg.id = id;
+  msg
+}
+
+#[test]
+fn reply_echoes_the_request_id() {
+  let request = msg(Command::Ping, 42);
+  let reply = request.view().reply(Command::Ack, &[]);
+  assert_eq!(reply.id, 42);
+  assert_eq!(reply.command, Command::Ack as u16);
+}
+
+#[test]
+fn repeated_message_is_a_duplicate_within_the_window() {
+  let mut recent: RecentIds<4> = RecentIds::new(WINDOW_MS);
+  let ping = msg(Command::Ping, 7);
+  assert!(!recent.is_duplicate(&ping.view(), 100));
+  assert!(recent.is_duplicate(&ping.view(), 500));
+  // Forgotten once the window has passed
+  assert!(!recent.is_duplicate(&ping.view(), 100 + WINDOW_MS));
+}
+
+#[test]
+fn command_and_fragment_tell_messages_apart() {
+  let mut recent: RecentIds<4> = RecentIds::new(WINDOW_MS);
+  // A SetConfig and the Ack committing it share an id
+  assert!(!recent.is_duplicate(&msg(Command::SetConfig, 9).view(), 0));
+  assert!(!recent.is_duplicate(&msg(Command::Ack, 9).view(), 0));
+  let mut fragment = msg(Command::Raw, 9);
+  fragment.fragments = 2;
+  fragment.fragment = 0;
+  assert!(!recent.is_duplicate(&fragment.view(), 0));
+  fragment.fragment = 1;
+  assert!(!recent.is_duplicate(&fragment.view(), 0));
+}
+
+#[test]
+fn unassigned_id_is_never_a_duplicate() {
+  let mut recent: RecentIds<4> = RecentIds::new(WINDOW_MS);
+  let ping = msg(Command::Ping, 0);
+  assert!(!recent.is_duplicate(&ping.view(), 0));
+  assert!(!recent.is_duplicate(&ping.view(), 0));
+}
+
+#[test]
+fn oldest_entry_is_evicted() {
+  let mut recent: RecentIds<2> = RecentIds::new(WINDOW_MS);
+  for id in 1..=3 {
+    assert!(!recent.is_duplicate(&msg(Command::Ping, id).view(), 0));
+  }
+  assert!(!recent.is_duplicate(&msg(Command::Ping, 1).view(), 0));
+  assert!(recent.is_duplicate(&msg(Command::Ping, 3).view(), 0));
+}
+
+#[test]
+fn wrapping_clock_keeps_the_window() {
+  let mut recent: RecentIds<4> = RecentIds::new(WINDOW_MS);
+  let ping = msg(Command::Ping, 5);
+  assert!(!recent.is_duplicate(&ping.view(), u32::MAX - 100));
+  assert!(recent.is_duplicate(&ping.view(), 500));
+  // And still expires after the wrap
+  assert!(!recent.is_duplicate(&ping.view(), WINDOW_MS));
+}
+
+#[test]
+fn refused_message_is_delivered_on_retransmit() {
+  let mut recent: RecentIds<4> = RecentIds::new(WINDOW_MS);
+  let mut fragment = msg(Command::Raw, 11);
+  fragment.fragments = 3;
+  fragment.fragment = 1;
+  assert!(!recent.is_duplicate(&fragment.view(), 0));
+  // NAKed `QueueFull`: the host sends the same id and fragment again
+  recent.forget(&fragment.view());
+  assert!(!recent.is_duplicate(&fragment.view(), 50));
+  assert!(recent.is_duplicate(&fragment.view(), 100));
+}
+
+#[test]
+fn duplicate_of_an_answered_request_gets_the_reply_again() {
+  let mut recent: RecentIds<4> = RecentIds::new(WINDOW_MS);
+  let mut replies: ReplyCache<2> = ReplyCache::new(WINDOW_MS);
+  let request = msg(Command::Stats, 21);
+  assert!(!recent.is_duplicate(&request.view(), 0));
+  // Only a message carrying a received id is a reply worth keeping
+  assert!(recent.answers(21, 0, 10));
+  assert!(!recent.answers(22, 0, 10));
+  let reply = request.view().reply(Command::Stats, &[9, 9]);
+  replies.store(&reply, 10);
+  // The reply was lost and the host retries
+  assert!(recent.is_duplicate(&request.view(), 500));
+  let again = replies.find(&request.view(), 500).unwrap();
+  assert_eq!((again.id, again.command, &again.payload[..]), (21, Command::Stats as u16, &[9u8, 9][..]));
+  // A later reply to the same message replaces it; nothing is kept past the window
+  replies.store(&request.view().reply(Command::Ack, &[]), 600);
+  assert_eq!(replies.find(&request.view(), 700).unwrap().command, Command::Ack as u16);
+  assert!(replies.find(&request.view(), 600 + WINDOW_MS).is_none());
+  assert!(replies.find(&msg(Command::Stats, 22).view(), 700).is_none());
+}
+
+#[test]
+fn fragmented_reply_is_not_kept() {
+  let mut replies: ReplyCache<2> = ReplyCache::new(WINDOW_MS);
+  let request = msg(Command::EventLog, 31);
+  // An earlier single reply to the same id is dropped along with it
+  replies.store(&request.view().reply(Command::Ack, &[]), 0);
+  for fragment in 0..3 {
+    let mut reply = request.view().reply(Command::EventLog, &[fragment as u8; 4]);
+    reply.fragments = 3;
+    reply.fragment = fragment;
+    replies.store(&reply, 10);
+  }
+  // The retransmitted request is dropped without a partial answer
+  assert!(replies.find(&request.view(), 500).is_none());
+  // Replies to other messages are untouched
+  replies.store(&msg(Command::Stats, 32).view().reply(Command::Stats, &[1]), 20);
+  replies.store(&request.view().reply(Command::EventLog, &[]), 30);
+  assert!(replies.find(&msg(Command::Stats, 32).view(), 500).is_some());
+}
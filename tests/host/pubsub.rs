@@ -0,0 +1,44 @@
+//! Publish/subscribe payloads and topic sets
+//!
+//! Run with `cd tests/host && cargo test`.
+
+use embassy_stm32_starter_host_tests::message::{COMMS_MAX_PAYLOAD, CommsPayload};
+use embassy_stm32_starter_host_tests::pubsub::{self, TopicSet, topic};
+
+#[test]
+fn publish_round_trip() {
+  let payload: CommsPayload = pubsub::encode_publish(topic::LOG, b"boot ok").unwrap();
+  assert_eq!(&payload[..2], &[0x02, 0x00]);
+  assert_eq!(pubsub::decode_publish(&payload), Some((topic::LOG, &b"boot ok"[..])));
+  assert_eq!(pubsub::decode_publish(&[0x01, 0x00]), Some((topic::TELEMETRY, &[][..])));
+  assert_eq!(pubsub::decode_publish(&[0x01]), None);
+}
+
+#[test]
+fn publish_that_does_not_fit_is_refused() {
+  let data = [0u8; COMMS_MAX_PAYLOAD - 1];
+  assert!(pubsub::encode_publish::<COMMS_MAX_PAYLOAD>(topic::USER, &data).is_none());
+  assert!(pubsub::encode_publish::<COMMS_MAX_PAYLOAD>(topic::USER, &data[1..]).is_some());
+}
+
+#[test]
+fn topic_lists() {
+  let ids: Vec<u16> = pubsub::decode_topics(&[0x01, 0x00, 0x00, 0x01]).unwrap().collect();
+  assert_eq!(ids, [topic::TELEMETRY, topic::USER]);
+  assert_eq!(pubsub::decode_topics(&[]).unwrap().count(), 0);
+  assert!(pubsub::decode_topics(&[0x01, 0x00, 0x02]).is_none());
+}
+
+#[test]
+fn topic_set_ignores_duplicates_and_fills_up() {
+  let mut set: TopicSet<2> = TopicSet::new();
+  assert_eq!(set.insert(topic::LOG), Ok(()));
+  assert_eq!(set.insert(topic::LOG), Ok(()));
+  assert_eq!(set.insert(topic::CONTROL), Ok(()));
+  assert_eq!(set.insert(topic::USER), Err(topic::USER));
+  assert_eq!(set.len(), 2);
+  set.remove(topic::LOG);
+  assert!(!set.contains(topic::LOG) && set.contains(topic::CONTROL));
+  set.clear();
+  assert!(set.is_empty());
+}
@@ -0,0 +1,205 @@
+//! Fuzzed framing/deframing round trips through a software loopback
+//!
+//! Run with `cd tests/host && cargo test`.
+
+use embassy_stm32_starter_host_tests::hdlc::{self, HDLC_ESCAPE, HDLC_FLAG, HdlcError};
+use embassy_stm32_starter_host_tests::message::{COMMS_FRAMED_MAX, COMMS_HEADER_LEN, COMMS_MAX_PAYLOAD, Command, CommsFrameBuf, Message, MessageRef};
+use heapless::Vec;
+
+const ITERATIONS: usize = 2000;
+// Worst case: every byte escaped, plus two flags
+const FRAMED_MAX: usize = COMMS_FRAMED_MAX;
+
+type Framed = Vec<u8, FRAMED_MAX>;
+
+/// Deterministic xorshift so failures are reproducible
+struct Rng(u32);
+
+impl Rng {
+  fn next(&mut self) -> u32 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 17;
+    self.0 ^= self.0 << 5;
+    self.0
+  }
+
+  fn below(&mut self, bound: usize) -> usize {
+    self.next() as usize % bound
+  }
+
+  /// Random byte, biased towards the HDLC flag/escape values
+  fn byte(&mut self) -> u8 {
+    match self.below(8) {
+      0 => HDLC_FLAG,
+      1 => HDLC_ESCAPE,
+      _ => self.next() as u8,
+    }
+  }
+}
+
+/// Software loopback: feed `wire` in random-sized chunks, collect decoded frames
+fn loopback(rng: &mut Rng, wire: &[u8]) -> std::vec::Vec<Result<std::vec::Vec<u8>, HdlcError>> {
+  let mut rx: Framed = Vec::new();
+  let mut out: Framed = Vec::new();
+  let mut frames = std::vec::Vec::new();
+  let mut rest = wire;
+  while !rest.is_empty() {
+    let (chunk, tail) = rest.split_at(1 + rng.below(rest.len().min(64)));
+    rest = tail;
+    rx.extend_from_slice(chunk).unwrap();
+    loop {
+      match hdlc::hdlc_deframe(&mut rx, &mut out) {
+        Ok(()) => frames.push(Ok(out.to_vec())),
+        Err(HdlcError::Incomplete) => break,
+        Err(e) => frames.push(Err(e)),
+      }
+    }
+  }
+  frames
+}
+
+fn random_message(rng: &mut Rng) -> Message {
+  let commands = [
+    Command::Ack,
+    Command::Nak,
+    Command::Ping,
+    Command::Raw,
+    Command::Stats,
+    Command::Alarm,
+    Command::AlarmAck,
+    Command::Rules,
+    Command::ConfigExport,
+    Command::ConfigImport,
+    Command::Telemetry,
+    Command::Sensors,
+    Command::GetConfig,
+    Command::SetConfig,
+    Command::Ident,
+    Command::SetKey,
+    Command::Subscribe,
+    Command::Unsubscribe,
+    Command::Publish,
+    Command::Protection,
+    Command::Bootloader,
+    Command::EventLog,
+  ];
+  let payload: std::vec::Vec<u8> = (0..rng.below(COMMS_MAX_PAYLOAD + 1)).map(|_| rng.byte()).collect();
+  let mut msg = Message::new(commands[rng.below(commands.len())], &payload);
+  msg.id = rng.next() as u8;
+  msg.fragments = rng.next() as u16;
+  msg.fragment = rng.next() as u16;
+  msg
+}
+
+#[test]
+fn messages_survive_framing_noise_and_chunking() {
+  let mut rng = Rng(0x1234_5678);
+  for i in 0..ITERATIONS {
+    let msg = random_message(&mut rng);
+    let mut unframed: CommsFrameBuf = Vec::new();
+    msg.encode(&mut unframed);
+    let mut framed: Framed = Vec::new();
+    hdlc::hdlc_frame(&unframed, &mut framed);
+
+    // Leading line noise (no flags), optional repeated flags, then the frame
+    let mut wire: std::vec::Vec<u8> = (0..rng.below(8)).map(|_| rng.byte()).filter(|&b| b != HDLC_FLAG).collect();
+    wire.extend(std::iter::repeat_n(HDLC_FLAG, rng.below(3)));
+    wire.extend_from_slice(&framed);
+
+    let frames = loopback(&mut rng, &wire);
+    assert_eq!(frames.len(), 1, "iteration {i}: {frames:?}");
+    let decoded = Message::parse(frames[0].as_ref().unwrap()).unwrap_or_else(|e| panic!("iteration {i}: {e:?}"));
+    assert_eq!(
+      (decoded.command, decoded.id, decoded.fragments, decoded.fragment, decoded.payload.as_slice()),
+      (msg.command, msg.id, msg.fragments, msg.fragment, msg.payload.as_slice()),
+      "iteration {i}"
+    );
+  }
+}
+
+#[test]
+fn borrowed_view_matches_owned_parse() {
+  let mut rng = Rng(0x5EED_0001);
+  for i in 0..ITERATIONS {
+    let msg = random_message(&mut rng);
+    let mut unframed: CommsFrameBuf = Vec::new();
+    msg.encode(&mut unframed);
+
+    let view = MessageRef::parse(&unframed).unwrap_or_else(|e| panic!("iteration {i}: {e:?}"));
+    // The payload is a slice of the frame buffer, not a copy
+    assert_eq!(view.payload.as_ptr(), unframed[COMMS_HEADER_LEN..].as_ptr(), "iteration {i}");
+    let owned = view.to_message();
+    assert_eq!(
+      (owned.command, owned.id, owned.fragments, owned.fragment, owned.length as usize, owned.payload.as_slice()),
+      (msg.command, msg.id, msg.fragments, msg.fragment, msg.payload.len(), msg.payload.as_slice()),
+      "iteration {i}"
+    );
+    let again = owned.view();
+    assert_eq!((again.command, again.id, again.payload), (view.command, view.id, view.payload), "iteration {i}");
+  }
+  assert!(MessageRef::parse(&[0x01, 0x00]).is_err());
+}
+
+#[test]
+fn back_to_back_frames_share_flags() {
+  let mut rng = Rng(0xC0FF_EE11);
+  for _ in 0..ITERATIONS / 10 {
+    let messages: std::vec::Vec<Message> = (0..1 + rng.below(4)).map(|_| random_message(&mut rng)).collect();
+    let mut wire = std::vec::Vec::new();
+    for msg in &messages {
+      let mut unframed: CommsFrameBuf = Vec::new();
+      msg.encode(&mut unframed);
+      let mut framed: Framed = Vec::new();
+      hdlc::hdlc_frame(&unframed, &mut framed);
+      // Drop the opening flag half the time: the previous closing flag is shared
+      let skip = usize::from(!wire.is_empty() && rng.below(2) == 0);
+      wire.extend_from_slice(&framed[skip..]);
+    }
+
+    let frames = loopback(&mut rng, &wire);
+    assert_eq!(frames.len(), messages.len());
+    for (frame, msg) in frames.iter().zip(&messages) {
+      assert_eq!(Message::parse(frame.as_ref().unwrap()).unwrap().payload, msg.payload);
+    }
+  }
+}
+
+#[test]
+fn corrupted_frames_are_rejected() {
+  let mut rng = Rng(0xDEAD_BEEF);
+  for _ in 0..ITERATIONS {
+    let msg = random_message(&mut rng);
+    let mut unframed: CommsFrameBuf = Vec::new();
+    msg.encode(&mut unframed);
+    let mut framed: Framed = Vec::new();
+    hdlc::hdlc_frame(&unframed, &mut framed);
+
+    // Flip one bit inside the frame body, keeping the flags/escapes intact
+    let pos = 1 + rng.below(framed.len() - 2);
+    let flipped = framed[pos] ^ (1 << rng.below(8));
+    if [HDLC_FLAG, HDLC_ESCAPE].contains(&framed[pos]) || [HDLC_FLAG, HDLC_ESCAPE].contains(&flipped) {
+      continue;
+    }
+    framed[pos] = flipped;
+
+    for frame in loopback(&mut rng, &framed) {
+      if cfg!(feature = "hdlc_fcs") {
+        assert!(matches!(frame, Err(HdlcError::FcsMismatch { .. })));
+      }
+    }
+  }
+}
+
+#[test]
+fn garbage_never_panics() {
+  let mut rng = Rng(0x0BAD_F00D);
+  for _ in 0..ITERATIONS {
+    let garbage: std::vec::Vec<u8> = (0..rng.below(FRAMED_MAX)).map(|_| rng.byte()).collect();
+    for frame in loopback(&mut rng, &garbage).into_iter().flatten() {
+      if let Ok(msg) = Message::parse(&frame) {
+        assert_eq!(frame.len(), COMMS_HEADER_LEN + msg.payload.len());
+      }
+    }
+    let _ = Message::parse(&garbage);
+  }
+}
@@ -0,0 +1,62 @@
+//! Routing table decisions and the addressed header layout
+//!
+//! Run with `cd tests/host && cargo test --features comms_routing` to cover the 12-byte header.
+
+use embassy_stm32_starter_host_tests::routing::{BROADCAST, DropReason, MAX_HOPS, Route, RoutingTable};
+
+#[test]
+fn local_and_broadcast_are_delivered() {
+  let table: RoutingTable<4> = RoutingTable::new(0x10);
+  assert_eq!(table.route(0x10, 0), Route::Local);
+  assert_eq!(table.route(BROADCAST, MAX_HOPS), Route::Local);
+}
+
+#[test]
+fn explicit_routes_win_over_default() {
+  let mut table: RoutingTable<4> = RoutingTable::new(0x10);
+  assert_eq!(table.route(0x20, 0), Route::Drop(DropReason::NoRoute));
+  table.set_default(Some(0));
+  table.add(0x20, 2).unwrap();
+  assert_eq!(table.route(0x20, 0), Route::Forward(2));
+  assert_eq!(table.route(0x30, 0), Route::Forward(0));
+  table.add(0x20, 1).unwrap(); // replace
+  assert_eq!(table.route(0x20, 0), Route::Forward(1));
+  table.remove(0x20);
+  assert_eq!(table.route(0x20, 0), Route::Forward(0));
+}
+
+#[test]
+fn hop_limit_stops_loops() {
+  let mut table: RoutingTable<4> = RoutingTable::new(0x10);
+  table.add(0x20, 1).unwrap();
+  assert_eq!(table.route(0x20, MAX_HOPS - 1), Route::Forward(1));
+  assert_eq!(table.route(0x20, MAX_HOPS), Route::Drop(DropReason::HopLimit));
+}
+
+#[test]
+fn table_capacity_is_enforced() {
+  let mut table: RoutingTable<2> = RoutingTable::new(0x10);
+  table.add(0x20, 1).unwrap();
+  table.add(0x21, 1).unwrap();
+  assert!(table.add(0x22, 1).is_err());
+  assert!(table.add(0x21, 2).is_ok()); // replacing still works when full
+}
+
+#[cfg(feature = "comms_routing")]
+#[test]
+fn addressed_header_round_trips() {
+  use embassy_stm32_starter_host_tests::message::{COMMS_HEADER_LEN, Command, CommsFrameBuf, Message};
+
+  let mut msg = Message::new(Command::Raw, &[0xAB]);
+  msg.id = 7;
+  msg.src = 0x10;
+  msg.dst = 0x20;
+  msg.hops = 2;
+  let mut buf = CommsFrameBuf::new();
+  msg.encode(&mut buf);
+  assert_eq!(COMMS_HEADER_LEN, 12);
+  assert_eq!(&buf[2..6], &[7, 0x10, 0x20, 2]);
+
+  let parsed = Message::parse(&buf).unwrap();
+  assert_eq!((parsed.src, parsed.dst, parsed.hops, parsed.payload.as_slice()), (0x10, 0x20, 2, &[0xAB][..]));
+}
@@ -0,0 +1,112 @@
+//! Rule encoding and hold-time / threshold evaluation
+
+use embassy_stm32_starter_host_tests::rules::{Action, Condition, RULE_LEN, RULES_MAX, RULES_RECORD_MAX, Rule, RuleState, decode_record, encode_record};
+
+const LOW_FOR_500MS_ALARM: Rule = Rule {
+  condition: Condition::InputLow { pin: 2, ms: 500 },
+  action: Action::Alarm {
+    id: 7,
+    severity: 2,
+    latching: true,
+  },
+};
+
+#[test]
+fn encode_decode_roundtrip() {
+  let rules = [
+    LOW_FOR_500MS_ALARM,
+    Rule {
+      condition: Condition::AdcAbove { channel: 1, threshold: 3000 },
+      action: Action::Output { pin: 0, level: true },
+    },
+  ];
+  for rule in rules {
+    let bytes = rule.to_bytes();
+    assert_eq!(bytes.len(), RULE_LEN);
+    assert_eq!(Rule::from_bytes(&bytes), Some(rule));
+  }
+  assert_eq!(LOW_FOR_500MS_ALARM.to_bytes(), [1, 2, 0xF4, 0x01, 1, 7, 0x82, 0]);
+}
+
+#[test]
+fn unknown_kinds_are_rejected() {
+  assert_eq!(Rule::from_bytes(&[0, 2, 0, 0, 1, 7, 0, 0]), None);
+  assert_eq!(Rule::from_bytes(&[1, 2, 0, 0, 9, 7, 0, 0]), None);
+}
+
+#[test]
+fn input_must_hold_longer_than_hold_time() {
+  let mut state = RuleState::default();
+  let rule = LOW_FOR_500MS_ALARM;
+  assert_eq!(rule.evaluate(&mut state, 0, 1000), None);
+  assert_eq!(rule.evaluate(&mut state, 0, 1500), None);
+  // A bounce restarts the hold timer
+  assert_eq!(rule.evaluate(&mut state, 1, 1400), None);
+  assert_eq!(rule.evaluate(&mut state, 0, 2000), None);
+  assert_eq!(rule.evaluate(&mut state, 0, 2501), Some(true));
+  assert_eq!(rule.evaluate(&mut state, 0, 3000), None);
+  assert_eq!(rule.evaluate(&mut state, 1, 3100), Some(false));
+  assert!(!state.active);
+}
+
+#[test]
+fn hold_time_spans_the_millisecond_wrap() {
+  let mut state = RuleState::default();
+  let rule = LOW_FOR_500MS_ALARM;
+  assert_eq!(rule.evaluate(&mut state, 0, u32::MAX - 200), None);
+  assert_eq!(rule.evaluate(&mut state, 0, 250), None);
+  assert_eq!(rule.evaluate(&mut state, 0, 300), Some(true));
+}
+
+#[test]
+fn input_held_for_days_stays_active() {
+  const HOUR_MS: u32 = 3_600_000;
+  let mut state = RuleState::default();
+  let rule = LOW_FOR_500MS_ALARM;
+  assert_eq!(rule.evaluate(&mut state, 0, 0), None);
+  assert_eq!(rule.evaluate(&mut state, 0, 501), Some(true));
+  // Sampled every hour for 60 days: past the u32 wrap (~49.7 days) the hold must not restart
+  let mut now: u32 = 501;
+  for _ in 0..60 * 24 {
+    now = now.wrapping_add(HOUR_MS);
+    assert_eq!(rule.evaluate(&mut state, 0, now), None);
+  }
+  // Samples landing just after the wrap, less than the hold time after the input went low
+  assert_eq!(rule.evaluate(&mut state, 0, 100), None);
+  assert!(state.active);
+  assert_eq!(rule.evaluate(&mut state, 1, 200), Some(false));
+}
+
+#[test]
+fn adc_threshold_activates_immediately() {
+  let rule = Rule {
+    condition: Condition::AdcBelow { channel: 0, threshold: 100 },
+    action: Action::Output { pin: 1, level: false },
+  };
+  let mut state = RuleState::default();
+  assert_eq!(rule.evaluate(&mut state, 100, 0), None);
+  assert_eq!(rule.evaluate(&mut state, 99, 10), Some(true));
+  assert_eq!(rule.evaluate(&mut state, 50, 20), None);
+  assert_eq!(rule.evaluate(&mut state, 4000, 30), Some(false));
+}
+
+#[test]
+fn record_roundtrip_and_crc() {
+  let rules = [
+    LOW_FOR_500MS_ALARM,
+    Rule {
+      condition: Condition::AdcBelow { channel: 3, threshold: 0xFFFF },
+      action: Action::Output { pin: 4, level: true },
+    },
+  ];
+  let record = encode_record(&rules);
+  assert_eq!(decode_record(&record).as_deref(), Some(&rules[..]));
+  // Trailing flash bytes are ignored
+  let mut slot = record.to_vec();
+  slot.extend_from_slice(&[0xFF; 16]);
+  assert_eq!(decode_record(&slot).as_deref(), Some(&rules[..]));
+  slot[6] ^= 1;
+  assert_eq!(decode_record(&slot), None);
+  assert_eq!(decode_record(&encode_record(&[])).map(|r| r.len()), Some(0));
+  assert_eq!(encode_record(&[LOW_FOR_500MS_ALARM; RULES_MAX]).len(), RULES_RECORD_MAX);
+}
@@ -0,0 +1,123 @@
+//! Schedule entry encoding, calendar arithmetic and next-firing search
+
+use embassy_stm32_starter_host_tests::schedule::{ANY_HOUR, CalendarTime, SCHEDULE_ENTRY_LEN, ScheduleEntry, decode_record, encode_record, next_due};
+
+const DAILY_0200: ScheduleEntry = ScheduleEntry::daily(2, 0, 3);
+
+fn at(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> CalendarTime {
+  CalendarTime {
+    year,
+    month,
+    day,
+    hour,
+    minute,
+    second,
+  }
+}
+
+#[test]
+fn encode_decode_roundtrip() {
+  let hourly = ScheduleEntry {
+    minute: 15,
+    hour: None,
+    weekdays: 0b001_1111,
+    day: 0,
+    event: 9,
+  };
+  for entry in [DAILY_0200, hourly] {
+    let bytes = entry.to_bytes();
+    assert_eq!(bytes.len(), SCHEDULE_ENTRY_LEN);
+    assert_eq!(ScheduleEntry::from_bytes(&bytes), Some(entry));
+  }
+  assert_eq!(hourly.to_bytes(), [15, ANY_HOUR, 0x1F, 0, 9, 0]);
+}
+
+#[test]
+fn out_of_range_fields_are_rejected() {
+  assert_eq!(ScheduleEntry::from_bytes(&[60, 2, 0, 0, 1, 0]), None);
+  assert_eq!(ScheduleEntry::from_bytes(&[0, 24, 0, 0, 1, 0]), None);
+  assert_eq!(ScheduleEntry::from_bytes(&[0, 2, 0x80, 0, 1, 0]), None);
+  assert_eq!(ScheduleEntry::from_bytes(&[0, 2, 0, 32, 1, 0]), None);
+}
+
+#[test]
+fn weekdays_and_month_lengths() {
+  assert_eq!(at(2000, 1, 1, 0, 0, 0).weekday(), 6);
+  assert_eq!(at(2024, 2, 29, 0, 0, 0).weekday(), 4);
+  assert_eq!(at(2026, 10, 18, 0, 0, 0).weekday(), 7);
+  assert_eq!(at(2024, 2, 1, 0, 0, 0).days_in_month(), 29);
+  assert_eq!(at(2100, 2, 1, 0, 0, 0).days_in_month(), 28);
+  assert_eq!(at(2026, 9, 1, 0, 0, 0).days_in_month(), 30);
+}
+
+#[test]
+fn daily_entry_fires_today_then_tomorrow() {
+  assert_eq!(DAILY_0200.next_after(&at(2026, 10, 16, 1, 30, 10)), Some(at(2026, 10, 16, 2, 0, 0)));
+  // At its own minute the next firing is the next day's
+  assert_eq!(DAILY_0200.next_after(&at(2026, 10, 16, 2, 0, 0)), Some(at(2026, 10, 17, 2, 0, 0)));
+  assert_eq!(DAILY_0200.next_after(&at(2026, 10, 31, 3, 0, 0)), Some(at(2026, 11, 1, 2, 0, 0)));
+  assert_eq!(DAILY_0200.next_after(&at(2026, 12, 31, 23, 59, 59)), Some(at(2027, 1, 1, 2, 0, 0)));
+  assert!(DAILY_0200.matches(&at(2026, 10, 16, 2, 0, 30)));
+  assert!(!DAILY_0200.matches(&at(2026, 10, 16, 2, 1, 0)));
+}
+
+#[test]
+fn hourly_entry_rolls_into_the_next_hour_and_day() {
+  let quarter_past = ScheduleEntry {
+    minute: 15,
+    hour: None,
+    weekdays: 0,
+    day: 0,
+    event: 1,
+  };
+  assert_eq!(quarter_past.next_after(&at(2026, 10, 16, 10, 14, 59)), Some(at(2026, 10, 16, 10, 15, 0)));
+  assert_eq!(quarter_past.next_after(&at(2026, 10, 16, 10, 20, 0)), Some(at(2026, 10, 16, 11, 15, 0)));
+  assert_eq!(quarter_past.next_after(&at(2026, 10, 16, 23, 20, 0)), Some(at(2026, 10, 17, 0, 15, 0)));
+}
+
+#[test]
+fn weekday_and_day_of_month_restrict_the_days() {
+  // Mondays at 07:30, from Friday 16 October 2026
+  let mondays = ScheduleEntry {
+    weekdays: 1 << 0,
+    ..ScheduleEntry::daily(7, 30, 2)
+  };
+  assert_eq!(mondays.next_after(&at(2026, 10, 16, 12, 0, 0)), Some(at(2026, 10, 19, 7, 30, 0)));
+  // The 31st skips November
+  let month_end = ScheduleEntry { day: 31, ..DAILY_0200 };
+  assert_eq!(month_end.next_after(&at(2026, 11, 1, 0, 0, 0)), Some(at(2026, 12, 31, 2, 0, 0)));
+  // The 29th skips February outside leap years
+  let the_29th = ScheduleEntry { day: 29, ..DAILY_0200 };
+  assert_eq!(the_29th.next_after(&at(2027, 2, 1, 0, 0, 0)), Some(at(2027, 3, 29, 2, 0, 0)));
+  assert_eq!(the_29th.next_after(&at(2028, 2, 1, 0, 0, 0)), Some(at(2028, 2, 29, 2, 0, 0)));
+  // Friday the 13th
+  let friday_13th = ScheduleEntry {
+    weekdays: 1 << 4,
+    day: 13,
+    ..DAILY_0200
+  };
+  assert_eq!(friday_13th.next_after(&at(2026, 10, 16, 0, 0, 0)), Some(at(2026, 11, 13, 2, 0, 0)));
+}
+
+#[test]
+fn next_due_is_the_earliest_entry() {
+  let entries = [DAILY_0200, ScheduleEntry::daily(1, 45, 4), ScheduleEntry::daily(23, 0, 5)];
+  assert_eq!(next_due(&entries, &at(2026, 10, 16, 1, 0, 0)), Some(at(2026, 10, 16, 1, 45, 0)));
+  assert_eq!(next_due(&entries, &at(2026, 10, 16, 1, 45, 0)), Some(at(2026, 10, 16, 2, 0, 0)));
+  assert_eq!(next_due(&entries, &at(2026, 10, 16, 22, 0, 0)), Some(at(2026, 10, 16, 23, 0, 0)));
+  assert_eq!(next_due(&[], &at(2026, 10, 16, 22, 0, 0)), None);
+}
+
+#[test]
+fn record_roundtrip_and_crc() {
+  let entries = [DAILY_0200, ScheduleEntry::daily(12, 30, 7)];
+  let record = encode_record(&entries);
+  assert_eq!(decode_record(&record).as_deref(), Some(&entries[..]));
+  // Trailing flash bytes are ignored
+  let mut slot = record.to_vec();
+  slot.extend_from_slice(&[0xFF; 16]);
+  assert_eq!(decode_record(&slot).as_deref(), Some(&entries[..]));
+  slot[5] ^= 1;
+  assert_eq!(decode_record(&slot), None);
+  assert_eq!(decode_record(&encode_record(&[])).map(|e| e.len()), Some(0));
+}
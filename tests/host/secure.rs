@@ -0,0 +1,137 @@
+//! Comms payload sealing (AES-128-CCM) and the stored key record
+
+use embassy_stm32_starter_host_tests::message::{Command, Message};
+use embassy_stm32_starter_host_tests::secure::{
+  CHALLENGE_LEN, DIRECTION_DEVICE, DIRECTION_HOST, KEY_RECORD_LEN, KeyRecord, Nonce, ReplayWindow, SECURE_MAX_PLAINTEXT, SECURE_OVERHEAD, SecureError, aad, open, seal,
+};
+
+const KEY: [u8; 16] = *b"0123456789abcdef";
+// Device challenges, one per host session
+const FIRST: [u8; CHALLENGE_LEN] = [0x91, 0x92, 0x93, 0x94];
+const SECOND: [u8; CHALLENGE_LEN] = [0xA1, 0xA2, 0xA3, 0xA4];
+const THIRD: [u8; CHALLENGE_LEN] = [0xB1, 0xB2, 0xB3, 0xB4];
+
+fn nonce(counter: u32) -> Nonce {
+  Nonce {
+    direction: DIRECTION_HOST,
+    salt: [1, 2, 3, 4, 5, 6, 7, 8],
+    counter,
+  }
+}
+
+#[test]
+fn seal_open_roundtrip() {
+  let msg = Message::new(Command::SetConfig, b"payload");
+  let sealed = seal(&KEY, &nonce(1), &aad(&msg), &msg.payload).unwrap();
+  assert_eq!(sealed.len(), msg.payload.len() + SECURE_OVERHEAD);
+  assert_ne!(&sealed[13..13 + 7], b"payload");
+  let (n, plain) = open(&KEY, &aad(&msg), &sealed).unwrap();
+  assert_eq!(n, nonce(1));
+  assert_eq!(&plain[..], b"payload");
+
+  // Empty payloads are authenticated too
+  let sealed = seal(&KEY, &nonce(2), &aad(&msg), &[]).unwrap();
+  assert_eq!(open(&KEY, &aad(&msg), &sealed).unwrap().1.len(), 0);
+}
+
+#[test]
+fn rejects_tampering() {
+  let msg = Message::new(Command::Raw, &[0xD8, 0x01]);
+  let sealed = seal(&KEY, &nonce(1), &aad(&msg), &msg.payload).unwrap();
+
+  let mut flipped = sealed.clone();
+  flipped[14] ^= 0x01;
+  assert_eq!(open(&KEY, &aad(&msg), &flipped).unwrap_err(), SecureError::Auth);
+
+  // Same payload presented under another command
+  let other = Message::new(Command::Ping, &[]);
+  assert_eq!(open(&KEY, &aad(&other), &sealed).unwrap_err(), SecureError::Auth);
+
+  let mut wrong_key = KEY;
+  wrong_key[0] ^= 0xFF;
+  assert_eq!(open(&wrong_key, &aad(&msg), &sealed).unwrap_err(), SecureError::Auth);
+
+  assert_eq!(open(&KEY, &aad(&msg), &sealed[..SECURE_OVERHEAD - 1]).unwrap_err(), SecureError::Malformed);
+}
+
+#[test]
+fn plaintext_limit() {
+  let big = [0u8; SECURE_MAX_PLAINTEXT + 1];
+  assert_eq!(seal(&KEY, &nonce(1), &[], &big).unwrap_err(), SecureError::TooLong);
+  assert!(seal(&KEY, &nonce(1), &[], &big[..SECURE_MAX_PLAINTEXT]).is_ok());
+}
+
+// Nonce of a host session opened with `challenge`
+fn session_nonce(challenge: [u8; CHALLENGE_LEN], counter: u32) -> Nonce {
+  let mut salt = [0x5A; 8];
+  salt[..CHALLENGE_LEN].copy_from_slice(&challenge);
+  Nonce {
+    direction: DIRECTION_HOST,
+    salt,
+    counter,
+  }
+}
+
+#[test]
+fn replay_window() {
+  let mut window = ReplayWindow::new(FIRST);
+  assert_eq!(window.accept(&session_nonce(FIRST, 1), SECOND), Ok(()));
+  assert_eq!(window.challenge(), SECOND);
+  assert_eq!(window.accept(&session_nonce(FIRST, 1), THIRD), Err(SecureError::Replay));
+  assert_eq!(window.accept(&session_nonce(FIRST, 3), THIRD), Ok(()));
+  assert_eq!(window.accept(&session_nonce(FIRST, 2), THIRD), Err(SecureError::Replay));
+  // Counting on within the open session keeps the challenge
+  assert_eq!(window.challenge(), SECOND);
+}
+
+#[test]
+fn session_must_carry_the_challenge() {
+  let mut window = ReplayWindow::new(FIRST);
+  // A salt the device never handed out
+  assert_eq!(window.accept(&nonce(1), SECOND), Err(SecureError::Challenge));
+  assert_eq!(window.challenge(), FIRST);
+}
+
+#[test]
+fn frames_from_an_old_session_are_rejected() {
+  let mut window = ReplayWindow::new(FIRST);
+  // Host session A, then the host restarts and opens session B with the next challenge
+  assert_eq!(window.accept(&session_nonce(FIRST, 1), SECOND), Ok(()));
+  assert_eq!(window.accept(&session_nonce(FIRST, 2), SECOND), Ok(()));
+  assert_eq!(window.accept(&session_nonce(SECOND, 1), THIRD), Ok(()));
+  // Recorded frames of A, then of B, cannot be played back alternately
+  assert_eq!(window.accept(&session_nonce(FIRST, 3), [0; CHALLENGE_LEN]), Err(SecureError::Challenge));
+  assert_eq!(window.accept(&session_nonce(SECOND, 1), [0; CHALLENGE_LEN]), Err(SecureError::Replay));
+  assert_eq!(window.accept(&session_nonce(FIRST, 1), [0; CHALLENGE_LEN]), Err(SecureError::Challenge));
+  assert_eq!(window.challenge(), THIRD);
+  // B itself carries on
+  assert_eq!(window.accept(&session_nonce(SECOND, 2), [0; CHALLENGE_LEN]), Ok(()));
+}
+
+#[test]
+fn nonce_layout() {
+  let n = Nonce {
+    direction: DIRECTION_DEVICE,
+    salt: [0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17],
+    counter: 0x0403_0201,
+  };
+  let bytes = n.to_bytes();
+  assert_eq!(bytes, [b'D', 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x01, 0x02, 0x03, 0x04]);
+  assert_eq!(Nonce::from_bytes(&bytes), n);
+}
+
+#[test]
+fn key_record() {
+  let record = KeyRecord { key: KEY, epoch: 41 };
+  let bytes = record.encode();
+  assert_eq!(KeyRecord::decode(&bytes), Some(record));
+  // Read from a whole flash slot
+  let mut slot = bytes.to_vec();
+  slot.resize(84, 0xFF);
+  assert_eq!(KeyRecord::decode(&slot), Some(record));
+
+  let mut torn = bytes;
+  torn[6] ^= 0x01;
+  assert_eq!(KeyRecord::decode(&torn), None);
+  assert_eq!(KeyRecord::decode(&bytes[..KEY_RECORD_LEN - 1]), None);
+}
@@ -0,0 +1,75 @@
+//! SLIP (RFC 1055) framing/deframing
+//!
+//! Run with `cd tests/host && cargo test`.
+
+use embassy_stm32_starter_host_tests::message::{COMMS_FRAMED_MAX, Command, CommsFrameBuf, Message};
+use embassy_stm32_starter_host_tests::slip::{self, SLIP_END, SLIP_ESC, SLIP_ESC_END, SLIP_ESC_ESC, SlipError};
+use heapless::Vec;
+
+type Framed = Vec<u8, COMMS_FRAMED_MAX>;
+
+/// Feed `wire` one byte at a time, collect decoded packets
+fn deframe_all(wire: &[u8]) -> std::vec::Vec<std::vec::Vec<u8>> {
+  let mut rx: Framed = Vec::new();
+  let mut out: Framed = Vec::new();
+  let mut packets = std::vec::Vec::new();
+  for &b in wire {
+    rx.push(b).unwrap();
+    while slip::slip_deframe(&mut rx, &mut out).is_ok() {
+      packets.push(out.to_vec());
+    }
+  }
+  packets
+}
+
+#[test]
+fn escapes_end_and_esc() {
+  let mut framed: Framed = Vec::new();
+  slip::slip_frame(&[0x01, SLIP_END, SLIP_ESC, 0x02], &mut framed);
+  assert_eq!(&framed[..], &[SLIP_END, 0x01, SLIP_ESC, SLIP_ESC_END, SLIP_ESC, SLIP_ESC_ESC, 0x02, SLIP_END]);
+  assert_eq!(deframe_all(&framed), [[0x01, SLIP_END, SLIP_ESC, 0x02]]);
+}
+
+#[test]
+fn message_round_trip_back_to_back() {
+  let mut wire = std::vec::Vec::new();
+  let mut msgs = std::vec::Vec::new();
+  for id in 0..4u8 {
+    let mut msg = Message::new(Command::Raw, &[SLIP_END, id, SLIP_ESC, 0xC0, 0xDB]);
+    msg.id = id;
+    let mut buf: CommsFrameBuf = Vec::new();
+    msg.encode(&mut buf);
+    let mut framed: Framed = Vec::new();
+    slip::slip_frame(&buf, &mut framed);
+    wire.extend_from_slice(&framed);
+    msgs.push(msg);
+  }
+  let packets = deframe_all(&wire);
+  assert_eq!(packets.len(), msgs.len());
+  for (packet, msg) in packets.iter().zip(&msgs) {
+    let parsed = Message::parse(packet).unwrap();
+    assert_eq!((parsed.id, &parsed.payload[..]), (msg.id, &msg.payload[..]));
+  }
+}
+
+#[test]
+fn partial_packet_waits_for_end() {
+  let mut rx: Framed = Vec::from_slice(&[SLIP_END, SLIP_END, 0x01, SLIP_ESC]).unwrap();
+  let mut out: Framed = Vec::new();
+  assert_eq!(slip::slip_deframe(&mut rx, &mut out), Err(SlipError::Incomplete));
+  assert_eq!(&rx[..], &[0x01, SLIP_ESC]);
+  rx.extend_from_slice(&[SLIP_ESC_END, SLIP_END]).unwrap();
+  assert_eq!(slip::slip_deframe(&mut rx, &mut out), Ok(()));
+  assert_eq!(&out[..], &[0x01, SLIP_END]);
+  assert!(rx.is_empty());
+}
+
+#[test]
+fn noise_is_flushed_by_leading_end() {
+  // Line noise before a frame comes out as its own (unparseable) packet
+  let mut framed: Framed = Vec::new();
+  slip::slip_frame(&[0x0A, 0x0B], &mut framed);
+  let mut wire = std::vec::Vec::from([0x55, 0x66]);
+  wire.extend_from_slice(&framed);
+  assert_eq!(deframe_all(&wire), [std::vec::Vec::from([0x55, 0x66]), std::vec::Vec::from([0x0A, 0x0B])]);
+}
@@ -0,0 +1,144 @@
+//! XMODEM-CRC / YMODEM receiver against a simulated sender
+//!
+//! Run with `cd tests/host && cargo test`.
+
+use embassy_stm32_starter_host_tests::ymodem::{
+  YMODEM_ACK, YMODEM_CAN, YMODEM_CRC, YMODEM_EOT, YMODEM_MAX_ERRORS, YMODEM_NAK, YMODEM_SOH, YMODEM_STX, YmodemEvent, YmodemReceiver, crc16_xmodem,
+};
+
+/// Block `seq` with `data` padded to 128 (SOH) or 1024 (STX) bytes
+fn block(seq: u8, data: &[u8], pad: u8) -> Vec<u8> {
+  let len = if data.len() <= 128 { 128 } else { 1024 };
+  let mut payload = data.to_vec();
+  payload.resize(len, pad);
+  let mut out = vec![if len == 128 { YMODEM_SOH } else { YMODEM_STX }, seq, !seq];
+  out.extend_from_slice(&payload);
+  out.extend_from_slice(&crc16_xmodem(&payload).to_be_bytes());
+  out
+}
+
+/// What the receiver handed out, in a comparable form
+#[derive(Debug, Eq, PartialEq)]
+enum Got {
+  File(String, Option<u32>),
+  Data(Vec<u8>),
+  FileEnd,
+  Done,
+  Aborted,
+}
+
+/// Feed `bytes`; collect events and the replies sent after each
+fn feed(rx: &mut YmodemReceiver, bytes: &[u8]) -> (Vec<Got>, Vec<u8>) {
+  let (mut got, mut replies) = (Vec::new(), Vec::new());
+  for &b in bytes {
+    if let Some(event) = rx.push(b) {
+      got.push(match event {
+        YmodemEvent::File { name, size } => Got::File(name.to_string(), size),
+        YmodemEvent::Data(data) => Got::Data(data.to_vec()),
+        YmodemEvent::FileEnd => Got::FileEnd,
+        YmodemEvent::Done => Got::Done,
+        YmodemEvent::Aborted => Got::Aborted,
+      });
+    }
+    replies.extend_from_slice(&rx.reply());
+  }
+  (got, replies)
+}
+
+#[test]
+fn crc_matches_xmodem_check_value() {
+  assert_eq!(crc16_xmodem(b"123456789"), 0x31C3);
+}
+
+#[test]
+fn ymodem_batch_trims_to_size() {
+  let mut rx = YmodemReceiver::new();
+  assert_eq!(rx.reply(), [YMODEM_CRC]);
+  let data: Vec<u8> = (0..1100u32).map(|i| i as u8).collect();
+
+  let (got, replies) = feed(&mut rx, &block(0, b"fw.bin\x001100 14000000000 100644", 0));
+  assert_eq!(got, [Got::File("fw.bin".into(), Some(1100))]);
+  assert_eq!(replies, [YMODEM_ACK, YMODEM_CRC]);
+
+  let (got, replies) = feed(&mut rx, &block(1, &data[..1024], 0x1A));
+  assert_eq!(got, [Got::Data(data[..1024].to_vec())]);
+  assert_eq!(replies, [YMODEM_ACK]);
+  let (got, _) = feed(&mut rx, &block(2, &data[1024..], 0x1A));
+  assert_eq!(got, [Got::Data(data[1024..].to_vec())]);
+  assert_eq!(rx.received(), 1100);
+
+  // EOT is NAKed once, then ACKed with a request for the next header
+  let (got, replies) = feed(&mut rx, &[YMODEM_EOT]);
+  assert!(got.is_empty());
+  assert_eq!(replies, [YMODEM_NAK]);
+  let (got, replies) = feed(&mut rx, &[YMODEM_EOT]);
+  assert_eq!(got, [Got::FileEnd]);
+  assert_eq!(replies, [YMODEM_ACK, YMODEM_CRC]);
+
+  let (got, replies) = feed(&mut rx, &block(0, &[], 0));
+  assert_eq!(got, [Got::Done]);
+  assert_eq!(replies, [YMODEM_ACK]);
+  assert!(rx.is_finished());
+}
+
+#[test]
+fn xmodem_without_header() {
+  let mut rx = YmodemReceiver::new();
+  rx.reply();
+  let (got, _) = feed(&mut rx, &block(1, b"hello", 0x1A));
+  assert_eq!(got, [Got::Data(block(1, b"hello", 0x1A)[3..131].to_vec())]);
+  let (got, replies) = feed(&mut rx, &[YMODEM_EOT]);
+  assert_eq!(got, [Got::FileEnd]);
+  assert_eq!(replies, [YMODEM_ACK]);
+  assert!(rx.is_finished());
+}
+
+#[test]
+fn naks_bad_blocks_and_skips_repeats() {
+  let mut rx = YmodemReceiver::new();
+  rx.reply();
+  feed(&mut rx, &block(0, b"a\x003", 0));
+  let mut corrupt = block(1, b"abc", 0x1A);
+  corrupt[10] ^= 0x01;
+  let (got, replies) = feed(&mut rx, &corrupt);
+  assert!(got.is_empty());
+  assert_eq!(replies, [YMODEM_NAK]);
+  let (got, _) = feed(&mut rx, &block(1, b"abc", 0x1A));
+  assert_eq!(got, [Got::Data(b"abc".to_vec())]);
+  // The sender missed our ACK and repeats the block: ACK it again, no data twice
+  let (got, replies) = feed(&mut rx, &block(1, b"abc", 0x1A));
+  assert!(got.is_empty());
+  assert_eq!(replies, [YMODEM_ACK]);
+  // A block from further on means some were lost
+  let (got, replies) = feed(&mut rx, &block(3, b"xyz", 0x1A));
+  assert_eq!(got, [Got::Aborted]);
+  assert_eq!(replies, [YMODEM_CAN, YMODEM_CAN]);
+}
+
+#[test]
+fn timeouts_retry_then_give_up() {
+  let mut rx = YmodemReceiver::new();
+  rx.reply();
+  // Half a block, then silence: dropped and asked for again
+  feed(&mut rx, &block(0, b"f\x001", 0)[..50]);
+  assert_eq!(rx.timeout(), None);
+  assert_eq!(rx.reply(), [YMODEM_CRC]);
+  let (got, _) = feed(&mut rx, &block(0, b"f\x001", 0));
+  assert_eq!(got, [Got::File("f".into(), Some(1))]);
+  for _ in 1..YMODEM_MAX_ERRORS {
+    assert_eq!(rx.timeout(), None);
+    assert_eq!(rx.reply(), [YMODEM_NAK]);
+  }
+  assert_eq!(rx.timeout(), Some(YmodemEvent::Aborted));
+  assert_eq!(rx.reply(), [YMODEM_CAN, YMODEM_CAN]);
+  assert!(rx.is_finished());
+}
+
+#[test]
+fn sender_cancel() {
+  let mut rx = YmodemReceiver::new();
+  let (got, _) = feed(&mut rx, &[YMODEM_CAN, YMODEM_CAN]);
+  assert_eq!(got, [Got::Aborted]);
+  assert!(rx.is_finished());
+  assert_eq!(YmodemReceiver::new().cancel(), [YMODEM_CAN; 5]);
+}
@@ -1,18 +1,23 @@
 #![no_std]
 #![no_main]
 
-use embassy_stm32_starter::*;
-use semihosting::process; // import from lib.rs
+use embassy_stm32_starter::board::BoardConfig;
+use embassy_stm32_starter::common::buildinfo;
 
-#[cortex_m_rt::entry]
-fn main() -> ! {
-  let _p = embassy_stm32::init(Default::default());
+#[defmt_test::tests]
+mod tests {
+  use super::*;
 
-  info!("Integration test started");
+  #[init]
+  fn init() {
+    embassy_stm32::init(BoardConfig::embassy_config());
+  }
 
-  cortex_m::asm::delay(100);
-
-  info!("Integration test completed successfully");
-
-  process::exit(0)
+  /// Clocks come up and the core keeps running
+  #[test]
+  fn board_boots() {
+    defmt::info!("Board: {}", BoardConfig::BOARD_NAME);
+    buildinfo::log();
+    cortex_m::asm::delay(100);
+  }
 }
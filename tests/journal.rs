@@ -0,0 +1,160 @@
+#![no_std]
+#![no_main]
+
+// Runs the message journal over a RAM-backed NorFlash: the board's flash is not touched.
+
+use embassy_stm32_starter::protocol::message::{COMMS_HEADER_LEN, Command, Message};
+use embassy_stm32_starter::service::journal::{self, JOURNAL_RECORD_OVERHEAD, Journal, JournalError};
+use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash, check_erase, check_read, check_write};
+
+const SIZE: usize = 512;
+const ERASE_SIZE: usize = 256;
+
+// NOR behaviour: erase sets bytes to 0xFF, writes can only clear bits
+struct RamFlash {
+  bytes: [u8; SIZE],
+  erases: u32,
+}
+
+impl RamFlash {
+  const fn new() -> Self {
+    Self { bytes: [0xFF; SIZE], erases: 0 }
+  }
+}
+
+#[derive(Copy, Clone, Debug, defmt::Format)]
+struct OutOfBounds;
+
+impl NorFlashError for OutOfBounds {
+  fn kind(&self) -> NorFlashErrorKind {
+    NorFlashErrorKind::OutOfBounds
+  }
+}
+
+impl ErrorType for RamFlash {
+  type Error = OutOfBounds;
+}
+
+impl ReadNorFlash for RamFlash {
+  const READ_SIZE: usize = 1;
+
+  fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+    check_read(self, offset, bytes.len()).map_err(|_| OutOfBounds)?;
+    bytes.copy_from_slice(&self.bytes[offset as usize..offset as usize + bytes.len()]);
+    Ok(())
+  }
+
+  fn capacity(&self) -> usize {
+    SIZE
+  }
+}
+
+impl NorFlash for RamFlash {
+  const WRITE_SIZE: usize = 1;
+  const ERASE_SIZE: usize = ERASE_SIZE;
+
+  fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+    check_erase(self, from, to).map_err(|_| OutOfBounds)?;
+    self.bytes[from as usize..to as usize].fill(0xFF);
+    self.erases += 1;
+    Ok(())
+  }
+
+  fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+    check_write(self, offset, bytes.len()).map_err(|_| OutOfBounds)?;
+    for (cell, byte) in self.bytes[offset as usize..].iter_mut().zip(bytes) {
+      *cell &= byte;
+    }
+    Ok(())
+  }
+}
+
+fn reading(value: u8) -> Message {
+  Message::new(Command::Telemetry, &[value; 16])
+}
+
+#[defmt_test::tests]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn replays_in_order_with_sequence_ids() {
+    let mut flash = RamFlash::new();
+    let mut journal = defmt::unwrap!(Journal::open(&mut flash, SIZE as u32));
+    for value in 0..3 {
+      defmt::assert_eq!(journal.append(&reading(value)).ok(), Some(value as u16));
+    }
+    defmt::assert_eq!(defmt::unwrap!(journal.pending()), 3);
+    for value in 0..3 {
+      let (entry, msg) = defmt::unwrap!(defmt::unwrap!(journal.next_pending()));
+      defmt::assert_eq!(msg.payload[0], value);
+      defmt::assert_eq!(msg.id, journal::message_id(entry.seq));
+      defmt::unwrap!(journal.mark_delivered(&entry));
+    }
+    defmt::assert!(defmt::unwrap!(journal.next_pending()).is_none());
+  }
+
+  #[test]
+  fn message_ids_skip_zero() {
+    defmt::assert_eq!(journal::message_id(0), 1);
+    defmt::assert_eq!(journal::message_id(254), 255);
+    // Sequence 255 would have been id 0 ("unassigned", never a duplicate)
+    defmt::assert_eq!(journal::message_id(255), 1);
+    defmt::assert!((0..=u16::MAX).all(|seq| journal::message_id(seq) != 0));
+  }
+
+  #[test]
+  fn pending_records_survive_a_reset() {
+    let mut flash = RamFlash::new();
+    {
+      let mut journal = defmt::unwrap!(Journal::open(&mut flash, SIZE as u32));
+      defmt::unwrap!(journal.append(&reading(1)).ok());
+      defmt::unwrap!(journal.append(&reading(2)).ok());
+      let (entry, _) = defmt::unwrap!(defmt::unwrap!(journal.next_pending()));
+      defmt::unwrap!(journal.mark_delivered(&entry));
+    }
+    let mut journal = defmt::unwrap!(Journal::open(&mut flash, SIZE as u32));
+    defmt::assert_eq!(defmt::unwrap!(journal.pending()), 1);
+    let (_, msg) = defmt::unwrap!(defmt::unwrap!(journal.next_pending()));
+    defmt::assert_eq!(msg.payload[0], 2);
+    // Numbering carries on after the records already written
+    defmt::assert_eq!(journal.append(&reading(3)).ok(), Some(2));
+  }
+
+  #[test]
+  fn torn_record_is_skipped() {
+    let mut flash = RamFlash::new();
+    {
+      let mut journal = defmt::unwrap!(Journal::open(&mut flash, SIZE as u32));
+      defmt::unwrap!(journal.append(&reading(1)).ok());
+      defmt::unwrap!(journal.append(&reading(2)).ok());
+    }
+    // Reset before the second record's commit byte was programmed
+    let record = JOURNAL_RECORD_OVERHEAD as usize + COMMS_HEADER_LEN + 16;
+    flash.bytes[2 * record - 2] = 0xFF;
+    let mut journal = defmt::unwrap!(Journal::open(&mut flash, SIZE as u32));
+    defmt::assert_eq!(defmt::unwrap!(journal.pending()), 1);
+    let (_, msg) = defmt::unwrap!(defmt::unwrap!(journal.next_pending()));
+    defmt::assert_eq!(msg.payload[0], 1);
+  }
+
+  #[test]
+  fn full_region_is_reclaimed_only_once_delivered() {
+    let mut flash = RamFlash::new();
+    let mut journal = defmt::unwrap!(Journal::open(&mut flash, SIZE as u32));
+    let mut stored = 0;
+    while journal.append(&reading(stored)).is_ok() {
+      stored += 1;
+    }
+    defmt::assert!(stored > 2);
+    defmt::assert!(matches!(journal.append(&reading(0)), Err(JournalError::Full)));
+    while let Some((entry, _)) = defmt::unwrap!(journal.next_pending()) {
+      defmt::unwrap!(journal.mark_delivered(&entry));
+    }
+    // Everything delivered: the region is erased and the journal starts over
+    defmt::assert!(journal.append(&reading(0)).is_ok());
+    defmt::assert_eq!(defmt::unwrap!(journal.pending()), 1);
+    drop(journal);
+    defmt::assert_eq!(flash.erases, 1);
+  }
+}
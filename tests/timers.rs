@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+
+use embassy_stm32_starter::board::BoardConfig;
+use embassy_stm32_starter::service::timers;
+use embassy_time::{Duration, Instant, block_for};
+
+const DAY_MS: u32 = 24 * 3600 * 1000;
+// Core cycles in 100 ms at the 16 MHz HSI, and 1% of them
+const CYCLES_100MS: u32 = 1_600_000;
+const CYCLES_TOLERANCE: u32 = 16_000;
+
+#[defmt_test::tests]
+mod tests {
+  use super::*;
+  use cortex_m::peripheral::DWT;
+
+  #[init]
+  fn init() {
+    embassy_stm32::init(Default::default());
+    // SAFETY: only the cycle counter is touched, before any test runs
+    let mut cp = unsafe { cortex_m::Peripherals::steal() };
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+  }
+
+  #[test]
+  fn time_driver_timer_runs() {
+    // SAFETY: read-only access to CR1 of the board's time driver timer
+    let cr1 = unsafe { (BoardConfig::TIME_DRIVER_TIM_BASE as *const u32).read_volatile() };
+    defmt::assert!(cr1 & 1 != 0, "time driver timer stopped: CR1 0x{:08X}", cr1);
+  }
+
+  #[test]
+  fn ticks_follow_the_core_clock() {
+    let start = DWT::cycle_count();
+    block_for(Duration::from_millis(100));
+    let cycles = DWT::cycle_count().wrapping_sub(start);
+    defmt::assert!(cycles.abs_diff(CYCLES_100MS) < CYCLES_TOLERANCE, "100 ms took {} cycles", cycles);
+  }
+
+  #[test]
+  fn time_is_monotonic_across_counter_overflows() {
+    // The driver extends the hardware count with an overflow period every 2 s (16-bit count at
+    // 32.768 kHz): watch for steps back or jumps over 2.5 s
+    let end = Instant::now() + Duration::from_millis(2_500);
+    let mut last = Instant::now();
+    while last < end {
+      let now = Instant::now();
+      defmt::assert!(now >= last, "time stepped back: {} -> {} ticks", last.as_ticks(), now.as_ticks());
+      defmt::assert!(now - last < Duration::from_millis(5), "time jumped: {} -> {} ticks", last.as_ticks(), now.as_ticks());
+      last = now;
+    }
+  }
+
+  #[test]
+  fn deadlines_days_ahead_do_not_wrap() {
+    // 60 days: past the 49.7-day wrap of a u32 millisecond count
+    let now = Instant::now();
+    let later = now + Duration::from_secs(60 * 24 * 3600);
+    defmt::assert!(later > now);
+    defmt::assert_eq!((later - now).as_millis(), 60 * DAY_MS as u64);
+    defmt::assert!(later.as_millis() > u32::MAX as u64);
+    // Tick arithmetic saturates at its 64-bit end instead of wrapping
+    defmt::assert!(Instant::MAX.checked_add(Duration::from_ticks(1)).is_none());
+  }
+
+  #[test]
+  fn soft_timer_armed_days_ahead() {
+    defmt::assert!(timers::one_shot(200, 3 * DAY_MS, |_| {}).is_ok());
+    let left = timers::remaining_ms(200);
+    defmt::assert!(left.is_some_and(|ms| ms <= 3 * DAY_MS && ms > 3 * DAY_MS - 1_000), "remaining {}", left);
+    defmt::assert!(timers::disarm(200));
+  }
+}